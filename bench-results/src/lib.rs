@@ -0,0 +1,477 @@
+//! Export criterion benchmark results into a single self describing JSON file and compare two such files.
+//!
+//! Criterion already stores the raw samples of every benchmark under its output directory,
+//! but the files are split across one directory per benchmark and say nothing about the machine the benchmarks ran on.
+//! [`BenchResults`] gathers the raw samples along with the [`Environment`] so that results can be archived,
+//! attached to a PR or compared against a run made on a different machine or at a different commit.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BenchResults {
+    pub environment: Environment,
+    pub benches: Vec<BenchResult>,
+}
+
+impl BenchResults {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read bench results from {path:?}"))?;
+        serde_json::from_str(&file)
+            .with_context(|| format!("Failed to parse bench results from {path:?}"))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = serde_json::to_string_pretty(self)?;
+        fs::write(path, file).with_context(|| format!("Failed to write bench results to {path:?}"))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    /// The full criterion id of the benchmark e.g. `transform/loopback`
+    pub id: String,
+    /// The time taken by a single iteration in each sample, in nanoseconds
+    pub samples_ns: Vec<f64>,
+}
+
+impl BenchResult {
+    pub fn mean_ns(&self) -> f64 {
+        mean(&self.samples_ns)
+    }
+}
+
+/// Describes the machine and build the benchmarks were run on.
+/// Everything that cannot be determined is left as `None` rather than failing the export.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Environment {
+    pub shotover_version: String,
+    pub git_commit: Option<String>,
+    /// true when the working tree had uncommitted changes at the time of the run
+    pub git_dirty: Option<bool>,
+    pub rustc_version: Option<String>,
+    pub os: String,
+    pub arch: String,
+    pub cpu_model: Option<String>,
+    pub cpu_count: Option<usize>,
+    /// Seconds since the unix epoch at the time the results were exported
+    pub timestamp: u64,
+}
+
+impl Environment {
+    pub fn capture(shotover_version: &str) -> Self {
+        Environment {
+            shotover_version: shotover_version.to_owned(),
+            git_commit: command_output("git", &["rev-parse", "HEAD"]),
+            git_dirty: command_output("git", &["status", "--porcelain"]).map(|x| !x.is_empty()),
+            rustc_version: command_output("rustc", &["--version"]),
+            os: std::env::consts::OS.to_owned(),
+            arch: std::env::consts::ARCH.to_owned(),
+            cpu_model: cpu_model(),
+            cpu_count: std::thread::available_parallelism().ok().map(|x| x.get()),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Returns a description of every property that differs between the two environments and is likely to affect performance.
+    pub fn differences(&self, other: &Environment) -> Vec<String> {
+        let mut differences = vec![];
+        let mut check = |name: &str, a: String, b: String| {
+            if a != b {
+                differences.push(format!("{name}: {a} -> {b}"));
+            }
+        };
+        check("os", self.os.clone(), other.os.clone());
+        check("arch", self.arch.clone(), other.arch.clone());
+        check(
+            "cpu model",
+            format!("{:?}", self.cpu_model),
+            format!("{:?}", other.cpu_model),
+        );
+        check(
+            "cpu count",
+            format!("{:?}", self.cpu_count),
+            format!("{:?}", other.cpu_count),
+        );
+        check(
+            "rustc version",
+            format!("{:?}", self.rustc_version),
+            format!("{:?}", other.rustc_version),
+        );
+        differences
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+    } else {
+        None
+    }
+}
+
+fn cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo
+        .lines()
+        .find(|line| line.starts_with("model name"))
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, model)| model.trim().to_owned())
+}
+
+/// The subset of criterion's `benchmark.json` that we need.
+#[derive(Deserialize)]
+struct CriterionBenchmark {
+    full_id: String,
+}
+
+/// The subset of criterion's `sample.json` that we need.
+#[derive(Deserialize)]
+struct CriterionSample {
+    iters: Vec<f64>,
+    times: Vec<f64>,
+}
+
+/// Collects the results of every benchmark in the criterion output directory that was run since `since`.
+/// Benchmarks that were filtered out of the run keep their results from an earlier run on disk, so they are skipped by checking the modification time.
+pub fn collect_criterion(criterion_dir: &Path, since: SystemTime) -> Result<Vec<BenchResult>> {
+    let mut benches = vec![];
+    let mut dirs = vec![criterion_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries =
+            fs::read_dir(&dir).with_context(|| format!("Failed to read directory {dir:?}"))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                if path.file_name().map(|x| x == "new").unwrap_or(false) {
+                    if let Some(bench) = load_criterion_bench(&path, since)? {
+                        benches.push(bench);
+                    }
+                } else {
+                    dirs.push(path);
+                }
+            }
+        }
+    }
+    benches.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(benches)
+}
+
+fn load_criterion_bench(dir: &Path, since: SystemTime) -> Result<Option<BenchResult>> {
+    let sample_path = dir.join("sample.json");
+    let benchmark_path = dir.join("benchmark.json");
+    if !sample_path.exists() || !benchmark_path.exists() {
+        return Ok(None);
+    }
+    if fs::metadata(&sample_path)?.modified()? < since {
+        return Ok(None);
+    }
+
+    let benchmark: CriterionBenchmark = read_json(&benchmark_path)?;
+    let sample: CriterionSample = read_json(&sample_path)?;
+    if sample.iters.len() != sample.times.len() {
+        return Err(anyhow!(
+            "{sample_path:?} contains {} iteration counts but {} times",
+            sample.iters.len(),
+            sample.times.len()
+        ));
+    }
+    Ok(Some(BenchResult {
+        id: benchmark.full_id,
+        samples_ns: sample
+            .times
+            .iter()
+            .zip(&sample.iters)
+            .map(|(time, iters)| time / iters)
+            .collect(),
+    }))
+}
+
+fn read_json<T: for<'a> Deserialize<'a>>(path: &Path) -> Result<T> {
+    let file = fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+    serde_json::from_str(&file).with_context(|| format!("Failed to parse {path:?}"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Improved,
+    Regressed,
+    NoChange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub id: String,
+    pub old_mean_ns: f64,
+    pub new_mean_ns: f64,
+    /// The relative change in mean time, e.g. `0.1` means the new run is 10% slower
+    pub change: f64,
+    /// The two tailed p-value of Welch's t-test, `None` when either run has less than 2 samples
+    pub p_value: Option<f64>,
+    pub verdict: Verdict,
+}
+
+pub struct ComparisonReport {
+    pub comparisons: Vec<Comparison>,
+    /// Benchmarks that only exist in the old results
+    pub removed: Vec<String>,
+    /// Benchmarks that only exist in the new results
+    pub added: Vec<String>,
+}
+
+/// Compares every benchmark present in both results.
+/// A change is only considered significant when its p-value is below `significance`
+/// and the relative change in mean is larger than `noise_threshold`.
+pub fn compare(
+    old: &BenchResults,
+    new: &BenchResults,
+    significance: f64,
+    noise_threshold: f64,
+) -> ComparisonReport {
+    let old_benches: BTreeMap<&str, &BenchResult> =
+        old.benches.iter().map(|x| (x.id.as_str(), x)).collect();
+    let new_benches: BTreeMap<&str, &BenchResult> =
+        new.benches.iter().map(|x| (x.id.as_str(), x)).collect();
+
+    let mut comparisons = vec![];
+    let mut removed = vec![];
+    for (id, old_bench) in &old_benches {
+        let Some(new_bench) = new_benches.get(id) else {
+            removed.push(id.to_string());
+            continue;
+        };
+        let old_mean_ns = old_bench.mean_ns();
+        let new_mean_ns = new_bench.mean_ns();
+        let change = (new_mean_ns - old_mean_ns) / old_mean_ns;
+        let p_value = welch_t_test(&old_bench.samples_ns, &new_bench.samples_ns);
+        let significant =
+            p_value.map(|p| p < significance).unwrap_or(false) && change.abs() > noise_threshold;
+        let verdict = match significant {
+            false => Verdict::NoChange,
+            true if change < 0.0 => Verdict::Improved,
+            true => Verdict::Regressed,
+        };
+        comparisons.push(Comparison {
+            id: id.to_string(),
+            old_mean_ns,
+            new_mean_ns,
+            change,
+            p_value,
+            verdict,
+        });
+    }
+    let added = new_benches
+        .keys()
+        .filter(|id| !old_benches.contains_key(*id))
+        .map(|id| id.to_string())
+        .collect();
+
+    ComparisonReport {
+        comparisons,
+        removed,
+        added,
+    }
+}
+
+fn mean(samples: &[f64]) -> f64 {
+    samples.iter().sum::<f64>() / samples.len() as f64
+}
+
+/// Unbiased sample variance
+fn variance(samples: &[f64], mean: f64) -> f64 {
+    samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (samples.len() - 1) as f64
+}
+
+/// Returns the two tailed p-value of Welch's t-test for the null hypothesis that both samples have the same mean.
+/// Welch's variant is used since there is no reason to assume the variance is the same across runs.
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let mean_a = mean(a);
+    let mean_b = mean(b);
+    let se_a = variance(a, mean_a) / a.len() as f64;
+    let se_b = variance(b, mean_b) / b.len() as f64;
+    let se = se_a + se_b;
+    if se == 0.0 {
+        // Both samples are constant, so the means are either exactly the same or certainly different
+        return Some(if mean_a == mean_b { 1.0 } else { 0.0 });
+    }
+
+    let t = (mean_a - mean_b) / se.sqrt();
+    // Welch–Satterthwaite equation
+    let degrees_of_freedom =
+        se.powi(2) / (se_a.powi(2) / (a.len() - 1) as f64 + se_b.powi(2) / (b.len() - 1) as f64);
+    Some(regularized_incomplete_beta(
+        degrees_of_freedom / (degrees_of_freedom + t * t),
+        degrees_of_freedom / 2.0,
+        0.5,
+    ))
+}
+
+/// Evaluates I_x(a, b) via its continued fraction representation as described in Numerical Recipes.
+/// This gives the CDF of the student's t distribution without needing a statistics dependency.
+fn regularized_incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges quickly only on this side of the mean, otherwise use the symmetry relation
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(x, a, b) / a
+    } else {
+        1.0 - front * beta_continued_fraction(1.0 - x, b, a) / b
+    }
+}
+
+fn beta_continued_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 300;
+    const EPSILON: f64 = 1e-14;
+    const TINY: f64 = 1e-300;
+    let avoid_zero = |x: f64| if x.abs() < TINY { TINY } else { x };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / avoid_zero(1.0 - (a + b) * x / (a + 1.0));
+    let mut result = d;
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / avoid_zero(1.0 + even * d);
+        c = avoid_zero(1.0 + even / c);
+        result *= d * c;
+
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / avoid_zero(1.0 + odd * d);
+        c = avoid_zero(1.0 + odd / c);
+        let delta = d * c;
+        result *= delta;
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+    result
+}
+
+/// Lanczos approximation of ln(Γ(x)) with g = 7
+fn ln_gamma(x: f64) -> f64 {
+    use std::f64::consts::PI;
+    const COEFFICIENTS: [f64; 9] = [
+        0.9999999999998099,
+        676.5203681218851,
+        -1259.1392167224028,
+        771.3234287776531,
+        -176.6150291621406,
+        12.507343278686905,
+        -0.13857109526572012,
+        9.984369578019572e-6,
+        1.5056327351493116e-7,
+    ];
+    if x < 0.5 {
+        // reflection formula
+        (PI / (PI * x).sin()).ln() - ln_gamma(1.0 - x)
+    } else {
+        let x = x - 1.0;
+        let sum = COEFFICIENTS
+            .iter()
+            .enumerate()
+            .skip(1)
+            .fold(COEFFICIENTS[0], |sum, (i, c)| sum + c / (x + i as f64));
+        let t = x + 7.5;
+        0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "expected {expected} but was {actual}"
+        );
+    }
+
+    #[test]
+    fn ln_gamma_matches_factorial() {
+        assert_close(ln_gamma(1.0), 0.0);
+        assert_close(ln_gamma(5.0), 24f64.ln());
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln());
+    }
+
+    #[test]
+    fn welch_t_test_p_value() {
+        // t = -1 with 8 degrees of freedom
+        let p = welch_t_test(&[1.0, 2.0, 3.0, 4.0, 5.0], &[2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert_close(p, 0.346593488);
+
+        let p = welch_t_test(&[1.0, 2.0, 3.0], &[1.0, 2.0, 3.0]).unwrap();
+        assert_close(p, 1.0);
+
+        assert_eq!(welch_t_test(&[1.0], &[1.0, 2.0]), None);
+        assert_eq!(welch_t_test(&[2.0, 2.0], &[2.0, 2.0]), Some(1.0));
+        assert_eq!(welch_t_test(&[2.0, 2.0], &[3.0, 3.0]), Some(0.0));
+    }
+
+    fn results(benches: &[(&str, &[f64])]) -> BenchResults {
+        BenchResults {
+            environment: Environment::capture("0.0.0"),
+            benches: benches
+                .iter()
+                .map(|(id, samples)| BenchResult {
+                    id: id.to_string(),
+                    samples_ns: samples.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn compare_results() {
+        let old = results(&[
+            ("regressed", &[100.0, 101.0, 99.0, 100.0]),
+            ("improved", &[100.0, 101.0, 99.0, 100.0]),
+            ("noisy", &[100.0, 150.0, 50.0, 100.0]),
+            ("removed", &[100.0, 100.0]),
+        ]);
+        let new = results(&[
+            ("regressed", &[120.0, 121.0, 119.0, 120.0]),
+            ("improved", &[80.0, 81.0, 79.0, 80.0]),
+            ("noisy", &[110.0, 160.0, 60.0, 110.0]),
+            ("added", &[100.0, 100.0]),
+        ]);
+
+        let report = compare(&old, &new, 0.05, 0.01);
+        let verdicts: Vec<_> = report
+            .comparisons
+            .iter()
+            .map(|x| (x.id.as_str(), x.verdict))
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                ("improved", Verdict::Improved),
+                ("noisy", Verdict::NoChange),
+                ("regressed", Verdict::Regressed),
+            ]
+        );
+        assert_close(report.comparisons[2].change, 0.2);
+        assert_eq!(report.removed, vec!["removed".to_owned()]);
+        assert_eq!(report.added, vec!["added".to_owned()]);
+    }
+}
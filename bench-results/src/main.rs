@@ -0,0 +1,117 @@
+use anyhow::Result;
+use bench_results::{compare, BenchResults, Verdict};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Compares two bench results files exported by setting SHOTOVER_BENCH_RESULTS when running `cargo bench`.
+#[derive(Parser)]
+#[clap()]
+struct Args {
+    /// The results to use as the baseline
+    pub old: PathBuf,
+
+    /// The results to compare against the baseline
+    pub new: PathBuf,
+
+    /// A change is only reported when the p-value of Welch's t-test is below this value
+    #[clap(long, default_value_t = 0.05)]
+    pub significance: f64,
+
+    /// A change is only reported when the mean changes by more than this fraction, e.g. 0.02 ignores changes smaller than 2%
+    #[clap(long, default_value_t = 0.02)]
+    pub noise_threshold: f64,
+
+    /// Exit with a non-zero status code if any benchmark significantly regressed
+    #[clap(long)]
+    pub fail_on_regression: bool,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let old = BenchResults::load(&args.old)?;
+    let new = BenchResults::load(&args.new)?;
+
+    println!(
+        "old: shotover {} at {}",
+        old.environment.shotover_version,
+        old.environment
+            .git_commit
+            .as_deref()
+            .unwrap_or("unknown commit")
+    );
+    println!(
+        "new: shotover {} at {}",
+        new.environment.shotover_version,
+        new.environment
+            .git_commit
+            .as_deref()
+            .unwrap_or("unknown commit")
+    );
+    let differences = old.environment.differences(&new.environment);
+    if !differences.is_empty() {
+        println!("WARNING: the results were recorded in different environments, changes may not be caused by the code:");
+        for difference in differences {
+            println!("  {difference}");
+        }
+    }
+    println!();
+
+    let report = compare(&old, &new, args.significance, args.noise_threshold);
+    let id_width = report
+        .comparisons
+        .iter()
+        .map(|x| x.id.len())
+        .max()
+        .unwrap_or(0)
+        .max("bench".len());
+    println!(
+        "{:id_width$}  {:>10}  {:>10}  {:>8}  {:>8}  verdict",
+        "bench", "old", "new", "change", "p-value"
+    );
+    let mut regressed = false;
+    for comparison in &report.comparisons {
+        let verdict = match comparison.verdict {
+            Verdict::Improved => "improved",
+            Verdict::Regressed => {
+                regressed = true;
+                "REGRESSED"
+            }
+            Verdict::NoChange => "no change",
+        };
+        let p_value = match comparison.p_value {
+            Some(p) => format!("{p:.3}"),
+            None => "n/a".to_owned(),
+        };
+        println!(
+            "{:id_width$}  {:>10}  {:>10}  {:>+7.2}%  {:>8}  {verdict}",
+            comparison.id,
+            format_ns(comparison.old_mean_ns),
+            format_ns(comparison.new_mean_ns),
+            comparison.change * 100.0,
+            p_value,
+        );
+    }
+    for id in &report.removed {
+        println!("{id} only exists in the old results");
+    }
+    for id in &report.added {
+        println!("{id} only exists in the new results");
+    }
+
+    if regressed && args.fail_on_regression {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn format_ns(ns: f64) -> String {
+    if ns < 1_000.0 {
+        format!("{ns:.1}ns")
+    } else if ns < 1_000_000.0 {
+        format!("{:.2}µs", ns / 1_000.0)
+    } else if ns < 1_000_000_000.0 {
+        format!("{:.2}ms", ns / 1_000_000.0)
+    } else {
+        format!("{:.2}s", ns / 1_000_000_000.0)
+    }
+}
@@ -0,0 +1,144 @@
+use crate::workload::{KeyDistribution, Operation, Workload};
+use bytes::Bytes;
+use rand::distributions::{Distribution, Uniform, WeightedIndex};
+use rand::rngs::SmallRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_distr::Zipf;
+
+/// Generates the operation, key and value of each request sent by a single connection.
+pub struct Generator {
+    rng: SmallRng,
+    operations: Vec<Operation>,
+    operation_weights: WeightedIndex<u32>,
+    keys: KeyGenerator,
+    key_prefix: String,
+    value_size: Uniform<usize>,
+    /// Values are slices of this random data so that generating a value does not need to allocate.
+    value_data: Bytes,
+}
+
+enum KeyGenerator {
+    Uniform(Uniform<u64>),
+    Sequential { next: u64, count: u64 },
+    Zipf(Zipf<f64>),
+}
+
+impl Generator {
+    /// `connection_index` is used to spread the connections out over the keys when the keys are used sequentially
+    pub fn new(workload: &Workload, connection_index: usize) -> Self {
+        let mut rng = SmallRng::from_entropy();
+        let count = workload.keys.count;
+        let keys = match workload.keys.distribution {
+            KeyDistribution::Uniform => KeyGenerator::Uniform(Uniform::new(0, count)),
+            KeyDistribution::Sequential => KeyGenerator::Sequential {
+                next: count / workload.connections as u64 * connection_index as u64,
+                count,
+            },
+            KeyDistribution::Zipf { exponent } => {
+                KeyGenerator::Zipf(Zipf::new(count, exponent).unwrap())
+            }
+        };
+
+        let mut value_data = vec![0; workload.value_size.max * 2];
+        rng.fill_bytes(&mut value_data);
+
+        Generator {
+            operations: workload.operations.iter().map(|x| x.operation).collect(),
+            operation_weights: WeightedIndex::new(workload.operations.iter().map(|x| x.weight))
+                .unwrap(),
+            keys,
+            key_prefix: workload.keys.prefix.clone(),
+            value_size: Uniform::new_inclusive(workload.value_size.min, workload.value_size.max),
+            value_data: value_data.into(),
+            rng,
+        }
+    }
+
+    pub fn operation(&mut self) -> Operation {
+        self.operations[self.operation_weights.sample(&mut self.rng)]
+    }
+
+    pub fn key(&mut self) -> String {
+        let index = match &mut self.keys {
+            KeyGenerator::Uniform(uniform) => uniform.sample(&mut self.rng),
+            KeyGenerator::Sequential { next, count } => {
+                let index = *next;
+                *next = (*next + 1) % *count;
+                index
+            }
+            // zipf samples are in the range 1..=count with 1 being the most common
+            KeyGenerator::Zipf(zipf) => zipf.sample(&mut self.rng) as u64 - 1,
+        };
+        format!("{}{index}", self.key_prefix)
+    }
+
+    pub fn value(&mut self) -> Bytes {
+        let size = self.value_size.sample(&mut self.rng);
+        let start = self.rng.gen_range(0..=self.value_data.len() - size);
+        self.value_data.slice(start..start + size)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::workload::{Keys, Protocol, ValueSize, WeightedOperation};
+    use pretty_assertions::assert_eq;
+
+    fn workload(distribution: KeyDistribution) -> Workload {
+        Workload {
+            address: "127.0.0.1:6379".to_owned(),
+            protocol: Protocol::Redis,
+            connections: 2,
+            max_in_flight_per_connection: 1,
+            duration_secs: 1,
+            target_qps: None,
+            keys: Keys {
+                count: 10,
+                distribution,
+                prefix: "foo".to_owned(),
+            },
+            value_size: ValueSize { min: 5, max: 10 },
+            operations: vec![
+                WeightedOperation {
+                    operation: Operation::Read,
+                    weight: 1,
+                },
+                WeightedOperation {
+                    operation: Operation::Delete,
+                    weight: 0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn sequential_keys() {
+        let mut generator = Generator::new(&workload(KeyDistribution::Sequential), 1);
+        let keys: Vec<String> = (0..7).map(|_| generator.key()).collect();
+        assert_eq!(
+            keys,
+            vec!["foo5", "foo6", "foo7", "foo8", "foo9", "foo0", "foo1"]
+        );
+    }
+
+    #[test]
+    fn generated_requests_stay_in_bounds() {
+        for distribution in [
+            KeyDistribution::Uniform,
+            KeyDistribution::Zipf { exponent: 1.0 },
+        ] {
+            let mut generator = Generator::new(&workload(distribution), 0);
+            for _ in 0..1000 {
+                assert_eq!(generator.operation(), Operation::Read);
+
+                let key = generator.key();
+                let index: u64 = key.strip_prefix("foo").unwrap().parse().unwrap();
+                assert!(index < 10, "{key} is out of range");
+
+                let size = generator.value().len();
+                assert!((5..=10).contains(&size), "{size} is out of range");
+            }
+        }
+    }
+}
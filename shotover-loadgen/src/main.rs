@@ -0,0 +1,92 @@
+//! Generates synthetic load against a shotover topology as described by a YAML workload spec.
+//! Requests are encoded and responses decoded with shotover's own codecs, so no external drivers are required.
+
+use anyhow::Result;
+use clap::Parser;
+use stats::{Progress, Stats};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use workload::Workload;
+
+mod generator;
+mod protocol;
+mod runner;
+mod stats;
+mod workload;
+
+/// Generates synthetic redis, cassandra or kafka load against shotover as described by a workload spec.
+#[derive(Parser)]
+#[clap()]
+struct Args {
+    /// Path to the YAML workload spec
+    pub workload: PathBuf,
+
+    /// Send requests to this address instead of the address in the workload spec
+    #[clap(long)]
+    pub address: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut workload = Workload::load(&args.workload)?;
+    if let Some(address) = args.address {
+        workload.address = address;
+    }
+    let workload = Arc::new(workload);
+
+    protocol::prepare(&workload).await?;
+    let mut connections = vec![];
+    for _ in 0..workload.connections {
+        connections.push(protocol::connect(&workload).await?);
+    }
+
+    let progress = Arc::new(Progress::default());
+    let start = Instant::now();
+    let end = start + Duration::from_secs(workload.duration_secs);
+    let tasks: Vec<_> = connections
+        .into_iter()
+        .enumerate()
+        .map(|(index, (connection, client))| {
+            tokio::spawn(runner::run_connection(
+                workload.clone(),
+                index,
+                connection,
+                client,
+                progress.clone(),
+                start,
+                end,
+            ))
+        })
+        .collect();
+    let reporter = tokio::spawn(report_progress(progress.clone(), start));
+
+    let mut stats = Stats::default();
+    for task in tasks {
+        stats.merge(task.await??);
+    }
+    reporter.abort();
+
+    println!("\n{}", stats.report(start.elapsed()));
+    Ok(())
+}
+
+async fn report_progress(progress: Arc<Progress>, start: Instant) {
+    let mut interval =
+        tokio::time::interval_at(start + Duration::from_secs(1), Duration::from_secs(1));
+    let mut last_completed = 0;
+    loop {
+        interval.tick().await;
+        let completed = progress.completed.load(Ordering::Relaxed);
+        println!(
+            "{:>4}s: {} requests/s, {} errors total",
+            start.elapsed().as_secs(),
+            completed - last_completed,
+            progress.errors.load(Ordering::Relaxed)
+        );
+        last_completed = completed;
+    }
+}
@@ -0,0 +1,159 @@
+use super::{new_connection, send_receive, Client};
+use crate::workload::Operation;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use cassandra_protocol::consistency::Consistency;
+use cassandra_protocol::frame::message_startup::BodyReqStartup;
+use cassandra_protocol::frame::Version;
+use cassandra_protocol::query::{QueryParams, QueryValues};
+use cassandra_protocol::types::value::Value;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use shotover::codec::cassandra::CassandraCodecBuilder;
+use shotover::connection::SinkConnection;
+use shotover::frame::cassandra::{parse_statement_single, Tracing};
+use shotover::frame::{CassandraFrame, CassandraOperation, Frame};
+use shotover::message::Message;
+use std::collections::HashMap;
+
+pub async fn connect(
+    address: &str,
+    keyspace: &str,
+    table: &str,
+) -> Result<(SinkConnection, Box<dyn Client>)> {
+    let connection = handshake(address).await?;
+    let client = CassandraClient {
+        read: parse_statement_single(&format!(
+            "SELECT value FROM {keyspace}.{table} WHERE key = ?"
+        )),
+        write: parse_statement_single(&format!(
+            "INSERT INTO {keyspace}.{table} (key, value) VALUES (?, ?)"
+        )),
+        delete: parse_statement_single(&format!("DELETE FROM {keyspace}.{table} WHERE key = ?")),
+        next_stream_id: 0,
+    };
+    Ok((connection, Box::new(client)))
+}
+
+pub async fn create_schema(address: &str, keyspace: &str, table: &str) -> Result<()> {
+    let mut connection = handshake(address).await?;
+    for query in [
+        format!("CREATE KEYSPACE IF NOT EXISTS {keyspace} WITH REPLICATION = {{ 'class' : 'SimpleStrategy', 'replication_factor' : 1 }}"),
+        format!("CREATE TABLE IF NOT EXISTS {keyspace}.{table} (key text PRIMARY KEY, value blob)"),
+    ] {
+        let request = frame(
+            0,
+            CassandraOperation::Query {
+                query: Box::new(parse_statement_single(&query)),
+                params: Box::new(params(None)),
+            },
+        );
+        let mut response = send_receive(&mut connection, request).await?;
+        if let Some(err) = response_error(&mut response) {
+            return Err(anyhow!("{query} failed: {err}"));
+        }
+    }
+    Ok(())
+}
+
+async fn handshake(address: &str) -> Result<SinkConnection> {
+    let mut connection = new_connection::<CassandraCodecBuilder>(address).await?;
+    let startup = frame(
+        0,
+        CassandraOperation::Startup(BodyReqStartup {
+            map: HashMap::from([("CQL_VERSION".to_owned(), "3.0.0".to_owned())]),
+        }),
+    );
+    let mut response = send_receive(&mut connection, startup).await?;
+    match response.frame() {
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Ready(_),
+            ..
+        })) => Ok(connection),
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Authenticate(_),
+            ..
+        })) => Err(anyhow!(
+            "{address} requires authentication which is not supported by the load generator"
+        )),
+        _ => Err(anyhow!(
+            "Unexpected response to STARTUP: {}",
+            response.to_high_level_string()
+        )),
+    }
+}
+
+struct CassandraClient {
+    read: CassandraStatement,
+    write: CassandraStatement,
+    delete: CassandraStatement,
+    next_stream_id: i16,
+}
+
+impl Client for CassandraClient {
+    fn request(&mut self, operation: Operation, key: &str, value: Bytes) -> Message {
+        let key = Value::Some(key.as_bytes().to_vec());
+        let (query, values) = match operation {
+            Operation::Read => (&self.read, vec![key]),
+            Operation::Write => (&self.write, vec![key, Value::Some(value.to_vec())]),
+            Operation::Delete => (&self.delete, vec![key]),
+        };
+        let operation = CassandraOperation::Query {
+            query: Box::new(query.clone()),
+            params: Box::new(params(Some(QueryValues::SimpleValues(values)))),
+        };
+
+        // The number of requests in flight is far lower than the number of stream ids,
+        // so a stream id will never be reused while a request using it is still in flight.
+        // i16::MAX is skipped since shotover uses it for keepalives.
+        let stream_id = self.next_stream_id;
+        self.next_stream_id = (self.next_stream_id + 1) % i16::MAX;
+        frame(stream_id, operation)
+    }
+
+    fn response_error(&mut self, response: &mut Message) -> Option<String> {
+        // Checking the opcode avoids parsing the rows of every successful read
+        if response.is_error() {
+            response_error(response)
+        } else {
+            None
+        }
+    }
+}
+
+fn response_error(response: &mut Message) -> Option<String> {
+    match response.frame() {
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Error(err),
+            ..
+        })) => Some(format!("{:?}: {}", err.ty, err.message)),
+        Some(Frame::Cassandra(_)) => None,
+        _ => Some(format!(
+            "Invalid cassandra response {}",
+            response.to_high_level_string()
+        )),
+    }
+}
+
+fn frame(stream_id: i16, operation: CassandraOperation) -> Message {
+    Message::from_frame(Frame::Cassandra(CassandraFrame {
+        version: Version::V4,
+        stream_id,
+        tracing: Tracing::Request(false),
+        warnings: vec![],
+        operation,
+    }))
+}
+
+fn params(values: Option<QueryValues>) -> QueryParams {
+    QueryParams {
+        consistency: Consistency::One,
+        with_names: false,
+        values,
+        page_size: Some(5000),
+        paging_state: None,
+        serial_consistency: None,
+        timestamp: None,
+        keyspace: None,
+        now_in_seconds: None,
+    }
+}
@@ -0,0 +1,244 @@
+use super::{new_connection, send_receive, Client};
+use crate::workload::Operation;
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use kafka_protocol::messages::fetch_request::{FetchPartition, FetchTopic};
+use kafka_protocol::messages::metadata_request::MetadataRequestTopic;
+use kafka_protocol::messages::produce_request::{PartitionProduceData, TopicProduceData};
+use kafka_protocol::messages::{
+    ApiKey, BrokerId, FetchRequest, MetadataRequest, ProduceRequest, RequestHeader, TopicName,
+};
+use kafka_protocol::records::{
+    Compression, Record, RecordBatchEncoder, RecordEncodeOptions, TimestampType,
+};
+use kafka_protocol::ResponseError;
+use shotover::codec::kafka::KafkaCodecBuilder;
+use shotover::connection::SinkConnection;
+use shotover::frame::kafka::{KafkaFrame, RequestBody, ResponseBody, StrBytes};
+use shotover::frame::Frame;
+use shotover::message::Message;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+pub async fn connect(
+    address: &str,
+    topic: &str,
+    acks: i16,
+    fetch_max_bytes: i32,
+) -> Result<(SinkConnection, Box<dyn Client>)> {
+    let mut connection = new_connection::<KafkaCodecBuilder>(address).await?;
+    let topic = TopicName(StrBytes::from_string(topic.to_owned()));
+
+    let request = Message::from_frame(Frame::Kafka(KafkaFrame::Request {
+        header: header(ApiKey::MetadataKey, 4, 0),
+        body: RequestBody::Metadata(
+            MetadataRequest::builder()
+                .topics(Some(vec![MetadataRequestTopic::builder()
+                    .name(Some(topic.clone()))
+                    .build()
+                    .unwrap()]))
+                .allow_auto_topic_creation(true)
+                .build()
+                .unwrap(),
+        ),
+    }));
+    let mut response = send_receive(&mut connection, request).await?;
+    let partitions = match response.frame() {
+        Some(Frame::Kafka(KafkaFrame::Response {
+            body: ResponseBody::Metadata(metadata),
+            ..
+        })) => {
+            let metadata = metadata
+                .topics
+                .get(&topic)
+                .ok_or_else(|| anyhow!("Metadata response did not include topic {topic:?}"))?;
+            if let Some(err) = ResponseError::try_from_code(metadata.error_code) {
+                return Err(anyhow!(
+                    "Failed to fetch metadata of topic {topic:?}: {err}"
+                ));
+            }
+            metadata.partitions.len() as i32
+        }
+        _ => {
+            return Err(anyhow!(
+                "Unexpected response to metadata request: {}",
+                response.to_high_level_string()
+            ))
+        }
+    };
+    if partitions == 0 {
+        return Err(anyhow!("Topic {topic:?} has no partitions"));
+    }
+
+    let client = KafkaClient {
+        topic,
+        partitions,
+        acks,
+        fetch_max_bytes,
+        next_correlation_id: 1,
+        fetch_offsets: vec![0; partitions as usize],
+    };
+    Ok((connection, Box::new(client)))
+}
+
+struct KafkaClient {
+    topic: TopicName,
+    partitions: i32,
+    acks: i16,
+    fetch_max_bytes: i32,
+    next_correlation_id: i32,
+    /// The offset to fetch from for each partition.
+    /// Starts at the beginning of the partition and follows the latest offset written by this connection.
+    fetch_offsets: Vec<i64>,
+}
+
+impl KafkaClient {
+    fn partition_of(&self, key: &str) -> i32 {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.partitions as u64) as i32
+    }
+
+    fn produce(&self, partition: i32, key: &str, value: Bytes) -> RequestBody {
+        let record = Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset: 0,
+            sequence: -1,
+            timestamp: 0,
+            key: Some(Bytes::copy_from_slice(key.as_bytes())),
+            value: Some(value),
+            headers: Default::default(),
+        };
+        let mut records = BytesMut::new();
+        RecordBatchEncoder::encode(
+            &mut records,
+            [record].iter(),
+            &RecordEncodeOptions {
+                version: 2,
+                compression: Compression::None,
+            },
+        )
+        .unwrap();
+
+        RequestBody::Produce(
+            ProduceRequest::builder()
+                .acks(self.acks)
+                .timeout_ms(30_000)
+                .topic_data(
+                    [(
+                        self.topic.clone(),
+                        TopicProduceData::builder()
+                            .partition_data(vec![PartitionProduceData::builder()
+                                .index(partition)
+                                .records(Some(records.freeze()))
+                                .build()
+                                .unwrap()])
+                            .build()
+                            .unwrap(),
+                    )]
+                    .into_iter()
+                    .collect(),
+                )
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn fetch(&self, partition: i32) -> RequestBody {
+        RequestBody::Fetch(
+            FetchRequest::builder()
+                .replica_id(BrokerId(-1))
+                .max_wait_ms(0)
+                .min_bytes(1)
+                .max_bytes(self.fetch_max_bytes)
+                .session_epoch(-1)
+                .topics(vec![FetchTopic::builder()
+                    .topic(self.topic.clone())
+                    .partitions(vec![FetchPartition::builder()
+                        .partition(partition)
+                        .current_leader_epoch(-1)
+                        .fetch_offset(self.fetch_offsets[partition as usize])
+                        .log_start_offset(-1)
+                        .partition_max_bytes(self.fetch_max_bytes)
+                        .build()
+                        .unwrap()])
+                    .build()
+                    .unwrap()])
+                .build()
+                .unwrap(),
+        )
+    }
+}
+
+impl Client for KafkaClient {
+    fn request(&mut self, operation: Operation, key: &str, value: Bytes) -> Message {
+        let partition = self.partition_of(key);
+        let (header, body) = match operation {
+            Operation::Write => (
+                header(ApiKey::ProduceKey, 7, self.next_correlation_id),
+                self.produce(partition, key, value),
+            ),
+            Operation::Read => (
+                header(ApiKey::FetchKey, 11, self.next_correlation_id),
+                self.fetch(partition),
+            ),
+            Operation::Delete => unreachable!("Rejected by workload validation"),
+        };
+        self.next_correlation_id = self.next_correlation_id.wrapping_add(1);
+        Message::from_frame(Frame::Kafka(KafkaFrame::Request { header, body }))
+    }
+
+    fn response_error(&mut self, response: &mut Message) -> Option<String> {
+        match response.frame() {
+            Some(Frame::Kafka(KafkaFrame::Response {
+                body: ResponseBody::Produce(produce),
+                ..
+            })) => {
+                for topic in produce.responses.values() {
+                    for partition in &topic.partition_responses {
+                        if let Some(err) = ResponseError::try_from_code(partition.error_code) {
+                            return Some(format!("Produce failed: {err}"));
+                        }
+                        if let Some(offset) = self.fetch_offsets.get_mut(partition.index as usize) {
+                            *offset = (*offset).max(partition.base_offset);
+                        }
+                    }
+                }
+                None
+            }
+            Some(Frame::Kafka(KafkaFrame::Response {
+                body: ResponseBody::Fetch(fetch),
+                ..
+            })) => {
+                if let Some(err) = ResponseError::try_from_code(fetch.error_code) {
+                    return Some(format!("Fetch failed: {err}"));
+                }
+                fetch
+                    .responses
+                    .iter()
+                    .flat_map(|topic| &topic.partitions)
+                    .find_map(|partition| ResponseError::try_from_code(partition.error_code))
+                    .map(|err| format!("Fetch failed: {err}"))
+            }
+            _ => Some(format!(
+                "Unexpected kafka response {}",
+                response.to_high_level_string()
+            )),
+        }
+    }
+}
+
+fn header(api_key: ApiKey, version: i16, correlation_id: i32) -> RequestHeader {
+    RequestHeader::builder()
+        .request_api_key(api_key as i16)
+        .request_api_version(version)
+        .correlation_id(correlation_id)
+        .client_id(Some(StrBytes::from_static_str("shotover-loadgen")))
+        .build()
+        .unwrap()
+}
@@ -0,0 +1,72 @@
+use crate::workload::{Operation, Protocol, Workload};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use shotover::codec::CodecBuilder;
+use shotover::connection::SinkConnection;
+use shotover::message::Message;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+mod cassandra;
+mod kafka;
+mod redis;
+
+/// Translates operations into requests of a specific protocol.
+pub trait Client: Send {
+    fn request(&mut self, operation: Operation, key: &str, value: Bytes) -> Message;
+
+    /// Returns a description of the error when the response indicates that the request failed.
+    fn response_error(&mut self, response: &mut Message) -> Option<String>;
+}
+
+/// Performs any setup that needs to be done once before the connections start sending requests.
+pub async fn prepare(workload: &Workload) -> Result<()> {
+    match &workload.protocol {
+        Protocol::Cassandra {
+            keyspace,
+            table,
+            create_schema: true,
+        } => cassandra::create_schema(&workload.address, keyspace, table).await,
+        _ => Ok(()),
+    }
+}
+
+/// Creates a new connection that has completed any handshake required by the protocol.
+pub async fn connect(workload: &Workload) -> Result<(SinkConnection, Box<dyn Client>)> {
+    match &workload.protocol {
+        Protocol::Redis => redis::connect(&workload.address).await,
+        Protocol::Cassandra {
+            keyspace, table, ..
+        } => cassandra::connect(&workload.address, keyspace, table).await,
+        Protocol::Kafka {
+            topic,
+            acks,
+            fetch_max_bytes,
+        } => kafka::connect(&workload.address, topic, *acks, *fetch_max_bytes).await,
+    }
+}
+
+async fn new_connection<C: CodecBuilder + 'static>(address: &str) -> Result<SinkConnection> {
+    SinkConnection::new(
+        address,
+        C::new(shotover::codec::Direction::Sink, "loadgen".to_owned()),
+        &None,
+        Duration::from_secs(5),
+        Arc::new(Notify::new()),
+        None,
+        None,
+    )
+    .await
+    .with_context(|| format!("Failed to connect to {address}"))
+}
+
+/// Sends a single request and waits for its response, for use during setup.
+async fn send_receive(connection: &mut SinkConnection, request: Message) -> Result<Message> {
+    connection.send(vec![request])?;
+    connection
+        .recv()
+        .await?
+        .pop()
+        .ok_or_else(|| anyhow!("No response was received"))
+}
@@ -0,0 +1,42 @@
+use super::{new_connection, Client};
+use crate::workload::Operation;
+use anyhow::Result;
+use bytes::Bytes;
+use shotover::codec::redis::RedisCodecBuilder;
+use shotover::connection::SinkConnection;
+use shotover::frame::{Frame, RedisFrame};
+use shotover::message::Message;
+
+pub async fn connect(address: &str) -> Result<(SinkConnection, Box<dyn Client>)> {
+    let connection = new_connection::<RedisCodecBuilder>(address).await?;
+    Ok((connection, Box::new(RedisClient)))
+}
+
+struct RedisClient;
+
+impl Client for RedisClient {
+    fn request(&mut self, operation: Operation, key: &str, value: Bytes) -> Message {
+        let key = RedisFrame::BulkString(Bytes::copy_from_slice(key.as_bytes()));
+        let command = match operation {
+            Operation::Read => vec![RedisFrame::BulkString("GET".into()), key],
+            Operation::Write => vec![
+                RedisFrame::BulkString("SET".into()),
+                key,
+                RedisFrame::BulkString(value),
+            ],
+            Operation::Delete => vec![RedisFrame::BulkString("DEL".into()), key],
+        };
+        Message::from_frame(Frame::Redis(RedisFrame::Array(command)))
+    }
+
+    fn response_error(&mut self, response: &mut Message) -> Option<String> {
+        match response.frame() {
+            Some(Frame::Redis(RedisFrame::Error(err))) => Some(err.to_string()),
+            Some(Frame::Redis(_)) => None,
+            _ => Some(format!(
+                "Invalid redis response {}",
+                response.to_high_level_string()
+            )),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::generator::Generator;
+use crate::protocol::Client;
+use crate::stats::{Progress, Stats};
+use crate::workload::{Operation, Workload};
+use anyhow::Result;
+use shotover::connection::SinkConnection;
+use shotover::message::MessageId;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long to wait for the responses of requests still in flight once the run has finished sending requests.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Only the first few errors are logged so that a misconfigured workload does not flood the terminal.
+const MAX_LOGGED_ERRORS: u64 = 10;
+
+/// Sends requests over a single connection from `start` until `end` and returns the results once all responses are received.
+pub async fn run_connection(
+    workload: Arc<Workload>,
+    index: usize,
+    mut connection: SinkConnection,
+    mut client: Box<dyn Client>,
+    progress: Arc<Progress>,
+    start: Instant,
+    end: Instant,
+) -> Result<Stats> {
+    let mut generator = Generator::new(&workload, index);
+    let interval = workload
+        .target_qps
+        .map(|target_qps| Duration::from_secs_f64(workload.connections as f64 / target_qps as f64));
+    // Spread the connections out so that they do not all send their requests at the same instant
+    let mut next_send = match interval {
+        Some(interval) => start + interval.mul_f64(index as f64 / workload.connections as f64),
+        None => start,
+    };
+    let mut in_flight: HashMap<MessageId, (Operation, Instant)> = HashMap::new();
+    let mut stats = Stats::default();
+
+    loop {
+        let can_send = next_send < end && in_flight.len() < workload.max_in_flight_per_connection;
+        let sending_finished = next_send >= end;
+        if sending_finished && in_flight.is_empty() {
+            break;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_send), if can_send => {
+                let operation = generator.operation();
+                let key = generator.key();
+                let request = client.request(operation, &key, generator.value());
+                // When throttled, latency is measured from when the request was scheduled rather than when it was sent.
+                // Otherwise time spent waiting for a slow response to free up an in flight slot would be hidden from the results.
+                let sent_at = match interval {
+                    Some(_) => next_send,
+                    None => Instant::now(),
+                };
+                in_flight.insert(request.id(), (operation, sent_at));
+                connection.send(vec![request])?;
+                next_send = match interval {
+                    Some(interval) => next_send + interval,
+                    None => Instant::now(),
+                };
+            }
+            responses = connection.recv() => {
+                for mut response in responses? {
+                    let Some((operation, sent_at)) = response
+                        .request_id()
+                        .and_then(|id| in_flight.remove(&id))
+                    else {
+                        continue;
+                    };
+                    let latency = sent_at.elapsed();
+                    match client.response_error(&mut response) {
+                        Some(err) => {
+                            stats.record_error(operation);
+                            if progress.errors.load(Ordering::Relaxed) < MAX_LOGGED_ERRORS {
+                                println!("connection {index}: {operation:?} failed: {err}");
+                            }
+                            progress.record(true);
+                        }
+                        None => {
+                            stats.record_success(operation, latency);
+                            progress.record(false);
+                        }
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(end + DRAIN_TIMEOUT), if sending_finished => {
+                println!(
+                    "connection {index}: gave up waiting on {} responses after {DRAIN_TIMEOUT:?}",
+                    in_flight.len()
+                );
+                for (operation, _) in in_flight.into_values() {
+                    stats.record_error(operation);
+                    progress.record(true);
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,121 @@
+use crate::workload::Operation;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters shared by all connections so that progress can be reported while the run is in progress.
+#[derive(Default)]
+pub struct Progress {
+    pub completed: AtomicU64,
+    pub errors: AtomicU64,
+}
+
+impl Progress {
+    pub fn record(&self, error: bool) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        if error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The results of a single connection, merged together at the end of the run.
+#[derive(Default)]
+pub struct Stats {
+    operations: BTreeMap<Operation, OperationStats>,
+}
+
+#[derive(Default)]
+struct OperationStats {
+    /// The latency of every successful request in microseconds
+    latencies: Vec<u64>,
+    errors: u64,
+}
+
+impl Stats {
+    pub fn record_success(&mut self, operation: Operation, latency: Duration) {
+        self.operations
+            .entry(operation)
+            .or_default()
+            .latencies
+            .push(latency.as_micros() as u64);
+    }
+
+    pub fn record_error(&mut self, operation: Operation) {
+        self.operations.entry(operation).or_default().errors += 1;
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (operation, other) in other.operations {
+            let stats = self.operations.entry(operation).or_default();
+            stats.latencies.extend(other.latencies);
+            stats.errors += other.errors;
+        }
+    }
+
+    pub fn report(mut self, elapsed: Duration) -> String {
+        let requests: u64 = self
+            .operations
+            .values()
+            .map(|x| x.latencies.len() as u64 + x.errors)
+            .sum();
+        let errors: u64 = self.operations.values().map(|x| x.errors).sum();
+
+        let mut report = format!(
+            "Ran for {:.1}s, completed {requests} requests ({:.1} per second) with {errors} errors\n\n",
+            elapsed.as_secs_f64(),
+            requests as f64 / elapsed.as_secs_f64(),
+        );
+        writeln!(
+            report,
+            "{:10} {:>10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "operation", "requests", "errors", "p50", "p90", "p99", "p99.9", "max"
+        )
+        .unwrap();
+        for (operation, stats) in &mut self.operations {
+            stats.latencies.sort_unstable();
+            let latencies = &stats.latencies;
+            writeln!(
+                report,
+                "{:10} {:>10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                format!("{operation:?}"),
+                latencies.len() as u64 + stats.errors,
+                stats.errors,
+                percentile(latencies, 0.5),
+                percentile(latencies, 0.9),
+                percentile(latencies, 0.99),
+                percentile(latencies, 0.999),
+                percentile(latencies, 1.0),
+            )
+            .unwrap();
+        }
+        report
+    }
+}
+
+/// Formats the latency below which the `quantile` of the sorted latencies fall
+fn percentile(sorted_latencies: &[u64], quantile: f64) -> String {
+    if sorted_latencies.is_empty() {
+        return "-".to_owned();
+    }
+    let index = ((sorted_latencies.len() as f64 * quantile).ceil() as usize).max(1) - 1;
+    let micros = sorted_latencies[index];
+    format!("{:.3}ms", micros as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn percentiles() {
+        let latencies: Vec<u64> = (1..=1000).map(|x| x * 1000).collect();
+        assert_eq!(percentile(&latencies, 0.5), "500.000ms");
+        assert_eq!(percentile(&latencies, 0.999), "999.000ms");
+        assert_eq!(percentile(&latencies, 1.0), "1000.000ms");
+        assert_eq!(percentile(&latencies[..1], 0.5), "1.000ms");
+        assert_eq!(percentile(&[], 0.5), "-");
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Describes the load to generate, loaded from a YAML file.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Workload {
+    /// The address of the shotover source to send requests to e.g. `127.0.0.1:6379`
+    pub address: String,
+    pub protocol: Protocol,
+    #[serde(default = "default_connections")]
+    pub connections: usize,
+    /// The maximum number of requests per connection that can be waiting for a response at once
+    #[serde(default = "default_max_in_flight_per_connection")]
+    pub max_in_flight_per_connection: usize,
+    pub duration_secs: u64,
+    /// The total number of requests per second to send across all connections.
+    /// When not set, requests are sent as fast as `max_in_flight_per_connection` allows.
+    pub target_qps: Option<u64>,
+    pub keys: Keys,
+    pub value_size: ValueSize,
+    pub operations: Vec<WeightedOperation>,
+}
+
+fn default_connections() -> usize {
+    1
+}
+
+fn default_max_in_flight_per_connection() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum Protocol {
+    Redis,
+    Cassandra {
+        keyspace: String,
+        table: String,
+        /// Create the keyspace and table before the run starts if they do not already exist
+        #[serde(default)]
+        create_schema: bool,
+    },
+    Kafka {
+        topic: String,
+        #[serde(default = "default_acks")]
+        acks: i16,
+        #[serde(default = "default_fetch_max_bytes")]
+        fetch_max_bytes: i32,
+    },
+}
+
+fn default_acks() -> i16 {
+    1
+}
+
+fn default_fetch_max_bytes() -> i32 {
+    1024 * 1024
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Keys {
+    /// The number of distinct keys to use
+    pub count: u64,
+    #[serde(default)]
+    pub distribution: KeyDistribution,
+    #[serde(default = "default_key_prefix")]
+    pub prefix: String,
+}
+
+fn default_key_prefix() -> String {
+    "key".to_owned()
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub enum KeyDistribution {
+    /// Every key is equally likely to be used
+    #[default]
+    Uniform,
+    /// Every connection cycles through all keys in order, each starting from a different offset
+    Sequential,
+    /// A small number of keys are used far more often than the rest, the larger the exponent the more skewed the distribution
+    Zipf { exponent: f64 },
+}
+
+/// The size of each written value is picked uniformly between `min` and `max` inclusive
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ValueSize {
+    pub min: usize,
+    pub max: usize,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WeightedOperation {
+    pub operation: Operation,
+    /// The likelihood of this operation being picked relative to the weights of the other operations
+    pub weight: u32,
+}
+
+/// The operations are translated to the equivalent request of each protocol:
+///
+/// | operation | redis | cassandra | kafka   |
+/// |-----------|-------|-----------|---------|
+/// | Read      | GET   | SELECT    | Fetch   |
+/// | Write     | SET   | INSERT    | Produce |
+/// | Delete    | DEL   | DELETE    |         |
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Operation {
+    Read,
+    Write,
+    Delete,
+}
+
+impl Workload {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read workload from {path:?}"))?;
+        let workload: Workload = serde_yaml::from_str(&file)
+            .with_context(|| format!("Failed to parse workload from {path:?}"))?;
+        workload.validate()?;
+        Ok(workload)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = vec![];
+
+        if self.connections == 0 {
+            errors.push("  connections must be greater than 0".to_owned());
+        }
+        if self.max_in_flight_per_connection == 0 {
+            errors.push("  max_in_flight_per_connection must be greater than 0".to_owned());
+        }
+        if self.duration_secs == 0 {
+            errors.push("  duration_secs must be greater than 0".to_owned());
+        }
+        if self.target_qps == Some(0) {
+            errors.push("  target_qps must be greater than 0".to_owned());
+        }
+        if self.keys.count == 0 {
+            errors.push("  keys.count must be greater than 0".to_owned());
+        }
+        if let KeyDistribution::Zipf { exponent } = self.keys.distribution {
+            if exponent <= 0.0 {
+                errors.push(format!(
+                    "  keys.distribution zipf exponent must be greater than 0 but was {exponent}"
+                ));
+            }
+        }
+        if self.value_size.min > self.value_size.max {
+            errors.push(format!(
+                "  value_size.min ({}) must not be greater than value_size.max ({})",
+                self.value_size.min, self.value_size.max
+            ));
+        }
+        if self.operations.iter().map(|x| x.weight).sum::<u32>() == 0 {
+            errors.push(
+                "  operations must contain at least one operation with a weight greater than 0"
+                    .to_owned(),
+            );
+        }
+        if let Protocol::Kafka { acks, .. } = self.protocol {
+            if self
+                .operations
+                .iter()
+                .any(|x| x.operation == Operation::Delete)
+            {
+                errors.push("  the Delete operation is not supported by kafka".to_owned());
+            }
+            if acks == 0 {
+                errors.push("  kafka acks must not be 0 as the broker does not respond to such requests, so their latency cannot be measured".to_owned());
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            errors.insert(0, "Invalid workload:".to_owned());
+            Err(anyhow!(errors.join("\n")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_workload() {
+        let workload: Workload = serde_yaml::from_str(
+            r#"
+address: 127.0.0.1:9042
+protocol: !Cassandra
+  keyspace: loadgen
+  table: kv
+  create_schema: true
+connections: 4
+duration_secs: 60
+target_qps: 1000
+keys:
+  count: 1000
+  distribution: !Zipf
+    exponent: 1.1
+value_size:
+  min: 10
+  max: 100
+operations:
+  - operation: Read
+    weight: 9
+  - operation: Write
+    weight: 1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            workload,
+            Workload {
+                address: "127.0.0.1:9042".to_owned(),
+                protocol: Protocol::Cassandra {
+                    keyspace: "loadgen".to_owned(),
+                    table: "kv".to_owned(),
+                    create_schema: true,
+                },
+                connections: 4,
+                max_in_flight_per_connection: 1,
+                duration_secs: 60,
+                target_qps: Some(1000),
+                keys: Keys {
+                    count: 1000,
+                    distribution: KeyDistribution::Zipf { exponent: 1.1 },
+                    prefix: "key".to_owned(),
+                },
+                value_size: ValueSize { min: 10, max: 100 },
+                operations: vec![
+                    WeightedOperation {
+                        operation: Operation::Read,
+                        weight: 9
+                    },
+                    WeightedOperation {
+                        operation: Operation::Write,
+                        weight: 1
+                    },
+                ],
+            }
+        );
+        workload.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_workload() {
+        let workload: Workload = serde_yaml::from_str(
+            r#"
+address: 127.0.0.1:9092
+protocol: !Kafka
+  topic: loadgen
+  acks: 0
+connections: 0
+duration_secs: 10
+keys:
+  count: 1000
+value_size:
+  min: 100
+  max: 10
+operations:
+  - operation: Delete
+    weight: 1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            workload.validate().unwrap_err().to_string(),
+            "Invalid workload:
+  connections must be greater than 0
+  value_size.min (100) must not be greater than value_size.max (10)
+  the Delete operation is not supported by kafka
+  kafka acks must not be 0 as the broker does not respond to such requests, so their latency cannot be measured"
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! A plugin used by the tests that claims to be built for a different ABI version,
+//! as if it was compiled against a newer shotover-plugin.
+
+#[no_mangle]
+pub extern "C" fn shotover_plugin_abi_version() -> u32 {
+    shotover_plugin::ABI_VERSION + 1
+}
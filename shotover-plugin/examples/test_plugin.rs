@@ -0,0 +1,86 @@
+//! A plugin used by the tests of shotover and shotover-plugin to exercise every part of the plugin ABI.
+//!
+//! Requests:
+//! * containing `request-replace` are replaced with a redis `PING`
+//! * containing `request-reject` are rejected
+//! * containing `panic` cause the plugin to panic
+//!
+//! Responses:
+//! * containing `response-replace` are replaced with a redis `+REPLACED` simple string
+//! * containing `response-reject` are rejected
+//! * containing `panic` cause the plugin to panic
+//!
+//! Everything else is forwarded.
+//! Creating an instance with the config `fail` returns an error.
+
+use shotover_plugin::{Action, Plugin};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the allocations made by this library that are still live,
+/// so that the tests can check every buffer handed to shotover is returned to this library to be freed.
+struct CountingAllocator;
+
+static LIVE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[no_mangle]
+pub extern "C" fn test_plugin_live_allocations() -> usize {
+    LIVE_ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+struct TestPlugin;
+
+impl Plugin for TestPlugin {
+    fn new(config: &str) -> Result<Self, String> {
+        if config.trim() == "fail" {
+            Err("config was fail".to_owned())
+        } else {
+            Ok(TestPlugin)
+        }
+    }
+
+    fn on_request(&mut self, request: &[u8]) -> Action {
+        if contains(request, b"panic") {
+            panic!("test plugin panicked on request");
+        } else if contains(request, b"request-replace") {
+            Action::Replace(b"*1\r\n$4\r\nPING\r\n".to_vec())
+        } else if contains(request, b"request-reject") {
+            Action::Reject("request rejected by test plugin".to_owned())
+        } else {
+            Action::Forward
+        }
+    }
+
+    fn on_response(&mut self, response: &[u8]) -> Action {
+        if contains(response, b"panic") {
+            panic!("test plugin panicked on response");
+        } else if contains(response, b"response-replace") {
+            Action::Replace(b"+REPLACED\r\n".to_vec())
+        } else if contains(response, b"response-reject") {
+            Action::Reject("response rejected by test plugin".to_owned())
+        } else {
+            Action::Forward
+        }
+    }
+}
+
+fn contains(message: &[u8], needle: &[u8]) -> bool {
+    message.windows(needle.len()).any(|x| x == needle)
+}
+
+shotover_plugin::export_plugin!(TestPlugin);
@@ -0,0 +1,238 @@
+//! A stable ABI for shotover transforms that are compiled separately from shotover and loaded from a shared library at runtime.
+//!
+//! Rust does not have a stable ABI, so a plugin and shotover only ever communicate through the `extern "C"` functions generated by [`export_plugin!`].
+//! Messages cross that boundary as the raw bytes of the protocol the chain is using.
+//! This means a plugin does not need to be compiled with the same rustc version or shotover version as the shotover binary that loads it,
+//! it only needs to be compiled against a `shotover-plugin` with the same [`ABI_VERSION`].
+//!
+//! This crate is the only place shotover uses unsafe code for plugins, it exposes a safe API for both sides of the ABI:
+//! * Plugin authors implement [`Plugin`] and call [`export_plugin!`] in a crate with `crate-type = ["cdylib"]`.
+//! * Shotover uses [`LoadedPlugin`] to load the shared library and drive instances of the plugin.
+//!
+//! ```no_run
+//! use shotover_plugin::{Action, Plugin};
+//!
+//! struct DenyFlushAll;
+//!
+//! impl Plugin for DenyFlushAll {
+//!     fn new(_config: &str) -> Result<Self, String> {
+//!         Ok(DenyFlushAll)
+//!     }
+//!
+//!     fn on_request(&mut self, request: &[u8]) -> Action {
+//!         if request.windows(8).any(|x| x.eq_ignore_ascii_case(b"FLUSHALL")) {
+//!             Action::Reject("FLUSHALL is not allowed".to_owned())
+//!         } else {
+//!             Action::Forward
+//!         }
+//!     }
+//! }
+//!
+//! shotover_plugin::export_plugin!(DenyFlushAll);
+//! ```
+
+mod loader;
+
+pub use loader::{LoadedPlugin, PluginError, PluginInstance};
+
+/// Incremented whenever the signature or semantics of any exported function changes.
+/// Shotover refuses to load a plugin that was compiled against a different version.
+pub const ABI_VERSION: u32 = 1;
+
+/// What shotover should do with a message after the plugin has inspected it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Send the message on unchanged.
+    Forward,
+    /// Replace the message with these bytes, which must be a complete message in the same protocol.
+    Replace(Vec<u8>),
+    /// For a request: do not send it down the chain, instead respond to the client with this error.
+    /// For a response: replace it with this error.
+    Reject(String),
+}
+
+/// Implemented by a plugin to define its transformation logic.
+/// A new instance is created for every incoming connection, mirroring how regular transforms are built.
+pub trait Plugin: Send + Sized + 'static {
+    /// Create a new instance from the `config` field of the `Plugin` transform, serialized as yaml.
+    fn new(config: &str) -> Result<Self, String>;
+
+    /// Called with the raw bytes of each request before it is sent down the chain.
+    fn on_request(&mut self, _request: &[u8]) -> Action {
+        Action::Forward
+    }
+
+    /// Called with the raw bytes of each response before it is returned up the chain.
+    fn on_response(&mut self, _response: &[u8]) -> Action {
+        Action::Forward
+    }
+}
+
+/// A byte buffer allocated by the plugin and handed to shotover.
+/// Shotover must return it via `shotover_plugin_free_buffer` so that it is freed by the allocator that created it.
+#[doc(hidden)]
+#[repr(C)]
+pub struct PluginBuffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub cap: usize,
+}
+
+impl PluginBuffer {
+    fn from_vec(vec: Vec<u8>) -> Self {
+        let mut vec = std::mem::ManuallyDrop::new(vec);
+        PluginBuffer {
+            ptr: vec.as_mut_ptr(),
+            len: vec.len(),
+            cap: vec.capacity(),
+        }
+    }
+
+    fn empty() -> Self {
+        PluginBuffer::from_vec(vec![])
+    }
+}
+
+#[doc(hidden)]
+pub const STATUS_FORWARD: u32 = 0;
+#[doc(hidden)]
+pub const STATUS_REPLACE: u32 = 1;
+#[doc(hidden)]
+pub const STATUS_REJECT: u32 = 2;
+#[doc(hidden)]
+pub const STATUS_PANICKED: u32 = 3;
+
+/// Exports the `extern "C"` functions that make up the plugin ABI for the given [`Plugin`] implementation.
+/// Must be called exactly once in a `cdylib` crate.
+#[macro_export]
+macro_rules! export_plugin {
+    ($ty:ty) => {
+        #[no_mangle]
+        pub extern "C" fn shotover_plugin_abi_version() -> u32 {
+            $crate::ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn shotover_plugin_create(
+            config: *const u8,
+            config_len: usize,
+            error: *mut $crate::PluginBuffer,
+        ) -> *mut ::std::ffi::c_void {
+            $crate::__private::create::<$ty>(config, config_len, error)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn shotover_plugin_destroy(instance: *mut ::std::ffi::c_void) {
+            $crate::__private::destroy::<$ty>(instance)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn shotover_plugin_on_request(
+            instance: *mut ::std::ffi::c_void,
+            message: *const u8,
+            message_len: usize,
+            out: *mut $crate::PluginBuffer,
+        ) -> u32 {
+            $crate::__private::on_message::<$ty>(instance, message, message_len, out, true)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn shotover_plugin_on_response(
+            instance: *mut ::std::ffi::c_void,
+            message: *const u8,
+            message_len: usize,
+            out: *mut $crate::PluginBuffer,
+        ) -> u32 {
+            $crate::__private::on_message::<$ty>(instance, message, message_len, out, false)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn shotover_plugin_free_buffer(buffer: $crate::PluginBuffer) {
+            $crate::__private::free_buffer(buffer)
+        }
+    };
+}
+
+/// Implementation of the plugin side of the ABI, only public so that [`export_plugin!`] can call it.
+///
+/// Every function catches panics from the plugin since unwinding across an `extern "C"` boundary aborts the process.
+/// A caught panic is reported to shotover which will stop using that instance of the plugin.
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+    use std::ffi::c_void;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// # Safety
+    /// `config` must point to `config_len` readable bytes and `error` must be valid for writes.
+    pub unsafe fn create<P: Plugin>(
+        config: *const u8,
+        config_len: usize,
+        error: *mut PluginBuffer,
+    ) -> *mut c_void {
+        let config = std::slice::from_raw_parts(config, config_len);
+        let result = catch_unwind(|| {
+            let config = std::str::from_utf8(config).map_err(|e| e.to_string())?;
+            P::new(config)
+        });
+        let message = match result {
+            Ok(Ok(plugin)) => return Box::into_raw(Box::new(plugin)) as *mut c_void,
+            Ok(Err(message)) => message,
+            Err(_) => "plugin panicked while being created".to_owned(),
+        };
+        error.write(PluginBuffer::from_vec(message.into_bytes()));
+        std::ptr::null_mut()
+    }
+
+    /// # Safety
+    /// `instance` must have been returned by [`create`] with the same `P` and not yet destroyed.
+    pub unsafe fn destroy<P: Plugin>(instance: *mut c_void) {
+        let instance = Box::from_raw(instance as *mut P);
+        catch_unwind(AssertUnwindSafe(move || drop(instance))).ok();
+    }
+
+    /// # Safety
+    /// `instance` must have been returned by [`create`] with the same `P` and not yet destroyed.
+    /// `message` must point to `message_len` readable bytes and `out` must be valid for writes.
+    pub unsafe fn on_message<P: Plugin>(
+        instance: *mut c_void,
+        message: *const u8,
+        message_len: usize,
+        out: *mut PluginBuffer,
+        is_request: bool,
+    ) -> u32 {
+        let plugin = &mut *(instance as *mut P);
+        let message = std::slice::from_raw_parts(message, message_len);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            if is_request {
+                plugin.on_request(message)
+            } else {
+                plugin.on_response(message)
+            }
+        }));
+        match result {
+            Ok(Action::Forward) => {
+                out.write(PluginBuffer::empty());
+                STATUS_FORWARD
+            }
+            Ok(Action::Replace(bytes)) => {
+                out.write(PluginBuffer::from_vec(bytes));
+                STATUS_REPLACE
+            }
+            Ok(Action::Reject(error)) => {
+                out.write(PluginBuffer::from_vec(error.into_bytes()));
+                STATUS_REJECT
+            }
+            Err(_) => {
+                out.write(PluginBuffer::empty());
+                STATUS_PANICKED
+            }
+        }
+    }
+
+    /// # Safety
+    /// `buffer` must have been created by this library and not yet freed.
+    pub unsafe fn free_buffer(buffer: PluginBuffer) {
+        drop(Vec::from_raw_parts(buffer.ptr, buffer.len, buffer.cap));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{Action, PluginBuffer, ABI_VERSION, STATUS_FORWARD, STATUS_REJECT, STATUS_REPLACE};
+use libloading::Library;
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type CreateFn = unsafe extern "C" fn(*const u8, usize, *mut PluginBuffer) -> *mut c_void;
+type DestroyFn = unsafe extern "C" fn(*mut c_void);
+type OnMessageFn = unsafe extern "C" fn(*mut c_void, *const u8, usize, *mut PluginBuffer) -> u32;
+type FreeBufferFn = unsafe extern "C" fn(PluginBuffer);
+
+#[derive(thiserror::Error, Debug)]
+pub enum PluginError {
+    #[error("Failed to load plugin {path:?}: {source}")]
+    Load {
+        path: PathBuf,
+        source: libloading::Error,
+    },
+    #[error("Plugin {path:?} is not a shotover plugin, it is missing the symbol {symbol}")]
+    MissingSymbol { path: PathBuf, symbol: &'static str },
+    #[error(
+        "Plugin {path:?} was built for plugin ABI version {found} but shotover requires version {}",
+        ABI_VERSION
+    )]
+    AbiMismatch { path: PathBuf, found: u32 },
+    #[error("Plugin {path:?} could not be created: {message}")]
+    Create { path: PathBuf, message: String },
+    #[error("Plugin {path:?} panicked, this instance of the plugin will no longer be used")]
+    Panicked { path: PathBuf },
+}
+
+/// A shared library containing a plugin that has been loaded into the process.
+///
+/// Loading a library runs its initialization code and then calls into it through the exported ABI.
+/// Shotover places the same trust in plugins listed in the topology as it does in its own binary,
+/// the checks performed here guard against accidents like loading the wrong file or an outdated plugin, not against malicious libraries.
+pub struct LoadedPlugin {
+    path: PathBuf,
+    create: CreateFn,
+    destroy: DestroyFn,
+    on_request: OnMessageFn,
+    on_response: OnMessageFn,
+    free_buffer: FreeBufferFn,
+    // The function pointers above point into this library so it must outlive them.
+    _library: Library,
+}
+
+impl LoadedPlugin {
+    pub fn load(path: &Path) -> Result<Arc<Self>, PluginError> {
+        let library = unsafe { Library::new(path) }.map_err(|source| PluginError::Load {
+            path: path.to_owned(),
+            source,
+        })?;
+
+        let abi_version: AbiVersionFn = symbol(&library, path, "shotover_plugin_abi_version")?;
+        let found = unsafe { abi_version() };
+        if found != ABI_VERSION {
+            return Err(PluginError::AbiMismatch {
+                path: path.to_owned(),
+                found,
+            });
+        }
+
+        Ok(Arc::new(LoadedPlugin {
+            path: path.to_owned(),
+            create: symbol(&library, path, "shotover_plugin_create")?,
+            destroy: symbol(&library, path, "shotover_plugin_destroy")?,
+            on_request: symbol(&library, path, "shotover_plugin_on_request")?,
+            on_response: symbol(&library, path, "shotover_plugin_on_response")?,
+            free_buffer: symbol(&library, path, "shotover_plugin_free_buffer")?,
+            _library: library,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create a new instance of the plugin with the provided configuration
+    pub fn create_instance(self: &Arc<Self>, config: &str) -> Result<PluginInstance, PluginError> {
+        let mut error = PluginBuffer {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            cap: 0,
+        };
+        let instance = unsafe { (self.create)(config.as_ptr(), config.len(), &mut error) };
+        if instance.is_null() {
+            let message = String::from_utf8_lossy(&self.take_buffer(error)).into_owned();
+            return Err(PluginError::Create {
+                path: self.path.clone(),
+                message,
+            });
+        }
+
+        Ok(PluginInstance {
+            plugin: self.clone(),
+            instance,
+            poisoned: false,
+        })
+    }
+
+    /// Copy the contents of a buffer allocated by the plugin and hand it back to the plugin to be freed
+    fn take_buffer(&self, buffer: PluginBuffer) -> Vec<u8> {
+        let bytes = unsafe { std::slice::from_raw_parts(buffer.ptr, buffer.len) }.to_vec();
+        unsafe { (self.free_buffer)(buffer) };
+        bytes
+    }
+}
+
+fn symbol<T: Copy>(library: &Library, path: &Path, symbol: &'static str) -> Result<T, PluginError> {
+    unsafe { library.get::<T>(symbol.as_bytes()) }
+        .map(|x| *x)
+        .map_err(|_| PluginError::MissingSymbol {
+            path: path.to_owned(),
+            symbol,
+        })
+}
+
+/// A single instance of a plugin, created for each incoming connection.
+pub struct PluginInstance {
+    plugin: Arc<LoadedPlugin>,
+    instance: *mut c_void,
+    poisoned: bool,
+}
+
+// `Plugin` requires `Send` so the instance can be moved between threads,
+// and `&mut self` on every method ensures it is never called concurrently.
+unsafe impl Send for PluginInstance {}
+
+impl PluginInstance {
+    pub fn on_request(&mut self, request: &[u8]) -> Result<Action, PluginError> {
+        self.call(self.plugin.on_request, request)
+    }
+
+    pub fn on_response(&mut self, response: &[u8]) -> Result<Action, PluginError> {
+        self.call(self.plugin.on_response, response)
+    }
+
+    fn call(&mut self, function: OnMessageFn, message: &[u8]) -> Result<Action, PluginError> {
+        if self.poisoned {
+            return Err(PluginError::Panicked {
+                path: self.plugin.path.clone(),
+            });
+        }
+
+        let mut out = PluginBuffer {
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            cap: 0,
+        };
+        let status = unsafe { function(self.instance, message.as_ptr(), message.len(), &mut out) };
+        let bytes = self.plugin.take_buffer(out);
+        match status {
+            STATUS_FORWARD => Ok(Action::Forward),
+            STATUS_REPLACE => Ok(Action::Replace(bytes)),
+            STATUS_REJECT => Ok(Action::Reject(String::from_utf8_lossy(&bytes).into_owned())),
+            _ => {
+                // The plugin may have been left in an inconsistent state so never call into this instance again.
+                self.poisoned = true;
+                Err(PluginError::Panicked {
+                    path: self.plugin.path.clone(),
+                })
+            }
+        }
+    }
+}
+
+impl Drop for PluginInstance {
+    fn drop(&mut self) {
+        unsafe { (self.plugin.destroy)(self.instance) };
+    }
+}
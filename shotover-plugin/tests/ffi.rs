@@ -0,0 +1,162 @@
+//! Drives the plugins in `examples/` across the real shared library boundary.
+
+use libloading::Library;
+use pretty_assertions::assert_eq;
+use shotover_plugin::{Action, LoadedPlugin, PluginError, ABI_VERSION};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard, Once};
+
+/// Builds the plugins in `examples/` and returns the path to the shared library of the requested plugin.
+///
+/// The plugins are built into their own target directory so that the build neither waits on the lock held by the cargo invocation running the tests,
+/// nor depends on the profile or location of the test binary to find the built libraries.
+fn plugin_path(name: &str) -> PathBuf {
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("plugins");
+
+    static BUILD: Once = Once::new();
+    BUILD.call_once(|| {
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--examples", "--manifest-path"])
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "Failed to build the test plugins");
+    });
+
+    target_dir
+        .join("debug")
+        .join("examples")
+        .join(libloading::library_filename(name))
+}
+
+/// Every test shares the one copy of the test plugin loaded into this process,
+/// so they must run one at a time for the allocation counts to be meaningful.
+fn lock() -> MutexGuard<'static, ()> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[test]
+fn test_abi_mismatch_rejected() {
+    let _guard = lock();
+    let path = plugin_path("abi_mismatch_plugin");
+    match LoadedPlugin::load(&path).err().unwrap() {
+        PluginError::AbiMismatch {
+            path: error_path,
+            found,
+        } => {
+            assert_eq!(error_path, path);
+            assert_eq!(found, ABI_VERSION + 1);
+        }
+        err => panic!("Expected an ABI mismatch but was {err}"),
+    }
+}
+
+#[test]
+fn test_create_error() {
+    let _guard = lock();
+    let plugin = LoadedPlugin::load(&plugin_path("test_plugin")).unwrap();
+    match plugin.create_instance("fail").err().unwrap() {
+        PluginError::Create { message, .. } => assert_eq!(message, "config was fail"),
+        err => panic!("Expected a create error but was {err}"),
+    }
+}
+
+#[test]
+fn test_actions() {
+    let _guard = lock();
+    let plugin = LoadedPlugin::load(&plugin_path("test_plugin")).unwrap();
+    let mut instance = plugin.create_instance("").unwrap();
+
+    assert_eq!(instance.on_request(b"GET foo").unwrap(), Action::Forward);
+    assert_eq!(
+        instance.on_request(b"GET request-replace").unwrap(),
+        Action::Replace(b"*1\r\n$4\r\nPING\r\n".to_vec())
+    );
+    assert_eq!(
+        instance.on_request(b"GET request-reject").unwrap(),
+        Action::Reject("request rejected by test plugin".to_owned())
+    );
+
+    assert_eq!(instance.on_response(b"+foo").unwrap(), Action::Forward);
+    assert_eq!(
+        instance.on_response(b"+response-replace").unwrap(),
+        Action::Replace(b"+REPLACED\r\n".to_vec())
+    );
+    assert_eq!(
+        instance.on_response(b"+response-reject").unwrap(),
+        Action::Reject("response rejected by test plugin".to_owned())
+    );
+
+    // Each hook only acts on its own markers
+    assert_eq!(
+        instance.on_request(b"GET response-replace").unwrap(),
+        Action::Forward
+    );
+    assert_eq!(
+        instance.on_response(b"+request-reject").unwrap(),
+        Action::Forward
+    );
+}
+
+#[test]
+fn test_panic_poisons_instance() {
+    let _guard = lock();
+    let plugin = LoadedPlugin::load(&plugin_path("test_plugin")).unwrap();
+    let mut instance = plugin.create_instance("").unwrap();
+
+    assert!(matches!(
+        instance.on_request(b"GET panic"),
+        Err(PluginError::Panicked { .. })
+    ));
+    // The instance is never called into again, even for a message it would have forwarded
+    assert!(matches!(
+        instance.on_request(b"GET foo"),
+        Err(PluginError::Panicked { .. })
+    ));
+    assert!(matches!(
+        instance.on_response(b"+foo"),
+        Err(PluginError::Panicked { .. })
+    ));
+
+    // Other instances of the plugin are unaffected
+    let mut other = plugin.create_instance("").unwrap();
+    assert_eq!(other.on_request(b"GET foo").unwrap(), Action::Forward);
+    assert!(matches!(
+        other.on_response(b"+panic"),
+        Err(PluginError::Panicked { .. })
+    ));
+}
+
+#[test]
+fn test_buffers_freed_by_plugin() {
+    let _guard = lock();
+    let path = plugin_path("test_plugin");
+    let plugin = LoadedPlugin::load(&path).unwrap();
+    // Opening the library again returns the copy already loaded by `LoadedPlugin`.
+    let library = unsafe { Library::new(&path) }.unwrap();
+    let live_allocations = unsafe {
+        *library
+            .get::<unsafe extern "C" fn() -> usize>(b"test_plugin_live_allocations")
+            .unwrap()
+    };
+    let live = || unsafe { live_allocations() };
+
+    let before = live();
+    let mut instance = plugin.create_instance("").unwrap();
+    for _ in 0..100 {
+        instance.on_request(b"GET foo").unwrap();
+        instance.on_request(b"GET request-replace").unwrap();
+        instance.on_request(b"GET request-reject").unwrap();
+        instance.on_response(b"+response-replace").unwrap();
+        instance.on_response(b"+response-reject").unwrap();
+    }
+    plugin.create_instance("fail").err().unwrap();
+    drop(instance);
+
+    // Any buffer shotover failed to hand back, or freed with its own allocator, would still be counted as live.
+    assert_eq!(live(), before);
+}
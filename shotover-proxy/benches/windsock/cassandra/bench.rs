@@ -417,12 +417,14 @@ impl CassandraBench {
                     connect_timeout_ms: 3000,
                     local_shotover_host_id: "2dd022d6-2937-4754-89d6-02d2933a8f7a".parse().unwrap(),
                     read_timeout: None,
+                    keepalive_interval: None,
                     shotover_nodes: vec![ShotoverNode {
                         address: host_address.parse().unwrap(),
                         data_center: "datacenter1".to_owned(),
                         rack: "rack1".to_owned(),
                         host_id: "2dd022d6-2937-4754-89d6-02d2933a8f7a".parse().unwrap(),
                     }],
+                    split_unlogged_batches: false,
                 }));
             }
             CassandraTopology::Single => {
@@ -431,6 +433,7 @@ impl CassandraBench {
                     tls: None,
                     connect_timeout_ms: 3000,
                     read_timeout: None,
+                    keepalive_interval: None,
                 }));
             }
         }
@@ -443,7 +446,7 @@ impl CassandraBench {
                 hard_connection_limit: None,
                 tls: None,
                 timeout: None,
-                chain: TransformChainConfig(transforms),
+                chain: TransformChainConfig(transforms).into(),
                 transport: None,
             },
         ))
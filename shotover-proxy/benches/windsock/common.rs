@@ -23,6 +23,7 @@ impl Shotover {
 pub fn generate_topology(source: SourceConfig) -> String {
     ShotoverTopology {
         sources: vec![source],
+        chains: Default::default(),
     }
     .serialize()
     .unwrap()
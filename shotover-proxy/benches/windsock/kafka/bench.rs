@@ -87,11 +87,13 @@ impl KafkaBench {
                 destination_port: 9192,
                 connect_timeout_ms: 3000,
                 read_timeout: None,
+                keepalive_interval: None,
                 tls: None,
             }),
             KafkaTopology::Cluster1 | KafkaTopology::Cluster3 => Box::new(KafkaSinkClusterConfig {
                 connect_timeout_ms: 3000,
                 read_timeout: None,
+                keepalive_interval: None,
                 first_contact_points: vec![kafka_address],
                 shotover_nodes: vec![ShotoverNodeConfig {
                     address: host_address.parse().unwrap(),
@@ -110,7 +112,7 @@ impl KafkaBench {
             hard_connection_limit: None,
             tls: None,
             timeout: None,
-            chain: TransformChainConfig(transforms),
+            chain: TransformChainConfig(transforms).into(),
         }))
     }
 
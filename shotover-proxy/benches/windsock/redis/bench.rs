@@ -119,6 +119,10 @@ impl RedisBench {
                     tls: tls_connector,
                     connection_count: None,
                     connect_timeout_ms: 3000,
+                    pipelining: Default::default(),
+                    split_request_timeout_ms: None,
+                    prewarm: None,
+                    keepalive_interval: None,
                 }));
             }
             RedisTopology::Single => {
@@ -126,6 +130,8 @@ impl RedisBench {
                     address: redis_address,
                     tls: tls_connector,
                     connect_timeout_ms: 3000,
+                    read_timeout: None,
+                    keepalive_interval: None,
                 }));
             }
         }
@@ -137,7 +143,7 @@ impl RedisBench {
             hard_connection_limit: None,
             tls: tls_acceptor,
             timeout: None,
-            chain: TransformChainConfig(transforms),
+            chain: TransformChainConfig(transforms).into(),
         }))
     }
 
@@ -1,8 +1,10 @@
 mod bench;
+mod sharding;
 
 use crate::common::*;
 use crate::ShotoverBench;
 use bench::*;
+use sharding::*;
 
 pub fn benches() -> Vec<ShotoverBench> {
     itertools::iproduct!(
@@ -18,5 +20,15 @@ pub fn benches() -> Vec<ShotoverBench> {
     .map(|(topology, shotover, operation, encryption)| {
         Box::new(RedisBench::new(topology, shotover, operation, encryption)) as ShotoverBench
     })
+    .chain(
+        itertools::iproduct!(
+            [false, true],
+            [100, 1000],
+            [RedisOperation::Get, RedisOperation::Set]
+        )
+        .map(|(sharded, connections, operation)| {
+            Box::new(RedisShardingBench::new(sharded, connections, operation)) as ShotoverBench
+        }),
+    )
     .collect()
 }
@@ -0,0 +1,238 @@
+use super::bench::RedisOperation;
+use crate::{
+    cloud::{CloudResources, CloudResourcesRequired},
+    common::{self, Shotover},
+    profilers::{self, ProfilerRunner},
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use fred::prelude::*;
+use pretty_assertions::assert_eq;
+use shotover::{
+    config::chain::TransformChainConfig,
+    sources::SourceConfig,
+    transforms::{redis::sink_single::RedisSinkSingleConfig, TransformConfig},
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use test_helpers::{
+    mock_redis,
+    shotover_process::{bin_path, ShotoverProcessBuilder},
+};
+use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
+use windsock::{Bench, BenchParameters, BenchTask, Profiling, Report};
+
+/// Compares shotover with and without `--shards` at high client connection counts.
+/// Shotover sits in front of the mock redis so that the bench measures shotover rather than redis.
+pub struct RedisShardingBench {
+    sharded: bool,
+    connections: usize,
+    operation: RedisOperation,
+}
+
+impl RedisShardingBench {
+    pub fn new(sharded: bool, connections: usize, operation: RedisOperation) -> Self {
+        RedisShardingBench {
+            sharded,
+            connections,
+            operation,
+        }
+    }
+
+    fn generate_topology_yaml(&self) -> String {
+        common::generate_topology(SourceConfig::Redis(shotover::sources::redis::RedisConfig {
+            name: "redis".to_owned(),
+            listen_addr: "127.0.0.1:6379".to_owned(),
+            connection_limit: None,
+            hard_connection_limit: None,
+            tls: None,
+            timeout: None,
+            chain: TransformChainConfig(vec![Box::new(RedisSinkSingleConfig {
+                address: "127.0.0.1:1111".to_owned(),
+                tls: None,
+                connect_timeout_ms: 3000,
+                read_timeout: None,
+                keepalive_interval: None,
+            }) as Box<dyn TransformConfig>])
+            .into(),
+        }))
+    }
+}
+
+#[async_trait]
+impl Bench for RedisShardingBench {
+    type CloudResourcesRequired = CloudResourcesRequired;
+    type CloudResources = CloudResources;
+
+    fn tags(&self) -> HashMap<String, String> {
+        [
+            ("db".to_owned(), "redis".to_owned()),
+            ("topology".to_owned(), "mock".to_owned()),
+            (
+                "operation".to_owned(),
+                match &self.operation {
+                    RedisOperation::Set => "set".to_owned(),
+                    RedisOperation::Get => "get".to_owned(),
+                },
+            ),
+            (
+                "shards".to_owned(),
+                if self.sharded {
+                    "per-core".to_owned()
+                } else {
+                    "none".to_owned()
+                },
+            ),
+            ("connections".to_owned(), self.connections.to_string()),
+            Shotover::Standard.to_tag(),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    fn supported_profilers(&self) -> Vec<String> {
+        profilers::supported_profilers(Shotover::Standard)
+    }
+
+    fn cores_required(&self) -> usize {
+        2
+    }
+
+    fn required_cloud_resources(&self) -> Self::CloudResourcesRequired {
+        CloudResourcesRequired {
+            shotover_instance_count: 0,
+            docker_instance_count: 0,
+            include_shotover_in_docker_instance: false,
+        }
+    }
+
+    async fn orchestrate_cloud(
+        &self,
+        _cloud_resources: CloudResources,
+        _running_in_release: bool,
+        _profiling: Profiling,
+        _parameters: BenchParameters,
+    ) -> Result<()> {
+        Err(anyhow!(
+            "The sharding bench runs shotover against a mock redis which is only supported locally"
+        ))
+    }
+
+    async fn orchestrate_local(
+        &self,
+        _running_in_release: bool,
+        profiling: Profiling,
+        parameters: BenchParameters,
+    ) -> Result<()> {
+        let _mock = mock_redis::start(1111);
+        let mut profiler = ProfilerRunner::new(self.name(), profiling);
+
+        let topology_path = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        std::fs::write(&topology_path, self.generate_topology_yaml()).unwrap();
+        let mut builder =
+            ShotoverProcessBuilder::new_with_topology(topology_path.to_str().unwrap())
+                .with_config("config/config.yaml")
+                .with_bin(bin_path!("shotover-proxy"))
+                .with_profile(profiler.shotover_profile());
+        if self.sharded {
+            builder = builder.with_shards(std::thread::available_parallelism().unwrap().get());
+        }
+        let shotover = Some(builder.start().await);
+        profiler.run(&shotover).await;
+
+        self.execute_run("redis://127.0.0.1:6379", &parameters)
+            .await;
+
+        if let Some(shotover) = shotover {
+            shotover.shutdown_and_then_consume_events(&[]).await;
+        }
+
+        Ok(())
+    }
+
+    async fn run_bencher(
+        &self,
+        resources: &str,
+        parameters: BenchParameters,
+        reporter: UnboundedSender<Report>,
+    ) {
+        // only one string field so we just directly store the value in resources
+        let address = resources;
+
+        // Every connection in the pool is a separate client connection to shotover, requests are sent round robin across them.
+        let config = RedisConfig::from_url(address).unwrap();
+        let pool = Arc::new(RedisPool::new(config, None, None, None, self.connections).unwrap());
+        let shutdown_handle = pool.connect();
+        pool.wait_for_connect().await.unwrap();
+
+        if let RedisOperation::Get = self.operation {
+            let _: () = pool.next().set("foo", 42, None, None, false).await.unwrap();
+        }
+
+        let tasks = BenchTaskRedisPool {
+            pool: pool.clone(),
+            operation: self.operation,
+        }
+        .spawn_tasks(reporter.clone(), parameters.operations_per_second)
+        .await;
+
+        // warm up and then start
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        reporter.send(Report::Start).unwrap();
+        let start = Instant::now();
+
+        for _ in 0..parameters.runtime_seconds {
+            let second = Instant::now();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            reporter
+                .send(Report::SecondPassed(second.elapsed()))
+                .unwrap();
+        }
+
+        reporter.send(Report::FinishedIn(start.elapsed())).unwrap();
+
+        // make sure the tasks complete before we drop the database they are connecting to
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        pool.quit().await.unwrap();
+        shutdown_handle.await.unwrap().unwrap();
+    }
+}
+
+#[derive(Clone)]
+struct BenchTaskRedisPool {
+    pool: Arc<RedisPool>,
+    operation: RedisOperation,
+}
+
+#[async_trait]
+impl BenchTask for BenchTaskRedisPool {
+    async fn run_one_operation(&self) -> Result<(), String> {
+        match self.operation {
+            RedisOperation::Set => {
+                let _: () = self
+                    .pool
+                    .next()
+                    .set("foo", "bar", None, None, false)
+                    .await
+                    .map_err(|err| format!("{err}"))?;
+            }
+            RedisOperation::Get => {
+                let result: u32 = self
+                    .pool
+                    .next()
+                    .get("foo")
+                    .await
+                    .map_err(|err| format!("{err}"))?;
+                assert_eq!(result, 42);
+            }
+        }
+        Ok(())
+    }
+}
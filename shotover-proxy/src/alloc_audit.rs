@@ -0,0 +1,56 @@
+//! Counts heap allocations for auditing allocations on the hot path.
+//!
+//! The global allocator must be declared by the final binary which is why this lives here instead of in the shotover crate.
+//!
+//! The running total is reported as the `shotover_allocations_count` metric.
+//! Comparing its rate against the rate of `shotover_chain_messages_per_batch_count_sum` gives the number of allocations made per message,
+//! which can be compared between builds to measure the effect of an optimization.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+pub struct CountingAllocator;
+
+// SAFETY: all allocation is delegated to the system allocator, we only count calls.
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    // A realloc that grows a collection is counted as its own allocation,
+    // since avoiding it is as much a goal as avoiding the original allocation.
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Periodically copies the allocation count into the `shotover_allocations_count` metric.
+///
+/// The metric is updated from a plain thread rather than from the allocator itself,
+/// since recording a metric may allocate.
+pub fn start_reporting() {
+    std::thread::Builder::new()
+        .name("alloc-audit".to_owned())
+        .spawn(|| {
+            let counter = metrics::counter!("shotover_allocations_count");
+            loop {
+                counter.absolute(ALLOCATIONS.load(Ordering::Relaxed));
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        })
+        .expect("Failed to spawn alloc-audit thread");
+}
@@ -1,5 +1,18 @@
 use shotover::runner::Shotover;
 
+#[cfg(feature = "alloc-audit")]
+mod alloc_audit;
+
+#[cfg(feature = "alloc-audit")]
+#[global_allocator]
+static ALLOCATOR: alloc_audit::CountingAllocator = alloc_audit::CountingAllocator;
+
 fn main() {
-    Shotover::new().run_block();
+    let shotover = Shotover::new();
+
+    // Must start after Shotover::new has installed the metrics recorder
+    #[cfg(feature = "alloc-audit")]
+    alloc_audit::start_reporting();
+
+    shotover.run_block();
 }
@@ -1473,6 +1473,45 @@ async fn test_pubsub_conn_reuse_multisub(sub_connection: Connection) {
     assert_eq!(&res, "bar");
 }
 
+/// A WAIT that cannot be satisfied has its timeout capped, so that it holds up the other clients sharing its connections only briefly
+pub async fn test_wait_isolation(connection: &mut Connection, port: u16) {
+    let mut waiting = redis_connection::new_async("127.0.0.1", port).await;
+    let _: () = redis::cmd("SET")
+        .arg("wait_isolation")
+        .arg("1")
+        .query_async(&mut waiting)
+        .await
+        .unwrap();
+    // The cluster has far fewer than 100 replicas, so without the cap this waits forever
+    let wait = tokio::spawn(async move {
+        redis::cmd("WAIT")
+            .arg(100)
+            .arg(0)
+            .query_async::<_, i64>(&mut waiting)
+            .await
+    });
+
+    for _ in 0..10 {
+        let value: String = timeout(
+            Duration::from_secs(5),
+            redis::cmd("GET")
+                .arg("wait_isolation")
+                .query_async(connection),
+        )
+        .await
+        .expect("a WAIT from another client held up this client")
+        .unwrap();
+        assert_eq!(value, "1");
+    }
+
+    let replicas = timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("the timeout of the WAIT was not capped")
+        .unwrap()
+        .unwrap();
+    assert!(replicas < 100);
+}
+
 pub async fn run_all_cluster_hiding(connection: &mut Connection, flusher: &mut Flusher) {
     test_cluster_pipe(connection).await;
     test_pipeline_error(connection).await; //TODO: script does not seem to be loading in the server?
@@ -1556,6 +1595,14 @@ pub async fn run_all_cluster_handling(connection: &mut Connection, flusher: &mut
     test_time(connection).await;
 }
 
+/// Runs the tests that only use commands supported by `test_helpers::mock_redis`.
+pub async fn run_basic(connection: &mut Connection) {
+    test_args(connection).await;
+    test_getset(connection).await;
+    test_incr(connection).await;
+    test_ping_echo(connection).await;
+}
+
 pub async fn run_all<Fut>(connection_creator: impl Fn() -> Fut, flusher: &mut Flusher)
 where
     Fut: Future<Output = Connection>,
@@ -11,7 +11,7 @@ use std::path::Path;
 use std::thread::sleep;
 use std::time::Duration;
 use test_helpers::connection::redis_connection;
-use test_helpers::docker_compose::docker_compose;
+use test_helpers::docker_compose::{docker_compose, docker_compose_or_mock};
 use test_helpers::shotover_process::{Count, EventMatcher, Level};
 
 pub mod assert;
@@ -46,6 +46,23 @@ async fn passthrough_standard() {
         .await;
 }
 
+// Covers redis passthrough on machines without docker, where passthrough_standard cannot run.
+#[tokio::test(flavor = "multi_thread")]
+async fn passthrough_basic() {
+    let _compose =
+        docker_compose_or_mock("tests/test-configs/redis/passthrough/docker-compose.yaml");
+    let shotover = shotover_process("tests/test-configs/redis/passthrough/topology.yaml")
+        .start()
+        .await;
+    let mut connection = redis_connection::new_async("127.0.0.1", 6379).await;
+
+    run_basic(&mut connection).await;
+    test_invalid_frame().await;
+    shotover
+        .shutdown_and_then_consume_events(&[invalid_frame_event()])
+        .await;
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn passthrough_redis_down() {
     let shotover = shotover_process("tests/test-configs/redis/passthrough/topology.yaml")
@@ -217,6 +234,7 @@ async fn cluster_hiding() {
     let mut flusher = Flusher::new_cluster().await;
 
     run_all_cluster_hiding(connection, &mut flusher).await;
+    test_wait_isolation(connection, 6379).await;
     test_cluster_ports_rewrite_slots(connection, 6379).await;
     test_cluster_ports_rewrite_nodes(connection, 6379).await;
 
@@ -236,6 +254,7 @@ async fn cluster_handling() {
     let mut flusher = Flusher::new_cluster().await;
 
     run_all_cluster_handling(connection, &mut flusher).await;
+    test_wait_isolation(connection, 6379).await;
     test_cluster_ports_rewrite_slots(connection, 6379).await;
     test_cluster_ports_rewrite_nodes(connection, 6379).await;
 
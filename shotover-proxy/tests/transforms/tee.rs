@@ -1,7 +1,7 @@
 use crate::shotover_process;
 use pretty_assertions::assert_eq;
 use test_helpers::connection::redis_connection;
-use test_helpers::docker_compose::docker_compose;
+use test_helpers::docker_compose::docker_compose_or_mock;
 use test_helpers::shotover_process::{Count, EventMatcher, Level};
 
 #[tokio::test(flavor = "multi_thread")]
@@ -132,7 +132,8 @@ async fn test_fail_with_mismatch() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn test_subchain_matches() {
-    let _compose = docker_compose("tests/test-configs/redis/passthrough/docker-compose.yaml");
+    let _compose =
+        docker_compose_or_mock("tests/test-configs/redis/passthrough/docker-compose.yaml");
     let shotover = shotover_process("tests/test-configs/tee/subchain.yaml")
         .start()
         .await;
@@ -167,7 +168,8 @@ async fn test_subchain_matches() {
 
 #[tokio::test(flavor = "multi_thread")]
 async fn test_subchain_with_mismatch() {
-    let _compose = docker_compose("tests/test-configs/redis/passthrough/docker-compose.yaml");
+    let _compose =
+        docker_compose_or_mock("tests/test-configs/redis/passthrough/docker-compose.yaml");
     let shotover = shotover_process("tests/test-configs/tee/subchain_with_mismatch.yaml")
         .start()
         .await;
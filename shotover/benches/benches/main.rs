@@ -3,8 +3,10 @@ use metrics_exporter_prometheus::PrometheusBuilder;
 
 mod chain;
 mod codec;
+mod results;
 
 fn init() {
+    results::record_start();
     std::env::set_var("RUST_BACKTRACE", "1");
     std::env::set_var("RUST_LIB_BACKTRACE", "0");
 
@@ -15,5 +17,7 @@ fn init() {
 criterion_main!(
     chain::benches,
     codec::kafka::benches,
-    codec::cassandra::benches
+    codec::cassandra::benches,
+    // must be last so that it can export the results of all the other benches
+    results::export
 );
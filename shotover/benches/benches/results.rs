@@ -0,0 +1,43 @@
+use bench_results::{collect_criterion, BenchResults, Environment};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+static STARTED: OnceLock<SystemTime> = OnceLock::new();
+
+/// Called before any benchmarks run so that results left over from earlier runs can be excluded from the export.
+pub fn record_start() {
+    STARTED.get_or_init(SystemTime::now);
+}
+
+/// When SHOTOVER_BENCH_RESULTS is set, write the results of this run along with a description of the environment to the path it contains.
+/// The results can then be compared with `cargo bench-compare old.json new.json`
+pub fn export() {
+    let Ok(path) = std::env::var("SHOTOVER_BENCH_RESULTS") else {
+        return;
+    };
+    let since = STARTED.get().copied().unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let benches = collect_criterion(&criterion_dir(), since).unwrap();
+    let results = BenchResults {
+        environment: Environment::capture(env!("CARGO_PKG_VERSION")),
+        benches,
+    };
+    results.save(Path::new(&path)).unwrap();
+    println!(
+        "Exported results of {} benches to {path}",
+        results.benches.len()
+    );
+}
+
+/// Matches the logic criterion uses to decide where to store its results.
+fn criterion_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("CRITERION_HOME") {
+        return dir.into();
+    }
+    let target = match std::env::var("CARGO_TARGET_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../target"),
+    };
+    target.join("criterion")
+}
@@ -1,6 +1,6 @@
-use super::{CodecBuilder, CodecReadError, CodecWriteError, Direction};
+use super::{CodecBuilder, CodecReadError, CodecWriteError, DecoderHalf, Direction, EncoderHalf};
 use crate::codec::CodecState;
-use crate::frame::cassandra::{CassandraOperation, Tracing};
+use crate::frame::cassandra::{table_name_mut, CassandraOperation, Tracing};
 use crate::frame::{CassandraFrame, Frame, MessageType};
 use crate::message::{Encodable, Message, MessageId, Messages, Metadata};
 use anyhow::{anyhow, Result};
@@ -192,6 +192,9 @@ pub struct CassandraDecoder {
     payload_buffer: BytesMut,
     stream_id_to_request_id_rx: Option<mpsc::Receiver<StreamIdToRequestId>>,
     stream_id_to_request_id: HashMap<i16, MessageId>,
+    /// The stream id and request id of the keepalive in flight.
+    /// Kept apart from `stream_id_to_request_id` since a client request may reuse the keepalive's stream id before its response arrives.
+    keepalive: Option<(i16, MessageId)>,
 }
 
 impl CassandraDecoder {
@@ -214,6 +217,7 @@ impl CassandraDecoder {
             expected_payload_len: None,
             stream_id_to_request_id_rx,
             stream_id_to_request_id: HashMap::new(),
+            keepalive: None,
         }
     }
 }
@@ -579,6 +583,8 @@ fn set_startup_state(
     version_state.store(version.into(), Ordering::Relaxed);
 }
 
+impl DecoderHalf for CassandraDecoder {}
+
 impl Decoder for CassandraDecoder {
     type Item = Messages;
     type Error = CodecReadError;
@@ -604,8 +610,12 @@ impl Decoder for CassandraDecoder {
 
                 if let Some(rx) = &self.stream_id_to_request_id_rx {
                     while let Ok(pair) = rx.try_recv() {
-                        self.stream_id_to_request_id
-                            .insert(pair.stream_id, pair.request_id);
+                        if pair.keepalive {
+                            self.keepalive = Some((pair.stream_id, pair.request_id));
+                        } else {
+                            self.stream_id_to_request_id
+                                .insert(pair.stream_id, pair.request_id);
+                        }
                     }
                 }
 
@@ -625,9 +635,19 @@ impl Decoder for CassandraDecoder {
                     }
 
                     if !matches!(meta.opcode, Opcode::Event) {
-                        if let Some(request_id) =
-                            self.stream_id_to_request_id.remove(&meta.stream_id)
-                        {
+                        // The keepalive is an OPTIONS request so its response is the only SUPPORTED response that can be in flight,
+                        // clients only send OPTIONS while setting up the connection, before any keepalive is sent.
+                        let request_id = match self.keepalive {
+                            Some((stream_id, request_id))
+                                if stream_id == meta.stream_id
+                                    && matches!(meta.opcode, Opcode::Supported) =>
+                            {
+                                self.keepalive = None;
+                                Some(request_id)
+                            }
+                            _ => self.stream_id_to_request_id.remove(&meta.stream_id),
+                        };
+                        if let Some(request_id) = request_id {
                             message.set_request_id(request_id);
                         }
                     }
@@ -652,6 +672,7 @@ impl Decoder for CassandraDecoder {
 struct StreamIdToRequestId {
     stream_id: i16,
     request_id: MessageId,
+    keepalive: bool,
 }
 
 fn get_use_keyspace(message: &mut Message) -> Option<Identifier> {
@@ -669,47 +690,8 @@ fn set_default_keyspace(message: &mut Message, keyspace: &Identifier) {
     // TODO: rewrite Operation::Prepared in the same way
     if let Some(Frame::Cassandra(frame)) = message.frame() {
         for query in frame.operation.queries() {
-            let name = match query {
-                CassandraStatement::AlterMaterializedView(x) => &mut x.name,
-                CassandraStatement::AlterTable(x) => &mut x.name,
-                CassandraStatement::AlterType(x) => &mut x.name,
-                CassandraStatement::CreateAggregate(x) => &mut x.name,
-                CassandraStatement::CreateFunction(x) => &mut x.name,
-                CassandraStatement::CreateIndex(x) => &mut x.table,
-                CassandraStatement::CreateMaterializedView(x) => &mut x.name,
-                CassandraStatement::CreateTable(x) => &mut x.name,
-                CassandraStatement::CreateTrigger(x) => &mut x.name,
-                CassandraStatement::CreateType(x) => &mut x.name,
-                CassandraStatement::Delete(x) => &mut x.table_name,
-                CassandraStatement::DropAggregate(x) => &mut x.name,
-                CassandraStatement::DropFunction(x) => &mut x.name,
-                CassandraStatement::DropIndex(x) => &mut x.name,
-                CassandraStatement::DropMaterializedView(x) => &mut x.name,
-                CassandraStatement::DropTable(x) => &mut x.name,
-                CassandraStatement::DropTrigger(x) => &mut x.name,
-                CassandraStatement::DropType(x) => &mut x.name,
-                CassandraStatement::Insert(x) => &mut x.table_name,
-                CassandraStatement::Select(x) => &mut x.table_name,
-                CassandraStatement::Truncate(name) => name,
-                CassandraStatement::Update(x) => &mut x.table_name,
-                CassandraStatement::AlterKeyspace(_)
-                | CassandraStatement::AlterRole(_)
-                | CassandraStatement::AlterUser(_)
-                | CassandraStatement::ApplyBatch
-                | CassandraStatement::CreateKeyspace(_)
-                | CassandraStatement::CreateRole(_)
-                | CassandraStatement::CreateUser(_)
-                | CassandraStatement::DropRole(_)
-                | CassandraStatement::DropUser(_)
-                | CassandraStatement::Grant(_)
-                | CassandraStatement::ListRoles(_)
-                | CassandraStatement::Revoke(_)
-                | CassandraStatement::DropKeyspace(_)
-                | CassandraStatement::ListPermissions(_)
-                | CassandraStatement::Use(_)
-                | CassandraStatement::Unknown(_) => {
-                    return;
-                }
+            let Some(name) = table_name_mut(query) else {
+                return;
             };
             if name.keyspace.is_none() {
                 name.keyspace = Some(keyspace.clone());
@@ -747,6 +729,11 @@ pub struct CassandraEncoder {
     handshake_complete: Arc<AtomicBool>,
     message_latency: Histogram,
     stream_id_to_request_id_tx: Option<mpsc::Sender<StreamIdToRequestId>>,
+    /// Set once a request other than those used to set up the connection has been sent.
+    /// Cassandra rejects an OPTIONS request while authentication is in progress.
+    ready: bool,
+    /// The id of the last keepalive created, until it is encoded.
+    keepalive_id: Option<MessageId>,
 }
 
 impl CassandraEncoder {
@@ -765,7 +752,28 @@ impl CassandraEncoder {
             direction,
             handshake_complete,
             stream_id_to_request_id_tx,
+            ready: false,
+            keepalive_id: None,
+        }
+    }
+}
+
+impl EncoderHalf for CassandraEncoder {
+    fn keepalive_request(&mut self) -> Option<Message> {
+        if self.stream_id_to_request_id_tx.is_none() || !self.ready {
+            return None;
         }
+        // Keepalives are only sent while no other requests are pending, but a client may still send a request with the same stream id
+        // before the keepalive's response arrives. The decoder tells the two responses apart by the keepalive's SUPPORTED opcode.
+        let keepalive = Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: self.version.load(Ordering::Relaxed).into(),
+            stream_id: i16::MAX,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+            operation: CassandraOperation::Options(vec![]),
+        }));
+        self.keepalive_id = Some(keepalive.id());
+        Some(keepalive)
     }
 }
 
@@ -827,9 +835,20 @@ impl CassandraEncoder {
             let Ok(Metadata::Cassandra(meta)) = m.metadata() else {
                 unreachable!("Guaranteed to be cassandra")
             };
+            if !matches!(
+                meta.opcode,
+                Opcode::Startup | Opcode::Options | Opcode::AuthResponse
+            ) {
+                self.ready = true;
+            }
+            let keepalive = self.keepalive_id == Some(m.id);
+            if keepalive {
+                self.keepalive_id = None;
+            }
             tx.send(StreamIdToRequestId {
                 stream_id: meta.stream_id,
                 request_id: m.id,
+                keepalive,
             })
             .ok();
         }
@@ -1048,7 +1067,7 @@ impl CassandraEncoder {
 #[cfg(test)]
 mod cassandra_protocol_tests {
     use crate::codec::cassandra::CassandraCodecBuilder;
-    use crate::codec::{CodecBuilder, Direction};
+    use crate::codec::{CodecBuilder, Direction, EncoderHalf};
     use crate::frame::cassandra::{
         parse_statement_single, CassandraFrame, CassandraOperation, CassandraResult, Tracing,
     };
@@ -1062,6 +1081,7 @@ mod cassandra_protocol_tests {
         TableSpec,
     };
     use cassandra_protocol::frame::message_startup::BodyReqStartup;
+    use cassandra_protocol::frame::message_supported::BodyResSupported;
     use cassandra_protocol::frame::Version;
     use hex_literal::hex;
     use pretty_assertions::assert_eq;
@@ -1324,4 +1344,89 @@ mod cassandra_protocol_tests {
         }))];
         test_frame_codec_roundtrip(&mut codec, &bytes, messages);
     }
+
+    fn frame(stream_id: i16, tracing: Tracing, operation: CassandraOperation) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id,
+            tracing,
+            warnings: vec![],
+            operation,
+        }))
+    }
+
+    #[test]
+    fn test_keepalive_stream_id_reused_by_client() {
+        let codec = CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned());
+        let (mut decoder, mut encoder) = codec.build();
+
+        // no keepalive is sent until the connection is set up
+        assert!(encoder.keepalive_request().is_none());
+        let query = |stream_id| {
+            frame(
+                stream_id,
+                Tracing::Request(false),
+                CassandraOperation::Query {
+                    query: Box::new(parse_statement_single("SELECT * FROM system.local")),
+                    params: Box::default(),
+                },
+            )
+        };
+        let first_query = query(1);
+        let first_query_id = first_query.id();
+        encoder
+            .encode(vec![first_query], &mut BytesMut::new())
+            .unwrap();
+
+        // The client sends a request with the keepalive's stream id while the keepalive is in flight
+        let keepalive = encoder.keepalive_request().unwrap();
+        let keepalive_id = keepalive.id();
+        let second_query = query(i16::MAX);
+        let second_query_id = second_query.id();
+        encoder
+            .encode(vec![keepalive, second_query], &mut BytesMut::new())
+            .unwrap();
+
+        // Responses are encoded as if sent by cassandra
+        let (_, mut response_encoder) =
+            CassandraCodecBuilder::new(Direction::Source, "cassandra".to_owned()).build();
+        let mut responses = BytesMut::new();
+        response_encoder
+            .encode(
+                vec![
+                    frame(
+                        1,
+                        Tracing::Response(None),
+                        CassandraOperation::Result(CassandraResult::Void),
+                    ),
+                    frame(
+                        i16::MAX,
+                        Tracing::Response(None),
+                        CassandraOperation::Result(CassandraResult::Void),
+                    ),
+                    frame(
+                        i16::MAX,
+                        Tracing::Response(None),
+                        CassandraOperation::Supported(BodyResSupported {
+                            data: HashMap::new(),
+                        }),
+                    ),
+                ],
+                &mut responses,
+            )
+            .unwrap();
+
+        let mut request_ids = vec![];
+        while let Some(messages) = decoder.decode(&mut responses).unwrap() {
+            request_ids.extend(messages.iter().map(|message| message.request_id()));
+        }
+        assert_eq!(
+            request_ids,
+            vec![
+                Some(first_query_id),
+                Some(second_query_id),
+                Some(keepalive_id)
+            ]
+        );
+    }
 }
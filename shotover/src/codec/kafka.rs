@@ -1,10 +1,12 @@
 use super::{message_latency, CodecWriteError, Direction};
-use crate::codec::{CodecBuilder, CodecReadError, CodecState};
-use crate::frame::MessageType;
+use crate::codec::{CodecBuilder, CodecReadError, CodecState, DecoderHalf, EncoderHalf};
+use crate::frame::kafka::{KafkaFrame, RequestBody};
+use crate::frame::{Frame, MessageType};
 use crate::message::{Encodable, Message, MessageId, Messages};
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
-use kafka_protocol::messages::ApiKey;
+use kafka_protocol::messages::{ApiKey, ApiVersionsRequest, RequestHeader as KafkaRequestHeader};
+use kafka_protocol::protocol::Builder;
 use metrics::Histogram;
 use std::sync::mpsc;
 use std::time::Instant;
@@ -93,6 +95,8 @@ fn get_length_of_full_message(src: &BytesMut) -> Option<usize> {
     }
 }
 
+impl DecoderHalf for KafkaDecoder {}
+
 impl Decoder for KafkaDecoder {
     type Item = Messages;
     type Error = CodecReadError;
@@ -140,6 +144,9 @@ pub struct KafkaEncoder {
     // Some when Sink (because it sends requests)
     request_header_tx: Option<mpsc::Sender<RequestInfo>>,
     direction: Direction,
+    /// Set once a request other than those used to negotiate versions and authenticate has been sent.
+    /// Brokers reject requests sent unprompted while a SASL authentication is in progress.
+    ready: bool,
 }
 
 impl KafkaEncoder {
@@ -152,7 +159,25 @@ impl KafkaEncoder {
             message_latency,
             request_header_tx,
             direction,
+            ready: false,
+        }
+    }
+}
+
+impl EncoderHalf for KafkaEncoder {
+    fn keepalive_request(&mut self) -> Option<Message> {
+        if self.request_header_tx.is_none() || !self.ready {
+            return None;
         }
+        // Version 0 of ApiVersions is supported by every broker
+        Some(Message::from_frame(Frame::Kafka(KafkaFrame::Request {
+            header: KafkaRequestHeader::builder()
+                .request_api_key(ApiKey::ApiVersionsKey as i16)
+                .request_api_version(0)
+                .build()
+                .unwrap(),
+            body: RequestBody::ApiVersions(ApiVersionsRequest::default()),
+        })))
     }
 }
 
@@ -184,6 +209,11 @@ impl Encoder<Messages> for KafkaEncoder {
                     let api_key = ApiKey::try_from(api_key).map_err(|_| {
                         CodecWriteError::Encoder(anyhow!("unknown api key {api_key}"))
                     })?;
+                    match api_key {
+                        ApiKey::SaslHandshakeKey => self.ready = false,
+                        ApiKey::ApiVersionsKey | ApiKey::SaslAuthenticateKey => {}
+                        _ => self.ready = true,
+                    }
                     tx.send(RequestInfo {
                         header: RequestHeader { api_key, version },
                         id,
@@ -1,12 +1,16 @@
 //! Codec types to use for connecting to a DB in a sink transform
 
-use crate::{frame::MessageType, message::Messages};
+use crate::{
+    frame::MessageType,
+    message::{Message, Messages},
+};
 #[cfg(feature = "cassandra")]
 use cassandra_protocol::compression::Compression;
 use core::fmt;
 #[cfg(feature = "kafka")]
 use kafka::RequestHeader;
 use metrics::{histogram, Histogram};
+use std::time::Duration;
 use tokio_util::codec::{Decoder, Encoder};
 
 #[cfg(feature = "cassandra")]
@@ -113,13 +117,27 @@ impl From<std::io::Error> for CodecWriteError {
     }
 }
 
-// TODO: Replace with trait_alias (rust-lang/rust#41517).
-pub trait DecoderHalf: Decoder<Item = Messages, Error = CodecReadError> + Send {}
-impl<T: Decoder<Item = Messages, Error = CodecReadError> + Send> DecoderHalf for T {}
+pub trait DecoderHalf: Decoder<Item = Messages, Error = CodecReadError> + Send {
+    /// How long to wait for the next response while requests are pending before considering the connection dead,
+    /// given the `read_timeout` configured for the connection.
+    /// Returns `None` to wait forever.
+    ///
+    /// Codecs of protocols with requests that legitimately take a long time to respond, such as the blocking commands of redis,
+    /// override this to extend the timeout while such a request is the next to be responded to.
+    fn response_timeout(&mut self, read_timeout: Duration) -> Option<Duration> {
+        Some(read_timeout)
+    }
+}
 
-// TODO: Replace with trait_alias (rust-lang/rust#41517).
-pub trait EncoderHalf: Encoder<Messages, Error = CodecWriteError> + Send {}
-impl<T: Encoder<Messages, Error = CodecWriteError> + Send> EncoderHalf for T {}
+pub trait EncoderHalf: Encoder<Messages, Error = CodecWriteError> + Send {
+    /// A cheap request to send over an idle sink connection so that NATs and firewalls along the way do not consider it dead.
+    /// Its response is discarded by the connection.
+    /// Returns `None` if the protocol has no such request or the connection is in a state where no request can be sent unprompted,
+    /// such as during authentication.
+    fn keepalive_request(&mut self) -> Option<Message> {
+        None
+    }
+}
 
 pub trait CodecBuilder: Clone + Send {
     type Decoder: DecoderHalf;
@@ -1,4 +1,4 @@
-use super::{CodecBuilder, CodecReadError, CodecWriteError, Direction};
+use super::{CodecBuilder, CodecReadError, CodecWriteError, DecoderHalf, Direction, EncoderHalf};
 use crate::message::{Encodable, Message, Messages};
 use crate::{
     frame::{
@@ -203,6 +203,8 @@ enum State {
     ReadingBody(HttpHead, usize),
 }
 
+impl DecoderHalf for OpenSearchDecoder {}
+
 impl Decoder for OpenSearchDecoder {
     type Item = Messages;
     type Error = CodecReadError;
@@ -292,6 +294,8 @@ impl OpenSearchEncoder {
     }
 }
 
+impl EncoderHalf for OpenSearchEncoder {}
+
 impl Encoder<Messages> for OpenSearchEncoder {
     type Error = CodecWriteError;
 
@@ -1,8 +1,11 @@
-use std::sync::mpsc;
-use std::time::Instant;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
-use super::{CodecWriteError, Direction};
+use super::{CodecWriteError, DecoderHalf, Direction, EncoderHalf};
 use crate::codec::{CodecBuilder, CodecReadError};
+use crate::frame::redis::{redis_blocking, RedisBlocking};
 use crate::frame::{Frame, MessageType, RedisFrame};
 use crate::message::{Encodable, Message, MessageId, Messages};
 use anyhow::{anyhow, Result};
@@ -38,10 +41,14 @@ impl CodecBuilder for RedisCodecBuilder {
                 (Some(tx), Some(rx))
             }
         };
-        (
-            RedisDecoder::new(rx, self.direction),
-            RedisEncoder::new(tx, self.direction, self.message_latency.clone()),
-        )
+        let decoder = RedisDecoder::new(rx, self.direction);
+        let encoder = RedisEncoder::new(
+            tx,
+            self.direction,
+            self.message_latency.clone(),
+            decoder.is_subscribed.clone(),
+        );
+        (decoder, encoder)
     }
 
     fn protocol(&self) -> MessageType {
@@ -52,6 +59,7 @@ impl CodecBuilder for RedisCodecBuilder {
 pub struct RequestInfo {
     ty: RequestType,
     id: MessageId,
+    blocking: RedisBlocking,
 }
 pub enum RequestType {
     /// a pubsub subscribe
@@ -69,13 +77,31 @@ pub struct RedisEncoder {
     request_header_tx: Option<mpsc::Sender<RequestInfo>>,
     direction: Direction,
     message_latency: Histogram,
+    /// Set between a MULTI and the EXEC, DISCARD or RESET that ends the transaction,
+    /// during which a keepalive PING would be queued into the transaction
+    in_transaction: bool,
+    /// Shared with the decoder half, which sets it from the subscription count in the responses to subscribe and unsubscribe commands.
+    /// The PONG of a keepalive sent while subscribed cannot be told apart from the messages pushed to the subscription.
+    subscribed: Arc<AtomicBool>,
+    /// The reply mode set by CLIENT REPLY. While replies are off a keepalive is never responded to,
+    /// and while skipping, a keepalive would take the place of the client's next request whose reply is meant to be skipped.
+    client_reply: ClientReply,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ClientReply {
+    On,
+    Off,
+    Skip,
 }
 
 pub struct RedisDecoder {
     // Some when Sink (because it receives responses)
     request_header_rx: Option<mpsc::Receiver<RequestInfo>>,
+    /// The requests sent by the encoder half that have not yet been responded to, oldest first
+    pending_requests: VecDeque<RequestInfo>,
     direction: Direction,
-    is_subscribed: bool,
+    is_subscribed: Arc<AtomicBool>,
 }
 
 impl RedisDecoder {
@@ -86,7 +112,41 @@ impl RedisDecoder {
         Self {
             direction,
             request_header_rx,
-            is_subscribed: false,
+            pending_requests: VecDeque::new(),
+            is_subscribed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    fn receive_pending_requests(&mut self) -> Result<(), CodecReadError> {
+        if let Some(rx) = self.request_header_rx.as_ref() {
+            loop {
+                match rx.try_recv() {
+                    Ok(request_info) => self.pending_requests.push_back(request_info),
+                    Err(mpsc::TryRecvError::Empty) => return Ok(()),
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        return Err(CodecReadError::Parser(anyhow!(
+                            "redis encoder half was lost"
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DecoderHalf for RedisDecoder {
+    fn response_timeout(&mut self, read_timeout: Duration) -> Option<Duration> {
+        if self.receive_pending_requests().is_err() {
+            // The connection is shutting down, leave the error to be reported by decode
+            return Some(read_timeout);
+        }
+        // Responses are returned in the order requests were sent, so only the oldest pending request can be holding up the connection.
+        // Blocking requests are given their own timeout on top of the read timeout.
+        match self.pending_requests.front().map(|x| x.blocking) {
+            Some(RedisBlocking::For(blocking)) => Some(read_timeout + blocking),
+            Some(RedisBlocking::Forever) => None,
+            Some(RedisBlocking::No) | None => Some(read_timeout),
         }
     }
 }
@@ -133,7 +193,7 @@ impl Decoder for RedisDecoder {
                 // they have no way to collide with the `message` value of a subscription message.
                 // So while we are in subscription mode we can use that to determine if an
                 // incoming message is a subscription message.
-                let is_subscription_message = if self.is_subscribed {
+                let is_subscription_message = if self.is_subscribed.load(Ordering::Relaxed) {
                     if let Some(Frame::Redis(RedisFrame::Array(array))) = message.frame() {
                         if let [RedisFrame::BulkString(ty), ..] = array.as_slice() {
                             ty.as_ref() == b"message"
@@ -155,30 +215,34 @@ impl Decoder for RedisDecoder {
                 // In order to keep the incoming request MessageTypes in sync with their corresponding responses
                 // we must only process a MessageType when the message is not a subscription message.
                 // This is fine because subscription messages cannot affect the is_subscribed state.
-                if !is_subscription_message {
-                    if let Some(rx) = self.request_header_rx.as_ref() {
-                        let request_info = rx.recv().map_err(|_| {
-                            CodecReadError::Parser(anyhow!("redis encoder half was lost"))
-                        })?;
-                        message.set_request_id(request_info.id);
-                        match request_info.ty {
-                            RequestType::Subscribe | RequestType::Unsubscribe => {
-                                if let Some(Frame::Redis(RedisFrame::Array(array))) =
-                                    message.frame()
+                if !is_subscription_message && self.request_header_rx.is_some() {
+                    // The encoder half always sends the request info before writing the request, so it is available by the time the response arrives
+                    if self.pending_requests.is_empty() {
+                        self.receive_pending_requests()?;
+                    }
+                    let request_info = self.pending_requests.pop_front().ok_or_else(|| {
+                        CodecReadError::Parser(anyhow!(
+                            "received a redis response without a pending request"
+                        ))
+                    })?;
+                    message.set_request_id(request_info.id);
+                    match request_info.ty {
+                        RequestType::Subscribe | RequestType::Unsubscribe => {
+                            if let Some(Frame::Redis(RedisFrame::Array(array))) = message.frame() {
+                                if let Some(RedisFrame::Integer(number_of_subscribed_channels)) =
+                                    array.get(2)
                                 {
-                                    if let Some(RedisFrame::Integer(
-                                        number_of_subscribed_channels,
-                                    )) = array.get(2)
-                                    {
-                                        self.is_subscribed = *number_of_subscribed_channels != 0;
-                                    }
+                                    self.is_subscribed.store(
+                                        *number_of_subscribed_channels != 0,
+                                        Ordering::Relaxed,
+                                    );
                                 }
                             }
-                            RequestType::Reset => {
-                                self.is_subscribed = false;
-                            }
-                            RequestType::Other => {}
                         }
+                        RequestType::Reset => {
+                            self.is_subscribed.store(false, Ordering::Relaxed);
+                        }
+                        RequestType::Other => {}
                     }
                 }
                 Ok(Some(vec![message]))
@@ -193,12 +257,31 @@ impl RedisEncoder {
         request_header_tx: Option<mpsc::Sender<RequestInfo>>,
         direction: Direction,
         message_latency: Histogram,
+        subscribed: Arc<AtomicBool>,
     ) -> Self {
         Self {
             request_header_tx,
             direction,
             message_latency,
+            in_transaction: false,
+            subscribed,
+            client_reply: ClientReply::On,
+        }
+    }
+}
+
+impl EncoderHalf for RedisEncoder {
+    fn keepalive_request(&mut self) -> Option<Message> {
+        if self.request_header_tx.is_none()
+            || self.in_transaction
+            || self.subscribed.load(Ordering::Relaxed)
+            || self.client_reply != ClientReply::On
+        {
+            return None;
         }
+        Some(Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+            RedisFrame::BulkString("PING".into()),
+        ]))))
     }
 }
 
@@ -212,9 +295,41 @@ impl Encoder<Messages> for RedisEncoder {
                 .map_err(CodecWriteError::Encoder)?;
             let received_at = m.received_from_source_or_sink_at;
             if let Some(tx) = self.request_header_tx.as_ref() {
+                let blocking = match m.frame() {
+                    Some(Frame::Redis(frame)) => redis_blocking(frame),
+                    _ => RedisBlocking::No,
+                };
                 let ty = if let Some(Frame::Redis(RedisFrame::Array(array))) = m.frame() {
                     if let Some(RedisFrame::BulkString(bytes)) = array.first() {
-                        match bytes.to_ascii_uppercase().as_slice() {
+                        let command = bytes.to_ascii_uppercase();
+                        // CLIENT REPLY SKIP only skips the reply to the request that follows it
+                        if self.client_reply == ClientReply::Skip {
+                            self.client_reply = ClientReply::On;
+                        }
+                        match command.as_slice() {
+                            b"MULTI" => self.in_transaction = true,
+                            b"EXEC" | b"DISCARD" => self.in_transaction = false,
+                            b"RESET" => {
+                                self.in_transaction = false;
+                                self.client_reply = ClientReply::On;
+                            }
+                            b"CLIENT" => {
+                                if let [_, RedisFrame::BulkString(subcommand), RedisFrame::BulkString(mode)] =
+                                    array.as_slice()
+                                {
+                                    if subcommand.eq_ignore_ascii_case(b"REPLY") {
+                                        match mode.to_ascii_uppercase().as_slice() {
+                                            b"ON" => self.client_reply = ClientReply::On,
+                                            b"OFF" => self.client_reply = ClientReply::Off,
+                                            b"SKIP" => self.client_reply = ClientReply::Skip,
+                                            _ => {}
+                                        }
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                        match command.as_slice() {
                             b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" => RequestType::Subscribe,
                             b"UNSUBSCRIBE" | b"PUNSUBSCRIBE" | b"SUNSUBSCRIBE" => {
                                 RequestType::Unsubscribe
@@ -228,8 +343,12 @@ impl Encoder<Messages> for RedisEncoder {
                 } else {
                     RequestType::Other
                 };
-                tx.send(RequestInfo { ty, id: m.id() })
-                    .map_err(|e| CodecWriteError::Encoder(anyhow!(e)))?;
+                tx.send(RequestInfo {
+                    ty,
+                    id: m.id(),
+                    blocking,
+                })
+                .map_err(|e| CodecWriteError::Encoder(anyhow!(e)))?;
             }
             let result = match m.into_encodable() {
                 Encodable::Bytes(bytes) => {
@@ -259,10 +378,14 @@ impl Encoder<Messages> for RedisEncoder {
 #[cfg(test)]
 mod redis_tests {
 
-    use crate::codec::{redis::RedisCodecBuilder, CodecBuilder, Direction};
+    use crate::codec::{
+        redis::RedisCodecBuilder, CodecBuilder, DecoderHalf, Direction, EncoderHalf,
+    };
+    use crate::transforms::testing::redis_request;
     use bytes::BytesMut;
     use hex_literal::hex;
     use pretty_assertions::assert_eq;
+    use std::time::Duration;
     use tokio_util::codec::{Decoder, Encoder};
 
     const SET_MESSAGE: [u8; 45] = hex!("2a330d0a24330d0a5345540d0a2431360d0a6b65793a5f5f72616e645f696e745f5f0d0a24330d0a7878780d0a");
@@ -344,4 +467,139 @@ mod redis_tests {
     fn test_hset_codec() {
         test_frame(&HSET_MESSAGE);
     }
+
+    #[test]
+    fn test_blocking_response_timeout() {
+        let (mut decoder, mut encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        let read_timeout = Duration::from_secs(1);
+
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(
+                vec![
+                    redis_request(&["BLPOP", "list", "2"]),
+                    redis_request(&["BLPOP", "list", "0"]),
+                    redis_request(&["GET", "key"]),
+                ],
+                &mut dest,
+            )
+            .unwrap();
+
+        assert_eq!(
+            decoder.response_timeout(read_timeout),
+            Some(Duration::from_secs(3))
+        );
+        decoder.decode(&mut BytesMut::from("$-1\r\n")).unwrap();
+        assert_eq!(decoder.response_timeout(read_timeout), None);
+        decoder.decode(&mut BytesMut::from("$-1\r\n")).unwrap();
+        assert_eq!(decoder.response_timeout(read_timeout), Some(read_timeout));
+    }
+
+    #[test]
+    fn test_keepalive_subscriptions() {
+        let (mut decoder, mut encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        let mut dest = BytesMut::new();
+        assert!(encoder.keepalive_request().is_some());
+
+        encoder
+            .encode(
+                vec![
+                    redis_request(&["SUBSCRIBE", "channel"]),
+                    redis_request(&["PSUBSCRIBE", "pattern*"]),
+                ],
+                &mut dest,
+            )
+            .unwrap();
+        decoder
+            .decode(&mut BytesMut::from(
+                "*3\r\n$9\r\nsubscribe\r\n$7\r\nchannel\r\n:1\r\n",
+            ))
+            .unwrap();
+        decoder
+            .decode(&mut BytesMut::from(
+                "*3\r\n$10\r\npsubscribe\r\n$8\r\npattern*\r\n:2\r\n",
+            ))
+            .unwrap();
+        assert!(encoder.keepalive_request().is_none());
+
+        // still subscribed to the pattern
+        encoder
+            .encode(vec![redis_request(&["UNSUBSCRIBE"])], &mut dest)
+            .unwrap();
+        decoder
+            .decode(&mut BytesMut::from(
+                "*3\r\n$11\r\nunsubscribe\r\n$7\r\nchannel\r\n:1\r\n",
+            ))
+            .unwrap();
+        assert!(encoder.keepalive_request().is_none());
+
+        encoder
+            .encode(vec![redis_request(&["PUNSUBSCRIBE"])], &mut dest)
+            .unwrap();
+        decoder
+            .decode(&mut BytesMut::from(
+                "*3\r\n$12\r\npunsubscribe\r\n$8\r\npattern*\r\n:0\r\n",
+            ))
+            .unwrap();
+        assert!(encoder.keepalive_request().is_some());
+    }
+
+    #[test]
+    fn test_keepalive_client_reply() {
+        let (_decoder, mut encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        let mut dest = BytesMut::new();
+
+        encoder
+            .encode(vec![redis_request(&["CLIENT", "REPLY", "OFF"])], &mut dest)
+            .unwrap();
+        assert!(encoder.keepalive_request().is_none());
+        encoder
+            .encode(vec![redis_request(&["SET", "key", "value"])], &mut dest)
+            .unwrap();
+        assert!(encoder.keepalive_request().is_none());
+        encoder
+            .encode(vec![redis_request(&["client", "reply", "on"])], &mut dest)
+            .unwrap();
+        assert!(encoder.keepalive_request().is_some());
+
+        // only the reply to the request following the SKIP is skipped
+        encoder
+            .encode(vec![redis_request(&["CLIENT", "REPLY", "SKIP"])], &mut dest)
+            .unwrap();
+        assert!(encoder.keepalive_request().is_none());
+        encoder
+            .encode(vec![redis_request(&["SET", "key", "value"])], &mut dest)
+            .unwrap();
+        assert!(encoder.keepalive_request().is_some());
+
+        encoder
+            .encode(
+                vec![
+                    redis_request(&["CLIENT", "REPLY", "OFF"]),
+                    redis_request(&["RESET"]),
+                ],
+                &mut dest,
+            )
+            .unwrap();
+        assert!(encoder.keepalive_request().is_some());
+    }
+
+    #[test]
+    fn test_keepalive_transaction() {
+        let (_decoder, mut encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        let mut dest = BytesMut::new();
+
+        encoder
+            .encode(vec![redis_request(&["MULTI"])], &mut dest)
+            .unwrap();
+        assert!(encoder.keepalive_request().is_none());
+        encoder
+            .encode(vec![redis_request(&["EXEC"])], &mut dest)
+            .unwrap();
+        assert!(encoder.keepalive_request().is_some());
+    }
 }
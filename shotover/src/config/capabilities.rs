@@ -0,0 +1,174 @@
+//! A machine readable report of what a shotover instance is configured to do, for auditing running instances.
+
+use crate::config::chain::TransformChainConfig;
+use crate::config::topology::Topology;
+use crate::sources::SourceConfig;
+use crate::tls::TlsAcceptorConfig;
+use anyhow::Result;
+use clap::crate_version;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+#[derive(Serialize, Debug)]
+pub struct CapabilityReport {
+    pub shotover_version: &'static str,
+    /// The protocols and other cargo features this shotover binary was compiled with
+    pub features: Vec<&'static str>,
+    pub sources: Vec<SourceReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SourceReport {
+    pub name: String,
+    pub protocol: &'static str,
+    pub listen_addr: String,
+    pub tls: Option<TlsReport>,
+    /// The name of the topology chain used by this source when it is shared with other sources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared_chain: Option<String>,
+    pub transforms: Vec<TransformReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TlsReport {
+    pub certificate_path: String,
+    /// Client certificates are only verified when a certificate authority is configured
+    pub certificate_authority_path: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TransformReport {
+    pub name: String,
+    /// Hash of the transform's configuration, including any subchains.
+    /// Instances with the same hash for a transform are configured identically.
+    pub config_hash: String,
+}
+
+impl CapabilityReport {
+    pub fn new(topology: &Topology) -> Result<Self> {
+        Ok(CapabilityReport {
+            shotover_version: crate_version!(),
+            features: compiled_features(),
+            sources: topology
+                .sources
+                .iter()
+                .map(|source| SourceReport::new(source, &topology.chains))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Generate the yaml representation of this report
+    pub fn serialize(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+}
+
+impl SourceReport {
+    fn new(source: &SourceConfig, chains: &HashMap<String, TransformChainConfig>) -> Result<Self> {
+        let (protocol, listen_addr, tls, chain): (_, _, Option<&TlsAcceptorConfig>, _) =
+            match source {
+                #[cfg(feature = "cassandra")]
+                SourceConfig::Cassandra(c) => {
+                    ("cassandra", &c.listen_addr, c.tls.as_ref(), &c.chain)
+                }
+                #[cfg(feature = "redis")]
+                SourceConfig::Redis(r) => ("redis", &r.listen_addr, r.tls.as_ref(), &r.chain),
+                #[cfg(feature = "kafka")]
+                SourceConfig::Kafka(k) => ("kafka", &k.listen_addr, k.tls.as_ref(), &k.chain),
+                #[cfg(feature = "opensearch")]
+                SourceConfig::OpenSearch(o) => ("opensearch", &o.listen_addr, None, &o.chain),
+            };
+        Ok(SourceReport {
+            name: source.get_name().to_owned(),
+            protocol,
+            listen_addr: listen_addr.clone(),
+            tls: tls.map(|tls| TlsReport {
+                certificate_path: tls.certificate_path.clone(),
+                certificate_authority_path: tls.certificate_authority_path.clone(),
+            }),
+            shared_chain: chain.shared_name().map(|x| x.to_owned()),
+            transforms: transform_reports(chain.resolve(chains)?)?,
+        })
+    }
+}
+
+fn transform_reports(chain: &TransformChainConfig) -> Result<Vec<TransformReport>> {
+    chain
+        .0
+        .iter()
+        .map(|transform| {
+            let mut output = vec![];
+            let mut serializer = serde_yaml::Serializer::new(&mut output);
+            serde_yaml::with::singleton_map_recursive::serialize(transform, &mut serializer)?;
+
+            // FNV is used as it is stable across shotover versions and platforms, unlike the std hasher
+            let mut hasher = fnv::FnvHasher::default();
+            hasher.write(&output);
+            Ok(TransformReport {
+                name: transform.typetag_name().to_owned(),
+                config_hash: format!("{:016x}", hasher.finish()),
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn compiled_features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature = "cassandra") {
+        features.push("cassandra");
+    }
+    if cfg!(feature = "redis") {
+        features.push("redis");
+    }
+    if cfg!(feature = "kafka") {
+        features.push("kafka");
+    }
+    if cfg!(feature = "opensearch") {
+        features.push("opensearch");
+    }
+    if cfg!(feature = "alpha-transforms") {
+        features.push("alpha-transforms");
+    }
+    features
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::sources::redis::RedisConfig;
+    use crate::transforms::null::NullSinkConfig;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_capability_report() {
+        let topology = Topology {
+            sources: vec![SourceConfig::Redis(RedisConfig {
+                name: "redis".to_string(),
+                listen_addr: "127.0.0.1:6379".to_string(),
+                connection_limit: None,
+                hard_connection_limit: None,
+                tls: None,
+                timeout: None,
+                chain: TransformChainConfig(vec![Box::new(NullSinkConfig)]).into(),
+            })],
+            chains: HashMap::new(),
+        };
+
+        let report = CapabilityReport::new(&topology).unwrap();
+        assert_eq!(report.sources.len(), 1);
+        let source = &report.sources[0];
+        assert_eq!(source.protocol, "redis");
+        assert_eq!(source.listen_addr, "127.0.0.1:6379");
+        assert!(source.tls.is_none());
+        assert_eq!(source.transforms.len(), 1);
+        assert_eq!(source.transforms[0].name, "NullSink");
+
+        // the hash is stable for identical configuration
+        let report2 = CapabilityReport::new(&topology).unwrap();
+        assert_eq!(
+            source.transforms[0].config_hash,
+            report2.sources[0].transforms[0].config_hash
+        );
+    }
+}
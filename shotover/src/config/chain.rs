@@ -1,12 +1,16 @@
 use crate::transforms::chain::TransformChainBuilder;
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{
-    DownChainProtocol, TransformBuilder, TransformConfig, TransformContextConfig, UpChainProtocol,
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol,
 };
 use anyhow::{anyhow, Result};
 use serde::de::{DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
 use std::fmt::{self, Debug};
 use std::iter;
+use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -18,8 +22,17 @@ pub struct TransformChainConfig(
 impl TransformChainConfig {
     pub async fn get_builder(
         &self,
-        mut transform_context: TransformContextConfig,
+        transform_context: TransformContextConfig,
     ) -> Result<TransformChainBuilder> {
+        let chain_name = transform_context.chain_name.clone();
+        let transforms = self.get_transform_builders(transform_context).await?;
+        Ok(TransformChainBuilder::new(transforms, chain_name.leak()))
+    }
+
+    async fn get_transform_builders(
+        &self,
+        mut transform_context: TransformContextConfig,
+    ) -> Result<Vec<Box<dyn TransformBuilder>>> {
         let mut transforms: Vec<Box<dyn TransformBuilder>> = Vec::new();
         let mut upchain_protocol = transform_context.protocol;
         for (i, tc) in self.0.iter().enumerate() {
@@ -50,10 +63,189 @@ impl TransformChainConfig {
                 }
             }
         }
-        Ok(TransformChainBuilder::new(
-            transforms,
-            transform_context.chain_name.leak(),
-        ))
+        Ok(transforms)
+    }
+
+    /// Returns the problems found by [`TransformConfig::validate_config`] for every transform in this chain and its subchains.
+    /// Each problem is prefixed with the location of the transform, starting with `path`, the location of this chain.
+    pub fn validate_config(&self, path: &str) -> Vec<String> {
+        let mut errors = vec![];
+        for (i, transform) in self.0.iter().enumerate() {
+            let path = format!("{path}[{i}].{}", transform.typetag_name());
+            errors.extend(
+                transform
+                    .validate_config()
+                    .into_iter()
+                    .map(|error| format!("{path}: {error}")),
+            );
+            for (field, subchain) in transform.subchains() {
+                errors.extend(subchain.validate_config(&format!("{path}.{field}")));
+            }
+        }
+        errors
+    }
+}
+
+/// The chain of a source.
+/// Either defined inline as a list of transforms or as the name of one of the topology's `chains`, which can be shared by multiple sources.
+#[derive(Debug)]
+pub enum SourceChainConfig {
+    Inline(TransformChainConfig),
+    Shared(String),
+}
+
+impl From<TransformChainConfig> for SourceChainConfig {
+    fn from(chain: TransformChainConfig) -> Self {
+        SourceChainConfig::Inline(chain)
+    }
+}
+
+impl SourceChainConfig {
+    /// Returns the name of the shared chain this source uses, if any.
+    pub fn shared_name(&self) -> Option<&str> {
+        match self {
+            SourceChainConfig::Inline(_) => None,
+            SourceChainConfig::Shared(name) => Some(name),
+        }
+    }
+
+    /// Returns the chain config, looking up the chain in `chains` if it is shared.
+    pub fn resolve<'a>(
+        &'a self,
+        chains: &'a HashMap<String, TransformChainConfig>,
+    ) -> Result<&'a TransformChainConfig> {
+        match self {
+            SourceChainConfig::Inline(chain) => Ok(chain),
+            SourceChainConfig::Shared(name) => chains.get(name).ok_or_else(|| {
+                anyhow!("The chain {name:?} is not defined in the topology's chains")
+            }),
+        }
+    }
+
+    /// `transform_context.chain_name` should be the name of the source so that the chain's metrics are labelled with the source even when the chain is shared.
+    pub async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+        shared_chains: &SharedChains,
+    ) -> Result<TransformChainBuilder> {
+        match self {
+            SourceChainConfig::Inline(chain) => chain.get_builder(transform_context).await,
+            SourceChainConfig::Shared(name) => shared_chains
+                .get(name)
+                .map(|chain| chain.builder_for_source(transform_context.chain_name.leak()))
+                .ok_or_else(|| {
+                    anyhow!("The chain {name:?} is not defined in the topology's chains")
+                }),
+        }
+    }
+}
+
+impl Serialize for SourceChainConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SourceChainConfig::Inline(chain) => chain.serialize(serializer),
+            SourceChainConfig::Shared(name) => serializer.serialize_str(name),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SourceChainConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SourceChainConfigVisitor;
+
+        impl<'de> Visitor<'de> for SourceChainConfigVisitor {
+            type Value = SourceChainConfig;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("list of TransformConfig or the name of a chain")
+            }
+
+            fn visit_str<E>(self, name: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(SourceChainConfig::Shared(name.to_owned()))
+            }
+
+            fn visit_seq<S>(self, seq: S) -> Result<Self::Value, S::Error>
+            where
+                S: SeqAccess<'de>,
+            {
+                let de = serde::de::value::SeqAccessDeserializer::new(seq);
+                Ok(SourceChainConfig::Inline(TransformChainConfig(
+                    vec_transform_config(de)?,
+                )))
+            }
+        }
+
+        deserializer.deserialize_any(SourceChainConfigVisitor)
+    }
+}
+
+/// The chains shared by multiple sources, keyed by the name used to refer to them.
+pub type SharedChains = HashMap<String, SharedChain>;
+
+/// A chain whose transform builders are created once and then used by every source that refers to the chain.
+/// Any state a transform builder shares between the transforms it builds, such as sink connection pools or caches,
+/// is therefore shared between all connections of all of those sources.
+pub struct SharedChain {
+    transforms: Vec<Arc<dyn TransformBuilder>>,
+}
+
+impl SharedChain {
+    pub async fn new(
+        chain: &TransformChainConfig,
+        transform_context: TransformContextConfig,
+    ) -> Result<Self> {
+        Ok(SharedChain {
+            transforms: chain
+                .get_transform_builders(transform_context)
+                .await?
+                .into_iter()
+                .map(Arc::from)
+                .collect(),
+        })
+    }
+
+    /// Each source gets its own [`TransformChainBuilder`] so that the chain metrics stay labelled with the source name.
+    pub fn builder_for_source(&self, source_name: &'static str) -> TransformChainBuilder {
+        TransformChainBuilder::new(
+            self.transforms
+                .iter()
+                .map(|transform| {
+                    Box::new(SharedTransformBuilder(transform.clone())) as Box<dyn TransformBuilder>
+                })
+                .collect(),
+            source_name,
+        )
+    }
+}
+
+struct SharedTransformBuilder(Arc<dyn TransformBuilder>);
+
+impl TransformBuilder for SharedTransformBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        self.0.build(transform_context)
+    }
+
+    fn get_name(&self) -> &'static str {
+        self.0.get_name()
+    }
+
+    fn validate(&self) -> Vec<String> {
+        self.0.validate()
+    }
+
+    fn is_terminating(&self) -> bool {
+        self.0.is_terminating()
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        self.0.delivery_guarantees()
+    }
+
+    fn required_guarantees(&self) -> DeliveryGuarantees {
+        self.0.required_guarantees()
     }
 }
 
@@ -0,0 +1,148 @@
+//! Turns the errors from parsing configuration files into diagnostics that point at the offending line of the file,
+//! along with a suggestion when an unknown field or variant is a near miss of one that was expected.
+
+use std::fmt::Write;
+
+/// Formats `err`, raised while parsing `contents` read from `filepath`, into a diagnostic.
+pub fn yaml_error(filepath: &str, contents: &str, err: &serde_yaml::Error) -> String {
+    let mut message = err.to_string();
+    let mut diagnostic = String::new();
+
+    match err.location() {
+        Some(location) => {
+            // The location is already included in the snippet, so drop it from the message
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            if let Some(stripped) = message.strip_suffix(&suffix) {
+                message = stripped.to_owned();
+            }
+
+            writeln!(diagnostic, "{message}").unwrap();
+            writeln!(
+                diagnostic,
+                "  --> {filepath}:{}:{}",
+                location.line(),
+                location.column()
+            )
+            .unwrap();
+            if let Some(line) = contents.lines().nth(location.line().saturating_sub(1)) {
+                let number = location.line().to_string();
+                let gutter = " ".repeat(number.len());
+                writeln!(diagnostic, "{gutter} |").unwrap();
+                writeln!(diagnostic, "{number} | {line}").unwrap();
+                writeln!(
+                    diagnostic,
+                    "{gutter} | {}^",
+                    " ".repeat(location.column().saturating_sub(1))
+                )
+                .unwrap();
+            }
+        }
+        None => writeln!(diagnostic, "{message}").unwrap(),
+    }
+
+    if let Some(suggestion) = suggestion(&message) {
+        writeln!(diagnostic, "help: did you mean `{suggestion}`?").unwrap();
+    }
+
+    diagnostic.truncate(diagnostic.trim_end().len());
+    diagnostic
+}
+
+/// Parses serde's `unknown field `x`, expected one of `a`, `b`` style messages and returns the expected name closest to the unknown one.
+fn suggestion(message: &str) -> Option<&str> {
+    let start = message
+        .find("unknown field `")
+        .or_else(|| message.find("unknown variant `"))?;
+    let mut names = message[start..].split('`').skip(1).step_by(2);
+    let unknown = names.next()?;
+
+    names
+        .map(|expected| (edit_distance(unknown, expected), expected))
+        .filter(|(distance, expected)| *distance <= (unknown.len().max(expected.len()) / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, expected)| expected)
+}
+
+/// The number of single character insertions, deletions or substitutions needed to turn `a` into `b`, ignoring case.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.to_lowercase().chars().collect();
+    let b: Vec<char> = b.to_lowercase().chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("address", "address"), 0);
+        assert_eq!(edit_distance("adress", "address"), 1);
+        assert_eq!(edit_distance("NullSink", "nullsink"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn test_suggestion() {
+        assert_eq!(
+            suggestion("unknown field `conect_timeout_ms`, expected one of `address`, `tls`, `connect_timeout_ms`"),
+            Some("connect_timeout_ms")
+        );
+        assert_eq!(
+            suggestion(
+                "sources[0]: unknown variant `Reddis`, expected one of `Cassandra`, `Redis`"
+            ),
+            Some("Redis")
+        );
+        assert_eq!(suggestion("unknown field `name`, expected `address`"), None);
+        assert_eq!(suggestion("invalid type: string \"a\", expected u64"), None);
+    }
+
+    #[test]
+    fn test_yaml_error() {
+        #[derive(serde::Deserialize, Debug)]
+        #[serde(deny_unknown_fields)]
+        #[allow(dead_code)]
+        struct Sink {
+            address: String,
+            connect_timeout_ms: u64,
+        }
+
+        let contents = "address: 127.0.0.1:6379\nconect_timeout_ms: 3000\n";
+        let err = serde_yaml::from_str::<Sink>(contents).unwrap_err();
+        assert_eq!(
+            yaml_error("topology.yaml", contents, &err),
+            "unknown field `conect_timeout_ms`, expected `address` or `connect_timeout_ms`
+  --> topology.yaml:2:1
+  |
+2 | conect_timeout_ms: 3000
+  | ^
+help: did you mean `connect_timeout_ms`?"
+        );
+
+        let contents = "address: 127.0.0.1:6379\nconnect_timeout_ms: soon\n";
+        let err = serde_yaml::from_str::<Sink>(contents).unwrap_err();
+        assert_eq!(
+            yaml_error("topology.yaml", contents, &err),
+            "connect_timeout_ms: invalid type: string \"soon\", expected u64
+  --> topology.yaml:2:21
+  |
+2 | connect_timeout_ms: soon
+  |                     ^"
+        );
+    }
+}
@@ -1,9 +1,12 @@
 //! Config types, used for serializing/deserializing shotover configuration files
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use serde::Deserialize;
 
+pub mod capabilities;
 pub mod chain;
+mod diagnostics;
+pub mod self_test;
 pub mod topology;
 
 #[derive(Deserialize, Debug, Clone)]
@@ -15,9 +18,10 @@ pub struct Config {
 
 impl Config {
     pub fn from_file(filepath: String) -> Result<Config> {
-        let file = std::fs::File::open(&filepath)
+        let contents = std::fs::read_to_string(&filepath)
             .with_context(|| format!("Couldn't open the config file {}", &filepath))?;
-        serde_yaml::from_reader(file)
+        serde_yaml::from_str(&contents)
+            .map_err(|err| anyhow!(diagnostics::yaml_error(&filepath, &contents, &err)))
             .with_context(|| format!("Failed to parse config file {}", &filepath))
     }
 }
@@ -0,0 +1,330 @@
+//! Exercises the chain of each source with a probe request before shotover starts,
+//! catching misconfigurations that would otherwise only surface on the first real request.
+//!
+//! The terminating transform of each chain is replaced with a loopback sink,
+//! so the probe never reaches the database, but the probe still passes through every other transform
+//! and through the encoders and decoders of both the source and the sink.
+//! Sinks within subchains, such as those of Tee or ParallelMap, are not replaced and will attempt to connect to their destination.
+
+use crate::codec::{CodecBuilder, Direction};
+use crate::config::topology::Topology;
+use crate::frame::MessageType;
+use crate::message::{Message, Messages};
+use crate::transforms::chain::TransformChainBuilder;
+use crate::transforms::{
+    Transform, TransformBuilder, TransformContextBuilder, TransformContextConfig, Wrapper,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_util::codec::{Decoder, Encoder};
+use tokio_util::sync::CancellationToken;
+
+/// How long a probe may take to pass through a chain before the chain is considered stuck
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug)]
+pub struct SelfTestReport {
+    pub sources: Vec<SourceSelfTest>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SourceSelfTest {
+    pub name: String,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+impl SelfTestReport {
+    /// Returns true if the chain of every source passed
+    pub fn passed(&self) -> bool {
+        self.sources.iter().all(|source| source.passed)
+    }
+
+    /// Generate the yaml representation of this report
+    pub fn serialize(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+}
+
+/// Validates the topology and then sends a probe request through the chain of each source.
+/// Returns an error if the topology is invalid, otherwise returns whether the probe passed for each source.
+pub async fn self_test(topology: &Topology) -> Result<SelfTestReport> {
+    topology.validate().await?;
+    let shared_chains = topology.build_shared_chains().await?;
+
+    let mut sources = vec![];
+    for source in &topology.sources {
+        let name = source.get_name();
+        let protocol = source.get_protocol();
+        let context = TransformContextConfig {
+            chain_name: name.to_owned(),
+            protocol,
+        };
+        let result = match source
+            .get_chain()
+            .get_builder(context, &shared_chains)
+            .await
+        {
+            Ok(chain) => {
+                match tokio::time::timeout(PROBE_TIMEOUT, probe_chain(chain, name, protocol)).await
+                {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow!(
+                        "The probe did not complete within {PROBE_TIMEOUT:?}"
+                    )),
+                }
+            }
+            Err(err) => Err(err),
+        };
+        sources.push(SourceSelfTest {
+            name: name.to_owned(),
+            passed: result.is_ok(),
+            error: result.err().map(|err| format!("{err:?}")),
+        });
+    }
+    Ok(SelfTestReport { sources })
+}
+
+async fn probe_chain(
+    chain: TransformChainBuilder,
+    name: &str,
+    protocol: MessageType,
+) -> Result<()> {
+    match protocol {
+        #[cfg(feature = "redis")]
+        MessageType::Redis => {
+            // PING
+            let request = b"*1\r\n$4\r\nPING\r\n";
+            let response = b"+PONG\r\n";
+            probe::<crate::codec::redis::RedisCodecBuilder>(chain, name, request, response).await
+        }
+        #[cfg(feature = "cassandra")]
+        MessageType::Cassandra => {
+            // A v4 OPTIONS request with stream id 0
+            let request = &[0x04, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00];
+            // A v4 SUPPORTED response with no options
+            let response = &[
+                0x84, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00,
+            ];
+            probe::<crate::codec::cassandra::CassandraCodecBuilder>(chain, name, request, response)
+                .await
+        }
+        #[cfg(feature = "kafka")]
+        MessageType::Kafka => {
+            // A v0 ApiVersions request with correlation id 1 and no client id
+            let request = &[
+                0x00, 0x00, 0x00, 0x0a, 0x00, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xff, 0xff,
+            ];
+            // A v0 ApiVersions response with no error and no api keys
+            let response = &[
+                0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ];
+            probe::<crate::codec::kafka::KafkaCodecBuilder>(chain, name, request, response).await
+        }
+        #[cfg(feature = "opensearch")]
+        MessageType::OpenSearch => {
+            let request = b"GET / HTTP/1.1\r\nhost: localhost\r\ncontent-length: 0\r\n\r\n";
+            let response = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}";
+            probe::<crate::codec::opensearch::OpenSearchCodecBuilder>(
+                chain, name, request, response,
+            )
+            .await
+        }
+        MessageType::Dummy => unreachable!("Sources never use the dummy protocol"),
+    }
+}
+
+/// Decodes `request` as if a client had sent it, sends it through the chain with its sink replaced by a loopback sink answering with `response`,
+/// and then encodes the response as if sending it to the client.
+async fn probe<C: CodecBuilder + 'static>(
+    mut chain: TransformChainBuilder,
+    name: &str,
+    request: &[u8],
+    response: &'static [u8],
+) -> Result<()> {
+    let (mut source_decoder, mut source_encoder) =
+        C::new(Direction::Source, name.to_owned()).build();
+    let mut requests = source_decoder
+        .decode(&mut BytesMut::from(request))
+        .map_err(|err| anyhow!("Failed to decode the probe request: {err:?}"))?
+        .ok_or_else(|| anyhow!("The probe request was incomplete"))?;
+    for request in &mut requests {
+        if request.frame().is_none() {
+            return Err(anyhow!("Failed to parse the probe request"));
+        }
+    }
+    let request_ids: Vec<_> = requests.iter().map(|request| request.id()).collect();
+
+    let sink_codec = Mutex::new(C::new(Direction::Sink, name.to_owned()));
+    let sink = chain
+        .chain
+        .last_mut()
+        .ok_or_else(|| anyhow!("Chain cannot be empty"))?;
+    sink.builder = Box::new(LoopbackSinkBuilder {
+        respond: Arc::new(move |requests| loopback(&sink_codec, requests, response)),
+    });
+
+    let mut chain = chain.build(TransformContextBuilder {
+        force_run_chain: Arc::new(Notify::new()),
+        client_details: "self-test".to_owned(),
+        client_addr: None,
+        close_connection: CancellationToken::new(),
+    });
+    let mut responses = chain
+        .process_request(Wrapper::new_with_addr(
+            requests,
+            "127.0.0.1:0".parse().unwrap(),
+        ))
+        .await?;
+
+    for request_id in request_ids {
+        if !responses
+            .iter()
+            .any(|response| response.request_id() == Some(request_id))
+        {
+            return Err(anyhow!(
+                "The chain returned no response to the probe request"
+            ));
+        }
+    }
+    for response in &mut responses {
+        if !response.is_dummy() && response.frame().is_none() {
+            return Err(anyhow!(
+                "Failed to parse the response returned by the chain"
+            ));
+        }
+    }
+    responses.retain(|response| !response.is_dummy());
+    source_encoder
+        .encode(responses, &mut BytesMut::new())
+        .map_err(|err| anyhow!("Failed to encode the response returned by the chain: {err:?}"))
+}
+
+/// Encodes each request as if sending it to the database, and answers it by decoding `response` as if the database had sent it.
+fn loopback<C: CodecBuilder>(
+    sink_codec: &Mutex<C>,
+    requests: Messages,
+    response: &[u8],
+) -> Result<Messages> {
+    let (mut decoder, mut encoder) = sink_codec.lock().unwrap().build();
+    let mut responses = vec![];
+    for request in requests {
+        let request_id = request.id();
+        if request.is_dummy() {
+            let mut dummy = Message::from_frame(crate::frame::Frame::Dummy);
+            dummy.set_request_id(request_id);
+            responses.push(dummy);
+            continue;
+        }
+        encoder
+            .encode(vec![request], &mut BytesMut::new())
+            .map_err(|err| anyhow!("Failed to encode the request sent to the sink: {err:?}"))?;
+        let mut decoded = decoder
+            .decode(&mut BytesMut::from(response))
+            .map_err(|err| anyhow!("Failed to decode the loopback response: {err:?}"))?
+            .and_then(|mut responses| responses.pop())
+            .ok_or_else(|| anyhow!("The loopback response was incomplete"))?;
+        decoded.set_request_id(request_id);
+        responses.push(decoded);
+    }
+    Ok(responses)
+}
+
+type Respond = Arc<dyn Fn(Messages) -> Result<Messages> + Send + Sync>;
+
+struct LoopbackSinkBuilder {
+    respond: Respond,
+}
+
+const NAME: &str = "SelfTestLoopbackSink";
+
+impl TransformBuilder for LoopbackSinkBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(LoopbackSink {
+            respond: self.respond.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+}
+
+struct LoopbackSink {
+    respond: Respond,
+}
+
+#[async_trait]
+impl Transform for LoopbackSink {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        (self.respond)(requests_wrapper.requests)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::config::chain::TransformChainConfig;
+    use crate::sources::redis::RedisConfig;
+    use crate::sources::SourceConfig;
+    use crate::transforms::debug::printer::DebugPrinterConfig;
+    use crate::transforms::null::NullSinkConfig;
+    use crate::transforms::redis::sink_single::RedisSinkSingleConfig;
+    use crate::transforms::TransformConfig;
+
+    fn topology(chain: Vec<Box<dyn TransformConfig>>) -> Topology {
+        Topology {
+            sources: vec![SourceConfig::Redis(RedisConfig {
+                name: "redis".to_string(),
+                listen_addr: "127.0.0.1:0".to_string(),
+                connection_limit: None,
+                hard_connection_limit: None,
+                tls: None,
+                timeout: None,
+                chain: TransformChainConfig(chain).into(),
+            })],
+            chains: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_self_test_passes() {
+        let report = self_test(&topology(vec![
+            Box::new(DebugPrinterConfig),
+            Box::new(RedisSinkSingleConfig {
+                // nothing listens here, the self test must not connect to the destination
+                address: "127.0.0.1:1".to_owned(),
+                tls: None,
+                connect_timeout_ms: 100,
+                read_timeout: None,
+                keepalive_interval: None,
+            }),
+        ]))
+        .await
+        .unwrap();
+        assert!(report.passed(), "{report:?}");
+    }
+
+    #[tokio::test]
+    async fn test_self_test_invalid_topology() {
+        let result = self_test(&topology(vec![
+            Box::new(NullSinkConfig),
+            Box::new(NullSinkConfig),
+        ]))
+        .await;
+        assert!(result.is_err());
+    }
+}
@@ -1,7 +1,12 @@
+use crate::config::chain::{SharedChain, SharedChains, SourceChainConfig, TransformChainConfig};
+use crate::config::diagnostics;
 use crate::sources::{Source, SourceConfig};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::TransformContextConfig;
 use anyhow::{anyhow, Context, Result};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt::Write;
 use tokio::sync::watch;
 use tracing::info;
@@ -10,16 +15,21 @@ use tracing::info;
 #[serde(deny_unknown_fields)]
 pub struct Topology {
     pub sources: Vec<SourceConfig>,
+    /// Chains that can be referred to by name from the `chain` field of multiple sources.
+    /// The sources then share the chain's transforms, including their sink connection pools and caches.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub chains: HashMap<String, TransformChainConfig>,
 }
 
 impl Topology {
     /// Load the topology.yaml from the provided path into a Topology instance
     pub fn from_file(filepath: &str) -> Result<Topology> {
-        let file = std::fs::File::open(filepath)
+        let contents = std::fs::read_to_string(filepath)
             .with_context(|| format!("Couldn't open the topology file {}", filepath))?;
 
-        let deserializer = serde_yaml::Deserializer::from_reader(file);
+        let deserializer = serde_yaml::Deserializer::from_str(&contents);
         serde_yaml::with::singleton_map_recursive::deserialize(deserializer)
+            .map_err(|err| anyhow!(diagnostics::yaml_error(filepath, &contents, &err)))
             .with_context(|| format!("Failed to parse topology file {}", filepath))
     }
 
@@ -31,14 +41,7 @@ impl Topology {
         Ok(String::from_utf8(output).unwrap())
     }
 
-    pub async fn run_chains(
-        &self,
-        trigger_shutdown_rx: watch::Receiver<bool>,
-    ) -> Result<Vec<Source>> {
-        let mut sources: Vec<Source> = Vec::new();
-
-        let mut topology_errors = String::new();
-
+    fn check_source_names(&self, topology_errors: &mut String) -> Result<()> {
         let mut duplicated_names = vec![];
         for source in &self.sources {
             let name = source.get_name();
@@ -52,9 +55,170 @@ impl Topology {
                 "Source name {name:?} occurred more than once. Make sure all source names are unique. The names will be used in logging and metrics."
             )?;
         }
+        Ok(())
+    }
 
+    /// Returns the problems found by each transform's [`TransformConfig::validate_config`](crate::transforms::TransformConfig::validate_config),
+    /// these are checked before any transform is built.
+    fn transform_config_errors(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for (i, source) in self.sources.iter().enumerate() {
+            if let SourceChainConfig::Inline(chain) = source.get_chain() {
+                let path = format!("sources[{i}].{:?}.chain", source.get_protocol());
+                errors.extend(chain.validate_config(&path));
+            }
+        }
+        for (name, chain) in self.chains.iter().sorted_by_key(|(name, _)| *name) {
+            errors.extend(chain.validate_config(&format!("chains.{name}")));
+        }
+        errors
+    }
+
+    /// Builds each of the topology's `chains` that is used by at least one source.
+    /// Returns all errors found in the chains or in the references of sources to them.
+    pub async fn build_shared_chains(&self) -> Result<SharedChains> {
+        let mut errors = String::new();
         for source in &self.sources {
-            match source.get_source(trigger_shutdown_rx.clone()).await {
+            if let Some(chain_name) = source.get_chain().shared_name() {
+                if !self.chains.contains_key(chain_name) {
+                    writeln!(
+                        errors,
+                        "{} source:\n  The chain {chain_name:?} is not defined in the topology's chains",
+                        source.get_name()
+                    )?;
+                }
+            }
+        }
+
+        let mut shared_chains = SharedChains::new();
+        for (chain_name, chain) in self.chains.iter().sorted_by_key(|(name, _)| *name) {
+            let users: Vec<&SourceConfig> = self
+                .sources
+                .iter()
+                .filter(|x| x.get_chain().shared_name() == Some(chain_name.as_str()))
+                .collect();
+            let Some(first_user) = users.first() else {
+                continue;
+            };
+
+            let protocol = first_user.get_protocol();
+            if users.iter().any(|x| x.get_protocol() != protocol) {
+                writeln!(
+                    errors,
+                    "{chain_name} chain:\n  Used by sources of different protocols: {}. All sources sharing a chain must use the same protocol.",
+                    users
+                        .iter()
+                        .map(|x| format!("{} ({:?})", x.get_name(), x.get_protocol()))
+                        .join(", ")
+                )?;
+                continue;
+            }
+
+            let context = TransformContextConfig {
+                chain_name: chain_name.clone(),
+                protocol,
+            };
+            match SharedChain::new(chain, context).await {
+                Ok(shared_chain) => {
+                    shared_chains.insert(chain_name.clone(), shared_chain);
+                }
+                Err(err) => writeln!(errors, "{chain_name} chain:\n  {err:?}")?,
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(shared_chains)
+        } else {
+            Err(anyhow!(errors))
+        }
+    }
+
+    /// Runs the checks that cover the whole topology and builds its shared chains, ready for the chain of each source to be built.
+    /// Used by both [`Topology::validate`] and [`Topology::run_chains`] so that validating a topology finds the same errors as starting it.
+    ///
+    /// Errors that should not stop the sources from being checked are written to `topology_errors`,
+    /// otherwise the returned error contains every error found so far.
+    async fn check_and_build_shared_chains(
+        &self,
+        topology_errors: &mut String,
+    ) -> Result<SharedChains> {
+        self.check_source_names(topology_errors)?;
+        let config_errors = self.transform_config_errors();
+        if !config_errors.is_empty() {
+            for error in config_errors {
+                writeln!(topology_errors, "{error}")?;
+            }
+            return Err(anyhow!("Topology errors\n{topology_errors}"));
+        }
+        match self.build_shared_chains().await {
+            Ok(shared_chains) => Ok(shared_chains),
+            Err(err) => {
+                topology_errors.push_str(&err.to_string());
+                Err(anyhow!("Topology errors\n{topology_errors}"))
+            }
+        }
+    }
+
+    /// Builds the chain of every source without starting the sources.
+    /// Returns the delivery guarantees of each chain, or the errors found in the topology.
+    pub async fn validate(&self) -> Result<GuaranteesReport> {
+        let mut topology_errors = String::new();
+        let shared_chains = self
+            .check_and_build_shared_chains(&mut topology_errors)
+            .await?;
+
+        let mut sources = vec![];
+        for source in &self.sources {
+            let name = source.get_name();
+            let context = TransformContextConfig {
+                chain_name: name.to_owned(),
+                protocol: source.get_protocol(),
+            };
+            match source
+                .get_chain()
+                .get_builder(context, &shared_chains)
+                .await
+            {
+                Ok(chain) => {
+                    let errors = chain.validate();
+                    if errors.is_empty() {
+                        sources.push(SourceGuarantees {
+                            name: name.to_owned(),
+                            delivery_guarantees: chain.delivery_guarantees(),
+                        });
+                    } else {
+                        writeln!(topology_errors, "{name} source:")?;
+                        for error in errors {
+                            writeln!(topology_errors, "  {error}")?;
+                        }
+                    }
+                }
+                Err(err) => writeln!(topology_errors, "{name} source:\n  {err:?}")?,
+            }
+        }
+
+        if !topology_errors.is_empty() {
+            return Err(anyhow!("Topology errors\n{topology_errors}"));
+        }
+        Ok(GuaranteesReport { sources })
+    }
+
+    pub async fn run_chains(
+        &self,
+        trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Vec<Source>> {
+        let mut sources: Vec<Source> = Vec::new();
+
+        let mut topology_errors = String::new();
+        let shared_chains = self
+            .check_and_build_shared_chains(&mut topology_errors)
+            .await?;
+
+        for source in &self.sources {
+            match source
+                .get_source(trigger_shutdown_rx.clone(), &shared_chains)
+                .await
+            {
                 Ok(source) => sources.push(source),
                 Err(source_errors) => {
                     if !source_errors.is_empty() {
@@ -77,6 +241,25 @@ impl Topology {
     }
 }
 
+/// The delivery guarantees of the chain of each source, see [`DeliveryGuarantees`].
+#[derive(Serialize, Debug)]
+pub struct GuaranteesReport {
+    pub sources: Vec<SourceGuarantees>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SourceGuarantees {
+    pub name: String,
+    pub delivery_guarantees: DeliveryGuarantees,
+}
+
+impl GuaranteesReport {
+    /// Generate the yaml representation of this report
+    pub fn serialize(&self) -> Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+}
+
 #[cfg(all(test, feature = "redis", feature = "cassandra"))]
 mod topology_tests {
     use crate::config::chain::TransformChainConfig;
@@ -104,7 +287,7 @@ mod topology_tests {
             hard_connection_limit: None,
             tls: None,
             timeout: None,
-            chain: TransformChainConfig(chain),
+            chain: TransformChainConfig(chain).into(),
         })]
     }
 
@@ -118,7 +301,7 @@ mod topology_tests {
             hard_connection_limit: None,
             tls: None,
             timeout: None,
-            chain: TransformChainConfig(chain),
+            chain: TransformChainConfig(chain).into(),
             transport: None,
         })]
     }
@@ -128,7 +311,10 @@ mod topology_tests {
     ) -> anyhow::Result<Vec<Source>> {
         let sources = create_source_from_chain_redis(chain);
 
-        let topology = Topology { sources };
+        let topology = Topology {
+            sources,
+            chains: HashMap::new(),
+        };
 
         let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
 
@@ -140,7 +326,10 @@ mod topology_tests {
     ) -> anyhow::Result<Vec<Source>> {
         let sources = create_source_from_chain_cassandra(chain);
 
-        let topology = Topology { sources };
+        let topology = Topology {
+            sources,
+            chains: HashMap::new(),
+        };
 
         let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
 
@@ -273,6 +462,10 @@ foo source:
                     Box::new(NullSinkConfig),
                 ]),
                 caching_schema,
+                negative_ttl_seconds: None,
+                ttl_seconds: None,
+                stale_while_revalidate_seconds: None,
+                ttl_jitter: None,
             }),
             Box::new(NullSinkConfig),
         ])
@@ -301,6 +494,10 @@ foo source:
                     Box::new(NullSinkConfig),
                 ]),
                 caching_schema: HashMap::new(),
+                negative_ttl_seconds: None,
+                ttl_seconds: None,
+                stale_while_revalidate_seconds: None,
+                ttl_jitter: None,
             }),
             Box::new(NullSinkConfig),
         ])
@@ -311,6 +508,28 @@ foo source:
         assert_eq!(error, expected);
     }
 
+    #[tokio::test]
+    async fn test_validate_reports_delivery_guarantees() {
+        let topology = Topology {
+            sources: create_source_from_chain_redis(vec![
+                Box::new(DebugPrinterConfig),
+                Box::new(NullSinkConfig),
+            ]),
+            chains: HashMap::new(),
+        };
+
+        let report = topology.validate().await.unwrap().serialize().unwrap();
+        assert_eq!(
+            report,
+            r#"sources:
+- name: foo
+  delivery_guarantees:
+    ordering: Preserved
+    delivery: AtMostOnce
+"#
+        );
+    }
+
     #[tokio::test]
     async fn test_validate_chain_valid_subchain_parallel_map() {
         run_test_topology_redis(vec![
@@ -469,17 +688,178 @@ Source name "foo" occurred more than once. Make sure all source names are unique
             NullSinkConfig,
         )]));
 
-        let topology = Topology { sources };
+        let topology = Topology {
+            sources,
+            chains: HashMap::new(),
+        };
+        let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
+        let error = topology
+            .run_chains(trigger_shutdown_rx)
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(error, expected);
+    }
+
+    fn topology_from_yaml(yaml: &str) -> Topology {
+        let deserializer = serde_yaml::Deserializer::from_str(yaml);
+        serde_yaml::with::singleton_map_recursive::deserialize(deserializer).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shared_chain() {
+        let topology = topology_from_yaml(
+            r#"
+sources:
+  - Redis:
+      name: "redis-tls"
+      listen_addr: "127.0.0.1:0"
+      chain: main
+  - Redis:
+      name: "redis-plain"
+      listen_addr: "127.0.0.1:0"
+      chain: main
+chains:
+  main:
+    - DebugPrinter
+    - NullSink
+"#,
+        );
+
+        let report = topology.validate().await.unwrap().serialize().unwrap();
+        assert_eq!(
+            report,
+            r#"sources:
+- name: redis-tls
+  delivery_guarantees:
+    ordering: Preserved
+    delivery: AtMostOnce
+- name: redis-plain
+  delivery_guarantees:
+    ordering: Preserved
+    delivery: AtMostOnce
+"#
+        );
+
+        let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
+        let sources = topology.run_chains(trigger_shutdown_rx).await.unwrap();
+        assert_eq!(sources.len(), 2);
+
+        // shared chains are written back out by name
+        let serialized = topology.serialize().unwrap();
+        assert!(serialized.contains("chain: main\n"), "{serialized}");
+    }
+
+    #[tokio::test]
+    async fn test_shared_chain_errors() {
+        let topology = topology_from_yaml(
+            r#"
+sources:
+  - Redis:
+      name: "redis"
+      listen_addr: "127.0.0.1:0"
+      chain: main
+  - Cassandra:
+      name: "cassandra"
+      listen_addr: "127.0.0.1:0"
+      chain: main
+  - Redis:
+      name: "redis-typo"
+      listen_addr: "127.0.0.1:0"
+      chain: mian
+chains:
+  main:
+    - NullSink
+  invalid:
+    - DebugPrinter
+"#,
+        );
+
+        let expected = r#"Topology errors
+redis-typo source:
+  The chain "mian" is not defined in the topology's chains
+main chain:
+  Used by sources of different protocols: redis (Redis), cassandra (Cassandra). All sources sharing a chain must use the same protocol.
+"#;
+        let error = topology.validate().await.unwrap_err().to_string();
+        assert_eq!(error, expected);
+
+        // Starting the topology fails with the same errors that validating it reports
         let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
         let error = topology
             .run_chains(trigger_shutdown_rx)
             .await
             .unwrap_err()
             .to_string();
+        assert_eq!(error, expected);
+    }
 
+    #[tokio::test]
+    async fn test_validate_transform_configs() {
+        let topology = topology_from_yaml(
+            r#"
+sources:
+  - Cassandra:
+      name: "cassandra"
+      listen_addr: "127.0.0.1:0"
+      chain:
+        - RedisCache:
+            caching_schema: {}
+            ttl_jitter: 1.5
+            stale_while_revalidate_seconds: 10
+            chain:
+              - RedisTtlPolicy:
+                  rules:
+                    - key_pattern: "*"
+              - NullSink
+        - NullSink
+"#,
+        );
+
+        let expected = r#"Topology errors
+sources[0].Cassandra.chain[0].RedisCache: ttl_jitter must be at least 0 and less than 1 but was 1.5
+sources[0].Cassandra.chain[0].RedisCache: stale_while_revalidate_seconds requires ttl_seconds or negative_ttl_seconds to be set
+sources[0].Cassandra.chain[0].RedisCache.chain[0].RedisTtlPolicy: rule for "*" does not enforce anything, set at least one of default_ttl_seconds, max_ttl_seconds or deny_persist
+"#;
+        let error = topology.validate().await.unwrap_err().to_string();
+        assert_eq!(error, expected);
+
+        // Starting the topology fails with the same errors that validating it reports
+        let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
+        let error = topology
+            .run_chains(trigger_shutdown_rx)
+            .await
+            .unwrap_err()
+            .to_string();
         assert_eq!(error, expected);
     }
 
+    #[test]
+    fn test_parse_error_diagnostic() {
+        let error =
+            Topology::from_file("../shotover-proxy/tests/test-configs/invalid_field_typo.yaml")
+                .unwrap_err();
+        let diagnostic = error.root_cause().to_string();
+        assert!(
+            diagnostic.contains("unknown field `conect_timeout_ms`"),
+            "{diagnostic}"
+        );
+        assert!(
+            diagnostic
+                .contains("  --> ../shotover-proxy/tests/test-configs/invalid_field_typo.yaml:9:"),
+            "{diagnostic}"
+        );
+        assert!(
+            diagnostic.contains("9 |             conect_timeout_ms: 3000"),
+            "{diagnostic}"
+        );
+        assert!(
+            diagnostic.ends_with("help: did you mean `connect_timeout_ms`?"),
+            "{diagnostic}"
+        );
+    }
+
     #[tokio::test]
     async fn test_validate_chain_multiple_subchains() {
         let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
@@ -1,11 +1,12 @@
 //! All Sink transforms use SinkConnection for their outgoing connections.
 
-use crate::codec::{CodecBuilder, CodecReadError, CodecWriteError};
+use crate::codec::{CodecBuilder, CodecReadError, CodecWriteError, DecoderHalf, EncoderHalf};
 use crate::frame::Frame;
 use crate::message::{Message, MessageId, Messages};
 use crate::tcp;
 use crate::tls::{TlsConnector, ToHostname};
 use futures::{SinkExt, StreamExt};
+use metrics::counter;
 use std::io::ErrorKind;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
@@ -14,7 +15,7 @@ use tokio::io::{split, AsyncRead, AsyncWrite};
 use tokio::net::ToSocketAddrs;
 use tokio::sync::mpsc::error::TryRecvError;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tokio::sync::{mpsc, Notify};
+use tokio::sync::{mpsc, oneshot, Notify};
 use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::error;
 use tracing::Instrument;
@@ -22,9 +23,12 @@ use tracing::Instrument;
 pub struct SinkConnection {
     in_rx: mpsc::Receiver<Vec<Message>>,
     out_tx: mpsc::UnboundedSender<Vec<Message>>,
+    take_unwritten_tx: mpsc::UnboundedSender<oneshot::Sender<Messages>>,
     connection_closed_rx: mpsc::Receiver<ConnectionError>,
     error: Option<ConnectionError>,
     dummy_response_inserter: DummyResponseInserter,
+    /// Dropped along with the SinkConnection to tell the writer task to discard the requests it has not written yet.
+    _writer_cancel_tx: oneshot::Sender<()>,
 }
 
 impl SinkConnection {
@@ -35,11 +39,14 @@ impl SinkConnection {
         connect_timeout: Duration,
         force_run_chain: Arc<Notify>,
         read_timeout: Option<Duration>,
+        keepalive_interval: Option<Duration>,
     ) -> anyhow::Result<Self> {
         let destination = tokio::net::lookup_host(&host).await?.next().unwrap();
         let (in_tx, in_rx) = mpsc::channel::<Messages>(10_000);
         let (out_tx, out_rx) = mpsc::unbounded_channel::<Messages>();
+        let (take_unwritten_tx, take_unwritten_rx) = mpsc::unbounded_channel();
         let (connection_closed_tx, connection_closed_rx) = mpsc::channel(1);
+        let (writer_cancel_tx, writer_cancel_rx) = oneshot::channel();
 
         if let Some(tls) = tls.as_ref() {
             let tls_stream = tls.connect(connect_timeout, host).await?;
@@ -51,9 +58,12 @@ impl SinkConnection {
                 in_tx,
                 out_rx,
                 out_tx.clone(),
+                take_unwritten_rx,
+                writer_cancel_rx,
                 force_run_chain,
                 connection_closed_tx,
                 read_timeout,
+                keepalive_interval,
             );
         } else {
             let tcp_stream = tcp::tcp_stream(connect_timeout, destination).await?;
@@ -65,9 +75,12 @@ impl SinkConnection {
                 in_tx,
                 out_rx,
                 out_tx.clone(),
+                take_unwritten_rx,
+                writer_cancel_rx,
                 force_run_chain,
                 connection_closed_tx,
                 read_timeout,
+                keepalive_interval,
             );
         }
 
@@ -76,9 +89,11 @@ impl SinkConnection {
         Ok(SinkConnection {
             in_rx,
             out_tx,
+            take_unwritten_tx,
             connection_closed_rx,
             error: None,
             dummy_response_inserter,
+            _writer_cancel_tx: writer_cancel_tx,
         })
     }
 
@@ -99,6 +114,21 @@ impl SinkConnection {
         }
     }
 
+    /// Takes back the sent requests that are still queued and have not yet been written to the connection,
+    /// so that they can be sent through another connection instead, e.g. when the destination is being drained.
+    /// Responses to the requests that were already written are received from this connection as normal.
+    pub async fn take_unwritten(&mut self) -> Messages {
+        let (tx, rx) = oneshot::channel();
+        if self.take_unwritten_tx.send(tx).is_err() {
+            // The writer task has terminated, the connection error will be returned by the next send or recv
+            return vec![];
+        }
+        let unwritten = rx.await.unwrap_or_default();
+        self.dummy_response_inserter
+            .forget_unwritten_requests(unwritten.len());
+        unwritten
+    }
+
     /// Receives messages, if there are no messages available it awaits until there are messages.
     /// If there is a problem with the connection an error is returned.
     pub async fn recv(&mut self) -> Result<Vec<Message>, ConnectionError> {
@@ -241,9 +271,12 @@ fn spawn_read_write_tasks<
     in_tx: mpsc::Sender<Messages>,
     out_rx: UnboundedReceiver<Messages>,
     out_tx: UnboundedSender<Messages>,
+    take_unwritten_rx: UnboundedReceiver<oneshot::Sender<Messages>>,
+    writer_cancel_rx: oneshot::Receiver<()>,
     force_run_chain: Arc<Notify>,
     connection_closed_tx: mpsc::Sender<ConnectionError>,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
 ) {
     let (decoder, encoder) = codec.build();
     let reader = FramedRead::new(rx, decoder);
@@ -253,13 +286,14 @@ fn spawn_read_write_tasks<
         notify: Notify::new(),
         count: 0.into(),
     });
+    let (keepalive_tx, keepalive_rx) = mpsc::unbounded_channel();
 
     // Shutdown flows
     //
     // The Connection is dropped:
-    // 1. The Connection is dropped, dropping in_rx and the first out_tx
-    // 2. The reader task detects that in_rx has dropped and terminates, the last out_tx instance is dropped
-    // 3. The writer task detects that the last out_tx is dropped by out_rx returning None and terminates
+    // 1. The Connection is dropped, dropping in_rx, the first out_tx and writer_cancel_tx
+    // 2. The reader task detects that in_rx has dropped and terminates
+    // 3. The writer task detects that writer_cancel_tx has dropped, writes only the queued requests that receive no response and terminates
     //
     // Client closes connection and then shotover tries to receive:
     // 1.   The reader task detects that the client has closed the connection via reader returning None and terminates,
@@ -292,6 +326,7 @@ fn spawn_read_write_tasks<
                 force_run_chain,
                 request_pending2,
                 read_timeout,
+                keepalive_rx,
             )
             .await
             {
@@ -306,7 +341,17 @@ fn spawn_read_write_tasks<
 
     tokio::spawn(
         async move {
-            match writer_task::<C, _>(writer, out_rx, request_pending).await {
+            match writer_task::<C, _>(
+                writer,
+                out_rx,
+                take_unwritten_rx,
+                writer_cancel_rx,
+                request_pending,
+                keepalive_interval,
+                keepalive_tx,
+            )
+            .await
+            {
                 Ok(()) => {}
                 Err(err) => {
                     connection_closed_tx.try_send(err).ok();
@@ -324,7 +369,9 @@ async fn reader_task<C: CodecBuilder + 'static, R: AsyncRead + Unpin + Send + 's
     force_run_chain: Arc<Notify>,
     request_pending: Arc<RequestPending>,
     read_timeout: Option<Duration>,
+    mut keepalive_rx: UnboundedReceiver<MessageId>,
 ) -> Result<(), ConnectionError> {
+    let mut keepalive_ids = vec![];
     loop {
         let read_timeout = if request_pending.get() == 0 {
             // There are no requests pending so we should not trigger a timeout.
@@ -332,7 +379,9 @@ async fn reader_task<C: CodecBuilder + 'static, R: AsyncRead + Unpin + Send + 's
             None
         } else {
             // There are requests pending so we need to timeout after the configure timeout elapses.
+            // The codec may extend it while waiting on a request that legitimately takes a long time, such as a redis BLPOP.
             read_timeout
+                .and_then(|read_timeout| reader.decoder_mut().response_timeout(read_timeout))
         };
         tokio::select! {
             biased;
@@ -345,10 +394,21 @@ async fn reader_task<C: CodecBuilder + 'static, R: AsyncRead + Unpin + Send + 's
             result = reader.next() => {
                 if let Some(messages) = result {
                     match messages {
-                        Ok(messages) => {
+                        Ok(mut messages) => {
                             let count = messages.iter().filter(|x| x.request_id.is_some()).count();
                             request_pending.sub(count as u64);
 
+                            // The writer task registers each keepalive before writing it, so its id is always received before its response.
+                            while let Ok(id) = keepalive_rx.try_recv() {
+                                keepalive_ids.push(id);
+                            }
+                            if !keepalive_ids.is_empty() {
+                                messages.retain(|message| !take_keepalive_response(&mut keepalive_ids, message));
+                                if messages.is_empty() {
+                                    continue;
+                                }
+                            }
+
                             if in_tx.send(messages).await.is_err() {
                                 // main task has shutdown, this task is no longer needed
                                 return Ok(());
@@ -384,6 +444,19 @@ async fn reader_task<C: CodecBuilder + 'static, R: AsyncRead + Unpin + Send + 's
     }
 }
 
+/// Returns true if `message` is the response to one of the keepalives in `keepalive_ids`, removing that keepalive
+fn take_keepalive_response(keepalive_ids: &mut Vec<MessageId>, message: &Message) -> bool {
+    let Some(request_id) = message.request_id() else {
+        return false;
+    };
+    if let Some(index) = keepalive_ids.iter().position(|id| *id == request_id) {
+        keepalive_ids.swap_remove(index);
+        true
+    } else {
+        false
+    }
+}
+
 async fn sleep_for_duration_or_forever(duration: Option<Duration>) {
     if let Some(duration) = duration {
         tokio::time::sleep(duration).await
@@ -392,33 +465,97 @@ async fn sleep_for_duration_or_forever(duration: Option<Duration>) {
     }
 }
 
+/// Writes requests to the connection.
+///
+/// Any batches that were queued while the previous write was in progress are encoded into the write buffer together and flushed once,
+/// so under load many small requests are sent to the destination in a single write instead of one write each.
+///
+/// When requested through `take_unwritten_rx`, the batches still queued are returned instead of being written.
+///
+/// When the `SinkConnection` is dropped, e.g. because the client it was serving disconnected, nobody is left to receive the responses to the queued requests.
+/// So they are discarded instead of being written, except for requests that never receive a response, such as kafka produce requests with `acks=0`,
+/// which the client considers sent as soon as shotover has received them.
+///
+/// When `keepalive_interval` elapses without any requests being written or pending, the codec's keepalive request is written.
+/// Its id is sent to the reader task through `keepalive_tx` so that the response is discarded instead of being returned to the transform.
 async fn writer_task<C: CodecBuilder + 'static, W: AsyncWrite + Unpin + Send + 'static>(
     mut writer: FramedWrite<W, <C as CodecBuilder>::Encoder>,
     mut out_rx: UnboundedReceiver<Messages>,
+    mut take_unwritten_rx: UnboundedReceiver<oneshot::Sender<Messages>>,
+    mut writer_cancel_rx: oneshot::Receiver<()>,
     request_pending: Arc<RequestPending>,
+    keepalive_interval: Option<Duration>,
+    keepalive_tx: UnboundedSender<MessageId>,
 ) -> Result<(), ConnectionError> {
     loop {
-        if let Some(messages) = out_rx.recv().await {
-            request_pending.add(messages.len() as u64);
-            match writer.send(messages).await {
-                Err(CodecWriteError::Encoder(err)) => {
-                    return Err(ConnectionError::MessageEncode(Arc::new(err)));
+        tokio::select! {
+            biased;
+            _ = &mut writer_cancel_rx => {
+                let mut no_response = vec![];
+                let mut abandoned = 0;
+                while let Ok(messages) = out_rx.try_recv() {
+                    for mut message in messages {
+                        if message.response_is_dummy() {
+                            no_response.push(message);
+                        } else {
+                            abandoned += 1;
+                        }
+                    }
                 }
-                Err(CodecWriteError::Io(err)) => {
-                    if matches!(
-                        err.kind(),
-                        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
-                    ) {
-                        return Err(ConnectionError::OtherSideClosed);
-                    } else {
-                        return Err(ConnectionError::Io(Arc::new(err)));
+                counter!("shotover_abandoned_upstream_requests_count").increment(abandoned);
+                if !no_response.is_empty() {
+                    writer.send(no_response).await.map_err(write_error)?;
+                }
+                return Ok(());
+            }
+            Some(unwritten_tx) = take_unwritten_rx.recv() => {
+                let mut unwritten = vec![];
+                while let Ok(messages) = out_rx.try_recv() {
+                    unwritten.extend(messages);
+                }
+                unwritten_tx.send(unwritten).ok();
+            }
+            messages = out_rx.recv() => {
+                if let Some(messages) = messages {
+                    request_pending.add(messages.len() as u64);
+                    writer.feed(messages).await.map_err(write_error)?;
+
+                    while let Ok(messages) = out_rx.try_recv() {
+                        request_pending.add(messages.len() as u64);
+                        writer.feed(messages).await.map_err(write_error)?;
                     }
+
+                    writer.flush().await.map_err(write_error)?;
+                } else {
+                    // shotover is no longer sending responses, this task is no longer needed
+                    return Ok(());
                 }
-                Ok(()) => {}
             }
-        } else {
-            // shotover is no longer sending responses, this task is no longer needed
-            return Ok(());
+            _ = sleep_for_duration_or_forever(keepalive_interval) => {
+                if request_pending.get() == 0 {
+                    if let Some(keepalive) = writer.encoder_mut().keepalive_request() {
+                        keepalive_tx.send(keepalive.id()).ok();
+                        request_pending.add(1);
+                        writer.send(vec![keepalive]).await.map_err(write_error)?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn write_error(err: CodecWriteError) -> ConnectionError {
+    match err {
+        CodecWriteError::Encoder(err) => ConnectionError::MessageEncode(Arc::new(err)),
+        CodecWriteError::Io(err) => {
+            if matches!(
+                err.kind(),
+                ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+            ) {
+                ConnectionError::OtherSideClosed
+            } else {
+                ConnectionError::Io(Arc::new(err))
+            }
         }
     }
 }
@@ -457,6 +594,15 @@ impl DummyResponseInserter {
         self.pending_requests_count += requests.len();
     }
 
+    /// Forgets the last `count` requests passed to process_requests,
+    /// they were taken back before being written so they will never receive a response.
+    fn forget_unwritten_requests(&mut self, count: usize) {
+        self.pending_requests_count -= count;
+        let pending_requests_count = self.pending_requests_count;
+        self.dummy_requests
+            .retain(|dummy_request| dummy_request.request_index < pending_requests_count);
+    }
+
     /// Insert dummy responses into the list of responses.
     /// All elements before the element at index `start_at` is ignored,
     /// those elements should have been already processed by a previous call to process_responses.
@@ -503,10 +649,297 @@ impl DummyResponseInserter {
 
 #[cfg(all(test, feature = "redis"))]
 mod tests {
-    use super::DummyResponseInserter;
+    use super::{writer_task, DummyResponseInserter, RequestPending, SinkConnection};
+    use crate::codec::redis::RedisCodecBuilder;
+    use crate::codec::{CodecBuilder, Direction};
     use crate::frame::{Frame, RedisFrame};
     use crate::message::Message;
     use pretty_assertions::assert_eq;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWrite};
+    use tokio::net::TcpListener;
+    use tokio::sync::{mpsc, oneshot, Notify};
+    use tokio::time::timeout;
+    use tokio_util::codec::FramedWrite;
+
+    /// Records each write so that tests can check how writes were coalesced
+    #[derive(Clone, Default)]
+    struct RecordingWriter {
+        writes: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    impl AsyncWrite for RecordingWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.writes.lock().unwrap().push(buf.to_vec());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn writer_task_coalesces_queued_batches() {
+        let recording = RecordingWriter::default();
+        let (_, encoder) = RedisCodecBuilder::new(Direction::Source, "redis".to_owned()).build();
+        let writer = FramedWrite::new(recording.clone(), encoder);
+        let (out_tx, out_rx) = mpsc::unbounded_channel();
+        let (_, take_unwritten_rx) = mpsc::unbounded_channel();
+        let (_writer_cancel_tx, writer_cancel_rx) = oneshot::channel();
+        for _ in 0..3 {
+            out_tx
+                .send(vec![Message::from_frame(Frame::Redis(
+                    RedisFrame::SimpleString("OK".into()),
+                ))])
+                .unwrap();
+        }
+        drop(out_tx);
+
+        let request_pending = Arc::new(RequestPending {
+            notify: Notify::new(),
+            count: 0.into(),
+        });
+        writer_task::<RedisCodecBuilder, _>(
+            writer,
+            out_rx,
+            take_unwritten_rx,
+            writer_cancel_rx,
+            request_pending.clone(),
+            None,
+            mpsc::unbounded_channel().0,
+        )
+        .await
+        .unwrap();
+
+        // all three batches were queued before the writer ran, so they are sent in a single write
+        assert_eq!(
+            *recording.writes.lock().unwrap(),
+            vec![b"+OK\r\n+OK\r\n+OK\r\n".to_vec()]
+        );
+        assert_eq!(request_pending.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn writer_task_returns_unwritten_batches() {
+        let recording = RecordingWriter::default();
+        let (_, encoder) = RedisCodecBuilder::new(Direction::Source, "redis".to_owned()).build();
+        let writer = FramedWrite::new(recording.clone(), encoder);
+        let (out_tx, out_rx) = mpsc::unbounded_channel();
+        let (take_unwritten_tx, take_unwritten_rx) = mpsc::unbounded_channel();
+        let (_writer_cancel_tx, writer_cancel_rx) = oneshot::channel();
+        let requests = vec![redis_request(), redis_request()];
+        out_tx.send(vec![requests[0].clone()]).unwrap();
+        out_tx.send(vec![requests[1].clone()]).unwrap();
+        let (unwritten_tx, unwritten_rx) = oneshot::channel();
+        take_unwritten_tx.send(unwritten_tx).unwrap();
+        drop(out_tx);
+
+        let request_pending = Arc::new(RequestPending {
+            notify: Notify::new(),
+            count: 0.into(),
+        });
+        writer_task::<RedisCodecBuilder, _>(
+            writer,
+            out_rx,
+            take_unwritten_rx,
+            writer_cancel_rx,
+            request_pending.clone(),
+            None,
+            mpsc::unbounded_channel().0,
+        )
+        .await
+        .unwrap();
+
+        // the queued requests were handed back instead of being written
+        assert_eq!(unwritten_rx.await.unwrap(), requests);
+        assert!(recording.writes.lock().unwrap().is_empty());
+        assert_eq!(request_pending.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn writer_task_writes_keepalive_when_idle() {
+        let recording = RecordingWriter::default();
+        let (_decoder, encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        let writer = FramedWrite::new(recording.clone(), encoder);
+        let (out_tx, out_rx) = mpsc::unbounded_channel();
+        let (_take_unwritten_tx, take_unwritten_rx) = mpsc::unbounded_channel();
+        let (_writer_cancel_tx, writer_cancel_rx) = oneshot::channel();
+        let (keepalive_tx, mut keepalive_rx) = mpsc::unbounded_channel();
+
+        let request_pending = Arc::new(RequestPending {
+            notify: Notify::new(),
+            count: 0.into(),
+        });
+        let task = tokio::spawn(writer_task::<RedisCodecBuilder, _>(
+            writer,
+            out_rx,
+            take_unwritten_rx,
+            writer_cancel_rx,
+            request_pending.clone(),
+            Some(Duration::from_millis(10)),
+            keepalive_tx,
+        ));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drop(out_tx);
+        task.await.unwrap().unwrap();
+
+        // only a single keepalive is written since its response never arrives to mark the connection idle again
+        assert_eq!(
+            *recording.writes.lock().unwrap(),
+            vec![b"*1\r\n$4\r\nPING\r\n".to_vec()]
+        );
+        assert!(keepalive_rx.try_recv().is_ok());
+        assert_eq!(request_pending.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn writer_task_discards_unwritten_requests_when_cancelled() {
+        let recording = RecordingWriter::default();
+        let (_decoder, encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        let writer = FramedWrite::new(recording.clone(), encoder);
+        let (out_tx, out_rx) = mpsc::unbounded_channel();
+        let (_take_unwritten_tx, take_unwritten_rx) = mpsc::unbounded_channel();
+        let (writer_cancel_tx, writer_cancel_rx) = oneshot::channel();
+        out_tx.send(vec![redis_request(), redis_request()]).unwrap();
+        // The SinkConnection is dropped before the writer gets to the requests, e.g. because its client disconnected
+        drop(writer_cancel_tx);
+
+        let request_pending = Arc::new(RequestPending {
+            notify: Notify::new(),
+            count: 0.into(),
+        });
+        writer_task::<RedisCodecBuilder, _>(
+            writer,
+            out_rx,
+            take_unwritten_rx,
+            writer_cancel_rx,
+            request_pending.clone(),
+            None,
+            mpsc::unbounded_channel().0,
+        )
+        .await
+        .unwrap();
+
+        assert!(recording.writes.lock().unwrap().is_empty());
+        assert_eq!(request_pending.get(), 0);
+    }
+
+    #[cfg(feature = "kafka")]
+    #[tokio::test]
+    async fn writer_task_writes_requests_without_responses_when_cancelled() {
+        use crate::codec::kafka::KafkaCodecBuilder;
+        use crate::frame::kafka::{KafkaFrame, RequestBody};
+        use bytes::BytesMut;
+        use kafka_protocol::messages::{ApiKey, ProduceRequest, RequestHeader};
+        use kafka_protocol::protocol::Builder;
+        use tokio_util::codec::Encoder;
+
+        let produce = |acks| {
+            let mut produce = ProduceRequest::default();
+            produce.acks = acks;
+            Message::from_frame(Frame::Kafka(KafkaFrame::Request {
+                header: RequestHeader::builder()
+                    .request_api_key(ApiKey::ProduceKey as i16)
+                    .request_api_version(3)
+                    .build()
+                    .unwrap(),
+                body: RequestBody::Produce(produce),
+            }))
+        };
+
+        let recording = RecordingWriter::default();
+        let codec = KafkaCodecBuilder::new(Direction::Sink, "kafka".to_owned());
+        let (_decoder, encoder) = codec.build();
+        let writer = FramedWrite::new(recording.clone(), encoder);
+        let (out_tx, out_rx) = mpsc::unbounded_channel();
+        let (_take_unwritten_tx, take_unwritten_rx) = mpsc::unbounded_channel();
+        let (writer_cancel_tx, writer_cancel_rx) = oneshot::channel();
+        out_tx.send(vec![produce(1), produce(0)]).unwrap();
+        drop(writer_cancel_tx);
+
+        writer_task::<KafkaCodecBuilder, _>(
+            writer,
+            out_rx,
+            take_unwritten_rx,
+            writer_cancel_rx,
+            Arc::new(RequestPending {
+                notify: Notify::new(),
+                count: 0.into(),
+            }),
+            None,
+            mpsc::unbounded_channel().0,
+        )
+        .await
+        .unwrap();
+
+        // The client considers a produce with acks=0 sent once shotover has received it, so it is still written
+        let mut expected = BytesMut::new();
+        codec
+            .build()
+            .1
+            .encode(vec![produce(0)], &mut expected)
+            .unwrap();
+        assert_eq!(*recording.writes.lock().unwrap(), vec![expected.to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn sink_connection_dropped_closes_connection_without_writing() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let mut connection = SinkConnection::new(
+            listener.local_addr().unwrap(),
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()),
+            &None,
+            Duration::from_secs(3),
+            Arc::new(Notify::new()),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+
+        // On this single threaded runtime the writer task cannot run between the send and the drop,
+        // so the request is still queued when the connection is dropped.
+        connection.send(vec![redis_request()]).unwrap();
+        drop(connection);
+
+        let mut received = vec![];
+        timeout(Duration::from_secs(5), socket.read_to_end(&mut received))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, b"");
+    }
+
+    #[test]
+    fn dummy_response_inserter_forget_unwritten() {
+        let mut inserter = DummyResponseInserter::new();
+        let mut requests = vec![redis_request(), dummy(), redis_request(), dummy()];
+        inserter.process_requests(&mut requests);
+
+        // the last redis request and dummy request were taken back before being written
+        inserter.forget_unwritten_requests(2);
+
+        let mut responses = vec![redis_response(&requests[0])];
+        inserter.process_responses(&mut responses, 0);
+        assert_eq!(responses, vec![redis_response(&requests[0]), dummy()]);
+        assert_eq!(inserter.pending_requests_count, 0);
+        assert!(inserter.dummy_requests.is_empty());
+    }
 
     fn dummy() -> Message {
         Message::from_frame(Frame::Dummy)
@@ -33,7 +33,7 @@ use cassandra_protocol::types::{CBytesShort, CLong};
 use cql3_parser::begin_batch::{BatchType as ParserBatchType, BeginBatch};
 use cql3_parser::cassandra_ast::CassandraAST;
 use cql3_parser::cassandra_statement::CassandraStatement;
-use cql3_parser::common::Operand;
+use cql3_parser::common::{FQName, Operand};
 use nonzero_ext::nonzero;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::{Cursor, Write};
@@ -633,7 +633,7 @@ impl CassandraOperation {
     }
 }
 
-fn get_query_type(statement: &CassandraStatement) -> QueryType {
+pub(crate) fn get_query_type(statement: &CassandraStatement) -> QueryType {
     match statement {
         CassandraStatement::AlterKeyspace(_) => QueryType::SchemaChange,
         CassandraStatement::AlterMaterializedView(_) => QueryType::SchemaChange,
@@ -676,6 +676,50 @@ fn get_query_type(statement: &CassandraStatement) -> QueryType {
     }
 }
 
+/// Returns the name of the table, or other schema object within a keyspace, that the statement operates on
+pub fn table_name_mut(statement: &mut CassandraStatement) -> Option<&mut FQName> {
+    match statement {
+        CassandraStatement::AlterMaterializedView(x) => Some(&mut x.name),
+        CassandraStatement::AlterTable(x) => Some(&mut x.name),
+        CassandraStatement::AlterType(x) => Some(&mut x.name),
+        CassandraStatement::CreateAggregate(x) => Some(&mut x.name),
+        CassandraStatement::CreateFunction(x) => Some(&mut x.name),
+        CassandraStatement::CreateIndex(x) => Some(&mut x.table),
+        CassandraStatement::CreateMaterializedView(x) => Some(&mut x.name),
+        CassandraStatement::CreateTable(x) => Some(&mut x.name),
+        CassandraStatement::CreateTrigger(x) => Some(&mut x.name),
+        CassandraStatement::CreateType(x) => Some(&mut x.name),
+        CassandraStatement::Delete(x) => Some(&mut x.table_name),
+        CassandraStatement::DropAggregate(x) => Some(&mut x.name),
+        CassandraStatement::DropFunction(x) => Some(&mut x.name),
+        CassandraStatement::DropIndex(x) => Some(&mut x.name),
+        CassandraStatement::DropMaterializedView(x) => Some(&mut x.name),
+        CassandraStatement::DropTable(x) => Some(&mut x.name),
+        CassandraStatement::DropTrigger(x) => Some(&mut x.name),
+        CassandraStatement::DropType(x) => Some(&mut x.name),
+        CassandraStatement::Insert(x) => Some(&mut x.table_name),
+        CassandraStatement::Select(x) => Some(&mut x.table_name),
+        CassandraStatement::Truncate(name) => Some(name),
+        CassandraStatement::Update(x) => Some(&mut x.table_name),
+        CassandraStatement::AlterKeyspace(_)
+        | CassandraStatement::AlterRole(_)
+        | CassandraStatement::AlterUser(_)
+        | CassandraStatement::ApplyBatch
+        | CassandraStatement::CreateKeyspace(_)
+        | CassandraStatement::CreateRole(_)
+        | CassandraStatement::CreateUser(_)
+        | CassandraStatement::DropRole(_)
+        | CassandraStatement::DropUser(_)
+        | CassandraStatement::Grant(_)
+        | CassandraStatement::ListRoles(_)
+        | CassandraStatement::Revoke(_)
+        | CassandraStatement::DropKeyspace(_)
+        | CassandraStatement::ListPermissions(_)
+        | CassandraStatement::Use(_)
+        | CassandraStatement::Unknown(_) => None,
+    }
+}
+
 pub enum StatementResult {
     Query(Box<CassandraStatement>),
     /// Since this is already specified as a batch, CassandraStatement batch values must not be used in the Vec.
@@ -804,17 +848,17 @@ pub enum BatchStatementType {
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct BatchStatement {
-    ty: BatchStatementType,
-    values: QueryValues,
+    pub ty: BatchStatementType,
+    pub values: QueryValues,
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct CassandraBatch {
-    ty: BatchType,
-    queries: Vec<BatchStatement>,
-    consistency: Consistency,
-    serial_consistency: Option<Consistency>,
-    timestamp: Option<CLong>,
+    pub ty: BatchType,
+    pub queries: Vec<BatchStatement>,
+    pub consistency: Consistency,
+    pub serial_consistency: Option<Consistency>,
+    pub timestamp: Option<CLong>,
 }
 
 impl Display for CassandraFrame {
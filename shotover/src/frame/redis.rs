@@ -1,5 +1,6 @@
 use crate::frame::RedisFrame;
 use crate::message::QueryType;
+use std::time::Duration;
 
 #[inline]
 pub fn redis_query_type(frame: &RedisFrame) -> QueryType {
@@ -37,3 +38,96 @@ pub fn redis_query_name(frame: &RedisFrame) -> Option<String> {
     }
     None
 }
+
+/// How long redis may hold a request before responding to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedisBlocking {
+    /// The request is responded to as soon as it is processed
+    No,
+    /// The request may wait up to this long for data, e.g. `BLPOP list 5`
+    For(Duration),
+    /// The request waits until data arrives, e.g. `BLPOP list 0`
+    Forever,
+}
+
+/// Determines whether the request is a blocking command such as BLPOP, XREAD BLOCK or WAIT and how long it may block for
+pub fn redis_blocking(frame: &RedisFrame) -> RedisBlocking {
+    let RedisFrame::Array(args) = frame else {
+        return RedisBlocking::No;
+    };
+    let Some(RedisFrame::BulkString(command)) = args.first() else {
+        return RedisBlocking::No;
+    };
+    let arg = |index: usize| match args.get(index) {
+        Some(RedisFrame::BulkString(arg)) => std::str::from_utf8(arg).ok(),
+        _ => None,
+    };
+    // Timeouts of list and sorted set commands are in seconds and may be fractional, the rest are in milliseconds
+    let seconds = |arg: Option<&str>| {
+        arg.and_then(|x| x.parse::<f64>().ok())
+            .filter(|x| x.is_finite() && *x >= 0.0)
+            .map(Duration::from_secs_f64)
+    };
+    let millis = |arg: Option<&str>| arg.and_then(|x| x.parse().ok()).map(Duration::from_millis);
+
+    let timeout = match command.to_ascii_uppercase().as_slice() {
+        b"BLPOP" | b"BRPOP" | b"BRPOPLPUSH" | b"BLMOVE" | b"BZPOPMIN" | b"BZPOPMAX" => {
+            seconds(arg(args.len() - 1))
+        }
+        b"BLMPOP" | b"BZMPOP" => seconds(arg(1)),
+        b"WAIT" => millis(arg(2)),
+        b"WAITAOF" => millis(arg(3)),
+        b"XREAD" | b"XREADGROUP" => {
+            // Options come before STREAMS, after which every argument is a stream name or id
+            let block = (1..args.len())
+                .take_while(|i| !arg(*i).is_some_and(|x| x.eq_ignore_ascii_case("STREAMS")))
+                .find(|i| arg(*i).is_some_and(|x| x.eq_ignore_ascii_case("BLOCK")));
+            match block {
+                Some(i) => millis(arg(i + 1)),
+                None => return RedisBlocking::No,
+            }
+        }
+        _ => return RedisBlocking::No,
+    };
+    match timeout {
+        Some(timeout) if timeout.is_zero() => RedisBlocking::Forever,
+        Some(timeout) => RedisBlocking::For(timeout),
+        // redis will reject the request with an error
+        None => RedisBlocking::No,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transforms::testing::redis_args;
+    use pretty_assertions::assert_eq;
+
+    fn blocking(command: &[&'static str]) -> RedisBlocking {
+        redis_blocking(&RedisFrame::Array(redis_args(command)))
+    }
+
+    #[test]
+    fn test_redis_blocking() {
+        assert_eq!(blocking(&["GET", "key"]), RedisBlocking::No);
+        assert_eq!(
+            blocking(&["blpop", "a", "b", "1.5"]),
+            RedisBlocking::For(Duration::from_millis(1500))
+        );
+        assert_eq!(blocking(&["BRPOP", "a", "0"]), RedisBlocking::Forever);
+        assert_eq!(
+            blocking(&["BZMPOP", "2", "1", "zset", "MIN"]),
+            RedisBlocking::For(Duration::from_secs(2))
+        );
+        assert_eq!(
+            blocking(&["XREAD", "COUNT", "2", "block", "100", "STREAMS", "s", "$"]),
+            RedisBlocking::For(Duration::from_millis(100))
+        );
+        assert_eq!(
+            blocking(&["XREAD", "STREAMS", "block", "0"]),
+            RedisBlocking::No
+        );
+        assert_eq!(blocking(&["WAIT", "1", "0"]), RedisBlocking::Forever);
+        assert_eq!(blocking(&["BLPOP", "a", "soon"]), RedisBlocking::No);
+    }
+}
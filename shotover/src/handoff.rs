@@ -0,0 +1,277 @@
+//! Hands the listening sockets of a running shotover to the shotover replacing it, so that shotover can be upgraded without refusing any client connections.
+//!
+//! When `--handoff-socket` is set shotover first connects to that unix socket to inherit the listening sockets of the instance already running there.
+//! A source whose `listen_addr` matches an inherited socket accepts on that socket instead of binding a new one.
+//! Once its sources are running shotover listens on the unix socket itself, ready to hand its listening sockets to the next instance.
+//!
+//! After handing off its listening sockets an instance stops accepting connections but keeps serving the connections it already has
+//! until the drain period elapses, after which it shuts down as if it had received SIGTERM.
+//! Existing connections are not transferred, their clients reconnect to the new instance once the old instance closes them.
+
+use anyhow::{anyhow, Context, Result};
+use rustix::net::{
+    recvmsg, sendmsg, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, SendAncillaryBuffer,
+    SendAncillaryMessage, SendFlags,
+};
+use std::io::{ErrorKind, IoSlice, IoSliceMut};
+use std::net::TcpListener;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::net::UnixListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// The most listening sockets that can be handed off, one is used per source.
+const MAX_LISTENERS: usize = 64;
+
+struct Handoff {
+    path: PathBuf,
+    drain: Duration,
+}
+
+static HANDOFF: OnceLock<Handoff> = OnceLock::new();
+
+/// Listening sockets inherited from the previous instance that have not yet been claimed by a source, keyed by listen_addr.
+static INHERITED: Mutex<Vec<(String, TcpListener)>> = Mutex::new(Vec::new());
+
+/// A duplicate of every listening socket currently accepting in this instance, keyed by listen_addr.
+static LISTENERS: Mutex<Vec<(String, TcpListener)>> = Mutex::new(Vec::new());
+
+fn handed_off_token() -> &'static CancellationToken {
+    static HANDED_OFF: OnceLock<CancellationToken> = OnceLock::new();
+    HANDED_OFF.get_or_init(CancellationToken::new)
+}
+
+/// Enables handing off listening sockets through the unix socket at `path`,
+/// first inheriting the listening sockets of any instance already listening there.
+pub(crate) fn enable(path: PathBuf, drain: Duration) -> Result<()> {
+    match UnixStream::connect(&path) {
+        Ok(stream) => {
+            let inherited = receive_listeners(&stream)
+                .with_context(|| format!("Failed to inherit listening sockets from {path:?}"))?;
+            info!(
+                "Inherited {} listening sockets from the shotover instance at {path:?}",
+                inherited.len()
+            );
+            *INHERITED.lock().unwrap() = inherited;
+        }
+        Err(err)
+            if matches!(
+                err.kind(),
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused
+            ) =>
+        {
+            info!("No shotover instance to inherit listening sockets from at {path:?}");
+        }
+        Err(err) => {
+            return Err(anyhow!(err).context(format!("Failed to connect to {path:?}")));
+        }
+    }
+
+    HANDOFF
+        .set(Handoff { path, drain })
+        .map_err(|_| anyhow!("socket handoff was already enabled"))
+}
+
+/// Binds a listening socket to `listen_addr`, or uses the inherited listening socket for `listen_addr` if there is one.
+/// The socket is recorded so that it can be handed off.
+pub(crate) async fn bind(listen_addr: &str) -> std::io::Result<tokio::net::TcpListener> {
+    let listener = match take_inherited(listen_addr) {
+        Some(listener) => {
+            listener.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(listener)?
+        }
+        None => tokio::net::TcpListener::bind(listen_addr).await?,
+    };
+
+    if HANDOFF.get().is_some() {
+        let duplicate = TcpListener::from(listener.as_fd().try_clone_to_owned()?);
+        let mut listeners = LISTENERS.lock().unwrap();
+        listeners.retain(|(addr, _)| addr != listen_addr);
+        listeners.push((listen_addr.to_owned(), duplicate));
+    }
+    Ok(listener)
+}
+
+fn take_inherited(listen_addr: &str) -> Option<TcpListener> {
+    let mut inherited = INHERITED.lock().unwrap();
+    let index = inherited.iter().position(|(addr, _)| addr == listen_addr)?;
+    Some(inherited.swap_remove(index).1)
+}
+
+/// Forgets a listening socket that was closed, so that it stays closed instead of being handed off.
+pub(crate) fn unregister(listen_addr: &str) {
+    LISTENERS
+        .lock()
+        .unwrap()
+        .retain(|(addr, _)| addr != listen_addr);
+}
+
+pub(crate) fn is_handed_off() -> bool {
+    handed_off_token().is_cancelled()
+}
+
+/// Completes once the listening sockets have been handed off to a new instance.
+pub(crate) async fn handed_off() {
+    handed_off_token().cancelled().await
+}
+
+/// Completes once the drain period has elapsed after handing off the listening sockets, at which point this instance should shut down.
+/// Never completes if handoff is not enabled.
+pub(crate) async fn drained() {
+    match HANDOFF.get() {
+        Some(handoff) => {
+            handed_off().await;
+            tokio::time::sleep(handoff.drain).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Starts listening for the instance that will replace this one.
+/// Must be called once all sources are running so that every listening socket is handed off.
+pub(crate) fn listen_for_successor() -> Result<()> {
+    let Some(handoff) = HANDOFF.get() else {
+        return Ok(());
+    };
+
+    for (listen_addr, _) in INHERITED.lock().unwrap().drain(..) {
+        warn!("Closing inherited listening socket for {listen_addr} as no source listens on it");
+    }
+
+    // Any existing socket file belongs to the instance we inherited from, or to an instance that no longer exists
+    if let Err(err) = std::fs::remove_file(&handoff.path) {
+        if err.kind() != ErrorKind::NotFound {
+            return Err(anyhow!(err).context(format!("Failed to remove {:?}", handoff.path)));
+        }
+    }
+    let listener = UnixListener::bind(&handoff.path)
+        .with_context(|| format!("Failed to listen on {:?}", handoff.path))?;
+
+    tokio::spawn(async move {
+        loop {
+            let result = match listener.accept().await {
+                Ok((stream, _)) => stream
+                    .into_std()
+                    .and_then(|stream| {
+                        stream.set_nonblocking(false)?;
+                        Ok(stream)
+                    })
+                    .map_err(anyhow::Error::from)
+                    .and_then(|stream| send_listeners(&stream)),
+                Err(err) => Err(err.into()),
+            };
+            match result {
+                Ok(()) => {
+                    info!("Handed off listening sockets to a new shotover instance, no longer accepting connections");
+                    handed_off_token().cancel();
+                    return;
+                }
+                Err(err) => {
+                    warn!("{:?}", err.context("Failed to hand off listening sockets"));
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The listen_addr of each socket is sent newline separated, followed by the sockets themselves in the same order.
+fn send_listeners(stream: &UnixStream) -> Result<()> {
+    let listeners = LISTENERS.lock().unwrap();
+    if listeners.len() > MAX_LISTENERS {
+        return Err(anyhow!(
+            "{} listening sockets cannot be handed off, the limit is {MAX_LISTENERS}",
+            listeners.len()
+        ));
+    }
+
+    let addrs = listeners
+        .iter()
+        .map(|(addr, _)| addr.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let fds: Vec<BorrowedFd> = listeners
+        .iter()
+        .map(|(_, listener)| listener.as_fd())
+        .collect();
+
+    let mut space = vec![0; rustix::cmsg_space!(ScmRights(MAX_LISTENERS))];
+    let mut control = SendAncillaryBuffer::new(&mut space);
+    if !control.push(SendAncillaryMessage::ScmRights(&fds)) {
+        return Err(anyhow!(
+            "listening sockets did not fit in the control message"
+        ));
+    }
+    // A message must contain at least one byte to carry the sockets, so the addresses are terminated with a newline
+    let data = format!("{addrs}\n");
+    sendmsg(
+        stream,
+        &[IoSlice::new(data.as_bytes())],
+        &mut control,
+        SendFlags::empty(),
+    )?;
+    Ok(())
+}
+
+fn receive_listeners(stream: &UnixStream) -> Result<Vec<(String, TcpListener)>> {
+    let mut data = vec![0; 64 * 1024];
+    let mut space = vec![0; rustix::cmsg_space!(ScmRights(MAX_LISTENERS))];
+    let mut control = RecvAncillaryBuffer::new(&mut space);
+    let received = recvmsg(
+        stream,
+        &mut [IoSliceMut::new(&mut data)],
+        &mut control,
+        RecvFlags::CMSG_CLOEXEC,
+    )?;
+
+    let mut listeners = vec![];
+    for message in control.drain() {
+        if let RecvAncillaryMessage::ScmRights(fds) = message {
+            listeners.extend(fds.map(TcpListener::from));
+        }
+    }
+
+    let addrs = std::str::from_utf8(&data[..received.bytes])?;
+    let addrs: Vec<&str> = addrs.lines().filter(|addr| !addr.is_empty()).collect();
+    if addrs.len() != listeners.len() {
+        return Err(anyhow!(
+            "received {} listen addresses but {} listening sockets",
+            addrs.len(),
+            listeners.len()
+        ));
+    }
+    Ok(addrs
+        .into_iter()
+        .map(str::to_owned)
+        .zip(listeners)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_send_receive_listeners() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        LISTENERS.lock().unwrap().push((
+            "127.0.0.1:0".to_owned(),
+            TcpListener::from(listener.as_fd().try_clone_to_owned().unwrap()),
+        ));
+
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        send_listeners(&sender).unwrap();
+        let received = receive_listeners(&receiver).unwrap();
+
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].0, "127.0.0.1:0");
+        // the received socket is the same socket, not a new one bound to the same address
+        assert_eq!(received[0].1.local_addr().unwrap(), local_addr);
+    }
+}
@@ -10,6 +10,8 @@
 //!
 //! Simple transforms can implement all of these onto a single struct but generally you need seperate structs for each.
 //!
+//! Transforms can be unit tested by running them in a chain with [`transforms::testing::ChainTester`].
+//!
 //! ## The shotover binary
 //! All custom transforms the user wants to use are statically compiled into a single binary.
 //! The crate for this binary is very simple, it just consists of a `main.rs` like:
@@ -59,16 +61,23 @@ pub mod config;
 pub mod connection;
 mod connection_span;
 pub mod frame;
+mod handoff;
 mod http;
+mod maintenance;
 pub mod message;
 mod observability;
+mod panics;
 pub mod runner;
 mod server;
+mod sharding;
+mod snapshot;
 pub mod sources;
+mod state_sync;
 pub mod tcp;
 pub mod tls;
 mod tracing_panic_handler;
 pub mod transforms;
+mod tuning;
 
 /// Imports a custom transform into the shotover binary.
 ///
@@ -0,0 +1,153 @@
+//! Lets an operator take an upstream node out of service through the observability interface, so that it can be rebooted without clients seeing errors.
+//!
+//! While a node is in maintenance sinks that can route around it stop sending new requests to it and close their connections to it
+//! once every request already sent over them has a response.
+//! A node is quiesced once no connections to it remain, at which point it is safe to take it down.
+
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+struct NodeState {
+    address: SocketAddr,
+    in_maintenance: AtomicBool,
+    open_connections: AtomicUsize,
+}
+
+/// Every upstream node that has had a connection opened to it or has been put into maintenance
+static NODES: Mutex<Vec<Arc<NodeState>>> = Mutex::new(Vec::new());
+
+/// The number of nodes currently in maintenance, lets routing skip the lookup in the common case of no nodes being in maintenance
+static IN_MAINTENANCE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Incremented whenever a node enters or leaves maintenance so that sinks can cheaply check if they need to act on a change
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn node(address: SocketAddr) -> Arc<NodeState> {
+    let mut nodes = NODES.lock().unwrap();
+    if let Some(node) = nodes.iter().find(|node| node.address == address) {
+        return node.clone();
+    }
+    let node = Arc::new(NodeState {
+        address,
+        in_maintenance: AtomicBool::new(false),
+        open_connections: AtomicUsize::new(0),
+    });
+    nodes.push(node.clone());
+    node
+}
+
+/// Puts the node into or takes the node out of maintenance
+pub(crate) fn set(address: SocketAddr, in_maintenance: bool) {
+    let node = node(address);
+    if node.in_maintenance.swap(in_maintenance, Ordering::Relaxed) != in_maintenance {
+        if in_maintenance {
+            IN_MAINTENANCE_COUNT.fetch_add(1, Ordering::Relaxed);
+        } else {
+            IN_MAINTENANCE_COUNT.fetch_sub(1, Ordering::Relaxed);
+        }
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns true if new requests should not be routed to the node
+pub(crate) fn in_maintenance(address: SocketAddr) -> bool {
+    IN_MAINTENANCE_COUNT.load(Ordering::Relaxed) > 0
+        && NODES
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|node| node.address == address && node.in_maintenance.load(Ordering::Relaxed))
+}
+
+/// Changes every time a node enters or leaves maintenance
+pub(crate) fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Counts a connection to the node as open until the returned guard is dropped
+pub(crate) fn track_connection(address: SocketAddr) -> ConnectionGuard {
+    let node = node(address);
+    node.open_connections.fetch_add(1, Ordering::Relaxed);
+    ConnectionGuard { node }
+}
+
+pub(crate) struct ConnectionGuard {
+    node: Arc<NodeState>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.node.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct MaintenanceReport {
+    pub address: SocketAddr,
+    /// The number of connections shotover still has open to the node
+    pub open_connections: usize,
+    /// True once no connections to the node remain
+    pub quiesced: bool,
+}
+
+/// Reports the progress of draining every node currently in maintenance
+pub(crate) fn report() -> Vec<MaintenanceReport> {
+    NODES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|node| node.in_maintenance.load(Ordering::Relaxed))
+        .map(|node| {
+            let open_connections = node.open_connections.load(Ordering::Relaxed);
+            MaintenanceReport {
+                address: node.address,
+                open_connections,
+                quiesced: open_connections == 0,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_quiesced_once_connections_closed() {
+        let address: SocketAddr = "127.0.0.1:19042".parse().unwrap();
+        let connection = track_connection(address);
+        assert!(!in_maintenance(address));
+
+        let generation = generation();
+        set(address, true);
+        assert!(in_maintenance(address));
+        assert_ne!(generation, super::generation());
+
+        let report_for = |address| report().into_iter().find(|x| x.address == address);
+        assert_eq!(
+            report_for(address),
+            Some(MaintenanceReport {
+                address,
+                open_connections: 1,
+                quiesced: false,
+            })
+        );
+
+        drop(connection);
+        assert_eq!(
+            report_for(address),
+            Some(MaintenanceReport {
+                address,
+                open_connections: 0,
+                quiesced: true,
+            })
+        );
+
+        set(address, false);
+        assert!(!in_maintenance(address));
+        assert_eq!(report_for(address), None);
+    }
+}
@@ -8,6 +8,8 @@ use crate::frame::{redis::redis_query_type, RedisFrame};
 use crate::frame::{Frame, MessageType};
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
+#[cfg(feature = "cassandra")]
+use cassandra_protocol::frame::Opcode;
 use derivative::Derivative;
 use fnv::FnvBuildHasher;
 use nonzero_ext::nonzero;
@@ -239,6 +241,27 @@ impl Message {
         }
     }
 
+    /// Returns the raw bytes of the message as they were received, if they still reflect the contents of the message.
+    /// Returns None if the message has been modified since it was received or was generated by shotover.
+    pub fn raw_bytes(&self) -> Option<&Bytes> {
+        match self.inner.as_ref().unwrap() {
+            MessageInner::RawBytes { bytes, .. } | MessageInner::Parsed { bytes, .. } => {
+                Some(bytes)
+            }
+            MessageInner::Modified { .. } => None,
+        }
+    }
+
+    /// Replaces the contents of the message with the provided raw bytes.
+    /// The bytes must contain a single complete message of the same protocol and codec state as the existing message.
+    /// The message keeps its id and request_id.
+    pub fn replace_with_raw_bytes(&mut self, bytes: Bytes) {
+        self.inner = Some(MessageInner::RawBytes {
+            bytes,
+            message_type: MessageType::from(&self.codec_state),
+        });
+    }
+
     pub fn message_type(&self) -> MessageType {
         match self.inner.as_ref().unwrap() {
             MessageInner::RawBytes { message_type, .. } => *message_type,
@@ -455,6 +478,25 @@ impl Message {
         }
     }
 
+    /// Returns true iff the message is a response that reports an error to the client.
+    /// Kafka defines errors per response type so kafka errors are not detected here.
+    pub fn is_error(&mut self) -> bool {
+        match self.message_type() {
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra => matches!(
+                self.metadata(),
+                Ok(Metadata::Cassandra(metadata)) if metadata.opcode == Opcode::Error
+            ),
+            #[cfg(feature = "redis")]
+            MessageType::Redis => matches!(self.frame(), Some(Frame::Redis(RedisFrame::Error(_)))),
+            #[cfg(feature = "kafka")]
+            MessageType::Kafka => false,
+            #[cfg(feature = "opensearch")]
+            MessageType::OpenSearch => false,
+            MessageType::Dummy => false,
+        }
+    }
+
     pub fn is_dummy(&self) -> bool {
         matches!(
             self.inner,
@@ -1,32 +1,49 @@
+use crate::handoff;
 use crate::http::HttpServerError;
+use crate::maintenance;
+use crate::panics;
 use crate::runner::ReloadHandle;
+use crate::snapshot;
+use crate::tuning;
 use anyhow::{anyhow, Context, Result};
 use axum::{extract::State, response::Html, Router};
 use metrics_exporter_prometheus::PrometheusHandle;
+use serde_json::Value;
 use std::str;
+use std::time::Duration;
 use std::{net::SocketAddr, sync::Arc};
+use tokio::net::TcpListener;
 use tracing::{error, trace};
 
 /// Exports metrics over HTTP.
 pub(crate) struct LogFilterHttpExporter {
     recorder_handle: PrometheusHandle,
     address: SocketAddr,
+    listener: std::io::Result<TcpListener>,
     tracing_handle: ReloadHandle,
+    capability_report: String,
+    sources: Value,
 }
 
 impl LogFilterHttpExporter {
-    /// Creates a new [`LogFilterHttpExporter`] that listens on the given `address`.
+    /// Creates a new [`LogFilterHttpExporter`] that serves on the `listener` bound to the given `address`.
     ///
     /// Observers expose their output by being converted into strings.
     pub fn new(
         recorder_handle: PrometheusHandle,
         address: SocketAddr,
+        listener: std::io::Result<TcpListener>,
         tracing_handle: ReloadHandle,
+        capability_report: String,
+        sources: Value,
     ) -> Self {
         LogFilterHttpExporter {
             recorder_handle,
             address,
+            listener,
             tracing_handle,
+            capability_report,
+            sources,
         }
     }
 
@@ -42,30 +59,141 @@ impl LogFilterHttpExporter {
         let state = AppState {
             recorder_handle: Arc::new(self.recorder_handle),
             tracing_handle: Arc::new(self.tracing_handle),
+            capability_report: Arc::new(self.capability_report),
+            sources: Arc::new(self.sources),
         };
 
         let app = Router::new()
             .route("/", axum::routing::get(root))
             .route("/metrics", axum::routing::get(serve_metrics))
             .route("/filter", axum::routing::put(put_filter))
-            .with_state(state);
+            .route("/capabilities", axum::routing::get(serve_capabilities))
+            .route(
+                "/payload_captures",
+                axum::routing::get(serve_payload_captures),
+            )
+            .route(
+                "/snapshot",
+                axum::routing::get(serve_snapshot).put(put_snapshot),
+            )
+            .route(
+                "/maintenance",
+                axum::routing::get(serve_maintenance)
+                    .put(put_maintenance)
+                    .delete(delete_maintenance),
+            )
+            .route("/panics", axum::routing::get(serve_panics))
+            .route("/tuning", axum::routing::get(serve_tuning).put(put_tuning));
+        #[cfg(feature = "cassandra")]
+        let app = app.route(
+            "/cassandra/clusters",
+            axum::routing::get(serve_cassandra_clusters),
+        );
+        let app = app.with_state(state);
 
         let address = self.address;
-        let listener = tokio::net::TcpListener::bind(address)
-            .await
+        let listener = self
+            .listener
             .with_context(|| format!("Failed to bind to {}", address))?;
-        axum::serve(listener, app).await.map_err(|e| anyhow!(e))
+        // Once handed off, the new shotover instance serves the observability interface
+        axum::serve(listener, app)
+            .with_graceful_shutdown(handoff::handed_off())
+            .await
+            .map_err(|e| anyhow!(e))
     }
 }
 
 async fn root() -> Html<&'static str> {
-    Html("try /filter or /metrics")
+    Html("try /filter, /metrics, /capabilities, /payload_captures, /snapshot, /maintenance, /panics, /tuning or /cassandra/clusters")
 }
 
 async fn serve_metrics(State(state): State<AppState>) -> Html<String> {
     Html(state.recorder_handle.as_ref().render())
 }
 
+async fn serve_capabilities(State(state): State<AppState>) -> String {
+    state.capability_report.as_ref().clone()
+}
+
+/// The cached model of each cassandra cluster routed to by a `CassandraSinkCluster`, keyed by chain name
+#[cfg(feature = "cassandra")]
+async fn serve_cassandra_clusters() -> Result<String, HttpServerError> {
+    Ok(serde_yaml::to_string(
+        &crate::transforms::cassandra::cluster_metadata::snapshot(),
+    )?)
+}
+
+/// The payloads captured by the PayloadCapture transforms of each chain, keyed by chain name
+async fn serve_payload_captures() -> Result<String, HttpServerError> {
+    Ok(serde_yaml::to_string(
+        &crate::transforms::payload_capture::captures(),
+    )?)
+}
+
+async fn serve_snapshot(State(state): State<AppState>) -> Result<String, HttpServerError> {
+    let snapshot = snapshot::take(state.sources.clone()).await;
+    Ok(serde_json::to_string_pretty(&snapshot)?)
+}
+
+/// Writes a snapshot to the path given in the body, or to `shotover-snapshot-<unix timestamp>.json` in the working directory if no path is given
+async fn put_snapshot(
+    State(state): State<AppState>,
+    path: String,
+) -> Result<String, HttpServerError> {
+    let snapshot = snapshot::take(state.sources.clone()).await;
+    let path = match path.trim() {
+        "" => format!("shotover-snapshot-{}.json", snapshot.taken_at),
+        path => path.to_owned(),
+    };
+    tokio::fs::write(&path, serde_json::to_vec_pretty(&snapshot)?)
+        .await
+        .with_context(|| format!("Failed to write snapshot to {path}"))?;
+    tracing::info!("snapshot written to {path}");
+    Ok(format!("Snapshot written to {path}"))
+}
+
+/// The number of times each transform has panicked, keyed by chain and transform name
+async fn serve_panics() -> Result<String, HttpServerError> {
+    Ok(serde_yaml::to_string(&panics::report())?)
+}
+
+/// The recommendations of the most recently started workload analysis
+async fn serve_tuning() -> Result<String, HttpServerError> {
+    match tuning::report() {
+        Some(report) => Ok(serde_yaml::to_string(&report)?),
+        None => Ok("No workload analysis has been started, start one by sending the number of seconds to observe traffic for in a PUT request to /tuning".to_owned()),
+    }
+}
+
+/// Starts a workload analysis observing traffic for the number of seconds given in the body
+async fn put_tuning(seconds: String) -> Result<Html<&'static str>, HttpServerError> {
+    let seconds: u64 = seconds.trim().parse()?;
+    if seconds == 0 {
+        return Err(anyhow!("The analysis must observe traffic for at least 1 second").into());
+    }
+    tuning::start(Duration::from_secs(seconds));
+    tracing::info!("workload analysis started, observing traffic for {seconds} seconds");
+    Ok(Html("Workload analysis started"))
+}
+
+async fn serve_maintenance() -> Result<String, HttpServerError> {
+    Ok(serde_yaml::to_string(&maintenance::report())?)
+}
+
+async fn put_maintenance(address: String) -> Result<Html<&'static str>, HttpServerError> {
+    let address = address.trim().parse()?;
+    maintenance::set(address, true);
+    tracing::info!("{address} put into maintenance");
+    Ok(Html("Node put into maintenance"))
+}
+
+async fn delete_maintenance(address: String) -> Result<Html<&'static str>, HttpServerError> {
+    let address = address.trim().parse()?;
+    maintenance::set(address, false);
+    tracing::info!("{address} taken out of maintenance");
+    Ok(Html("Node taken out of maintenance"))
+}
+
 async fn put_filter(
     State(state): State<AppState>,
     new_filter_string: String,
@@ -81,4 +209,6 @@ async fn put_filter(
 struct AppState {
     tracing_handle: Arc<ReloadHandle>,
     recorder_handle: Arc<PrometheusHandle>,
+    capability_report: Arc<String>,
+    sources: Arc<Value>,
 }
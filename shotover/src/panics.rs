@@ -0,0 +1,110 @@
+//! Isolates a panic in a transform to the chain it panicked in.
+//!
+//! A panic while a transform processes requests or a connection event is caught and turned into an error,
+//! so the connection whose chain panicked is closed with an error response to each pending request, just like any other transform error,
+//! while every other connection carries on unaffected.
+//! The chain is never reused after a panic since the transform may have been left in an inconsistent state,
+//! a new connection builds a fresh chain instead.
+//!
+//! Every panic is counted per chain and transform, for the `/panics` endpoint of the observability interface,
+//! so that a buggy transform or plugin can be spotted without trawling through logs.
+
+use futures::FutureExt;
+use serde::Serialize;
+use std::any::Any;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+
+/// The error returned in place of the result of a transform that panicked
+#[derive(Debug)]
+pub struct TransformPanic {
+    pub transform: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for TransformPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} transform panicked: {}", self.transform, self.message)
+    }
+}
+
+impl std::error::Error for TransformPanic {}
+
+impl TransformPanic {
+    /// Returns the panic that caused `err`, if it was caused by a transform panicking
+    pub fn find(err: &anyhow::Error) -> Option<&TransformPanic> {
+        err.chain().find_map(|cause| cause.downcast_ref())
+    }
+}
+
+/// Runs `future`, which is driving `transform`, returning an error instead of unwinding if it panics.
+/// The panic itself is logged along with its backtrace by the panic hook.
+pub async fn catch_unwind<F: Future>(
+    chain: &'static str,
+    transform: &'static str,
+    future: F,
+) -> Result<F::Output, TransformPanic> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|payload| {
+            let panic = TransformPanic {
+                transform,
+                message: payload_message(payload.as_ref()),
+            };
+            record(chain, &panic);
+            tracing::error!(
+                chain,
+                transform,
+                "{panic}, closing chain {chain} so it is not reused in an inconsistent state"
+            );
+            panic
+        })
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PanicCount {
+    pub chain: &'static str,
+    pub transform: &'static str,
+    pub count: u64,
+    /// The message of the most recent panic
+    pub last_message: String,
+}
+
+static PANICS: Mutex<Vec<PanicCount>> = Mutex::new(Vec::new());
+
+fn record(chain: &'static str, panic: &TransformPanic) {
+    let mut panics = PANICS.lock().unwrap();
+    match panics
+        .iter_mut()
+        .find(|count| count.chain == chain && count.transform == panic.transform)
+    {
+        Some(count) => {
+            count.count += 1;
+            count.last_message.clone_from(&panic.message);
+        }
+        None => panics.push(PanicCount {
+            chain,
+            transform: panic.transform,
+            count: 1,
+            last_message: panic.message.clone(),
+        }),
+    }
+}
+
+/// The number of times each transform has panicked, for every transform that has panicked at least once
+pub fn report() -> Vec<PanicCount> {
+    PANICS.lock().unwrap().clone()
+}
@@ -1,13 +1,20 @@
 //! Tools for initializing shotover in the final binary.
+use crate::config::capabilities::{compiled_features, CapabilityReport};
+use crate::config::self_test::self_test;
 use crate::config::topology::Topology;
 use crate::config::Config;
+use crate::handoff;
 use crate::observability::LogFilterHttpExporter;
+use crate::sharding::{self, Shards};
+use crate::state_sync;
 use anyhow::Context;
 use anyhow::{anyhow, Result};
 use clap::{crate_version, Parser};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use std::env;
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use tokio::runtime::{self, Runtime};
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::watch;
@@ -38,12 +45,54 @@ struct ConfigOpts {
     #[clap(long)]
     pub core_threads: Option<usize>,
 
+    // Number of shards to run client connections on.
+    // Each shard is a single threaded runtime that runs the tasks of its connections, reducing contention at high connection counts.
+    // Connections are still accepted on the tokio worker threads, and tasks shared between connections,
+    // such as the pooled sink connections of RedisSinkCluster, are not sharded and still communicate across shards.
+    // By default connections are not sharded and run on the tokio worker threads.
+    #[clap(long)]
+    pub shards: Option<usize>,
+
+    // Path of a unix socket used to hand listening sockets over to a new shotover instance during an upgrade.
+    // On startup the listening sockets of the instance already at this path are inherited.
+    #[clap(long)]
+    pub handoff_socket: Option<PathBuf>,
+
+    // Seconds to keep serving existing connections after handing off the listening sockets, before shutting down.
+    #[clap(long, default_value = "30")]
+    pub handoff_drain_secs: u64,
+
+    // Address to accept standby shotover instances on, each is periodically sent the cluster state learned by this instance.
+    #[clap(long)]
+    pub state_sync_listen: Option<String>,
+
+    // Address of the active shotover instance's --state-sync-listen, making this a warm standby that imports the cluster state the active instance has learned.
+    #[clap(long)]
+    pub state_sync_from: Option<String>,
+
+    // Milliseconds between each state sync sent to standby instances, also the delay before reconnecting to the active instance.
+    #[clap(long, default_value = "5000")]
+    pub state_sync_interval_ms: u64,
+
     // 2,097,152 = 2 * 1024 * 1024 (2MiB)
     #[clap(long, default_value = "2097152")]
     pub stack_size: usize,
 
     #[arg(long, value_enum, default_value = "human")]
     pub log_format: LogFormat,
+
+    // Print a report of the capabilities enabled by the binary and topology file, then exit without starting shotover.
+    #[clap(long)]
+    pub print_config: bool,
+
+    // Validate the topology file and print the delivery guarantees of each source's chain, then exit without starting shotover.
+    #[clap(long)]
+    pub validate: bool,
+
+    // Send a probe request through the chain of each source, with each chain's sink replaced by a loopback sink,
+    // and print whether each chain passed, then exit without starting shotover.
+    #[clap(long)]
+    pub self_test: bool,
 }
 
 #[derive(clap::ValueEnum, Clone, Copy)]
@@ -58,8 +107,17 @@ impl Default for ConfigOpts {
             topology_file: "config/topology.yaml".into(),
             config_file: "config/config.yaml".into(),
             core_threads: None,
+            shards: None,
+            handoff_socket: None,
+            handoff_drain_secs: 30,
+            state_sync_listen: None,
+            state_sync_from: None,
+            state_sync_interval_ms: 5000,
             stack_size: 2097152,
             log_format: LogFormat::Human,
+            print_config: false,
+            validate: false,
+            self_test: false,
         }
     }
 }
@@ -81,6 +139,53 @@ impl Shotover {
         let opts = ConfigOpts::parse();
         let log_format = opts.log_format;
 
+        if opts.print_config {
+            match Topology::from_file(&opts.topology_file)
+                .and_then(|topology| CapabilityReport::new(&topology)?.serialize())
+            {
+                Ok(report) => {
+                    print!("{report}");
+                    std::process::exit(0);
+                }
+                Err(err) => {
+                    eprintln!("{:?}", err.context("Failed to generate capability report"));
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if opts.validate {
+            let result = Topology::from_file(&opts.topology_file).and_then(|topology| {
+                // Building the chains requires a runtime, but nothing is left running once validation completes.
+                Runtime::new()?.block_on(topology.validate())?.serialize()
+            });
+            match result {
+                Ok(report) => {
+                    print!("{report}");
+                    std::process::exit(0);
+                }
+                Err(err) => {
+                    eprintln!("{:?}", err.context("Topology is invalid"));
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        if opts.self_test {
+            let result = Topology::from_file(&opts.topology_file)
+                .and_then(|topology| Runtime::new()?.block_on(self_test(&topology)));
+            match result.and_then(|report| Ok((report.serialize()?, report.passed()))) {
+                Ok((report, passed)) => {
+                    print!("{report}");
+                    std::process::exit(if passed { 0 } else { 1 });
+                }
+                Err(err) => {
+                    eprintln!("{:?}", err.context("Self test failed"));
+                    std::process::exit(1);
+                }
+            }
+        }
+
         match Shotover::new_inner(opts) {
             Ok(x) => x,
             Err(err) => {
@@ -108,8 +213,19 @@ impl Shotover {
         let topology = Topology::from_file(&params.topology_file)?;
         let tracing = TracingState::new(config.main_log_level.as_str(), params.log_format)?;
         let runtime = Shotover::create_runtime(params.stack_size, params.core_threads);
+        if let Some(shards) = params.shards {
+            sharding::enable(Shards::new(shards, params.stack_size)?)?;
+        }
+        if let Some(path) = params.handoff_socket {
+            handoff::enable(path, Duration::from_secs(params.handoff_drain_secs))?;
+        }
+        state_sync::enable(
+            params.state_sync_listen,
+            params.state_sync_from,
+            Duration::from_millis(params.state_sync_interval_ms),
+        )?;
 
-        Shotover::start_observability_interface(&runtime, &config, &tracing)?;
+        Shotover::start_observability_interface(&runtime, &config, &topology, &tracing)?;
 
         Ok(Shotover {
             runtime,
@@ -122,6 +238,7 @@ impl Shotover {
     fn start_observability_interface(
         runtime: &Runtime,
         config: &Config,
+        topology: &Topology,
         tracing: &TracingState,
     ) -> Result<()> {
         let recorder = PrometheusBuilder::new()
@@ -132,7 +249,18 @@ impl Shotover {
         metrics::set_global_recorder(recorder)?;
 
         let socket: SocketAddr = config.observability_interface.parse()?;
-        let exporter = LogFilterHttpExporter::new(handle, socket, tracing.handle.clone());
+        let capability_report = CapabilityReport::new(topology)?.serialize()?;
+        let sources = serde_json::to_value(&topology.sources)?;
+        // Bound before any source starts, so that an inherited observability interface socket is claimed before unclaimed inherited sockets are closed
+        let listener = runtime.block_on(handoff::bind(&config.observability_interface));
+        let exporter = LogFilterHttpExporter::new(
+            handle,
+            socket,
+            listener,
+            tracing.handle.clone(),
+            capability_report,
+            sources,
+        );
 
         runtime.spawn(exporter.async_run());
         Ok(())
@@ -159,6 +287,9 @@ impl Shotover {
                 _ = terminate.recv() => {
                     info!("received SIGTERM");
                 },
+                _ = handoff::drained() => {
+                    info!("finished draining connections after handing off listening sockets");
+                },
             };
 
             trigger_shutdown_tx.send(true).unwrap();
@@ -297,11 +428,15 @@ async fn run(
     trigger_shutdown_rx: watch::Receiver<bool>,
 ) -> Result<()> {
     info!("Starting Shotover {}", crate_version!());
+    info!("Compiled with features: {}", compiled_features().join(", "));
     info!(configuration = ?config);
     info!(topology = ?topology);
 
     match topology.run_chains(trigger_shutdown_rx).await {
         Ok(sources) => {
+            // Started first so that an inherited state sync socket is claimed before unclaimed inherited sockets are closed
+            state_sync::start().await?;
+            handoff::listen_for_successor()?;
             futures::future::join_all(sources.into_iter().map(|x| x.into_join_handle())).await;
             Ok(())
         }
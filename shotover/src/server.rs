@@ -1,16 +1,28 @@
 use crate::codec::{CodecBuilder, CodecReadError, CodecWriteError};
-use crate::config::chain::TransformChainConfig;
-use crate::frame::MessageType;
-use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::config::chain::{SharedChains, SourceChainConfig};
+#[cfg(feature = "kafka")]
+use crate::frame::KafkaFrame;
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+use crate::frame::{Frame, MessageType};
+use crate::handoff;
+use crate::message::{Message, MessageIdMap, MessageIdSet, Messages, Metadata};
+use crate::sharding;
 use crate::sources::Transport;
 use crate::tls::{AcceptError, TlsAcceptor};
 use crate::transforms::chain::{TransformChain, TransformChainBuilder};
-use crate::transforms::{TransformContextBuilder, TransformContextConfig, Wrapper};
+use crate::transforms::{
+    ConnectionEvent, TransformContextBuilder, TransformContextConfig, Wrapper,
+};
 use anyhow::{anyhow, Context, Result};
 use bytes::BytesMut;
+#[cfg(feature = "cassandra")]
+use cassandra_protocol::frame::Opcode;
 use futures::future::join_all;
 use futures::{SinkExt, StreamExt};
-use metrics::{gauge, Gauge};
+#[cfg(feature = "kafka")]
+use kafka_protocol::messages::{RequestBody, ResponseBody};
+use metrics::{counter, gauge, Gauge};
 use std::io::ErrorKind;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -26,6 +38,7 @@ use tokio_tungstenite::tungstenite::{
     protocol::Message as WsMessage,
 };
 use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
+use tokio_util::sync::CancellationToken;
 use tracing::Instrument;
 use tracing::{debug, error, warn};
 
@@ -78,7 +91,8 @@ pub struct TcpCodecListener<C: CodecBuilder> {
 impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
     #![allow(clippy::too_many_arguments)]
     pub async fn new(
-        chain_config: &TransformChainConfig,
+        chain_config: &SourceChainConfig,
+        shared_chains: &SharedChains,
         source_name: String,
         listen_addr: String,
         hard_connection_limit: bool,
@@ -98,7 +112,7 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
             protocol: codec.protocol(),
         };
         let chain_builder = chain_config
-            .get_builder(chain_usage_config)
+            .get_builder(chain_usage_config, shared_chains)
             .await
             .map_err(|x| vec![format!("{x:?}")])?;
 
@@ -163,6 +177,7 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
                     Err(_e) => {
                         //close the socket too full!
                         self.listener = None;
+                        handoff::unregister(&self.listen_addr);
                         tokio::time::sleep(Duration::from_secs(1)).await;
                         continue;
                     }
@@ -170,6 +185,12 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
             } else {
                 self.limit_connections.clone().acquire_owned().await?
             };
+            if handoff::is_handed_off() {
+                // The listening socket now belongs to the shotover instance replacing us,
+                // stop accepting and leave the existing connections running until shutdown
+                self.listener = None;
+                std::future::pending::<()>().await;
+            }
             if self.listener.is_none() {
                 self.listener = Some(create_listener(&self.listen_addr).await?);
             }
@@ -188,16 +209,19 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
                 self.available_connections_gauge
                     .set(self.limit_connections.available_permits() as f64);
 
-                let client_details = stream
-                    .peer_addr()
+                let client_addr = stream.peer_addr().ok();
+                let client_details = client_addr
                     .map(|p| p.ip().to_string())
-                    .unwrap_or_else(|_| "Unknown peer".to_string());
+                    .unwrap_or_else(|| "Unknown peer".to_string());
                 tracing::debug!("New connection from {}", client_details);
 
                 let force_run_chain = Arc::new(Notify::new());
+                let close_connection = CancellationToken::new();
                 let context = TransformContextBuilder {
                     force_run_chain: force_run_chain.clone(),
                     client_details: client_details.clone(),
+                    client_addr,
+                    close_connection: close_connection.clone(),
                 };
 
                 let handler = Handler {
@@ -206,13 +230,16 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
                     shutdown: Shutdown::new(self.trigger_shutdown_rx.clone()),
                     tls: self.tls.clone(),
                     pending_requests: PendingRequests::new(self.codec.protocol()),
+                    handshake: Handshake::new(self.codec.protocol()),
                     timeout: self.timeout,
+                    close_connection,
                     _permit: permit,
                 };
 
                 // Spawn a new task to process the connections.
-                self.connection_handles.push(tokio::spawn(
-                    async move {
+                self.connection_handles.push(sharding::spawn_connection(
+                    stream,
+                    move |stream| async move {
                         // Process the connection. If an error is encountered, log it.
                         if let Err(err) = handler
                             .run(stream, transport, force_run_chain, client_details)
@@ -223,9 +250,8 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
                                 err.context("connection was unexpectedly terminated")
                             );
                         }
-                    }
-                    .in_current_span(),
-                ));
+                    },
+                )?);
                 // Only prune the list every so often
                 // theres no point in doing it every iteration because most likely none of the handles will have completed
                 if self.connection_count % 1000 == 0 {
@@ -256,7 +282,17 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
         loop {
             // Perform the accept operation. If a socket is successfully
             // accepted, return it. Otherwise, save the error.
-            match self.listener.as_mut().unwrap().accept().await {
+            let accepted = tokio::select! {
+                result = self.listener.as_mut().unwrap().accept() => Some(result),
+                _ = handoff::handed_off() => None,
+            };
+            let Some(result) = accepted else {
+                // The listening socket now belongs to the shotover instance replacing us,
+                // stop accepting and leave the existing connections running until shutdown
+                self.listener = None;
+                return std::future::pending().await;
+            };
+            match result {
                 Ok((socket, _)) => return Ok(socket),
                 Err(err) => {
                     if backoff > 64 {
@@ -276,7 +312,7 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
 }
 
 async fn create_listener(listen_addr: &str) -> Result<TcpListener> {
-    TcpListener::bind(listen_addr)
+    handoff::bind(listen_addr)
         .await
         .map_err(|e| anyhow!("{} address={}", e, listen_addr))
 }
@@ -285,6 +321,7 @@ pub struct Handler<C: CodecBuilder> {
     chain: TransformChain,
     codec: C,
     pending_requests: PendingRequests,
+    handshake: Handshake,
     tls: Option<TlsAcceptor>,
     /// Listen for shutdown notifications.
     ///
@@ -297,6 +334,8 @@ pub struct Handler<C: CodecBuilder> {
     shutdown: Shutdown,
     /// Timeout in seconds after which to kill an idle connection. No timeout means connections will never be timed out.
     timeout: Option<Duration>,
+    /// Cancelled by a transform to close the connection
+    close_connection: CancellationToken,
     _permit: OwnedSemaphorePermit,
 }
 
@@ -630,13 +669,25 @@ impl<C: CodecBuilder + 'static> Handler<C> {
             }
         };
 
-        let result = self
-            .process_messages(&client_details, local_addr, in_rx, out_tx, force_run_chain)
-            .await;
+        let result = match self
+            .chain
+            .process_connection_event(ConnectionEvent::Connected)
+            .await
+        {
+            Ok(()) => {
+                self.process_messages(&client_details, local_addr, in_rx, out_tx, force_run_chain)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
 
         // Only flush messages if we are shutting down due to application shutdown
         // If a Transform::transform returns an Err the transform is no longer in a usable state and needs to be destroyed without reusing.
         if result.is_ok() {
+            if self.shutdown.is_shutdown() {
+                self.process_connection_event(ConnectionEvent::Draining)
+                    .await;
+            }
             match self.chain.process_request(Wrapper::flush()).await {
                 Ok(_) => {}
                 Err(e) => error!(
@@ -648,10 +699,24 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                 ),
             }
         }
+        self.process_connection_event(ConnectionEvent::Closed).await;
 
         result
     }
 
+    /// Notifies the chain of an event that cannot close the connection, logging any failures
+    async fn process_connection_event(&mut self, event: ConnectionEvent) {
+        if let Err(err) = self.chain.process_connection_event(event).await {
+            error!(
+                "{:?}",
+                err.context(format!(
+                    "chain {} failed to handle {event:?}",
+                    self.chain.name
+                ))
+            );
+        }
+    }
+
     async fn receive_with_timeout(
         timeout: Option<Duration>,
         in_rx: &mut mpsc::Receiver<Vec<Message>>,
@@ -690,6 +755,10 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                     // This will result in the task terminating.
                     return Ok(());
                 }
+                _ = self.close_connection.cancelled() => {
+                    debug!("A transform in the chain closed the connection to {client_details}");
+                    return Ok(());
+                }
                 () = force_run_chain.notified() => {
                     let mut requests = vec!();
                     while let Ok(x) = in_rx.try_recv() {
@@ -709,6 +778,7 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                         }
                         None => {
                             // Either we timed out the connection or the client disconnected, so terminate this connection
+                            self.record_abandoned_requests(client_details);
                             return Ok(())
                         }
                     }
@@ -720,6 +790,7 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                 debug!("sending response to client: {:?}", responses);
                 if out_tx.send(responses).is_err() {
                     // the client has disconnected so we should terminate this connection
+                    self.record_abandoned_requests(client_details);
                     return Ok(());
                 }
             }
@@ -728,12 +799,33 @@ impl<C: CodecBuilder + 'static> Handler<C> {
         Ok(())
     }
 
+    /// Counts the requests that will never be responded to because the client is gone before their responses arrived.
+    /// The upstream work for them is cancelled by dropping the chain along with the connection:
+    /// sinks close their connections, discarding any requests not yet written, and requests queued on connections shared with other clients are skipped.
+    /// The chain is never interrupted partway through a batch, since a client that has only closed its sending side of the connection still reads the responses.
+    fn record_abandoned_requests(&mut self, client_details: &str) {
+        let abandoned = self.pending_requests.len();
+        if abandoned > 0 {
+            debug!("{client_details} disconnected with {abandoned} requests awaiting a response, abandoning them");
+            counter!(
+                "shotover_abandoned_requests_count",
+                "chain" => self.chain.name
+            )
+            .increment(abandoned as u64);
+        }
+    }
+
     async fn process(
         &mut self,
         local_addr: SocketAddr,
         out_tx: &mpsc::UnboundedSender<Messages>,
-        requests: Messages,
+        mut requests: Messages,
     ) -> Result<Messages> {
+        if self.handshake.process_requests(&mut requests) {
+            self.chain
+                .process_connection_event(ConnectionEvent::Authenticated)
+                .await?;
+        }
         self.pending_requests.process_requests(&requests);
 
         let wrapper = Wrapper::new_with_addr(requests, local_addr);
@@ -741,8 +833,18 @@ impl<C: CodecBuilder + 'static> Handler<C> {
         match self.chain.process_request(wrapper).await.context(
             "Chain failed to send and/or receive messages, the connection will now be closed.",
         ) {
-            Ok(x) => {
+            Ok(mut x) => {
                 self.pending_requests.process_responses(&x);
+                if self.handshake.process_responses(&mut x) {
+                    if let Err(err) = self
+                        .chain
+                        .process_connection_event(ConnectionEvent::Authenticated)
+                        .await
+                    {
+                        out_tx.send(x)?;
+                        return Err(err);
+                    }
+                }
                 Ok(x)
             }
             Err(err) => {
@@ -806,6 +908,163 @@ impl Shutdown {
     }
 }
 
+/// Detects the client successfully authenticating while it establishes its connection, for [`ConnectionEvent::Authenticated`].
+/// Requests are only inspected until the first request that is not part of establishing the connection,
+/// so that the requests of an established connection are not parsed just to look for authentication.
+struct Handshake {
+    /// Set once the client sends a request that is not part of establishing the connection
+    complete: bool,
+    /// Whether the most recent response to an authentication request was successful
+    authenticated: bool,
+    /// Set once the authentication has been reported, so that it is only reported once
+    reported: bool,
+    /// Authentication requests that have not received a response yet
+    pending_authentications: MessageIdSet,
+}
+
+enum HandshakeRequest {
+    Authenticate,
+    Other,
+    /// The request is not part of establishing the connection, so the handshake is complete
+    NotHandshake,
+}
+
+impl Handshake {
+    fn new(message_type: MessageType) -> Self {
+        let complete = match message_type {
+            #[cfg(feature = "redis")]
+            MessageType::Redis => false,
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra => false,
+            #[cfg(feature = "kafka")]
+            MessageType::Kafka => false,
+            #[cfg(feature = "opensearch")]
+            MessageType::OpenSearch => true,
+            MessageType::Dummy => true,
+        };
+        Handshake {
+            complete,
+            authenticated: false,
+            reported: false,
+            pending_authentications: Default::default(),
+        }
+    }
+
+    /// Returns true if the handshake has just completed and the client authenticated during it
+    fn process_requests(&mut self, requests: &mut [Message]) -> bool {
+        if self.complete {
+            return false;
+        }
+        for request in requests {
+            match handshake_request(request) {
+                HandshakeRequest::Authenticate => {
+                    self.pending_authentications.insert(request.id());
+                }
+                HandshakeRequest::Other => {}
+                HandshakeRequest::NotHandshake => {
+                    self.complete = true;
+                    return self.report();
+                }
+            }
+        }
+        false
+    }
+
+    /// Returns true if the handshake is already complete and the client has just been authenticated.
+    /// This occurs when the client pipelines requests after its authentication request without waiting for the response.
+    fn process_responses(&mut self, responses: &mut [Message]) -> bool {
+        if self.pending_authentications.is_empty() {
+            return false;
+        }
+        for response in responses {
+            if let Some(request_id) = response.request_id() {
+                if self.pending_authentications.remove(&request_id) {
+                    self.authenticated = authentication_succeeded(response);
+                }
+            }
+        }
+        self.complete && self.report()
+    }
+
+    fn report(&mut self) -> bool {
+        let report = self.authenticated && !self.reported;
+        self.reported |= report;
+        report
+    }
+}
+
+fn handshake_request(request: &mut Message) -> HandshakeRequest {
+    match request.message_type() {
+        #[cfg(feature = "cassandra")]
+        MessageType::Cassandra => match request.metadata() {
+            Ok(Metadata::Cassandra(metadata)) => match metadata.opcode {
+                Opcode::AuthResponse => HandshakeRequest::Authenticate,
+                Opcode::Options | Opcode::Startup => HandshakeRequest::Other,
+                _ => HandshakeRequest::NotHandshake,
+            },
+            _ => HandshakeRequest::NotHandshake,
+        },
+        #[cfg(feature = "redis")]
+        MessageType::Redis => {
+            let Some(Frame::Redis(RedisFrame::Array(args))) = request.frame() else {
+                return HandshakeRequest::NotHandshake;
+            };
+            let is = |arg: &RedisFrame, name: &[u8]| matches!(arg, RedisFrame::BulkString(arg) if arg.eq_ignore_ascii_case(name));
+            match args.first() {
+                Some(command) if is(command, b"AUTH") => HandshakeRequest::Authenticate,
+                Some(command) if is(command, b"HELLO") => {
+                    if args.iter().skip(1).any(|arg| is(arg, b"AUTH")) {
+                        HandshakeRequest::Authenticate
+                    } else {
+                        HandshakeRequest::Other
+                    }
+                }
+                Some(command) if is(command, b"CLIENT") => HandshakeRequest::Other,
+                _ => HandshakeRequest::NotHandshake,
+            }
+        }
+        #[cfg(feature = "kafka")]
+        MessageType::Kafka => match request.frame() {
+            Some(Frame::Kafka(KafkaFrame::Request { body, .. })) => match body {
+                RequestBody::SaslAuthenticate(_) => HandshakeRequest::Authenticate,
+                RequestBody::ApiVersions(_) | RequestBody::SaslHandshake(_) => {
+                    HandshakeRequest::Other
+                }
+                _ => HandshakeRequest::NotHandshake,
+            },
+            _ => HandshakeRequest::NotHandshake,
+        },
+        #[cfg(feature = "opensearch")]
+        MessageType::OpenSearch => HandshakeRequest::NotHandshake,
+        MessageType::Dummy => HandshakeRequest::NotHandshake,
+    }
+}
+
+/// Kafka SASL mechanisms such as SCRAM take multiple SaslAuthenticate round trips that each succeed,
+/// which is why authentication is only reported once the handshake is complete.
+fn authentication_succeeded(response: &mut Message) -> bool {
+    match response.message_type() {
+        #[cfg(feature = "cassandra")]
+        MessageType::Cassandra => matches!(
+            response.metadata(),
+            Ok(Metadata::Cassandra(metadata)) if metadata.opcode == Opcode::AuthSuccess
+        ),
+        #[cfg(feature = "redis")]
+        MessageType::Redis => !response.is_error(),
+        #[cfg(feature = "kafka")]
+        MessageType::Kafka => matches!(
+            response.frame(),
+            Some(Frame::Kafka(KafkaFrame::Response {
+                body: ResponseBody::SaslAuthenticate(authenticate),
+                ..
+            })) if authenticate.error_code == 0
+        ),
+        #[cfg(feature = "opensearch")]
+        MessageType::OpenSearch => false,
+        MessageType::Dummy => false,
+    }
+}
+
 /// Keeps track of all currently pending requests.
 /// This allows error responses to be generated if the connection needs to be terminated before the response comes back.
 enum PendingRequests {
@@ -846,6 +1105,15 @@ impl PendingRequests {
         }
     }
 
+    /// The number of requests still awaiting a response, always 0 for protocols where requests are not tracked
+    fn len(&self) -> usize {
+        match self {
+            PendingRequests::Ordered(pending_requests) => pending_requests.len(),
+            PendingRequests::Unordered(pending_requests) => pending_requests.len(),
+            PendingRequests::Unsupported => 0,
+        }
+    }
+
     fn process_responses(&mut self, responses: &[Message]) {
         match self {
             PendingRequests::Ordered(pending_requests) => {
@@ -932,3 +1200,54 @@ impl PendingRequests {
         }
     }
 }
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::transforms::testing::redis_request;
+
+    fn response_to(request: &Message, frame: RedisFrame) -> Message {
+        let mut response = Message::from_frame(Frame::Redis(frame));
+        response.set_request_id(request.id());
+        response
+    }
+
+    #[test]
+    fn test_handshake_authenticated() {
+        let mut handshake = Handshake::new(MessageType::Redis);
+        let mut requests = vec![redis_request(&["HELLO", "3", "AUTH", "user", "pass"])];
+        assert!(!handshake.process_requests(&mut requests));
+        let mut responses = vec![response_to(&requests[0], RedisFrame::Array(vec![]))];
+        assert!(!handshake.process_responses(&mut responses));
+        assert!(handshake.process_requests(&mut [redis_request(&["GET", "foo"])]));
+        assert!(!handshake.process_requests(&mut [redis_request(&["AUTH", "pass"])]));
+    }
+
+    #[test]
+    fn test_handshake_pipelined_authentication() {
+        let mut handshake = Handshake::new(MessageType::Redis);
+        let mut requests = vec![
+            redis_request(&["AUTH", "pass"]),
+            redis_request(&["GET", "foo"]),
+        ];
+        assert!(!handshake.process_requests(&mut requests));
+        let mut responses = vec![
+            response_to(&requests[0], RedisFrame::SimpleString("OK".into())),
+            response_to(&requests[1], RedisFrame::Null),
+        ];
+        assert!(handshake.process_responses(&mut responses));
+    }
+
+    #[test]
+    fn test_handshake_failed_authentication() {
+        let mut handshake = Handshake::new(MessageType::Redis);
+        let mut requests = vec![redis_request(&["AUTH", "wrong"])];
+        assert!(!handshake.process_requests(&mut requests));
+        let mut responses = vec![response_to(
+            &requests[0],
+            RedisFrame::Error("WRONGPASS invalid username-password pair".into()),
+        )];
+        assert!(!handshake.process_responses(&mut responses));
+        assert!(!handshake.process_requests(&mut [redis_request(&["GET", "foo"])]));
+    }
+}
@@ -0,0 +1,133 @@
+//! Runs client connections on a fixed set of single threaded runtimes, called shards.
+//!
+//! By default every task of every connection runs on the shared multi threaded tokio runtime,
+//! so the tasks of a single connection (the handler, its codec read/write tasks and its sink connections) are free to move between threads.
+//! At high connection counts this causes contention on the runtime's shared queues and on the channels between the tasks of a connection.
+//!
+//! When sharding is enabled each accepted connection is assigned to a shard and everything spawned by that connection stays on the shard's thread,
+//! so the channels between a connection's tasks are never used across threads.
+//!
+//! Sharding deliberately stops short of each shard owning its connections end to end:
+//! * Connections are accepted by the source's single listener on the shared runtime, and the accepted socket is then moved to its shard.
+//!   A listener per shard bound with `SO_REUSEPORT` would leave the kernel to balance connections between shards,
+//!   could not be handed to a new instance as a single socket by `--handoff-socket`, and would split the source's connection limit.
+//! * Sink connections pooled between client connections, such as those of `RedisSinkCluster`, are shared by every shard.
+//!   A pool per shard would multiply the connections opened to each node by the number of shards.
+//! * Shards use tokio's readiness based IO driver, there is no completion based driver such as io_uring.
+//!   Sockets of completion based runtimes own the buffers they read into and so do not implement `AsyncRead`/`AsyncWrite`,
+//!   which every codec is driven through by `FramedRead`/`FramedWrite`.
+//!   Their tasks are also `!Send`, while transforms and the tasks of a connection are spawned as `Send`.
+
+use anyhow::{ensure, Context, Result};
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tokio::net::TcpStream;
+use tokio::runtime::{self, Handle};
+use tokio::task::JoinHandle;
+use tracing::{error, Instrument};
+
+static SHARDS: OnceLock<Shards> = OnceLock::new();
+
+pub struct Shards {
+    handles: Vec<Handle>,
+    next: AtomicUsize,
+}
+
+impl Shards {
+    /// Starts `count` shards, each running a single threaded runtime on its own thread.
+    /// The shards run until the process exits.
+    pub fn new(count: usize, stack_size: usize) -> Result<Self> {
+        ensure!(count > 0, "at least one shard is required");
+        let mut handles = Vec::with_capacity(count);
+        for i in 0..count {
+            let runtime = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .context("Failed to create shard runtime")?;
+            handles.push(runtime.handle().clone());
+            std::thread::Builder::new()
+                .name(format!("shotover-shard-{i}"))
+                .stack_size(stack_size)
+                .spawn(move || runtime.block_on(std::future::pending::<()>()))
+                .context("Failed to spawn shard thread")?;
+        }
+        Ok(Shards {
+            handles,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Spawns the future onto the next shard, shards are assigned round robin.
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let shard = self.next.fetch_add(1, Ordering::Relaxed) % self.handles.len();
+        self.handles[shard].spawn(future)
+    }
+}
+
+/// Enables sharding for all connections accepted from now on.
+/// Returns an error if sharding was already enabled.
+pub(crate) fn enable(shards: Shards) -> Result<()> {
+    SHARDS
+        .set(shards)
+        .map_err(|_| anyhow::anyhow!("sharding was already enabled"))
+}
+
+/// Spawns the task that handles a client connection, onto a shard if sharding is enabled.
+///
+/// The socket is reregistered with the IO driver of the shard it is assigned to,
+/// otherwise its readiness events would still be delivered by the runtime that accepted it.
+pub(crate) fn spawn_connection<F, Fut>(stream: TcpStream, handler: F) -> Result<JoinHandle<()>>
+where
+    F: FnOnce(TcpStream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    match SHARDS.get() {
+        Some(shards) => {
+            let stream = stream.into_std()?;
+            Ok(shards.spawn(
+                async move {
+                    match TcpStream::from_std(stream) {
+                        Ok(stream) => handler(stream).await,
+                        Err(err) => error!("Failed to move connection to shard: {err:?}"),
+                    }
+                }
+                .in_current_span(),
+            ))
+        }
+        None => Ok(tokio::spawn(handler(stream).in_current_span())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let shards = Shards::new(2, 2 * 1024 * 1024).unwrap();
+        let mut threads = vec![];
+        for _ in 0..4 {
+            threads.push(
+                shards
+                    .spawn(async { std::thread::current().name().unwrap().to_owned() })
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(
+            threads,
+            vec![
+                "shotover-shard-0",
+                "shotover-shard-1",
+                "shotover-shard-0",
+                "shotover-shard-1"
+            ]
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Snapshots of shotover's internal state, served by the observability interface for attaching to bug reports about a running instance.
+//!
+//! Components with state worth reporting, such as the slot map of a `RedisSinkCluster`, register a [`StateSnapshot`] along with the name of their chain.
+//! A component stops being included in snapshots once it is dropped.
+
+use async_trait::async_trait;
+use clap::crate_version;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[async_trait]
+pub(crate) trait StateSnapshot: Send + Sync {
+    /// Serializes the current state of the component.
+    /// The state should be read under a single acquisition of each lock so that it is consistent with itself.
+    async fn snapshot(&self) -> Value;
+}
+
+struct Registration {
+    component: &'static str,
+    chain: String,
+    state: Weak<dyn StateSnapshot>,
+}
+
+static REGISTRATIONS: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
+/// Includes `state` in snapshots until it is dropped
+pub(crate) fn register<S: StateSnapshot + 'static>(
+    component: &'static str,
+    chain: &str,
+    state: &Arc<S>,
+) {
+    let state: Weak<dyn StateSnapshot> = Arc::downgrade(state);
+    let mut registrations = REGISTRATIONS.lock().unwrap();
+    registrations.retain(|registration| registration.state.strong_count() > 0);
+    registrations.push(Registration {
+        component,
+        chain: chain.to_owned(),
+        state,
+    });
+}
+
+#[derive(Serialize)]
+pub(crate) struct Snapshot {
+    pub shotover_version: &'static str,
+    /// Seconds since the unix epoch at which the snapshot was taken
+    pub taken_at: u64,
+    /// The sources of the topology the instance was started with, including the configuration of every transform in their chains
+    pub sources: Arc<Value>,
+    pub components: Vec<ComponentSnapshot>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ComponentSnapshot {
+    pub component: &'static str,
+    pub chain: String,
+    pub state: Value,
+}
+
+/// Takes a snapshot of every registered component.
+/// Each component is internally consistent, but components are snapshotted one after the other
+/// so state shared between components may have changed in between.
+pub(crate) async fn take(sources: Arc<Value>) -> Snapshot {
+    let taken_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+
+    // The registrations must not be locked across an await, so hold onto every live component before snapshotting them
+    let mut live: Vec<_> = REGISTRATIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|registration| {
+            Some((
+                registration.component,
+                registration.chain.clone(),
+                registration.state.upgrade()?,
+            ))
+        })
+        .collect();
+    live.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+
+    let mut components = Vec::with_capacity(live.len());
+    for (component, chain, state) in live {
+        components.push(ComponentSnapshot {
+            component,
+            chain,
+            state: state.snapshot().await,
+        });
+    }
+
+    Snapshot {
+        shotover_version: crate_version!(),
+        taken_at,
+        sources,
+        components,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    struct Counter(usize);
+
+    #[async_trait]
+    impl StateSnapshot for Counter {
+        async fn snapshot(&self) -> Value {
+            json!({ "count": self.0 })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dropped_components_are_not_snapshotted() {
+        let kept = Arc::new(Counter(1));
+        let dropped = Arc::new(Counter(2));
+        register("TestCounter", "kept_chain", &kept);
+        register("TestCounter", "dropped_chain", &dropped);
+        std::mem::drop(dropped);
+
+        let snapshot = take(Arc::new(json!([]))).await;
+        let counters: Vec<_> = snapshot
+            .components
+            .iter()
+            .filter(|x| x.component == "TestCounter")
+            .map(|x| (x.chain.as_str(), x.state.clone()))
+            .collect();
+        assert_eq!(counters, vec![("kept_chain", json!({ "count": 1 }))]);
+    }
+}
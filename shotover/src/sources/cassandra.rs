@@ -1,6 +1,6 @@
 use crate::codec::Direction;
 use crate::codec::{cassandra::CassandraCodecBuilder, CodecBuilder};
-use crate::config::chain::TransformChainConfig;
+use crate::config::chain::{SharedChains, SourceChainConfig};
 use crate::server::TcpCodecListener;
 use crate::sources::{Source, Transport};
 use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
@@ -22,18 +22,20 @@ pub struct CassandraConfig {
     pub tls: Option<TlsAcceptorConfig>,
     pub timeout: Option<u64>,
     pub transport: Option<Transport>,
-    pub chain: TransformChainConfig,
+    pub chain: SourceChainConfig,
 }
 
 impl CassandraConfig {
     pub async fn get_source(
         &self,
         trigger_shutdown_rx: watch::Receiver<bool>,
+        shared_chains: &SharedChains,
     ) -> Result<Source, Vec<String>> {
         Ok(Source::Cassandra(
             CassandraSource::new(
                 self.name.clone(),
                 &self.chain,
+                shared_chains,
                 self.listen_addr.clone(),
                 trigger_shutdown_rx,
                 self.connection_limit,
@@ -56,7 +58,8 @@ impl CassandraSource {
     #![allow(clippy::too_many_arguments)]
     pub async fn new(
         name: String,
-        chain_config: &TransformChainConfig,
+        chain_config: &SourceChainConfig,
+        shared_chains: &SharedChains,
         listen_addr: String,
         mut trigger_shutdown_rx: watch::Receiver<bool>,
         connection_limit: Option<usize>,
@@ -69,6 +72,7 @@ impl CassandraSource {
 
         let mut listener = TcpCodecListener::new(
             chain_config,
+            shared_chains,
             name.to_string(),
             listen_addr.clone(),
             hard_connection_limit.unwrap_or(false),
@@ -1,5 +1,5 @@
 use crate::codec::{kafka::KafkaCodecBuilder, CodecBuilder, Direction};
-use crate::config::chain::TransformChainConfig;
+use crate::config::chain::{SharedChains, SourceChainConfig};
 use crate::server::TcpCodecListener;
 use crate::sources::{Source, Transport};
 use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
@@ -20,18 +20,20 @@ pub struct KafkaConfig {
     pub hard_connection_limit: Option<bool>,
     pub tls: Option<TlsAcceptorConfig>,
     pub timeout: Option<u64>,
-    pub chain: TransformChainConfig,
+    pub chain: SourceChainConfig,
 }
 
 impl KafkaConfig {
     pub async fn get_source(
         &self,
         trigger_shutdown_rx: watch::Receiver<bool>,
+        shared_chains: &SharedChains,
     ) -> Result<Source, Vec<String>> {
         Ok(Source::Kafka(
             KafkaSource::new(
                 self.name.clone(),
                 &self.chain,
+                shared_chains,
                 self.listen_addr.clone(),
                 trigger_shutdown_rx,
                 self.connection_limit,
@@ -53,7 +55,8 @@ impl KafkaSource {
     #![allow(clippy::too_many_arguments)]
     pub async fn new(
         name: String,
-        chain_config: &TransformChainConfig,
+        chain_config: &SourceChainConfig,
+        shared_chains: &SharedChains,
         listen_addr: String,
         mut trigger_shutdown_rx: watch::Receiver<bool>,
         connection_limit: Option<usize>,
@@ -65,6 +68,7 @@ impl KafkaSource {
 
         let mut listener = TcpCodecListener::new(
             chain_config,
+            shared_chains,
             name.to_string(),
             listen_addr.clone(),
             hard_connection_limit.unwrap_or(false),
@@ -1,5 +1,7 @@
 //! Sources used to listen for connections and send/recieve with the client.
 
+use crate::config::chain::{SharedChains, SourceChainConfig};
+use crate::frame::MessageType;
 #[cfg(feature = "cassandra")]
 use crate::sources::cassandra::{CassandraConfig, CassandraSource};
 #[cfg(feature = "kafka")]
@@ -73,16 +75,43 @@ impl SourceConfig {
     pub(crate) async fn get_source(
         &self,
         trigger_shutdown_rx: watch::Receiver<bool>,
+        shared_chains: &SharedChains,
     ) -> Result<Source, Vec<String>> {
         match self {
             #[cfg(feature = "cassandra")]
-            SourceConfig::Cassandra(c) => c.get_source(trigger_shutdown_rx).await,
+            SourceConfig::Cassandra(c) => c.get_source(trigger_shutdown_rx, shared_chains).await,
             #[cfg(feature = "redis")]
-            SourceConfig::Redis(r) => r.get_source(trigger_shutdown_rx).await,
+            SourceConfig::Redis(r) => r.get_source(trigger_shutdown_rx, shared_chains).await,
             #[cfg(feature = "kafka")]
-            SourceConfig::Kafka(r) => r.get_source(trigger_shutdown_rx).await,
+            SourceConfig::Kafka(r) => r.get_source(trigger_shutdown_rx, shared_chains).await,
             #[cfg(feature = "opensearch")]
-            SourceConfig::OpenSearch(r) => r.get_source(trigger_shutdown_rx).await,
+            SourceConfig::OpenSearch(r) => r.get_source(trigger_shutdown_rx, shared_chains).await,
+        }
+    }
+
+    pub(crate) fn get_chain(&self) -> &SourceChainConfig {
+        match self {
+            #[cfg(feature = "cassandra")]
+            SourceConfig::Cassandra(c) => &c.chain,
+            #[cfg(feature = "redis")]
+            SourceConfig::Redis(r) => &r.chain,
+            #[cfg(feature = "kafka")]
+            SourceConfig::Kafka(r) => &r.chain,
+            #[cfg(feature = "opensearch")]
+            SourceConfig::OpenSearch(r) => &r.chain,
+        }
+    }
+
+    pub(crate) fn get_protocol(&self) -> MessageType {
+        match self {
+            #[cfg(feature = "cassandra")]
+            SourceConfig::Cassandra(_) => MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            SourceConfig::Redis(_) => MessageType::Redis,
+            #[cfg(feature = "kafka")]
+            SourceConfig::Kafka(_) => MessageType::Kafka,
+            #[cfg(feature = "opensearch")]
+            SourceConfig::OpenSearch(_) => MessageType::OpenSearch,
         }
     }
 
@@ -1,5 +1,5 @@
 use crate::codec::{opensearch::OpenSearchCodecBuilder, CodecBuilder, Direction};
-use crate::config::chain::TransformChainConfig;
+use crate::config::chain::{SharedChains, SourceChainConfig};
 use crate::server::TcpCodecListener;
 use crate::sources::{Source, Transport};
 use anyhow::Result;
@@ -17,18 +17,20 @@ pub struct OpenSearchConfig {
     pub connection_limit: Option<usize>,
     pub hard_connection_limit: Option<bool>,
     pub timeout: Option<u64>,
-    pub chain: TransformChainConfig,
+    pub chain: SourceChainConfig,
 }
 
 impl OpenSearchConfig {
     pub async fn get_source(
         &self,
         trigger_shutdown_rx: watch::Receiver<bool>,
+        shared_chains: &SharedChains,
     ) -> Result<Source, Vec<String>> {
         Ok(Source::OpenSearch(
             OpenSearchSource::new(
                 self.name.clone(),
                 &self.chain,
+                shared_chains,
                 self.listen_addr.clone(),
                 trigger_shutdown_rx,
                 self.connection_limit,
@@ -48,7 +50,8 @@ pub struct OpenSearchSource {
 impl OpenSearchSource {
     pub async fn new(
         name: String,
-        chain_config: &TransformChainConfig,
+        chain_config: &SourceChainConfig,
+        shared_chains: &SharedChains,
         listen_addr: String,
         mut trigger_shutdown_rx: watch::Receiver<bool>,
         connection_limit: Option<usize>,
@@ -59,6 +62,7 @@ impl OpenSearchSource {
 
         let mut listener = TcpCodecListener::new(
             chain_config,
+            shared_chains,
             name.to_string(),
             listen_addr.clone(),
             hard_connection_limit.unwrap_or(false),
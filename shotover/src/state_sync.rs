@@ -0,0 +1,306 @@
+//! Keeps a standby shotover warm by syncing the state the active shotover has learned about its upstream clusters,
+//! so that when a VIP or load balancer fails over to the standby it does not first have to re-learn the cluster topology.
+//!
+//! When `--state-sync-listen` is set shotover accepts connections from standby instances on that address,
+//! and sends each of them the state of every registered component every `--state-sync-interval-ms`.
+//! When `--state-sync-from` is set shotover connects to the active instance at that address and imports the state it receives.
+//!
+//! Each sync is a JSON document prefixed with its length as a big endian u32.
+//! State is matched to components by the component name and the name of its chain,
+//! so the standby should be running the same topology as the active instance.
+//! A component ignores synced state once it has learned the state itself, which happens when it serves its first client,
+//! so two instances can sync from each other and whichever is not receiving traffic is kept warm.
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use clap::crate_version;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{debug, info, warn};
+
+/// Syncs larger than this are rejected rather than allocating a buffer for them.
+const MAX_SYNC_BYTES: usize = 64 * 1024 * 1024;
+
+#[async_trait]
+pub(crate) trait SyncedState: Send + Sync {
+    /// Returns the state to send to standby instances, or None if nothing has been learned yet.
+    async fn export(&self) -> Option<Value>;
+
+    /// Applies state exported by the same component of the active instance.
+    /// Should do nothing if the component has already learned the state itself.
+    async fn import(&self, state: Value) -> Result<()>;
+}
+
+struct Registration {
+    component: &'static str,
+    chain: String,
+    state: Weak<dyn SyncedState>,
+}
+
+static REGISTRATIONS: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+
+/// Includes `state` in state sync until it is dropped
+pub(crate) fn register<S: SyncedState + 'static>(
+    component: &'static str,
+    chain: &str,
+    state: &Arc<S>,
+) {
+    let state: Weak<dyn SyncedState> = Arc::downgrade(state);
+    let mut registrations = REGISTRATIONS.lock().unwrap();
+    registrations.retain(|registration| registration.state.strong_count() > 0);
+    registrations.push(Registration {
+        component,
+        chain: chain.to_owned(),
+        state,
+    });
+}
+
+fn live_registrations() -> Vec<(&'static str, String, Arc<dyn SyncedState>)> {
+    // The registrations must not be locked across an await, so hold onto every live component before using them
+    REGISTRATIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|registration| {
+            Some((
+                registration.component,
+                registration.chain.clone(),
+                registration.state.upgrade()?,
+            ))
+        })
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SyncMessage {
+    shotover_version: String,
+    components: Vec<ComponentState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct ComponentState {
+    component: String,
+    chain: String,
+    state: Value,
+}
+
+async fn export() -> SyncMessage {
+    let mut components = vec![];
+    for (component, chain, state) in live_registrations() {
+        if let Some(state) = state.export().await {
+            components.push(ComponentState {
+                component: component.to_owned(),
+                chain,
+                state,
+            });
+        }
+    }
+    SyncMessage {
+        shotover_version: crate_version!().to_owned(),
+        components,
+    }
+}
+
+/// Imports each component state into every registered component with the same name and chain.
+/// Returns the number of components that state was imported into.
+async fn import(sync: SyncMessage) -> usize {
+    let registrations = live_registrations();
+    let mut imported = 0;
+    for component_state in sync.components {
+        for (component, chain, state) in &registrations {
+            if *component == component_state.component && *chain == component_state.chain {
+                match state.import(component_state.state.clone()).await {
+                    Ok(()) => imported += 1,
+                    Err(err) => warn!(
+                        "{:?}",
+                        err.context(format!(
+                            "Failed to import synced state of {component} in chain {chain}"
+                        ))
+                    ),
+                }
+            }
+        }
+    }
+    imported
+}
+
+struct StateSync {
+    listen: Option<String>,
+    from: Option<String>,
+    interval: Duration,
+}
+
+static STATE_SYNC: OnceLock<StateSync> = OnceLock::new();
+
+/// Enables sending state to standby instances connecting to `listen` and importing state from the active instance at `from`.
+pub(crate) fn enable(
+    listen: Option<String>,
+    from: Option<String>,
+    interval: Duration,
+) -> Result<()> {
+    if listen.is_none() && from.is_none() {
+        return Ok(());
+    }
+    if interval.is_zero() {
+        return Err(anyhow!("--state-sync-interval-ms must be greater than 0"));
+    }
+    STATE_SYNC
+        .set(StateSync {
+            listen,
+            from,
+            interval,
+        })
+        .map_err(|_| anyhow!("state sync was already enabled"))
+}
+
+/// Starts syncing state.
+/// Must be called once all sources are running so that the components of every chain are registered.
+pub(crate) async fn start() -> Result<()> {
+    let Some(state_sync) = STATE_SYNC.get() else {
+        return Ok(());
+    };
+
+    if let Some(listen) = &state_sync.listen {
+        let listener = crate::handoff::bind(listen)
+            .await
+            .with_context(|| format!("Failed to listen for standby instances on {listen}"))?;
+        info!("Sending state to standby instances that connect to {listen}");
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, address)) => {
+                        info!("Standby instance {address} connected for state sync");
+                        tokio::spawn(async move {
+                            if let Err(err) = send_syncs(stream, state_sync.interval).await {
+                                warn!("Stopped sending state to standby instance {address}: {err}");
+                            }
+                        });
+                    }
+                    Err(err) => warn!("Failed to accept standby instance: {err}"),
+                }
+            }
+        });
+    }
+
+    if let Some(from) = &state_sync.from {
+        tokio::spawn(async move {
+            loop {
+                match TcpStream::connect(from).await {
+                    Ok(stream) => {
+                        info!("Receiving state from the active instance at {from}");
+                        if let Err(err) = receive_syncs(stream).await {
+                            warn!("Stopped receiving state from the active instance at {from}: {err:?}");
+                        }
+                    }
+                    Err(err) => {
+                        warn!("Failed to connect to the active instance at {from} for state sync: {err}")
+                    }
+                }
+                tokio::time::sleep(state_sync.interval).await;
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn send_syncs(mut stream: TcpStream, interval: Duration) -> Result<()> {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        write_sync(&mut stream, &export().await).await?;
+    }
+}
+
+async fn receive_syncs(mut stream: TcpStream) -> Result<()> {
+    let mut warned_version = false;
+    loop {
+        let sync = read_sync(&mut stream).await?;
+        if sync.shotover_version != crate_version!() && !warned_version {
+            warn!(
+                "The active instance is running shotover {} while this instance is running shotover {}, synced state that cannot be imported will be ignored",
+                sync.shotover_version,
+                crate_version!()
+            );
+            warned_version = true;
+        }
+        let imported = import(sync).await;
+        debug!("Imported synced state into {imported} components");
+    }
+}
+
+async fn write_sync<W: AsyncWrite + Unpin>(writer: &mut W, sync: &SyncMessage) -> Result<()> {
+    let bytes = serde_json::to_vec(sync)?;
+    let len = u32::try_from(bytes.len())
+        .ok()
+        .filter(|len| *len as usize <= MAX_SYNC_BYTES)
+        .ok_or_else(|| anyhow!("state sync of {} bytes is too large", bytes.len()))?;
+    writer.write_u32(len).await?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+async fn read_sync<R: AsyncRead + Unpin>(reader: &mut R) -> Result<SyncMessage> {
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_SYNC_BYTES {
+        return Err(anyhow!("state sync of {len} bytes is too large"));
+    }
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes).await?;
+    serde_json::from_slice(&bytes).context("Failed to parse state sync")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[derive(Default)]
+    struct Learned(Mutex<Option<Value>>);
+
+    #[async_trait]
+    impl SyncedState for Learned {
+        async fn export(&self) -> Option<Value> {
+            self.0.lock().unwrap().clone()
+        }
+
+        async fn import(&self, state: Value) -> Result<()> {
+            self.0.lock().unwrap().get_or_insert(state);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_round_trip() {
+        let active = Arc::new(Learned(Mutex::new(Some(json!({ "slots": [1, 2] })))));
+        register("TestLearned", "active_chain", &active);
+        let sync = export().await;
+        std::mem::drop(active);
+        let state = sync
+            .components
+            .iter()
+            .find(|x| x.component == "TestLearned")
+            .unwrap()
+            .state
+            .clone();
+        assert_eq!(state, json!({ "slots": [1, 2] }));
+
+        let (mut writer, mut reader) = tokio::io::duplex(1024);
+        write_sync(&mut writer, &sync).await.unwrap();
+        let received = read_sync(&mut reader).await.unwrap();
+        assert_eq!(received, sync);
+
+        let standby = Arc::new(Learned::default());
+        register("TestLearned", "active_chain", &standby);
+        let other_chain = Arc::new(Learned::default());
+        register("TestLearned", "other_chain", &other_chain);
+        import(received).await;
+        assert_eq!(*standby.0.lock().unwrap(), Some(json!({ "slots": [1, 2] })));
+        assert_eq!(*other_chain.0.lock().unwrap(), None);
+    }
+}
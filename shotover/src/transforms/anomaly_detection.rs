@@ -0,0 +1,643 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::frame::MessageType;
+use crate::message::{Message, MessageIdMap, Messages, QueryType};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
+use anyhow::Result;
+use async_trait::async_trait;
+use governor::{
+    clock::DefaultClock,
+    middleware::NoOpMiddleware,
+    state::{InMemoryState, NotKeyed},
+    Quota, RateLimiter,
+};
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+
+/// Weight given to the most recent window when updating a baseline.
+const BASELINE_ALPHA: f64 = 0.2;
+
+/// Number of separately locked shards that client statistics are split across, so that connections from different clients rarely wait on each other.
+const CLIENT_SHARDS: usize = 16;
+
+/// A client is forgotten once it has sent no requests for this many windows, so that memory is only used for recently seen clients.
+const IDLE_WINDOWS: u32 = 10;
+
+/// Statistics of every client, split into shards by client.
+type Clients = Arc<Vec<Mutex<ClientShard>>>;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AnomalyDetectionConfig {
+    /// Length of the window over which a clients traffic is summarized before being compared to its baseline.
+    pub window_seconds: u64,
+    /// Number of windows that must be observed for a client before it can be penalized.
+    pub warmup_windows: u32,
+    /// How many standard deviations above the baseline the request rate or error rate must be to count as an anomaly.
+    pub deviation_threshold: f64,
+    /// When set, the proportion of each query type is also tracked and a window is anomalous
+    /// if the total variation distance from the baseline command mix exceeds this value (0.0 - 1.0).
+    pub max_command_mix_shift: Option<f64>,
+    pub action: AnomalyAction,
+    /// How long a penalty lasts once applied.
+    pub penalty_seconds: u64,
+    /// Clients that are never penalized regardless of their traffic.
+    #[serde(default)]
+    pub exempt_clients: Vec<IpAddr>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub enum AnomalyAction {
+    /// Respond with an error to every request from the client.
+    Block,
+    /// Respond with an error to requests from the client that exceed this rate.
+    Throttle { max_requests_per_second: NonZeroU32 },
+}
+
+const NAME: &str = "AnomalyDetection";
+#[typetag::serde(name = "AnomalyDetection")]
+#[async_trait(?Send)]
+impl TransformConfig for AnomalyDetectionConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(AnomalyDetectionBuilder {
+            settings: Settings {
+                window: Duration::from_secs(self.window_seconds),
+                warmup_windows: self.warmup_windows,
+                deviation_threshold: self.deviation_threshold,
+                max_command_mix_shift: self.max_command_mix_shift,
+                action: self.action,
+                penalty: Duration::from_secs(self.penalty_seconds),
+            },
+            exempt_clients: self.exempt_clients.clone(),
+            clients: Arc::new((0..CLIENT_SHARDS).map(|_| Mutex::default()).collect()),
+            penalized_clients: counter!("shotover_anomaly_detection_penalized_clients_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Settings {
+    window: Duration,
+    warmup_windows: u32,
+    deviation_threshold: f64,
+    max_command_mix_shift: Option<f64>,
+    action: AnomalyAction,
+    penalty: Duration,
+}
+
+pub struct AnomalyDetectionBuilder {
+    settings: Settings,
+    exempt_clients: Vec<IpAddr>,
+    /// Statistics are shared between all connections from the same client
+    clients: Clients,
+    penalized_clients: Counter,
+}
+
+impl TransformBuilder for AnomalyDetectionBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        let exempt = transform_context
+            .client_details
+            .parse::<IpAddr>()
+            .map(|ip| self.exempt_clients.contains(&ip))
+            .unwrap_or(false);
+
+        let mut hasher = DefaultHasher::new();
+        transform_context.client_details.hash(&mut hasher);
+        let shard = hasher.finish() as usize % self.clients.len();
+
+        Box::new(AnomalyDetection {
+            settings: self.settings,
+            client: transform_context.client_details,
+            exempt,
+            clients: self.clients.clone(),
+            shard,
+            penalized_clients: self.penalized_clients.clone(),
+            rejected_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Requests from penalized clients are rejected
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.settings.window.is_zero() {
+            errors.push("  window_seconds must be greater than 0".to_owned());
+        }
+        if self.settings.warmup_windows == 0 {
+            errors.push("  warmup_windows must be greater than 0".to_owned());
+        }
+        if self.settings.deviation_threshold <= 0.0 {
+            errors.push("  deviation_threshold must be greater than 0".to_owned());
+        }
+        if let Some(shift) = self.settings.max_command_mix_shift {
+            if !(0.0..=1.0).contains(&shift) {
+                errors.push("  max_command_mix_shift must be between 0.0 and 1.0".to_owned());
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", NAME));
+        }
+
+        errors
+    }
+}
+
+pub struct AnomalyDetection {
+    settings: Settings,
+    client: String,
+    exempt: bool,
+    clients: Clients,
+    /// The index of the shard of `clients` holding the statistics of this client
+    shard: usize,
+    penalized_clients: Counter,
+    rejected_requests: MessageIdMap<Message>,
+}
+
+#[async_trait]
+impl Transform for AnomalyDetection {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        if self.exempt {
+            return requests_wrapper.call_next_transform().await;
+        }
+
+        let now = Instant::now();
+        {
+            let mut shard = self.clients[self.shard].lock().unwrap();
+            shard.evict_idle(now, &self.settings);
+            let state = shard
+                .clients
+                .entry(self.client.clone())
+                .or_insert_with(|| ClientState::new(now));
+            state.last_seen = now;
+
+            if state.maybe_close_window(now, &self.settings) {
+                tracing::warn!(
+                    "AnomalyDetection: client {} deviated from its baseline, applying {:?} for {:?}",
+                    self.client,
+                    self.settings.action,
+                    self.settings.penalty,
+                );
+                self.penalized_clients.increment(1);
+            }
+
+            for request in &mut requests_wrapper.requests {
+                if self.settings.max_command_mix_shift.is_some() {
+                    state.current.record_query_type(request);
+                }
+                state.current.requests += 1;
+
+                if state.should_reject(now) {
+                    self.rejected_requests.insert(
+                        request.id(),
+                        request
+                            .from_request_to_error_response(
+                                "Client was penalized by shotover for anomalous traffic".to_owned(),
+                            )
+                            .map_err(|e| e.context("Failed to reject message"))?,
+                    );
+                    request.replace_with_dummy();
+                }
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        let mut errors = 0;
+        for response in responses.iter_mut() {
+            if let Some(request_id) = response.request_id() {
+                if let Some(error_response) = self.rejected_requests.remove(&request_id) {
+                    *response = error_response;
+                    continue;
+                }
+            }
+            if response.is_error() {
+                errors += 1;
+            }
+        }
+        if let Some(state) = self.clients[self.shard]
+            .lock()
+            .unwrap()
+            .clients
+            .get_mut(&self.client)
+        {
+            state.current.responses += responses.len() as u64;
+            state.current.errors += errors;
+        }
+
+        Ok(responses)
+    }
+}
+
+/// An exponentially weighted mean and variance, updated once per window.
+#[derive(Default)]
+struct Ewma {
+    initialized: bool,
+    mean: f64,
+    variance: f64,
+}
+
+impl Ewma {
+    fn update(&mut self, value: f64) {
+        if !self.initialized {
+            self.initialized = true;
+            self.mean = value;
+            return;
+        }
+        let diff = value - self.mean;
+        let increment = BASELINE_ALPHA * diff;
+        self.mean += increment;
+        self.variance = (1.0 - BASELINE_ALPHA) * (self.variance + diff * increment);
+    }
+
+    /// How many standard deviations `value` is above the mean.
+    /// The standard deviation is floored so that a perfectly steady baseline does not flag tiny fluctuations.
+    fn deviation(&self, value: f64, floor: f64) -> f64 {
+        let stddev = self.variance.sqrt().max(self.mean * 0.1).max(floor);
+        (value - self.mean) / stddev
+    }
+}
+
+const QUERY_TYPES: usize = 5;
+
+fn query_type_index(query_type: QueryType) -> usize {
+    match query_type {
+        QueryType::Read => 0,
+        QueryType::Write => 1,
+        QueryType::ReadWrite => 2,
+        QueryType::SchemaChange => 3,
+        QueryType::PubSubMessage => 4,
+    }
+}
+
+struct Window {
+    started: Instant,
+    requests: u64,
+    responses: u64,
+    errors: u64,
+    query_types: [u64; QUERY_TYPES],
+}
+
+impl Window {
+    fn new(started: Instant) -> Self {
+        Window {
+            started,
+            requests: 0,
+            responses: 0,
+            errors: 0,
+            query_types: [0; QUERY_TYPES],
+        }
+    }
+
+    fn record_query_type(&mut self, request: &mut Message) {
+        // Query types are only defined for these protocols
+        match request.message_type() {
+            #[cfg(feature = "redis")]
+            MessageType::Redis => {}
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra => {}
+            _ => return,
+        }
+        self.query_types[query_type_index(request.get_query_type())] += 1;
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.responses == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.responses as f64
+        }
+    }
+
+    fn command_mix(&self) -> Option<[f64; QUERY_TYPES]> {
+        let total: u64 = self.query_types.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let mut mix = [0.0; QUERY_TYPES];
+        for (proportion, count) in mix.iter_mut().zip(self.query_types) {
+            *proportion = count as f64 / total as f64;
+        }
+        Some(mix)
+    }
+}
+
+struct Penalty {
+    until: Instant,
+    limiter: Option<RateLimiter<NotKeyed, InMemoryState, DefaultClock, NoOpMiddleware>>,
+}
+
+#[derive(Default)]
+struct ClientShard {
+    clients: HashMap<String, ClientState>,
+    last_eviction: Option<Instant>,
+}
+
+impl ClientShard {
+    /// Forgets clients that have been idle for [`IDLE_WINDOWS`], at most once per window.
+    /// Clients that are still penalized are kept so that reconnecting does not lift their penalty.
+    fn evict_idle(&mut self, now: Instant, settings: &Settings) {
+        if self
+            .last_eviction
+            .is_some_and(|last| now.duration_since(last) < settings.window)
+        {
+            return;
+        }
+        self.last_eviction = Some(now);
+
+        let idle = settings.window * IDLE_WINDOWS;
+        self.clients.retain(|_, state| {
+            now.duration_since(state.last_seen) < idle
+                || state
+                    .penalty
+                    .as_ref()
+                    .is_some_and(|penalty| penalty.until > now)
+        });
+    }
+}
+
+struct ClientState {
+    /// When the client last sent a request
+    last_seen: Instant,
+    windows_observed: u32,
+    request_rate: Ewma,
+    error_rate: Ewma,
+    command_mix: Option<[f64; QUERY_TYPES]>,
+    current: Window,
+    penalty: Option<Penalty>,
+}
+
+impl ClientState {
+    fn new(now: Instant) -> Self {
+        ClientState {
+            last_seen: now,
+            windows_observed: 0,
+            request_rate: Ewma::default(),
+            error_rate: Ewma::default(),
+            command_mix: None,
+            current: Window::new(now),
+            penalty: None,
+        }
+    }
+
+    /// If the current window has elapsed, compare it against the baseline and start a new window.
+    /// Returns true if the client was newly penalized.
+    fn maybe_close_window(&mut self, now: Instant, settings: &Settings) -> bool {
+        let elapsed = now.duration_since(self.current.started);
+        if elapsed < settings.window {
+            return false;
+        }
+        let window = std::mem::replace(&mut self.current, Window::new(now));
+
+        if let Some(penalty) = &self.penalty {
+            if penalty.until > now {
+                // Traffic during a penalty is not representative of the client so dont learn from it.
+                return false;
+            }
+            tracing::info!("AnomalyDetection: penalty for client has expired");
+            self.penalty = None;
+        }
+
+        let request_rate = window.requests as f64 / elapsed.as_secs_f64();
+        let error_rate = window.error_rate();
+        let command_mix = window.command_mix();
+
+        if self.windows_observed >= settings.warmup_windows
+            && self.is_anomalous(request_rate, error_rate, command_mix, settings)
+        {
+            self.penalty = Some(Penalty {
+                until: now + settings.penalty,
+                limiter: match settings.action {
+                    AnomalyAction::Block => None,
+                    AnomalyAction::Throttle {
+                        max_requests_per_second,
+                    } => Some(RateLimiter::direct(Quota::per_second(
+                        max_requests_per_second,
+                    ))),
+                },
+            });
+            return true;
+        }
+
+        // Only learn from windows that were considered normal so a misbehaving client cannot drag its own baseline up.
+        self.windows_observed = self.windows_observed.saturating_add(1);
+        self.request_rate.update(request_rate);
+        self.error_rate.update(error_rate);
+        if let Some(mix) = command_mix {
+            let baseline = self.command_mix.get_or_insert(mix);
+            for (baseline, value) in baseline.iter_mut().zip(mix) {
+                *baseline += BASELINE_ALPHA * (value - *baseline);
+            }
+        }
+        false
+    }
+
+    fn is_anomalous(
+        &self,
+        request_rate: f64,
+        error_rate: f64,
+        command_mix: Option<[f64; QUERY_TYPES]>,
+        settings: &Settings,
+    ) -> bool {
+        if self.request_rate.deviation(request_rate, 1.0) > settings.deviation_threshold {
+            return true;
+        }
+        if self.error_rate.deviation(error_rate, 0.01) > settings.deviation_threshold {
+            return true;
+        }
+        if let (Some(max_shift), Some(baseline), Some(mix)) = (
+            settings.max_command_mix_shift,
+            self.command_mix,
+            command_mix,
+        ) {
+            let shift: f64 = baseline
+                .iter()
+                .zip(mix)
+                .map(|(baseline, value)| (baseline - value).abs())
+                .sum::<f64>()
+                / 2.0;
+            if shift > max_shift {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn should_reject(&self, now: Instant) -> bool {
+        match &self.penalty {
+            Some(penalty) if penalty.until > now => match &penalty.limiter {
+                None => true,
+                Some(limiter) => limiter.check().is_err(),
+            },
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            window: Duration::from_secs(10),
+            warmup_windows: 3,
+            deviation_threshold: 4.0,
+            max_command_mix_shift: Some(0.5),
+            action: AnomalyAction::Block,
+            penalty: Duration::from_secs(60),
+        }
+    }
+
+    fn run_window(state: &mut ClientState, start: Instant, requests: u64, errors: u64) -> bool {
+        state.current.requests = requests;
+        state.current.responses = requests;
+        state.current.errors = errors;
+        state.current.query_types[query_type_index(QueryType::Read)] = requests;
+        state.maybe_close_window(start + Duration::from_secs(10), &settings())
+    }
+
+    #[test]
+    fn test_steady_client_is_not_penalized() {
+        let start = Instant::now();
+        let mut state = ClientState::new(start);
+        for i in 0..10 {
+            let window_start = start + Duration::from_secs(10 * i);
+            assert!(!run_window(&mut state, window_start, 1000 + i * 10, 1));
+        }
+        assert!(state.penalty.is_none());
+        assert_eq!(state.windows_observed, 10);
+    }
+
+    #[test]
+    fn test_runaway_client_is_blocked() {
+        let start = Instant::now();
+        let mut state = ClientState::new(start);
+        for i in 0..5 {
+            assert!(!run_window(
+                &mut state,
+                start + Duration::from_secs(10 * i),
+                1000,
+                0
+            ));
+        }
+
+        let spike_start = start + Duration::from_secs(50);
+        assert!(run_window(&mut state, spike_start, 50_000, 0));
+        assert!(state.should_reject(spike_start + Duration::from_secs(15)));
+        // the spike must not have been learnt into the baseline
+        assert_eq!(state.windows_observed, 5);
+
+        // the penalty expires
+        assert!(!state.should_reject(spike_start + Duration::from_secs(71)));
+    }
+
+    #[test]
+    fn test_no_penalty_during_warmup() {
+        let start = Instant::now();
+        let mut state = ClientState::new(start);
+        assert!(!run_window(&mut state, start, 10, 0));
+        assert!(!run_window(
+            &mut state,
+            start + Duration::from_secs(10),
+            50_000,
+            0
+        ));
+        assert!(state.penalty.is_none());
+    }
+
+    #[test]
+    fn test_error_spike_is_penalized() {
+        let start = Instant::now();
+        let mut state = ClientState::new(start);
+        for i in 0..5 {
+            assert!(!run_window(
+                &mut state,
+                start + Duration::from_secs(10 * i),
+                1000,
+                0
+            ));
+        }
+        assert!(run_window(
+            &mut state,
+            start + Duration::from_secs(50),
+            1000,
+            900
+        ));
+    }
+
+    #[test]
+    fn test_idle_clients_are_evicted() {
+        let start = Instant::now();
+        let mut shard = ClientShard::default();
+        shard
+            .clients
+            .insert("idle".to_owned(), ClientState::new(start));
+        shard
+            .clients
+            .insert("active".to_owned(), ClientState::new(start));
+        let mut penalized = ClientState::new(start);
+        penalized.penalty = Some(Penalty {
+            until: start + Duration::from_secs(1000),
+            limiter: None,
+        });
+        shard.clients.insert("penalized".to_owned(), penalized);
+
+        let later = start + Duration::from_secs(100);
+        shard.clients.get_mut("active").unwrap().last_seen = later;
+        shard.evict_idle(later, &settings());
+
+        let mut remaining: Vec<_> = shard.clients.keys().cloned().collect();
+        remaining.sort();
+        assert_eq!(remaining, vec!["active".to_owned(), "penalized".to_owned()]);
+    }
+
+    #[test]
+    fn test_command_mix_shift_is_penalized() {
+        let start = Instant::now();
+        let mut state = ClientState::new(start);
+        for i in 0..5 {
+            assert!(!run_window(
+                &mut state,
+                start + Duration::from_secs(10 * i),
+                1000,
+                0
+            ));
+        }
+
+        state.current.requests = 1000;
+        state.current.responses = 1000;
+        state.current.query_types[query_type_index(QueryType::Write)] = 1000;
+        assert!(state.maybe_close_window(start + Duration::from_secs(60), &settings()));
+    }
+}
@@ -0,0 +1,319 @@
+use crate::config::chain::TransformChainConfig;
+use crate::frame::MessageType;
+use crate::message::{Message, MessageIdMap, Messages, QueryType};
+use crate::transforms::chain::{BufferedChain, TransformChainBuilder};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tracing::debug;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BackupReadVerificationConfig {
+    /// The fraction of reads, between 0 and 1, that are also sent to the backup chain
+    pub sample_rate: f64,
+    /// The chain that sends sampled reads to the backup cluster
+    pub chain: TransformChainConfig,
+    /// The number of batches of sampled reads that may be queued for the backup chain, defaults to 5
+    pub buffer_size: Option<usize>,
+    /// How long to wait for room in the queue before giving up on verifying a batch, defaults to waiting indefinitely
+    pub timeout_micros: Option<u64>,
+}
+
+const NAME: &str = "BackupReadVerification";
+#[typetag::serde(name = "BackupReadVerification")]
+#[async_trait(?Send)]
+impl TransformConfig for BackupReadVerificationConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        if !(0.0..=1.0).contains(&self.sample_rate) {
+            bail!("sample_rate must be between 0 and 1");
+        }
+        let backup_chain = self
+            .chain
+            .get_builder(TransformContextConfig {
+                chain_name: "backup_chain".to_string(),
+                protocol: transform_context.protocol,
+            })
+            .await?;
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(BackupReadVerificationBuilder {
+            backup_chain,
+            sample_rate: self.sample_rate,
+            buffer_size: self.buffer_size.unwrap_or(5),
+            timeout_micros: self.timeout_micros,
+            metrics: VerificationMetrics {
+                sampled: counter!(
+                    "shotover_backup_verification_sampled_count",
+                    "chain" => chain_name.clone()
+                ),
+                divergent: counter!(
+                    "shotover_backup_verification_divergent_count",
+                    "chain" => chain_name.clone()
+                ),
+                failed: counter!(
+                    "shotover_backup_verification_failed_count",
+                    "chain" => chain_name
+                ),
+            },
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        // Reads can only be told apart from writes for these protocols
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        vec![("chain", &self.chain)]
+    }
+}
+
+#[derive(Clone)]
+struct VerificationMetrics {
+    /// Reads sent to the backup chain
+    sampled: Counter,
+    /// Sampled reads for which the backup returned a different response than the regular chain
+    divergent: Counter,
+    /// Sampled reads that could not be verified because the backup chain failed or was too far behind
+    failed: Counter,
+}
+
+pub struct BackupReadVerificationBuilder {
+    backup_chain: TransformChainBuilder,
+    sample_rate: f64,
+    buffer_size: usize,
+    timeout_micros: Option<u64>,
+    metrics: VerificationMetrics,
+}
+
+impl TransformBuilder for BackupReadVerificationBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(BackupReadVerification {
+            backup_chain: self
+                .backup_chain
+                .build_buffered(self.buffer_size, transform_context),
+            sample_rate: self.sample_rate,
+            timeout_micros: self.timeout_micros,
+            metrics: self.metrics.clone(),
+            sampled_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self
+            .backup_chain
+            .validate()
+            .iter()
+            .map(|x| format!("  {x}"))
+            .collect::<Vec<String>>();
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{NAME}:"));
+        }
+
+        errors
+    }
+}
+
+/// Sends a sample of reads to a backup cluster as well as down the chain and compares the responses,
+/// giving continuous verification that the backup is in sync with the cluster it is a backup of.
+///
+/// Responses are always returned from down the chain and are never delayed by the backup,
+/// verification happens in the background after the response has been returned to the client.
+pub struct BackupReadVerification {
+    backup_chain: BufferedChain,
+    sample_rate: f64,
+    timeout_micros: Option<u64>,
+    metrics: VerificationMetrics,
+    /// Sampled reads that are waiting for their response from down the chain
+    sampled_requests: MessageIdMap<Message>,
+}
+
+#[async_trait]
+impl Transform for BackupReadVerification {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in requests_wrapper.requests.iter_mut() {
+            if !request.is_dummy()
+                && rand::thread_rng().gen_bool(self.sample_rate)
+                && request.get_query_type() == QueryType::Read
+            {
+                self.sampled_requests.insert(request.id(), request.clone());
+            }
+        }
+
+        let local_addr = requests_wrapper.local_addr;
+        let responses = requests_wrapper.call_next_transform().await?;
+
+        let mut requests = vec![];
+        let mut expected = MessageIdMap::default();
+        for response in &responses {
+            if let Some(request_id) = response.request_id() {
+                if let Some(request) = self.sampled_requests.remove(&request_id) {
+                    requests.push(request);
+                    expected.insert(request_id, response.clone());
+                }
+            }
+        }
+        if !requests.is_empty() {
+            self.metrics.sampled.increment(requests.len() as u64);
+            tokio::spawn(verify(
+                self.backup_chain.clone(),
+                local_addr,
+                requests,
+                expected,
+                self.timeout_micros,
+                self.metrics.clone(),
+            ));
+        }
+
+        Ok(responses)
+    }
+}
+
+/// Sends the requests to the backup chain and compares its responses against the `expected` responses from down the chain.
+async fn verify(
+    mut backup_chain: BufferedChain,
+    local_addr: SocketAddr,
+    requests: Messages,
+    mut expected: MessageIdMap<Message>,
+    timeout_micros: Option<u64>,
+    metrics: VerificationMetrics,
+) {
+    let backup_responses = match backup_chain
+        .process_request(Wrapper::new_with_addr(requests, local_addr), timeout_micros)
+        .await
+    {
+        Ok(responses) => responses,
+        Err(err) => {
+            debug!("Failed to send sampled reads to the backup chain: {err:?}");
+            metrics.failed.increment(expected.len() as u64);
+            return;
+        }
+    };
+
+    metrics
+        .divergent
+        .increment(count_divergent(&mut expected, backup_responses) as u64);
+    // the backup chain did not respond to these reads
+    metrics.failed.increment(expected.len() as u64);
+}
+
+/// Compares each backup response against the expected response to the same request, removing it from `expected`.
+/// Returns the number of backup responses that differ from the expected response.
+fn count_divergent(expected: &mut MessageIdMap<Message>, backup_responses: Messages) -> usize {
+    let mut divergent = 0;
+    for mut backup_response in backup_responses {
+        let Some(request_id) = backup_response.request_id() else {
+            continue;
+        };
+        if let Some(mut response) = expected.remove(&request_id) {
+            if response != backup_response {
+                debug!(
+                    "Backup diverged:\nresponse: {}\nbackup response: {}",
+                    response.to_high_level_string(),
+                    backup_response.to_high_level_string()
+                );
+                divergent += 1;
+            }
+        }
+    }
+    divergent
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::frame::{Frame, RedisFrame};
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use crate::transforms::null::NullSinkConfig;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_validate_subchain_invalid() {
+        let config = BackupReadVerificationConfig {
+            sample_rate: 0.01,
+            chain: TransformChainConfig(vec![Box::new(NullSinkConfig), Box::new(NullSinkConfig)]),
+            buffer_size: None,
+            timeout_micros: None,
+        };
+        let transform = config
+            .get_builder(TransformContextConfig {
+                chain_name: "".into(),
+                protocol: MessageType::Redis,
+            })
+            .await
+            .unwrap();
+        let expected = r#"BackupReadVerification:
+  backup_chain chain:
+    Terminating transform "NullSink" is not last in chain. Terminating transform must be last in chain."#;
+        assert_eq!(transform.validate().join("\n"), expected);
+    }
+
+    #[tokio::test]
+    async fn test_count_divergent() {
+        let mut backup_chain = TransformChainBuilder::new(
+            vec![Box::new(DebugReturner::new(Response::Redis("a".into())))],
+            "backup_chain",
+        )
+        .build_buffered(5, TransformContextBuilder::new_test());
+
+        let requests: Messages = (0..4)
+            .map(|_| {
+                Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString("GET".into()),
+                    RedisFrame::BulkString("key".into()),
+                ])))
+            })
+            .collect();
+        // the last request was not sent to the backup
+        let mut expected: MessageIdMap<Message> = requests
+            .iter()
+            .zip(["a", "b", "a", "a"])
+            .map(|(request, value)| {
+                let mut response =
+                    Message::from_frame(Frame::Redis(RedisFrame::BulkString(value.into())));
+                response.set_request_id(request.id());
+                (request.id(), response)
+            })
+            .collect();
+        let sent = requests[..3].to_vec();
+
+        let backup_responses = backup_chain
+            .process_request(
+                Wrapper::new_with_addr(sent, "127.0.0.1:0".parse().unwrap()),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(count_divergent(&mut expected, backup_responses), 1);
+        assert_eq!(expected.len(), 1);
+    }
+}
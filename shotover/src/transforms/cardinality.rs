@@ -0,0 +1,283 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::frame::Frame;
+use crate::message::Messages;
+use crate::transforms::util::hyperloglog::{HyperLogLog, MAX_PRECISION, MIN_PRECISION};
+use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{gauge, Gauge};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CardinalityMetricsConfig {
+    /// How often the estimates are exported, each estimate covers the keys and clients seen since the previous export
+    pub interval_seconds: u64,
+    /// The number of hash bits used to pick a register of each sketch, defaults to 14.
+    /// Each increment doubles the memory used by the sketches and reduces their error by about 30%.
+    pub precision: Option<u8>,
+}
+
+const NAME: &str = "CardinalityMetrics";
+#[typetag::serde(name = "CardinalityMetrics")]
+#[async_trait(?Send)]
+impl TransformConfig for CardinalityMetricsConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let precision = self.precision.unwrap_or(14);
+        let sketches = Arc::new(Mutex::new(Sketches {
+            keys: HyperLogLog::new(precision),
+            clients: HyperLogLog::new(precision),
+        }));
+        spawn_export(
+            Arc::downgrade(&sketches),
+            Duration::from_secs(self.interval_seconds),
+            gauge!("shotover_unique_keys_count", "chain" => transform_context.chain_name.clone()),
+            gauge!("shotover_unique_clients_count", "chain" => transform_context.chain_name),
+        );
+        Ok(Box::new(CardinalityMetricsBuilder { sketches }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+
+    fn validate_config(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.interval_seconds == 0 {
+            errors.push("interval_seconds must be greater than 0".to_owned());
+        }
+        if let Some(precision) = self.precision {
+            if !(MIN_PRECISION..=MAX_PRECISION).contains(&precision) {
+                errors.push(format!(
+                    "precision must be between {MIN_PRECISION} and {MAX_PRECISION}"
+                ));
+            }
+        }
+        errors
+    }
+}
+
+/// The keys and clients seen by every connection through the chain during the current interval
+struct Sketches {
+    keys: HyperLogLog,
+    clients: HyperLogLog,
+}
+
+/// Exports the estimates every `interval` and starts a new interval, until the builder is dropped
+fn spawn_export(
+    sketches: Weak<Mutex<Sketches>>,
+    interval: Duration,
+    unique_keys: Gauge,
+    unique_clients: Gauge,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        // the first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let Some(sketches) = sketches.upgrade() else {
+                return;
+            };
+            // Copy out the sketches so that connections are not blocked while the estimates are calculated
+            let (keys, clients) = {
+                let mut sketches = sketches.lock().unwrap();
+                let taken = (sketches.keys.clone(), sketches.clients.clone());
+                sketches.keys.clear();
+                sketches.clients.clear();
+                taken
+            };
+            unique_keys.set(keys.estimate() as f64);
+            unique_clients.set(clients.estimate() as f64);
+        }
+    });
+}
+
+pub struct CardinalityMetricsBuilder {
+    sketches: Arc<Mutex<Sketches>>,
+}
+
+impl TransformBuilder for CardinalityMetricsBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CardinalityMetrics {
+            sketches: self.sketches.clone(),
+            client: transform_context.client_details,
+            keys: vec![],
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// Estimates the number of distinct keys accessed and distinct clients connected through the chain during each interval.
+pub struct CardinalityMetrics {
+    sketches: Arc<Mutex<Sketches>>,
+    client: String,
+    /// Reused between batches to avoid allocating
+    keys: Vec<Vec<u8>>,
+}
+
+#[async_trait]
+impl Transform for CardinalityMetrics {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in &mut requests_wrapper.requests {
+            match request.frame() {
+                #[cfg(feature = "redis")]
+                Some(Frame::Redis(crate::frame::RedisFrame::Array(args))) => {
+                    self.keys
+                        .extend(redis_keys(args).into_iter().map(|key| key.to_vec()));
+                }
+                #[cfg(feature = "cassandra")]
+                Some(Frame::Cassandra(frame)) => {
+                    for statement in frame.operation.queries() {
+                        if let Some(key) = cassandra_key(statement) {
+                            self.keys.push(key);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !requests_wrapper.requests.is_empty() {
+            let mut sketches = self.sketches.lock().unwrap();
+            sketches.clients.insert(self.client.as_str());
+            for key in self.keys.drain(..) {
+                sketches.keys.insert(key.as_slice());
+            }
+        }
+
+        requests_wrapper.call_next_transform().await
+    }
+}
+
+/// The keys accessed by a redis command
+#[cfg(feature = "redis")]
+pub(crate) fn redis_keys(args: &[crate::frame::RedisFrame]) -> Vec<&[u8]> {
+    use crate::frame::RedisFrame;
+
+    let Some(RedisFrame::BulkString(command)) = args.first() else {
+        return vec![];
+    };
+    let keys: &[RedisFrame] = match command.to_ascii_uppercase().as_slice() {
+        // Commands that do not access any keys
+        b"ACL" | b"AUTH" | b"BGREWRITEAOF" | b"BGSAVE" | b"CLIENT" | b"CLUSTER" | b"COMMAND"
+        | b"CONFIG" | b"DBSIZE" | b"DEBUG" | b"DISCARD" | b"ECHO" | b"EXEC" | b"FAILOVER"
+        | b"FLUSHALL" | b"FLUSHDB" | b"FUNCTION" | b"HELLO" | b"INFO" | b"KEYS" | b"LASTSAVE"
+        | b"LATENCY" | b"MEMORY" | b"MONITOR" | b"MULTI" | b"OBJECT" | b"PING" | b"PSUBSCRIBE"
+        | b"PUBLISH" | b"PUNSUBSCRIBE" | b"QUIT" | b"READONLY" | b"READWRITE" | b"REPLICAOF"
+        | b"RESET" | b"ROLE" | b"SAVE" | b"SCAN" | b"SCRIPT" | b"SELECT" | b"SHUTDOWN"
+        | b"SLAVEOF" | b"SLOWLOG" | b"SUBSCRIBE" | b"SWAPDB" | b"TIME" | b"UNSUBSCRIBE"
+        | b"WAIT" | b"WAITAOF" => &[],
+        // Commands where every argument is a key
+        b"DEL" | b"EXISTS" | b"MGET" | b"PFCOUNT" | b"SDIFF" | b"SINTER" | b"SUNION" | b"TOUCH"
+        | b"UNLINK" | b"WATCH" => &args[1..],
+        b"MSET" | b"MSETNX" => {
+            return args[1..]
+                .iter()
+                .step_by(2)
+                .filter_map(bulk_string)
+                .collect();
+        }
+        b"EVAL" | b"EVALSHA" => {
+            let key_count = args
+                .get(2)
+                .and_then(bulk_string)
+                .and_then(|count| std::str::from_utf8(count).ok())
+                .and_then(|count| count.parse::<usize>().ok())
+                .unwrap_or(0);
+            let keys = args.get(3..).unwrap_or(&[]);
+            &keys[..key_count.min(keys.len())]
+        }
+        // Every other command takes a single key as its first argument
+        _ => args.get(1..2).unwrap_or(&[]),
+    };
+    keys.iter().filter_map(bulk_string).collect()
+}
+
+#[cfg(feature = "redis")]
+fn bulk_string(frame: &crate::frame::RedisFrame) -> Option<&[u8]> {
+    match frame {
+        crate::frame::RedisFrame::BulkString(bytes) => Some(bytes),
+        _ => None,
+    }
+}
+
+/// The table and where clause of a statement that accesses specific rows, identifying the rows it accesses.
+/// Statements that need the schema to tell which columns make up the key, such as inserts, are not tracked.
+#[cfg(feature = "cassandra")]
+pub(crate) fn cassandra_key(
+    statement: &cql3_parser::cassandra_statement::CassandraStatement,
+) -> Option<Vec<u8>> {
+    use cql3_parser::cassandra_statement::CassandraStatement;
+    use std::fmt::Write;
+
+    let (table_name, where_clause) = match statement {
+        CassandraStatement::Select(select) if !select.filtering => {
+            (&select.table_name, &select.where_clause)
+        }
+        CassandraStatement::Update(update) => (&update.table_name, &update.where_clause),
+        CassandraStatement::Delete(delete) => (&delete.table_name, &delete.where_clause),
+        _ => return None,
+    };
+    if where_clause.is_empty() {
+        return None;
+    }
+
+    let mut key = table_name.to_string();
+    for relation in where_clause {
+        write!(key, " {} {}", relation.obj, relation.value).unwrap();
+    }
+    Some(key.into_bytes())
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::transforms::testing::redis_args;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_redis_keys() {
+        assert_eq!(
+            redis_keys(&redis_args(&["GET", "foo"])),
+            vec![b"foo".as_slice()]
+        );
+        assert_eq!(
+            redis_keys(&redis_args(&["SET", "foo", "bar", "EX", "10"])),
+            vec![b"foo".as_slice()]
+        );
+        assert_eq!(
+            redis_keys(&redis_args(&["del", "foo", "bar"])),
+            vec![b"foo".as_slice(), b"bar".as_slice()]
+        );
+        assert_eq!(
+            redis_keys(&redis_args(&["MSET", "foo", "1", "bar", "2"])),
+            vec![b"foo".as_slice(), b"bar".as_slice()]
+        );
+        assert_eq!(
+            redis_keys(&redis_args(&["EVAL", "script", "1", "foo", "arg"])),
+            vec![b"foo".as_slice()]
+        );
+        assert_eq!(redis_keys(&redis_args(&["PING"])), Vec::<&[u8]>::new());
+        assert_eq!(
+            redis_keys(&redis_args(&["SELECT", "1"])),
+            Vec::<&[u8]>::new()
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::codec::CodecState;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, Messages, Metadata};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cassandra_protocol::compression::Compression;
+use cassandra_protocol::frame::{Opcode, Version};
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraClientCompressionConfig {
+    /// Responses smaller than this are sent to the client uncompressed, defaults to 512
+    pub min_response_size_bytes: Option<usize>,
+}
+
+const NAME: &str = "CassandraClientCompression";
+#[typetag::serde(name = "CassandraClientCompression")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraClientCompressionConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(CassandraClientCompressionBuilder {
+            min_response_size_bytes: self.min_response_size_bytes.unwrap_or(512),
+            compressed_responses: counter!("shotover_client_compressed_responses_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+pub struct CassandraClientCompressionBuilder {
+    min_response_size_bytes: usize,
+    compressed_responses: Counter,
+}
+
+impl TransformBuilder for CassandraClientCompressionBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraClientCompression {
+            min_response_size_bytes: self.min_response_size_bytes,
+            compressed_responses: self.compressed_responses.clone(),
+            client_compression: Compression::None,
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// Negotiates compression with the client independently of the connection to the cluster.
+///
+/// The COMPRESSION option is removed from the STARTUP message before it is sent down the chain so that the cluster is always spoken to uncompressed,
+/// while the source keeps using the compression requested by the client.
+/// For protocol v5 the source codec compresses every segment sent to the client.
+/// For earlier versions compression is applied per frame, so this transform only compresses the RESULT frames large enough to benefit from it.
+pub struct CassandraClientCompression {
+    min_response_size_bytes: usize,
+    compressed_responses: Counter,
+    client_compression: Compression,
+}
+
+impl CassandraClientCompression {
+    fn strip_startup_compression(&mut self, request: &mut Message) {
+        if let Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Startup(startup),
+            ..
+        })) = request.frame()
+        {
+            if let Some(compression) = startup.map.remove("COMPRESSION") {
+                self.client_compression = match compression.as_str() {
+                    "snappy" | "SNAPPY" => Compression::Snappy,
+                    "lz4" | "LZ4" => Compression::Lz4,
+                    _ => Compression::None,
+                };
+                request.invalidate_cache();
+            }
+        }
+    }
+
+    fn should_compress(&self, response: &Message) -> bool {
+        if self.client_compression == Compression::None {
+            return false;
+        }
+        match response.metadata() {
+            Ok(Metadata::Cassandra(meta)) => {
+                meta.version != Version::V5
+                    && meta.opcode == Opcode::Result
+                    && response.codec_state.as_cassandra() == Compression::None
+                    && response
+                        .raw_bytes()
+                        .map(|bytes| bytes.len() >= self.min_response_size_bytes)
+                        .unwrap_or(true)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraClientCompression {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in requests_wrapper.requests.iter_mut() {
+            let Ok(Metadata::Cassandra(meta)) = request.metadata() else {
+                continue;
+            };
+            if meta.opcode == Opcode::Startup {
+                self.strip_startup_compression(request);
+            } else if request.codec_state.as_cassandra() != Compression::None {
+                // The cluster never agreed to compression, so decompress the frame before sending it down the chain.
+                if request.frame().is_some() {
+                    request.codec_state = CodecState::Cassandra {
+                        compression: Compression::None,
+                    };
+                    request.invalidate_cache();
+                }
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if self.should_compress(response) && response.frame().is_some() {
+                response.codec_state = CodecState::Cassandra {
+                    compression: self.client_compression,
+                };
+                response.invalidate_cache();
+                self.compressed_responses.increment(1);
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::cassandra::Tracing;
+    use cassandra_protocol::frame::message_startup::BodyReqStartup;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_strip_startup_compression() {
+        let mut transform = CassandraClientCompression {
+            min_response_size_bytes: 512,
+            compressed_responses: Counter::noop(),
+            client_compression: Compression::None,
+        };
+        let mut request = Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 0,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+            operation: CassandraOperation::Startup(BodyReqStartup {
+                map: HashMap::from([
+                    ("CQL_VERSION".to_owned(), "3.0.0".to_owned()),
+                    ("COMPRESSION".to_owned(), "lz4".to_owned()),
+                ]),
+            }),
+        }));
+
+        transform.strip_startup_compression(&mut request);
+
+        assert_eq!(transform.client_compression, Compression::Lz4);
+        assert_eq!(
+            request.frame(),
+            Some(&mut Frame::Cassandra(CassandraFrame {
+                version: Version::V4,
+                stream_id: 0,
+                tracing: Tracing::Request(false),
+                warnings: vec![],
+                operation: CassandraOperation::Startup(BodyReqStartup {
+                    map: HashMap::from([("CQL_VERSION".to_owned(), "3.0.0".to_owned())]),
+                }),
+            }))
+        );
+    }
+}
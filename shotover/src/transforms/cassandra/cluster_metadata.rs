@@ -0,0 +1,130 @@
+//! A cached model of each cassandra cluster that shotover routes to via a `CassandraSinkCluster`,
+//! covering the token ring, node status and schema as read from the system tables of the cluster.
+//!
+//! The model is maintained by the topology task of the `CassandraSinkCluster` in a chain,
+//! which keeps it up to date from the events sent by the cluster and by periodically querying the system tables in case an event was missed.
+//! Other transforms in the chain and the observability interface read the model from here rather than querying the system tables themselves.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ClusterMetadata {
+    /// The nodes of the cluster in the data center shotover is configured for
+    pub nodes: Vec<NodeMetadata>,
+    pub keyspaces: BTreeMap<String, KeyspaceSchema>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NodeMetadata {
+    pub address: SocketAddr,
+    pub rack: String,
+    pub host_id: Uuid,
+    pub is_up: bool,
+    /// The tokens owned by the node in the token ring
+    pub tokens: Vec<i64>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct KeyspaceSchema {
+    /// The replication factor in the data center shotover is configured for
+    pub replication_factor: usize,
+    pub replication_strategy: String,
+    pub tables: BTreeMap<String, TableSchema>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TableSchema {
+    /// The columns of the table, in the order returned by system_schema.columns
+    pub columns: Vec<ColumnSchema>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+    pub name: String,
+    /// One of `partition_key`, `clustering` , `regular` or `static`
+    pub kind: String,
+    /// The CQL type of the column
+    #[serde(rename = "type")]
+    pub ty: String,
+}
+
+impl ClusterMetadata {
+    /// Returns the schema of a table given its keyspace and name
+    pub fn table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
+        self.keyspaces.get(keyspace)?.tables.get(table)
+    }
+}
+
+type MetadataTx = Arc<watch::Sender<Arc<ClusterMetadata>>>;
+
+/// The model of the cluster routed to by each chain, keyed by the name of the chain
+static CLUSTERS: Mutex<Vec<(String, MetadataTx)>> = Mutex::new(Vec::new());
+
+fn sender(chain_name: &str) -> MetadataTx {
+    let mut clusters = CLUSTERS.lock().unwrap();
+    if let Some((_, tx)) = clusters.iter().find(|(name, _)| name == chain_name) {
+        return tx.clone();
+    }
+    let tx = Arc::new(watch::channel(Arc::new(ClusterMetadata::default())).0);
+    clusters.push((chain_name.to_owned(), tx.clone()));
+    tx
+}
+
+/// Returns a receiver of the model of the cluster routed to by the `CassandraSinkCluster` in the chain.
+/// The model is empty until the sink has connected to the cluster, or forever if the chain has no such sink.
+pub fn subscribe(chain_name: &str) -> watch::Receiver<Arc<ClusterMetadata>> {
+    sender(chain_name).subscribe()
+}
+
+/// Replaces the model of the cluster routed to by the chain, notifying every receiver if it changed
+pub(crate) fn publish(chain_name: &str, metadata: ClusterMetadata) {
+    sender(chain_name).send_if_modified(|current| {
+        if **current == metadata {
+            false
+        } else {
+            *current = Arc::new(metadata);
+            true
+        }
+    });
+}
+
+/// Returns the current model of every cluster, keyed by the name of the chain routing to it
+pub fn snapshot() -> BTreeMap<String, Arc<ClusterMetadata>> {
+    CLUSTERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, tx)| (name.clone(), tx.borrow().clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_publish_notifies_subscribers() {
+        let mut rx = subscribe("test_publish_notifies_subscribers");
+        assert_eq!(**rx.borrow_and_update(), ClusterMetadata::default());
+
+        let mut metadata = ClusterMetadata::default();
+        metadata
+            .keyspaces
+            .insert("ks".to_owned(), KeyspaceSchema::default());
+        publish("test_publish_notifies_subscribers", metadata.clone());
+        assert!(rx.has_changed().unwrap());
+        assert_eq!(**rx.borrow_and_update(), metadata);
+
+        // publishing an identical model does not wake up subscribers
+        publish("test_publish_notifies_subscribers", metadata);
+        assert!(!rx.has_changed().unwrap());
+
+        assert!(snapshot().contains_key("test_publish_notifies_subscribers"));
+    }
+}
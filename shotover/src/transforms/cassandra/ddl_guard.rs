@@ -0,0 +1,485 @@
+use crate::config::chain::TransformChainConfig;
+use crate::frame::cassandra::{parse_statement_single, CassandraMetadata, Tracing};
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use cassandra_protocol::compression::Compression;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::frame::Flags;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraDdlGuardConfig {
+    /// What to do with requests that alter the schema
+    pub action: DdlActionConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum DdlActionConfig {
+    /// Reject every schema altering request
+    Block,
+    /// Reject schema altering requests unless their custom payload contains `token` under `key`
+    RequireToken { key: String, token: String },
+    /// Send schema altering requests down this chain instead of down-chain
+    Route(TransformChainConfig),
+}
+
+const NAME: &str = "CassandraDdlGuard";
+#[typetag::serde(name = "CassandraDdlGuard")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraDdlGuardConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let action = match &self.action {
+            DdlActionConfig::Block => DdlActionBuilder::Block,
+            DdlActionConfig::RequireToken { key, token } => DdlActionBuilder::RequireToken {
+                key: key.clone(),
+                token: Bytes::from(token.clone()),
+            },
+            DdlActionConfig::Route(chain) => DdlActionBuilder::Route(
+                chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: "ddl_chain".to_string(),
+                        protocol: transform_context.protocol,
+                    })
+                    .await?,
+            ),
+        };
+        Ok(Box::new(CassandraDdlGuardBuilder {
+            action,
+            rejected: counter!("shotover_ddl_rejected_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        match &self.action {
+            DdlActionConfig::Route(chain) => vec![("action.Route", chain)],
+            _ => vec![],
+        }
+    }
+}
+
+enum DdlActionBuilder {
+    Block,
+    RequireToken { key: String, token: Bytes },
+    Route(TransformChainBuilder),
+}
+
+pub struct CassandraDdlGuardBuilder {
+    action: DdlActionBuilder,
+    rejected: Counter,
+}
+
+impl TransformBuilder for CassandraDdlGuardBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraDdlGuard {
+            action: match &self.action {
+                DdlActionBuilder::Block => DdlAction::Block,
+                DdlActionBuilder::RequireToken { key, token } => DdlAction::RequireToken {
+                    key: key.clone(),
+                    token: token.clone(),
+                },
+                DdlActionBuilder::Route(chain) => DdlAction::Route {
+                    chain: chain.build(transform_context),
+                    in_flight: 0,
+                },
+            },
+            rejected: self.rejected.clone(),
+            rejected_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        match &self.action {
+            // schema changes run on ddl_chain concurrently with the requests sent down chain
+            DdlActionBuilder::Route(chain) => {
+                DeliveryGuarantees::UNORDERED.then(chain.delivery_guarantees())
+            }
+            DdlActionBuilder::Block | DdlActionBuilder::RequireToken { .. } => {
+                DeliveryGuarantees::AT_MOST_ONCE
+            }
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let errors = match &self.action {
+            DdlActionBuilder::Block => vec![],
+            DdlActionBuilder::RequireToken { key, token } => {
+                let mut errors = vec![];
+                if key.is_empty() {
+                    errors.push("  key must not be empty".to_owned());
+                }
+                if token.is_empty() {
+                    errors.push("  token must not be empty".to_owned());
+                }
+                errors
+            }
+            DdlActionBuilder::Route(chain) => {
+                chain.validate().iter().map(|x| format!("  {x}")).collect()
+            }
+        };
+
+        if errors.is_empty() {
+            errors
+        } else {
+            let mut output = vec![format!("{NAME}:")];
+            output.extend(errors);
+            output
+        }
+    }
+}
+
+enum DdlAction {
+    Block,
+    RequireToken {
+        key: String,
+        token: Bytes,
+    },
+    Route {
+        chain: TransformChain,
+        /// The number of requests sent down `chain` that have not yet received a response
+        in_flight: usize,
+    },
+}
+
+/// Intercepts requests that alter the schema (CREATE, ALTER, DROP and TRUNCATE statements)
+/// to protect a cluster from accidental schema changes by clients sharing credentials with the tooling allowed to make them.
+///
+/// Preparing a schema altering statement is treated the same as executing it,
+/// since executing the prepared statement later does not include the statement text.
+pub struct CassandraDdlGuard {
+    action: DdlAction,
+    rejected: Counter,
+    rejected_requests: MessageIdMap<Message>,
+}
+
+#[async_trait]
+impl Transform for CassandraDdlGuard {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let mut routed = vec![];
+        for mut request in std::mem::take(&mut requests_wrapper.requests) {
+            if !is_ddl_request(&mut request) {
+                requests_wrapper.requests.push(request);
+                continue;
+            }
+
+            let rejection = match &self.action {
+                DdlAction::Block => Some("Schema altering statements are blocked by shotover"),
+                DdlAction::RequireToken { key, token } => {
+                    let approved = request.raw_bytes().is_some_and(|bytes| {
+                        custom_payload_value(bytes, request.codec_state.as_cassandra(), key)
+                            .is_some_and(|value| &value == token)
+                    });
+                    (!approved).then_some(
+                        "Schema altering statements require an approval token in the custom payload",
+                    )
+                }
+                DdlAction::Route { .. } => {
+                    routed.push(request);
+                    continue;
+                }
+            };
+
+            if let Some(message) = rejection {
+                if let Ok(Metadata::Cassandra(metadata)) = request.metadata() {
+                    let mut response = Message::from_frame(Frame::Cassandra(reject(
+                        &metadata,
+                        message.to_owned(),
+                    )));
+                    response.set_request_id(request.id());
+                    self.rejected_requests.insert(request.id(), response);
+                    request.replace_with_dummy();
+                    self.rejected.increment(1);
+                }
+            }
+            requests_wrapper.requests.push(request);
+        }
+
+        let mut routed_responses = vec![];
+        if let DdlAction::Route { chain, in_flight } = &mut self.action {
+            // Responses from the routed chain may arrive after the batch that sent the request,
+            // so keep polling it until every routed request has a response.
+            if !routed.is_empty() || *in_flight > 0 {
+                *in_flight += routed.len();
+                let mut wrapper = Wrapper::new_with_addr(routed, requests_wrapper.local_addr);
+                wrapper.flush = requests_wrapper.flush;
+                routed_responses = chain.process_request(wrapper).await?;
+                let responded = routed_responses
+                    .iter()
+                    .filter(|response| response.request_id().is_some())
+                    .count();
+                *in_flight = in_flight.saturating_sub(responded);
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if let Some(rejection) = response
+                .request_id()
+                .and_then(|id| self.rejected_requests.remove(&id))
+            {
+                *response = rejection;
+            }
+        }
+        responses.extend(routed_responses);
+
+        Ok(responses)
+    }
+}
+
+fn is_ddl_request(request: &mut Message) -> bool {
+    match request.frame() {
+        Some(Frame::Cassandra(frame)) => match &mut frame.operation {
+            CassandraOperation::Prepare(body) => prepared_query(body)
+                .map(|query| is_ddl(&parse_statement_single(query)))
+                .unwrap_or(false),
+            operation => operation.queries().any(|statement| is_ddl(statement)),
+        },
+        _ => false,
+    }
+}
+
+/// Returns the query of a PREPARE body, which starts with the query as a [long string]
+pub(crate) fn prepared_query(body: &[u8]) -> Option<&str> {
+    let len = i32::from_be_bytes(body.get(..4)?.try_into().ok()?);
+    let query = body.get(4..4 + usize::try_from(len).ok()?)?;
+    std::str::from_utf8(query).ok()
+}
+
+fn is_ddl(statement: &CassandraStatement) -> bool {
+    match statement {
+        CassandraStatement::AlterKeyspace(_)
+        | CassandraStatement::AlterMaterializedView(_)
+        | CassandraStatement::AlterRole(_)
+        | CassandraStatement::AlterTable(_)
+        | CassandraStatement::AlterType(_)
+        | CassandraStatement::AlterUser(_)
+        | CassandraStatement::CreateAggregate(_)
+        | CassandraStatement::CreateFunction(_)
+        | CassandraStatement::CreateIndex(_)
+        | CassandraStatement::CreateKeyspace(_)
+        | CassandraStatement::CreateMaterializedView(_)
+        | CassandraStatement::CreateRole(_)
+        | CassandraStatement::CreateTable(_)
+        | CassandraStatement::CreateTrigger(_)
+        | CassandraStatement::CreateType(_)
+        | CassandraStatement::CreateUser(_)
+        | CassandraStatement::DropAggregate(_)
+        | CassandraStatement::DropFunction(_)
+        | CassandraStatement::DropIndex(_)
+        | CassandraStatement::DropKeyspace(_)
+        | CassandraStatement::DropMaterializedView(_)
+        | CassandraStatement::DropRole(_)
+        | CassandraStatement::DropTable(_)
+        | CassandraStatement::DropTrigger(_)
+        | CassandraStatement::DropType(_)
+        | CassandraStatement::DropUser(_)
+        | CassandraStatement::Truncate(_) => true,
+        // The parser does not support every statement, so fall back to the leading keyword rather than let unparsed DDL through
+        CassandraStatement::Unknown(cql) => cql
+            .split_whitespace()
+            .next()
+            .map(|keyword| {
+                ["CREATE", "ALTER", "DROP", "TRUNCATE"]
+                    .iter()
+                    .any(|ddl| keyword.eq_ignore_ascii_case(ddl))
+            })
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+const ENVELOPE_HEADER_LENGTH: usize = 9;
+
+/// Returns the value stored under `key` in the custom payload of a request envelope.
+///
+/// Shotover does not keep the custom payload when parsing a frame, so it is read from the envelope's bytes.
+/// The custom payload is a [bytes map] at the start of a request's body.
+fn custom_payload_value(envelope: &[u8], compression: Compression, key: &str) -> Option<Bytes> {
+    let flags = Flags::from_bits_truncate(*envelope.get(1)?);
+    if !flags.contains(Flags::CUSTOM_PAYLOAD) {
+        return None;
+    }
+    let body = envelope.get(ENVELOPE_HEADER_LENGTH..)?;
+    let decompressed;
+    let mut body = if flags.contains(Flags::COMPRESSION) {
+        decompressed = match compression {
+            // An lz4 compressed body is prefixed with its decompressed length
+            Compression::Lz4 => {
+                let len = u32::from_be_bytes(body.get(..4)?.try_into().ok()?);
+                lz4_flex::decompress(&body[4..], len as usize).ok()?
+            }
+            Compression::Snappy | Compression::None => return None,
+        };
+        decompressed.as_slice()
+    } else {
+        body
+    };
+
+    let count = read_u16(&mut body)?;
+    for _ in 0..count {
+        let len = read_u16(&mut body)? as usize;
+        let entry_key = take(&mut body, len)?;
+        let len = i32::from_be_bytes(take(&mut body, 4)?.try_into().ok()?);
+        let value = take(&mut body, usize::try_from(len).unwrap_or(0))?;
+        if entry_key == key.as_bytes() {
+            return Some(Bytes::copy_from_slice(value));
+        }
+    }
+    None
+}
+
+fn read_u16(body: &mut &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes(take(body, 2)?.try_into().ok()?))
+}
+
+fn take<'a>(body: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if body.len() < len {
+        return None;
+    }
+    let (taken, rest) = body.split_at(len);
+    *body = rest;
+    Some(taken)
+}
+
+fn reject(metadata: &CassandraMetadata, message: String) -> CassandraFrame {
+    CassandraFrame {
+        version: metadata.version,
+        stream_id: metadata.stream_id,
+        operation: CassandraOperation::Error(ErrorBody {
+            message,
+            ty: ErrorType::Unauthorized,
+        }),
+        tracing: Tracing::Response(None),
+        warnings: vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_is_ddl() {
+        for cql in [
+            "CREATE TABLE ks.t (id int PRIMARY KEY)",
+            "ALTER TABLE ks.t ADD foo int",
+            "DROP KEYSPACE ks",
+            "TRUNCATE ks.t",
+            "drop materialized view ks.v",
+        ] {
+            assert!(is_ddl(&parse_statement_single(cql)), "{cql}");
+        }
+        for cql in [
+            "SELECT * FROM ks.t",
+            "INSERT INTO ks.t (id) VALUES (1)",
+            "USE ks",
+        ] {
+            assert!(!is_ddl(&parse_statement_single(cql)), "{cql}");
+        }
+    }
+
+    fn envelope(flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut envelope = vec![0x04, flags, 0, 1, 0x07];
+        envelope.extend((body.len() as u32).to_be_bytes());
+        envelope.extend(body);
+        envelope
+    }
+
+    fn custom_payload(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = (entries.len() as u16).to_be_bytes().to_vec();
+        for (key, value) in entries {
+            body.extend((key.len() as u16).to_be_bytes());
+            body.extend(key.as_bytes());
+            body.extend((value.len() as i32).to_be_bytes());
+            body.extend(*value);
+        }
+        body
+    }
+
+    #[test]
+    fn test_custom_payload_value() {
+        let mut body = custom_payload(&[("other", b"x"), ("approval", b"secret")]);
+        // the query body follows the custom payload
+        body.extend([0, 0, 0, 4]);
+        body.extend(b"TRUNCATE t");
+
+        let with_payload = envelope(Flags::CUSTOM_PAYLOAD.bits(), &body);
+        assert_eq!(
+            custom_payload_value(&with_payload, Compression::None, "approval"),
+            Some(Bytes::from_static(b"secret"))
+        );
+        assert_eq!(
+            custom_payload_value(&with_payload, Compression::None, "missing"),
+            None
+        );
+
+        let without_payload = envelope(0, &body);
+        assert_eq!(
+            custom_payload_value(&without_payload, Compression::None, "approval"),
+            None
+        );
+
+        let mut compressed = (body.len() as u32).to_be_bytes().to_vec();
+        compressed.extend(lz4_flex::compress(&body));
+        let compressed = envelope(
+            (Flags::CUSTOM_PAYLOAD | Flags::COMPRESSION).bits(),
+            &compressed,
+        );
+        assert_eq!(
+            custom_payload_value(&compressed, Compression::Lz4, "approval"),
+            Some(Bytes::from_static(b"secret"))
+        );
+
+        // truncated envelopes are treated as having no custom payload
+        assert_eq!(
+            custom_payload_value(
+                &with_payload[..ENVELOPE_HEADER_LENGTH + 12],
+                Compression::None,
+                "approval"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prepared_query() {
+        let mut body = 10_i32.to_be_bytes().to_vec();
+        body.extend(b"DROP TABLE");
+        body.extend([0, 1]);
+        assert_eq!(prepared_query(&body), Some("DROP TABLE"));
+        assert_eq!(prepared_query(&body[..6]), None);
+    }
+}
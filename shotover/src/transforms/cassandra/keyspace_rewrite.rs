@@ -0,0 +1,344 @@
+use crate::frame::cassandra::{parse_statement_single, table_name_mut};
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame, MessageType};
+use crate::message::{MessageIdMap, Messages};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{FQName, Identifier};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraKeyspaceRewriteConfig {
+    /// Maps keyspace names used by the client to keyspace names used in the cluster
+    #[serde(default)]
+    pub keyspaces: HashMap<String, String>,
+    /// Maps fully qualified table names used by the client to fully qualified table names used in the cluster.
+    /// Takes precedence over `keyspaces`.
+    #[serde(default)]
+    pub tables: HashMap<String, String>,
+}
+
+const NAME: &str = "CassandraKeyspaceRewrite";
+#[typetag::serde(name = "CassandraKeyspaceRewrite")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraKeyspaceRewriteConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(CassandraKeyspaceRewriteBuilder {
+            mapping: Arc::new(Mapping {
+                keyspaces: self
+                    .keyspaces
+                    .iter()
+                    .map(|(k, v)| (Identifier::parse(k), Identifier::parse(v)))
+                    .collect(),
+                tables: self
+                    .tables
+                    .iter()
+                    .map(|(k, v)| (FQName::parse(k), FQName::parse(v)))
+                    .collect(),
+            }),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct Mapping {
+    keyspaces: HashMap<Identifier, Identifier>,
+    tables: HashMap<FQName, FQName>,
+}
+
+impl Mapping {
+    /// Returns the name used in the cluster for a name used by the client.
+    /// Unqualified names refer to `current_keyspace`, the keyspace the client last switched to with USE.
+    fn map(&self, name: &FQName, current_keyspace: Option<&Identifier>) -> Option<FQName> {
+        match &name.keyspace {
+            Some(keyspace) => self.tables.get(name).cloned().or_else(|| {
+                self.keyspaces.get(keyspace).map(|keyspace| FQName {
+                    keyspace: Some(keyspace.clone()),
+                    name: name.name.clone(),
+                })
+            }),
+            // The USE statement was already rewritten so the cluster resolves unqualified names against the mapped keyspace,
+            // only table mappings need to be applied.
+            None => self
+                .tables
+                .get(&FQName {
+                    keyspace: Some(current_keyspace?.clone()),
+                    name: name.name.clone(),
+                })
+                .cloned(),
+        }
+    }
+}
+
+pub struct CassandraKeyspaceRewriteBuilder {
+    mapping: Arc<Mapping>,
+}
+
+impl TransformBuilder for CassandraKeyspaceRewriteBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraKeyspaceRewrite {
+            mapping: self.mapping.clone(),
+            current_keyspace: None,
+            use_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for (from, to) in &self.mapping.tables {
+            if from.keyspace.is_none() || to.keyspace.is_none() {
+                errors.push(format!(
+                    "  table mapping {from} -> {to} must use fully qualified table names"
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            errors
+        } else {
+            let mut output = vec![format!("{NAME}:")];
+            output.extend(errors);
+            output
+        }
+    }
+}
+
+/// Rewrites keyspace and table names in requests so that clients can use different names than the cluster.
+///
+/// Responses to USE are rewritten back to the keyspace the client asked for since drivers check it,
+/// but metadata in other responses, such as rows metadata or the system_schema tables, reports the names used in the cluster.
+pub struct CassandraKeyspaceRewrite {
+    mapping: Arc<Mapping>,
+    /// The keyspace, as named by the client, that unqualified names refer to
+    current_keyspace: Option<Identifier>,
+    /// The keyspace named by the client in each in flight USE request
+    use_requests: MessageIdMap<Identifier>,
+}
+
+#[async_trait]
+impl Transform for CassandraKeyspaceRewrite {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in requests_wrapper.requests.iter_mut() {
+            let request_id = request.id();
+            let Some(Frame::Cassandra(frame)) = request.frame() else {
+                continue;
+            };
+            let modified = match &mut frame.operation {
+                CassandraOperation::Prepare(body) => {
+                    match rewrite_prepare(&self.mapping, self.current_keyspace.as_ref(), body) {
+                        Some(new_body) => {
+                            *body = new_body;
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                operation => {
+                    let mut modified = false;
+                    for statement in operation.queries() {
+                        if let CassandraStatement::Use(keyspace) = statement {
+                            self.current_keyspace = Some(keyspace.clone());
+                            self.use_requests.insert(request_id, keyspace.clone());
+                        }
+                        modified |= rewrite_statement(
+                            &self.mapping,
+                            self.current_keyspace.as_ref(),
+                            statement,
+                        );
+                    }
+                    modified
+                }
+            };
+            if modified {
+                request.invalidate_cache();
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            let Some(keyspace) = response
+                .request_id()
+                .and_then(|id| self.use_requests.remove(&id))
+            else {
+                continue;
+            };
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::SetKeyspace(set_keyspace)),
+                ..
+            })) = response.frame()
+            {
+                set_keyspace.body = identifier_name(&keyspace).to_owned();
+                response.invalidate_cache();
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+/// Returns true if the statement was modified
+fn rewrite_statement(
+    mapping: &Mapping,
+    current_keyspace: Option<&Identifier>,
+    statement: &mut CassandraStatement,
+) -> bool {
+    if let CassandraStatement::Use(keyspace) = statement {
+        return match mapping.keyspaces.get(keyspace) {
+            Some(mapped) => {
+                *keyspace = mapped.clone();
+                true
+            }
+            None => false,
+        };
+    }
+
+    match table_name_mut(statement) {
+        Some(name) => match mapping.map(name, current_keyspace) {
+            Some(mapped) => {
+                *name = mapped;
+                true
+            }
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// A PREPARE body begins with the query as a `[long string]`, the query is rewritten and the rest of the body is kept as is.
+/// Returns None if the query does not need to be rewritten.
+fn rewrite_prepare(
+    mapping: &Mapping,
+    current_keyspace: Option<&Identifier>,
+    body: &[u8],
+) -> Option<Vec<u8>> {
+    let len = usize::try_from(i32::from_be_bytes(body.get(..4)?.try_into().ok()?)).ok()?;
+    let query = std::str::from_utf8(body.get(4..4 + len)?).ok()?;
+
+    let mut statement = parse_statement_single(query);
+    if !rewrite_statement(mapping, current_keyspace, &mut statement) {
+        return None;
+    }
+
+    let query = statement.to_string();
+    let rest = &body[4 + len..];
+    let mut new_body = Vec::with_capacity(4 + query.len() + rest.len());
+    new_body.extend_from_slice(&(query.len() as i32).to_be_bytes());
+    new_body.extend_from_slice(query.as_bytes());
+    new_body.extend_from_slice(rest);
+    Some(new_body)
+}
+
+fn identifier_name(identifier: &Identifier) -> &str {
+    match identifier {
+        Identifier::Unquoted(name) | Identifier::Quoted(name) => name,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn mapping() -> Mapping {
+        Mapping {
+            keyspaces: HashMap::from([(
+                Identifier::parse("app"),
+                Identifier::parse("tenant_a_app"),
+            )]),
+            tables: HashMap::from([(
+                FQName::parse("app.legacy"),
+                FQName::parse("tenant_a_archive.legacy_v2"),
+            )]),
+        }
+    }
+
+    fn rewrite(query: &str, current_keyspace: Option<&str>) -> CassandraStatement {
+        let mut statement = parse_statement_single(query);
+        let current_keyspace = current_keyspace.map(Identifier::parse);
+        rewrite_statement(&mapping(), current_keyspace.as_ref(), &mut statement);
+        statement
+    }
+
+    #[test]
+    fn test_rewrite_keyspace() {
+        assert_eq!(
+            rewrite("SELECT * FROM app.users WHERE id = 1", None),
+            parse_statement_single("SELECT * FROM tenant_a_app.users WHERE id = 1")
+        );
+        assert_eq!(
+            rewrite("INSERT INTO app.users (id) VALUES (1)", None),
+            parse_statement_single("INSERT INTO tenant_a_app.users (id) VALUES (1)")
+        );
+        assert_eq!(
+            rewrite("USE app", None),
+            parse_statement_single("USE tenant_a_app")
+        );
+        assert_eq!(
+            rewrite("SELECT * FROM other.users", None),
+            parse_statement_single("SELECT * FROM other.users")
+        );
+    }
+
+    #[test]
+    fn test_rewrite_table() {
+        assert_eq!(
+            rewrite("SELECT * FROM app.legacy", None),
+            parse_statement_single("SELECT * FROM tenant_a_archive.legacy_v2")
+        );
+        // unqualified names are resolved against the current keyspace
+        assert_eq!(
+            rewrite("SELECT * FROM legacy", Some("app")),
+            parse_statement_single("SELECT * FROM tenant_a_archive.legacy_v2")
+        );
+        assert_eq!(
+            rewrite("SELECT * FROM users", Some("app")),
+            parse_statement_single("SELECT * FROM users")
+        );
+    }
+
+    #[test]
+    fn test_rewrite_prepare() {
+        let query = "SELECT * FROM app.users WHERE id = ?";
+        let mut body = (query.len() as i32).to_be_bytes().to_vec();
+        body.extend_from_slice(query.as_bytes());
+
+        let new_body = rewrite_prepare(&mapping(), None, &body).unwrap();
+        let len = i32::from_be_bytes(new_body[..4].try_into().unwrap()) as usize;
+        assert_eq!(len, new_body.len() - 4);
+        assert_eq!(
+            parse_statement_single(std::str::from_utf8(&new_body[4..]).unwrap()),
+            parse_statement_single("SELECT * FROM tenant_a_app.users WHERE id = ?")
+        );
+
+        let query = "SELECT * FROM other.users WHERE id = ?";
+        let mut body = (query.len() as i32).to_be_bytes().to_vec();
+        body.extend_from_slice(query.as_bytes());
+        assert_eq!(rewrite_prepare(&mapping(), None, &body), None);
+    }
+}
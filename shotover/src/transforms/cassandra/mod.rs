@@ -1,3 +1,9 @@
+pub mod client_compression;
+pub mod cluster_metadata;
+pub mod ddl_guard;
+pub mod keyspace_rewrite;
 pub mod peers_rewrite;
+pub mod protocol_version_pin;
+pub mod result_projection;
 pub mod sink_cluster;
 pub mod sink_single;
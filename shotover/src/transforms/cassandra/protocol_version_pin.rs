@@ -0,0 +1,246 @@
+use crate::frame::cassandra::{CassandraMetadata, Tracing};
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::frame::{Opcode, Version};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraProtocolVersionPinConfig {
+    /// The highest protocol version clients may negotiate.
+    /// When not set, the lowest version successfully negotiated with the cluster is pinned.
+    pub version: Option<u8>,
+}
+
+const NAME: &str = "CassandraProtocolVersionPin";
+#[typetag::serde(name = "CassandraProtocolVersionPin")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraProtocolVersionPinConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(CassandraProtocolVersionPinBuilder {
+            configured_version: self.version,
+            pinned_version: Arc::new(AtomicU8::new(self.version.unwrap_or(UNPINNED))),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+const UNPINNED: u8 = 0;
+
+pub struct CassandraProtocolVersionPinBuilder {
+    configured_version: Option<u8>,
+    /// Shared by every connection to the chain, [`UNPINNED`] until a version is negotiated
+    pinned_version: Arc<AtomicU8>,
+}
+
+impl TransformBuilder for CassandraProtocolVersionPinBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraProtocolVersionPin {
+            learn: self.configured_version.is_none(),
+            pinned_version: self.pinned_version.clone(),
+            rejected_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        match self.configured_version {
+            Some(version)
+                if !matches!(
+                    Version::try_from(version),
+                    Ok(Version::V3 | Version::V4 | Version::V5)
+                ) =>
+            {
+                vec![
+                    format!("{NAME}:"),
+                    format!("  version {version} is not a supported protocol version, must be one of 3, 4 or 5"),
+                ]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Pins the protocol version that clients negotiate through shotover.
+///
+/// During a rolling upgrade the nodes of a cluster support different protocol versions,
+/// so a client may negotiate a new version on one connection and be refused it on the next, causing the driver to renegotiate repeatedly.
+/// This transform refuses any handshake above the pinned version itself, before it reaches the cluster,
+/// and removes the newer versions from the PROTOCOL_VERSIONS advertised in SUPPORTED responses,
+/// so that every client settles on the pinned version regardless of which node it is routed to.
+pub struct CassandraProtocolVersionPin {
+    learn: bool,
+    pinned_version: Arc<AtomicU8>,
+    rejected_requests: MessageIdMap<Message>,
+}
+
+impl CassandraProtocolVersionPin {
+    fn pinned_version(&self) -> Option<u8> {
+        match self.pinned_version.load(Ordering::Relaxed) {
+            UNPINNED => None,
+            version => Some(version),
+        }
+    }
+
+    /// Pins the lowest version negotiated so far
+    fn learn_version(&self, version: Version) {
+        let version = u8::from(version);
+        self.pinned_version
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pinned| {
+                (pinned == UNPINNED || version < pinned).then_some(version)
+            })
+            .ok();
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraProtocolVersionPin {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        if let Some(pinned) = self.pinned_version() {
+            for request in requests_wrapper.requests.iter_mut() {
+                if let Ok(Metadata::Cassandra(metadata)) = request.metadata() {
+                    if matches!(metadata.opcode, Opcode::Options | Opcode::Startup)
+                        && u8::from(metadata.version) > pinned
+                    {
+                        let mut response =
+                            Message::from_frame(Frame::Cassandra(reject(&metadata, pinned)));
+                        response.set_request_id(request.id());
+                        self.rejected_requests.insert(request.id(), response);
+                        request.replace_with_dummy();
+                    }
+                }
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if let Some(rejection) = response
+                .request_id()
+                .and_then(|id| self.rejected_requests.remove(&id))
+            {
+                *response = rejection;
+                continue;
+            }
+
+            let Ok(Metadata::Cassandra(metadata)) = response.metadata() else {
+                continue;
+            };
+            match metadata.opcode {
+                // The handshake completed successfully at this version
+                Opcode::Ready | Opcode::Authenticate if self.learn => {
+                    self.learn_version(metadata.version)
+                }
+                Opcode::Supported => {
+                    if let Some(pinned) = self.pinned_version() {
+                        if let Some(Frame::Cassandra(CassandraFrame {
+                            operation: CassandraOperation::Supported(supported),
+                            ..
+                        })) = response.frame()
+                        {
+                            if let Some(versions) = supported.data.get_mut("PROTOCOL_VERSIONS") {
+                                versions.retain(|version| advertised_version(version) <= pinned);
+                                response.invalidate_cache();
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+/// Returns the version number from an entry of PROTOCOL_VERSIONS such as `4/v4` or `5/v5-beta`
+fn advertised_version(version: &str) -> u8 {
+    version
+        .split('/')
+        .next()
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(u8::MAX)
+}
+
+/// Drivers recognize this error message and retry the handshake with a lower version
+fn reject(metadata: &CassandraMetadata, pinned: u8) -> CassandraFrame {
+    let supported = [Version::V3, Version::V4, Version::V5]
+        .into_iter()
+        .map(u8::from)
+        .filter(|version| *version <= pinned)
+        .map(|version| format!("{version}/v{version}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    CassandraFrame {
+        version: Version::try_from(pinned).unwrap_or(Version::V4),
+        stream_id: metadata.stream_id,
+        operation: CassandraOperation::Error(ErrorBody {
+            message: format!(
+                "Invalid or unsupported protocol version ({}); supported versions are ({supported})",
+                u8::from(metadata.version)
+            ),
+            ty: ErrorType::Protocol,
+        }),
+        tracing: Tracing::Response(None),
+        warnings: vec![],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_learn_version() {
+        let transform = CassandraProtocolVersionPin {
+            learn: true,
+            pinned_version: Arc::new(AtomicU8::new(UNPINNED)),
+            rejected_requests: MessageIdMap::default(),
+        };
+        assert_eq!(transform.pinned_version(), None);
+
+        transform.learn_version(Version::V5);
+        assert_eq!(transform.pinned_version(), Some(5));
+
+        transform.learn_version(Version::V4);
+        assert_eq!(transform.pinned_version(), Some(4));
+
+        // a higher version never raises the pin
+        transform.learn_version(Version::V5);
+        assert_eq!(transform.pinned_version(), Some(4));
+    }
+
+    #[test]
+    fn test_advertised_version() {
+        assert_eq!(advertised_version("4/v4"), 4);
+        assert_eq!(advertised_version("5/v5-beta"), 5);
+        assert_eq!(advertised_version("garbage"), u8::MAX);
+    }
+}
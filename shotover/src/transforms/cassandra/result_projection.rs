@@ -0,0 +1,386 @@
+use crate::frame::value::GenericValue;
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame, MessageType};
+use crate::message::{MessageIdMap, Messages};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_result::{
+    ColSpec, ColType, ColTypeOption, RowsMetadata, RowsMetadataFlags,
+};
+use cassandra_protocol::types::CBytesShort;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraResultProjectionConfig {
+    /// Maps fully qualified table names to the projection applied to rows read from that table
+    pub tables: HashMap<String, TableProjectionConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TableProjectionConfig {
+    /// Columns removed from the rows returned to the client
+    #[serde(default)]
+    pub hide: Vec<String>,
+    /// Maps column names used in the table to the column names reported to the client
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+    /// Text columns with a fixed value appended to the rows returned to the client
+    #[serde(default)]
+    pub add: Vec<StaticColumnConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StaticColumnConfig {
+    pub name: String,
+    pub value: String,
+}
+
+const NAME: &str = "CassandraResultProjection";
+#[typetag::serde(name = "CassandraResultProjection")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraResultProjectionConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(CassandraResultProjectionBuilder {
+            tables: Arc::new(self.tables.clone()),
+            prepared: Arc::new(Mutex::new(HashMap::new())),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// The rows metadata, as returned by the cluster, of every prepared statement whose results are projected.
+/// Shared between connections since a statement can be prepared on one connection and executed on another.
+type PreparedResults = Arc<Mutex<HashMap<CBytesShort, Arc<RowsMetadata>>>>;
+
+pub struct CassandraResultProjectionBuilder {
+    tables: Arc<HashMap<String, TableProjectionConfig>>,
+    prepared: PreparedResults,
+}
+
+impl TransformBuilder for CassandraResultProjectionBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraResultProjection {
+            tables: self.tables.clone(),
+            prepared: self.prepared.clone(),
+            executes: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for (table, projection) in self.tables.iter() {
+            if !table.contains('.') {
+                errors.push(format!(
+                    "  table {table:?} must be a fully qualified table name"
+                ));
+            }
+            for hidden in &projection.hide {
+                if projection.rename.contains_key(hidden) {
+                    errors.push(format!(
+                        "  column {hidden:?} of table {table} is both hidden and renamed"
+                    ));
+                }
+            }
+            for (i, column) in projection.add.iter().enumerate() {
+                if projection.add[..i].iter().any(|x| x.name == column.name)
+                    || projection.rename.values().any(|x| *x == column.name)
+                {
+                    errors.push(format!(
+                        "  added column {:?} of table {table} is reported more than once",
+                        column.name
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            errors
+        } else {
+            let mut output = vec![format!("{NAME}:")];
+            output.extend(errors);
+            output
+        }
+    }
+}
+
+/// Hides, renames and adds columns in the rows returned from configured tables,
+/// allowing simple view like shaping of results without changing the schema.
+///
+/// Only results are shaped, requests are passed down the chain unmodified,
+/// so a hidden column can still be used in the WHERE clause of a query.
+pub struct CassandraResultProjection {
+    tables: Arc<HashMap<String, TableProjectionConfig>>,
+    prepared: PreparedResults,
+    /// The prepared statement id of each in flight EXECUTE request
+    executes: MessageIdMap<CBytesShort>,
+}
+
+#[async_trait]
+impl Transform for CassandraResultProjection {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in requests_wrapper.requests.iter_mut() {
+            let request_id = request.id();
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Execute(execute),
+                ..
+            })) = request.frame()
+            {
+                self.executes.insert(request_id, execute.id.clone());
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            let execute_id = response
+                .request_id()
+                .and_then(|request_id| self.executes.remove(&request_id));
+            let Some(Frame::Cassandra(frame)) = response.frame() else {
+                continue;
+            };
+            let projected = match &mut frame.operation {
+                CassandraOperation::Result(CassandraResult::Rows { rows, metadata }) => {
+                    if metadata.flags.contains(RowsMetadataFlags::NO_METADATA) {
+                        // The client skipped the metadata since it already has it from the PREPARE response,
+                        // which was projected when it passed through this transform.
+                        let prepared = execute_id
+                            .and_then(|id| self.prepared.lock().unwrap().get(&id).cloned());
+                        match prepared.and_then(|prepared| self.columns(&prepared)) {
+                            Some(columns) => {
+                                project_rows(rows, &columns);
+                                true
+                            }
+                            None => false,
+                        }
+                    } else {
+                        match self.columns(metadata) {
+                            Some(columns) => {
+                                project_metadata(metadata, &columns);
+                                project_rows(rows, &columns);
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                }
+                CassandraOperation::Result(CassandraResult::Prepared(prepared)) => {
+                    match self.columns(&prepared.result_metadata) {
+                        Some(columns) => {
+                            self.prepared.lock().unwrap().insert(
+                                prepared.id.clone(),
+                                Arc::new(prepared.result_metadata.clone()),
+                            );
+                            project_metadata(&mut prepared.result_metadata, &columns);
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                _ => false,
+            };
+            if projected {
+                response.invalidate_cache();
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+impl CassandraResultProjection {
+    /// Returns the columns to report to the client if the rows described by `metadata` belong to a configured table
+    fn columns(&self, metadata: &RowsMetadata) -> Option<Vec<ProjectedColumn>> {
+        let table_spec = metadata
+            .global_table_spec
+            .as_ref()
+            .or_else(|| metadata.col_specs.first()?.table_spec.as_ref())?;
+        let projection = self
+            .tables
+            .get(&format!("{}.{}", table_spec.ks_name, table_spec.table_name))?;
+        Some(projected_columns(projection, &metadata.col_specs))
+    }
+}
+
+struct ProjectedColumn {
+    spec: ColSpec,
+    value: ColumnValue,
+}
+
+enum ColumnValue {
+    /// The value of the column at this index in the rows returned by the cluster
+    Index(usize),
+    Static(String),
+}
+
+fn projected_columns(
+    projection: &TableProjectionConfig,
+    col_specs: &[ColSpec],
+) -> Vec<ProjectedColumn> {
+    let mut columns: Vec<ProjectedColumn> = col_specs
+        .iter()
+        .enumerate()
+        .filter(|(_, spec)| !projection.hide.contains(&spec.name))
+        .map(|(i, spec)| {
+            let mut spec = spec.clone();
+            if let Some(name) = projection.rename.get(&spec.name) {
+                spec.name = name.clone();
+            }
+            ProjectedColumn {
+                spec,
+                value: ColumnValue::Index(i),
+            }
+        })
+        .collect();
+
+    let table_spec = col_specs.first().and_then(|spec| spec.table_spec.clone());
+    columns.extend(projection.add.iter().map(|column| ProjectedColumn {
+        spec: ColSpec {
+            table_spec: table_spec.clone(),
+            name: column.name.clone(),
+            col_type: ColTypeOption {
+                id: ColType::Varchar,
+                value: None,
+            },
+        },
+        value: ColumnValue::Static(column.value.clone()),
+    }));
+    columns
+}
+
+fn project_metadata(metadata: &mut RowsMetadata, columns: &[ProjectedColumn]) {
+    metadata.col_specs = columns.iter().map(|column| column.spec.clone()).collect();
+    metadata.columns_count = columns.len() as i32;
+}
+
+fn project_rows(rows: &mut [Vec<GenericValue>], columns: &[ProjectedColumn]) {
+    for row in rows.iter_mut() {
+        *row = columns
+            .iter()
+            .map(|column| match &column.value {
+                ColumnValue::Index(i) => row.get(*i).cloned().unwrap_or(GenericValue::Null),
+                ColumnValue::Static(value) => GenericValue::Strings(value.clone()),
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::value::IntSize;
+    use cassandra_protocol::frame::message_result::TableSpec;
+    use pretty_assertions::assert_eq;
+
+    fn col_spec(name: &str, id: ColType) -> ColSpec {
+        ColSpec {
+            table_spec: None,
+            name: name.into(),
+            col_type: ColTypeOption { id, value: None },
+        }
+    }
+
+    fn metadata(col_specs: Vec<ColSpec>) -> RowsMetadata {
+        RowsMetadata {
+            flags: RowsMetadataFlags::GLOBAL_TABLE_SPACE,
+            columns_count: col_specs.len() as i32,
+            paging_state: None,
+            new_metadata_id: None,
+            global_table_spec: Some(TableSpec {
+                ks_name: "app".into(),
+                table_name: "users".into(),
+            }),
+            col_specs,
+        }
+    }
+
+    fn projection() -> TableProjectionConfig {
+        TableProjectionConfig {
+            hide: vec!["internal_flags".into()],
+            rename: HashMap::from([("name".into(), "display_name".into())]),
+            add: vec![StaticColumnConfig {
+                name: "tenant".into(),
+                value: "acme".into(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_project() {
+        let mut metadata = metadata(vec![
+            col_spec("id", ColType::Int),
+            col_spec("internal_flags", ColType::Int),
+            col_spec("name", ColType::Varchar),
+        ]);
+        let mut rows = vec![vec![
+            GenericValue::Integer(1, IntSize::I32),
+            GenericValue::Integer(7, IntSize::I32),
+            GenericValue::Strings("alice".into()),
+        ]];
+
+        let columns = projected_columns(&projection(), &metadata.col_specs);
+        project_metadata(&mut metadata, &columns);
+        project_rows(&mut rows, &columns);
+
+        assert_eq!(
+            metadata,
+            self::metadata(vec![
+                col_spec("id", ColType::Int),
+                col_spec("display_name", ColType::Varchar),
+                col_spec("tenant", ColType::Varchar),
+            ])
+        );
+        assert_eq!(
+            rows,
+            vec![vec![
+                GenericValue::Integer(1, IntSize::I32),
+                GenericValue::Strings("alice".into()),
+                GenericValue::Strings("acme".into()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_columns_only_for_configured_tables() {
+        let transform = CassandraResultProjection {
+            tables: Arc::new(HashMap::from([("app.users".into(), projection())])),
+            prepared: Default::default(),
+            executes: Default::default(),
+        };
+
+        let users = metadata(vec![col_spec("id", ColType::Int)]);
+        assert!(transform.columns(&users).is_some());
+
+        let mut other = users.clone();
+        other.global_table_spec = Some(TableSpec {
+            ks_name: "app".into(),
+            table_name: "orders".into(),
+        });
+        assert!(transform.columns(&other).is_none());
+    }
+}
@@ -3,11 +3,13 @@ use std::collections::HashSet;
 use crate::{
     connection::{ConnectionError, SinkConnection},
     frame::{CassandraFrame, Frame},
+    maintenance::{self, ConnectionGuard},
     message::Message,
 };
 use anyhow::Result;
 use cassandra_protocol::frame::Version;
 use fnv::FnvBuildHasher;
+use std::net::SocketAddr;
 
 /// Wraps SinkConnection to:
 /// * convert connection errors into cassandra error messages
@@ -17,14 +19,17 @@ pub struct CassandraConnection {
     pending_request_stream_ids: HashSet<i16, FnvBuildHasher>,
     // Does not neccesarily equal pending_request_stream_ids.len() since the client could reuse stream_ids
     pending_request_count: usize,
+    /// Counts this connection as open until it is dropped, so that a node in maintenance is only reported as quiesced once it is closed
+    _open_connection: ConnectionGuard,
 }
 
 impl CassandraConnection {
-    pub fn new(connection: SinkConnection) -> Self {
+    pub fn new(connection: SinkConnection, address: SocketAddr) -> Self {
         CassandraConnection {
             connection,
             pending_request_stream_ids: Default::default(),
             pending_request_count: 0,
+            _open_connection: maintenance::track_connection(address),
         }
     }
 
@@ -35,6 +40,22 @@ impl CassandraConnection {
         self.connection.send(requests)
     }
 
+    /// Takes back the requests that have not yet been written to the connection so they can be sent to another node.
+    /// See [`SinkConnection::take_unwritten`]
+    pub async fn take_unwritten(&mut self) -> Vec<Message> {
+        let unwritten = self.connection.take_unwritten().await;
+        for request in &unwritten {
+            self.pending_request_stream_ids
+                .remove(&request.stream_id().unwrap());
+        }
+        self.pending_request_count -= unwritten.len();
+        unwritten
+    }
+
+    pub fn pending_request_count(&self) -> usize {
+        self.pending_request_count
+    }
+
     /// receive 0 or more responses
     pub fn try_recv(&mut self, responses: &mut Vec<Message>, version: Version) -> Result<(), ()> {
         let previous_len = responses.len();
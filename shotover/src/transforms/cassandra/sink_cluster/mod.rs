@@ -3,8 +3,13 @@ use self::node_pool::{get_accessible_owned_connection, NodePoolBuilder, Prepared
 use self::rewrite::{BatchMode, MessageRewriter};
 use crate::frame::cassandra::{CassandraMetadata, Tracing};
 use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame, MessageType};
+use crate::maintenance;
 use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::snapshot::{self, StateSnapshot};
+use crate::state_sync::{self, SyncedState};
 use crate::tls::{TlsConnector, TlsConnectorConfig};
+use crate::transforms::cassandra::cluster_metadata::NodeMetadata;
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{
     DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
     TransformContextConfig, UpChainProtocol, Wrapper,
@@ -15,6 +20,7 @@ use cassandra_protocol::events::ServerEvent;
 use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType, UnpreparedError};
 use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
 use cassandra_protocol::frame::{Opcode, Version};
+use cassandra_protocol::token::Murmur3Token;
 use cassandra_protocol::types::CBytesShort;
 use cql3_parser::cassandra_statement::CassandraStatement;
 use cql3_parser::common::IdentifierRef;
@@ -23,8 +29,11 @@ use node::{CassandraNode, ConnectionFactory};
 use node_pool::{GetReplicaErr, KeyspaceMetadata, NodePool};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{mpsc, watch};
 use topology::{create_topology_task, TaskConnectionInfo};
@@ -62,6 +71,10 @@ pub struct CassandraSinkClusterConfig {
     pub tls: Option<TlsConnectorConfig>,
     pub connect_timeout_ms: u64,
     pub read_timeout: Option<u64>,
+    pub keepalive_interval: Option<u64>,
+    /// Split unlogged batches of prepared statements into a batch per partition, each routed to a replica of its partition
+    #[serde(default)]
+    pub split_unlogged_batches: bool,
 }
 
 const NAME: &str = "CassandraSinkCluster";
@@ -94,6 +107,8 @@ impl TransformConfig for CassandraSinkClusterConfig {
             tls,
             self.connect_timeout_ms,
             self.read_timeout,
+            self.keepalive_interval,
+            self.split_unlogged_batches,
         )))
     }
 
@@ -115,9 +130,128 @@ pub struct CassandraSinkClusterBuilder {
     keyspaces_rx: KeyspaceChanRx,
     task_handshake_tx: mpsc::Sender<TaskConnectionInfo>,
     pool: NodePoolBuilder,
+    /// Kept alive so that the state of the transform is included in snapshots and state sync for as long as the builder exists
+    _state: Arc<CassandraSinkClusterState>,
+}
+
+/// The state shared between every instance of a CassandraSinkCluster, for state snapshots and state sync
+struct CassandraSinkClusterState {
+    nodes_tx: Arc<watch::Sender<Vec<CassandraNode>>>,
+    nodes_rx: watch::Receiver<Vec<CassandraNode>>,
+    keyspaces_tx: Arc<KeyspaceChanTx>,
+    keyspaces_rx: KeyspaceChanRx,
+    /// Set once the topology task has started learning the topology of the cluster itself
+    learned: Arc<AtomicBool>,
+    pool: NodePoolBuilder,
+}
+
+#[async_trait]
+impl StateSnapshot for CassandraSinkClusterState {
+    async fn snapshot(&self) -> serde_json::Value {
+        let nodes: Vec<_> = self
+            .nodes_rx
+            .borrow()
+            .iter()
+            .map(|node| {
+                json!({
+                    "address": node.address.to_string(),
+                    "rack": node.rack,
+                    "host_id": node.host_id.to_string(),
+                    "is_up": node.is_up,
+                })
+            })
+            .collect();
+        let keyspace_count = self.keyspaces_rx.borrow().len();
+        json!({
+            "nodes": nodes,
+            "keyspace_count": keyspace_count,
+            "prepared_statement_count": self.pool.prepared_statement_count().await,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncedTopology {
+    nodes: Vec<NodeMetadata>,
+    keyspaces: HashMap<String, KeyspaceMetadata>,
+    prepared: Vec<(Vec<u8>, PreparedMetadata)>,
+}
+
+#[async_trait]
+impl SyncedState for CassandraSinkClusterState {
+    async fn export(&self) -> Option<serde_json::Value> {
+        let nodes: Vec<_> = self
+            .nodes_rx
+            .borrow()
+            .iter()
+            .map(|node| NodeMetadata {
+                address: node.address,
+                rack: node.rack.clone(),
+                host_id: node.host_id,
+                is_up: node.is_up,
+                tokens: node.tokens.iter().map(|token| token.value).collect(),
+            })
+            .collect();
+        if nodes.is_empty() {
+            return None;
+        }
+        let keyspaces = self.keyspaces_rx.borrow().clone();
+        serde_json::to_value(SyncedTopology {
+            nodes,
+            keyspaces,
+            prepared: self.pool.export_prepared_metadata().await,
+        })
+        .ok()
+    }
+
+    async fn import(&self, state: serde_json::Value) -> Result<()> {
+        let synced: SyncedTopology = serde_json::from_value(state)?;
+        let nodes: Vec<_> = synced
+            .nodes
+            .into_iter()
+            .map(|synced_node| {
+                let mut node = CassandraNode::new(
+                    synced_node.address,
+                    synced_node.rack,
+                    synced_node
+                        .tokens
+                        .into_iter()
+                        .map(Murmur3Token::new)
+                        .collect(),
+                    synced_node.host_id,
+                );
+                node.is_up = synced_node.is_up;
+                node
+            })
+            .collect();
+
+        // Once the topology task has started it is the only source of the topology.
+        // The flag is checked while the channel is locked so that a topology sent by the task is never overwritten.
+        self.nodes_tx.send_if_modified(|current| {
+            if self.learned.load(Ordering::Relaxed) {
+                false
+            } else {
+                *current = nodes;
+                true
+            }
+        });
+        self.keyspaces_tx.send_if_modified(|current| {
+            if self.learned.load(Ordering::Relaxed) {
+                false
+            } else {
+                *current = synced.keyspaces;
+                true
+            }
+        });
+
+        // Prepared statements are never invalidated by the cluster, so merge in the ones this instance has not seen yet
+        self.pool.import_prepared_metadata(synced.prepared).await;
+        Ok(())
+    }
 }
 
 impl CassandraSinkClusterBuilder {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         contact_points: Vec<String>,
         shotover_peers: Vec<ShotoverNode>,
@@ -126,22 +260,30 @@ impl CassandraSinkClusterBuilder {
         tls: Option<TlsConnector>,
         connect_timeout_ms: u64,
         read_timeout: Option<u64>,
+        keepalive_interval: Option<u64>,
+        split_unlogged_batches: bool,
     ) -> Self {
         let failed_requests = counter!("shotover_failed_requests_count", "chain" => chain_name.clone(), "transform" => "CassandraSinkCluster");
         let read_timeout = read_timeout.map(Duration::from_secs);
+        let keepalive_interval = keepalive_interval.map(Duration::from_secs);
         let connect_timeout = Duration::from_millis(connect_timeout_ms);
 
         let (local_nodes_tx, local_nodes_rx) = watch::channel(vec![]);
+        let local_nodes_tx = Arc::new(local_nodes_tx);
         let (keyspaces_tx, keyspaces_rx): (KeyspaceChanTx, KeyspaceChanRx) =
             watch::channel(HashMap::new());
+        let keyspaces_tx = Arc::new(keyspaces_tx);
+        let learned = Arc::new(AtomicBool::new(false));
 
         let (task_handshake_tx, task_handshake_rx) = mpsc::channel(1);
 
         create_topology_task(
-            local_nodes_tx,
-            keyspaces_tx,
+            local_nodes_tx.clone(),
+            keyspaces_tx.clone(),
             task_handshake_rx,
+            learned.clone(),
             local_shotover_node.data_center.clone(),
+            chain_name.clone(),
         );
 
         let message_rewriter = MessageRewriter {
@@ -149,17 +291,38 @@ impl CassandraSinkClusterBuilder {
             local_shotover_node,
             to_rewrite: vec![],
             prepare_requests_to_destination_nodes: MessageIdMap::default(),
+            split_unlogged_batches,
+            batch_routing: MessageIdMap::default(),
+            warned_unsplit_batch: false,
         };
 
+        let pool = NodePoolBuilder::new(chain_name.clone());
+        let state = Arc::new(CassandraSinkClusterState {
+            nodes_tx: local_nodes_tx,
+            nodes_rx: local_nodes_rx.clone(),
+            keyspaces_tx,
+            keyspaces_rx: keyspaces_rx.clone(),
+            learned,
+            pool: pool.clone(),
+        });
+        snapshot::register(NAME, &chain_name, &state);
+        state_sync::register(NAME, &chain_name, &state);
+
         Self {
             contact_points,
-            connection_factory: ConnectionFactory::new(connect_timeout, read_timeout, tls),
+            connection_factory: ConnectionFactory::new(
+                connect_timeout,
+                read_timeout,
+                keepalive_interval,
+                tls,
+            ),
             message_rewriter,
             failed_requests,
             nodes_rx: local_nodes_rx,
             keyspaces_rx,
             task_handshake_tx,
-            pool: NodePoolBuilder::new(chain_name),
+            pool,
+            _state: state,
         }
     }
 }
@@ -184,6 +347,8 @@ impl TransformBuilder for CassandraSinkClusterBuilder {
             keyspaces_rx: self.keyspaces_rx.clone(),
             rng: SmallRng::from_rng(rand::thread_rng()).unwrap(),
             task_handshake_tx: self.task_handshake_tx.clone(),
+            draining_connections: vec![],
+            maintenance_generation: maintenance::generation(),
         })
     }
 
@@ -191,6 +356,11 @@ impl TransformBuilder for CassandraSinkClusterBuilder {
         NAME
     }
 
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Requests are spread across the replicas of their token, which execute them concurrently
+        DeliveryGuarantees::UNORDERED
+    }
+
     fn is_terminating(&self) -> bool {
         true
     }
@@ -229,6 +399,11 @@ pub struct CassandraSinkCluster {
     keyspaces_rx: KeyspaceChanRx,
     rng: SmallRng,
     task_handshake_tx: mpsc::Sender<TaskConnectionInfo>,
+    /// Connections to nodes that were removed, went down or were put into maintenance.
+    /// No new requests are sent to them but they are kept until every request already written to them has a response.
+    draining_connections: Vec<CassandraConnection>,
+    /// The [`maintenance::generation`] last acted on
+    maintenance_generation: u64,
 }
 
 impl CassandraSinkCluster {
@@ -252,29 +427,58 @@ impl CassandraSinkCluster {
             }
         }
 
-        if self.nodes_rx.has_changed()? {
+        // Requests queued for connections that are now draining, they are sent to another node instead
+        let mut migrated_requests = vec![];
+        let nodes_changed = self.nodes_rx.has_changed()?;
+        if nodes_changed {
             // This approach to keeping nodes list up to date has a problem when a node goes down and then up again before this transform instance can process the down going down.
             // When this happens we never detect that the node went down and a dead connection is left around.
             // Broadcast channel's SendError::Lagged would solve this problem but we cant use broadcast channels because cloning them doesnt keep a past value.
             // It might be worth implementing a custom watch channel that supports Lagged errors to improve correctness.
             //
             // However none of this is actually a problem because dead connection detection logic handles this case for us.
-            self.pool.update_nodes(&mut self.nodes_rx);
+            for connection in self.pool.update_nodes(&mut self.nodes_rx) {
+                self.drain_connection(connection, &mut migrated_requests)
+                    .await;
+            }
+        }
 
-            // recreate the control connection if it is down
+        let maintenance_generation = maintenance::generation();
+        let maintenance_changed = maintenance_generation != self.maintenance_generation;
+        if maintenance_changed {
+            self.maintenance_generation = maintenance_generation;
+            let connections: Vec<CassandraConnection> = self
+                .pool
+                .nodes_mut()
+                .iter_mut()
+                .filter(|node| maintenance::in_maintenance(node.address))
+                .filter_map(|node| node.outbound.take())
+                .collect();
+            for connection in connections {
+                self.drain_connection(connection, &mut migrated_requests)
+                    .await;
+            }
+        }
+
+        if nodes_changed || maintenance_changed {
+            // recreate the control connection if it is down or in maintenance
             if let Some(address) = self.control_connection_address {
                 if !self
                     .pool
                     .nodes()
                     .iter()
-                    .any(|x| x.address == address && x.is_up)
+                    .any(|x| x.address == address && x.is_available())
                 {
                     let (connection, address) = self.pool.get_random_owned_connection_in_dc_rack(
                         &self.message_rewriter.local_shotover_node.rack,
                         &mut self.rng,
                         &self.connection_factory,
                     ).await
-                    .context("Failed to recreate control connection after control connection node went down")?;
+                    .context("Failed to recreate control connection after control connection node went down or entered maintenance")?;
+                    if let Some(old_connection) = self.control_connection.take() {
+                        self.drain_connection(old_connection, &mut migrated_requests)
+                            .await;
+                    }
                     self.set_control_connection(connection, address)
                 }
             }
@@ -307,17 +511,16 @@ impl CassandraSinkCluster {
                 node.recv_all_pending(&mut responses, self.version.unwrap())
                     .await;
             }
+            self.recv_from_draining_connections(&mut responses, true)
+                .await;
         }
 
         // Create the initial connection.
         // Messages will be sent through this connection until we have extracted the handshake.
         if self.control_connection.is_none() {
-            let (connection, address) = if self
-                .pool
-                .nodes()
-                .iter()
-                .any(|x| x.is_up && x.rack == self.message_rewriter.local_shotover_node.rack)
-            {
+            let (connection, address) = if self.pool.nodes().iter().any(|x| {
+                x.is_available() && x.rack == self.message_rewriter.local_shotover_node.rack
+            }) {
                 self.pool
                     .get_random_owned_connection_in_dc_rack(
                         &self.message_rewriter.local_shotover_node.rack,
@@ -365,6 +568,8 @@ impl CassandraSinkCluster {
             }
         }
 
+        self.route_requests(migrated_requests, &mut responses)
+            .await?;
         self.route_requests(requests, &mut responses).await?;
 
         // receive messages from all connections
@@ -380,6 +585,8 @@ impl CassandraSinkCluster {
                     node.recv_all_pending(&mut responses, self.version.unwrap())
                         .await;
                 }
+                self.recv_from_draining_connections(&mut responses, true)
+                    .await;
             }
             BatchMode::Pipelined => {
                 if let Some(connection) = self.control_connection.as_mut() {
@@ -390,6 +597,8 @@ impl CassandraSinkCluster {
                 for node in self.pool.nodes_mut().iter_mut() {
                     node.try_recv(&mut responses, self.version.unwrap());
                 }
+                self.recv_from_draining_connections(&mut responses, false)
+                    .await;
             }
         }
 
@@ -445,6 +654,40 @@ impl CassandraSinkCluster {
         Ok(responses)
     }
 
+    /// Stops sending requests to the connection, its requests that have not yet been written are moved to `migrated_requests` to be sent to another node.
+    /// The connection is kept until every request already written to it has a response, so that removing a node is invisible to the client.
+    async fn drain_connection(
+        &mut self,
+        mut connection: CassandraConnection,
+        migrated_requests: &mut Vec<Message>,
+    ) {
+        migrated_requests.extend(connection.take_unwritten().await);
+        if connection.pending_request_count() > 0 {
+            self.draining_connections.push(connection);
+        }
+    }
+
+    /// Receives responses from the draining connections, dropping each connection once it has no pending requests.
+    /// When `wait_for_all` is set, waits for a response to every pending request.
+    async fn recv_from_draining_connections(
+        &mut self,
+        responses: &mut Vec<Message>,
+        wait_for_all: bool,
+    ) {
+        let version = self.version.unwrap();
+        for mut connection in std::mem::take(&mut self.draining_connections) {
+            let result = if wait_for_all {
+                connection.recv_all_pending(responses, version).await
+            } else {
+                connection.try_recv(responses, version)
+            };
+            // On error the pending requests were already answered with errors
+            if result.is_ok() && connection.pending_request_count() > 0 {
+                self.draining_connections.push(connection);
+            }
+        }
+    }
+
     async fn route_requests(
         &mut self,
         requests: Vec<Message>,
@@ -502,6 +745,42 @@ impl CassandraSinkCluster {
                         &format!("{err}"),
                     )?),
                 }
+            } else if let Some((routing_key, keyspace)) =
+                self.message_rewriter.get_routing_for_batch(&message)
+            {
+                // Batches created by splitting an unlogged batch contain a single partition, so route them to a replica of that partition
+                let rack = &self.message_rewriter.local_shotover_node.rack;
+                match self
+                    .pool
+                    .get_replica_connection_for_token(
+                        routing_key,
+                        &keyspace,
+                        rack,
+                        &mut self.rng,
+                        &self.connection_factory,
+                    )
+                    .await
+                {
+                    Ok(connection) => connection.send(vec![message])?,
+                    Err(GetReplicaErr::NoNodeAvailable(err)) => responses.push(
+                        send_error_in_response_to_message(&message, &format!("{err}"))?,
+                    ),
+                    Err(_) => match self
+                        .pool
+                        .get_random_connection_in_dc_rack(
+                            rack,
+                            &mut self.rng,
+                            &self.connection_factory,
+                        )
+                        .await
+                    {
+                        Ok(connection) => connection.send(vec![message])?,
+                        Err(err) => responses.push(send_error_in_response_to_message(
+                            &message,
+                            &format!("{err}"),
+                        )?),
+                    },
+                }
             } else if let Some((execute, metadata)) = get_execute_message(&mut message) {
                 // If the message is an execute we should perform token aware routing
                 let rack = &self.message_rewriter.local_shotover_node.rack;
@@ -3,6 +3,7 @@ use crate::codec::cassandra::CassandraCodecBuilder;
 use crate::codec::{CodecBuilder, Direction};
 use crate::connection::SinkConnection;
 use crate::frame::Frame;
+use crate::maintenance;
 use crate::message::Message;
 use crate::tls::{TlsConnector, ToHostname};
 use anyhow::{anyhow, Result};
@@ -71,6 +72,11 @@ impl CassandraNode {
         Ok(self.outbound.as_mut().unwrap())
     }
 
+    /// Returns true if new requests may be routed to the node
+    pub fn is_available(&self) -> bool {
+        self.is_up && !maintenance::in_maintenance(self.address)
+    }
+
     pub fn try_recv(&mut self, responses: &mut Vec<Message>, version: Version) {
         if let Some(connection) = self.outbound.as_mut() {
             if let Err(()) = connection.try_recv(responses, version) {
@@ -98,6 +104,7 @@ impl CassandraNode {
 pub struct ConnectionFactory {
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     init_handshake: Vec<Message>,
     use_message: Option<Message>,
     #[derivative(Debug = "ignore")]
@@ -113,6 +120,7 @@ impl Clone for ConnectionFactory {
         Self {
             connect_timeout: self.connect_timeout,
             read_timeout: self.read_timeout,
+            keepalive_interval: self.keepalive_interval,
             init_handshake: self.init_handshake.clone(),
             use_message: None,
             tls: self.tls.clone(),
@@ -127,11 +135,13 @@ impl ConnectionFactory {
     pub fn new(
         connect_timeout: Duration,
         read_timeout: Option<Duration>,
+        keepalive_interval: Option<Duration>,
         tls: Option<TlsConnector>,
     ) -> Self {
         Self {
             connect_timeout,
             read_timeout,
+            keepalive_interval,
             init_handshake: vec![],
             use_message: None,
             tls,
@@ -150,6 +160,7 @@ impl ConnectionFactory {
             connect_timeout: self.connect_timeout,
             init_handshake: vec![],
             read_timeout: self.read_timeout,
+            keepalive_interval: self.keepalive_interval,
             use_message: None,
             tls: self.tls.clone(),
             force_run_chain: None,
@@ -158,10 +169,7 @@ impl ConnectionFactory {
         }
     }
 
-    pub async fn new_connection<A: ToSocketAddrs + ToHostname + std::fmt::Debug>(
-        &self,
-        address: A,
-    ) -> Result<CassandraConnection> {
+    pub async fn new_connection(&self, address: SocketAddr) -> Result<CassandraConnection> {
         let mut connection = SinkConnection::new(
             address,
             self.codec_builder.clone(),
@@ -169,6 +177,7 @@ impl ConnectionFactory {
             self.connect_timeout,
             self.force_run_chain.clone().unwrap(),
             self.read_timeout,
+            self.keepalive_interval,
         )
         .await
         .map_err(|e| e.context("Failed to create new connection"))?;
@@ -184,7 +193,7 @@ impl ConnectionFactory {
             connection.recv().await?;
         }
 
-        Ok(CassandraConnection::new(connection))
+        Ok(CassandraConnection::new(connection, address))
     }
 
     pub fn push_handshake_message(&mut self, mut request: Message) {
@@ -207,6 +216,7 @@ impl ConnectionFactory {
             self.connect_timeout,
             self.force_run_chain.clone().unwrap(),
             self.read_timeout,
+            self.keepalive_interval,
         )
         .await
         .map_err(|e| e.context("Failed to create new connection"))?;
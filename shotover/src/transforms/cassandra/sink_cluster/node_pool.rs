@@ -5,14 +5,17 @@ use super::token_ring::TokenRing;
 use super::KeyspaceChanRx;
 use anyhow::{anyhow, Context, Error, Result};
 use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
+use cassandra_protocol::query::QueryValues;
+use cassandra_protocol::token::Murmur3Token;
 use cassandra_protocol::types::CBytesShort;
 use metrics::{counter, Counter};
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use std::{collections::HashMap, net::SocketAddr};
 use tokio::sync::{watch, RwLock};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PreparedMetadata {
     pub pk_indexes: Vec<i16>,
     pub keyspace: Option<String>,
@@ -27,7 +30,7 @@ pub enum GetReplicaErr {
     NoRoutingKey,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ReplicationStrategy {
     SimpleStrategy,
     NetworkTopologyStrategy,
@@ -35,7 +38,7 @@ pub enum ReplicationStrategy {
     Unknown,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct KeyspaceMetadata {
     pub replication_factor: usize,
     pub replication_strategy: ReplicationStrategy,
@@ -56,6 +59,31 @@ impl NodePoolBuilder {
         }
     }
 
+    /// The number of prepared statements whose metadata is cached
+    pub async fn prepared_statement_count(&self) -> usize {
+        self.prepared_metadata.read().await.len()
+    }
+
+    /// The metadata of every cached prepared statement, alongside the id of the statement
+    pub async fn export_prepared_metadata(&self) -> Vec<(Vec<u8>, PreparedMetadata)> {
+        self.prepared_metadata
+            .read()
+            .await
+            .iter()
+            .filter_map(|(id, metadata)| Some((id.clone().into_plain()?, (**metadata).clone())))
+            .collect()
+    }
+
+    /// Caches the metadata of any of the prepared statements that are not already cached
+    pub async fn import_prepared_metadata(&self, prepared: Vec<(Vec<u8>, PreparedMetadata)>) {
+        let mut write_lock = self.prepared_metadata.write().await;
+        for (id, metadata) in prepared {
+            write_lock
+                .entry(CBytesShort::new(id))
+                .or_insert_with(|| Arc::new(metadata));
+        }
+    }
+
     pub fn build(&self) -> NodePool {
         NodePool {
             prepared_metadata: self.prepared_metadata.clone(),
@@ -84,17 +112,23 @@ impl NodePool {
         &self.nodes
     }
 
-    /// if the node list has been updated use the new list, copying over any existing connections
-    pub fn update_nodes(&mut self, nodes_rx: &mut watch::Receiver<Vec<CassandraNode>>) {
+    /// if the node list has been updated use the new list, copying over any existing connections.
+    /// Returns the connections to nodes that were removed, went down or are in maintenance.
+    pub fn update_nodes(
+        &mut self,
+        nodes_rx: &mut watch::Receiver<Vec<CassandraNode>>,
+    ) -> Vec<CassandraConnection> {
         let mut new_nodes = nodes_rx.borrow_and_update().clone();
+        let mut removed_connections = vec![];
 
         for node in self.nodes.drain(..) {
             if let Some(outbound) = node.outbound {
-                for new_node in &mut new_nodes {
-                    if new_node.host_id == node.host_id && new_node.is_up {
-                        new_node.outbound = Some(outbound);
-                        break;
-                    }
+                match new_nodes
+                    .iter_mut()
+                    .find(|new_node| new_node.host_id == node.host_id && new_node.is_available())
+                {
+                    Some(new_node) => new_node.outbound = Some(outbound),
+                    None => removed_connections.push(outbound),
                 }
             }
         }
@@ -105,6 +139,7 @@ impl NodePool {
             self.nodes,
             self.token_map
         );
+        removed_connections
     }
 
     pub fn update_keyspaces(&mut self, keyspaces_rx: &mut KeyspaceChanRx) {
@@ -126,7 +161,7 @@ impl NodePool {
         let mut nodes: Vec<_> = self
             .nodes
             .iter_mut()
-            .filter(|node| node.is_up && node.rack == *rack)
+            .filter(|node| node.is_available() && node.rack == *rack)
             .collect();
         nodes.shuffle(rng);
         get_accessible_node(connection_factory, nodes)
@@ -178,32 +213,60 @@ impl NodePool {
         rack: &str,
         rng: &mut SmallRng,
     ) -> Result<Vec<&mut CassandraNode>, GetReplicaErr> {
+        let (routing_key, keyspace) = self
+            .get_routing_info(
+                &execute.id,
+                execute.query_parameters.values.as_ref().ok_or_else(|| {
+                    GetReplicaErr::Other(anyhow!("Execute body does not have query parameters"))
+                })?,
+            )
+            .await?;
+
+        self.get_replica_node_for_token(routing_key, &keyspace, rack, rng)
+    }
+
+    /// Calculate the token and keyspace of a prepared statement executed with the supplied values
+    pub async fn get_routing_info(
+        &self,
+        id: &CBytesShort,
+        values: &QueryValues,
+    ) -> Result<(Murmur3Token, String), GetReplicaErr> {
         let metadata = {
             let read_lock = self.prepared_metadata.read().await;
             read_lock
-                .get(&execute.id)
+                .get(id)
                 .ok_or(GetReplicaErr::NoPreparedMetadata)?
                 .clone()
         };
 
+        let keyspace = metadata
+            .keyspace
+            .clone()
+            .ok_or(GetReplicaErr::NoKeyspaceMetadata)?;
+        if !self.keyspace_metadata.contains_key(&keyspace) {
+            return Err(GetReplicaErr::NoKeyspaceMetadata);
+        }
+
+        let routing_key = calculate_routing_key(&metadata.pk_indexes, values)
+            .ok_or(GetReplicaErr::NoRoutingKey)?;
+
+        Ok((routing_key, keyspace))
+    }
+
+    /// Get the replica nodes of the supplied token.
+    /// Nodes in the supplied rack are ordered first, followed by the rest of the nodes in the data center
+    pub fn get_replica_node_for_token(
+        &mut self,
+        routing_key: Murmur3Token,
+        keyspace: &str,
+        rack: &str,
+        rng: &mut SmallRng,
+    ) -> Result<Vec<&mut CassandraNode>, GetReplicaErr> {
         let keyspace = self
             .keyspace_metadata
-            .get(
-                metadata
-                    .keyspace
-                    .as_ref()
-                    .ok_or(GetReplicaErr::NoKeyspaceMetadata)?,
-            )
+            .get(keyspace)
             .ok_or(GetReplicaErr::NoKeyspaceMetadata)?;
 
-        let routing_key = calculate_routing_key(
-            &metadata.pk_indexes,
-            execute.query_parameters.values.as_ref().ok_or_else(|| {
-                GetReplicaErr::Other(anyhow!("Execute body does not have query parameters"))
-            })?,
-        )
-        .ok_or(GetReplicaErr::NoRoutingKey)?;
-
         let replica_host_ids = self
             .token_map
             .iter_replica_nodes(self.nodes(), routing_key, keyspace)
@@ -212,7 +275,7 @@ impl NodePool {
         let mut nodes: Vec<&mut CassandraNode> = self
             .nodes
             .iter_mut()
-            .filter(|node| replica_host_ids.contains(&node.host_id) && node.is_up)
+            .filter(|node| replica_host_ids.contains(&node.host_id) && node.is_available())
             .collect();
         nodes.shuffle(rng);
 
@@ -262,6 +325,27 @@ impl NodePool {
                     .expect("it is set to Some by get_accessible_node")
             })
     }
+
+    pub async fn get_replica_connection_for_token(
+        &mut self,
+        routing_key: Murmur3Token,
+        keyspace: &str,
+        rack: &str,
+        rng: &mut SmallRng,
+        connection_factory: &ConnectionFactory,
+    ) -> Result<&mut CassandraConnection, GetReplicaErr> {
+        let nodes = self.get_replica_node_for_token(routing_key, keyspace, rack, rng)?;
+
+        get_accessible_node(connection_factory, nodes)
+            .await
+            .context("Failed to open a connection to any replicas of a specific token")
+            .map_err(GetReplicaErr::NoNodeAvailable)
+            .map(|x| {
+                x.outbound
+                    .as_mut()
+                    .expect("it is set to Some by get_accessible_node")
+            })
+    }
 }
 
 pub struct AddressError {
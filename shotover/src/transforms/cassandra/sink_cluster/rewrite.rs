@@ -1,6 +1,7 @@
 use super::node::ConnectionFactory;
 use super::node_pool::NodePool;
 use super::ShotoverNode;
+use crate::frame::cassandra::{BatchStatement, BatchStatementType};
 use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
 use crate::message::{Message, MessageIdMap, Messages};
 use crate::{
@@ -11,8 +12,11 @@ use crate::{
     message::MessageId,
 };
 use anyhow::{anyhow, Result};
+use cassandra_protocol::frame::message_batch::BatchType;
 use cassandra_protocol::frame::message_result::BodyResResultPrepared;
 use cassandra_protocol::frame::Version;
+use cassandra_protocol::query::QueryValues;
+use cassandra_protocol::token::Murmur3Token;
 use cql3_parser::cassandra_statement::CassandraStatement;
 use cql3_parser::common::{
     FQNameRef, Identifier, IdentifierRef, Operand, RelationElement, RelationOperator,
@@ -57,6 +61,11 @@ pub struct MessageRewriter {
     pub local_shotover_node: ShotoverNode,
     pub to_rewrite: Vec<TableToRewrite>,
     pub prepare_requests_to_destination_nodes: MessageIdMap<Uuid>,
+    pub split_unlogged_batches: bool,
+    /// The token and keyspace that each batch created by splitting an unlogged batch should be routed by
+    pub batch_routing: MessageIdMap<(Murmur3Token, String)>,
+    /// Set once a warning has been logged for an unlogged batch that could not be split, so that it is only logged once per connection
+    pub warned_unsplit_batch: bool,
 }
 
 pub enum BatchMode {
@@ -118,7 +127,7 @@ impl MessageRewriter {
                 RewriteTableTy::Prepare { clone_index } => {
                     let mut first = true;
                     for node in pool.nodes().iter() {
-                        if node.is_up && node.rack == self.local_shotover_node.rack {
+                        if node.is_available() && node.rack == self.local_shotover_node.rack {
                             if first {
                                 let message_id = messages[*clone_index].id();
                                 self.prepare_requests_to_destination_nodes
@@ -157,9 +166,115 @@ impl MessageRewriter {
         }
         self.to_rewrite.extend(new_rewrites);
 
+        if self.split_unlogged_batches && self.split_batches(messages, pool).await? {
+            batch_mode = BatchMode::Isolated;
+        }
+
         Ok(batch_mode)
     }
 
+    /// Split each unlogged batch of prepared statements into a batch per partition so that each batch can be routed to a replica of its partition.
+    /// The original request keeps the statements of the first partition and a new request is inserted for each other partition.
+    /// Returns true if any new requests were inserted.
+    async fn split_batches(
+        &mut self,
+        messages: &mut Vec<Message>,
+        pool: &NodePool,
+    ) -> Result<bool> {
+        let mut inserted = false;
+        for i in 0..messages.len() {
+            let request_id = messages[i].id();
+            let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Batch(batch),
+                ..
+            })) = messages[i].frame()
+            else {
+                continue;
+            };
+            if !matches!(batch.ty, BatchType::Unlogged) {
+                continue;
+            }
+
+            // Statements are grouped by partition, preserving the order of first appearance of each partition.
+            let mut partitions: Vec<(Murmur3Token, String, Vec<BatchStatement>)> = vec![];
+            let mut unroutable = None;
+            for statement in &batch.queries {
+                let BatchStatementType::PreparedId(id) = &statement.ty else {
+                    unroutable = Some("it contains a statement that is not prepared");
+                    break;
+                };
+                // Only positional values can be matched up with the partition key indexes of the prepared metadata
+                if !matches!(statement.values, QueryValues::SimpleValues(_)) {
+                    unroutable = Some("it contains a statement bound with named values");
+                    break;
+                }
+                let Ok((token, keyspace)) = pool.get_routing_info(id, &statement.values).await
+                else {
+                    unroutable =
+                        Some("the partition of one of its statements could not be determined");
+                    break;
+                };
+                match partitions
+                    .iter_mut()
+                    .find(|(t, k, _)| t.value == token.value && k == &keyspace)
+                {
+                    Some((_, _, statements)) => statements.push(statement.clone()),
+                    None => partitions.push((token, keyspace, vec![statement.clone()])),
+                }
+            }
+            if let Some(reason) = unroutable {
+                if !self.warned_unsplit_batch {
+                    self.warned_unsplit_batch = true;
+                    tracing::warn!("An unlogged batch was sent unchanged instead of being split by partition because {reason}. Further batches on this connection that cannot be split will not be reported.");
+                }
+                continue;
+            }
+            if partitions.is_empty() {
+                continue;
+            }
+
+            let (token, keyspace, statements) = partitions.remove(0);
+            if partitions.is_empty() {
+                // All statements belong to a single partition, route the batch as is.
+                self.batch_routing.insert(request_id, (token, keyspace));
+                continue;
+            }
+
+            batch.queries = statements;
+            messages[i].invalidate_cache();
+            let template = messages[i].clone();
+            self.batch_routing.insert(request_id, (token, keyspace));
+
+            let mut collected_messages = vec![MessageOrId::Id(request_id)];
+            for (token, keyspace, statements) in partitions {
+                let stream_id = get_unused_stream_id(messages)?;
+                let mut message = template.clone_with_new_id();
+                if let Some(Frame::Cassandra(frame)) = message.frame() {
+                    frame.stream_id = stream_id;
+                    if let CassandraOperation::Batch(batch) = &mut frame.operation {
+                        batch.queries = statements;
+                    }
+                }
+                message.invalidate_cache();
+                self.batch_routing.insert(message.id(), (token, keyspace));
+                collected_messages.push(MessageOrId::Id(message.id()));
+                messages.push(message);
+            }
+            self.to_rewrite.push(TableToRewrite {
+                collected_messages,
+                ty: RewriteTableTy::SplitBatch,
+                warnings: vec![],
+                selects: vec![],
+            });
+            inserted = true;
+        }
+        Ok(inserted)
+    }
+
+    pub fn get_routing_for_batch(&mut self, message: &Message) -> Option<(Murmur3Token, String)> {
+        self.batch_routing.remove(&message.id())
+    }
+
     /// Returns any information required to correctly rewrite the response.
     /// Will also perform minor modifications to the query required for the rewrite.
     fn get_rewrite_table(
@@ -245,14 +360,6 @@ impl MessageRewriter {
         table: &mut TableToRewrite,
         responses: &mut Vec<Message>,
     ) -> Result<bool> {
-        fn get_warnings(message: &mut Message) -> Vec<String> {
-            if let Some(Frame::Cassandra(frame)) = message.frame() {
-                frame.warnings.clone()
-            } else {
-                vec![]
-            }
-        }
-
         for message_or_id in table.collected_messages.iter_mut() {
             if let MessageOrId::Id(id) = message_or_id {
                 if let Some(i) = responses.iter().position(|x| x.request_id() == Some(*id)) {
@@ -386,6 +493,9 @@ impl MessageRewriter {
                     }
                     responses.push(collected_messages.remove(0));
                 }
+                RewriteTableTy::SplitBatch => {
+                    responses.push(merge_split_batch_responses(collected_messages));
+                }
             }
             Ok(true)
         } else {
@@ -701,6 +811,40 @@ fn get_unused_stream_id(messages: &Messages) -> Result<i16> {
     Err(anyhow!("Ran out of stream ids"))
 }
 
+fn get_warnings(message: &mut Message) -> Vec<String> {
+    if let Some(Frame::Cassandra(frame)) = message.frame() {
+        frame.warnings.clone()
+    } else {
+        vec![]
+    }
+}
+
+/// Merges the responses to the batches that an unlogged batch was split into, the first being the response to the client's original request.
+/// If any of the batches failed, the first failure is reported to the client under the original stream_id.
+/// When some batches failed and others succeeded a warning is added, as the batch was then only partially written.
+fn merge_split_batch_responses(mut responses: Vec<Message>) -> Message {
+    let mut warnings: Vec<String> = responses.iter_mut().flat_map(get_warnings).collect();
+    let stream_id = responses[0].stream_id();
+    let failed: Vec<bool> = responses.iter_mut().map(|x| x.is_error()).collect();
+    let first_failure = failed.iter().position(|failed| *failed);
+    if first_failure.is_some() && failed.contains(&false) {
+        warnings.push(
+            "Shotover: The batch was split by partition and only some of the partitions were written"
+                .to_owned(),
+        );
+    }
+
+    let mut response = responses.swap_remove(first_failure.unwrap_or(0));
+    if let Some(Frame::Cassandra(frame)) = response.frame() {
+        if let Some(stream_id) = stream_id {
+            frame.stream_id = stream_id;
+        }
+        frame.warnings = warnings;
+    }
+    response.invalidate_cache();
+    response
+}
+
 fn has_no_where_clause(ty: &RewriteTableTy, select: &Select) -> bool {
     select.where_clause.is_empty()
         // Most drivers do `FROM system.local WHERE key = 'local'` when determining the topology.
@@ -736,6 +880,7 @@ pub enum RewriteTableTy {
     Peers,
     // We need to know which nodes we are sending to early on so that we can create the appropriate number of messages early and keep our rewrite indexes intact
     Prepare { clone_index: usize },
+    SplitBatch,
 }
 
 struct NodeInfo {
@@ -834,3 +979,82 @@ fn parse_system_nodes(mut response: Message) -> Result<Vec<NodeInfo>, MessagePar
         )))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::merge_split_batch_responses;
+    use crate::frame::cassandra::Tracing;
+    use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
+    use crate::message::Message;
+    use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+    use cassandra_protocol::frame::Version;
+    use pretty_assertions::assert_eq;
+
+    const PARTIAL_WRITE: &str =
+        "Shotover: The batch was split by partition and only some of the partitions were written";
+
+    fn frame(stream_id: i16, error: Option<&str>, warnings: &[&str]) -> Frame {
+        Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id,
+            tracing: Tracing::Response(None),
+            warnings: warnings.iter().map(|x| x.to_string()).collect(),
+            operation: match error {
+                Some(message) => CassandraOperation::Error(ErrorBody {
+                    message: message.to_owned(),
+                    ty: ErrorType::Server,
+                }),
+                None => CassandraOperation::Result(CassandraResult::Void),
+            },
+        })
+    }
+
+    /// The responses to the client's original batch on stream 1 and the batches split from it on streams 2 and 3
+    fn responses(errors: [Option<&str>; 3]) -> Vec<Message> {
+        errors
+            .into_iter()
+            .enumerate()
+            .map(|(i, error)| Message::from_frame(frame(i as i16 + 1, error, &[])))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_batch_all_succeed() {
+        let mut responses = responses([None, None, None]);
+        if let Some(Frame::Cassandra(frame)) = responses[2].frame() {
+            frame.warnings = vec!["warning from a split batch".to_owned()];
+        }
+
+        let mut response = merge_split_batch_responses(responses);
+        assert_eq!(
+            response.frame().unwrap(),
+            &frame(1, None, &["warning from a split batch"])
+        );
+    }
+
+    #[test]
+    fn test_split_batch_first_fails() {
+        let mut response = merge_split_batch_responses(responses([Some("first"), None, None]));
+        assert_eq!(
+            response.frame().unwrap(),
+            &frame(1, Some("first"), &[PARTIAL_WRITE])
+        );
+    }
+
+    #[test]
+    fn test_split_batch_later_fails() {
+        let mut response = merge_split_batch_responses(responses([None, None, Some("third")]));
+        assert_eq!(
+            response.frame().unwrap(),
+            &frame(1, Some("third"), &[PARTIAL_WRITE])
+        );
+    }
+
+    #[test]
+    fn test_split_batch_all_fail() {
+        // Nothing was written so there is no partial write to warn about
+        let mut response =
+            merge_split_batch_responses(responses([Some("first"), Some("second"), Some("third")]));
+        assert_eq!(response.frame().unwrap(), &frame(1, Some("first"), &[]));
+    }
+}
@@ -8,37 +8,54 @@ use crate::frame::{
     CassandraFrame, CassandraOperation, CassandraResult, Frame,
 };
 use crate::message::Message;
+use crate::transforms::cassandra::cluster_metadata::{
+    self, ClusterMetadata, ColumnSchema, KeyspaceSchema, NodeMetadata, TableSchema,
+};
 use anyhow::{anyhow, Result};
 use cassandra_protocol::events::{ServerEvent, SimpleServerEvent};
 use cassandra_protocol::frame::events::{StatusChangeType, TopologyChangeType};
 use cassandra_protocol::frame::message_register::BodyReqRegister;
 use cassandra_protocol::frame::Version;
 use cassandra_protocol::token::Murmur3Token;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, watch, Notify};
 
+/// How often the system tables are queried in case the cluster failed to send an event, or the event was lost
+const METADATA_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The tables of each keyspace, keyed by keyspace name
+type Tables = HashMap<String, BTreeMap<String, TableSchema>>;
+
 #[derive(Debug)]
 pub struct TaskConnectionInfo {
     pub connection_factory: ConnectionFactory,
     pub address: SocketAddr,
 }
 
+/// `learned` is set once the task has been handed a connection to learn the topology from,
+/// after which the topology must only be sent by the task.
 pub fn create_topology_task(
-    nodes_tx: watch::Sender<Vec<CassandraNode>>,
-    keyspaces_tx: KeyspaceChanTx,
+    nodes_tx: Arc<watch::Sender<Vec<CassandraNode>>>,
+    keyspaces_tx: Arc<KeyspaceChanTx>,
     mut connection_info_rx: mpsc::Receiver<TaskConnectionInfo>,
+    learned: Arc<AtomicBool>,
     data_center: String,
+    chain_name: String,
 ) {
     tokio::spawn(async move {
         while let Some(mut connection_info) = connection_info_rx.recv().await {
+            learned.store(true, Ordering::Relaxed);
             let mut attempts = 0;
             match topology_task_process(
                 &nodes_tx,
                 &keyspaces_tx,
                 &mut connection_info,
                 &data_center,
+                &chain_name,
             )
             .await
             {
@@ -64,6 +81,7 @@ async fn topology_task_process(
     keyspaces_tx: &KeyspaceChanTx,
     connection_info: &mut TaskConnectionInfo,
     data_center: &str,
+    chain_name: &str,
 ) -> Result<()> {
     let force_run_chain = Arc::new(Notify::new());
     connection_info
@@ -83,13 +101,16 @@ async fn topology_task_process(
         return Ok(());
     }
 
-    let keyspaces = system_keyspaces::query(&mut connection, data_center, version)
-        .await?
-        .0;
-    if let Err(watch::error::SendError(_)) = keyspaces_tx.send(keyspaces) {
+    let (mut keyspaces, mut events) =
+        system_keyspaces::query(&mut connection, data_center, version).await?;
+    if let Err(watch::error::SendError(_)) = keyspaces_tx.send(keyspaces.clone()) {
         return Ok(());
     }
 
+    let (mut tables, extra_events) = system_schema_columns::query(&mut connection, version).await?;
+    events.extend(extra_events);
+    publish_metadata(chain_name, &nodes, &keyspaces, &tables);
+
     register_for_topology_and_status_events(&mut connection, version).await?;
 
     tracing::info!(
@@ -97,7 +118,9 @@ async fn topology_task_process(
         connection_info.address
     );
 
-    let mut events = vec![];
+    let mut refresh = tokio::time::interval(METADATA_REFRESH_INTERVAL);
+    // the first tick completes immediately but everything was just queried
+    refresh.tick().await;
     loop {
         if events.is_empty() {
             // Wait for events to come in from the cassandra node.
@@ -107,6 +130,34 @@ async fn topology_task_process(
                     Ok(()) => {}
                     Err(err) => return Err(anyhow!(err).context("topology control connection was closed")),
                 },
+                _ = refresh.tick() => {
+                    let mut new_nodes =
+                        fetch_current_nodes(&mut connection, connection_info, data_center, version)
+                            .await?;
+                    carry_over_is_up(&nodes, &mut new_nodes);
+                    if !same_nodes(&nodes, &new_nodes) {
+                        nodes = new_nodes;
+                        if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
+                            return Ok(());
+                        }
+                    }
+
+                    let (new_keyspaces, extra_events) =
+                        system_keyspaces::query(&mut connection, data_center, version).await?;
+                    events.extend(extra_events);
+                    if new_keyspaces != keyspaces {
+                        keyspaces = new_keyspaces;
+                        if let Err(watch::error::SendError(_)) = keyspaces_tx.send(keyspaces.clone()) {
+                            return Ok(());
+                        }
+                    }
+
+                    let (new_tables, extra_events) =
+                        system_schema_columns::query(&mut connection, version).await?;
+                    events.extend(extra_events);
+                    tables = new_tables;
+                    publish_metadata(chain_name, &nodes, &keyspaces, &tables);
+                }
                 _ = nodes_tx.closed() => return Ok(())
             };
         }
@@ -127,22 +178,14 @@ async fn topology_task_process(
                             )
                             .await?;
 
-                            // is_up state gets carried over to new list
-                            for node in &nodes {
-                                if !node.is_up {
-                                    for new_node in &mut new_nodes {
-                                        if new_node.address == node.address {
-                                            new_node.is_up = false;
-                                        }
-                                    }
-                                }
-                            }
+                            carry_over_is_up(&nodes, &mut new_nodes);
 
                             nodes = new_nodes;
 
                             if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
                                 return Ok(());
                             }
+                            publish_metadata(chain_name, &nodes, &keyspaces, &tables);
                         }
                         TopologyChangeType::RemovedNode => {
                             nodes.retain(|node| node.address != topology.addr);
@@ -150,6 +193,7 @@ async fn topology_task_process(
                             if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
                                 return Ok(());
                             }
+                            publish_metadata(chain_name, &nodes, &keyspaces, &tables);
                         }
                         _ => unreachable!(),
                     },
@@ -166,14 +210,23 @@ async fn topology_task_process(
                         if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
                             return Ok(());
                         }
+                        publish_metadata(chain_name, &nodes, &keyspaces, &tables);
                     }
                     ServerEvent::SchemaChange(_change) => {
-                        let (keyspaces, extra_events) =
+                        let (new_keyspaces, extra_events) =
                             system_keyspaces::query(&mut connection, data_center, version).await?;
+                        keyspaces = new_keyspaces;
                         events.extend(extra_events);
-                        if let Err(watch::error::SendError(_)) = keyspaces_tx.send(keyspaces) {
+                        if let Err(watch::error::SendError(_)) =
+                            keyspaces_tx.send(keyspaces.clone())
+                        {
                             return Ok(());
                         }
+                        let (new_tables, extra_events) =
+                            system_schema_columns::query(&mut connection, version).await?;
+                        tables = new_tables;
+                        events.extend(extra_events);
+                        publish_metadata(chain_name, &nodes, &keyspaces, &tables);
                     }
                     _ => unreachable!(),
                 }
@@ -182,6 +235,70 @@ async fn topology_task_process(
     }
 }
 
+/// The is_up state of nodes is only known from events, so it gets carried over to a newly queried list of nodes
+fn carry_over_is_up(nodes: &[CassandraNode], new_nodes: &mut [CassandraNode]) {
+    for node in nodes {
+        if !node.is_up {
+            for new_node in new_nodes.iter_mut() {
+                if new_node.address == node.address {
+                    new_node.is_up = false;
+                }
+            }
+        }
+    }
+}
+
+fn same_nodes(a: &[CassandraNode], b: &[CassandraNode]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.address == b.address
+                && a.rack == b.rack
+                && a.host_id == b.host_id
+                && a.is_up == b.is_up
+                && a.tokens.len() == b.tokens.len()
+                && a.tokens
+                    .iter()
+                    .zip(&b.tokens)
+                    .all(|(a, b)| a.value == b.value)
+        })
+}
+
+fn publish_metadata(
+    chain_name: &str,
+    nodes: &[CassandraNode],
+    keyspaces: &HashMap<String, KeyspaceMetadata>,
+    tables: &Tables,
+) {
+    cluster_metadata::publish(
+        chain_name,
+        ClusterMetadata {
+            nodes: nodes
+                .iter()
+                .map(|node| NodeMetadata {
+                    address: node.address,
+                    rack: node.rack.clone(),
+                    host_id: node.host_id,
+                    is_up: node.is_up,
+                    tokens: node.tokens.iter().map(|token| token.value).collect(),
+                })
+                .collect(),
+            keyspaces: keyspaces
+                .iter()
+                .map(|(name, keyspace)| {
+                    (
+                        name.clone(),
+                        KeyspaceSchema {
+                            replication_factor: keyspace.replication_factor,
+                            replication_strategy: format!("{:?}", keyspace.replication_strategy),
+                            tables: tables.get(name).cloned().unwrap_or_default(),
+                        },
+                    )
+                })
+                .collect(),
+        },
+    );
+}
+
 async fn register_for_topology_and_status_events(
     connection: &mut SinkConnection,
     version: Version,
@@ -364,6 +481,70 @@ mod system_keyspaces {
     }
 }
 
+mod system_schema_columns {
+    use super::*;
+
+    pub async fn query(
+        connection: &mut SinkConnection,
+        version: Version,
+    ) -> Result<(Tables, Vec<Message>)> {
+        let (response, extra_events) = super::send_recv(
+            connection,
+            Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version,
+                stream_id: 0,
+                tracing: Tracing::Request(false),
+                warnings: vec![],
+                operation: CassandraOperation::Query {
+                    query: Box::new(parse_statement_single(
+                        "SELECT keyspace_name, table_name, column_name, kind, type FROM system_schema.columns",
+                    )),
+                    params: Box::default(),
+                },
+            })),
+        )
+        .await?;
+        into_tables(response).map(|x| (x, extra_events))
+    }
+
+    fn into_tables(mut response: Message) -> Result<Tables> {
+        let Some(Frame::Cassandra(frame)) = response.frame() else {
+            return Err(anyhow!(
+                "Failed to parse system_schema.columns query response"
+            ));
+        };
+        let CassandraOperation::Result(CassandraResult::Rows { rows, .. }) = &mut frame.operation
+        else {
+            return Err(anyhow!(
+                "system_schema.columns query returned unexpected cassandra operation: {:?}",
+                frame.operation
+            ));
+        };
+
+        let mut tables = Tables::new();
+        for row in rows.drain(..) {
+            let row = row
+                .into_iter()
+                .map(|value| match value {
+                    GenericValue::Varchar(value) => Ok(value),
+                    _ => Err(anyhow!("system_schema.columns values should be varchars")),
+                })
+                .collect::<Result<Vec<String>>>()?;
+            let [keyspace, table, name, kind, ty]: [String; 5] = row
+                .try_into()
+                .map_err(|_| anyhow!("system_schema.columns query should return 5 columns"))?;
+            tables
+                .entry(keyspace)
+                .or_default()
+                .entry(table)
+                .or_default()
+                .columns
+                .push(ColumnSchema { name, kind, ty });
+        }
+        Ok(tables)
+    }
+}
+
 async fn send_recv(
     connection: &mut SinkConnection,
     request: Message,
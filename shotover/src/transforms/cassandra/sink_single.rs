@@ -27,6 +27,7 @@ pub struct CassandraSinkSingleConfig {
     pub tls: Option<TlsConnectorConfig>,
     pub connect_timeout_ms: u64,
     pub read_timeout: Option<u64>,
+    pub keepalive_interval: Option<u64>,
 }
 
 const NAME: &str = "CassandraSinkSingle";
@@ -44,6 +45,7 @@ impl TransformConfig for CassandraSinkSingleConfig {
             tls,
             self.connect_timeout_ms,
             self.read_timeout,
+            self.keepalive_interval,
         )))
     }
 
@@ -63,6 +65,7 @@ pub struct CassandraSinkSingleBuilder {
     tls: Option<TlsConnector>,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     codec_builder: CassandraCodecBuilder,
 }
 
@@ -73,6 +76,7 @@ impl CassandraSinkSingleBuilder {
         tls: Option<TlsConnector>,
         connect_timeout_ms: u64,
         timeout: Option<u64>,
+        keepalive_interval: Option<u64>,
     ) -> CassandraSinkSingleBuilder {
         let failed_requests = counter!("shotover_failed_requests_count", "chain" => chain_name, "transform" => "CassandraSinkSingle");
         let receive_timeout = timeout.map(Duration::from_secs);
@@ -86,6 +90,7 @@ impl CassandraSinkSingleBuilder {
             tls,
             connect_timeout: Duration::from_millis(connect_timeout_ms),
             read_timeout: receive_timeout,
+            keepalive_interval: keepalive_interval.map(Duration::from_secs),
             codec_builder,
         }
     }
@@ -101,6 +106,7 @@ impl TransformBuilder for CassandraSinkSingleBuilder {
             failed_requests: self.failed_requests.clone(),
             connect_timeout: self.connect_timeout,
             read_timeout: self.read_timeout,
+            keepalive_interval: self.keepalive_interval,
             codec_builder: self.codec_builder.clone(),
             force_run_chain: transform_context.force_run_chain,
         })
@@ -123,6 +129,7 @@ pub struct CassandraSinkSingle {
     tls: Option<TlsConnector>,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     codec_builder: CassandraCodecBuilder,
     force_run_chain: Arc<Notify>,
 }
@@ -158,6 +165,7 @@ impl CassandraSinkSingle {
                     self.connect_timeout,
                     self.force_run_chain.clone(),
                     self.read_timeout,
+                    self.keepalive_interval,
                 )
                 .await?,
             );
@@ -1,6 +1,9 @@
+use super::guarantees::DeliveryGuarantees;
 use super::TransformContextBuilder;
 use crate::message::Messages;
-use crate::transforms::{Transform, TransformBuilder, Wrapper};
+use crate::panics::{self, TransformPanic};
+use crate::transforms::{ConnectionEvent, Transform, TransformBuilder, Wrapper};
+use crate::tuning;
 use anyhow::{anyhow, Result};
 use futures::TryFutureExt;
 use metrics::{counter, histogram, Counter, Histogram};
@@ -162,6 +165,7 @@ impl TransformChain {
         wrapper.reset(&mut self.chain);
 
         self.chain_batch_size.record(wrapper.requests.len() as f64);
+        let observations = tuning::observe(&mut wrapper.requests);
         let result = wrapper.call_next_transform().await;
         self.chain_total.increment(1);
         if result.is_err() {
@@ -169,14 +173,52 @@ impl TransformChain {
         }
 
         self.chain_latency_seconds.record(start.elapsed());
+        if let Some(observations) = observations {
+            tuning::record_batch(self.name, observations, start.elapsed());
+        }
+        result
+    }
+
+    /// Notifies every transform in the chain of the event, in chain order.
+    /// Stops at the first transform that returns `Err`, except for [`ConnectionEvent::Closed`] which is always delivered to every transform.
+    pub async fn process_connection_event(&mut self, event: ConnectionEvent) -> Result<()> {
+        let mut result = Ok(());
+        for transform in &mut self.chain {
+            let name = transform.transform.get_name();
+            let transform_result = panics::catch_unwind(
+                self.name,
+                name,
+                transform.transform.on_connection_event(event),
+            )
+            .await
+            .unwrap_or_else(|panic| {
+                transform.transform_panics.increment(1);
+                Err(panic.into())
+            });
+            if let Err(err) = transform_result {
+                let err = err.context(format!(
+                    "{} transform failed to handle connection event {event:?}",
+                    transform.transform.get_name()
+                ));
+                if event != ConnectionEvent::Closed {
+                    return Err(err);
+                }
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
         result
     }
 }
 
 pub struct TransformAndMetrics {
     pub transform: Box<dyn Transform>,
+    /// The name of the chain the transform is in
+    pub chain: &'static str,
     pub transform_total: Counter,
     pub transform_failures: Counter,
+    pub transform_panics: Counter,
     pub transform_latency: Histogram,
 }
 
@@ -185,8 +227,10 @@ impl TransformAndMetrics {
     pub fn new(transform: Box<dyn Transform>) -> Self {
         TransformAndMetrics {
             transform,
+            chain: "test",
             transform_total: Counter::noop(),
             transform_failures: Counter::noop(),
+            transform_panics: Counter::noop(),
             transform_latency: Histogram::noop(),
         }
     }
@@ -196,15 +240,18 @@ pub struct TransformBuilderAndMetrics {
     pub builder: Box<dyn TransformBuilder>,
     transform_total: Counter,
     transform_failures: Counter,
+    transform_panics: Counter,
     transform_latency: Histogram,
 }
 
 impl TransformBuilderAndMetrics {
-    fn build(&self, context: TransformContextBuilder) -> TransformAndMetrics {
+    fn build(&self, chain: &'static str, context: TransformContextBuilder) -> TransformAndMetrics {
         TransformAndMetrics {
             transform: self.builder.build(context),
+            chain,
             transform_total: self.transform_total.clone(),
             transform_failures: self.transform_failures.clone(),
+            transform_panics: self.transform_panics.clone(),
             transform_latency: self.transform_latency.clone(),
         }
     }
@@ -225,6 +272,7 @@ impl TransformChainBuilder {
             TransformBuilderAndMetrics {
                 transform_total: counter!("shotover_transform_total_count", "transform" => builder.get_name()),
                 transform_failures: counter!("shotover_transform_failures_count", "transform" => builder.get_name()),
+                transform_panics: counter!("shotover_transform_panics_count", "transform" => builder.get_name()),
                 transform_latency: histogram!("shotover_transform_latency_seconds", "transform" => builder.get_name()),
                 builder,
             }
@@ -246,6 +294,12 @@ impl TransformChainBuilder {
     }
 
     pub fn validate(&self) -> Vec<String> {
+        self.validate_with_up_chain(DeliveryGuarantees::PASSTHROUGH)
+    }
+
+    /// Validates a chain that receives requests with the `up_chain` delivery guarantees,
+    /// used by transforms that change the guarantees of the requests they send to their subchains.
+    pub fn validate_with_up_chain(&self, up_chain: DeliveryGuarantees) -> Vec<String> {
         if self.chain.is_empty() {
             return vec![
                 format!("{} chain:", self.name),
@@ -254,6 +308,7 @@ impl TransformChainBuilder {
         }
 
         let last_index = self.chain.len() - 1;
+        let mut guarantees = up_chain;
 
         let mut errors = self
             .chain
@@ -274,6 +329,16 @@ impl TransformChainBuilder {
                     ));
                 }
 
+                let unmet = guarantees.unmet_requirements(&transform.builder.required_guarantees());
+                if !unmet.is_empty() {
+                    errors.push(format!(
+                        "  Transform {:?} requires {} but the transforms up chain of it only guarantee {guarantees} delivery.",
+                        transform.builder.get_name(),
+                        unmet.join(" and ")
+                    ));
+                }
+                guarantees = guarantees.then(transform.builder.delivery_guarantees());
+
                 errors.extend(transform.builder.validate().iter().map(|x| format!("  {x}")));
 
                 errors
@@ -287,6 +352,15 @@ impl TransformChainBuilder {
         errors
     }
 
+    /// The delivery guarantees of the whole chain.
+    pub fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        self.chain
+            .iter()
+            .fold(DeliveryGuarantees::PASSTHROUGH, |guarantees, transform| {
+                guarantees.then(transform.builder.delivery_guarantees())
+            })
+    }
+
     pub fn build_buffered(
         &self,
         buffer_size: usize,
@@ -321,8 +395,14 @@ impl TransformChainBuilder {
                     wrapper.flush = flush;
                     let chain_response = chain.process_request(wrapper).await;
 
-                    if let Err(e) = &chain_response {
-                        error!("Internal error in buffered chain: {e:?}");
+                    // A panic may have left a transform in an inconsistent state, so stop using the chain.
+                    // Callers will then fail to send to the chain, closing their own connections.
+                    let panicked = match &chain_response {
+                        Err(e) => {
+                            error!("Internal error in buffered chain: {e:?}");
+                            TransformPanic::find(e).is_some()
+                        }
+                        Ok(_) => false,
                     };
 
                     match return_chan {
@@ -333,6 +413,11 @@ impl TransformChainBuilder {
                             }
                         }
                     };
+
+                    if panicked {
+                        error!("Buffered chain {} is shutting down because a transform in it panicked", chain.name);
+                        return;
+                    }
                 }
 
                 debug!("buffered chain processing thread exiting, stopping chain loop and dropping");
@@ -363,7 +448,7 @@ impl TransformChainBuilder {
         let chain = self
             .chain
             .iter()
-            .map(|x| x.build(context.clone()))
+            .map(|x| x.build(self.name, context.clone()))
             .collect();
 
         TransformChain {
@@ -383,11 +468,77 @@ impl TransformChainBuilder {
 
 #[cfg(test)]
 mod chain_tests {
+    use crate::message::Messages;
+    use crate::panics::{self, PanicCount, TransformPanic};
     use crate::transforms::chain::TransformChainBuilder;
     use crate::transforms::debug::printer::DebugPrinter;
+    use crate::transforms::guarantees::DeliveryGuarantees;
     use crate::transforms::null::NullSink;
+    use crate::transforms::{Transform, TransformBuilder, TransformContextBuilder, Wrapper};
+    use anyhow::Result;
+    use async_trait::async_trait;
     use pretty_assertions::assert_eq;
 
+    struct PanicSink;
+
+    impl TransformBuilder for PanicSink {
+        fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+            Box::new(PanicSink)
+        }
+
+        fn get_name(&self) -> &'static str {
+            "PanicSink"
+        }
+
+        fn is_terminating(&self) -> bool {
+            true
+        }
+    }
+
+    #[async_trait]
+    impl Transform for PanicSink {
+        fn get_name(&self) -> &'static str {
+            "PanicSink"
+        }
+
+        async fn transform<'a>(&'a mut self, _requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+            panic!("the sink fell over")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panic_is_isolated_to_chain() {
+        let builder = TransformChainBuilder::new(
+            vec![Box::<DebugPrinter>::default(), Box::new(PanicSink)],
+            "panicking-chain",
+        );
+        let mut chain = builder.build(TransformContextBuilder::new_test());
+
+        for _ in 0..2 {
+            let err = chain
+                .process_request(Wrapper::new_test(vec![]))
+                .await
+                .unwrap_err();
+            let panic = TransformPanic::find(&err).unwrap();
+            assert_eq!(panic.transform, "PanicSink");
+            assert_eq!(panic.message, "the sink fell over");
+        }
+
+        let report: Vec<_> = panics::report()
+            .into_iter()
+            .filter(|count| count.chain == "panicking-chain")
+            .collect();
+        assert_eq!(
+            report,
+            vec![PanicCount {
+                chain: "panicking-chain",
+                transform: "PanicSink",
+                count: 2,
+                last_message: "the sink fell over".to_owned(),
+            }]
+        );
+    }
+
     #[tokio::test]
     async fn test_validate_invalid_chain() {
         let chain = TransformChainBuilder::new(vec![], "test-chain");
@@ -409,4 +560,16 @@ mod chain_tests {
         );
         assert_eq!(chain.validate(), Vec::<String>::new());
     }
+
+    #[tokio::test]
+    async fn test_delivery_guarantees() {
+        let chain = TransformChainBuilder::new(
+            vec![Box::<DebugPrinter>::default(), Box::<NullSink>::default()],
+            "test-chain",
+        );
+        assert_eq!(
+            chain.delivery_guarantees(),
+            DeliveryGuarantees::AT_MOST_ONCE
+        );
+    }
 }
@@ -1,4 +1,5 @@
 use crate::message::{Message, Messages};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{
     DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
     TransformContextConfig, UpChainProtocol, Wrapper,
@@ -64,6 +65,11 @@ impl TransformBuilder for DebugReturner {
         NAME
     }
 
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Every request is answered without being delivered
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
     fn is_terminating(&self) -> bool {
         true
     }
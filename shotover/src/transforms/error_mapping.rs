@@ -0,0 +1,344 @@
+#[cfg(feature = "cassandra")]
+use crate::frame::CassandraOperation;
+#[cfg(feature = "kafka")]
+use crate::frame::KafkaFrame;
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, Messages};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+#[cfg(feature = "kafka")]
+use kafka_protocol::messages::ResponseBody;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorMappingConfig {
+    /// Applied in order to every error response, each rule sees the error as modified by the rules before it
+    pub rules: Vec<ErrorRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum ErrorRule {
+    /// Replaces every IPv4 address, with or without a port, in the message of redis and cassandra errors
+    MaskAddresses { replacement: String },
+    /// Replaces the whole message of redis and cassandra errors whose message contains `contains`
+    RewriteMessage { contains: String, message: String },
+    /// Replaces the error codes in `from` with `to` in the partitions of kafka produce and fetch responses
+    #[cfg(feature = "kafka")]
+    KafkaErrorCode {
+        from: Vec<i16>,
+        to: i16,
+        /// When set, the throttle time of the response is raised to at least this value whenever an error code is replaced
+        #[serde(default)]
+        throttle_time_ms: Option<i32>,
+    },
+}
+
+const NAME: &str = "ErrorMapping";
+#[typetag::serde(name = "ErrorMapping")]
+#[async_trait(?Send)]
+impl TransformConfig for ErrorMappingConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(ErrorMappingBuilder {
+            rules: Arc::new(self.rules.clone()),
+            mapped: counter!(
+                "shotover_error_mappings_count",
+                "chain" => transform_context.chain_name
+            ),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+            #[cfg(feature = "kafka")]
+            MessageType::Kafka,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+pub struct ErrorMappingBuilder {
+    rules: Arc<Vec<ErrorRule>>,
+    mapped: Counter,
+}
+
+impl TransformBuilder for ErrorMappingBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(ErrorMapping {
+            rules: self.rules.clone(),
+            mapped: self.mapped.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.rules.is_empty() {
+            errors.push("  rules must not be empty".to_owned());
+        }
+        for rule in self.rules.iter() {
+            if let ErrorRule::RewriteMessage { contains, .. } = rule {
+                if contains.is_empty() {
+                    errors.push("  RewriteMessage contains must not be empty".to_owned());
+                }
+            }
+        }
+        if !errors.is_empty() {
+            errors.insert(0, format!("{NAME}:"));
+        }
+        errors
+    }
+}
+
+/// Translates the errors returned by the database into the errors clients of the chain should see,
+/// such as hiding the addresses of internal nodes or turning retriable errors into a throttle.
+/// Responses that are not errors are passed up the chain unmodified.
+pub struct ErrorMapping {
+    rules: Arc<Vec<ErrorRule>>,
+    mapped: Counter,
+}
+
+#[async_trait]
+impl Transform for ErrorMapping {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let mut responses = requests_wrapper.call_next_transform().await?;
+        for response in &mut responses {
+            if map_response(&self.rules, response) {
+                response.invalidate_cache();
+                self.mapped.increment(1);
+            }
+        }
+        Ok(responses)
+    }
+}
+
+/// Applies the rules to the response, returning true if it was modified
+fn map_response(rules: &[ErrorRule], response: &mut Message) -> bool {
+    match response.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(RedisFrame::Error(message))) => match map_message(rules, message) {
+            Some(mapped) => {
+                *message = mapped.into();
+                true
+            }
+            None => false,
+        },
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(frame)) => match &mut frame.operation {
+            CassandraOperation::Error(error) => match map_message(rules, &error.message) {
+                Some(mapped) => {
+                    error.message = mapped;
+                    true
+                }
+                None => false,
+            },
+            _ => false,
+        },
+        #[cfg(feature = "kafka")]
+        Some(Frame::Kafka(KafkaFrame::Response { body, .. })) => {
+            let mut modified = false;
+            for rule in rules {
+                if let ErrorRule::KafkaErrorCode {
+                    from,
+                    to,
+                    throttle_time_ms,
+                } = rule
+                {
+                    modified |= map_kafka_error_codes(body, from, *to, *throttle_time_ms);
+                }
+            }
+            modified
+        }
+        _ => false,
+    }
+}
+
+/// Applies the message rules to an error message, returning the new message if any rule applied
+fn map_message(rules: &[ErrorRule], message: &str) -> Option<String> {
+    let mut mapped: Option<String> = None;
+    for rule in rules {
+        let current = mapped.as_deref().unwrap_or(message);
+        let next = match rule {
+            ErrorRule::MaskAddresses { replacement } => mask_addresses(current, replacement),
+            ErrorRule::RewriteMessage { contains, message } => {
+                current.contains(contains.as_str()).then(|| message.clone())
+            }
+            #[cfg(feature = "kafka")]
+            ErrorRule::KafkaErrorCode { .. } => None,
+        };
+        if next.is_some() {
+            mapped = next;
+        }
+    }
+    mapped
+}
+
+#[cfg(feature = "kafka")]
+fn map_kafka_error_codes(
+    body: &mut ResponseBody,
+    from: &[i16],
+    to: i16,
+    throttle_time_ms: Option<i32>,
+) -> bool {
+    let mut modified = false;
+    let mut map = |error_code: &mut i16| {
+        if from.contains(error_code) {
+            *error_code = to;
+            modified = true;
+        }
+    };
+    let throttle = match body {
+        ResponseBody::Produce(produce) => {
+            for topic in produce.responses.values_mut() {
+                for partition in &mut topic.partition_responses {
+                    map(&mut partition.error_code);
+                }
+            }
+            &mut produce.throttle_time_ms
+        }
+        ResponseBody::Fetch(fetch) => {
+            for topic in &mut fetch.responses {
+                for partition in &mut topic.partitions {
+                    map(&mut partition.error_code);
+                }
+            }
+            &mut fetch.throttle_time_ms
+        }
+        _ => return false,
+    };
+    if modified {
+        if let Some(throttle_time_ms) = throttle_time_ms {
+            *throttle = (*throttle).max(throttle_time_ms);
+        }
+    }
+    modified
+}
+
+/// Replaces every IPv4 address in `text`, including any `:port` following it, with `replacement`.
+/// Returns None if `text` contains no addresses.
+fn mask_addresses(text: &str, replacement: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut result = String::with_capacity(text.len());
+    let mut copied_up_to = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let starts_word = i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'.');
+        if starts_word {
+            if let Some(end) = address_end(bytes, i) {
+                result.push_str(&text[copied_up_to..i]);
+                result.push_str(replacement);
+                copied_up_to = end;
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    if copied_up_to == 0 {
+        None
+    } else {
+        result.push_str(&text[copied_up_to..]);
+        Some(result)
+    }
+}
+
+/// If an IPv4 address starts at `start` returns the index just past it, including any `:port` following it
+fn address_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let digits = |from: usize, max: usize| {
+        let count = bytes[from..]
+            .iter()
+            .take_while(|x| x.is_ascii_digit())
+            .count();
+        (1..=max).contains(&count).then_some(from + count)
+    };
+
+    let mut i = digits(start, 3)?;
+    for _ in 0..3 {
+        if bytes.get(i) != Some(&b'.') {
+            return None;
+        }
+        i = digits(i + 1, 3)?;
+    }
+    if bytes.get(i) == Some(&b':') {
+        if let Some(end) = digits(i + 1, 5) {
+            i = end;
+        }
+    }
+    // Reject longer dotted sequences such as version numbers
+    match bytes.get(i) {
+        Some(x) if x.is_ascii_alphanumeric() => None,
+        Some(b'.') if bytes.get(i + 1).is_some_and(|x| x.is_ascii_digit()) => None,
+        _ => Some(i),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_mask_addresses() {
+        assert_eq!(
+            mask_addresses(
+                "Cannot achieve consistency level QUORUM, /172.16.1.2:9042 and 10.0.0.1 are down",
+                "<node>"
+            ),
+            Some("Cannot achieve consistency level QUORUM, /<node> and <node> are down".to_owned())
+        );
+        assert_eq!(
+            mask_addresses("MOVED 3999 127.0.0.1:6381", "x"),
+            Some("MOVED 3999 x".to_owned())
+        );
+        assert_eq!(mask_addresses("unsupported version 1.2.3.4.5", "x"), None);
+        assert_eq!(mask_addresses("no addresses 1.2.3 here", "x"), None);
+    }
+
+    #[test]
+    fn test_rules_apply_in_order() {
+        let rules = vec![
+            ErrorRule::MaskAddresses {
+                replacement: "<node>".to_owned(),
+            },
+            ErrorRule::RewriteMessage {
+                contains: "<node> is down".to_owned(),
+                message: "Service temporarily unavailable".to_owned(),
+            },
+        ];
+        assert_eq!(
+            map_message(&rules, "10.0.0.1:9042 is down"),
+            Some("Service temporarily unavailable".to_owned())
+        );
+        assert_eq!(
+            map_message(&rules, "connection to 10.0.0.1 reset"),
+            Some("connection to <node> reset".to_owned())
+        );
+        assert_eq!(map_message(&rules, "syntax error"), None);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::message::Messages;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use metrics::{histogram, Histogram};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FairSchedulerConfig {
+    /// Chains configured with the same scheduler name share its capacity
+    pub scheduler: String,
+    /// The number of batches of requests that may be in flight down chain at once, across every chain sharing the scheduler
+    pub max_concurrency: usize,
+    /// The share of the capacity given to this chain relative to the other chains sharing the scheduler
+    pub weight: u32,
+}
+
+const NAME: &str = "FairScheduler";
+#[typetag::serde(name = "FairScheduler")]
+#[async_trait(?Send)]
+impl TransformConfig for FairSchedulerConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        if self.max_concurrency == 0 {
+            bail!("max_concurrency must be greater than 0");
+        }
+        if self.weight == 0 {
+            bail!("weight must be greater than 0");
+        }
+        let scheduler = Scheduler::get_or_create(&self.scheduler, self.max_concurrency)?;
+        scheduler.register_chain(&transform_context.chain_name, self.weight);
+        Ok(Box::new(FairSchedulerBuilder {
+            scheduler,
+            queue_duration: histogram!(
+                "shotover_fair_scheduler_queue_duration_seconds",
+                "chain" => transform_context.chain_name.clone()
+            ),
+            chain_name: transform_context.chain_name,
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// Every scheduler in use, shared by name between chains
+static SCHEDULERS: Mutex<Vec<(String, Weak<Scheduler>)>> = Mutex::new(Vec::new());
+
+pub struct FairSchedulerBuilder {
+    scheduler: Arc<Scheduler>,
+    chain_name: String,
+    queue_duration: Histogram,
+}
+
+impl TransformBuilder for FairSchedulerBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(FairScheduler {
+            scheduler: self.scheduler.clone(),
+            chain_name: self.chain_name.clone(),
+            queue_duration: self.queue_duration.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// Waits for a turn from the scheduler shared with other chains before passing each batch of requests down chain,
+/// so that a burst of requests on one chain can not starve the other chains of the capacity of a destination they share.
+pub struct FairScheduler {
+    scheduler: Arc<Scheduler>,
+    chain_name: String,
+    queue_duration: Histogram,
+}
+
+#[async_trait]
+impl Transform for FairScheduler {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        if requests_wrapper.requests.is_empty() {
+            // nothing is sent down chain, so there is no need to wait for a turn
+            return requests_wrapper.call_next_transform().await;
+        }
+
+        let start = Instant::now();
+        let _permit = self
+            .scheduler
+            .acquire(&self.chain_name, requests_wrapper.requests.len())
+            .await;
+        self.queue_duration.record(start.elapsed());
+
+        requests_wrapper.call_next_transform().await
+    }
+}
+
+/// Start time fair queueing between chains:
+/// each batch is tagged with the virtual time at which its chain is next due a turn, advancing by the size of the batch divided by the chain's weight,
+/// and whenever capacity frees up the waiting batch with the earliest tag goes next.
+/// Over time each chain with waiting batches is given a share of requests proportional to its weight,
+/// while a chain that has been idle is not owed the turns it did not use.
+pub struct Scheduler {
+    max_concurrency: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    in_flight: usize,
+    /// The tag of the batch that most recently started
+    virtual_time: f64,
+    chains: HashMap<String, ChainState>,
+    waiting: Vec<Waiter>,
+    next_sequence: u64,
+}
+
+struct ChainState {
+    weight: f64,
+    /// The virtual time at which the last batch queued by the chain finishes
+    last_finish: f64,
+}
+
+struct Waiter {
+    tag: f64,
+    /// Breaks ties between equal tags in the order the batches were queued
+    sequence: u64,
+    granted: oneshot::Sender<()>,
+}
+
+impl Scheduler {
+    fn get_or_create(name: &str, max_concurrency: usize) -> Result<Arc<Scheduler>> {
+        let mut schedulers = SCHEDULERS.lock().unwrap();
+        schedulers.retain(|(_, scheduler)| scheduler.strong_count() > 0);
+        if let Some(scheduler) = schedulers
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .and_then(|(_, scheduler)| scheduler.upgrade())
+        {
+            if scheduler.max_concurrency != max_concurrency {
+                bail!(
+                    "scheduler {name:?} is configured with a max_concurrency of {} by another chain but {max_concurrency} by this chain",
+                    scheduler.max_concurrency
+                );
+            }
+            return Ok(scheduler);
+        }
+
+        let scheduler = Arc::new(Scheduler::new(max_concurrency));
+        schedulers.push((name.to_owned(), Arc::downgrade(&scheduler)));
+        Ok(scheduler)
+    }
+
+    fn new(max_concurrency: usize) -> Self {
+        Scheduler {
+            max_concurrency,
+            state: Mutex::new(State {
+                in_flight: 0,
+                virtual_time: 0.0,
+                chains: HashMap::new(),
+                waiting: vec![],
+                next_sequence: 0,
+            }),
+        }
+    }
+
+    fn register_chain(&self, chain: &str, weight: u32) {
+        self.state.lock().unwrap().chains.insert(
+            chain.to_owned(),
+            ChainState {
+                weight: weight as f64,
+                last_finish: 0.0,
+            },
+        );
+    }
+
+    /// Waits until the batch of `cost` requests from `chain` is due a turn.
+    /// The turn lasts until the returned [`Permit`] is dropped.
+    pub async fn acquire(self: &Arc<Self>, chain: &str, cost: usize) -> Permit {
+        let mut waiting = Waiting {
+            scheduler: self.clone(),
+            granted: Some(self.enqueue(chain, cost)),
+        };
+        // The scheduler only drops the sender when it is dropped itself, which can not happen while we hold a reference to it
+        waiting.granted.as_mut().unwrap().await.ok();
+        waiting.granted = None;
+        Permit {
+            scheduler: self.clone(),
+        }
+    }
+
+    /// Queues a batch, the returned receiver completes once the batch is granted its turn
+    fn enqueue(&self, chain: &str, cost: usize) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let mut state = self.state.lock().unwrap();
+        let virtual_time = state.virtual_time;
+        let chain = state.chains.entry(chain.to_owned()).or_insert(ChainState {
+            weight: 1.0,
+            last_finish: 0.0,
+        });
+        let tag = chain.last_finish.max(virtual_time);
+        chain.last_finish = tag + cost as f64 / chain.weight;
+
+        if state.in_flight < self.max_concurrency && state.waiting.is_empty() {
+            state.in_flight += 1;
+            state.virtual_time = tag;
+            tx.send(()).ok();
+        } else {
+            let sequence = state.next_sequence;
+            state.next_sequence += 1;
+            state.waiting.push(Waiter {
+                tag,
+                sequence,
+                granted: tx,
+            });
+        }
+        rx
+    }
+
+    /// Ends a turn, granting the next turn to the waiting batch with the earliest tag
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        while state.in_flight < self.max_concurrency {
+            let Some(next) = state
+                .waiting
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.tag.total_cmp(&b.tag).then(a.sequence.cmp(&b.sequence)))
+                .map(|(i, _)| i)
+            else {
+                return;
+            };
+            let waiter = state.waiting.swap_remove(next);
+            // The send fails when the batch stopped waiting, e.g. because the client disconnected, in which case the turn goes to the next batch
+            if waiter.granted.send(()).is_ok() {
+                state.in_flight += 1;
+                state.virtual_time = waiter.tag;
+            }
+        }
+    }
+}
+
+/// A batch waiting for its turn, which passes the turn on if the batch stops waiting after being granted it
+struct Waiting {
+    scheduler: Arc<Scheduler>,
+    granted: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if let Some(mut granted) = self.granted.take() {
+            granted.close();
+            if granted.try_recv().is_ok() {
+                self.scheduler.release();
+            }
+        }
+    }
+}
+
+/// A turn granted by the [`Scheduler`], which ends when this is dropped
+pub struct Permit {
+    scheduler: Arc<Scheduler>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_weighted_turns() {
+        let scheduler = Scheduler::new(1);
+        scheduler.register_chain("heavy", 3);
+        scheduler.register_chain("light", 1);
+
+        // occupy the only turn so that everything else queues up
+        let mut first = scheduler.enqueue("light", 1);
+        assert!(first.try_recv().is_ok());
+
+        let mut waiting: Vec<(&str, oneshot::Receiver<()>)> = vec![];
+        for _ in 0..4 {
+            waiting.push(("light", scheduler.enqueue("light", 1)));
+        }
+        for _ in 0..8 {
+            waiting.push(("heavy", scheduler.enqueue("heavy", 1)));
+        }
+
+        let mut order = vec![];
+        for _ in 0..8 {
+            scheduler.release();
+            for (chain, granted) in &mut waiting {
+                if granted.try_recv().is_ok() {
+                    order.push(*chain);
+                }
+            }
+        }
+        // heavy gets three turns for each turn light gets
+        assert_eq!(
+            order,
+            vec!["heavy", "heavy", "heavy", "light", "heavy", "heavy", "heavy", "light"]
+        );
+    }
+
+    #[test]
+    fn test_abandoned_turn_passed_on() {
+        let scheduler = Arc::new(Scheduler::new(1));
+        let mut first = scheduler.enqueue("a", 1);
+        assert!(first.try_recv().is_ok());
+
+        let abandoned = scheduler.enqueue("a", 1);
+        let mut next = scheduler.enqueue("b", 1);
+        drop(abandoned);
+
+        scheduler.release();
+        assert!(next.try_recv().is_ok());
+        assert_eq!(scheduler.state.lock().unwrap().in_flight, 1);
+    }
+
+    #[test]
+    fn test_mismatched_max_concurrency() {
+        let _scheduler = Scheduler::get_or_create("test_mismatched", 4).unwrap();
+        assert!(Scheduler::get_or_create("test_mismatched", 4).is_ok());
+        assert!(Scheduler::get_or_create("test_mismatched", 8).is_err());
+    }
+}
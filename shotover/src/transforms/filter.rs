@@ -1,5 +1,6 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
 use crate::message::{Message, MessageIdMap, Messages, QueryType};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -56,6 +57,11 @@ impl TransformBuilder for QueryTypeFilter {
     fn get_name(&self) -> &'static str {
         NAME
     }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Filtered requests are answered with an error or dropped instead of being delivered
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,190 @@
+//! The delivery guarantees each transform provides for the requests passing through it.
+//!
+//! Every transform declares its guarantees through [`TransformBuilder::delivery_guarantees`](super::TransformBuilder::delivery_guarantees),
+//! the guarantees of a chain are then the composition of the guarantees of each of its transforms.
+//! A transform that depends on the behaviour of the transforms up chain of it,
+//! such as a sink for a protocol where requests must arrive in order, declares this through
+//! [`TransformBuilder::required_guarantees`](super::TransformBuilder::required_guarantees)
+//! and the chain fails validation at startup if the transforms up chain of it do not meet the requirement.
+
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeliveryGuarantees {
+    pub ordering: RequestOrdering,
+    pub delivery: Delivery,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestOrdering {
+    /// Requests that touch the same data are executed in the order the client sent them.
+    Preserved,
+    /// Requests may be executed in a different order than the client sent them,
+    /// e.g. because they are sent concurrently over different connections.
+    NotPreserved,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Every request is delivered exactly once.
+    ExactlyOnce,
+    /// A request may be answered by shotover without being delivered, but is never delivered more than once.
+    AtMostOnce,
+    /// A request may be delivered more than once, e.g. when it is retried, but is never answered without being delivered.
+    AtLeastOnce,
+    /// A request may be answered without being delivered or may be delivered more than once.
+    NoGuarantee,
+}
+
+impl DeliveryGuarantees {
+    /// The guarantees of a transform that delivers every request it receives, in order, exactly once.
+    pub const PASSTHROUGH: DeliveryGuarantees = DeliveryGuarantees {
+        ordering: RequestOrdering::Preserved,
+        delivery: Delivery::ExactlyOnce,
+    };
+
+    /// The requirement of a transform that does not depend on any guarantees.
+    pub const NONE: DeliveryGuarantees = DeliveryGuarantees {
+        ordering: RequestOrdering::NotPreserved,
+        delivery: Delivery::NoGuarantee,
+    };
+
+    /// The guarantees of a transform that may answer requests itself instead of delivering them.
+    pub const AT_MOST_ONCE: DeliveryGuarantees = DeliveryGuarantees {
+        ordering: RequestOrdering::Preserved,
+        delivery: Delivery::AtMostOnce,
+    };
+
+    /// The guarantees of a transform that sends requests concurrently, e.g. over multiple connections.
+    pub const UNORDERED: DeliveryGuarantees = DeliveryGuarantees {
+        ordering: RequestOrdering::NotPreserved,
+        delivery: Delivery::ExactlyOnce,
+    };
+
+    /// The requirement of a transform that needs requests to be kept in order.
+    pub const ORDERED: DeliveryGuarantees = DeliveryGuarantees {
+        ordering: RequestOrdering::Preserved,
+        delivery: Delivery::NoGuarantee,
+    };
+
+    /// The guarantees of this transform followed by the `down_chain` transform.
+    pub fn then(self, down_chain: DeliveryGuarantees) -> DeliveryGuarantees {
+        DeliveryGuarantees {
+            ordering: match (self.ordering, down_chain.ordering) {
+                (RequestOrdering::Preserved, RequestOrdering::Preserved) => {
+                    RequestOrdering::Preserved
+                }
+                _ => RequestOrdering::NotPreserved,
+            },
+            delivery: match (self.delivery, down_chain.delivery) {
+                (Delivery::ExactlyOnce, delivery) | (delivery, Delivery::ExactlyOnce) => delivery,
+                (Delivery::AtMostOnce, Delivery::AtMostOnce) => Delivery::AtMostOnce,
+                (Delivery::AtLeastOnce, Delivery::AtLeastOnce) => Delivery::AtLeastOnce,
+                _ => Delivery::NoGuarantee,
+            },
+        }
+    }
+
+    /// Returns true if these guarantees are at least as strong as `required`.
+    pub fn satisfies(&self, required: &DeliveryGuarantees) -> bool {
+        self.unmet_requirements(required).is_empty()
+    }
+
+    /// Describes each part of `required` that these guarantees do not provide.
+    pub fn unmet_requirements(&self, required: &DeliveryGuarantees) -> Vec<&'static str> {
+        let mut unmet = vec![];
+        if required.ordering == RequestOrdering::Preserved
+            && self.ordering != RequestOrdering::Preserved
+        {
+            unmet.push("requests to be kept in order");
+        }
+        let delivery_met = match required.delivery {
+            Delivery::ExactlyOnce => self.delivery == Delivery::ExactlyOnce,
+            Delivery::AtMostOnce => {
+                matches!(self.delivery, Delivery::ExactlyOnce | Delivery::AtMostOnce)
+            }
+            Delivery::AtLeastOnce => {
+                matches!(self.delivery, Delivery::ExactlyOnce | Delivery::AtLeastOnce)
+            }
+            Delivery::NoGuarantee => true,
+        };
+        if !delivery_met {
+            unmet.push(match required.delivery {
+                Delivery::ExactlyOnce => "requests to be delivered exactly once",
+                Delivery::AtMostOnce => "requests to never be delivered more than once",
+                Delivery::AtLeastOnce => "requests to never be answered without being delivered",
+                Delivery::NoGuarantee => unreachable!(),
+            });
+        }
+        unmet
+    }
+}
+
+impl fmt::Display for DeliveryGuarantees {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ordering = match self.ordering {
+            RequestOrdering::Preserved => "ordered",
+            RequestOrdering::NotPreserved => "unordered",
+        };
+        let delivery = match self.delivery {
+            Delivery::ExactlyOnce => "exactly once",
+            Delivery::AtMostOnce => "at most once",
+            Delivery::AtLeastOnce => "at least once",
+            Delivery::NoGuarantee => "no delivery guarantee",
+        };
+        write!(f, "{ordering}, {delivery}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const AT_LEAST_ONCE: DeliveryGuarantees = DeliveryGuarantees {
+        ordering: RequestOrdering::Preserved,
+        delivery: Delivery::AtLeastOnce,
+    };
+
+    #[test]
+    fn test_then() {
+        assert_eq!(
+            DeliveryGuarantees::PASSTHROUGH.then(DeliveryGuarantees::AT_MOST_ONCE),
+            DeliveryGuarantees::AT_MOST_ONCE
+        );
+        assert_eq!(
+            DeliveryGuarantees::AT_MOST_ONCE.then(DeliveryGuarantees::AT_MOST_ONCE),
+            DeliveryGuarantees::AT_MOST_ONCE
+        );
+        // a request that is retried and may also be answered without delivery has no delivery guarantee
+        assert_eq!(
+            DeliveryGuarantees::AT_MOST_ONCE.then(AT_LEAST_ONCE),
+            DeliveryGuarantees {
+                ordering: RequestOrdering::Preserved,
+                delivery: Delivery::NoGuarantee,
+            }
+        );
+        assert_eq!(
+            DeliveryGuarantees::AT_MOST_ONCE.then(DeliveryGuarantees::UNORDERED),
+            DeliveryGuarantees {
+                ordering: RequestOrdering::NotPreserved,
+                delivery: Delivery::AtMostOnce,
+            }
+        );
+    }
+
+    #[test]
+    fn test_satisfies() {
+        assert!(DeliveryGuarantees::PASSTHROUGH.satisfies(&DeliveryGuarantees::AT_MOST_ONCE));
+        assert!(DeliveryGuarantees::PASSTHROUGH.satisfies(&AT_LEAST_ONCE));
+        assert!(!DeliveryGuarantees::AT_MOST_ONCE.satisfies(&AT_LEAST_ONCE));
+        assert!(!AT_LEAST_ONCE.satisfies(&DeliveryGuarantees::AT_MOST_ONCE));
+        assert!(DeliveryGuarantees::NONE.satisfies(&DeliveryGuarantees::NONE));
+        assert!(!DeliveryGuarantees::UNORDERED.satisfies(&DeliveryGuarantees::ORDERED));
+        assert_eq!(
+            DeliveryGuarantees::UNORDERED.unmet_requirements(&DeliveryGuarantees::PASSTHROUGH),
+            vec!["requests to be kept in order"]
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use crate::config::chain::TransformChainConfig;
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, Messages, QueryType};
+use crate::transforms::chain::{BufferedChain, TransformChainBuilder};
+use crate::transforms::guarantees::{Delivery, DeliveryGuarantees, RequestOrdering};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HedgeConfig {
+    /// The chain that every request is sent to first
+    pub primary_chain: TransformChainConfig,
+    /// The chain to an equivalent cluster that reads are also sent to when the primary chain is slow or fails
+    pub hedge_chain: TransformChainConfig,
+    /// How long to wait for the primary chain to succeed before also sending reads to the hedge chain
+    pub delay_ms: u64,
+    /// The number of batches that may be queued for each chain, defaults to 5
+    pub buffer_size: Option<usize>,
+}
+
+const NAME: &str = "Hedge";
+#[typetag::serde(name = "Hedge")]
+#[async_trait(?Send)]
+impl TransformConfig for HedgeConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let primary_chain = self
+            .primary_chain
+            .get_builder(TransformContextConfig {
+                chain_name: "primary_chain".to_string(),
+                protocol: transform_context.protocol,
+            })
+            .await?;
+        let hedge_chain = self
+            .hedge_chain
+            .get_builder(TransformContextConfig {
+                chain_name: "hedge_chain".to_string(),
+                protocol: transform_context.protocol,
+            })
+            .await?;
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(HedgeBuilder {
+            primary_chain,
+            hedge_chain,
+            delay: Duration::from_millis(self.delay_ms),
+            buffer_size: self.buffer_size.unwrap_or(5),
+            metrics: HedgeMetrics {
+                hedged: counter!("shotover_hedge_sent_count", "chain" => chain_name.clone()),
+                won: counter!("shotover_hedge_won_count", "chain" => chain_name),
+            },
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        // Reads can only be told apart from writes for these protocols
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::Terminating
+    }
+
+    fn validate_config(&self) -> Vec<String> {
+        if self.buffer_size == Some(0) {
+            vec!["buffer_size must be greater than 0".to_owned()]
+        } else {
+            vec![]
+        }
+    }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        vec![
+            ("primary_chain", &self.primary_chain),
+            ("hedge_chain", &self.hedge_chain),
+        ]
+    }
+}
+
+#[derive(Clone)]
+struct HedgeMetrics {
+    /// Reads sent to the hedge chain
+    hedged: Counter,
+    /// Hedged reads that were answered by the hedge chain
+    won: Counter,
+}
+
+pub struct HedgeBuilder {
+    primary_chain: TransformChainBuilder,
+    hedge_chain: TransformChainBuilder,
+    delay: Duration,
+    buffer_size: usize,
+    metrics: HedgeMetrics,
+}
+
+impl HedgeBuilder {
+    /// A hedged read is delivered to both chains and may be answered by a different cluster than the writes before it were sent to.
+    const HEDGE_GUARANTEES: DeliveryGuarantees = DeliveryGuarantees {
+        ordering: RequestOrdering::NotPreserved,
+        delivery: Delivery::AtLeastOnce,
+    };
+}
+
+impl TransformBuilder for HedgeBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(Hedge {
+            primary_chain: self
+                .primary_chain
+                .build_buffered(self.buffer_size, transform_context.clone()),
+            hedge_chain: self
+                .hedge_chain
+                .build_buffered(self.buffer_size, transform_context),
+            delay: self.delay,
+            metrics: self.metrics.clone(),
+            session_state_changed: false,
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        Self::HEDGE_GUARANTEES.then(self.primary_chain.delivery_guarantees())
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = [&self.primary_chain, &self.hedge_chain]
+            .iter()
+            .flat_map(|chain| {
+                chain
+                    .validate_with_up_chain(Self::HEDGE_GUARANTEES)
+                    .iter()
+                    .map(|x| format!("  {x}"))
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<String>>();
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{NAME}:"));
+        }
+
+        errors
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+}
+
+/// Sends every request to the primary chain, and if a batch of reads has not succeeded within the delay
+/// also sends it to the hedge chain, returning whichever responses succeed first.
+/// This bounds the tail latency of reads when either of two equivalent clusters is degraded.
+///
+/// Batches containing a write are only ever sent to the primary chain.
+/// The chain that loses the race still processes the batch in the background, its responses are discarded.
+///
+/// Requests that change the state of the connection, such as a redis `SELECT` or a cassandra `USE`, are only sent to the primary chain.
+/// The hedge chain would then answer reads in a different session, so hedging stops on a connection once such a request is sent.
+pub struct Hedge {
+    primary_chain: BufferedChain,
+    hedge_chain: BufferedChain,
+    delay: Duration,
+    metrics: HedgeMetrics,
+    /// Set once the client has sent a request that changes the state of the connection
+    session_state_changed: bool,
+}
+
+#[async_trait]
+impl Transform for Hedge {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let mut reads = 0;
+        let mut all_reads = !requests_wrapper.flush;
+        for request in requests_wrapper.requests.iter_mut() {
+            if !request.is_dummy() {
+                if changes_session_state(request) {
+                    self.session_state_changed = true;
+                }
+                if request.get_query_type() == QueryType::Read {
+                    reads += 1;
+                } else {
+                    all_reads = false;
+                }
+            }
+        }
+        if !all_reads || reads == 0 || self.session_state_changed {
+            return self
+                .primary_chain
+                .process_request(requests_wrapper, None)
+                .await;
+        }
+
+        let hedge_wrapper = Wrapper::new_with_addr(
+            requests_wrapper.requests.clone(),
+            requests_wrapper.local_addr,
+        );
+        let primary = self.primary_chain.process_request(requests_wrapper, None);
+        tokio::pin!(primary);
+
+        let mut primary_result = tokio::select! {
+            result = &mut primary => Some(result),
+            _ = tokio::time::sleep(self.delay) => None,
+        };
+        if primary_result.as_mut().is_some_and(succeeded) {
+            return primary_result.unwrap();
+        }
+
+        self.metrics.hedged.increment(reads);
+        let hedge = self.hedge_chain.process_request(hedge_wrapper, None);
+        tokio::pin!(hedge);
+
+        let mut hedge_result = None;
+        while primary_result.is_none() || hedge_result.is_none() {
+            tokio::select! {
+                mut result = &mut primary, if primary_result.is_none() => {
+                    if succeeded(&mut result) {
+                        return result;
+                    }
+                    primary_result = Some(result);
+                }
+                mut result = &mut hedge, if hedge_result.is_none() => {
+                    if succeeded(&mut result) {
+                        self.metrics.won.increment(reads);
+                        return result;
+                    }
+                    hedge_result = Some(result);
+                }
+            }
+        }
+
+        // Neither chain succeeded, so return the outcome of the primary chain
+        primary_result.unwrap()
+    }
+}
+
+/// Returns true if the request changes the state of the connection it is sent on, affecting the responses to later requests
+fn changes_session_state(request: &mut Message) -> bool {
+    match request.frame() {
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(frame)) => matches!(
+            &frame.operation,
+            crate::frame::CassandraOperation::Query { query, .. }
+                if matches!(**query, cql3_parser::cassandra_statement::CassandraStatement::Use(_))
+        ),
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(RedisFrame::Array(args))) => match args.first() {
+            Some(RedisFrame::BulkString(command)) => matches!(
+                command.to_ascii_uppercase().as_slice(),
+                b"AUTH" | b"CLIENT" | b"HELLO" | b"READONLY" | b"READWRITE" | b"RESET" | b"SELECT"
+            ),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// A batch succeeded if the chain did not fail and none of its responses are errors
+fn succeeded(result: &mut Result<Messages>) -> bool {
+    match result {
+        Ok(responses) => !responses.iter_mut().any(|response| response.is_error()),
+        Err(_) => false,
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use crate::transforms::testing::redis_request;
+    use pretty_assertions::assert_eq;
+
+    fn hedge(primary: Response, hedge: Response, delay: Duration) -> Box<dyn Transform> {
+        HedgeBuilder {
+            primary_chain: TransformChainBuilder::new(
+                vec![Box::new(DebugReturner::new(primary))],
+                "primary_chain",
+            ),
+            hedge_chain: TransformChainBuilder::new(
+                vec![Box::new(DebugReturner::new(hedge))],
+                "hedge_chain",
+            ),
+            delay,
+            buffer_size: 5,
+            metrics: HedgeMetrics {
+                hedged: Counter::noop(),
+                won: Counter::noop(),
+            },
+        }
+        .build(TransformContextBuilder::new_test())
+    }
+
+    fn response_value(mut responses: Messages) -> Frame {
+        assert_eq!(responses.len(), 1);
+        responses.pop().unwrap().frame().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_fast_primary_is_not_hedged() {
+        let mut hedge = hedge(
+            Response::Redis("primary".into()),
+            Response::Redis("hedge".into()),
+            Duration::from_secs(60),
+        );
+        let responses = hedge
+            .transform(Wrapper::new_test(vec![redis_request(&["GET", "key"])]))
+            .await
+            .unwrap();
+        assert_eq!(
+            response_value(responses),
+            Frame::Redis(RedisFrame::BulkString("primary".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_primary_is_hedged() {
+        let mut hedge = hedge(
+            Response::Fail,
+            Response::Redis("hedge".into()),
+            Duration::from_secs(60),
+        );
+        let responses = hedge
+            .transform(Wrapper::new_test(vec![redis_request(&["GET", "key"])]))
+            .await
+            .unwrap();
+        assert_eq!(
+            response_value(responses),
+            Frame::Redis(RedisFrame::BulkString("hedge".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_not_hedged() {
+        let mut hedge = hedge(
+            Response::Fail,
+            Response::Redis("hedge".into()),
+            Duration::from_millis(0),
+        );
+        hedge
+            .transform(Wrapper::new_test(vec![redis_request(&[
+                "SET", "key", "value",
+            ])]))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_both_chains_fail() {
+        let mut hedge = hedge(Response::Fail, Response::Fail, Duration::from_millis(0));
+        hedge
+            .transform(Wrapper::new_test(vec![redis_request(&["GET", "key"])]))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_not_hedged_after_session_state_changes() {
+        let mut hedge = hedge(
+            Response::Fail,
+            Response::Redis("hedge".into()),
+            Duration::from_millis(0),
+        );
+        hedge
+            .transform(Wrapper::new_test(vec![redis_request(&["SELECT", "3"])]))
+            .await
+            .unwrap_err();
+        // The hedge chain connection is still on database 0 so must not answer the read
+        hedge
+            .transform(Wrapper::new_test(vec![redis_request(&["GET", "key"])]))
+            .await
+            .unwrap_err();
+    }
+}
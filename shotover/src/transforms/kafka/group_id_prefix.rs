@@ -0,0 +1,272 @@
+use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody};
+use crate::frame::{Frame, MessageType};
+use crate::message::{MessageIdSet, Messages};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use kafka_protocol::messages::GroupId;
+use kafka_protocol::protocol::StrBytes;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaGroupIdPrefixConfig {
+    /// Prepended to every consumer group id sent by the client
+    pub prefix: String,
+}
+
+const NAME: &str = "KafkaGroupIdPrefix";
+#[typetag::serde(name = "KafkaGroupIdPrefix")]
+#[async_trait(?Send)]
+impl TransformConfig for KafkaGroupIdPrefixConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(KafkaGroupIdPrefixBuilder {
+            prefix: self.prefix.clone(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Kafka])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+pub struct KafkaGroupIdPrefixBuilder {
+    prefix: String,
+}
+
+impl TransformBuilder for KafkaGroupIdPrefixBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(KafkaGroupIdPrefix {
+            prefix: self.prefix.clone(),
+            group_find_coordinator_requests: MessageIdSet::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        if self.prefix.is_empty() {
+            vec![format!("{NAME}:"), "  prefix must not be empty".to_owned()]
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Prefixes the consumer group ids sent by clients so that clients of different chains sharing one cluster can not see or join each others groups.
+///
+/// Group ids in responses are returned to the client without the prefix and ListGroups only returns the groups with the prefix.
+pub struct KafkaGroupIdPrefix {
+    prefix: String,
+    /// The in flight FindCoordinator requests that look up the coordinator of a group rather than of a transaction
+    group_find_coordinator_requests: MessageIdSet,
+}
+
+#[async_trait]
+impl Transform for KafkaGroupIdPrefix {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in requests_wrapper.requests.iter_mut() {
+            let request_id = request.id();
+            if let Some(Frame::Kafka(KafkaFrame::Request { body, .. })) = request.frame() {
+                if let RequestBody::FindCoordinator(find_coordinator) = body {
+                    if find_coordinator.key_type == 0 {
+                        self.group_find_coordinator_requests.insert(request_id);
+                    }
+                }
+                if prefix_request(&self.prefix, body) {
+                    request.invalidate_cache();
+                }
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            let group_find_coordinator = response
+                .request_id()
+                .map(|request_id| self.group_find_coordinator_requests.remove(&request_id))
+                .unwrap_or(false);
+            if let Some(Frame::Kafka(KafkaFrame::Response { body, .. })) = response.frame() {
+                if unprefix_response(&self.prefix, body, group_find_coordinator) {
+                    response.invalidate_cache();
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+fn prefixed(prefix: &str, group_id: &StrBytes) -> StrBytes {
+    StrBytes::from_string(format!("{prefix}{}", group_id.as_str()))
+}
+
+fn prefix_group(prefix: &str, group_id: &mut GroupId) {
+    group_id.0 = prefixed(prefix, &group_id.0);
+}
+
+/// Strips the prefix from the group id, returning false if the group id does not have the prefix
+fn unprefix(prefix: &str, group_id: &mut StrBytes) -> bool {
+    match group_id.as_str().strip_prefix(prefix) {
+        Some(stripped) => {
+            *group_id = StrBytes::from_string(stripped.to_owned());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Prefixes every group id in the request, returning true if the request was modified
+fn prefix_request(prefix: &str, body: &mut RequestBody) -> bool {
+    match body {
+        RequestBody::JoinGroup(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::SyncGroup(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::Heartbeat(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::LeaveGroup(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::ConsumerGroupHeartbeat(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::OffsetCommit(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::TxnOffsetCommit(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::AddOffsetsToTxn(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::OffsetDelete(request) => prefix_group(prefix, &mut request.group_id),
+        RequestBody::OffsetFetch(request) => {
+            // Versions up to 7 fetch a single group, later versions fetch a list of groups.
+            // The field not used by the version must be left empty or the request fails to encode.
+            if !request.group_id.0.is_empty() {
+                prefix_group(prefix, &mut request.group_id);
+            }
+            for group in &mut request.groups {
+                prefix_group(prefix, &mut group.group_id);
+            }
+        }
+        RequestBody::DescribeGroups(request) => {
+            for group_id in &mut request.groups {
+                prefix_group(prefix, group_id);
+            }
+        }
+        RequestBody::DeleteGroups(request) => {
+            for group_id in &mut request.groups_names {
+                prefix_group(prefix, group_id);
+            }
+        }
+        // key_type 0 is a group, the other key types are transactional ids which are left as is
+        RequestBody::FindCoordinator(request) if request.key_type == 0 => {
+            // Versions up to 3 look up a single key, later versions look up a list of keys
+            if !request.key.is_empty() {
+                request.key = prefixed(prefix, &request.key);
+            }
+            for key in &mut request.coordinator_keys {
+                *key = prefixed(prefix, key);
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Strips the prefix from every group id in the response, returning true if the response was modified
+fn unprefix_response(prefix: &str, body: &mut ResponseBody, group_find_coordinator: bool) -> bool {
+    match body {
+        ResponseBody::OffsetFetch(response) => {
+            for group in &mut response.groups {
+                unprefix(prefix, &mut group.group_id.0);
+            }
+        }
+        ResponseBody::DescribeGroups(response) => {
+            for group in &mut response.groups {
+                unprefix(prefix, &mut group.group_id.0);
+            }
+        }
+        ResponseBody::DeleteGroups(response) => {
+            response.results = std::mem::take(&mut response.results)
+                .into_iter()
+                .map(|(mut group_id, result)| {
+                    unprefix(prefix, &mut group_id.0);
+                    (group_id, result)
+                })
+                .collect();
+        }
+        // Only the groups of this chain are visible to its clients
+        ResponseBody::ListGroups(response) => response
+            .groups
+            .retain_mut(|group| unprefix(prefix, &mut group.group_id.0)),
+        ResponseBody::FindCoordinator(response) if group_find_coordinator => {
+            for coordinator in &mut response.coordinators {
+                unprefix(prefix, &mut coordinator.key);
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kafka_protocol::messages::list_groups_response::ListedGroup;
+    use kafka_protocol::messages::{JoinGroupRequest, ListGroupsResponse};
+    use kafka_protocol::protocol::Builder;
+    use pretty_assertions::assert_eq;
+
+    fn group_id(id: &'static str) -> GroupId {
+        GroupId(StrBytes::from_static_str(id))
+    }
+
+    #[test]
+    fn test_prefix_request() {
+        let mut body = RequestBody::JoinGroup(
+            JoinGroupRequest::builder()
+                .group_id(group_id("orders"))
+                .build()
+                .unwrap(),
+        );
+        assert!(prefix_request("staging.", &mut body));
+        let RequestBody::JoinGroup(request) = body else {
+            unreachable!()
+        };
+        assert_eq!(request.group_id, group_id("staging.orders"));
+    }
+
+    #[test]
+    fn test_list_groups_isolated() {
+        let listed_group = |id| {
+            ListedGroup::builder()
+                .group_id(group_id(id))
+                .build()
+                .unwrap()
+        };
+        let mut body = ResponseBody::ListGroups(
+            ListGroupsResponse::builder()
+                .groups(vec![
+                    listed_group("staging.orders"),
+                    listed_group("production.orders"),
+                    listed_group("staging.payments"),
+                ])
+                .build()
+                .unwrap(),
+        );
+        assert!(unprefix_response("staging.", &mut body, false));
+        let ResponseBody::ListGroups(response) = body else {
+            unreachable!()
+        };
+        assert_eq!(
+            response.groups,
+            vec![listed_group("orders"), listed_group("payments")]
+        );
+    }
+}
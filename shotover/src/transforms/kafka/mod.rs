@@ -1,2 +1,3 @@
+pub mod group_id_prefix;
 pub mod sink_cluster;
 pub mod sink_single;
@@ -2,7 +2,9 @@ use crate::connection::SinkConnection;
 use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody};
 use crate::frame::{Frame, MessageType};
 use crate::message::{Message, MessageIdMap, Messages};
+use crate::snapshot::{self, StateSnapshot};
 use crate::tls::{TlsConnector, TlsConnectorConfig};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{
     DownChainProtocol, Transform, TransformBuilder, TransformContextBuilder, UpChainProtocol,
     Wrapper,
@@ -11,14 +13,17 @@ use crate::transforms::{TransformConfig, TransformContextConfig};
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
 use dashmap::DashMap;
+use kafka_protocol::messages::describe_cluster_response::DescribeClusterBroker;
+use kafka_protocol::messages::describe_configs_request::DescribeConfigsResource;
+use kafka_protocol::messages::describe_configs_response::DescribeConfigsResult;
 use kafka_protocol::messages::fetch_request::FetchTopic;
 use kafka_protocol::messages::metadata_request::MetadataRequestTopic;
 use kafka_protocol::messages::metadata_response::MetadataResponseBroker;
 use kafka_protocol::messages::{
-    ApiKey, BrokerId, FetchRequest, FindCoordinatorRequest, FindCoordinatorResponse, GroupId,
-    HeartbeatRequest, JoinGroupRequest, MetadataRequest, MetadataResponse, OffsetFetchRequest,
-    RequestHeader, SaslAuthenticateRequest, SaslAuthenticateResponse, SaslHandshakeRequest,
-    SyncGroupRequest, TopicName,
+    ApiKey, BrokerId, DescribeClusterResponse, DescribeConfigsResponse, FetchRequest,
+    FindCoordinatorRequest, FindCoordinatorResponse, GroupId, HeartbeatRequest, JoinGroupRequest,
+    MetadataRequest, MetadataResponse, OffsetFetchRequest, RequestHeader, SaslAuthenticateRequest,
+    SaslAuthenticateResponse, SaslHandshakeRequest, SyncGroupRequest, TopicName,
 };
 use kafka_protocol::protocol::{Builder, StrBytes};
 use kafka_protocol::ResponseError;
@@ -31,6 +36,7 @@ use scram_over_mtls::{
     OriginalScramState,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::{HashMap, VecDeque};
 use std::hash::Hasher;
 use std::sync::atomic::AtomicI64;
@@ -44,6 +50,19 @@ mod scram_over_mtls;
 
 const SASL_SCRAM_MECHANISMS: [&str; 2] = ["SCRAM-SHA-256", "SCRAM-SHA-512"];
 
+/// Broker configs that contain the addresses of kafka brokers, hidden from clients of DescribeConfigs
+const HIDDEN_BROKER_CONFIGS: [&str; 9] = [
+    "listeners",
+    "advertised.listeners",
+    "host.name",
+    "advertised.host.name",
+    "port",
+    "advertised.port",
+    "controller.quorum.voters",
+    "controller.quorum.bootstrap.servers",
+    "zookeeper.connect",
+];
+
 #[derive(thiserror::Error, Debug)]
 enum FindCoordinatorError {
     #[error("Coordinator not available")]
@@ -60,6 +79,7 @@ pub struct KafkaSinkClusterConfig {
     pub local_shotover_broker_id: i32,
     pub connect_timeout_ms: u64,
     pub read_timeout: Option<u64>,
+    pub keepalive_interval: Option<u64>,
     pub tls: Option<TlsConnectorConfig>,
     pub authorize_scram_over_mtls: Option<AuthorizeScramOverMtlsConfig>,
 }
@@ -95,7 +115,7 @@ const NAME: &str = "KafkaSinkCluster";
 impl TransformConfig for KafkaSinkClusterConfig {
     async fn get_builder(
         &self,
-        _transform_context: TransformContextConfig,
+        transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
         let tls = self.tls.clone().map(TlsConnector::new).transpose()?;
 
@@ -125,7 +145,9 @@ impl TransformConfig for KafkaSinkClusterConfig {
             rack,
             self.connect_timeout_ms,
             self.read_timeout,
+            self.keepalive_interval,
             tls,
+            &transform_context.chain_name,
         )?))
     }
 
@@ -145,6 +167,7 @@ pub struct KafkaSinkClusterBuilder {
     rack: StrBytes,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     controller_broker: Arc<AtomicBrokerId>,
     group_to_coordinator_broker: Arc<DashMap<GroupId, BrokerId>>,
     topic_by_name: Arc<DashMap<TopicName, Topic>>,
@@ -152,9 +175,48 @@ pub struct KafkaSinkClusterBuilder {
     nodes_shared: Arc<RwLock<Vec<KafkaNode>>>,
     authorize_scram_over_mtls: Option<AuthorizeScramOverMtlsBuilder>,
     tls: Option<TlsConnector>,
+    /// Kept alive so that the state of the transform is included in snapshots for as long as the builder exists
+    _state: Arc<KafkaSinkClusterState>,
+}
+
+/// The state shared between every instance of a KafkaSinkCluster, for state snapshots
+struct KafkaSinkClusterState {
+    controller_broker: Arc<AtomicBrokerId>,
+    group_to_coordinator_broker: Arc<DashMap<GroupId, BrokerId>>,
+    topic_by_name: Arc<DashMap<TopicName, Topic>>,
+    topic_by_id: Arc<DashMap<Uuid, Topic>>,
+    nodes_shared: Arc<RwLock<Vec<KafkaNode>>>,
+}
+
+#[async_trait]
+impl StateSnapshot for KafkaSinkClusterState {
+    async fn snapshot(&self) -> serde_json::Value {
+        let nodes: Vec<_> = self
+            .nodes_shared
+            .read()
+            .await
+            .iter()
+            .map(|node| {
+                let address = &node.kafka_address;
+                json!({
+                    "broker_id": node.broker_id.0,
+                    "rack": node.rack.as_deref(),
+                    "address": format!("{}:{}", address.host.as_str(), address.port),
+                })
+            })
+            .collect();
+        json!({
+            "controller_broker": self.controller_broker.get().map(|x| x.0),
+            "nodes": nodes,
+            "cached_topics_by_name": self.topic_by_name.len(),
+            "cached_topics_by_id": self.topic_by_id.len(),
+            "cached_group_coordinators": self.group_to_coordinator_broker.len(),
+        })
+    }
 }
 
 impl KafkaSinkClusterBuilder {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         first_contact_points: Vec<String>,
         authorize_scram_over_mtls: &Option<AuthorizeScramOverMtlsConfig>,
@@ -162,11 +224,22 @@ impl KafkaSinkClusterBuilder {
         rack: StrBytes,
         connect_timeout_ms: u64,
         timeout: Option<u64>,
+        keepalive_interval: Option<u64>,
         tls: Option<TlsConnector>,
+        chain_name: &str,
     ) -> Result<KafkaSinkClusterBuilder> {
         let read_timeout = timeout.map(Duration::from_secs);
         let connect_timeout = Duration::from_millis(connect_timeout_ms);
 
+        let state = Arc::new(KafkaSinkClusterState {
+            controller_broker: Arc::new(AtomicBrokerId::new()),
+            group_to_coordinator_broker: Arc::new(DashMap::new()),
+            topic_by_name: Arc::new(DashMap::new()),
+            topic_by_id: Arc::new(DashMap::new()),
+            nodes_shared: Arc::new(RwLock::new(vec![])),
+        });
+        snapshot::register(NAME, chain_name, &state);
+
         Ok(KafkaSinkClusterBuilder {
             first_contact_points,
             authorize_scram_over_mtls: authorize_scram_over_mtls
@@ -177,12 +250,14 @@ impl KafkaSinkClusterBuilder {
             rack,
             connect_timeout,
             read_timeout,
-            controller_broker: Arc::new(AtomicBrokerId::new()),
-            group_to_coordinator_broker: Arc::new(DashMap::new()),
-            topic_by_name: Arc::new(DashMap::new()),
-            topic_by_id: Arc::new(DashMap::new()),
-            nodes_shared: Arc::new(RwLock::new(vec![])),
+            keepalive_interval: keepalive_interval.map(Duration::from_secs),
+            controller_broker: state.controller_broker.clone(),
+            group_to_coordinator_broker: state.group_to_coordinator_broker.clone(),
+            topic_by_name: state.topic_by_name.clone(),
+            topic_by_id: state.topic_by_id.clone(),
+            nodes_shared: state.nodes_shared.clone(),
             tls,
+            _state: state,
         })
     }
 }
@@ -192,7 +267,7 @@ impl TransformBuilder for KafkaSinkClusterBuilder {
         Box::new(KafkaSinkCluster {
             first_contact_points: self.first_contact_points.clone(),
             shotover_nodes: self.shotover_nodes.clone(),
-            _rack: self.rack.clone(),
+            rack: self.rack.clone(),
             nodes: vec![],
             nodes_shared: self.nodes_shared.clone(),
             controller_broker: self.controller_broker.clone(),
@@ -205,12 +280,14 @@ impl TransformBuilder for KafkaSinkClusterBuilder {
                 self.tls.clone(),
                 self.connect_timeout,
                 self.read_timeout,
+                self.keepalive_interval,
                 transform_context.force_run_chain,
             ),
             first_contact_node: None,
             control_connection: None,
             pending_requests: Default::default(),
             find_coordinator_requests: Default::default(),
+            describe_configs_requests: Default::default(),
             temp_responses_buffer: Default::default(),
             sasl_mechanism: None,
             authorize_scram_over_mtls: self.authorize_scram_over_mtls.as_ref().map(|x| x.build()),
@@ -221,6 +298,11 @@ impl TransformBuilder for KafkaSinkClusterBuilder {
         NAME
     }
 
+    fn required_guarantees(&self) -> DeliveryGuarantees {
+        // Produce requests from idempotent producers carry sequence numbers that the broker rejects when they arrive out of order
+        DeliveryGuarantees::ORDERED
+    }
+
     fn is_terminating(&self) -> bool {
         true
     }
@@ -251,8 +333,7 @@ impl AtomicBrokerId {
 pub struct KafkaSinkCluster {
     first_contact_points: Vec<String>,
     shotover_nodes: Vec<ShotoverNode>,
-    // TODO: use this for rack aware routing
-    _rack: StrBytes,
+    rack: StrBytes,
     nodes: Vec<KafkaNode>,
     nodes_shared: Arc<RwLock<Vec<KafkaNode>>>,
     controller_broker: Arc<AtomicBrokerId>,
@@ -268,6 +349,9 @@ pub struct KafkaSinkCluster {
     /// Ordering must be maintained to ensure responses match up with their request.
     pending_requests: VecDeque<PendingRequest>,
     find_coordinator_requests: MessageIdMap<FindCoordinator>,
+    /// The broker ids requested by the client for each resource of a DescribeConfigs request,
+    /// for resources that were rewritten to the id of the kafka broker the request was routed to.
+    describe_configs_requests: MessageIdMap<Vec<Option<StrBytes>>>,
     /// A temporary buffer used when receiving responses, only held onto in order to avoid reallocating.
     temp_responses_buffer: Vec<Message>,
     sasl_mechanism: Option<String>,
@@ -639,6 +723,12 @@ impl KafkaSinkCluster {
                     self.route_to_coordinator(message, group_id);
                 }
 
+                // route to the broker describing its own configs
+                Some(Frame::Kafka(KafkaFrame::Request {
+                    body: RequestBody::DescribeConfigs(_),
+                    ..
+                })) => self.route_describe_configs_request(message),
+
                 // route to controller broker
                 Some(Frame::Kafka(KafkaFrame::Request {
                     body: RequestBody::CreateTopics(_),
@@ -708,9 +798,12 @@ impl KafkaSinkCluster {
 
     /// This method removes all topics from the fetch request and returns them split up by their destination
     /// If any topics are unroutable they will have their BrokerId set to -1
+    ///
+    /// When `rack` is set the fetch may be sent to any replica (KIP-392), see [`fetch_destination`].
     fn split_fetch_request_by_destination(
         &mut self,
         fetch: &mut FetchRequest,
+        rack: Option<&StrBytes>,
     ) -> HashMap<BrokerId, Vec<FetchTopic>> {
         let mut result: HashMap<BrokerId, Vec<FetchTopic>> = Default::default();
 
@@ -731,12 +824,7 @@ impl KafkaSinkCluster {
                     let destination = if let Some(partition) =
                         topic_meta.partitions.get(partition_index)
                     {
-                        self.nodes
-                            .iter_mut()
-                            .filter(|node| partition.replica_nodes.contains(&node.broker_id))
-                            .choose(&mut self.rng)
-                            .unwrap()
-                            .broker_id
+                        fetch_destination(&self.nodes, partition, rack, &mut self.rng)
                     } else {
                         let partition_len = topic_meta.partitions.len();
                         let topic_name = Self::format_topic_name(&topic);
@@ -776,13 +864,14 @@ impl KafkaSinkCluster {
     }
 
     fn route_fetch_request(&mut self, mut message: Message) -> Result<()> {
+        let rack = set_fetch_rack_id(&mut message, &self.rack);
         if let Some(Frame::Kafka(KafkaFrame::Request {
             body: RequestBody::Fetch(fetch),
             ..
         })) = message.frame()
         {
             if fetch.session_id == 0 {
-                let routing = self.split_fetch_request_by_destination(fetch);
+                let routing = self.split_fetch_request_by_destination(fetch, rack.as_ref());
 
                 if routing.is_empty() {
                     // Fetch contains no topics, so we can just pick a random destination.
@@ -800,7 +889,7 @@ impl KafkaSinkCluster {
                     // Only 1 destination,
                     // so we can just reconstruct the original message as is,
                     // act like this never happened 😎,
-                    // we dont even need to invalidate the message's cache.
+                    // we dont even need to invalidate the message's cache for the split.
                     let (destination, topics) = routing.into_iter().next().unwrap();
                     let destination = if destination == -1 {
                         self.nodes.choose(&mut self.rng).unwrap().broker_id
@@ -1190,17 +1279,31 @@ impl KafkaSinkCluster {
                     ..
                 })) => {
                     fetch.session_id = 0;
+                    // Shotover routes fetches to a replica itself and the preferred replica is a kafka broker id unknown to the client,
+                    // so hide it from the client.
+                    for topic in &mut fetch.responses {
+                        for partition in &mut topic.partitions {
+                            partition.preferred_read_replica = BrokerId(-1);
+                        }
+                    }
                     response.invalidate_cache();
                 }
                 Some(Frame::Kafka(KafkaFrame::Response {
-                    body: ResponseBody::DescribeCluster(_),
+                    body: ResponseBody::DescribeCluster(describe_cluster),
                     ..
                 })) => {
-                    // If clients were to send this we would need to rewrite the broker information.
-                    // However I dont think clients actually send this, so just error to ensure we dont break invariants.
-                    return Err(anyhow!(
-                        "I think this is a raft specific message and never sent by clients"
-                    ));
+                    self.process_describe_cluster_response(describe_cluster)
+                        .await;
+                    self.rewrite_describe_cluster_response(describe_cluster)?;
+                    response.invalidate_cache();
+                }
+                Some(Frame::Kafka(KafkaFrame::Response {
+                    body: ResponseBody::DescribeConfigs(describe_configs),
+                    ..
+                })) => {
+                    let requested_names = self.describe_configs_requests.remove(&request_id);
+                    self.rewrite_describe_configs_response(describe_configs, requested_names);
+                    response.invalidate_cache();
                 }
                 _ => {}
             }
@@ -1265,6 +1368,68 @@ impl KafkaSinkCluster {
         });
     }
 
+    /// The configs of a broker can only be described by that broker, so the request is routed to the broker named by its first broker resource.
+    /// The client usually names a shotover node rather than a kafka broker, in which case the request is routed to a kafka broker in the rack of that shotover node,
+    /// which describes its own configs in place of the shotover node.
+    /// All brokers in a cluster are expected to be configured alike, so this is indistinguishable for the client.
+    fn route_describe_configs_request(&mut self, mut request: Message) {
+        let destination = if let Some(Frame::Kafka(KafkaFrame::Request {
+            body: RequestBody::DescribeConfigs(describe_configs),
+            ..
+        })) = request.frame()
+        {
+            // An empty name requests the cluster wide default configs, which do not belong to any broker
+            let is_named_broker = |resource: &DescribeConfigsResource| {
+                is_broker_resource(resource.resource_type) && !resource.resource_name.is_empty()
+            };
+            let destination = describe_configs
+                .resources
+                .iter()
+                .find(|resource| is_named_broker(resource))
+                .and_then(|resource| {
+                    describe_configs_destination(
+                        &self.nodes,
+                        &self.shotover_nodes,
+                        &resource.resource_name,
+                        &mut self.rng,
+                    )
+                })
+                .unwrap_or_else(|| self.nodes.choose(&mut self.rng).unwrap().broker_id);
+
+            let requested_names: Vec<_> = describe_configs
+                .resources
+                .iter_mut()
+                .map(|resource| {
+                    if !is_named_broker(resource) {
+                        return None;
+                    }
+                    let requested_name = resource.resource_name.clone();
+                    // A resource naming a kafka broker other than the destination is left for that broker to reject, as it would be without shotover
+                    if !is_kafka_broker(&self.nodes, &requested_name) {
+                        resource.resource_name = StrBytes::from_string(destination.0.to_string());
+                    }
+                    Some(requested_name)
+                })
+                .collect();
+            if requested_names.iter().any(Option::is_some) {
+                self.describe_configs_requests
+                    .insert(request.id(), requested_names);
+                request.invalidate_cache();
+            }
+            destination
+        } else {
+            self.nodes.choose(&mut self.rng).unwrap().broker_id
+        };
+
+        self.pending_requests.push_back(PendingRequest {
+            ty: PendingRequestTy::Routed {
+                destination,
+                request,
+            },
+            combine_responses: 1,
+        });
+    }
+
     fn route_to_controller(&mut self, request: Message) {
         let broker_id = self.controller_broker.get().unwrap();
 
@@ -1507,36 +1672,97 @@ impl KafkaSinkCluster {
             }
         }
 
-        if let Some(controller_node) = self
-            .nodes
+        self.rewrite_controller_id(&mut metadata.controller_id)
+    }
+
+    /// Rewrite the id of the controller broker to the id of the shotover node that stands in for it
+    fn rewrite_controller_id(&self, controller_id: &mut BrokerId) -> Result<()> {
+        rewrite_controller_id(&self.nodes, &self.shotover_nodes, controller_id)
+    }
+
+    async fn process_describe_cluster_response(
+        &mut self,
+        describe_cluster: &DescribeClusterResponse,
+    ) {
+        for (id, broker) in &describe_cluster.brokers {
+            let node = KafkaNode::new(
+                *id,
+                KafkaAddress::new(broker.host.clone(), broker.port),
+                broker.rack.clone(),
+            );
+            self.add_node_if_new(node).await;
+        }
+    }
+
+    /// Rewrite describe cluster response to appear as if the shotover cluster is the real cluster and the real kafka brokers do not exist
+    fn rewrite_describe_cluster_response(
+        &self,
+        describe_cluster: &mut DescribeClusterResponse,
+    ) -> Result<()> {
+        describe_cluster.brokers = self
+            .shotover_nodes
             .iter()
-            .find(|node| node.broker_id == metadata.controller_id)
-        {
-            // If broker has no rack - use the first shotover node
-            // If broker has rack - use the first shotover node with the same rack
-            // This is deterministic because the list of shotover nodes is sorted.
-            if let Some(shotover_node) = self.shotover_nodes.iter().find(|shotover_node| {
-                controller_node
-                    .rack
-                    .as_ref()
-                    .map(|rack| rack == &shotover_node.rack)
-                    .unwrap_or(true)
-            }) {
-                metadata.controller_id = shotover_node.broker_id;
-            } else {
-                tracing::warn!(
-                    "No shotover node configured to handle kafka rack {:?}",
-                    controller_node.rack
-                );
+            .map(|shotover_node| {
+                (
+                    shotover_node.broker_id,
+                    DescribeClusterBroker::builder()
+                        .host(shotover_node.address.host.clone())
+                        .port(shotover_node.address.port)
+                        .rack(Some(shotover_node.rack.clone()))
+                        .build()
+                        .unwrap(),
+                )
+            })
+            .collect();
+        self.rewrite_controller_id(&mut describe_cluster.controller_id)
+    }
+
+    /// Rewrite describe configs response to describe the shotover nodes requested by the client,
+    /// hiding any configs that would reveal the real kafka brokers.
+    fn rewrite_describe_configs_response(
+        &self,
+        describe_configs: &mut DescribeConfigsResponse,
+        requested_names: Option<Vec<Option<StrBytes>>>,
+    ) {
+        let requested_names = requested_names.unwrap_or_default();
+        for (i, result) in describe_configs.results.iter_mut().enumerate() {
+            if !is_broker_resource(result.resource_type) {
+                continue;
+            }
+            result
+                .configs
+                .retain(|config| !HIDDEN_BROKER_CONFIGS.contains(&config.name.as_str()));
+            if let Some(Some(requested_name)) = requested_names.get(i) {
+                result.resource_name = requested_name.clone();
+                self.rewrite_broker_identity_configs(result, requested_name);
             }
-        } else {
-            return Err(anyhow!(
-                "Invalid metadata, controller points at unknown node {:?}",
-                metadata.controller_id
-            ));
         }
+    }
 
-        Ok(())
+    /// Replace the configs identifying the kafka broker with those of the requested shotover node
+    fn rewrite_broker_identity_configs(
+        &self,
+        result: &mut DescribeConfigsResult,
+        requested_name: &StrBytes,
+    ) {
+        let shotover_node = self
+            .shotover_nodes
+            .iter()
+            .find(|shotover_node| shotover_node.broker_id.0.to_string() == requested_name.as_str());
+        for config in &mut result.configs {
+            let value = match config.name.as_str() {
+                "broker.id" | "node.id" => requested_name.clone(),
+                "broker.rack" => match shotover_node {
+                    Some(shotover_node) => shotover_node.rack.clone(),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            config.value = Some(value.clone());
+            for synonym in &mut config.synonyms {
+                synonym.value = Some(value.clone());
+            }
+        }
     }
 
     async fn add_node_if_new(&mut self, new_node: KafkaNode) {
@@ -1554,6 +1780,82 @@ impl KafkaSinkCluster {
     }
 }
 
+/// True for the BROKER and BROKER_LOGGER resource types, whose resources are named by broker id
+fn is_broker_resource(resource_type: i8) -> bool {
+    resource_type == 4 || resource_type == 8
+}
+
+/// True if `name` is the id of a known kafka broker
+fn is_kafka_broker(nodes: &[KafkaNode], name: &str) -> bool {
+    name.parse::<i32>()
+        .is_ok_and(|id| nodes.iter().any(|node| node.broker_id == id))
+}
+
+/// Returns the kafka broker that a DescribeConfigs request for the broker resource `name` should be routed to.
+/// This is the named kafka broker if there is one, otherwise a kafka broker in the rack of the named shotover node.
+fn describe_configs_destination(
+    nodes: &[KafkaNode],
+    shotover_nodes: &[ShotoverNode],
+    name: &str,
+    rng: &mut SmallRng,
+) -> Option<BrokerId> {
+    let id = name.parse::<i32>().ok()?;
+    if let Some(node) = nodes.iter().find(|node| node.broker_id == id) {
+        return Some(node.broker_id);
+    }
+    let shotover_node = shotover_nodes
+        .iter()
+        .find(|shotover_node| shotover_node.broker_id == id)?;
+    nodes
+        .iter()
+        .filter(|node| {
+            node.rack
+                .as_ref()
+                .map(|rack| rack == &shotover_node.rack)
+                .unwrap_or(true)
+        })
+        .choose(rng)
+        .map(|node| node.broker_id)
+}
+
+/// Rewrite the id of the controller broker to the id of the shotover node that stands in for it.
+/// An id of -1, which kafka reports when there is no active controller, is left as is.
+fn rewrite_controller_id(
+    nodes: &[KafkaNode],
+    shotover_nodes: &[ShotoverNode],
+    controller_id: &mut BrokerId,
+) -> Result<()> {
+    if *controller_id == -1 {
+        return Ok(());
+    }
+    if let Some(controller_node) = nodes.iter().find(|node| node.broker_id == *controller_id) {
+        // If broker has no rack - use the first shotover node
+        // If broker has rack - use the first shotover node with the same rack
+        // This is deterministic because the list of shotover nodes is sorted.
+        if let Some(shotover_node) = shotover_nodes.iter().find(|shotover_node| {
+            controller_node
+                .rack
+                .as_ref()
+                .map(|rack| rack == &shotover_node.rack)
+                .unwrap_or(true)
+        }) {
+            *controller_id = shotover_node.broker_id;
+        } else {
+            tracing::warn!(
+                "No shotover node configured to handle kafka rack {:?}",
+                controller_node.rack
+            );
+        }
+    } else {
+        return Err(anyhow!(
+            "Invalid response, controller points at unknown node {:?}",
+            controller_id
+        ));
+    }
+
+    Ok(())
+}
+
 fn hash_partition(topic_id: Uuid, partition_index: i32) -> usize {
     let mut hasher = xxhash_rust::xxh3::Xxh3::new();
     hasher.write(topic_id.as_bytes());
@@ -1573,6 +1875,57 @@ struct Partition {
     replica_nodes: Vec<i32>,
 }
 
+/// Chooses the broker to send the fetch of `partition` to, or `BrokerId(-1)` if none of the known brokers can serve it.
+///
+/// When the client's `rack` is known the fetch may be served by any replica (KIP-392), so a replica in that rack is preferred.
+/// Otherwise, or when no replica is in that rack, the fetch is sent to the leader,
+/// since a follower only serves fetches from clients outside its rack when the broker is configured with a `replica.selector.class`.
+fn fetch_destination(
+    nodes: &[KafkaNode],
+    partition: &Partition,
+    rack: Option<&StrBytes>,
+    rng: &mut SmallRng,
+) -> BrokerId {
+    if let Some(rack) = rack {
+        if let Some(node) = nodes
+            .iter()
+            .filter(|node| {
+                partition.replica_nodes.contains(&node.broker_id)
+                    && node.rack.as_ref() == Some(rack)
+            })
+            .choose(rng)
+        {
+            return node.broker_id;
+        }
+    }
+    nodes
+        .iter()
+        .find(|node| node.broker_id == partition.leader_id)
+        .map(|node| node.broker_id)
+        .unwrap_or(BrokerId(-1))
+}
+
+/// Returns the rack of the client if the fetch request may be routed to a follower, which fetch requests from version 11 onwards may be.
+/// A client that did not report its own rack is treated as being in the rack of this shotover node,
+/// and the fetch is rewritten to report that rack so that the leader's replica selector agrees with the replica that shotover routes to.
+fn set_fetch_rack_id(message: &mut Message, shotover_rack: &StrBytes) -> Option<StrBytes> {
+    if let Some(Frame::Kafka(KafkaFrame::Request {
+        body: RequestBody::Fetch(fetch),
+        header,
+    })) = message.frame()
+    {
+        if header.request_api_version >= 11 {
+            if !fetch.rack_id.is_empty() {
+                return Some(fetch.rack_id.clone());
+            }
+            fetch.rack_id = shotover_rack.clone();
+            message.invalidate_cache();
+            return Some(shotover_rack.clone());
+        }
+    }
+    None
+}
+
 struct FindCoordinator {
     key: StrBytes,
     key_type: i8,
@@ -1589,3 +1942,201 @@ fn get_username_from_scram_request(auth_request: &[u8]) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn rack(rack: &'static str) -> StrBytes {
+        StrBytes::from_static_str(rack)
+    }
+
+    fn nodes() -> Vec<KafkaNode> {
+        ["rack0", "rack1", "rack2", "rack3"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, node_rack)| {
+                KafkaNode::new(
+                    BrokerId(i as i32),
+                    KafkaAddress::new(rack("127.0.0.1"), 9092 + i as i32),
+                    Some(rack(node_rack)),
+                )
+            })
+            .collect()
+    }
+
+    fn partition() -> Partition {
+        Partition {
+            index: 0,
+            leader_id: 0,
+            replica_nodes: vec![0, 1, 2],
+        }
+    }
+
+    fn fetch(version: i16, rack_id: &'static str) -> Message {
+        let mut header = RequestHeader::default();
+        header.request_api_version = version;
+        let mut fetch = FetchRequest::default();
+        fetch.rack_id = rack(rack_id);
+        Message::from_frame(Frame::Kafka(KafkaFrame::Request {
+            header,
+            body: RequestBody::Fetch(fetch),
+        }))
+    }
+
+    fn fetch_rack_id(message: &mut Message) -> StrBytes {
+        match message.frame() {
+            Some(Frame::Kafka(KafkaFrame::Request {
+                body: RequestBody::Fetch(fetch),
+                ..
+            })) => fetch.rack_id.clone(),
+            frame => panic!("expected a fetch request but was {frame:?}"),
+        }
+    }
+
+    #[test]
+    fn test_fetch_destination_rack_local() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10 {
+            assert_eq!(
+                fetch_destination(&nodes(), &partition(), Some(&rack("rack1")), &mut rng),
+                BrokerId(1)
+            );
+            assert_eq!(
+                fetch_destination(&nodes(), &partition(), Some(&rack("rack2")), &mut rng),
+                BrokerId(2)
+            );
+        }
+    }
+
+    #[test]
+    fn test_fetch_destination_leader_fallback() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10 {
+            // rack3 contains a broker, but not a replica of the partition
+            assert_eq!(
+                fetch_destination(&nodes(), &partition(), Some(&rack("rack3")), &mut rng),
+                BrokerId(0)
+            );
+            assert_eq!(
+                fetch_destination(&nodes(), &partition(), Some(&rack("rack4")), &mut rng),
+                BrokerId(0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_fetch_destination_pre_v11() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10 {
+            assert_eq!(
+                fetch_destination(&nodes(), &partition(), None, &mut rng),
+                BrokerId(0)
+            );
+        }
+    }
+
+    #[test]
+    fn test_fetch_destination_unknown_leader() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let partition = Partition {
+            index: 0,
+            leader_id: 7,
+            replica_nodes: vec![7, 8],
+        };
+        assert_eq!(
+            fetch_destination(&nodes(), &partition, Some(&rack("rack1")), &mut rng),
+            BrokerId(-1)
+        );
+        assert_eq!(
+            fetch_destination(&nodes(), &partition, None, &mut rng),
+            BrokerId(-1)
+        );
+    }
+
+    fn shotover_nodes() -> Vec<ShotoverNode> {
+        ["rack1", "rack2"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, node_rack)| ShotoverNode {
+                address: KafkaAddress::new(rack("127.0.0.1"), 9192 + i as i32),
+                rack: rack(node_rack),
+                broker_id: BrokerId(10 + i as i32),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rewrite_controller_id() {
+        let mut controller_id = BrokerId(2);
+        rewrite_controller_id(&nodes(), &shotover_nodes(), &mut controller_id).unwrap();
+        assert_eq!(controller_id, BrokerId(11));
+
+        // Kafka reports -1 while there is no active controller
+        let mut controller_id = BrokerId(-1);
+        rewrite_controller_id(&nodes(), &shotover_nodes(), &mut controller_id).unwrap();
+        assert_eq!(controller_id, BrokerId(-1));
+
+        let mut controller_id = BrokerId(7);
+        rewrite_controller_id(&nodes(), &shotover_nodes(), &mut controller_id).unwrap_err();
+    }
+
+    #[test]
+    fn test_describe_configs_destination() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10 {
+            // A kafka broker describes itself
+            assert_eq!(
+                describe_configs_destination(&nodes(), &shotover_nodes(), "3", &mut rng),
+                Some(BrokerId(3))
+            );
+            // A shotover node is described by a kafka broker in its rack
+            assert_eq!(
+                describe_configs_destination(&nodes(), &shotover_nodes(), "10", &mut rng),
+                Some(BrokerId(1))
+            );
+            assert_eq!(
+                describe_configs_destination(&nodes(), &shotover_nodes(), "11", &mut rng),
+                Some(BrokerId(2))
+            );
+        }
+        assert_eq!(
+            describe_configs_destination(&nodes(), &shotover_nodes(), "42", &mut rng),
+            None
+        );
+        assert_eq!(
+            describe_configs_destination(&nodes(), &shotover_nodes(), "not an id", &mut rng),
+            None
+        );
+
+        assert!(is_kafka_broker(&nodes(), "0"));
+        assert!(!is_kafka_broker(&nodes(), "10"));
+    }
+
+    #[test]
+    fn test_set_fetch_rack_id() {
+        let shotover_rack = rack("rack1");
+
+        // The rack reported by the client is kept
+        let mut message = fetch(11, "rack2");
+        assert_eq!(
+            set_fetch_rack_id(&mut message, &shotover_rack),
+            Some(rack("rack2"))
+        );
+        assert_eq!(fetch_rack_id(&mut message), rack("rack2"));
+
+        // A client that did not report its rack is treated as being in shotover's rack
+        let mut message = fetch(11, "");
+        assert_eq!(
+            set_fetch_rack_id(&mut message, &shotover_rack),
+            Some(rack("rack1"))
+        );
+        assert_eq!(fetch_rack_id(&mut message), rack("rack1"));
+
+        // Fetches before version 11 have no rack and are always routed to the leader
+        let mut message = fetch(10, "");
+        assert_eq!(set_fetch_rack_id(&mut message, &shotover_rack), None);
+        assert_eq!(fetch_rack_id(&mut message), rack(""));
+    }
+}
@@ -23,6 +23,7 @@ pub struct ConnectionFactory {
     tls: Option<TlsConnector>,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     auth_requests: Vec<Message>,
     force_run_chain: Arc<Notify>,
 }
@@ -32,6 +33,7 @@ impl ConnectionFactory {
         tls: Option<TlsConnector>,
         connect_timeout: Duration,
         read_timeout: Option<Duration>,
+        keepalive_interval: Option<Duration>,
         force_run_chain: Arc<Notify>,
     ) -> Self {
         ConnectionFactory {
@@ -40,6 +42,7 @@ impl ConnectionFactory {
             auth_requests: vec![],
             force_run_chain,
             read_timeout,
+            keepalive_interval,
         }
     }
 
@@ -61,6 +64,7 @@ impl ConnectionFactory {
             self.connect_timeout,
             self.force_run_chain.clone(),
             self.read_timeout,
+            self.keepalive_interval,
         )
         .await
     }
@@ -80,6 +84,7 @@ impl ConnectionFactory {
             self.connect_timeout,
             self.force_run_chain.clone(),
             self.read_timeout,
+            self.keepalive_interval,
         )
         .await
         .context("Failed to create sink connection")?;
@@ -154,6 +154,7 @@ impl AuthorizeScramOverMtlsConfig {
             Some(TlsConnector::new(self.tls.clone())?),
             connect_timeout,
             read_timeout,
+            None,
             Arc::new(Notify::new()),
         );
         let contact_points: Result<Vec<_>> = self
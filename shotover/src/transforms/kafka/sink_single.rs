@@ -4,6 +4,7 @@ use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody};
 use crate::frame::{Frame, MessageType};
 use crate::message::Messages;
 use crate::tls::{TlsConnector, TlsConnectorConfig};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{DownChainProtocol, TransformConfig, UpChainProtocol};
 use crate::transforms::{
     Transform, TransformBuilder, TransformContextBuilder, TransformContextConfig, Wrapper,
@@ -24,6 +25,7 @@ pub struct KafkaSinkSingleConfig {
     pub destination_port: u16,
     pub connect_timeout_ms: u64,
     pub read_timeout: Option<u64>,
+    pub keepalive_interval: Option<u64>,
     pub tls: Option<TlsConnectorConfig>,
 }
 
@@ -41,6 +43,7 @@ impl TransformConfig for KafkaSinkSingleConfig {
             transform_context.chain_name,
             self.connect_timeout_ms,
             self.read_timeout,
+            self.keepalive_interval,
             tls,
         )))
     }
@@ -59,6 +62,7 @@ pub struct KafkaSinkSingleBuilder {
     address_port: u16,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     tls: Option<TlsConnector>,
 }
 
@@ -68,6 +72,7 @@ impl KafkaSinkSingleBuilder {
         _chain_name: String,
         connect_timeout_ms: u64,
         timeout: Option<u64>,
+        keepalive_interval: Option<u64>,
         tls: Option<TlsConnector>,
     ) -> KafkaSinkSingleBuilder {
         let receive_timeout = timeout.map(Duration::from_secs);
@@ -76,6 +81,7 @@ impl KafkaSinkSingleBuilder {
             address_port,
             connect_timeout: Duration::from_millis(connect_timeout_ms),
             read_timeout: receive_timeout,
+            keepalive_interval: keepalive_interval.map(Duration::from_secs),
             tls,
         }
     }
@@ -89,6 +95,7 @@ impl TransformBuilder for KafkaSinkSingleBuilder {
             connect_timeout: self.connect_timeout,
             tls: self.tls.clone(),
             read_timeout: self.read_timeout,
+            keepalive_interval: self.keepalive_interval,
             force_run_chain: transform_context.force_run_chain,
         })
     }
@@ -97,6 +104,11 @@ impl TransformBuilder for KafkaSinkSingleBuilder {
         NAME
     }
 
+    fn required_guarantees(&self) -> DeliveryGuarantees {
+        // Produce requests from idempotent producers carry sequence numbers that the broker rejects when they arrive out of order
+        DeliveryGuarantees::ORDERED
+    }
+
     fn is_terminating(&self) -> bool {
         true
     }
@@ -107,6 +119,7 @@ pub struct KafkaSinkSingle {
     connection: Option<SinkConnection>,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     tls: Option<TlsConnector>,
     force_run_chain: Arc<Notify>,
 }
@@ -129,6 +142,7 @@ impl Transform for KafkaSinkSingle {
                     self.connect_timeout,
                     self.force_run_chain.clone(),
                     self.read_timeout,
+                    self.keepalive_interval,
                 )
                 .await?,
             );
@@ -2,6 +2,7 @@ use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig,
 use crate::config::chain::TransformChainConfig;
 use crate::message::Messages;
 use crate::transforms::chain::{BufferedChain, TransformChainBuilder};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -41,6 +42,10 @@ impl TransformConfig for ConnectionBalanceAndPoolConfig {
     fn down_chain_protocol(&self) -> DownChainProtocol {
         DownChainProtocol::SameAsUpChain
     }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        vec![("chain", &self.chain)]
+    }
 }
 
 pub struct ConnectionBalanceAndPoolBuilder {
@@ -67,6 +72,10 @@ impl TransformBuilder for ConnectionBalanceAndPoolBuilder {
     fn get_name(&self) -> &'static str {
         NAME
     }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        self.chain_to_clone.delivery_guarantees()
+    }
 }
 
 /// Every cloned instance of ConnectionBalanceAndPool will use a new connection until `max_connections` clones are made.
@@ -1,8 +1,13 @@
 //! Various types required for defining a transform
 
 use self::chain::TransformAndMetrics;
+use self::guarantees::DeliveryGuarantees;
+use self::retry_budget::RetryBudget;
+use crate::config::chain::TransformChainConfig;
 use crate::frame::MessageType;
 use crate::message::{Message, MessageIdMap, Messages};
+use crate::panics;
+use crate::tuning;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use futures::Future;
@@ -13,13 +18,21 @@ use std::slice::IterMut;
 use std::sync::Arc;
 use tokio::sync::Notify;
 use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 
+pub mod anomaly_detection;
+pub mod backup_read_verification;
+pub mod cardinality;
 #[cfg(feature = "cassandra")]
 pub mod cassandra;
 pub mod chain;
 pub mod coalesce;
 pub mod debug;
+pub mod error_mapping;
+pub mod fair_scheduler;
 pub mod filter;
+pub mod guarantees;
+pub mod hedge;
 #[cfg(feature = "kafka")]
 pub mod kafka;
 pub mod load_balance;
@@ -29,13 +42,20 @@ pub mod null;
 #[cfg(all(feature = "alpha-transforms", feature = "opensearch"))]
 pub mod opensearch;
 pub mod parallel_map;
+pub mod payload_capture;
+pub mod plugin;
 #[cfg(feature = "cassandra")]
 pub mod protect;
 pub mod query_counter;
+pub mod read_only;
 #[cfg(feature = "redis")]
 pub mod redis;
+pub mod request_deduplication;
+pub mod retry_budget;
 pub mod sampler;
+pub mod static_response;
 pub mod tee;
+pub mod testing;
 #[cfg(feature = "cassandra")]
 pub mod throttling;
 pub mod util;
@@ -52,6 +72,11 @@ pub struct TransformContextBuilder {
     /// * This should be used when a transform needs to generate or flush messages after some kind of timeout or background process completes.
     pub force_run_chain: Arc<Notify>,
     pub client_details: String,
+    /// The address of the client, if it could be determined.
+    pub client_addr: Option<SocketAddr>,
+    /// A transform may close the connection to the client by calling `cancel` on this field.
+    /// The connection is closed once the responses from the current chain run have been sent to the client.
+    pub close_connection: CancellationToken,
 }
 
 #[allow(clippy::new_without_default)]
@@ -60,6 +85,8 @@ impl TransformContextBuilder {
         TransformContextBuilder {
             force_run_chain: Arc::new(Notify::new()),
             client_details: String::new(),
+            client_addr: None,
+            close_connection: CancellationToken::new(),
         }
     }
 }
@@ -76,6 +103,16 @@ pub trait TransformBuilder: Send + Sync {
     fn is_terminating(&self) -> bool {
         false
     }
+
+    /// The guarantees this transform provides for the requests it receives, including any subchains it delivers them through.
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        DeliveryGuarantees::PASSTHROUGH
+    }
+
+    /// The guarantees this transform needs the transforms up chain of it to provide.
+    fn required_guarantees(&self) -> DeliveryGuarantees {
+        DeliveryGuarantees::NONE
+    }
 }
 
 #[typetag::serde]
@@ -89,6 +126,19 @@ pub trait TransformConfig: Debug {
     fn up_chain_protocol(&self) -> UpChainProtocol;
 
     fn down_chain_protocol(&self) -> DownChainProtocol;
+
+    /// Checks constraints between fields of the config that cannot be expressed by their types, e.g. a field that is only valid when another field is set.
+    /// Called for every transform in the topology before any transform is built, so must not perform any IO.
+    /// Each returned string describes a single problem.
+    fn validate_config(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// The subchains of this transform, each along with the name of the field it is configured in.
+    /// Used to check the configs of the transforms within the subchains.
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        vec![]
+    }
 }
 
 pub enum UpChainProtocol {
@@ -126,6 +176,9 @@ pub struct Wrapper<'a> {
     /// This can occur at any time but will always occur before the transform is destroyed due to either
     /// shotover or the transform's chain shutting down.
     pub flush: bool,
+    /// Set by a [`RetryBudget`](retry_budget::RetryBudgetTransform) transform up chain.
+    /// Transforms that retry requests must withdraw from it when it is set.
+    pub retry_budget: Option<RetryBudget>,
 }
 
 /// [`Wrapper`] will not (cannot) bring the current list of transforms that it needs to traverse with it
@@ -138,6 +191,7 @@ impl<'a> Clone for Wrapper<'a> {
             transforms: [].iter_mut(),
             local_addr: self.local_addr,
             flush: self.flush,
+            retry_budget: self.retry_budget.clone(),
         }
     }
 }
@@ -154,10 +208,11 @@ impl<'a> Wrapper<'a> {
     pub async fn call_next_transform(mut self) -> Result<Messages> {
         let TransformAndMetrics {
             transform,
+            chain,
             transform_total,
             transform_failures,
+            transform_panics,
             transform_latency,
-            ..
         } = match self.transforms.next() {
             Some(transform) => transform,
             None => panic!("The transform chain does not end with a terminating transform. If you want to throw the messages away use a NullSink transform, otherwise use a terminating sink transform to send the messages somewhere.")
@@ -166,9 +221,13 @@ impl<'a> Wrapper<'a> {
         let transform_name = transform.get_name();
 
         let start = Instant::now();
-        let result = transform
-            .transform(self)
+        let _pool_sample = tuning::sample_pool(chain, transform_name);
+        let result = panics::catch_unwind(chain, transform_name, transform.transform(self))
             .await
+            .unwrap_or_else(|panic| {
+                transform_panics.increment(1);
+                Err(panic.into())
+            })
             .map_err(|e| e.context(anyhow!("{transform_name} transform failed")));
         transform_total.increment(1);
         if result.is_err() {
@@ -191,6 +250,7 @@ impl<'a> Wrapper<'a> {
             transforms: [].iter_mut(),
             local_addr: "127.0.0.1:8000".parse().unwrap(),
             flush: false,
+            retry_budget: None,
         }
     }
 
@@ -200,6 +260,7 @@ impl<'a> Wrapper<'a> {
             transforms: [].iter_mut(),
             local_addr,
             flush: false,
+            retry_budget: None,
         }
     }
 
@@ -210,6 +271,7 @@ impl<'a> Wrapper<'a> {
             // The connection is closed so we need to just fake an address here
             local_addr: "127.0.0.1:10000".parse().unwrap(),
             flush: true,
+            retry_budget: None,
         }
     }
 
@@ -298,7 +360,35 @@ pub trait Transform: Send {
     /// You can have have a transform that is both non-terminating and a sink.
     async fn transform<'a>(&'a mut self, requests_wrapper: Wrapper<'a>) -> Result<Messages>;
 
+    /// Called on every transform in the chain, in chain order, when the client connection the chain belongs to reaches a [`ConnectionEvent`].
+    /// Transforms that need to set up or tear down state per client session, rather than per request, can implement this.
+    /// Subchains owned by a transform are not notified unless the transform forwards the event via `TransformChain::process_connection_event`.
+    ///
+    /// Returning `Err` from [`ConnectionEvent::Connected`] or [`ConnectionEvent::Authenticated`] closes the connection.
+    /// [`ConnectionEvent::Closed`] is delivered even after [`Transform::transform`] has returned `Err`, so it must tolerate the transform being in an invalid state.
+    async fn on_connection_event(&mut self, _event: ConnectionEvent) -> Result<()> {
+        Ok(())
+    }
+
     fn get_name(&self) -> &'static str;
 }
 
+/// The lifecycle of the client connection that a chain instance is created for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The client has connected, delivered before the first call to [`Transform::transform`].
+    Connected,
+    /// The client successfully authenticated while establishing its connection:
+    /// * Cassandra: the server responded to an AUTH_RESPONSE with AUTH_SUCCESS
+    /// * Redis: the server responded to AUTH or HELLO with AUTH without an error
+    /// * Kafka: the server responded to SaslAuthenticate without an error
+    ///
+    /// Reauthenticating later in the connection is not reported.
+    Authenticated,
+    /// Shotover is shutting down, delivered before the chain is flushed and the connection closed.
+    Draining,
+    /// The connection is closed, the chain will be dropped once every transform has been notified.
+    Closed,
+}
+
 type ResponseFuture = Pin<Box<dyn Future<Output = Result<util::Response>> + Send + Sync>>;
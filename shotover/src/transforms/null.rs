@@ -1,5 +1,6 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
 use crate::message::Messages;
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -41,6 +42,11 @@ impl TransformBuilder for NullSink {
         NAME
     }
 
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Every request is answered without being delivered
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
     fn is_terminating(&self) -> bool {
         true
     }
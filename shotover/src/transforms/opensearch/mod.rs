@@ -122,6 +122,7 @@ impl Transform for OpenSearchSinkSingle {
                 .send(Request {
                     message,
                     return_chan: Some(tx),
+                    client_id: None,
                 })
                 .map_err(|_| anyhow!("Failed to send"))?;
 
@@ -2,6 +2,7 @@ use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig,
 use crate::config::chain::TransformChainConfig;
 use crate::message::Messages;
 use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -100,6 +101,10 @@ impl TransformConfig for ParallelMapConfig {
     fn down_chain_protocol(&self) -> DownChainProtocol {
         DownChainProtocol::Terminating
     }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        vec![("chain", &self.chain)]
+    }
 }
 
 #[async_trait]
@@ -137,6 +142,17 @@ impl Transform for ParallelMap {
     }
 }
 
+impl ParallelMapBuilder {
+    /// With more than one subchain requests run concurrently, so they may execute in any order even when `ordered_results` is set.
+    fn fan_out_guarantees(&self) -> DeliveryGuarantees {
+        if self.chains.len() > 1 {
+            DeliveryGuarantees::UNORDERED
+        } else {
+            DeliveryGuarantees::PASSTHROUGH
+        }
+    }
+}
+
 impl TransformBuilder for ParallelMapBuilder {
     fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
         Box::new(ParallelMap {
@@ -153,13 +169,20 @@ impl TransformBuilder for ParallelMapBuilder {
         NAME
     }
 
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        match self.chains.first() {
+            Some(chain) => self.fan_out_guarantees().then(chain.delivery_guarantees()),
+            None => self.fan_out_guarantees(),
+        }
+    }
+
     fn validate(&self) -> Vec<String> {
         let mut errors = self
             .chains
             .iter()
             .flat_map(|chain| {
                 chain
-                    .validate()
+                    .validate_with_up_chain(self.fan_out_guarantees())
                     .iter()
                     .map(|x| format!("  {x}"))
                     .collect::<Vec<String>>()
@@ -182,11 +205,33 @@ impl TransformBuilder for ParallelMapBuilder {
 mod parallel_map_tests {
     use crate::transforms::chain::TransformChainBuilder;
     use crate::transforms::debug::printer::DebugPrinter;
+    use crate::transforms::guarantees::DeliveryGuarantees;
     use crate::transforms::null::NullSink;
     use crate::transforms::parallel_map::ParallelMapBuilder;
-    use crate::transforms::TransformBuilder;
+    use crate::transforms::{Transform, TransformBuilder, TransformContextBuilder};
     use pretty_assertions::assert_eq;
 
+    /// A sink that requires its requests to be kept in order, like the kafka sinks
+    struct OrderedSink;
+
+    impl TransformBuilder for OrderedSink {
+        fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+            Box::new(NullSink {})
+        }
+
+        fn get_name(&self) -> &'static str {
+            "OrderedSink"
+        }
+
+        fn is_terminating(&self) -> bool {
+            true
+        }
+
+        fn required_guarantees(&self) -> DeliveryGuarantees {
+            DeliveryGuarantees::ORDERED
+        }
+    }
+
     #[tokio::test]
     async fn test_validate_invalid_chain() {
         let chain_1 = TransformChainBuilder::new(
@@ -240,4 +285,32 @@ mod parallel_map_tests {
 
         assert_eq!(transform.validate(), Vec::<String>::new());
     }
+
+    #[tokio::test]
+    async fn test_validate_subchain_requires_ordering() {
+        let ordered_chain = |name| TransformChainBuilder::new(vec![Box::new(OrderedSink)], name);
+
+        let transform = ParallelMapBuilder {
+            chains: vec![ordered_chain("test-chain-1")],
+            ordered: false,
+        };
+        assert_eq!(transform.validate(), Vec::<String>::new());
+
+        // requests run concurrently across the subchains so they can no longer be kept in order
+        let transform = ParallelMapBuilder {
+            chains: vec![ordered_chain("test-chain-1"), ordered_chain("test-chain-2")],
+            ordered: true,
+        };
+        let error = "    Transform \"OrderedSink\" requires requests to be kept in order but the transforms up chain of it only guarantee unordered, exactly once delivery.";
+        assert_eq!(
+            transform.validate(),
+            vec![
+                "ParallelMap:",
+                "  test-chain-1 chain:",
+                error,
+                "  test-chain-2 chain:",
+                error,
+            ]
+        );
+    }
 }
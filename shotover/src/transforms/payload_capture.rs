@@ -0,0 +1,388 @@
+use crate::frame::Frame;
+#[cfg(feature = "redis")]
+use crate::frame::{redis::redis_query_name, RedisFrame};
+#[cfg(feature = "cassandra")]
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PayloadCaptureConfig {
+    /// The number of captures kept for the chain, once full the oldest capture is discarded to make room for the next
+    pub capacity: usize,
+    /// Requests taking at least this long to be answered are captured, when not set only failed requests are captured
+    pub slow_threshold_ms: Option<u64>,
+    /// Capture the full payloads instead of redacting the values in them
+    #[serde(default)]
+    pub disable_redaction: bool,
+}
+
+const NAME: &str = "PayloadCapture";
+#[typetag::serde(name = "PayloadCapture")]
+#[async_trait(?Send)]
+impl TransformConfig for PayloadCaptureConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        if self.capacity == 0 {
+            bail!("capacity must be greater than 0");
+        }
+        Ok(Box::new(PayloadCaptureBuilder {
+            buffer: register(&transform_context.chain_name, self.capacity),
+            slow_threshold: self.slow_threshold_ms.map(Duration::from_millis),
+            redact: !self.disable_redaction,
+            errors_captured: counter!(
+                "shotover_payload_captures_count",
+                "chain" => transform_context.chain_name.clone(),
+                "reason" => "error"
+            ),
+            slow_captured: counter!(
+                "shotover_payload_captures_count",
+                "chain" => transform_context.chain_name,
+                "reason" => "slow"
+            ),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureReason {
+    Error,
+    Slow,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Capture {
+    /// Milliseconds since the unix epoch at which the response was received
+    pub captured_at_ms: u128,
+    pub reason: CaptureReason,
+    pub client: String,
+    pub duration_ms: u128,
+    pub request: String,
+    pub response: String,
+}
+
+struct RingBuffer {
+    capacity: usize,
+    captures: VecDeque<Capture>,
+}
+
+type Buffer = Arc<Mutex<RingBuffer>>;
+
+/// The captures of each chain, keyed by chain name
+static BUFFERS: Mutex<Vec<(String, Buffer)>> = Mutex::new(Vec::new());
+
+/// Returns the buffer of the chain, creating it if this is the first PayloadCapture in the chain.
+/// Every PayloadCapture in the same chain shares one buffer, using the largest configured capacity.
+fn register(chain_name: &str, capacity: usize) -> Buffer {
+    let mut buffers = BUFFERS.lock().unwrap();
+    if let Some((_, buffer)) = buffers.iter().find(|(name, _)| name == chain_name) {
+        let mut ring = buffer.lock().unwrap();
+        ring.capacity = ring.capacity.max(capacity);
+        return buffer.clone();
+    }
+    let buffer = Arc::new(Mutex::new(RingBuffer {
+        capacity,
+        captures: VecDeque::with_capacity(capacity),
+    }));
+    buffers.push((chain_name.to_owned(), buffer.clone()));
+    buffer
+}
+
+/// Returns the captures of every chain, oldest first, keyed by chain name
+pub(crate) fn captures() -> BTreeMap<String, Vec<Capture>> {
+    BUFFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(name, buffer)| {
+            (
+                name.clone(),
+                buffer.lock().unwrap().captures.iter().cloned().collect(),
+            )
+        })
+        .collect()
+}
+
+pub struct PayloadCaptureBuilder {
+    buffer: Buffer,
+    slow_threshold: Option<Duration>,
+    redact: bool,
+    errors_captured: Counter,
+    slow_captured: Counter,
+}
+
+impl TransformBuilder for PayloadCaptureBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(PayloadCapture {
+            buffer: self.buffer.clone(),
+            slow_threshold: self.slow_threshold,
+            redact: self.redact,
+            errors_captured: self.errors_captured.clone(),
+            slow_captured: self.slow_captured.clone(),
+            client: transform_context.client_details,
+            pending_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// Keeps the payloads of failed and slow requests along with their responses in a ring buffer per chain,
+/// which is served by the observability interface so that the requests behind an incident can be examined afterwards
+/// without logging every request.
+pub struct PayloadCapture {
+    buffer: Buffer,
+    slow_threshold: Option<Duration>,
+    redact: bool,
+    errors_captured: Counter,
+    slow_captured: Counter,
+    client: String,
+    /// Requests waiting for a response along with when they were sent down the chain
+    pending_requests: MessageIdMap<(Message, Instant)>,
+}
+
+#[async_trait]
+impl Transform for PayloadCapture {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let sent_at = Instant::now();
+        for request in &requests_wrapper.requests {
+            if !request.is_dummy() {
+                self.pending_requests
+                    .insert(request.id(), (request.clone(), sent_at));
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        let now = Instant::now();
+        for response in &mut responses {
+            let Some((mut request, sent_at)) = response
+                .request_id()
+                .and_then(|id| self.pending_requests.remove(&id))
+            else {
+                continue;
+            };
+            let duration = now - sent_at;
+            let reason = if response.is_error() {
+                self.errors_captured.increment(1);
+                CaptureReason::Error
+            } else if self
+                .slow_threshold
+                .map_or(false, |threshold| duration >= threshold)
+            {
+                self.slow_captured.increment(1);
+                CaptureReason::Slow
+            } else {
+                continue;
+            };
+            let capture = Capture {
+                captured_at_ms: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|x| x.as_millis())
+                    .unwrap_or_default(),
+                reason,
+                client: self.client.clone(),
+                duration_ms: duration.as_millis(),
+                request: describe(&mut request, self.redact),
+                response: describe(response, self.redact),
+            };
+
+            let mut ring = self.buffer.lock().unwrap();
+            if ring.captures.len() >= ring.capacity {
+                ring.captures.pop_front();
+            }
+            ring.captures.push_back(capture);
+        }
+
+        Ok(responses)
+    }
+}
+
+/// Describes the message, when `redact` is set the values in the message are left out of the description
+fn describe(message: &mut Message, redact: bool) -> String {
+    if !redact {
+        return message.to_high_level_string();
+    }
+    match message.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(frame)) => redact_redis(frame),
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(frame)) => redact_cassandra(frame),
+        Some(_) => format!("{:?} message", message.message_type()),
+        None => "Unparseable message".to_owned(),
+    }
+}
+
+/// Keeps the command name and the size of each argument
+#[cfg(feature = "redis")]
+fn redact_redis(frame: &RedisFrame) -> String {
+    match frame {
+        RedisFrame::Array(args) if redis_query_name(frame).is_some() => {
+            let mut description = redis_query_name(frame).unwrap();
+            for arg in &args[1..] {
+                description.push_str(&format!(" <{} bytes>", redis_size(arg)));
+            }
+            description
+        }
+        RedisFrame::Array(values) => format!("Array of {} values", values.len()),
+        RedisFrame::Error(error) => format!("Error {error}"),
+        RedisFrame::Integer(value) => format!("Integer {value}"),
+        RedisFrame::Null => "Null".to_owned(),
+        RedisFrame::SimpleString(_) | RedisFrame::BulkString(_) => {
+            format!("String of {} bytes", redis_size(frame))
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+fn redis_size(frame: &RedisFrame) -> usize {
+    match frame {
+        RedisFrame::SimpleString(bytes) | RedisFrame::BulkString(bytes) => bytes.len(),
+        RedisFrame::Error(error) => error.len(),
+        RedisFrame::Array(values) => values.iter().map(redis_size).sum(),
+        RedisFrame::Integer(_) | RedisFrame::Null => 0,
+    }
+}
+
+/// Keeps the statement with its literals replaced, leaving out bound values and the contents of results
+#[cfg(feature = "cassandra")]
+fn redact_cassandra(frame: &CassandraFrame) -> String {
+    match &frame.operation {
+        CassandraOperation::Query { query, .. } => redact_cql_literals(&query.to_string()),
+        CassandraOperation::Prepare(query) => {
+            format!(
+                "PREPARE {}",
+                redact_cql_literals(&String::from_utf8_lossy(query))
+            )
+        }
+        CassandraOperation::Execute(_) => "EXECUTE".to_owned(),
+        CassandraOperation::Batch(batch) => {
+            format!("BATCH of {} statements", batch.queries.len())
+        }
+        CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => {
+            format!("Rows: {} rows", rows.len())
+        }
+        CassandraOperation::Error(error) => format!("Error {:?}: {}", error.ty, error.message),
+        _ => format!("{:?}", frame.metadata().opcode),
+    }
+}
+
+/// Replaces the string, numeric, uuid and blob literals in a CQL statement with `?`
+#[cfg(feature = "cassandra")]
+fn redact_cql_literals(query: &str) -> String {
+    let mut redacted = String::with_capacity(query.len());
+    let mut chars = query.chars().peekable();
+    // true while within an identifier, whose digits are not literals
+    let mut in_identifier = false;
+    while let Some(c) = chars.next() {
+        if c == '\'' {
+            // two quotes within a string literal are an escaped quote
+            while let Some(c) = chars.next() {
+                if c == '\'' {
+                    if chars.peek() == Some(&'\'') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+            }
+            redacted.push('?');
+            in_identifier = false;
+        } else if c == '$' && chars.peek() == Some(&'$') {
+            chars.next();
+            let mut previous = ' ';
+            for c in chars.by_ref() {
+                if previous == '$' && c == '$' {
+                    break;
+                }
+                previous = c;
+            }
+            redacted.push('?');
+            in_identifier = false;
+        } else if c.is_ascii_digit() && !in_identifier {
+            while chars.peek().map_or(false, |c| {
+                c.is_ascii_alphanumeric() || *c == '.' || *c == '-'
+            }) {
+                chars.next();
+            }
+            redacted.push('?');
+        } else {
+            redacted.push(c);
+            in_identifier = c.is_alphanumeric() || c == '_';
+        }
+    }
+    redacted
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_redact_redis() {
+        let request = RedisFrame::Array(vec![
+            RedisFrame::BulkString("set".into()),
+            RedisFrame::BulkString("user:1".into()),
+            RedisFrame::BulkString("secret".into()),
+        ]);
+        assert_eq!(redact_redis(&request), "SET <6 bytes> <6 bytes>");
+        assert_eq!(
+            redact_redis(&RedisFrame::Error("ERR wrong type".into())),
+            "Error ERR wrong type"
+        );
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_redact_cql_literals() {
+        assert_eq!(
+            redact_cql_literals(
+                "INSERT INTO ks1.table2 (id, name, data) VALUES (5, 'it''s secret', 0xcafe)"
+            ),
+            "INSERT INTO ks1.table2 (id, name, data) VALUES (?, ?, ?)"
+        );
+        assert_eq!(
+            redact_cql_literals("SELECT * FROM t WHERE id = 123e4567-e89b-12d3-a456-426614174000 AND x = $$secret$$"),
+            "SELECT * FROM t WHERE id = ? AND x = ?"
+        );
+    }
+
+    #[test]
+    fn test_ring_buffer_shared_by_chain() {
+        let buffer = register("test_ring_buffer_shared_by_chain", 1);
+        let same_buffer = register("test_ring_buffer_shared_by_chain", 2);
+        assert!(Arc::ptr_eq(&buffer, &same_buffer));
+        assert_eq!(buffer.lock().unwrap().capacity, 2);
+        assert!(captures()["test_ring_buffer_shared_by_chain"].is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use shotover_plugin::{Action, LoadedPlugin, PluginError, PluginInstance};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    /// Path to the shared library containing the plugin
+    pub path: String,
+    /// Passed to the plugin as a yaml string when each instance is created
+    #[serde(default)]
+    pub config: serde_yaml::Value,
+}
+
+const NAME: &str = "Plugin";
+#[typetag::serde(name = "Plugin")]
+#[async_trait(?Send)]
+impl TransformConfig for PluginConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let plugin = LoadedPlugin::load(Path::new(&self.path))?;
+        let config = serde_yaml::to_string(&self.config)
+            .with_context(|| format!("Failed to serialize config for plugin {}", self.path))?;
+
+        // Create and immediately drop an instance so that invalid config is reported at startup
+        // instead of when the first client connects.
+        plugin.create_instance(&config)?;
+
+        Ok(Box::new(PluginBuilder {
+            panics: counter!("shotover_plugin_panics_count", "plugin" => self.path.clone()),
+            plugin,
+            config,
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+pub struct PluginBuilder {
+    plugin: Arc<LoadedPlugin>,
+    config: String,
+    panics: Counter,
+}
+
+impl TransformBuilder for PluginBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(PluginTransform {
+            instance: self.plugin.create_instance(&self.config),
+            panics: self.panics.clone(),
+            rejected_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+pub struct PluginTransform {
+    instance: Result<PluginInstance, PluginError>,
+    panics: Counter,
+    rejected_requests: MessageIdMap<Message>,
+}
+
+impl PluginTransform {
+    fn instance(&mut self) -> Result<&mut PluginInstance> {
+        self.instance.as_mut().map_err(|e| anyhow!("{e}"))
+    }
+
+    fn record_panic(&self, err: PluginError) -> anyhow::Error {
+        if let PluginError::Panicked { .. } = err {
+            self.panics.increment(1);
+        }
+        err.into()
+    }
+}
+
+/// Requests cross the plugin boundary as raw bytes so they must not have been modified by a prior transform.
+fn raw_bytes(message: &Message) -> Result<bytes::Bytes> {
+    message.raw_bytes().cloned().ok_or_else(|| {
+        anyhow!("Plugin transform received a message that was modified by an earlier transform, the Plugin transform must come before any transforms that modify messages")
+    })
+}
+
+#[async_trait]
+impl Transform for PluginTransform {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in &mut requests_wrapper.requests {
+            if request.is_dummy() {
+                continue;
+            }
+            let bytes = raw_bytes(request)?;
+            let action = self
+                .instance()?
+                .on_request(&bytes)
+                .map_err(|e| self.record_panic(e))?;
+            match action {
+                Action::Forward => {}
+                Action::Replace(bytes) => request.replace_with_raw_bytes(bytes.into()),
+                Action::Reject(error) => {
+                    self.rejected_requests.insert(
+                        request.id(),
+                        request
+                            .from_request_to_error_response(error)
+                            .map_err(|e| e.context("Failed to reject message"))?,
+                    );
+                    request.replace_with_dummy();
+                }
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if let Some(request_id) = response.request_id() {
+                if let Some(error_response) = self.rejected_requests.remove(&request_id) {
+                    *response = error_response;
+                    continue;
+                }
+            }
+            // Responses generated by shotover itself, such as errors, have no raw bytes and are not shown to the plugin.
+            let Some(bytes) = response.raw_bytes().cloned() else {
+                continue;
+            };
+            let action = self
+                .instance()?
+                .on_response(&bytes)
+                .map_err(|e| self.record_panic(e))?;
+            match action {
+                Action::Forward => {}
+                Action::Replace(bytes) => response.replace_with_raw_bytes(bytes.into()),
+                Action::Reject(error) => {
+                    *response = response.from_response_to_error_response(error)?
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::codec::CodecState;
+    use crate::frame::{Frame, RedisFrame};
+    use bytes::Bytes;
+    use pretty_assertions::assert_eq;
+
+    // Calling into a real plugin across the shared library boundary is tested in `shotover-plugin/tests/ffi.rs`
+
+    #[test]
+    fn test_raw_bytes() {
+        let bytes = Bytes::from_static(b"*1\r\n$4\r\nPING\r\n");
+        let message = Message::from_bytes(bytes.clone(), CodecState::Redis);
+        assert_eq!(raw_bytes(&message).unwrap(), bytes);
+
+        let modified = Message::from_frame(Frame::Redis(RedisFrame::SimpleString("PONG".into())));
+        assert!(raw_bytes(&modified)
+            .unwrap_err()
+            .to_string()
+            .contains("modified by an earlier transform"));
+    }
+}
@@ -0,0 +1,466 @@
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+#[cfg(feature = "cassandra")]
+use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult};
+use crate::frame::{Frame, MessageType};
+#[cfg(feature = "cassandra")]
+use crate::message::MessageIdSet;
+use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+#[cfg(feature = "cassandra")]
+use cassandra_protocol::types::CBytesShort;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "cassandra")]
+use std::collections::HashSet;
+use std::net::IpAddr;
+#[cfg(feature = "cassandra")]
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ReadOnlyConfig {
+    /// What to do with writes
+    pub mode: ReadOnlyMode,
+    /// Only connections from these clients are read only, when not set every connection to the chain is read only
+    pub clients: Option<Vec<IpAddr>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum ReadOnlyMode {
+    /// Respond to writes with an error, defaults to an error stating that the connection is read only
+    Reject { error: Option<String> },
+    /// Respond to writes as if they succeeded, without sending them to the database
+    DryRun,
+}
+
+const NAME: &str = "ReadOnly";
+#[typetag::serde(name = "ReadOnly")]
+#[async_trait(?Send)]
+impl TransformConfig for ReadOnlyConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(ReadOnlyBuilder {
+            mode: self.mode.clone(),
+            clients: self.clients.clone(),
+            intercepted: counter!(
+                "shotover_read_only_intercepted_writes_count",
+                "chain" => transform_context.chain_name
+            ),
+            #[cfg(feature = "cassandra")]
+            prepared_writes: Arc::new(Mutex::new(HashSet::new())),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// The ids of every prepared statement that writes.
+/// Shared between connections since a statement can be prepared on one connection and executed on another.
+#[cfg(feature = "cassandra")]
+type PreparedWrites = Arc<Mutex<HashSet<CBytesShort>>>;
+
+pub struct ReadOnlyBuilder {
+    mode: ReadOnlyMode,
+    clients: Option<Vec<IpAddr>>,
+    intercepted: Counter,
+    #[cfg(feature = "cassandra")]
+    prepared_writes: PreparedWrites,
+}
+
+impl TransformBuilder for ReadOnlyBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        let read_only = match &self.clients {
+            Some(clients) => transform_context
+                .client_details
+                .parse::<IpAddr>()
+                .map(|ip| clients.contains(&ip))
+                .unwrap_or(false),
+            None => true,
+        };
+
+        Box::new(ReadOnly {
+            mode: self.mode.clone(),
+            read_only,
+            client: transform_context.client_details,
+            intercepted: self.intercepted.clone(),
+            intercepted_requests: MessageIdMap::default(),
+            #[cfg(feature = "cassandra")]
+            prepared_writes: self.prepared_writes.clone(),
+            #[cfg(feature = "cassandra")]
+            pending_prepares: MessageIdSet::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Writes are answered without being delivered
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
+    fn validate(&self) -> Vec<String> {
+        match &self.clients {
+            Some(clients) if clients.is_empty() => vec![
+                format!("{NAME}:"),
+                "  clients must not be empty, remove it to make every connection read only"
+                    .to_owned(),
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+/// Stops writes from reaching the database for the configured clients, answering them with an error or, in dry run mode, with a synthetic success.
+/// Every intercepted write is logged, which makes dry run mode useful for validating a new release of an application against production.
+///
+/// Reads and requests that only affect the state of the connection are passed down the chain unmodified.
+pub struct ReadOnly {
+    mode: ReadOnlyMode,
+    /// False if the client of this connection is not one of the configured clients, in which case every request is passed down the chain
+    read_only: bool,
+    client: String,
+    intercepted: Counter,
+    intercepted_requests: MessageIdMap<Message>,
+    #[cfg(feature = "cassandra")]
+    prepared_writes: PreparedWrites,
+    /// In flight PREPARE requests for statements that write, the id of their prepared statement is recorded from the response
+    #[cfg(feature = "cassandra")]
+    pending_prepares: MessageIdSet,
+}
+
+#[async_trait]
+impl Transform for ReadOnly {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        if !self.read_only {
+            return requests_wrapper.call_next_transform().await;
+        }
+
+        for request in requests_wrapper.requests.iter_mut() {
+            if request.is_dummy() || !self.is_write(request) {
+                continue;
+            }
+            let Ok(metadata) = request.metadata() else {
+                continue;
+            };
+            info!(
+                "Intercepted write from {}: {}",
+                self.client,
+                request.to_high_level_string()
+            );
+            let mut response = match &self.mode {
+                ReadOnlyMode::Reject { error } => metadata.to_error_response(
+                    error
+                        .clone()
+                        .unwrap_or_else(|| "The connection is read only".to_owned()),
+                )?,
+                ReadOnlyMode::DryRun => dry_run_response(metadata),
+            };
+            response.set_request_id(request.id());
+            self.intercepted_requests.insert(request.id(), response);
+            request.replace_with_dummy();
+            self.intercepted.increment(1);
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            if let Some(intercepted) = self.intercepted_requests.remove(&request_id) {
+                *response = intercepted;
+                continue;
+            }
+            #[cfg(feature = "cassandra")]
+            self.record_prepared_write(request_id, response);
+        }
+
+        Ok(responses)
+    }
+}
+
+impl ReadOnly {
+    /// Records the id of the prepared statement if the response is to a PREPARE of a statement that writes
+    #[cfg(feature = "cassandra")]
+    fn record_prepared_write(
+        &mut self,
+        request_id: crate::message::MessageId,
+        response: &mut Message,
+    ) {
+        if self.pending_prepares.remove(&request_id) {
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+                ..
+            })) = response.frame()
+            {
+                self.prepared_writes
+                    .lock()
+                    .unwrap()
+                    .insert(prepared.id.clone());
+            }
+        }
+    }
+
+    /// Returns true if the request could modify the database
+    fn is_write(&mut self, request: &mut Message) -> bool {
+        #[cfg(feature = "cassandra")]
+        let request_id = request.id();
+        match request.frame() {
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(frame)) => match &mut frame.operation {
+                // Preparing a statement does not modify the database, but executing it later does
+                CassandraOperation::Prepare(body) => {
+                    if crate::transforms::cassandra::ddl_guard::prepared_query(body)
+                        .map(|query| {
+                            is_cassandra_write(&crate::frame::cassandra::parse_statement_single(
+                                query,
+                            ))
+                        })
+                        .unwrap_or(false)
+                    {
+                        self.pending_prepares.insert(request_id);
+                    }
+                    false
+                }
+                CassandraOperation::Execute(execute) => {
+                    self.prepared_writes.lock().unwrap().contains(&execute.id)
+                }
+                CassandraOperation::Batch(_) => true,
+                operation => operation
+                    .queries()
+                    .any(|statement| is_cassandra_write(statement)),
+            },
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(RedisFrame::Array(args))) => match args.first() {
+                Some(RedisFrame::BulkString(command)) => {
+                    !REDIS_READ_ONLY_COMMANDS.contains(&command.to_ascii_uppercase().as_slice())
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+/// The response the database would return for a successful write
+fn dry_run_response(metadata: Metadata) -> Message {
+    #[allow(unreachable_code)]
+    Message::from_frame(match metadata {
+        #[cfg(feature = "redis")]
+        Metadata::Redis => Frame::Redis(RedisFrame::SimpleString("OK".into())),
+        #[cfg(feature = "cassandra")]
+        Metadata::Cassandra(metadata) => Frame::Cassandra(CassandraFrame {
+            version: metadata.version,
+            stream_id: metadata.stream_id,
+            tracing: crate::frame::cassandra::Tracing::Response(None),
+            warnings: vec![],
+            operation: CassandraOperation::Result(CassandraResult::Void),
+        }),
+        #[cfg(feature = "kafka")]
+        Metadata::Kafka => unreachable!("ReadOnly does not accept kafka"),
+        #[cfg(feature = "opensearch")]
+        Metadata::OpenSearch => unreachable!("ReadOnly does not accept opensearch"),
+    })
+}
+
+#[cfg(feature = "cassandra")]
+fn is_cassandra_write(statement: &cql3_parser::cassandra_statement::CassandraStatement) -> bool {
+    use crate::message::QueryType;
+    use cql3_parser::cassandra_statement::CassandraStatement;
+    match statement {
+        // USE only changes the keyspace of the connection
+        CassandraStatement::Use(_) => false,
+        // The parser does not support every statement, so fall back to the leading keyword rather than let unparsed writes through
+        CassandraStatement::Unknown(cql) => !cql
+            .split_whitespace()
+            .next()
+            .map(|keyword| {
+                ["SELECT", "LIST", "DESCRIBE", "DESC"]
+                    .iter()
+                    .any(|read| keyword.eq_ignore_ascii_case(read))
+            })
+            .unwrap_or(false),
+        statement => !matches!(
+            crate::frame::cassandra::get_query_type(statement),
+            QueryType::Read
+        ),
+    }
+}
+
+/// Redis commands that never modify data, every other command is treated as a write.
+#[cfg(feature = "redis")]
+const REDIS_READ_ONLY_COMMANDS: &[&[u8]] = &[
+    b"AUTH",
+    b"BITCOUNT",
+    b"BITPOS",
+    b"CLIENT",
+    b"COMMAND",
+    b"DBSIZE",
+    b"DISCARD",
+    b"ECHO",
+    b"EXEC",
+    b"EXISTS",
+    b"GEODIST",
+    b"GEOHASH",
+    b"GEOPOS",
+    b"GET",
+    b"GETBIT",
+    b"GETRANGE",
+    b"HELLO",
+    b"HEXISTS",
+    b"HGET",
+    b"HGETALL",
+    b"HKEYS",
+    b"HLEN",
+    b"HMGET",
+    b"HSCAN",
+    b"HSTRLEN",
+    b"HVALS",
+    b"INFO",
+    b"KEYS",
+    b"LINDEX",
+    b"LLEN",
+    b"LPOS",
+    b"LRANGE",
+    b"MGET",
+    b"MULTI",
+    b"PFCOUNT",
+    b"PING",
+    b"PSUBSCRIBE",
+    b"PTTL",
+    b"PUNSUBSCRIBE",
+    b"QUIT",
+    b"RANDOMKEY",
+    b"READONLY",
+    b"READWRITE",
+    b"RESET",
+    b"SCAN",
+    b"SCARD",
+    b"SELECT",
+    b"SINTER",
+    b"SISMEMBER",
+    b"SMEMBERS",
+    b"SMISMEMBER",
+    b"SRANDMEMBER",
+    b"SSCAN",
+    b"STRLEN",
+    b"SUBSCRIBE",
+    b"SUNION",
+    b"TIME",
+    b"TTL",
+    b"TYPE",
+    b"UNSUBSCRIBE",
+    b"UNWATCH",
+    b"WATCH",
+    b"XLEN",
+    b"XRANGE",
+    b"XREAD",
+    b"XREVRANGE",
+    b"ZCARD",
+    b"ZCOUNT",
+    b"ZMSCORE",
+    b"ZRANGE",
+    b"ZRANGEBYLEX",
+    b"ZRANGEBYSCORE",
+    b"ZRANK",
+    b"ZREVRANGE",
+    b"ZREVRANK",
+    b"ZSCAN",
+    b"ZSCORE",
+];
+
+#[cfg(all(test, feature = "redis", feature = "cassandra"))]
+mod test {
+    use super::*;
+    use crate::frame::cassandra::parse_statement_single;
+    use crate::transforms::testing::{redis_request, ChainTester};
+    use pretty_assertions::assert_eq;
+
+    fn read_only() -> ReadOnly {
+        ReadOnly {
+            mode: ReadOnlyMode::DryRun,
+            read_only: true,
+            client: String::new(),
+            intercepted: Counter::noop(),
+            intercepted_requests: MessageIdMap::default(),
+            prepared_writes: Default::default(),
+            pending_prepares: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_redis_writes() {
+        let mut transform = read_only();
+        assert!(!transform.is_write(&mut redis_request(&["get", "key"])));
+        assert!(!transform.is_write(&mut redis_request(&["AUTH", "password"])));
+        assert!(transform.is_write(&mut redis_request(&["SET", "key", "value"])));
+        assert!(transform.is_write(&mut redis_request(&["APPEND", "key", "value"])));
+        assert!(transform.is_write(&mut redis_request(&["FLUSHALL"])));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_writes_do_not_reach_sink() {
+        let mut tester =
+            ChainTester::from_yaml("- ReadOnly:\n    mode: DryRun", MessageType::Redis)
+                .await
+                .unwrap();
+
+        let mut responses = tester
+            .send(vec![
+                redis_request(&["GET", "key"]),
+                redis_request(&["SET", "key", "value"]),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            responses[1].frame(),
+            Some(&mut Frame::Redis(RedisFrame::SimpleString("OK".into())))
+        );
+        let mut received = tester.sink().received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].frame(), redis_request(&["GET", "key"]).frame());
+    }
+
+    #[test]
+    fn test_cassandra_writes() {
+        assert!(!is_cassandra_write(&parse_statement_single(
+            "SELECT * FROM ks.table"
+        )));
+        assert!(!is_cassandra_write(&parse_statement_single("USE ks")));
+        assert!(is_cassandra_write(&parse_statement_single(
+            "INSERT INTO ks.table (id) VALUES (1)"
+        )));
+        assert!(is_cassandra_write(&parse_statement_single(
+            "DROP TABLE ks.table"
+        )));
+    }
+}
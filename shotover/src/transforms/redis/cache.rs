@@ -1,7 +1,10 @@
 use crate::config::chain::TransformChainConfig;
-use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType, RedisFrame};
+use crate::frame::{
+    CassandraFrame, CassandraOperation, CassandraResult, Frame, MessageType, RedisFrame,
+};
 use crate::message::{Message, MessageIdMap, Messages, Metadata};
 use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{
     DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
     TransformContextConfig, UpChainProtocol, Wrapper,
@@ -15,9 +18,13 @@ use cql3_parser::common::{FQName, Identifier, Operand, RelationElement, Relation
 use cql3_parser::select::Select;
 use itertools::Itertools;
 use metrics::{counter, Counter};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
 use tracing::{error, warn};
 
 /// Data is stored in Redis as a Hash (hset/hget) and constructed from the cassandra SELECT statement
@@ -36,6 +43,14 @@ use tracing::{error, warn};
 ///     `SELECT a, b, c as g FROM keyspace1.table2 WHERE e='foo' a[2]=3`
 /// will result in this redis command:
 ///     `hset "keyspace1.table2:'foo'" "a b c WHERE a[2]=3" $SELECT_RESPONSE_BYTES`
+///
+/// When negative caching is enabled, a SELECT response containing no rows is stored in the same field prefixed by
+/// `NEGATIVE_ENTRY_PREFIX` and the time at which it expires.
+/// Since it lives in the same hash as positive entries, it is invalidated by the same INSERT or UPDATE that creates the row.
+///
+/// When a ttl is configured, other responses are stored prefixed by `EXPIRING_ENTRY_PREFIX` and the time at which they expire.
+/// An entry that expired within the stale-while-revalidate window is still served, while a single request across all connections
+/// is sent to cassandra in the background to refresh it.
 
 // TODO: ensure quoted identifiers wont cause collisions in the above described format
 
@@ -82,6 +97,15 @@ impl From<&TableCacheSchemaConfig> for TableCacheSchema {
 pub struct RedisConfig {
     pub caching_schema: HashMap<String, TableCacheSchemaConfig>,
     pub chain: TransformChainConfig,
+    /// When set, responses containing no rows are only used for this many seconds after being cached
+    pub negative_ttl_seconds: Option<u64>,
+    /// When set, responses containing rows are only used for this many seconds after being cached
+    pub ttl_seconds: Option<u64>,
+    /// An expired entry is still served for this many seconds after it expires, while it is refreshed in the background
+    pub stale_while_revalidate_seconds: Option<u64>,
+    /// Each ttl is randomly lengthened or shortened by up to this fraction of itself,
+    /// so that entries cached at the same time do not all expire at the same time
+    pub ttl_jitter: Option<f64>,
 }
 
 const NAME: &str = "RedisCache";
@@ -93,6 +117,9 @@ impl TransformConfig for RedisConfig {
         _transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
         let missed_requests = counter!("shotover_cache_miss_count");
+        let negative_hits = counter!("shotover_cache_negative_hit_count");
+        let stale_hits = counter!("shotover_cache_stale_hit_count");
+        let refreshes = counter!("shotover_cache_refresh_count");
 
         let caching_schema: HashMap<FQName, TableCacheSchema> = self
             .caching_schema
@@ -109,6 +136,16 @@ impl TransformConfig for RedisConfig {
             cache_chain: self.chain.get_builder(transform_context_config).await?,
             caching_schema,
             missed_requests,
+            expiry: Expiry {
+                ttl: self.ttl_seconds.map(Duration::from_secs),
+                negative_ttl: self.negative_ttl_seconds.map(Duration::from_secs),
+                stale_window: Duration::from_secs(self.stale_while_revalidate_seconds.unwrap_or(0)),
+                ttl_jitter: self.ttl_jitter.unwrap_or(0.0),
+            },
+            negative_hits,
+            stale_hits,
+            refreshes,
+            refreshing: Default::default(),
         }))
     }
 
@@ -119,12 +156,72 @@ impl TransformConfig for RedisConfig {
     fn down_chain_protocol(&self) -> DownChainProtocol {
         DownChainProtocol::SameAsUpChain
     }
+
+    fn validate_config(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if let Some(ttl_jitter) = self.ttl_jitter {
+            if !(0.0..1.0).contains(&ttl_jitter) {
+                errors.push(format!(
+                    "ttl_jitter must be at least 0 and less than 1 but was {ttl_jitter}"
+                ));
+            }
+        }
+        if self.stale_while_revalidate_seconds.unwrap_or(0) != 0
+            && self.ttl_seconds.is_none()
+            && self.negative_ttl_seconds.is_none()
+        {
+            errors.push(
+                "stale_while_revalidate_seconds requires ttl_seconds or negative_ttl_seconds to be set"
+                    .to_owned(),
+            );
+        }
+        errors
+    }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        vec![("chain", &self.chain)]
+    }
+}
+
+/// Determines how long cached responses are used for
+#[derive(Clone, Copy, Default)]
+struct Expiry {
+    ttl: Option<Duration>,
+    negative_ttl: Option<Duration>,
+    stale_window: Duration,
+    ttl_jitter: f64,
 }
 
+impl Expiry {
+    /// The unix time in milliseconds at which a response cached at `now_millis` expires, None if it never expires
+    fn expires_at_millis(&self, negative: bool, now_millis: u64) -> Option<u64> {
+        let ttl = if negative {
+            self.negative_ttl
+        } else {
+            self.ttl
+        }?;
+        let ttl = if self.ttl_jitter > 0.0 {
+            let jitter = self.ttl_jitter;
+            ttl.mul_f64(rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter))
+        } else {
+            ttl
+        };
+        Some(now_millis + ttl.as_millis() as u64)
+    }
+}
+
+/// The address of every entry that is being refreshed, shared between all connections so that each entry is only refreshed once
+type Refreshing = Arc<Mutex<HashSet<HashAddress>>>;
+
 pub struct SimpleRedisCacheBuilder {
     cache_chain: TransformChainBuilder,
     caching_schema: HashMap<FQName, TableCacheSchema>,
     missed_requests: Counter,
+    expiry: Expiry,
+    negative_hits: Counter,
+    stale_hits: Counter,
+    refreshes: Counter,
+    refreshing: Refreshing,
 }
 
 impl TransformBuilder for SimpleRedisCacheBuilder {
@@ -133,6 +230,15 @@ impl TransformBuilder for SimpleRedisCacheBuilder {
             cache_chain: self.cache_chain.build(transform_context.clone()),
             caching_schema: self.caching_schema.clone(),
             missed_requests: self.missed_requests.clone(),
+            expiry: self.expiry,
+            negative_hits: self.negative_hits.clone(),
+            stale_hits: self.stale_hits.clone(),
+            refreshes: self.refreshes.clone(),
+            refreshing: self.refreshing.clone(),
+            force_run_chain: transform_context.force_run_chain.clone(),
+            pending_refreshes: vec![],
+            refresh_requests: Default::default(),
+            stream_ids: Default::default(),
             pending_cache_requests: Default::default(),
             cache_hit_cassandra_responses: vec![],
             cache_miss_cassandra_requests: vec![],
@@ -143,6 +249,11 @@ impl TransformBuilder for SimpleRedisCacheBuilder {
         NAME
     }
 
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Reads are answered from the cache when possible
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
     fn validate(&self) -> Vec<String> {
         let mut errors = self
             .cache_chain
@@ -163,7 +274,19 @@ pub struct SimpleRedisCache {
     cache_chain: TransformChain,
     caching_schema: HashMap<FQName, TableCacheSchema>,
     missed_requests: Counter,
-    pending_cache_requests: MessageIdMap<Message>,
+    expiry: Expiry,
+    negative_hits: Counter,
+    stale_hits: Counter,
+    refreshes: Counter,
+    refreshing: Refreshing,
+    force_run_chain: Arc<Notify>,
+    /// Requests refreshing stale entries, sent down the chain alongside the next requests
+    pending_refreshes: Vec<(HashAddress, Message)>,
+    /// Refresh requests that have been sent down the chain, their responses are not returned to the client
+    refresh_requests: MessageIdMap<HashAddress>,
+    stream_ids: StreamIds,
+    /// The cassandra request and the address of its cache entry for each request sent to the cache
+    pending_cache_requests: MessageIdMap<(Message, HashAddress)>,
 
     /// cleared by the end of every `Transform::transform` call, stored here to avoid reallocation
     cache_hit_cassandra_responses: Vec<Message>,
@@ -172,7 +295,7 @@ pub struct SimpleRedisCache {
 }
 
 impl SimpleRedisCache {
-    fn build_cache_query(&mut self, request: &mut Message) -> Option<Message> {
+    fn build_cache_query(&mut self, request: &mut Message) -> Option<(Message, HashAddress)> {
         if let Some(Frame::Cassandra(CassandraFrame {
             operation: CassandraOperation::Query { query, .. },
             ..
@@ -183,14 +306,15 @@ impl SimpleRedisCache {
                     if let Some(table_cache_schema) = self.caching_schema.get(table_name) {
                         match build_redis_key_from_cql3(query, table_cache_schema) {
                             Ok(address) => {
-                                return Some(Message::from_frame_diverged(
+                                let redis_request = Message::from_frame_diverged(
                                     Frame::Redis(RedisFrame::Array(vec![
                                         RedisFrame::BulkString("HGET".into()),
-                                        RedisFrame::BulkString(address.key),
-                                        RedisFrame::BulkString(address.field),
+                                        RedisFrame::BulkString(address.key.clone()),
+                                        RedisFrame::BulkString(address.field.clone()),
                                     ])),
                                     request,
-                                ));
+                                );
+                                return Some((redis_request, address));
                             }
                             Err(_e) => {} // TODO match Err(()) here or just have build_redis_key_from_cql3 return Option
                         }
@@ -204,7 +328,7 @@ impl SimpleRedisCache {
 
     fn unwrap_cache_response(&mut self, redis_responses: Messages) {
         for mut redis_response in redis_responses {
-            let original_request = self
+            let (original_request, address) = self
                 .pending_cache_requests
                 .remove(
                     &redis_response
@@ -220,9 +344,31 @@ impl SimpleRedisCache {
                             None
                         }
                         RedisFrame::BulkString(redis_bytes) => {
-                            match CassandraFrame::from_bytes(redis_bytes.clone(), Compression::None)
+                            let cached = match decode_cache_entry(
+                                redis_bytes,
+                                now_millis(),
+                                self.expiry.stale_window,
+                            ) {
+                                CacheEntry::Positive(cached) => Some(cached),
+                                CacheEntry::Negative(cached) => {
+                                    self.negative_hits.increment(1);
+                                    Some(cached)
+                                }
+                                CacheEntry::Stale(cached) => {
+                                    self.stale_hits.increment(1);
+                                    self.schedule_refresh(&original_request, address);
+                                    Some(cached)
+                                }
+                                CacheEntry::Expired => {
+                                    self.missed_requests.increment(1);
+                                    None
+                                }
+                            };
+                            match cached
+                                .map(|cached| CassandraFrame::from_bytes(cached, Compression::None))
                             {
-                                Ok(mut response_frame) => {
+                                None => None,
+                                Some(Ok(mut response_frame)) => {
                                     match original_request.metadata() {
                                         Ok(Metadata::Cassandra(meta)) => {
                                             if response_frame.version == meta.version {
@@ -245,7 +391,7 @@ impl SimpleRedisCache {
                                         }
                                     }
                                 }
-                                Err(err) => {
+                                Some(Err(err)) => {
                                     error!("Failed to decode cached cassandra message {err:?}");
                                     None
                                 }
@@ -283,9 +429,9 @@ impl SimpleRedisCache {
         for mut cassandra_request in cassandra_requests.drain(..) {
             match self.build_cache_query(&mut cassandra_request) {
                 // The request is cacheable, store the cassandra request for later and send the redis request
-                Some(redis_request) => {
+                Some((redis_request, address)) => {
                     self.pending_cache_requests
-                        .insert(cassandra_request.id(), cassandra_request);
+                        .insert(cassandra_request.id(), (cassandra_request, address));
                     redis_requests.push(redis_request);
                 }
                 // The request is not cacheable, add it directly to the cache miss list
@@ -303,6 +449,14 @@ impl SimpleRedisCache {
         Ok(())
     }
 
+    /// Queues a request refreshing the entry at `address`, unless some connection is already refreshing it
+    fn schedule_refresh(&mut self, request: &Message, address: HashAddress) {
+        if self.refreshing.lock().unwrap().insert(address.clone()) {
+            self.pending_refreshes
+                .push((address, request.clone_with_new_id()));
+        }
+    }
+
     /// Clears the cache for the entire table
     /// TODO make this drop only the specified keys not the entire cache
     fn drop_table(&self, _statement: &CassandraStatement, response: &Message) -> Message {
@@ -354,7 +508,19 @@ impl SimpleRedisCache {
                         // TODO: two performance issues here:
                         // 1. we should be able to generate the encoded bytes without cloning the entire frame
                         // 2. we should be able to directly use the raw bytes when the message has not yet been mutated
-                        let encoded = frame.clone().encode(Compression::None);
+                        let mut encoded = frame.clone().encode(Compression::None);
+
+                        let negative = is_empty_result(&frame.operation);
+                        if let Some(expires_at) =
+                            self.expiry.expires_at_millis(negative, now_millis())
+                        {
+                            let prefix = if negative {
+                                NEGATIVE_ENTRY_PREFIX
+                            } else {
+                                EXPIRING_ENTRY_PREFIX
+                            };
+                            encoded = encode_entry(prefix, expires_at, &encoded);
+                        }
 
                         return Ok(Some(Message::from_frame_at_instant(
                             Frame::Redis(RedisFrame::Array(vec![
@@ -424,6 +590,145 @@ impl SimpleRedisCache {
     }
 }
 
+/// Prefix of cached values holding a negative entry, followed by the big endian unix time in milliseconds at which it expires.
+/// Cached cassandra responses always begin with a version byte that has the response bit set, so can never begin with this prefix.
+const NEGATIVE_ENTRY_PREFIX: &[u8] = b"NEG";
+
+/// Prefix of cached values holding any other response that has a ttl, followed by the big endian unix time in milliseconds at which it expires.
+const EXPIRING_ENTRY_PREFIX: &[u8] = b"TTL";
+
+#[derive(PartialEq, Debug)]
+enum CacheEntry {
+    Positive(Bytes),
+    Negative(Bytes),
+    /// Expired but within the stale-while-revalidate window
+    Stale(Bytes),
+    Expired,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn is_empty_result(operation: &CassandraOperation) -> bool {
+    matches!(operation, CassandraOperation::Result(CassandraResult::Rows { rows, .. }) if rows.is_empty())
+}
+
+fn encode_entry(prefix: &[u8], expires_at_millis: u64, encoded: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(prefix.len() + 8 + encoded.len());
+    entry.extend_from_slice(prefix);
+    entry.extend_from_slice(&expires_at_millis.to_be_bytes());
+    entry.extend_from_slice(encoded);
+    entry
+}
+
+fn decode_cache_entry(bytes: &Bytes, now_millis: u64, stale_window: Duration) -> CacheEntry {
+    let (negative, start) = if bytes.starts_with(NEGATIVE_ENTRY_PREFIX) {
+        (true, NEGATIVE_ENTRY_PREFIX.len())
+    } else if bytes.starts_with(EXPIRING_ENTRY_PREFIX) {
+        (false, EXPIRING_ENTRY_PREFIX.len())
+    } else {
+        return CacheEntry::Positive(bytes.clone());
+    };
+    let Some(expires_at) = bytes.get(start..start + 8) else {
+        return CacheEntry::Expired;
+    };
+    let expires_at = u64::from_be_bytes(expires_at.try_into().unwrap());
+    let value = bytes.slice(start + 8..);
+    if now_millis < expires_at {
+        if negative {
+            CacheEntry::Negative(value)
+        } else {
+            CacheEntry::Positive(value)
+        }
+    } else if now_millis < expires_at + stale_window.as_millis() as u64 {
+        CacheEntry::Stale(value)
+    } else {
+        CacheEntry::Expired
+    }
+}
+
+/// Tracks the stream ids of the requests sent down the chain until they are responded to,
+/// so that a refresh never shares its stream id with another request in flight on the connection.
+///
+/// The client does not know about the refreshes so it may reuse the stream id of one still in flight,
+/// such a request is sent with another stream id and the client's stream id is restored on its response.
+#[derive(Default)]
+struct StreamIds {
+    /// The stream id of every request from the client that is in flight
+    requests: MessageIdMap<i16>,
+    /// The stream id of every refresh that is in flight
+    refreshes: MessageIdMap<i16>,
+    /// The client's stream id of every request in flight that was sent with another stream id
+    remapped: MessageIdMap<i16>,
+}
+
+impl StreamIds {
+    /// A stream id that neither `requests` nor any request in flight use
+    fn unused(&self, requests: &[Message]) -> i16 {
+        // Clients allocate stream ids from 0 upwards, so search from the top to find one quickly
+        (0..=i16::MAX)
+            .rev()
+            .find(|id| {
+                !requests
+                    .iter()
+                    .any(|request| request.stream_id() == Some(*id))
+                    && !self.requests.values().any(|x| x == id)
+                    && !self.refreshes.values().any(|x| x == id)
+            })
+            .unwrap_or(i16::MAX)
+    }
+
+    /// Gives the client's requests that reuse the stream id of a refresh in flight another stream id,
+    /// then gives each refresh an unused stream id and appends it to the requests.
+    fn send(&mut self, requests: &mut Vec<Message>, refreshes: Vec<Message>) {
+        for i in 0..requests.len() {
+            let Some(stream_id) = requests[i].stream_id() else {
+                continue;
+            };
+            if self.refreshes.values().any(|x| *x == stream_id) {
+                let new_stream_id = self.unused(requests);
+                set_stream_id(&mut requests[i], new_stream_id);
+                self.remapped.insert(requests[i].id(), stream_id);
+            }
+        }
+        for request in requests.iter() {
+            if let Some(stream_id) = request.stream_id() {
+                self.requests.insert(request.id(), stream_id);
+            }
+        }
+
+        for mut refresh in refreshes {
+            let stream_id = self.unused(requests);
+            set_stream_id(&mut refresh, stream_id);
+            self.refreshes.insert(refresh.id(), stream_id);
+            requests.push(refresh);
+        }
+    }
+
+    /// Stops tracking the request that `response` responds to, restoring the client's stream id if it was changed
+    fn receive(&mut self, response: &mut Message) {
+        let Some(request_id) = response.request_id() else {
+            return;
+        };
+        self.requests.remove(&request_id);
+        self.refreshes.remove(&request_id);
+        if let Some(stream_id) = self.remapped.remove(&request_id) {
+            set_stream_id(response, stream_id);
+        }
+    }
+}
+
+fn set_stream_id(message: &mut Message, stream_id: i16) {
+    if let Some(Frame::Cassandra(frame)) = message.frame() {
+        frame.stream_id = stream_id;
+    }
+    message.invalidate_cache();
+}
+
 fn is_cacheable(statement: &CassandraStatement) -> CacheableState {
     match statement {
         CassandraStatement::Select(select) => {
@@ -535,7 +840,7 @@ fn populate_value_map_from_where_clause(
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 struct HashAddress {
     key: Bytes,
     field: Bytes,
@@ -618,6 +923,10 @@ impl Transform for SimpleRedisCache {
     }
 
     async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        // Only send the refreshes scheduled by earlier calls,
+        // so that the stale responses served by this call are returned without waiting on their refresh
+        let refreshes = std::mem::take(&mut self.pending_refreshes);
+
         self.read_from_cache(&mut requests_wrapper.requests, requests_wrapper.local_addr)
             .await
             .unwrap_or_else(|err| error!("Failed to fetch from cache: {err:?}"));
@@ -629,32 +938,89 @@ impl Transform for SimpleRedisCache {
             &mut requests_wrapper.requests,
             &mut self.cache_miss_cassandra_requests,
         );
+        let refreshes = refreshes
+            .into_iter()
+            .map(|(address, refresh)| {
+                self.refresh_requests.insert(refresh.id(), address);
+                self.refreshes.increment(1);
+                refresh
+            })
+            .collect();
+        // Responses to requests sent by earlier calls may only arrive in a later call,
+        // so the stream ids of all requests in flight are tracked across calls.
+        self.stream_ids
+            .send(&mut requests_wrapper.requests, refreshes);
+
         let mut responses = self
             .execute_upstream_and_write_to_cache(requests_wrapper)
             .await?;
+        for response in &mut responses {
+            self.stream_ids.receive(response);
+        }
+
+        // The refreshed entries were written to the cache, the responses themselves were not requested by the client
+        if !self.refresh_requests.is_empty() {
+            let mut refreshing = self.refreshing.lock().unwrap();
+            responses.retain(|response| {
+                match response
+                    .request_id()
+                    .and_then(|id| self.refresh_requests.remove(&id))
+                {
+                    Some(address) => {
+                        refreshing.remove(&address);
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
 
         // add the cache hits to the final response
         responses.append(&mut self.cache_hit_cassandra_responses);
 
+        if !self.pending_refreshes.is_empty() {
+            // Ensure the refreshes are sent even if the client sends no more requests
+            self.force_run_chain.notify_one();
+        }
+
         Ok(responses)
     }
 }
 
+impl Drop for SimpleRedisCache {
+    fn drop(&mut self) {
+        // Let other connections refresh the entries that this connection never finished refreshing
+        let mut refreshing = self.refreshing.lock().unwrap();
+        for (address, _) in &self.pending_refreshes {
+            refreshing.remove(address);
+        }
+        for address in self.refresh_requests.values() {
+            refreshing.remove(address);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::frame::cassandra::parse_statement_single;
+    use crate::frame::cassandra::{parse_statement_single, Tracing};
+    use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame};
+    use crate::message::Message;
     use crate::transforms::chain::TransformChainBuilder;
     use crate::transforms::debug::printer::DebugPrinter;
     use crate::transforms::null::NullSink;
     use crate::transforms::redis::cache::{
-        build_redis_key_from_cql3, HashAddress, SimpleRedisCacheBuilder, TableCacheSchema,
+        build_redis_key_from_cql3, decode_cache_entry, encode_entry, CacheEntry, Expiry,
+        HashAddress, SimpleRedisCacheBuilder, StreamIds, TableCacheSchema, EXPIRING_ENTRY_PREFIX,
+        NEGATIVE_ENTRY_PREFIX,
     };
     use crate::transforms::TransformBuilder;
     use bytes::Bytes;
+    use cassandra_protocol::frame::Version;
     use cql3_parser::common::Identifier;
     use metrics::counter;
     use pretty_assertions::assert_eq;
     use std::collections::HashMap;
+    use std::time::Duration;
 
     #[test]
     fn equal_test() {
@@ -848,12 +1214,76 @@ mod test {
         );
     }
 
+    #[test]
+    fn negative_entry_test() {
+        let response = Bytes::from_static(&[0x84, 0, 0, 0, 8]);
+        let entry = Bytes::from(encode_entry(NEGATIVE_ENTRY_PREFIX, 1000, &response));
+
+        assert_eq!(
+            decode_cache_entry(&entry, 999, Duration::ZERO),
+            CacheEntry::Negative(response.clone())
+        );
+        assert_eq!(
+            decode_cache_entry(&entry, 1000, Duration::ZERO),
+            CacheEntry::Expired
+        );
+        assert_eq!(
+            decode_cache_entry(&response, 1000, Duration::ZERO),
+            CacheEntry::Positive(response.clone())
+        );
+    }
+
+    #[test]
+    fn stale_entry_test() {
+        let response = Bytes::from_static(&[0x84, 0, 0, 0, 8]);
+        let entry = Bytes::from(encode_entry(EXPIRING_ENTRY_PREFIX, 1000, &response));
+        let stale_window = Duration::from_secs(1);
+
+        assert_eq!(
+            decode_cache_entry(&entry, 999, stale_window),
+            CacheEntry::Positive(response.clone())
+        );
+        assert_eq!(
+            decode_cache_entry(&entry, 1000, stale_window),
+            CacheEntry::Stale(response.clone())
+        );
+        assert_eq!(
+            decode_cache_entry(&entry, 1999, stale_window),
+            CacheEntry::Stale(response.clone())
+        );
+        assert_eq!(
+            decode_cache_entry(&entry, 2000, stale_window),
+            CacheEntry::Expired
+        );
+    }
+
+    #[test]
+    fn jittered_expiry_test() {
+        let expiry = Expiry {
+            ttl: Some(Duration::from_secs(100)),
+            negative_ttl: None,
+            stale_window: Duration::ZERO,
+            ttl_jitter: 0.1,
+        };
+
+        for _ in 0..1000 {
+            let expires_at = expiry.expires_at_millis(false, 0).unwrap();
+            assert!((90_000..=110_000).contains(&expires_at), "{expires_at}");
+        }
+        assert_eq!(expiry.expires_at_millis(true, 0), None);
+    }
+
     #[test]
     fn test_validate_invalid_chain() {
         let transform = SimpleRedisCacheBuilder {
             cache_chain: TransformChainBuilder::new(vec![], "test-chain"),
             caching_schema: HashMap::new(),
             missed_requests: counter!("cache_miss"),
+            expiry: Expiry::default(),
+            negative_hits: counter!("cache_negative_hit"),
+            stale_hits: counter!("cache_stale_hit"),
+            refreshes: counter!("cache_refresh"),
+            refreshing: Default::default(),
         };
 
         assert_eq!(
@@ -881,8 +1311,88 @@ mod test {
             cache_chain,
             caching_schema: HashMap::new(),
             missed_requests: counter!("cache_miss"),
+            expiry: Expiry::default(),
+            negative_hits: counter!("cache_negative_hit"),
+            stale_hits: counter!("cache_stale_hit"),
+            refreshes: counter!("cache_refresh"),
+            refreshing: Default::default(),
         };
 
         assert_eq!(transform.validate(), Vec::<String>::new());
     }
+
+    fn query(stream_id: i16) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+            operation: CassandraOperation::Query {
+                query: Box::new(parse_statement_single("SELECT * FROM foo WHERE z = 1")),
+                params: Box::default(),
+            },
+        }))
+    }
+
+    /// The response cassandra would send to `request`
+    fn response(request: &Message) -> Message {
+        let mut response = Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: request.stream_id().unwrap(),
+            tracing: Tracing::Response(None),
+            warnings: vec![],
+            operation: CassandraOperation::Result(CassandraResult::Void),
+        }));
+        response.set_request_id(request.id());
+        response
+    }
+
+    #[test]
+    fn test_refresh_avoids_stream_ids_in_flight() {
+        let mut stream_ids = StreamIds::default();
+
+        // A request from an earlier batch is still in flight with the highest stream id
+        let mut in_flight = vec![query(i16::MAX)];
+        stream_ids.send(&mut in_flight, vec![]);
+
+        let mut requests = vec![query(i16::MAX - 1)];
+        stream_ids.send(&mut requests, vec![query(0)]);
+        assert_eq!(requests[0].stream_id(), Some(i16::MAX - 1));
+        assert_eq!(requests[1].stream_id(), Some(i16::MAX - 2));
+
+        // Once responded to, the stream id can be used again
+        stream_ids.receive(&mut response(&in_flight[0]));
+        let mut requests = vec![];
+        stream_ids.send(&mut requests, vec![query(0)]);
+        assert_eq!(requests[0].stream_id(), Some(i16::MAX));
+    }
+
+    #[test]
+    fn test_client_reuses_stream_id_of_refresh_in_flight() {
+        let mut stream_ids = StreamIds::default();
+
+        let mut requests = vec![];
+        stream_ids.send(&mut requests, vec![query(0)]);
+        let refresh = requests.pop().unwrap();
+        assert_eq!(refresh.stream_id(), Some(i16::MAX));
+
+        // The client does not know about the refresh so may send a request with its stream id
+        let mut requests = vec![query(i16::MAX)];
+        stream_ids.send(&mut requests, vec![]);
+        let request = requests.pop().unwrap();
+        assert_eq!(request.stream_id(), Some(i16::MAX - 1));
+
+        let mut refresh_response = response(&refresh);
+        stream_ids.receive(&mut refresh_response);
+        assert_eq!(refresh_response.stream_id(), Some(i16::MAX));
+
+        // The client receives its response under the stream id it sent
+        let mut request_response = response(&request);
+        stream_ids.receive(&mut request_response);
+        assert_eq!(request_response.stream_id(), Some(i16::MAX));
+
+        assert!(stream_ids.requests.is_empty());
+        assert!(stream_ids.refreshes.is_empty());
+        assert!(stream_ids.remapped.is_empty());
+    }
 }
@@ -0,0 +1,538 @@
+use super::ttl_policy::glob_match;
+use crate::config::chain::TransformChainConfig;
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageId, MessageIdMap, MessageIdSet, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use metrics::{counter, Counter};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisCacheWarmingConfig {
+    /// The chain containing the authoritative data that cache misses are loaded from
+    pub backend_chain: TransformChainConfig,
+    /// Only keys matching one of these rules are loaded, each key is governed by the first rule whose pattern it matches
+    pub rules: Vec<WarmingRuleConfig>,
+    /// Each ttl is randomly lengthened or shortened by up to this fraction of itself,
+    /// so that keys loaded at the same time do not all expire at the same time
+    pub ttl_jitter: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WarmingRuleConfig {
+    /// Redis style glob pattern, `*` matches any sequence of characters and `?` matches any single character
+    pub key_pattern: String,
+    /// Expiry given to keys written to the cache, when not set keys are written without an expiry
+    pub ttl_seconds: Option<u64>,
+}
+
+const NAME: &str = "RedisCacheWarming";
+#[typetag::serde(name = "RedisCacheWarming")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisCacheWarmingConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let backend_chain = self
+            .backend_chain
+            .get_builder(TransformContextConfig {
+                chain_name: "backend_chain".into(),
+                protocol: MessageType::Redis,
+            })
+            .await?;
+
+        Ok(Box::new(RedisCacheWarmingBuilder {
+            backend_chain,
+            rules: Arc::new(self.rules.clone()),
+            ttl_jitter: self.ttl_jitter.unwrap_or(0.0),
+            in_flight: Default::default(),
+            loads: counter!("shotover_cache_warming_loads_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        vec![("backend_chain", &self.backend_chain)]
+    }
+}
+
+/// The value loaded from the backend for each key currently being loaded, shared between all connections.
+/// The value is None until the load completes and the sender is dropped if the load fails.
+type InFlight = Arc<Mutex<HashMap<Bytes, watch::Receiver<Option<RedisFrame>>>>>;
+
+pub struct RedisCacheWarmingBuilder {
+    backend_chain: TransformChainBuilder,
+    rules: Arc<Vec<WarmingRuleConfig>>,
+    ttl_jitter: f64,
+    in_flight: InFlight,
+    loads: Counter,
+}
+
+impl TransformBuilder for RedisCacheWarmingBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisCacheWarming {
+            backend_chain: self.backend_chain.build(transform_context.clone()),
+            rules: self.rules.clone(),
+            ttl_jitter: self.ttl_jitter,
+            in_flight: self.in_flight.clone(),
+            loads: self.loads.clone(),
+            force_run_chain: transform_context.force_run_chain,
+            in_transaction: false,
+            get_requests: MessageIdMap::default(),
+            pending_writes: vec![],
+            write_requests: MessageIdSet::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self
+            .backend_chain
+            .validate()
+            .iter()
+            .map(|x| format!("  {x}"))
+            .collect::<Vec<String>>();
+
+        if !(0.0..1.0).contains(&self.ttl_jitter) {
+            errors.push("  ttl_jitter must be at least 0 and less than 1".to_owned());
+        }
+        if self.rules.is_empty() {
+            errors.push("  at least one rule must be configured".to_owned());
+        }
+        for rule in self.rules.iter() {
+            if rule.ttl_seconds == Some(0) {
+                errors.push(format!(
+                    "  rule for {:?} has a ttl_seconds of 0",
+                    rule.key_pattern
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{NAME}:"));
+        }
+
+        errors
+    }
+}
+
+/// Provides read-through semantics for the cache that this transform is in front of.
+///
+/// When a GET of a key matching a rule misses in the cache, the key is loaded from the backend chain and returned to the client.
+/// The loaded value is then written to the cache with `SET NX` alongside the next requests sent down the chain,
+/// so that the client does not wait on the write and a value written to the cache in the meantime is not overwritten.
+/// Only one load per key is performed at a time across all connections, other connections missing on that key wait for its result.
+pub struct RedisCacheWarming {
+    backend_chain: TransformChain,
+    rules: Arc<Vec<WarmingRuleConfig>>,
+    ttl_jitter: f64,
+    in_flight: InFlight,
+    loads: Counter,
+    force_run_chain: Arc<Notify>,
+    /// GETs inside a transaction return QUEUED, so are not considered for loading
+    in_transaction: bool,
+    /// The key of each GET request that may need to be loaded
+    get_requests: MessageIdMap<Bytes>,
+    /// Cache writes waiting to be sent down the chain
+    pending_writes: Vec<Message>,
+    /// Cache writes that have been sent down the chain, their responses are not returned to the client
+    write_requests: MessageIdSet,
+}
+
+/// How long a connection waits on another connection loading the same key before responding with the miss
+const FOLLOWER_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Load {
+    /// This connection loads the key and publishes the result to other connections
+    Leader(watch::Sender<Option<RedisFrame>>),
+    /// Another connection is loading the key
+    Follower(watch::Receiver<Option<RedisFrame>>),
+}
+
+#[async_trait]
+impl Transform for RedisCacheWarming {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in requests_wrapper.requests.iter_mut() {
+            self.track_request(request);
+        }
+
+        for write in self.pending_writes.drain(..) {
+            self.write_requests.insert(write.id());
+            requests_wrapper.requests.push(write);
+        }
+
+        let local_addr = requests_wrapper.local_addr;
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        responses.retain(|response| {
+            !response
+                .request_id()
+                .map(|id| self.write_requests.remove(&id))
+                .unwrap_or(false)
+        });
+
+        let mut misses: Vec<(usize, Bytes)> = vec![];
+        for (i, response) in responses.iter_mut().enumerate() {
+            let Some(key) = response
+                .request_id()
+                .and_then(|id| self.get_requests.remove(&id))
+            else {
+                continue;
+            };
+            if let Some(Frame::Redis(RedisFrame::Null)) = response.frame() {
+                misses.push((i, key));
+            }
+        }
+
+        if !misses.is_empty() {
+            self.load_misses(&mut responses, misses, local_addr).await?;
+        }
+
+        Ok(responses)
+    }
+}
+
+impl RedisCacheWarming {
+    fn track_request(&mut self, request: &mut Message) {
+        let request_id = request.id();
+        let Some(Frame::Redis(RedisFrame::Array(args))) = request.frame() else {
+            return;
+        };
+        let Some(RedisFrame::BulkString(command)) = args.first() else {
+            return;
+        };
+        if command.eq_ignore_ascii_case(b"MULTI") {
+            self.in_transaction = true;
+        } else if command.eq_ignore_ascii_case(b"EXEC") || command.eq_ignore_ascii_case(b"DISCARD")
+        {
+            self.in_transaction = false;
+        } else if !self.in_transaction && command.eq_ignore_ascii_case(b"GET") {
+            if let [_, RedisFrame::BulkString(key)] = args.as_slice() {
+                if self.find_rule(key).is_some() {
+                    self.get_requests.insert(request_id, key.clone());
+                }
+            }
+        }
+    }
+
+    fn find_rule(&self, key: &[u8]) -> Option<&WarmingRuleConfig> {
+        self.rules
+            .iter()
+            .find(|rule| glob_match(rule.key_pattern.as_bytes(), key))
+    }
+
+    async fn load_misses(
+        &mut self,
+        responses: &mut [Message],
+        misses: Vec<(usize, Bytes)>,
+        local_addr: std::net::SocketAddr,
+    ) -> Result<()> {
+        let loads = self.claim_loads(misses);
+        self.complete_loads(responses, loads, local_addr).await;
+
+        if !self.pending_writes.is_empty() {
+            // Ensure the writes are sent even if the client sends no more requests
+            self.force_run_chain.notify_one();
+        }
+
+        Ok(())
+    }
+
+    /// Decides for each miss whether this connection loads the key or waits on another connection loading it.
+    fn claim_loads(&self, misses: Vec<(usize, Bytes)>) -> Vec<(usize, Bytes, Load)> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        misses
+            .into_iter()
+            .map(|(i, key)| {
+                // A receiver whose sender has been dropped belongs to a connection that was closed mid load, so load the key again.
+                let load = match in_flight.get(&key) {
+                    Some(rx) if rx.has_changed().is_ok() => Load::Follower(rx.clone()),
+                    _ => {
+                        let (tx, rx) = watch::channel(None);
+                        in_flight.insert(key.clone(), rx);
+                        Load::Leader(tx)
+                    }
+                };
+                (i, key, load)
+            })
+            .collect()
+    }
+
+    /// Loads the keys this connection is the leader of and publishes them before waiting on any keys loaded by other connections.
+    /// Publishing first ensures two connections that each wait on a key the other is loading cannot wait on each other forever.
+    async fn complete_loads(
+        &mut self,
+        responses: &mut [Message],
+        loads: Vec<(usize, Bytes, Load)>,
+        local_addr: std::net::SocketAddr,
+    ) {
+        let mut leaders = vec![];
+        let mut followers = vec![];
+        for (i, key, load) in loads {
+            match load {
+                Load::Leader(tx) => leaders.push((i, key, tx)),
+                Load::Follower(rx) => followers.push((i, rx)),
+            }
+        }
+
+        let backend_requests: Vec<Message> = leaders
+            .iter()
+            .map(|(_, key, _)| {
+                Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString(Bytes::from_static(b"GET")),
+                    RedisFrame::BulkString(key.clone()),
+                ])))
+            })
+            .collect();
+        let backend_request_ids: Vec<MessageId> = backend_requests.iter().map(|x| x.id()).collect();
+
+        let mut backend_responses = if backend_requests.is_empty() {
+            vec![]
+        } else {
+            self.loads.increment(backend_requests.len() as u64);
+            let result = self
+                .backend_chain
+                .process_request(Wrapper::new_with_addr(backend_requests, local_addr))
+                .await;
+            match result {
+                Ok(responses) => responses,
+                Err(err) => {
+                    tracing::warn!("Failed to load cache misses from the backend chain: {err}");
+                    vec![]
+                }
+            }
+        };
+
+        let mut values = Vec::with_capacity(leaders.len() + followers.len());
+        for ((i, key, tx), backend_request_id) in leaders.into_iter().zip(backend_request_ids) {
+            let value = backend_responses
+                .iter()
+                .position(|x| x.request_id() == Some(backend_request_id))
+                .and_then(|index| match backend_responses[index].frame() {
+                    Some(Frame::Redis(frame @ (RedisFrame::BulkString(_) | RedisFrame::Null))) => {
+                        Some(frame.clone())
+                    }
+                    _ => None,
+                });
+            if let Some(RedisFrame::BulkString(bytes)) = &value {
+                let write = self.cache_write(&key, bytes.clone());
+                self.pending_writes.push(write);
+            }
+            // When the load failed the sender is dropped without a value, so followers keep their miss.
+            if value.is_some() {
+                tx.send(value.clone()).ok();
+            }
+            self.in_flight.lock().unwrap().remove(&key);
+            values.push((i, value));
+        }
+
+        for (i, mut rx) in followers {
+            let wait = async {
+                loop {
+                    if let Some(value) = rx.borrow_and_update().clone() {
+                        break Some(value);
+                    }
+                    if rx.changed().await.is_err() {
+                        break None;
+                    }
+                }
+            };
+            // A follower whose leader takes too long keeps its miss rather than holding up the client
+            let value = tokio::time::timeout(FOLLOWER_TIMEOUT, wait)
+                .await
+                .unwrap_or(None);
+            values.push((i, value));
+        }
+
+        for (i, value) in values {
+            if let Some(value @ RedisFrame::BulkString(_)) = value {
+                let response = &mut responses[i];
+                if let Some(frame) = response.frame() {
+                    *frame = Frame::Redis(value);
+                    response.invalidate_cache();
+                }
+            }
+        }
+    }
+
+    fn cache_write(&self, key: &Bytes, value: Bytes) -> Message {
+        let mut args = vec![
+            RedisFrame::BulkString(Bytes::from_static(b"SET")),
+            RedisFrame::BulkString(key.clone()),
+            RedisFrame::BulkString(value),
+            RedisFrame::BulkString(Bytes::from_static(b"NX")),
+        ];
+        if let Some(ttl_seconds) = self.find_rule(key).and_then(|rule| rule.ttl_seconds) {
+            if self.ttl_jitter > 0.0 {
+                // Milliseconds give the jitter a finer granularity than the configured seconds
+                let jitter = self.ttl_jitter;
+                let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+                let ttl_millis = (ttl_seconds as f64 * 1000.0 * factor).max(1.0) as u64;
+                args.push(RedisFrame::BulkString(Bytes::from_static(b"PX")));
+                args.push(RedisFrame::BulkString(ttl_millis.to_string().into()));
+            } else {
+                args.push(RedisFrame::BulkString(Bytes::from_static(b"EX")));
+                args.push(RedisFrame::BulkString(ttl_seconds.to_string().into()));
+            }
+        }
+        Message::from_frame(Frame::Redis(RedisFrame::Array(args)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use crate::transforms::testing::redis_request;
+    use pretty_assertions::assert_eq;
+
+    fn transform(rules: Vec<WarmingRuleConfig>) -> RedisCacheWarming {
+        RedisCacheWarming {
+            backend_chain: TransformChainBuilder::new(vec![], "backend_chain")
+                .build(TransformContextBuilder::new_test()),
+            rules: Arc::new(rules),
+            ttl_jitter: 0.0,
+            in_flight: Default::default(),
+            loads: Counter::noop(),
+            force_run_chain: Arc::new(Notify::new()),
+            in_transaction: false,
+            get_requests: MessageIdMap::default(),
+            pending_writes: vec![],
+            write_requests: MessageIdSet::default(),
+        }
+    }
+
+    #[test]
+    fn test_cache_write() {
+        let transform = transform(vec![
+            WarmingRuleConfig {
+                key_pattern: "user:*".to_owned(),
+                ttl_seconds: Some(300),
+            },
+            WarmingRuleConfig {
+                key_pattern: "*".to_owned(),
+                ttl_seconds: None,
+            },
+        ]);
+
+        let mut write = transform.cache_write(&Bytes::from_static(b"user:1"), "alice".into());
+        assert_eq!(
+            write.frame(),
+            redis_request(&["SET", "user:1", "alice", "NX", "EX", "300"]).frame()
+        );
+
+        let mut write = transform.cache_write(&Bytes::from_static(b"other"), "bob".into());
+        assert_eq!(
+            write.frame(),
+            redis_request(&["SET", "other", "bob", "NX"]).frame()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_crossed_loads_do_not_wait_on_each_other() {
+        let in_flight = InFlight::default();
+        let connection = || {
+            let backend_chain = TransformChainBuilder::new(
+                vec![Box::new(DebugReturner::new(Response::Redis(
+                    "value".into(),
+                )))],
+                "backend_chain",
+            );
+            RedisCacheWarming {
+                backend_chain: backend_chain.build(TransformContextBuilder::new_test()),
+                in_flight: in_flight.clone(),
+                ..transform(vec![WarmingRuleConfig {
+                    key_pattern: "*".to_owned(),
+                    ttl_seconds: None,
+                }])
+            }
+        };
+        let mut a = connection();
+        let mut b = connection();
+        let k1 = Bytes::from_static(b"k1");
+        let k2 = Bytes::from_static(b"k2");
+
+        // a loads k1 and waits on b for k2, while b loads k2 and waits on a for k1
+        let mut a_leader = a.claim_loads(vec![(1, k1.clone())]);
+        let mut b_leader = b.claim_loads(vec![(1, k2.clone())]);
+        let mut a_loads = a.claim_loads(vec![(0, k2.clone())]);
+        let mut b_loads = b.claim_loads(vec![(0, k1.clone())]);
+        assert!(matches!(a_loads[0].2, Load::Follower(_)));
+        assert!(matches!(b_loads[0].2, Load::Follower(_)));
+        a_loads.append(&mut a_leader);
+        b_loads.append(&mut b_leader);
+
+        let miss = || vec![redis_request(&["GET", "k"]), redis_request(&["GET", "k"])];
+        let mut a_responses = miss();
+        let mut b_responses = miss();
+        let local_addr = "127.0.0.1:8000".parse().unwrap();
+        tokio::time::timeout(Duration::from_secs(1), async {
+            tokio::join!(
+                a.complete_loads(&mut a_responses, a_loads, local_addr),
+                b.complete_loads(&mut b_responses, b_loads, local_addr),
+            )
+        })
+        .await
+        .unwrap();
+
+        for response in a_responses.iter_mut().chain(b_responses.iter_mut()) {
+            assert_eq!(
+                response.frame(),
+                Some(&mut Frame::Redis(RedisFrame::BulkString("value".into())))
+            );
+        }
+        assert!(in_flight.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_only_matching_gets_outside_transactions_are_tracked() {
+        let mut transform = transform(vec![WarmingRuleConfig {
+            key_pattern: "user:*".to_owned(),
+            ttl_seconds: None,
+        }]);
+        let mut requests = vec![
+            redis_request(&["GET", "user:1"]),
+            redis_request(&["GET", "other"]),
+            redis_request(&["MULTI"]),
+            redis_request(&["GET", "user:2"]),
+            redis_request(&["EXEC"]),
+        ];
+        for request in &mut requests {
+            transform.track_request(request);
+        }
+
+        assert_eq!(
+            transform.get_requests,
+            MessageIdMap::from_iter([(requests[0].id(), Bytes::from_static(b"user:1"))])
+        );
+        assert!(!transform.in_transaction);
+    }
+}
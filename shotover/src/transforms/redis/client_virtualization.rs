@@ -0,0 +1,548 @@
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisClientVirtualizationConfig;
+
+const NAME: &str = "RedisClientVirtualization";
+#[typetag::serde(name = "RedisClientVirtualization")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisClientVirtualizationConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RedisClientVirtualizationBuilder {
+            clients: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// Every client connected to the chain, keyed by client id
+type Clients = Arc<Mutex<BTreeMap<u64, Client>>>;
+
+pub struct RedisClientVirtualizationBuilder {
+    clients: Clients,
+    next_id: Arc<AtomicU64>,
+}
+
+impl TransformBuilder for RedisClientVirtualizationBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        self.clients.lock().unwrap().insert(
+            id,
+            Client {
+                addr: transform_context.client_addr,
+                local_addr: None,
+                name: None,
+                connected_at: now,
+                last_command_at: now,
+                last_command: String::new(),
+                close_connection: transform_context.close_connection,
+            },
+        );
+        Box::new(RedisClientVirtualization {
+            id,
+            clients: self.clients.clone(),
+            responses: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // CLIENT LIST and CLIENT KILL are answered without being delivered
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+}
+
+struct Client {
+    addr: Option<SocketAddr>,
+    /// The shotover address the client connected to, known once the client sends its first request
+    local_addr: Option<SocketAddr>,
+    name: Option<Bytes>,
+    connected_at: Instant,
+    last_command_at: Instant,
+    last_command: String,
+    close_connection: CancellationToken,
+}
+
+impl Client {
+    /// Formats the client in the format of a line from CLIENT LIST
+    fn describe(&self, id: u64, now: Instant) -> String {
+        format!(
+            "id={id} addr={} laddr={} name={} age={} idle={} cmd={}\n",
+            format_addr(self.addr),
+            format_addr(self.local_addr),
+            String::from_utf8_lossy(self.name.as_deref().unwrap_or_default()),
+            now.duration_since(self.connected_at).as_secs(),
+            now.duration_since(self.last_command_at).as_secs(),
+            if self.last_command.is_empty() {
+                "NULL"
+            } else {
+                self.last_command.as_str()
+            },
+        )
+    }
+}
+
+fn format_addr(addr: Option<SocketAddr>) -> String {
+    addr.map(|addr| addr.to_string()).unwrap_or_default()
+}
+
+/// Answers CLIENT LIST, CLIENT INFO, CLIENT ID, CLIENT KILL, CLIENT SETNAME and CLIENT GETNAME with the clients connected to shotover,
+/// rather than passing them to the destination where only shotover's own connections are visible.
+///
+/// A client killed by CLIENT KILL is disconnected from shotover, along with the connections shotover opened on its behalf.
+/// Other CLIENT subcommands are passed down the chain unmodified.
+pub struct RedisClientVirtualization {
+    id: u64,
+    clients: Clients,
+    responses: MessageIdMap<Message>,
+}
+
+impl Drop for RedisClientVirtualization {
+    fn drop(&mut self) {
+        self.clients.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[async_trait]
+impl Transform for RedisClientVirtualization {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let local_addr = requests_wrapper.local_addr;
+        for request in requests_wrapper.requests.iter_mut() {
+            if request.is_dummy() {
+                continue;
+            }
+            let response = match request.frame() {
+                Some(Frame::Redis(RedisFrame::Array(args))) => {
+                    let now = Instant::now();
+                    let mut clients = self.clients.lock().unwrap();
+                    if let Some(client) = clients.get_mut(&self.id) {
+                        client.local_addr = Some(local_addr);
+                        client.last_command_at = now;
+                        client.last_command = command_name(args);
+                    }
+                    client_command(self.id, &mut clients, args, now)
+                }
+                _ => None,
+            };
+            if let Some(frame) = response {
+                let mut response = Message::from_frame(Frame::Redis(frame));
+                response.set_request_id(request.id());
+                self.responses.insert(request.id(), response);
+                request.replace_with_dummy();
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if let Some(request_id) = response.request_id() {
+                if let Some(client_response) = self.responses.remove(&request_id) {
+                    *response = client_response;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+fn arg(frame: &RedisFrame) -> Option<&[u8]> {
+    match frame {
+        RedisFrame::BulkString(bytes) => Some(bytes),
+        _ => None,
+    }
+}
+
+/// The command name as reported in the cmd field of CLIENT LIST
+fn command_name(args: &[RedisFrame]) -> String {
+    let mut name = String::new();
+    for (i, frame) in args.iter().take(2).enumerate() {
+        let Some(value) = arg(frame) else {
+            break;
+        };
+        if i == 1 {
+            // Only commands with subcommands report the subcommand
+            if !name.eq_ignore_ascii_case("client") && !name.eq_ignore_ascii_case("config") {
+                break;
+            }
+            name.push('|');
+        }
+        name.push_str(&String::from_utf8_lossy(value).to_ascii_lowercase());
+    }
+    name
+}
+
+fn error(message: &str) -> Option<RedisFrame> {
+    Some(RedisFrame::Error(message.to_owned().into()))
+}
+
+/// Returns the response to the request if it is a CLIENT subcommand answered by shotover
+fn client_command(
+    id: u64,
+    clients: &mut BTreeMap<u64, Client>,
+    args: &[RedisFrame],
+    now: Instant,
+) -> Option<RedisFrame> {
+    if !arg(args.first()?)?.eq_ignore_ascii_case(b"CLIENT") {
+        return None;
+    }
+    let subcommand = arg(args.get(1)?)?.to_ascii_uppercase();
+    let args = &args[2..];
+    match subcommand.as_slice() {
+        b"ID" => Some(RedisFrame::Integer(id as i64)),
+        b"INFO" => Some(RedisFrame::BulkString(
+            clients.get(&id)?.describe(id, now).into(),
+        )),
+        b"LIST" => client_list(clients, args, now),
+        b"KILL" => client_kill(id, clients, args),
+        b"SETNAME" => {
+            let [name] = args else {
+                return error("ERR wrong number of arguments for 'client|setname' command");
+            };
+            let name = arg(name)?;
+            if name.iter().any(|c| !(b'!'..=b'~').contains(c)) {
+                return error(
+                    "ERR Client names cannot contain spaces, newlines or special characters.",
+                );
+            }
+            clients.get_mut(&id)?.name = (!name.is_empty()).then(|| Bytes::copy_from_slice(name));
+            Some(RedisFrame::SimpleString("OK".into()))
+        }
+        b"GETNAME" => Some(match &clients.get(&id)?.name {
+            Some(name) => RedisFrame::BulkString(name.clone()),
+            None => RedisFrame::Null,
+        }),
+        _ => None,
+    }
+}
+
+fn client_list(
+    clients: &BTreeMap<u64, Client>,
+    args: &[RedisFrame],
+    now: Instant,
+) -> Option<RedisFrame> {
+    let ids: Option<Vec<u64>> = match args {
+        [] => None,
+        [filter, ty] if arg(filter)?.eq_ignore_ascii_case(b"TYPE") => {
+            if is_normal_type(arg(ty)?)? {
+                None
+            } else {
+                Some(vec![])
+            }
+        }
+        [filter, ids @ ..] if arg(filter)?.eq_ignore_ascii_case(b"ID") && !ids.is_empty() => {
+            let mut parsed = vec![];
+            for id in ids {
+                match parse_id(arg(id)?) {
+                    Some(id) => parsed.push(id),
+                    None => return error("ERR Invalid client ID"),
+                }
+            }
+            Some(parsed)
+        }
+        _ => return error("ERR syntax error"),
+    };
+
+    let list: String = clients
+        .iter()
+        .filter(|(id, _)| ids.as_ref().map(|ids| ids.contains(id)).unwrap_or(true))
+        .map(|(id, client)| client.describe(*id, now))
+        .collect();
+    Some(RedisFrame::BulkString(list.into()))
+}
+
+/// Returns whether the client type matches normal clients, which are the only type of client shotover has.
+/// Returns None if the type is invalid.
+fn is_normal_type(ty: &[u8]) -> Option<bool> {
+    match ty.to_ascii_lowercase().as_slice() {
+        b"normal" => Some(true),
+        b"master" | b"replica" | b"slave" | b"pubsub" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_id(id: &[u8]) -> Option<u64> {
+    std::str::from_utf8(id)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|id| *id > 0)
+}
+
+fn client_kill(
+    own_id: u64,
+    clients: &mut BTreeMap<u64, Client>,
+    args: &[RedisFrame],
+) -> Option<RedisFrame> {
+    // The old form of CLIENT KILL takes just an address and can kill the calling client
+    if let [addr] = args {
+        let addr = arg(addr)?;
+        return match clients
+            .values()
+            .find(|client| format_addr(client.addr).as_bytes() == addr)
+        {
+            Some(client) => {
+                client.close_connection.cancel();
+                Some(RedisFrame::SimpleString("OK".into()))
+            }
+            None => error("ERR No such client"),
+        };
+    }
+
+    if args.is_empty() || args.len() % 2 != 0 {
+        return error("ERR syntax error");
+    }
+
+    let mut filter = KillFilter::default();
+    for pair in args.chunks(2) {
+        let value = arg(&pair[1])?;
+        match arg(&pair[0])?.to_ascii_uppercase().as_slice() {
+            b"ID" => match parse_id(value) {
+                Some(id) => filter.id = Some(id),
+                None => return error("ERR client-id should be greater than 0"),
+            },
+            b"ADDR" => filter.addr = Some(value),
+            b"LADDR" => filter.local_addr = Some(value),
+            b"TYPE" => match is_normal_type(value) {
+                Some(is_normal) => filter.normal_type = is_normal,
+                None => {
+                    return error(&format!(
+                        "ERR Unknown client type '{}'",
+                        String::from_utf8_lossy(value)
+                    ))
+                }
+            },
+            b"SKIPME" => match value.to_ascii_lowercase().as_slice() {
+                b"yes" => filter.skip_me = true,
+                b"no" => filter.skip_me = false,
+                _ => return error("ERR syntax error"),
+            },
+            _ => return error("ERR syntax error"),
+        }
+    }
+
+    let mut killed = 0;
+    for (id, client) in clients.iter() {
+        if filter.matches(own_id, *id, client) {
+            client.close_connection.cancel();
+            killed += 1;
+        }
+    }
+    Some(RedisFrame::Integer(killed))
+}
+
+struct KillFilter<'a> {
+    id: Option<u64>,
+    addr: Option<&'a [u8]>,
+    local_addr: Option<&'a [u8]>,
+    normal_type: bool,
+    skip_me: bool,
+}
+
+impl<'a> Default for KillFilter<'a> {
+    fn default() -> Self {
+        KillFilter {
+            id: None,
+            addr: None,
+            local_addr: None,
+            normal_type: true,
+            skip_me: true,
+        }
+    }
+}
+
+impl<'a> KillFilter<'a> {
+    fn matches(&self, own_id: u64, id: u64, client: &Client) -> bool {
+        self.normal_type
+            && !(self.skip_me && id == own_id)
+            && self.id.map(|filter| filter == id).unwrap_or(true)
+            && self
+                .addr
+                .map(|filter| format_addr(client.addr).as_bytes() == filter)
+                .unwrap_or(true)
+            && self
+                .local_addr
+                .map(|filter| format_addr(client.local_addr).as_bytes() == filter)
+                .unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transforms::testing::redis_args;
+    use pretty_assertions::assert_eq;
+
+    fn client(addr: &str) -> Client {
+        let now = Instant::now();
+        Client {
+            addr: Some(addr.parse().unwrap()),
+            local_addr: Some("127.0.0.1:6379".parse().unwrap()),
+            name: None,
+            connected_at: now,
+            last_command_at: now,
+            last_command: String::new(),
+            close_connection: CancellationToken::new(),
+        }
+    }
+
+    #[test]
+    fn test_client_list() {
+        let mut clients = BTreeMap::new();
+        clients.insert(1, client("10.0.0.1:5000"));
+        clients.insert(2, client("10.0.0.2:5000"));
+        let now = Instant::now();
+
+        assert_eq!(
+            client_command(1, &mut clients, &redis_args(&["CLIENT", "LIST"]), now),
+            Some(RedisFrame::BulkString(
+                "id=1 addr=10.0.0.1:5000 laddr=127.0.0.1:6379 name= age=0 idle=0 cmd=NULL\n\
+                 id=2 addr=10.0.0.2:5000 laddr=127.0.0.1:6379 name= age=0 idle=0 cmd=NULL\n"
+                    .into()
+            ))
+        );
+        assert_eq!(
+            client_command(
+                1,
+                &mut clients,
+                &redis_args(&["client", "list", "id", "2"]),
+                now
+            ),
+            Some(RedisFrame::BulkString(
+                "id=2 addr=10.0.0.2:5000 laddr=127.0.0.1:6379 name= age=0 idle=0 cmd=NULL\n".into()
+            ))
+        );
+
+        assert_eq!(
+            client_command(
+                2,
+                &mut clients,
+                &redis_args(&["CLIENT", "SETNAME", "app"]),
+                now
+            ),
+            Some(RedisFrame::SimpleString("OK".into()))
+        );
+        assert_eq!(
+            client_command(2, &mut clients, &redis_args(&["CLIENT", "INFO"]), now),
+            Some(RedisFrame::BulkString(
+                "id=2 addr=10.0.0.2:5000 laddr=127.0.0.1:6379 name=app age=0 idle=0 cmd=NULL\n"
+                    .into()
+            ))
+        );
+
+        // other subcommands are passed down the chain
+        assert_eq!(
+            client_command(
+                1,
+                &mut clients,
+                &redis_args(&["CLIENT", "PAUSE", "10"]),
+                now
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_client_kill() {
+        let mut clients = BTreeMap::new();
+        clients.insert(1, client("10.0.0.1:5000"));
+        clients.insert(2, client("10.0.0.2:5000"));
+        clients.insert(3, client("10.0.0.2:5001"));
+        let now = Instant::now();
+
+        assert_eq!(
+            client_command(
+                1,
+                &mut clients,
+                &redis_args(&["CLIENT", "KILL", "10.0.0.9:1"]),
+                now
+            ),
+            Some(RedisFrame::Error("ERR No such client".into()))
+        );
+        assert_eq!(
+            client_command(
+                1,
+                &mut clients,
+                &redis_args(&["CLIENT", "KILL", "10.0.0.2:5000"]),
+                now
+            ),
+            Some(RedisFrame::SimpleString("OK".into()))
+        );
+        assert!(clients[&2].close_connection.is_cancelled());
+        assert!(!clients[&3].close_connection.is_cancelled());
+
+        // SKIPME defaults to yes, so the calling client is not killed
+        assert_eq!(
+            client_command(
+                1,
+                &mut clients,
+                &redis_args(&["CLIENT", "KILL", "LADDR", "127.0.0.1:6379"]),
+                now
+            ),
+            Some(RedisFrame::Integer(2))
+        );
+        assert!(!clients[&1].close_connection.is_cancelled());
+        assert!(clients[&3].close_connection.is_cancelled());
+
+        assert_eq!(
+            client_command(
+                1,
+                &mut clients,
+                &redis_args(&["CLIENT", "KILL", "ID", "1", "SKIPME", "no"]),
+                now
+            ),
+            Some(RedisFrame::Integer(1))
+        );
+        assert!(clients[&1].close_connection.is_cancelled());
+
+        assert_eq!(
+            client_command(1, &mut clients, &redis_args(&["CLIENT", "KILL", "ID"]), now),
+            Some(RedisFrame::Error("ERR syntax error".into()))
+        );
+    }
+
+    #[test]
+    fn test_command_name() {
+        assert_eq!(command_name(&redis_args(&["GET", "foo"])), "get");
+        assert_eq!(
+            command_name(&redis_args(&["CLIENT", "LIST"])),
+            "client|list"
+        );
+    }
+}
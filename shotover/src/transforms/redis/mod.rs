@@ -2,10 +2,15 @@ use crate::transforms::util::ConnectionError;
 
 #[cfg(all(feature = "redis", feature = "cassandra"))]
 pub mod cache;
+pub mod cache_warming;
+pub mod client_virtualization;
 pub mod cluster_ports_rewrite;
+pub mod monitor;
+pub mod resp3_translation;
 pub mod sink_cluster;
 pub mod sink_single;
 pub mod timestamp_tagging;
+pub mod ttl_policy;
 
 #[derive(thiserror::Error, Clone, Debug)]
 pub enum RedisError {
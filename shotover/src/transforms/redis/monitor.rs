@@ -0,0 +1,361 @@
+use super::ttl_policy::glob_match;
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::Notify;
+
+/// The number of lines buffered for a monitoring client before further lines are dropped.
+const MONITOR_BUFFER_SIZE: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisMonitorConfig {
+    /// Only commands sent by clients whose address matches this glob pattern are reported.
+    pub client_pattern: Option<String>,
+    /// Only commands whose first argument matches this glob pattern are reported.
+    pub key_pattern: Option<String>,
+}
+
+const NAME: &str = "RedisMonitor";
+#[typetag::serde(name = "RedisMonitor")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisMonitorConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RedisMonitorBuilder {
+            filter: Arc::new(Filter {
+                client_pattern: self.client_pattern.clone(),
+                key_pattern: self.key_pattern.clone(),
+            }),
+            monitors: Arc::new(Mutex::new(BTreeMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            dropped_lines: counter!(
+                "shotover_monitor_dropped_lines_count",
+                "chain" => transform_context.chain_name
+            ),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// Every client of the chain that has issued MONITOR, keyed by the id of its transform instance
+type Monitors = Arc<Mutex<BTreeMap<u64, Monitor>>>;
+
+struct Monitor {
+    lines: Sender<Bytes>,
+    force_run_chain: Arc<Notify>,
+}
+
+pub struct RedisMonitorBuilder {
+    filter: Arc<Filter>,
+    monitors: Monitors,
+    next_id: Arc<AtomicU64>,
+    dropped_lines: Counter,
+}
+
+impl TransformBuilder for RedisMonitorBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisMonitor {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            client_addr: transform_context.client_addr,
+            force_run_chain: transform_context.force_run_chain,
+            filter: self.filter.clone(),
+            monitors: self.monitors.clone(),
+            db: 0,
+            lines: None,
+            responses: MessageIdMap::default(),
+            dropped_lines: self.dropped_lines.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // MONITOR is answered without being delivered
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+}
+
+struct Filter {
+    client_pattern: Option<String>,
+    key_pattern: Option<String>,
+}
+
+impl Filter {
+    fn matches(&self, client_addr: Option<SocketAddr>, args: &[RedisFrame]) -> bool {
+        let client_matches = match &self.client_pattern {
+            Some(pattern) => client_addr
+                .map(|addr| glob_match(pattern.as_bytes(), addr.to_string().as_bytes()))
+                .unwrap_or(false),
+            None => true,
+        };
+        let key_matches = match &self.key_pattern {
+            Some(pattern) => args
+                .get(1)
+                .and_then(arg)
+                .map(|key| glob_match(pattern.as_bytes(), key))
+                .unwrap_or(false),
+            None => true,
+        };
+        client_matches && key_matches
+    }
+}
+
+/// Answers MONITOR with a stream of the commands that the clients of this chain send through shotover,
+/// rather than passing it to the destination where the commands of every client are multiplexed onto shotover's own connections.
+///
+/// All other requests are passed down the chain unmodified.
+pub struct RedisMonitor {
+    id: u64,
+    client_addr: Option<SocketAddr>,
+    force_run_chain: Arc<Notify>,
+    filter: Arc<Filter>,
+    monitors: Monitors,
+    /// The database last selected by the client, reported alongside each of its commands
+    db: i64,
+    /// The lines to send to the client, set once the client has issued MONITOR
+    lines: Option<Receiver<Bytes>>,
+    responses: MessageIdMap<Message>,
+    dropped_lines: Counter,
+}
+
+impl Drop for RedisMonitor {
+    fn drop(&mut self) {
+        if self.lines.is_some() {
+            self.monitors.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for RedisMonitor {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        for request in requests_wrapper.requests.iter_mut() {
+            if request.is_dummy() {
+                continue;
+            }
+            let Some(Frame::Redis(RedisFrame::Array(args))) = request.frame() else {
+                continue;
+            };
+            let Some(command) = args.first().and_then(arg) else {
+                continue;
+            };
+
+            if command.eq_ignore_ascii_case(b"MONITOR") {
+                if self.lines.is_none() {
+                    let (tx, rx) = channel(MONITOR_BUFFER_SIZE);
+                    self.monitors.lock().unwrap().insert(
+                        self.id,
+                        Monitor {
+                            lines: tx,
+                            force_run_chain: self.force_run_chain.clone(),
+                        },
+                    );
+                    self.lines = Some(rx);
+                }
+                let mut response =
+                    Message::from_frame(Frame::Redis(RedisFrame::SimpleString("OK".into())));
+                response.set_request_id(request.id());
+                self.responses.insert(request.id(), response);
+                request.replace_with_dummy();
+                continue;
+            }
+
+            if command.eq_ignore_ascii_case(b"SELECT") {
+                if let Some(db) = args
+                    .get(1)
+                    .and_then(arg)
+                    .and_then(|db| std::str::from_utf8(db).ok())
+                    .and_then(|db| db.parse().ok())
+                {
+                    self.db = db;
+                }
+            }
+
+            // Like redis, never reveal credentials to monitoring clients
+            if command.eq_ignore_ascii_case(b"AUTH") || command.eq_ignore_ascii_case(b"HELLO") {
+                continue;
+            }
+
+            let monitors = self.monitors.lock().unwrap();
+            if monitors.keys().all(|id| *id == self.id)
+                || !self.filter.matches(self.client_addr, args)
+            {
+                continue;
+            }
+            let line = Bytes::from(format_line(
+                SystemTime::now(),
+                self.db,
+                self.client_addr,
+                args,
+            ));
+            for (id, monitor) in monitors.iter() {
+                if *id == self.id {
+                    continue;
+                }
+                match monitor.lines.try_send(line.clone()) {
+                    Ok(()) => monitor.force_run_chain.notify_one(),
+                    Err(TrySendError::Full(_)) => self.dropped_lines.increment(1),
+                    // The monitoring client is disconnecting and will remove itself
+                    Err(TrySendError::Closed(_)) => {}
+                }
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if let Some(request_id) = response.request_id() {
+                if let Some(monitor_response) = self.responses.remove(&request_id) {
+                    *response = monitor_response;
+                }
+            }
+        }
+
+        if let Some(lines) = &mut self.lines {
+            while let Ok(line) = lines.try_recv() {
+                responses.push(Message::from_frame(Frame::Redis(RedisFrame::SimpleString(
+                    line,
+                ))));
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+fn arg(frame: &RedisFrame) -> Option<&[u8]> {
+    match frame {
+        RedisFrame::BulkString(bytes) => Some(bytes),
+        _ => None,
+    }
+}
+
+/// Formats the command in the format of a line sent by redis to a client that issued MONITOR
+fn format_line(
+    time: SystemTime,
+    db: i64,
+    client_addr: Option<SocketAddr>,
+    args: &[RedisFrame],
+) -> String {
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} [{db} {}]",
+        time.as_secs(),
+        time.subsec_micros(),
+        client_addr
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|| "unknown".to_owned())
+    );
+    for frame in args {
+        line.push(' ');
+        match frame {
+            RedisFrame::BulkString(bytes) | RedisFrame::SimpleString(bytes) => {
+                quote(&mut line, bytes)
+            }
+            RedisFrame::Integer(value) => quote(&mut line, value.to_string().as_bytes()),
+            _ => line.push_str("\"\""),
+        }
+    }
+    line
+}
+
+/// Quotes and escapes the argument the same way redis does in MONITOR output
+fn quote(line: &mut String, bytes: &[u8]) {
+    line.push('"');
+    for byte in bytes {
+        match byte {
+            b'\\' => line.push_str("\\\\"),
+            b'"' => line.push_str("\\\""),
+            b'\n' => line.push_str("\\n"),
+            b'\r' => line.push_str("\\r"),
+            b'\t' => line.push_str("\\t"),
+            0x07 => line.push_str("\\a"),
+            0x08 => line.push_str("\\b"),
+            b' '..=b'~' => line.push(*byte as char),
+            _ => write!(line, "\\x{byte:02x}").unwrap(),
+        }
+    }
+    line.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transforms::testing::redis_args;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_line() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_339_518_083_107_412);
+        assert_eq!(
+            format_line(
+                time,
+                2,
+                Some("127.0.0.1:60866".parse().unwrap()),
+                &redis_args(&["set", "foo", "a \"quoted\"\r\nvalue\x01"]),
+            ),
+            r#"1339518083.107412 [2 127.0.0.1:60866] "set" "foo" "a \"quoted\"\r\nvalue\x01""#
+        );
+        assert_eq!(
+            format_line(UNIX_EPOCH, 0, None, &redis_args(&["ping"])),
+            r#"0.000000 [0 unknown] "ping""#
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let addr = Some("10.0.0.1:4000".parse().unwrap());
+        let filter = Filter {
+            client_pattern: Some("10.0.0.*".to_owned()),
+            key_pattern: Some("session:*".to_owned()),
+        };
+        assert!(filter.matches(addr, &redis_args(&["GET", "session:1"])));
+        assert!(!filter.matches(addr, &redis_args(&["GET", "user:1"])));
+        assert!(!filter.matches(addr, &redis_args(&["PING"])));
+        assert!(!filter.matches(
+            Some("10.0.1.1:4000".parse().unwrap()),
+            &redis_args(&["GET", "session:1"])
+        ));
+        assert!(!filter.matches(None, &redis_args(&["GET", "session:1"])));
+
+        let filter = Filter {
+            client_pattern: None,
+            key_pattern: None,
+        };
+        assert!(filter.matches(None, &redis_args(&["PING"])));
+    }
+}
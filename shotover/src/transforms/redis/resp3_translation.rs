@@ -0,0 +1,532 @@
+use crate::codec::CodecState;
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageIdMap, MessageIdSet, Messages};
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use redis_protocol::resp2::encode::extend_encode;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisResp3TranslationConfig {
+    /// The redis version reported to clients in the response to HELLO, defaults to 6.0.0
+    pub server_version: Option<String>,
+    /// When true, responses to RESP3 clients are preceded by an attribute frame containing the time spent in shotover and in the backend
+    #[serde(default)]
+    pub timing_attributes: bool,
+}
+
+const NAME: &str = "RedisResp3Translation";
+#[typetag::serde(name = "RedisResp3Translation")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisResp3TranslationConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RedisResp3TranslationBuilder {
+            server_version: self
+                .server_version
+                .clone()
+                .unwrap_or_else(|| "6.0.0".to_owned()),
+            timing_attributes: self.timing_attributes,
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+pub struct RedisResp3TranslationBuilder {
+    server_version: String,
+    timing_attributes: bool,
+}
+
+impl TransformBuilder for RedisResp3TranslationBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisResp3Translation {
+            server_version: self.server_version.clone(),
+            timing_attributes: self.timing_attributes,
+            client_uses_resp3: false,
+            pending: MessageIdMap::default(),
+            discard: MessageIdSet::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// Lets clients negotiate RESP3 via HELLO while the connection to the backend stays on RESP2.
+///
+/// HELLO is never sent to the backend, it is answered by shotover instead.
+/// This means backends that predate HELLO keep working and backends that do support RESP3 never switch to it,
+/// so responses from down chain are always RESP2.
+/// Once a client has switched to RESP3, responses are re-encoded into the RESP3 types that the client expects.
+pub struct RedisResp3Translation {
+    server_version: String,
+    timing_attributes: bool,
+    client_uses_resp3: bool,
+    /// What to do with the response to a request, keyed by the id of the request
+    pending: MessageIdMap<PendingResponse>,
+    /// Requests introduced by this transform whose responses must be removed
+    discard: MessageIdSet,
+}
+
+enum PendingResponse {
+    /// HELLO was answered without contacting the backend, replace the dummy response with this message
+    Replace(Message),
+    /// HELLO was rewritten into an AUTH, reply to HELLO if AUTH succeeds
+    Hello { resp3: bool },
+    /// The response must be re-encoded as RESP3, preceded by the timing attribute if the request was timed
+    Reshape(ReplyShape, Option<RequestTiming>),
+}
+
+struct RequestTiming {
+    /// The time from when shotover received the request until it was sent down the chain
+    queue: Duration,
+    /// The instant the request was sent down the chain
+    sent_at: Instant,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ReplyShape {
+    Plain,
+    Map,
+    Set,
+    Double,
+    Push,
+}
+
+fn reply_shape(command: &[u8], args: &[RedisFrame]) -> ReplyShape {
+    match command {
+        b"HGETALL" => ReplyShape::Map,
+        b"CONFIG" => match args.first() {
+            Some(RedisFrame::BulkString(sub)) if sub.eq_ignore_ascii_case(b"GET") => {
+                ReplyShape::Map
+            }
+            _ => ReplyShape::Plain,
+        },
+        b"SMEMBERS" | b"SINTER" | b"SUNION" | b"SDIFF" => ReplyShape::Set,
+        b"ZSCORE" | b"ZINCRBY" => ReplyShape::Double,
+        b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" | b"UNSUBSCRIBE" | b"PUNSUBSCRIBE"
+        | b"SUNSUBSCRIBE" => ReplyShape::Push,
+        _ => ReplyShape::Plain,
+    }
+}
+
+struct Hello {
+    protover: Option<i64>,
+    auth: Option<(Bytes, Bytes)>,
+    setname: Option<Bytes>,
+}
+
+/// Parse `HELLO [protover [AUTH username password] [SETNAME clientname]]`
+fn parse_hello(args: &[RedisFrame]) -> Result<Hello, &'static str> {
+    let mut args = args.iter().map(|arg| match arg {
+        RedisFrame::BulkString(arg) => Ok(arg.clone()),
+        _ => Err("ERR Protocol error: expected bulk string"),
+    });
+    let mut hello = Hello {
+        protover: None,
+        auth: None,
+        setname: None,
+    };
+
+    if let Some(protover) = args.next() {
+        let protover = std::str::from_utf8(&protover?)
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .ok_or("ERR Protocol version is not an integer or out of range")?;
+        hello.protover = Some(protover);
+    }
+
+    while let Some(option) = args.next() {
+        let option = option?;
+        if option.eq_ignore_ascii_case(b"AUTH") {
+            match (args.next(), args.next()) {
+                (Some(username), Some(password)) => hello.auth = Some((username?, password?)),
+                _ => return Err("ERR Syntax error in HELLO option 'AUTH'"),
+            }
+        } else if option.eq_ignore_ascii_case(b"SETNAME") {
+            match args.next() {
+                Some(name) => hello.setname = Some(name?),
+                None => return Err("ERR Syntax error in HELLO option 'SETNAME'"),
+            }
+        } else {
+            return Err("ERR Syntax error in HELLO option");
+        }
+    }
+
+    Ok(hello)
+}
+
+fn bulk(value: &'static [u8]) -> RedisFrame {
+    RedisFrame::BulkString(Bytes::from_static(value))
+}
+
+impl RedisResp3Translation {
+    fn hello_reply(&self, resp3: bool) -> RedisFrame {
+        RedisFrame::Array(vec![
+            bulk(b"server"),
+            bulk(b"redis"),
+            bulk(b"version"),
+            RedisFrame::BulkString(self.server_version.clone().into()),
+            bulk(b"proto"),
+            RedisFrame::Integer(if resp3 { 3 } else { 2 }),
+            bulk(b"id"),
+            RedisFrame::Integer(0),
+            bulk(b"mode"),
+            bulk(b"standalone"),
+            bulk(b"role"),
+            bulk(b"master"),
+            bulk(b"modules"),
+            RedisFrame::Array(vec![]),
+        ])
+    }
+
+    /// Create a response in the protocol version the client is using at the time of the response.
+    fn response(&self, frame: RedisFrame, shape: ReplyShape, resp3: bool) -> Result<Message> {
+        if resp3 {
+            let mut bytes = BytesMut::new();
+            encode_resp3(&frame, shape, &mut bytes)?;
+            Ok(Message::from_bytes(bytes.freeze(), CodecState::Redis))
+        } else {
+            Ok(Message::from_frame(Frame::Redis(frame)))
+        }
+    }
+
+    /// Rewrites the HELLO request in place and returns any extra request that must follow it
+    fn handle_hello(&mut self, request: &mut Message, hello: Hello) -> Result<Option<Message>> {
+        let resp3 = match hello.protover {
+            None => self.client_uses_resp3,
+            Some(2) => false,
+            Some(3) => true,
+            Some(_) => {
+                let error = RedisFrame::Error(
+                    "NOPROTO sorry, this protocol version is not supported".into(),
+                );
+                let mut response = self.response(error, ReplyShape::Plain, false)?;
+                response.set_request_id(request.id());
+                self.pending
+                    .insert(request.id(), PendingResponse::Replace(response));
+                request.replace_with_dummy();
+                return Ok(None);
+            }
+        };
+        self.client_uses_resp3 = resp3;
+
+        let setname = hello.setname.map(|name| {
+            RedisFrame::Array(vec![
+                bulk(b"CLIENT"),
+                bulk(b"SETNAME"),
+                RedisFrame::BulkString(name),
+            ])
+        });
+
+        match (hello.auth, setname) {
+            (Some((username, password)), setname) => {
+                // The default user is sent as a single argument AUTH to remain compatible with redis versions prior to ACLs
+                let auth = if username.as_ref() == b"default" {
+                    vec![bulk(b"AUTH"), RedisFrame::BulkString(password)]
+                } else {
+                    vec![
+                        bulk(b"AUTH"),
+                        RedisFrame::BulkString(username),
+                        RedisFrame::BulkString(password),
+                    ]
+                };
+                *request = replace_frame(request, RedisFrame::Array(auth));
+                self.pending
+                    .insert(request.id(), PendingResponse::Hello { resp3 });
+
+                Ok(setname.map(|setname| {
+                    let extra = Message::from_frame(Frame::Redis(setname));
+                    self.discard.insert(extra.id());
+                    extra
+                }))
+            }
+            (None, Some(setname)) => {
+                *request = replace_frame(request, setname);
+                self.pending
+                    .insert(request.id(), PendingResponse::Hello { resp3 });
+                Ok(None)
+            }
+            (None, None) => {
+                let mut response =
+                    self.response(self.hello_reply(resp3), ReplyShape::Map, resp3)?;
+                response.set_request_id(request.id());
+                self.pending
+                    .insert(request.id(), PendingResponse::Replace(response));
+                request.replace_with_dummy();
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Replace the contents of a request while keeping its id so the response can still be matched to it.
+fn replace_frame(request: &Message, frame: RedisFrame) -> Message {
+    Message::from_frame_diverged(Frame::Redis(frame), request)
+}
+
+#[async_trait]
+impl Transform for RedisResp3Translation {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let now = Instant::now();
+        let mut requests = Vec::with_capacity(requests_wrapper.requests.len());
+        for mut request in std::mem::take(&mut requests_wrapper.requests) {
+            let mut extra = None;
+            let request_id = request.id();
+            if let Some(Frame::Redis(RedisFrame::Array(array))) = request.frame() {
+                if let Some(RedisFrame::BulkString(command)) = array.first() {
+                    let command = command.to_ascii_uppercase();
+                    if command == b"HELLO" {
+                        match parse_hello(&array[1..]) {
+                            Ok(hello) => extra = self.handle_hello(&mut request, hello)?,
+                            Err(error) => {
+                                let mut response = self.response(
+                                    RedisFrame::Error(error.into()),
+                                    ReplyShape::Plain,
+                                    false,
+                                )?;
+                                response.set_request_id(request_id);
+                                self.pending
+                                    .insert(request_id, PendingResponse::Replace(response));
+                                request.replace_with_dummy();
+                            }
+                        }
+                    } else if self.client_uses_resp3 {
+                        let timing = self.timing_attributes.then(|| RequestTiming {
+                            queue: request
+                                .received_from_source_or_sink_at
+                                .map(|received_at| now.saturating_duration_since(received_at))
+                                .unwrap_or_default(),
+                            sent_at: now,
+                        });
+                        self.pending.insert(
+                            request_id,
+                            PendingResponse::Reshape(reply_shape(&command, &array[1..]), timing),
+                        );
+                    }
+                }
+            }
+            requests.push(request);
+            requests.extend(extra);
+        }
+        requests_wrapper.requests = requests;
+
+        let responses = requests_wrapper.call_next_transform().await?;
+
+        let mut result = Vec::with_capacity(responses.len());
+        for mut response in responses {
+            let request_id = response.request_id();
+            if let Some(request_id) = request_id {
+                if self.discard.remove(&request_id) {
+                    continue;
+                }
+            }
+            let pending = request_id.and_then(|id| self.pending.remove(&id));
+
+            let (shape, timing) = match pending {
+                Some(PendingResponse::Replace(replacement)) => {
+                    result.push(replacement);
+                    continue;
+                }
+                Some(PendingResponse::Hello { resp3 }) => {
+                    if !matches!(response.frame(), Some(Frame::Redis(RedisFrame::Error(_)))) {
+                        let mut reply =
+                            self.response(self.hello_reply(resp3), ReplyShape::Map, resp3)?;
+                        reply.set_request_id(request_id.unwrap());
+                        response = reply;
+                    }
+                    result.push(response);
+                    continue;
+                }
+                Some(PendingResponse::Reshape(shape, timing)) => (shape, timing),
+                // Responses without a request are pubsub messages which are push frames in RESP3
+                None if request_id.is_none() && self.client_uses_resp3 => (ReplyShape::Push, None),
+                None => {
+                    result.push(response);
+                    continue;
+                }
+            };
+
+            let frame = match response.frame() {
+                Some(Frame::Redis(frame)) => frame.clone(),
+                _ => return Err(anyhow!("Failed to parse redis response")),
+            };
+            let mut bytes = BytesMut::new();
+            if let Some(timing) = timing {
+                // The sink records when it read the response off the connection to the backend,
+                // any time after that was spent in shotover rather than in the backend.
+                let upstream = response
+                    .received_from_source_or_sink_at
+                    .unwrap_or_else(Instant::now)
+                    .saturating_duration_since(timing.sent_at);
+                encode_timing_attribute(timing.queue, upstream, &mut bytes);
+            }
+            encode_resp3(&frame, shape, &mut bytes)?;
+            let mut reshaped = Message::from_bytes(bytes.freeze(), CodecState::Redis);
+            if let Some(request_id) = request_id {
+                reshaped.set_request_id(request_id);
+            }
+            result.push(reshaped);
+        }
+
+        Ok(result)
+    }
+}
+
+fn encode_header(dst: &mut BytesMut, kind: u8, len: usize) {
+    dst.put_u8(kind);
+    dst.extend_from_slice(len.to_string().as_bytes());
+    dst.extend_from_slice(b"\r\n");
+}
+
+/// Encode a RESP3 attribute frame that tells instrumented clients how the latency of the following reply was spent
+fn encode_timing_attribute(queue: Duration, upstream: Duration, dst: &mut BytesMut) {
+    encode_header(dst, b'|', 2);
+    for (key, duration) in [("queue_time_us", queue), ("upstream_time_us", upstream)] {
+        dst.put_u8(b'+');
+        dst.extend_from_slice(key.as_bytes());
+        dst.extend_from_slice(b"\r\n");
+        encode_header(dst, b':', duration.as_micros() as usize);
+    }
+}
+
+/// Encode a RESP2 frame as RESP3 according to the shape RESP3 uses for the command's reply.
+/// Simple strings, errors, integers and bulk strings are encoded identically in both protocols.
+fn encode_resp3(frame: &RedisFrame, shape: ReplyShape, dst: &mut BytesMut) -> Result<()> {
+    match (shape, frame) {
+        (_, RedisFrame::Null) => dst.extend_from_slice(b"_\r\n"),
+        (ReplyShape::Map, RedisFrame::Array(array)) if array.len() % 2 == 0 => {
+            encode_header(dst, b'%', array.len() / 2);
+            for element in array {
+                encode_resp3(element, ReplyShape::Plain, dst)?;
+            }
+        }
+        (ReplyShape::Set, RedisFrame::Array(array)) => {
+            encode_header(dst, b'~', array.len());
+            for element in array {
+                encode_resp3(element, ReplyShape::Plain, dst)?;
+            }
+        }
+        (ReplyShape::Push, RedisFrame::Array(array)) => {
+            encode_header(dst, b'>', array.len());
+            for element in array {
+                encode_resp3(element, ReplyShape::Plain, dst)?;
+            }
+        }
+        (ReplyShape::Double, RedisFrame::BulkString(value)) => {
+            dst.put_u8(b',');
+            dst.extend_from_slice(value);
+            dst.extend_from_slice(b"\r\n");
+        }
+        (_, RedisFrame::Array(array)) => {
+            encode_header(dst, b'*', array.len());
+            for element in array {
+                encode_resp3(element, ReplyShape::Plain, dst)?;
+            }
+        }
+        (_, frame) => {
+            extend_encode(dst, frame).map_err(|e| anyhow!("Redis encoding error: {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn encode(frame: RedisFrame, shape: ReplyShape) -> Bytes {
+        let mut dst = BytesMut::new();
+        encode_resp3(&frame, shape, &mut dst).unwrap();
+        dst.freeze()
+    }
+
+    #[test]
+    fn test_encode_map() {
+        let frame = RedisFrame::Array(vec![bulk(b"a"), RedisFrame::Integer(1)]);
+        assert_eq!(
+            encode(frame, ReplyShape::Map),
+            &b"%1\r\n$1\r\na\r\n:1\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_encode_set_with_null_member() {
+        let frame = RedisFrame::Array(vec![bulk(b"a"), RedisFrame::Null]);
+        assert_eq!(
+            encode(frame, ReplyShape::Set),
+            &b"~2\r\n$1\r\na\r\n_\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_encode_double() {
+        assert_eq!(encode(bulk(b"1.5"), ReplyShape::Double), &b",1.5\r\n"[..]);
+        assert_eq!(encode(RedisFrame::Null, ReplyShape::Double), &b"_\r\n"[..]);
+    }
+
+    #[test]
+    fn test_encode_odd_map_falls_back_to_array() {
+        let frame = RedisFrame::Array(vec![bulk(b"a")]);
+        assert_eq!(encode(frame, ReplyShape::Map), &b"*1\r\n$1\r\na\r\n"[..]);
+    }
+
+    #[test]
+    fn test_encode_timing_attribute() {
+        let mut dst = BytesMut::new();
+        encode_timing_attribute(
+            Duration::from_micros(15),
+            Duration::from_millis(2),
+            &mut dst,
+        );
+        encode_resp3(&bulk(b"a"), ReplyShape::Plain, &mut dst).unwrap();
+        assert_eq!(
+            dst.freeze(),
+            &b"|2\r\n+queue_time_us\r\n:15\r\n+upstream_time_us\r\n:2000\r\n$1\r\na\r\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_parse_hello() {
+        let hello = parse_hello(&[
+            bulk(b"3"),
+            bulk(b"auth"),
+            bulk(b"user"),
+            bulk(b"pass"),
+            bulk(b"SETNAME"),
+            bulk(b"name"),
+        ])
+        .unwrap();
+        assert_eq!(hello.protover, Some(3));
+        assert_eq!(
+            hello.auth,
+            Some((Bytes::from_static(b"user"), Bytes::from_static(b"pass")))
+        );
+        assert_eq!(hello.setname, Some(Bytes::from_static(b"name")));
+
+        assert!(parse_hello(&[bulk(b"three")]).is_err());
+        assert!(parse_hello(&[bulk(b"3"), bulk(b"AUTH"), bulk(b"user")]).is_err());
+    }
+}
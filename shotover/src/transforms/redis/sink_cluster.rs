@@ -1,22 +1,30 @@
 use crate::codec::redis::RedisCodecBuilder;
 use crate::codec::{CodecBuilder, Direction};
+use crate::frame::redis::{redis_blocking, RedisBlocking};
 use crate::frame::{Frame, MessageType, RedisFrame};
-use crate::message::{Message, Messages};
+use crate::message::{Message, Messages, QueryType};
+use crate::snapshot::{self, StateSnapshot};
+use crate::state_sync::{self, SyncedState};
 use crate::tls::TlsConnectorConfig;
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::redis::RedisError;
 use crate::transforms::redis::TransformError;
-use crate::transforms::util::cluster_connection_pool::{Authenticator, ConnectionPool};
+use crate::transforms::util::cluster_connection_pool::{
+    Authenticator, Connection, ConnectionPool, PipeliningConfig,
+};
+use crate::transforms::util::gather::{gather_parts, Gather};
+use crate::transforms::util::prewarm::{Prewarm, PrewarmConfig};
 use crate::transforms::util::{Request, Response};
 use crate::transforms::{
     DownChainProtocol, ResponseFuture, Transform, TransformBuilder, TransformConfig,
     TransformContextBuilder, TransformContextConfig, UpChainProtocol, Wrapper,
 };
+use crate::tuning::{self, PooledSink};
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
 use derivative::Derivative;
 use futures::stream::FuturesOrdered;
-use futures::stream::FuturesUnordered;
 use futures::{StreamExt, TryFutureExt};
 use itertools::Itertools;
 use metrics::counter;
@@ -26,7 +34,9 @@ use rand::SeedableRng;
 use redis_protocol::bytes_utils::string::Str;
 use redis_protocol::resp2::types::Resp2Frame;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::{oneshot, RwLock};
@@ -34,6 +44,11 @@ use tokio::time::{timeout, Duration};
 use tracing::{debug, trace, warn};
 
 const SLOT_SIZE: usize = 16384;
+/// The longest a WAIT or WAITAOF may hold up a connection shared with other clients
+const SHARED_WAIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The most connections used only by a single client that are opened for its blocking commands
+const MAX_BLOCKING_CONNECTIONS: usize = 16;
 
 type ChannelMap = HashMap<String, Vec<UnboundedSender<Request>>>;
 
@@ -45,6 +60,13 @@ pub struct RedisSinkClusterConfig {
     pub tls: Option<TlsConnectorConfig>,
     pub connection_count: Option<usize>,
     pub connect_timeout_ms: u64,
+    #[serde(default)]
+    pub pipelining: PipeliningConfig,
+    /// How long to wait for each node when a request is split across multiple nodes.
+    pub split_request_timeout_ms: Option<u64>,
+    /// Grows the connections to each node beyond `connection_count` ahead of demand.
+    pub prewarm: Option<PrewarmConfig>,
+    pub keepalive_interval: Option<u64>,
 }
 
 const NAME: &str = "RedisSinkCluster";
@@ -60,14 +82,49 @@ impl TransformConfig for RedisSinkClusterConfig {
             RedisCodecBuilder::new(Direction::Sink, "RedisSinkCluster".to_owned()),
             RedisAuthenticator {},
             self.tls.clone(),
+            self.pipelining.clone(),
+            self.keepalive_interval.map(Duration::from_secs),
         )?;
+        let connection_count = self.connection_count.unwrap_or(1);
+        let prewarm = self
+            .prewarm
+            .as_ref()
+            .map(|config| {
+                Prewarm::new(
+                    config,
+                    connection_count,
+                    transform_context.chain_name.clone(),
+                )
+            })
+            .transpose()?;
+        if let Some(prewarm) = &prewarm {
+            let connection_pool = connection_pool.clone();
+            prewarm.spawn(move |connection_count| {
+                let connection_pool = connection_pool.clone();
+                async move { connection_pool.resize(connection_count).await }
+            });
+        }
+        let shared_topology = Arc::new(RwLock::new(Topology::new()));
+        let state = Arc::new(RedisSinkClusterState {
+            shared_topology: shared_topology.clone(),
+            connection_pool: connection_pool.clone(),
+            connection_count,
+            prewarm: prewarm.clone(),
+        });
+        snapshot::register(NAME, &transform_context.chain_name, &state);
+        state_sync::register(NAME, &transform_context.chain_name, &state);
+        tuning::register_pool(NAME, &transform_context.chain_name, &state);
         Ok(Box::new(RedisSinkClusterBuilder {
             first_contact_points: self.first_contact_points.clone(),
             direct_destination: self.direct_destination.clone(),
-            connection_count: self.connection_count.unwrap_or(1),
+            connection_count,
+            prewarm,
             connection_pool,
             chain_name: transform_context.chain_name,
-            shared_topology: Arc::new(RwLock::new(Topology::new())),
+            shared_topology,
+            _state: state,
+            client_counter: Arc::new(AtomicUsize::new(0)),
+            split_request_timeout: self.split_request_timeout_ms.map(Duration::from_millis),
         }))
     }
 
@@ -84,9 +141,82 @@ pub struct RedisSinkClusterBuilder {
     first_contact_points: Vec<String>,
     direct_destination: Option<String>,
     connection_count: usize,
+    prewarm: Option<Arc<Prewarm>>,
     connection_pool: ConnectionPool<RedisCodecBuilder, RedisAuthenticator, UsernamePasswordToken>,
     chain_name: String,
     shared_topology: Arc<RwLock<Topology>>,
+    client_counter: Arc<AtomicUsize>,
+    split_request_timeout: Option<Duration>,
+    /// Kept alive so that the state of the transform is included in snapshots for as long as the builder exists
+    _state: Arc<RedisSinkClusterState>,
+}
+
+/// The state shared between every instance of a RedisSinkCluster, for state snapshots, state sync and workload analysis
+struct RedisSinkClusterState {
+    shared_topology: Arc<RwLock<Topology>>,
+    connection_pool: ConnectionPool<RedisCodecBuilder, RedisAuthenticator, UsernamePasswordToken>,
+    connection_count: usize,
+    prewarm: Option<Arc<Prewarm>>,
+}
+
+impl PooledSink for RedisSinkClusterState {
+    fn connection_count(&self) -> usize {
+        match &self.prewarm {
+            Some(prewarm) => prewarm.connection_count(),
+            None => self.connection_count,
+        }
+    }
+
+    fn connection_count_setting(&self) -> &'static str {
+        match &self.prewarm {
+            Some(_) => "prewarm connection counts",
+            None => "connection_count",
+        }
+    }
+}
+
+#[async_trait]
+impl StateSnapshot for RedisSinkClusterState {
+    async fn snapshot(&self) -> serde_json::Value {
+        let slots = self.shared_topology.read().await.slots.clone();
+        json!({
+            "masters": slots.masters,
+            "replicas": slots.replicas,
+            "pool": self.connection_pool.open_connections().await,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncedSlots {
+    masters: BTreeMap<u16, String>,
+    replicas: BTreeMap<u16, String>,
+}
+
+#[async_trait]
+impl SyncedState for RedisSinkClusterState {
+    async fn export(&self) -> Option<serde_json::Value> {
+        let topology = self.shared_topology.read().await;
+        if topology.slots.masters.is_empty() {
+            return None;
+        }
+        serde_json::to_value(SyncedSlots {
+            masters: topology.slots.masters.clone(),
+            replicas: topology.slots.replicas.clone(),
+        })
+        .ok()
+    }
+
+    async fn import(&self, state: serde_json::Value) -> Result<()> {
+        let synced: SyncedSlots = serde_json::from_value(state)?;
+        let mut topology = self.shared_topology.write().await;
+        // Once this instance has connected to the cluster its own slot map is at least as recent
+        if topology.channels.is_empty() {
+            topology.slots = SlotMap::from_maps(synced.masters, synced.replicas);
+            topology.synced = true;
+        }
+        Ok(())
+    }
 }
 
 impl TransformBuilder for RedisSinkClusterBuilder {
@@ -95,9 +225,12 @@ impl TransformBuilder for RedisSinkClusterBuilder {
             self.first_contact_points.clone(),
             self.direct_destination.clone(),
             self.connection_count,
+            self.prewarm.clone(),
             self.chain_name.clone(),
             self.shared_topology.clone(),
             self.connection_pool.clone(),
+            self.client_counter.fetch_add(1, Ordering::Relaxed),
+            self.split_request_timeout,
         ))
     }
 
@@ -105,6 +238,16 @@ impl TransformBuilder for RedisSinkClusterBuilder {
         NAME
     }
 
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Each request picks one of the connections to its node at random,
+        // so when there is more than one connection requests to the same key can overtake each other.
+        if self.connection_count == 1 && self.prewarm.is_none() {
+            DeliveryGuarantees::PASSTHROUGH
+        } else {
+            DeliveryGuarantees::UNORDERED
+        }
+    }
+
     fn is_terminating(&self) -> bool {
         true
     }
@@ -114,6 +257,8 @@ impl TransformBuilder for RedisSinkClusterBuilder {
 struct Topology {
     slots: SlotMap,
     channels: ChannelMap,
+    /// The slot map was synced from the active instance of a warm standby pair and has not yet been used by this instance
+    synced: bool,
 }
 
 impl Topology {
@@ -121,6 +266,7 @@ impl Topology {
         Topology {
             slots: SlotMap::new(),
             channels: ChannelMap::new(),
+            synced: false,
         }
     }
 }
@@ -134,19 +280,31 @@ pub struct RedisSinkCluster {
     load_scores: HashMap<(String, usize), usize>,
     rng: SmallRng,
     connection_count: usize,
+    prewarm: Option<Arc<Prewarm>>,
     connection_pool: ConnectionPool<RedisCodecBuilder, RedisAuthenticator, UsernamePasswordToken>,
     reason_for_no_nodes: Option<&'static str>,
     rebuild_connections: bool,
     first_contact_points: Vec<String>,
     direct_destination: Option<String>,
     token: Option<UsernamePasswordToken>,
+    /// Identifies this client to connections shared with other clients
+    client_id: usize,
+    split_request_timeout: Option<Duration>,
+    /// Connections to each node used only by this client, for blocking commands.
+    /// A blocking command would hold up every other request on a connection shared with other clients,
+    /// and redis only cancels it once its connection is closed, which happens when this client disconnects and this transform is dropped.
+    blocking_connections: HashMap<String, Connection>,
+    /// The connections shared with other clients that the client has sent writes on since its last WAIT or WAITAOF, which the next WAIT needs to cover
+    written_connections: Vec<Connection>,
 }
 
 impl RedisSinkCluster {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         first_contact_points: Vec<String>,
         direct_destination: Option<String>,
         connection_count: usize,
+        prewarm: Option<Arc<Prewarm>>,
         chain_name: String,
         shared_topology: Arc<RwLock<Topology>>,
         connection_pool: ConnectionPool<
@@ -154,6 +312,8 @@ impl RedisSinkCluster {
             RedisAuthenticator,
             UsernamePasswordToken,
         >,
+        client_id: usize,
+        split_request_timeout: Option<Duration>,
     ) -> Self {
         let sink_cluster = RedisSinkCluster {
             chain_name: chain_name.clone(),
@@ -166,10 +326,15 @@ impl RedisSinkCluster {
             load_scores: HashMap::new(),
             rng: SmallRng::from_rng(rand::thread_rng()).unwrap(),
             connection_count,
+            prewarm,
             connection_pool,
             reason_for_no_nodes: None,
             rebuild_connections: true,
             token: None,
+            client_id,
+            split_request_timeout,
+            blocking_connections: HashMap::new(),
+            written_connections: vec![],
         };
 
         counter!("shotover_failed_requests_count", "chain" => chain_name, "transform" => sink_cluster.get_name());
@@ -177,6 +342,35 @@ impl RedisSinkCluster {
         sink_cluster
     }
 
+    /// Switches to the connection count chosen by prewarm, taking the already opened connections from the pool.
+    async fn update_connection_count(&mut self) {
+        let Some(prewarm) = &self.prewarm else {
+            return;
+        };
+        let connection_count = prewarm.connection_count();
+        if connection_count == self.connection_count {
+            return;
+        }
+
+        self.connection_count = connection_count;
+        for (host, channels) in self.topology.channels.iter_mut() {
+            match self
+                .connection_pool
+                .get_connections(host, &self.token, connection_count)
+                .await
+            {
+                Ok(connections) => {
+                    *channels = connections;
+                }
+                Err(err) => {
+                    debug!("failed to resize connections to {host}: {err}");
+                    self.rebuild_connections = true;
+                }
+            }
+        }
+        self.load_scores.clear();
+    }
+
     async fn direct_connection(&mut self) -> Result<&UnboundedSender<Request>> {
         if self.direct_connection.is_none() {
             match &self.direct_destination {
@@ -218,11 +412,15 @@ impl RedisSinkCluster {
     async fn send_message_to_slot(
         &mut self,
         slot: u16,
-        message: Message,
+        mut message: Message,
     ) -> Result<ResponseFuture> {
         if let Some((_, lookup)) = self.topology.slots.masters.range(&slot..).next() {
             let lookup = lookup.to_string();
-            let one_rx = self.choose_and_send(&lookup, message).await?;
+            let one_rx = if is_blocking(&mut message) {
+                self.send_blocking(&lookup, message).await?
+            } else {
+                self.choose_and_send(&lookup, message).await?
+            };
             Ok(Box::pin(
                 one_rx.map_err(|_| anyhow!("no response from single channel")),
             ))
@@ -256,55 +454,142 @@ impl RedisSinkCluster {
             // If any of the responses were a failure then return that failure.
             // Otherwise collate results according to routing_info.
             _ => {
-                let responses = FuturesUnordered::new();
+                let mut responses = vec![];
 
                 for channel in channels {
                     responses.push(self.choose_and_send(channel, message.clone()).await?);
                 }
-                Ok(Box::pin(async move {
-                    let response = responses
-                        .fold(None, |acc, response| async move {
-                            if let Some((_, RedisFrame::Error(_))) = acc {
-                                acc
-                            } else {
-                                match response {
-                                    Ok(Response {
-                                        response: Ok(mut message),
-                                        ..
-                                    }) => Some((
-                                        message.received_from_source_or_sink_at,
-                                        match message.frame().unwrap() {
-                                            Frame::Redis(frame) => {
-                                                let new_frame = frame.take();
-                                                match acc {
-                                                    Some((_, prev_frame)) => routing_info
-                                                        .response_join()
-                                                        .join(prev_frame, new_frame),
-                                                    None => new_frame,
-                                                }
-                                            }
-                                            _ => unreachable!("direct response from a redis sink"),
-                                        },
-                                    )),
-                                    Ok(Response {
-                                        response: Err(e), ..
-                                    }) => Some((None, RedisFrame::Error(e.to_string().into()))),
-                                    Err(e) => Some((None, RedisFrame::Error(e.to_string().into()))),
-                                }
-                            }
-                        })
-                        .await;
-
-                    let (received_at, response) = response.unwrap();
-                    Ok(Response {
-                        response: Ok(Message::from_frame_at_instant(
-                            Frame::Redis(response),
-                            received_at,
-                        )),
-                    })
-                }))
+                Ok(join_responses(responses, routing_info.response_join()))
+            }
+        }
+    }
+
+    /// WAIT and WAITAOF only wait for the writes previously sent on the connection they are sent on.
+    /// So the command is sent on every connection the client has sent writes on since its last WAIT,
+    /// and the lowest count is returned since that is the number of replicas that have acknowledged every write.
+    ///
+    /// These connections are shared with other clients, so the timeout is capped at [`SHARED_WAIT_TIMEOUT`] to hold up other clients for no longer than that.
+    /// A capped command may report fewer replicas than eventually acknowledge the writes, which errs on the side of the client retrying.
+    ///
+    /// When the client has not sent any writes the command is sent over the client's own connection to a master instead,
+    /// where it responds immediately with the count of connected replicas.
+    async fn send_message_to_all_master_connections(
+        &mut self,
+        message: Message,
+        response_join: ResponseJoin,
+    ) -> Result<ResponseFuture> {
+        let written_connections = std::mem::take(&mut self.written_connections);
+        let written_connections = written_connections
+            .into_iter()
+            .filter(|connection| !connection.is_closed())
+            .collect_vec();
+        if written_connections.is_empty() {
+            let Some(master) = self
+                .topology
+                .slots
+                .masters
+                .values()
+                .choose(&mut self.rng)
+                .cloned()
+            else {
+                return self.send_error_response(
+                    self.reason_for_no_nodes
+                        .unwrap_or("ERR Shotover RedisSinkCluster does not know of any nodes"),
+                );
+            };
+            return self.send_blocking(&master, message).await;
+        }
+
+        let capped = cap_wait_timeout(message, SHARED_WAIT_TIMEOUT);
+        let mut responses: Vec<ResponseFuture> = Vec::with_capacity(written_connections.len());
+        for connection in written_connections {
+            match send_message_request(&connection, capped.clone()) {
+                Ok(response) => responses.push(Box::pin(response.map_err(|e| anyhow!(e)))),
+                Err(_) => {
+                    self.rebuild_connections = true;
+                    return self.short_circuit_with_error();
+                }
             }
         }
+        Ok(join_responses(responses, response_join))
+    }
+
+    /// Records that the client sent a write on a connection shared with other clients, so that its next WAIT covers it
+    fn track_write(&mut self, connection: &Connection, message: &mut Message) {
+        if message.get_query_type() != QueryType::Read
+            && !self
+                .written_connections
+                .iter()
+                .any(|written| written.same_channel(connection))
+        {
+            self.written_connections.push(connection.clone());
+        }
+    }
+
+    /// Splits an MGET into one MGET per slot and reassembles the values into a single response in the order the keys were requested.
+    async fn send_mget_by_slot(&mut self, args: Vec<RedisFrame>) -> Result<ResponseFuture> {
+        let mut gather = Gather::default();
+        let mut parts: Vec<(u16, Vec<RedisFrame>)> = vec![];
+        for key in args.into_iter().skip(1) {
+            let Some(RoutingInfo::Slot(slot)) = RoutingInfo::for_key(&key) else {
+                bail!("syntax error: expected bulk string");
+            };
+            let part = match parts.iter().position(|(part_slot, _)| *part_slot == slot) {
+                Some(part) => part,
+                None => {
+                    parts.push((slot, vec![RedisFrame::BulkString("MGET".into())]));
+                    parts.len() - 1
+                }
+            };
+            parts[part].1.push(key);
+            gather.push(part);
+        }
+
+        let mut responses = Vec::with_capacity(parts.len());
+        for (slot, command) in parts {
+            let message = Message::from_frame(Frame::Redis(RedisFrame::Array(command)));
+            responses.push(self.send_message_to_slot(slot, message).await?);
+        }
+
+        let split_request_timeout = self.split_request_timeout;
+        Ok(Box::pin(async move {
+            let mut values = Vec::with_capacity(gather.part_count());
+            for response in gather_parts(responses, split_request_timeout).await {
+                let frame = match response.and_then(|x| x.response) {
+                    Ok(mut message) => match message.frame() {
+                        Some(Frame::Redis(frame)) => frame.take(),
+                        _ => unreachable!("direct response from a redis sink"),
+                    },
+                    Err(e) => RedisFrame::Error(format!("ERR {e}").into()),
+                };
+                match frame {
+                    RedisFrame::Array(part_values) => values.push(part_values),
+                    // An ASK for a single part cannot be retried by resending the whole MGET to the migrating node,
+                    // so respond the same way redis does when the keys of a multi key request are being migrated.
+                    // A MOVED is returned as is, the MGET is then split again according to the updated slot map.
+                    frame @ RedisFrame::Error(_)
+                        if matches!(Redirection::parse(&frame), Some(Redirection::Ask { .. })) =>
+                    {
+                        return Ok(Response {
+                            response: Ok(Message::from_frame(Frame::Redis(RedisFrame::Error(
+                                "TRYAGAIN Multiple keys request during rehashing of slot".into(),
+                            )))),
+                        });
+                    }
+                    frame => {
+                        return Ok(Response {
+                            response: Ok(Message::from_frame(Frame::Redis(frame))),
+                        })
+                    }
+                }
+            }
+
+            Ok(Response {
+                response: Ok(Message::from_frame(Frame::Redis(RedisFrame::Array(
+                    gather.reassemble(values)?,
+                )))),
+            })
+        }))
     }
 
     fn latest_contact_points(&self) -> Vec<&str> {
@@ -371,12 +656,18 @@ impl RedisSinkCluster {
         match self.build_connections_inner(&token).await {
             Ok((slots, channels)) => {
                 debug!("connected to cluster: {:?}", channels.keys());
-                self.topology = Topology { slots, channels };
+                self.topology = Topology {
+                    slots,
+                    channels,
+                    synced: false,
+                };
                 if token.is_none() {
                     // when authentication isnt used we can share topology between connections
                     *self.shared_topology.write().await = self.topology.clone();
                 }
                 self.token = token;
+                // Blocking connections were authenticated with the previous token
+                self.blocking_connections.clear();
                 self.reason_for_no_nodes = None;
                 self.rebuild_connections = false;
                 Ok(())
@@ -395,8 +686,16 @@ impl RedisSinkCluster {
         &mut self,
         token: &Option<UsernamePasswordToken>,
     ) -> Result<(SlotMap, ChannelMap), TransformError> {
-        // NOTE: Fetch slot map uses unpooled connections to check token validity before reusing pooled connections.
-        let slots = self.fetch_slot_map(token).await?;
+        let slots = if self.topology.synced && token.is_none() {
+            // The active instance only shares its slot map when authentication is not used, so there is no token to check.
+            // Any slots that have moved since the sync are corrected by MOVED redirections.
+            // Only used once, so that if connecting fails the slot map is fetched from the cluster on the next attempt.
+            self.topology.synced = false;
+            self.topology.slots.clone()
+        } else {
+            // NOTE: Fetch slot map uses unpooled connections to check token validity before reusing pooled connections.
+            self.fetch_slot_map(token).await?
+        };
 
         let mut channels = ChannelMap::new();
         let mut errors = Vec::new();
@@ -425,7 +724,11 @@ impl RedisSinkCluster {
     }
 
     #[inline]
-    async fn choose_and_send(&mut self, host: &str, message: Message) -> Result<ResponseFuture> {
+    async fn choose_and_send(
+        &mut self,
+        host: &str,
+        mut message: Message,
+    ) -> Result<ResponseFuture> {
         let channel = match self.topology.channels.get_mut(host) {
             Some(channels) if channels.len() == 1 => channels.get_mut(0),
             Some(channels) if channels.len() > 1 => {
@@ -491,11 +794,15 @@ impl RedisSinkCluster {
             }
         };
 
+        let channel = channel.clone();
+        self.track_write(&channel, &mut message);
+
         let (one_tx, one_rx) = oneshot::channel::<Response>();
         if channel
             .send(Request {
                 message,
                 return_chan: Some(one_tx),
+                client_id: Some(self.client_id),
             })
             .is_err()
         {
@@ -507,13 +814,68 @@ impl RedisSinkCluster {
         Ok(Box::pin(one_rx.map_err(|e| anyhow!(e))))
     }
 
+    /// Sends a blocking command over this client's own connection to the host, opening it if needed.
+    /// At most [`MAX_BLOCKING_CONNECTIONS`] are opened per client, since each is a connection to redis that is not pooled.
+    async fn send_blocking(&mut self, host: &str, message: Message) -> Result<ResponseFuture> {
+        self.blocking_connections
+            .retain(|_, connection| !connection.is_closed());
+        if !self.blocking_connections.contains_key(host) {
+            if self.blocking_connections.len() >= MAX_BLOCKING_CONNECTIONS {
+                return self.send_error_response(
+                    "ERR Shotover RedisSinkCluster has too many blocking connections open for this client",
+                );
+            }
+            match self
+                .connection_pool
+                .new_unpooled_connection(host, &self.token)
+                .await
+            {
+                Ok(connection) => {
+                    self.blocking_connections
+                        .insert(host.to_owned(), connection);
+                }
+                Err(e) => {
+                    debug!(
+                        "failed to connect to {} for a blocking command: {}",
+                        host, e
+                    );
+                    self.rebuild_connections = true;
+                    return self.short_circuit_with_error();
+                }
+            }
+        }
+        let connection = &self.blocking_connections[host];
+
+        let (one_tx, one_rx) = oneshot::channel::<Response>();
+        if connection
+            .send(Request {
+                message,
+                return_chan: Some(one_tx),
+                client_id: Some(self.client_id),
+            })
+            .is_err()
+        {
+            self.blocking_connections.remove(host);
+            return self.short_circuit_with_error();
+        }
+
+        Ok(Box::pin(one_rx.map_err(|e| anyhow!(e))))
+    }
+
     async fn dispatch_message_hiding(
         &mut self,
         routing_info: RoutingInfo,
-        message: Message,
+        mut message: Message,
     ) -> Result<ResponseFuture> {
         match routing_info {
             RoutingInfo::Slot(slot) => self.send_message_to_slot(slot, message).await,
+            RoutingInfo::SplitBySlot(_) => match message.frame() {
+                Some(Frame::Redis(RedisFrame::Array(args))) => {
+                    let args = std::mem::take(args);
+                    self.send_mget_by_slot(args).await
+                }
+                frame => bail!("syntax error: bad command: {frame:?}"),
+            },
             RoutingInfo::AllNodes(_) => {
                 self.send_message_to_channels(
                     &self.topology.slots.nodes.iter().cloned().collect_vec(),
@@ -530,6 +892,10 @@ impl RedisSinkCluster {
                 )
                 .await
             }
+            RoutingInfo::AllMasterConnections(response_join) => {
+                self.send_message_to_all_master_connections(message, response_join)
+                    .await
+            }
             RoutingInfo::Random => {
                 let lookup = self
                     .topology
@@ -557,19 +923,31 @@ impl RedisSinkCluster {
     async fn dispatch_message_handling(
         &mut self,
         routing_info: RoutingInfo,
-        message: Message,
+        mut message: Message,
     ) -> Result<ResponseFuture> {
         match routing_info {
-            RoutingInfo::Slot(slot) => self.send_message_to_slot(slot, message).await,
+            RoutingInfo::Slot(slot) | RoutingInfo::SplitBySlot(slot) => {
+                self.send_message_to_slot(slot, message).await
+            }
+            // The client's writes are routed by slot to every master, so WAIT needs to cover every master too
+            RoutingInfo::AllMasterConnections(response_join) => {
+                self.send_message_to_all_master_connections(message, response_join)
+                    .await
+            }
             RoutingInfo::AllNodes(_)
             | RoutingInfo::AllMasters(_)
             | RoutingInfo::Random
             | RoutingInfo::Unsupported
             | RoutingInfo::ShortCircuitNil
             | RoutingInfo::ShortCircuitOk => {
-                let connection = self.direct_connection().await?;
+                let destination = self.direct_destination.clone().unwrap();
+                if is_blocking(&mut message) {
+                    return self.send_blocking(&destination, message).await;
+                }
+                let connection = self.direct_connection().await?.clone();
+                self.track_write(&connection, &mut message);
                 Ok(Box::pin(
-                    send_message_request(connection, message)?
+                    send_message_request(&connection, message)?
                         .map_err(|_| anyhow!("no response from direct connection")),
                 ))
             }
@@ -651,6 +1029,15 @@ impl SlotMap {
         }
     }
 
+    fn from_maps(masters: BTreeMap<u16, String>, replicas: BTreeMap<u16, String>) -> Self {
+        let nodes = masters.values().chain(replicas.values()).cloned().collect();
+        Self {
+            masters,
+            replicas,
+            nodes,
+        }
+    }
+
     fn from_entries(
         master_entries: Vec<(String, u16, u16)>,
         replica_entries: Vec<(String, u16, u16)>,
@@ -680,11 +1067,17 @@ impl SlotMap {
 #[derive(Debug, Clone, Copy)]
 pub enum RoutingInfo {
     Slot(u16),
+    /// The keys span multiple slots, so the request is split into one request per slot.
+    /// In handling mode falls back to sending to the slot of the first key
+    SplitBySlot(u16),
     Auth,
     /// In handling mode falls back to sending to the destination address
     AllNodes(ResponseJoin),
     /// In handling mode falls back to sending to the destination address
     AllMasters(ResponseJoin),
+    /// Sent on every connection to every master rather than on a single connection to each master.
+    /// In handling mode falls back to sending to the destination address
+    AllMasterConnections(ResponseJoin),
     /// In handling mode falls back to sending to the destination address
     Random,
     /// In handling mode falls back to sending to the destination address
@@ -701,6 +1094,8 @@ pub enum ResponseJoin {
     ArrayJoin,
     IntegerSum,
     IntegerMin,
+    /// Takes the minimum of each element of arrays of integers
+    IntegerArrayMin,
 }
 
 impl RoutingInfo {
@@ -726,6 +1121,12 @@ impl RoutingInfo {
             // In order to maintain this use case we query every node and return the oldest save time.
             // This way the return value wont change until every node has completed their BGSAVE.
             b"LASTSAVE" => RoutingInfo::AllNodes(ResponseJoin::IntegerMin),
+            // WAIT returns the number of replicas that acknowledged the writes the client sent before it.
+            // Those writes may have gone to any master, so every master must be waited on and the lowest count returned.
+            // Forwarding it to a single master would only wait for the writes that happened to go to that master.
+            b"WAIT" => RoutingInfo::AllMasterConnections(ResponseJoin::IntegerMin),
+            // WAITAOF is the same as WAIT but returns an array of the local and replica fsync counts.
+            b"WAITAOF" => RoutingInfo::AllMasterConnections(ResponseJoin::IntegerArrayMin),
             // When a command that forces writing to disk occurs we want it to occur on every node.
             // Replica nodes receive updates from their master nodes and we want those to be written to disk too.
             b"BGSAVE" | b"SAVE" | b"BGREWRITEAOF" | b"ACL" => {
@@ -756,6 +1157,9 @@ impl RoutingInfo {
             // These commands can not reasonably be supported by shotover, so we just return an error to the client when they are used
             b"SCAN" | b"SHUTDOWN" | b"SLAVEOF" | b"REPLICAOF" | b"MOVE" | b"BITOP" | b"CONFIG"
             | b"SLOWLOG" | b"INFO" | b"TIME" => RoutingInfo::Unsupported,
+            // FAILOVER promotes a replica of whichever node receives it, there is no single node shotover could send it to
+            // that would match what the client expects from a non-clustered redis.
+            b"FAILOVER" => RoutingInfo::Unsupported,
             b"EVALSHA" | b"EVAL" => match args.get(2) {
                 Some(RedisFrame::BulkString(key_count)) => {
                     if key_count.as_ref() == b"0" {
@@ -772,6 +1176,25 @@ impl RoutingInfo {
                 }
                 _ => RoutingInfo::Unsupported,
             },
+            // Introspection commands describe a key as stored on the node holding it, so route them by the key following the sub command.
+            // Sub commands that describe a node rather than a key can not be answered for the cluster as a whole.
+            b"OBJECT" | b"MEMORY" | b"DEBUG" => {
+                let sub_command = match args.get(1) {
+                    Some(RedisFrame::BulkString(sub_command)) => sub_command.to_ascii_uppercase(),
+                    _ => return Ok(RoutingInfo::Unsupported),
+                };
+                match (command_name.as_slice(), sub_command.as_slice()) {
+                    (b"OBJECT", b"ENCODING" | b"FREQ" | b"IDLETIME" | b"REFCOUNT")
+                    | (b"MEMORY", b"USAGE")
+                    | (b"DEBUG", b"OBJECT") => args
+                        .get(2)
+                        .and_then(RoutingInfo::for_key)
+                        .unwrap_or(RoutingInfo::Unsupported),
+                    (b"OBJECT" | b"MEMORY", b"HELP") => RoutingInfo::Random,
+                    // The remaining DEBUG sub commands can crash or block the node, so are never forwarded
+                    _ => RoutingInfo::Unsupported,
+                }
+            }
             b"XGROUP" | b"XINFO" => args
                 .get(2)
                 .and_then(RoutingInfo::for_key)
@@ -787,6 +1210,28 @@ impl RoutingInfo {
                         .and_then(RoutingInfo::for_key)
                 })
                 .unwrap_or(RoutingInfo::Unsupported),
+            // MGET is the only multi key command that can be split across nodes without affecting its semantics.
+            b"MGET" => {
+                let mut slots = args.iter().skip(1).map(RoutingInfo::for_key);
+                match slots.next() {
+                    Some(Some(first @ RoutingInfo::Slot(first_slot))) => {
+                        let mut split = false;
+                        for slot in slots {
+                            match slot {
+                                Some(RoutingInfo::Slot(slot)) => split |= slot != first_slot,
+                                _ => return Ok(RoutingInfo::Unsupported),
+                            }
+                        }
+                        if split {
+                            RoutingInfo::SplitBySlot(first_slot)
+                        } else {
+                            first
+                        }
+                    }
+                    Some(_) => RoutingInfo::Unsupported,
+                    None => RoutingInfo::Random,
+                }
+            }
             b"AUTH" => RoutingInfo::Auth,
             // These are stateless commands that return a response.
             // We just need a single redis node to handle this for us so shotover can pretend to be a single node.
@@ -816,6 +1261,7 @@ impl RoutingInfo {
         match self {
             RoutingInfo::AllMasters(join) => *join,
             RoutingInfo::AllNodes(join) => *join,
+            RoutingInfo::AllMasterConnections(join) => *join,
             _ => ResponseJoin::First,
         }
     }
@@ -830,6 +1276,19 @@ impl ResponseJoin {
                 }
                 _ => RedisFrame::Error("One of the redis frames was not an integer".into()),
             },
+            ResponseJoin::IntegerArrayMin => match (prev_frame, next_frame) {
+                (RedisFrame::Array(prev), RedisFrame::Array(next)) if prev.len() == next.len() => {
+                    RedisFrame::Array(
+                        prev.into_iter()
+                            .zip(next)
+                            .map(|(prev, next)| ResponseJoin::IntegerMin.join(prev, next))
+                            .collect(),
+                    )
+                }
+                _ => RedisFrame::Error(
+                    "One of the redis frames was not an array of the same length".into(),
+                ),
+            },
             ResponseJoin::IntegerSum => match (prev_frame, next_frame) {
                 (RedisFrame::Integer(prev), RedisFrame::Integer(next)) => {
                     RedisFrame::Integer(prev + next)
@@ -963,6 +1422,7 @@ fn send_message_request(
     sender.send(Request {
         message,
         return_chan: Some(return_chan_tx),
+        client_id: None,
     })?;
 
     Ok(return_chan_rx)
@@ -979,6 +1439,80 @@ async fn receive_frame_response(receiver: oneshot::Receiver<Response>) -> Result
     }
 }
 
+/// Waits for every response and combines them into a single response with `response_join`.
+/// If any of the responses are an error then that error is returned instead.
+fn join_responses(responses: Vec<ResponseFuture>, response_join: ResponseJoin) -> ResponseFuture {
+    Box::pin(async move {
+        let mut acc = None;
+        for response in gather_parts(responses, None).await {
+            if let Some((_, RedisFrame::Error(_))) = acc {
+                break;
+            }
+            acc = match response {
+                Ok(Response {
+                    response: Ok(mut message),
+                    ..
+                }) => Some((
+                    message.received_from_source_or_sink_at,
+                    match message.frame().unwrap() {
+                        Frame::Redis(frame) => {
+                            let new_frame = frame.take();
+                            match acc {
+                                Some((_, prev_frame)) => response_join.join(prev_frame, new_frame),
+                                None => new_frame,
+                            }
+                        }
+                        _ => unreachable!("direct response from a redis sink"),
+                    },
+                )),
+                Ok(Response {
+                    response: Err(e), ..
+                }) => Some((None, RedisFrame::Error(e.to_string().into()))),
+                Err(e) => Some((None, RedisFrame::Error(e.to_string().into()))),
+            };
+        }
+
+        let (received_at, response) = acc.unwrap();
+        Ok(Response {
+            response: Ok(Message::from_frame_at_instant(
+                Frame::Redis(response),
+                received_at,
+            )),
+        })
+    })
+}
+
+/// Caps the timeout of a WAIT or WAITAOF at `cap`, including a timeout of 0 which would otherwise wait forever.
+/// A timeout that is not a valid integer is left for redis to reject.
+fn cap_wait_timeout(mut message: Message, cap: Duration) -> Message {
+    if let Some(Frame::Redis(RedisFrame::Array(args))) = message.frame() {
+        let index = match args.first() {
+            Some(RedisFrame::BulkString(command)) if command.eq_ignore_ascii_case(b"WAITAOF") => 3,
+            _ => 2,
+        };
+        let cap = cap.as_millis() as u64;
+        let capped = match args.get(index) {
+            Some(RedisFrame::BulkString(timeout)) => std::str::from_utf8(timeout)
+                .ok()
+                .and_then(|timeout| timeout.parse::<u64>().ok())
+                .map(|timeout| if timeout == 0 { cap } else { timeout.min(cap) }),
+            _ => None,
+        };
+        if let Some(capped) = capped {
+            args[index] = RedisFrame::BulkString(capped.to_string().into());
+            message.invalidate_cache();
+        }
+    }
+    message
+}
+
+fn is_blocking(message: &mut Message) -> bool {
+    match message.frame() {
+        Some(Frame::Redis(frame)) => redis_blocking(frame) != RedisBlocking::No,
+        _ => false,
+    }
+}
+
 fn short_circuit(frame: RedisFrame) -> Result<ResponseFuture> {
     let (one_tx, one_rx) = oneshot::channel::<Response>();
 
@@ -1026,6 +1560,11 @@ impl Transform for RedisSinkCluster {
             self.has_run_init = true;
         }
 
+        if let Some(prewarm) = &self.prewarm {
+            prewarm.record_requests(requests_wrapper.requests.len());
+        }
+        self.update_connection_count().await;
+
         if self.rebuild_connections {
             if let Err(err) = self.build_connections(self.token.clone()).await {
                 tracing::warn!("Error when rebuilding connections: {err:?}");
@@ -1070,8 +1609,9 @@ impl Transform for RedisSinkCluster {
 
                             self.rebuild_connections = true;
 
+                            // The updated slot map routes the retry to the new server
                             responses.push_front(Box::pin(
-                                self.choose_and_send(&server, original)
+                                self.dispatch_message(original)
                                     .await?
                                     .map_err(|e| e.context("Error while retrying MOVE")),
                             ));
@@ -1178,6 +1718,7 @@ mod test {
     use super::*;
     use crate::codec::redis::RedisDecoder;
     use crate::codec::Direction;
+    use crate::transforms::testing::{redis_args, redis_request};
     use pretty_assertions::assert_eq;
     use tokio_util::codec::Decoder;
 
@@ -1230,4 +1771,111 @@ mod test {
         assert_eq!(slots.masters.into_iter().collect::<Vec<_>>(), masters);
         assert_eq!(slots.replicas.into_iter().collect::<Vec<_>>(), replicas);
     }
+
+    #[test]
+    fn test_mget_routing() {
+        let mget = |keys: &[&'static str]| {
+            let mut args = redis_args(&["MGET"]);
+            args.extend(redis_args(keys));
+            RoutingInfo::for_command_frame(&args).unwrap()
+        };
+
+        assert!(matches!(
+            mget(&["{user}a", "{user}b"]),
+            RoutingInfo::Slot(_)
+        ));
+        assert!(matches!(
+            mget(&["a", "b", "c"]),
+            RoutingInfo::SplitBySlot(_)
+        ));
+    }
+
+    #[test]
+    fn test_wait_routing() {
+        let route = |command: &[&'static str]| {
+            RoutingInfo::for_command_frame(&redis_args(command)).unwrap()
+        };
+
+        assert!(matches!(
+            route(&["WAIT", "1", "0"]),
+            RoutingInfo::AllMasterConnections(ResponseJoin::IntegerMin)
+        ));
+        assert!(matches!(
+            route(&["waitaof", "1", "1", "0"]),
+            RoutingInfo::AllMasterConnections(ResponseJoin::IntegerArrayMin)
+        ));
+        assert!(matches!(route(&["FAILOVER"]), RoutingInfo::Unsupported));
+    }
+
+    #[test]
+    fn test_cap_wait_timeout() {
+        let capped = |command: &[&'static str]| {
+            let mut message = cap_wait_timeout(redis_request(command), Duration::from_millis(100));
+            match message.frame() {
+                Some(Frame::Redis(frame)) => frame.clone(),
+                frame => panic!("unexpected frame {frame:?}"),
+            }
+        };
+        let frame = |command: &[&'static str]| RedisFrame::Array(redis_args(command));
+
+        assert_eq!(capped(&["WAIT", "1", "0"]), frame(&["WAIT", "1", "100"]));
+        assert_eq!(capped(&["WAIT", "1", "5000"]), frame(&["WAIT", "1", "100"]));
+        assert_eq!(capped(&["WAIT", "1", "10"]), frame(&["WAIT", "1", "10"]));
+        assert_eq!(
+            capped(&["waitaof", "1", "1", "0"]),
+            frame(&["waitaof", "1", "1", "100"])
+        );
+        assert_eq!(capped(&["WAIT", "1", "x"]), frame(&["WAIT", "1", "x"]));
+    }
+
+    #[test]
+    fn test_introspection_routing() {
+        let route = |command: &[&'static str]| {
+            RoutingInfo::for_command_frame(&redis_args(command)).unwrap()
+        };
+        let key_slot = match RoutingInfo::for_key(&RedisFrame::BulkString("user:1".into())) {
+            Some(RoutingInfo::Slot(slot)) => slot,
+            _ => unreachable!(),
+        };
+
+        for command in [
+            &["OBJECT", "ENCODING", "user:1"][..],
+            &["object", "freq", "user:1"],
+            &["MEMORY", "USAGE", "user:1", "SAMPLES", "0"],
+            &["DEBUG", "OBJECT", "user:1"],
+            &["TYPE", "user:1"],
+            &["PTTL", "user:1"],
+        ] {
+            assert!(
+                matches!(route(command), RoutingInfo::Slot(slot) if slot == key_slot),
+                "{command:?} was not routed to the slot of its key"
+            );
+        }
+        assert!(matches!(route(&["OBJECT", "HELP"]), RoutingInfo::Random));
+        assert!(matches!(
+            route(&["MEMORY", "STATS"]),
+            RoutingInfo::Unsupported
+        ));
+        assert!(matches!(
+            route(&["DEBUG", "SLEEP", "1"]),
+            RoutingInfo::Unsupported
+        ));
+        assert!(matches!(route(&["OBJECT"]), RoutingInfo::Unsupported));
+    }
+
+    #[test]
+    fn test_integer_array_min_join() {
+        let array = |values: &[i64]| {
+            RedisFrame::Array(values.iter().map(|x| RedisFrame::Integer(*x)).collect())
+        };
+
+        assert_eq!(
+            ResponseJoin::IntegerArrayMin.join(array(&[1, 2]), array(&[0, 3])),
+            array(&[0, 2])
+        );
+        assert!(matches!(
+            ResponseJoin::IntegerArrayMin.join(array(&[1, 2]), array(&[1])),
+            RedisFrame::Error(_)
+        ));
+    }
 }
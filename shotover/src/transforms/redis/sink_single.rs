@@ -24,6 +24,8 @@ pub struct RedisSinkSingleConfig {
     pub address: String,
     pub tls: Option<TlsConnectorConfig>,
     pub connect_timeout_ms: u64,
+    pub read_timeout: Option<u64>,
+    pub keepalive_interval: Option<u64>,
 }
 
 const NAME: &str = "RedisSinkSingle";
@@ -40,6 +42,8 @@ impl TransformConfig for RedisSinkSingleConfig {
             tls,
             transform_context.chain_name,
             self.connect_timeout_ms,
+            self.read_timeout,
+            self.keepalive_interval,
         )))
     }
 
@@ -57,6 +61,8 @@ pub struct RedisSinkSingleBuilder {
     tls: Option<TlsConnector>,
     failed_requests: Counter,
     connect_timeout: Duration,
+    read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
 }
 
 impl RedisSinkSingleBuilder {
@@ -65,15 +71,21 @@ impl RedisSinkSingleBuilder {
         tls: Option<TlsConnector>,
         chain_name: String,
         connect_timeout_ms: u64,
+        timeout: Option<u64>,
+        keepalive_interval: Option<u64>,
     ) -> Self {
         let failed_requests = counter!("shotover_failed_requests_count", "chain" => chain_name, "transform" => "RedisSinkSingle");
         let connect_timeout = Duration::from_millis(connect_timeout_ms);
+        let read_timeout = timeout.map(Duration::from_secs);
+        let keepalive_interval = keepalive_interval.map(Duration::from_secs);
 
         RedisSinkSingleBuilder {
             address,
             tls,
             failed_requests,
             connect_timeout,
+            read_timeout,
+            keepalive_interval,
         }
     }
 }
@@ -86,6 +98,8 @@ impl TransformBuilder for RedisSinkSingleBuilder {
             connection: None,
             failed_requests: self.failed_requests.clone(),
             connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            keepalive_interval: self.keepalive_interval,
             force_run_chain: transform_context.force_run_chain,
         })
     }
@@ -105,6 +119,8 @@ pub struct RedisSinkSingle {
     connection: Option<SinkConnection>,
     failed_requests: Counter,
     connect_timeout: Duration,
+    read_timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
     force_run_chain: Arc<Notify>,
 }
 
@@ -124,7 +140,8 @@ impl Transform for RedisSinkSingle {
                     &self.tls,
                     self.connect_timeout,
                     self.force_run_chain.clone(),
-                    None,
+                    self.read_timeout,
+                    self.keepalive_interval,
                 )
                 .await?,
             );
@@ -0,0 +1,475 @@
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisTtlPolicyConfig {
+    /// Each key is governed by the first rule whose pattern it matches, keys matching no rule are left alone
+    pub rules: Vec<TtlRuleConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TtlRuleConfig {
+    /// Redis style glob pattern, `*` matches any sequence of characters and `?` matches any single character
+    pub key_pattern: String,
+    /// Expiry added to a SET that does not specify one
+    pub default_ttl_seconds: Option<u32>,
+    /// Longer expiries are reduced to this value
+    pub max_ttl_seconds: Option<u32>,
+    /// Reject requests that would remove the expiry of a key
+    #[serde(default)]
+    pub deny_persist: bool,
+}
+
+const NAME: &str = "RedisTtlPolicy";
+#[typetag::serde(name = "RedisTtlPolicy")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisTtlPolicyConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain = transform_context.chain_name;
+        Ok(Box::new(RedisTtlPolicyBuilder {
+            rules: Arc::new(self.rules.clone()),
+            metrics: Metrics {
+                default_ttl_injected: counter!("shotover_ttl_policy_enforcements_count", "chain" => chain.clone(), "action" => "default_ttl_injected"),
+                ttl_capped: counter!("shotover_ttl_policy_enforcements_count", "chain" => chain.clone(), "action" => "ttl_capped"),
+                persist_rejected: counter!("shotover_ttl_policy_enforcements_count", "chain" => chain, "action" => "persist_rejected"),
+            },
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+
+    fn validate_config(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for rule in self.rules.iter() {
+            let pattern = &rule.key_pattern;
+            if rule.default_ttl_seconds.is_none()
+                && rule.max_ttl_seconds.is_none()
+                && !rule.deny_persist
+            {
+                errors.push(format!("rule for {pattern:?} does not enforce anything, set at least one of default_ttl_seconds, max_ttl_seconds or deny_persist"));
+            }
+            if rule.default_ttl_seconds == Some(0) {
+                errors.push(format!(
+                    "rule for {pattern:?} has a default_ttl_seconds of 0"
+                ));
+            }
+            if rule.max_ttl_seconds == Some(0) {
+                errors.push(format!("rule for {pattern:?} has a max_ttl_seconds of 0"));
+            }
+            if let (Some(default), Some(max)) = (rule.default_ttl_seconds, rule.max_ttl_seconds) {
+                if default > max {
+                    errors.push(format!("rule for {pattern:?} has a default_ttl_seconds of {default} which is greater than its max_ttl_seconds of {max}"));
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[derive(Clone)]
+struct Metrics {
+    default_ttl_injected: Counter,
+    ttl_capped: Counter,
+    persist_rejected: Counter,
+}
+
+pub struct RedisTtlPolicyBuilder {
+    rules: Arc<Vec<TtlRuleConfig>>,
+    metrics: Metrics,
+}
+
+impl TransformBuilder for RedisTtlPolicyBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisTtlPolicy {
+            rules: self.rules.clone(),
+            metrics: self.metrics.clone(),
+            rejected_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        if self.rules.iter().any(|rule| rule.deny_persist) {
+            // PERSIST is rejected instead of being delivered
+            DeliveryGuarantees::AT_MOST_ONCE
+        } else {
+            DeliveryGuarantees::PASSTHROUGH
+        }
+    }
+}
+
+/// Enforces expiry policies on keys matching configured patterns.
+///
+/// Only requests that set an expiry are inspected, so keys written by other commands such as MSET or HSET are not given a default expiry.
+pub struct RedisTtlPolicy {
+    rules: Arc<Vec<TtlRuleConfig>>,
+    metrics: Metrics,
+    rejected_requests: MessageIdMap<Message>,
+}
+
+#[async_trait]
+impl Transform for RedisTtlPolicy {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let now_millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as i64)
+            .unwrap_or(0);
+
+        for request in requests_wrapper.requests.iter_mut() {
+            if request.is_dummy() {
+                continue;
+            }
+            let enforcement = match request.frame() {
+                Some(Frame::Redis(RedisFrame::Array(args))) => {
+                    enforce(&self.rules, args, now_millis)
+                }
+                _ => Enforcement::None,
+            };
+            match enforcement {
+                Enforcement::None => {}
+                Enforcement::DefaultTtlInjected => {
+                    self.metrics.default_ttl_injected.increment(1);
+                    request.invalidate_cache();
+                }
+                Enforcement::TtlCapped => {
+                    self.metrics.ttl_capped.increment(1);
+                    request.invalidate_cache();
+                }
+                Enforcement::PersistRejected(error) => {
+                    self.metrics.persist_rejected.increment(1);
+                    self.rejected_requests
+                        .insert(request.id(), request.from_request_to_error_response(error)?);
+                    request.replace_with_dummy();
+                }
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if let Some(request_id) = response.request_id() {
+                if let Some(error_response) = self.rejected_requests.remove(&request_id) {
+                    *response = error_response;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Enforcement {
+    None,
+    DefaultTtlInjected,
+    TtlCapped,
+    PersistRejected(String),
+}
+
+/// The unit of an expiry argument
+#[derive(Clone, Copy)]
+enum Expiry {
+    Seconds,
+    Millis,
+    UnixSeconds,
+    UnixMillis,
+}
+
+impl Expiry {
+    /// The value of an argument in this unit that expires a key `ttl_seconds` from now
+    fn value_for_ttl(self, ttl_seconds: u32, now_millis: i64) -> i64 {
+        let ttl_seconds = i64::from(ttl_seconds);
+        match self {
+            Expiry::Seconds => ttl_seconds,
+            Expiry::Millis => ttl_seconds * 1000,
+            Expiry::UnixSeconds => now_millis / 1000 + ttl_seconds,
+            Expiry::UnixMillis => now_millis + ttl_seconds * 1000,
+        }
+    }
+}
+
+enum ExpiryOption {
+    None,
+    KeepTtl,
+    Persist,
+    /// The expiry value is at `index`
+    Expiry {
+        index: usize,
+        unit: Expiry,
+    },
+}
+
+/// Finds the expiry option among the options of SET or GETEX starting at `start`
+fn find_expiry_option(args: &[RedisFrame], start: usize) -> ExpiryOption {
+    for (i, arg) in args.iter().enumerate().skip(start) {
+        if let RedisFrame::BulkString(arg) = arg {
+            let unit = match arg.to_ascii_uppercase().as_slice() {
+                b"EX" => Expiry::Seconds,
+                b"PX" => Expiry::Millis,
+                b"EXAT" => Expiry::UnixSeconds,
+                b"PXAT" => Expiry::UnixMillis,
+                b"KEEPTTL" => return ExpiryOption::KeepTtl,
+                b"PERSIST" => return ExpiryOption::Persist,
+                _ => continue,
+            };
+            return ExpiryOption::Expiry { index: i + 1, unit };
+        }
+    }
+    ExpiryOption::None
+}
+
+fn enforce(rules: &[TtlRuleConfig], args: &mut Vec<RedisFrame>, now_millis: i64) -> Enforcement {
+    let (Some(RedisFrame::BulkString(command)), Some(RedisFrame::BulkString(key))) =
+        (args.first(), args.get(1))
+    else {
+        return Enforcement::None;
+    };
+    let Some(rule) = rules
+        .iter()
+        .find(|rule| glob_match(rule.key_pattern.as_bytes(), key))
+    else {
+        return Enforcement::None;
+    };
+
+    match command.to_ascii_uppercase().as_slice() {
+        b"SET" => match find_expiry_option(args, 3) {
+            ExpiryOption::None => match rule.default_ttl_seconds {
+                Some(ttl) => {
+                    args.push(RedisFrame::BulkString(Bytes::from_static(b"EX")));
+                    args.push(RedisFrame::BulkString(ttl.to_string().into()));
+                    Enforcement::DefaultTtlInjected
+                }
+                None => Enforcement::None,
+            },
+            ExpiryOption::Expiry { index, unit } => cap(rule, args, index, unit, now_millis),
+            ExpiryOption::KeepTtl | ExpiryOption::Persist => Enforcement::None,
+        },
+        b"GETEX" => match find_expiry_option(args, 2) {
+            ExpiryOption::Persist => reject_persist(rule),
+            ExpiryOption::Expiry { index, unit } => cap(rule, args, index, unit, now_millis),
+            ExpiryOption::None | ExpiryOption::KeepTtl => Enforcement::None,
+        },
+        b"SETEX" | b"EXPIRE" => cap(rule, args, 2, Expiry::Seconds, now_millis),
+        b"PSETEX" | b"PEXPIRE" => cap(rule, args, 2, Expiry::Millis, now_millis),
+        b"EXPIREAT" => cap(rule, args, 2, Expiry::UnixSeconds, now_millis),
+        b"PEXPIREAT" => cap(rule, args, 2, Expiry::UnixMillis, now_millis),
+        b"PERSIST" => reject_persist(rule),
+        _ => Enforcement::None,
+    }
+}
+
+fn cap(
+    rule: &TtlRuleConfig,
+    args: &mut [RedisFrame],
+    index: usize,
+    unit: Expiry,
+    now_millis: i64,
+) -> Enforcement {
+    let Some(max_ttl) = rule.max_ttl_seconds else {
+        return Enforcement::None;
+    };
+    let Some(RedisFrame::BulkString(value)) = args.get_mut(index) else {
+        return Enforcement::None;
+    };
+    // Leave invalid values alone so that redis reports the error to the client
+    let Some(requested) = std::str::from_utf8(value)
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok())
+    else {
+        return Enforcement::None;
+    };
+
+    let max = unit.value_for_ttl(max_ttl, now_millis);
+    if requested > max {
+        *value = max.to_string().into();
+        Enforcement::TtlCapped
+    } else {
+        Enforcement::None
+    }
+}
+
+fn reject_persist(rule: &TtlRuleConfig) -> Enforcement {
+    if rule.deny_persist {
+        Enforcement::PersistRejected(format!(
+            "removing the expiry of keys matching {} is not allowed",
+            rule.key_pattern
+        ))
+    } else {
+        Enforcement::None
+    }
+}
+
+/// Matches a key against a redis style glob pattern supporting `*` and `?`
+pub(crate) fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => key.is_empty(),
+        Some((b'*', rest)) => (0..=key.len()).any(|i| glob_match(rest, &key[i..])),
+        Some((b'?', rest)) => !key.is_empty() && glob_match(rest, &key[1..]),
+        Some((c, rest)) => key.first() == Some(c) && glob_match(rest, &key[1..]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transforms::testing::redis_args;
+    use pretty_assertions::assert_eq;
+
+    const NOW_MILLIS: i64 = 1_700_000_000_000;
+
+    fn rules() -> Vec<TtlRuleConfig> {
+        vec![TtlRuleConfig {
+            key_pattern: "session:*".to_owned(),
+            default_ttl_seconds: Some(60),
+            max_ttl_seconds: Some(3600),
+            deny_persist: true,
+        }]
+    }
+
+    fn enforce_command(args: &[&str]) -> (Enforcement, Vec<RedisFrame>) {
+        let mut args = redis_args(args);
+        let enforcement = enforce(&rules(), &mut args, NOW_MILLIS);
+        (enforcement, args)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"session:*", b"session:1"));
+        assert!(glob_match(b"session:*", b"session:"));
+        assert!(glob_match(b"*:cache", b"user:1:cache"));
+        assert!(glob_match(b"user:?", b"user:1"));
+        assert!(!glob_match(b"user:?", b"user:12"));
+        assert!(!glob_match(b"session:*", b"user:1"));
+    }
+
+    #[test]
+    fn test_set_default_ttl_injected() {
+        assert_eq!(
+            enforce_command(&["set", "session:1", "value", "NX"]),
+            (
+                Enforcement::DefaultTtlInjected,
+                redis_args(&["set", "session:1", "value", "NX", "EX", "60"])
+            )
+        );
+        assert_eq!(
+            enforce_command(&["SET", "session:1", "value", "KEEPTTL"]),
+            (
+                Enforcement::None,
+                redis_args(&["SET", "session:1", "value", "KEEPTTL"])
+            )
+        );
+        assert_eq!(
+            enforce_command(&["SET", "user:1", "value"]),
+            (Enforcement::None, redis_args(&["SET", "user:1", "value"]))
+        );
+    }
+
+    #[test]
+    fn test_ttl_capped() {
+        assert_eq!(
+            enforce_command(&["SET", "session:1", "value", "px", "7200000"]),
+            (
+                Enforcement::TtlCapped,
+                redis_args(&["SET", "session:1", "value", "px", "3600000"])
+            )
+        );
+        assert_eq!(
+            enforce_command(&["EXPIRE", "session:1", "10"]),
+            (
+                Enforcement::None,
+                redis_args(&["EXPIRE", "session:1", "10"])
+            )
+        );
+        assert_eq!(
+            enforce_command(&["SETEX", "session:1", "86400", "value"]),
+            (
+                Enforcement::TtlCapped,
+                redis_args(&["SETEX", "session:1", "3600", "value"])
+            )
+        );
+        assert_eq!(
+            enforce_command(&["PEXPIREAT", "session:1", "1800000000000"]),
+            (
+                Enforcement::TtlCapped,
+                redis_args(&["PEXPIREAT", "session:1", "1700003600000"])
+            )
+        );
+        assert_eq!(
+            enforce_command(&["EXPIRE", "session:1", "not_a_number"]),
+            (
+                Enforcement::None,
+                redis_args(&["EXPIRE", "session:1", "not_a_number"])
+            )
+        );
+    }
+
+    #[test]
+    fn test_persist_rejected() {
+        let expected = Enforcement::PersistRejected(
+            "removing the expiry of keys matching session:* is not allowed".to_owned(),
+        );
+        assert_eq!(enforce_command(&["PERSIST", "session:1"]).0, expected);
+        assert_eq!(
+            enforce_command(&["GETEX", "session:1", "PERSIST"]).0,
+            expected
+        );
+        assert_eq!(enforce_command(&["PERSIST", "user:1"]).0, Enforcement::None);
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let config = RedisTtlPolicyConfig {
+            rules: vec![
+                TtlRuleConfig {
+                    key_pattern: "session:*".to_owned(),
+                    default_ttl_seconds: Some(120),
+                    max_ttl_seconds: Some(60),
+                    deny_persist: false,
+                },
+                TtlRuleConfig {
+                    key_pattern: "user:*".to_owned(),
+                    default_ttl_seconds: None,
+                    max_ttl_seconds: None,
+                    deny_persist: false,
+                },
+            ],
+        };
+        assert_eq!(
+            config.validate_config(),
+            vec![
+                "rule for \"session:*\" has a default_ttl_seconds of 120 which is greater than its max_ttl_seconds of 60",
+                "rule for \"user:*\" does not enforce anything, set at least one of default_ttl_seconds, max_ttl_seconds or deny_persist",
+            ]
+        );
+    }
+}
@@ -0,0 +1,517 @@
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, MessageId, MessageIdMap, Messages};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RequestDeduplicationConfig {
+    /// How long the response to a write is remembered for
+    pub window_ms: u64,
+    /// The maximum number of responses remembered per connection, defaults to 1000
+    pub max_entries: Option<usize>,
+}
+
+const NAME: &str = "RequestDeduplication";
+#[typetag::serde(name = "RequestDeduplication")]
+#[async_trait(?Send)]
+impl TransformConfig for RequestDeduplicationConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RequestDeduplicationBuilder {
+            window: Duration::from_millis(self.window_ms),
+            max_entries: self.max_entries.unwrap_or(1000),
+            duplicates: counter!("shotover_deduplicated_requests_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+pub struct RequestDeduplicationBuilder {
+    window: Duration,
+    max_entries: usize,
+    duplicates: Counter,
+}
+
+impl TransformBuilder for RequestDeduplicationBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RequestDeduplication {
+            window: self.window,
+            max_entries: self.max_entries,
+            duplicates: self.duplicates.clone(),
+            responses: HashMap::new(),
+            expiry_order: VecDeque::new(),
+            pending: MessageIdMap::default(),
+            in_redis_transaction: false,
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // A duplicate request is answered with the response to the request it duplicates
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.window.is_zero() {
+            errors.push("  window_ms must be greater than 0".to_owned());
+        }
+        if self.max_entries == 0 {
+            errors.push("  max_entries must be greater than 0".to_owned());
+        }
+
+        if errors.is_empty() {
+            errors
+        } else {
+            let mut output = vec![format!("{NAME}:")];
+            output.extend(errors);
+            output
+        }
+    }
+}
+
+/// Suppresses writes that exactly match a write recently sent on the same connection, responding with the response to the original write instead.
+///
+/// Only successful responses are remembered so a retry of a failed write is always sent down the chain.
+/// Only non-idempotent writes are deduplicated, repeating an idempotent write is harmless
+/// while suppressing it would lose an update made by another client in the meantime.
+pub struct RequestDeduplication {
+    window: Duration,
+    max_entries: usize,
+    duplicates: Counter,
+    /// Responses to recent writes, keyed by the fingerprint of the write
+    responses: HashMap<Bytes, CachedResponse>,
+    /// Fingerprints in the order they were inserted into `responses`, used to expire them
+    expiry_order: VecDeque<(Instant, Bytes)>,
+    /// What to do with the response to each request sent down the chain or suppressed in this batch, keyed by the id of the request
+    pending: MessageIdMap<Pending>,
+    /// Commands sent between MULTI and EXEC are only queued, so they must not be deduplicated
+    in_redis_transaction: bool,
+}
+
+/// Redis commands that modify data differently each time they are repeated, such as `INCR` or `LPOP`, only these are deduplicated.
+/// Commands that modify the state of the connection or that block are never deduplicated.
+#[cfg(feature = "redis")]
+const REDIS_NON_IDEMPOTENT_COMMANDS: &[&[u8]] = &[
+    b"APPEND",
+    b"DECR",
+    b"DECRBY",
+    b"EXPIRE",
+    b"GETDEL",
+    b"HINCRBY",
+    b"HINCRBYFLOAT",
+    b"INCR",
+    b"INCRBY",
+    b"INCRBYFLOAT",
+    b"LINSERT",
+    b"LMOVE",
+    b"LPOP",
+    b"LPUSH",
+    b"LPUSHX",
+    b"LREM",
+    b"LTRIM",
+    b"PEXPIRE",
+    b"PSETEX",
+    b"PUBLISH",
+    b"RENAME",
+    b"RENAMENX",
+    b"RPOP",
+    b"RPOPLPUSH",
+    b"RPUSH",
+    b"RPUSHX",
+    b"SETEX",
+    b"SPOP",
+    b"XADD",
+    b"ZADD",
+    b"ZINCRBY",
+    b"ZPOPMAX",
+    b"ZPOPMIN",
+];
+
+struct CachedResponse {
+    response: Message,
+    inserted_at: Instant,
+}
+
+enum Pending {
+    /// The write was sent down the chain, remember its response under this fingerprint
+    Remember(Bytes),
+    /// The write was a duplicate of an earlier write, respond with this message
+    Respond(Message),
+    /// The write was a duplicate of an earlier write in the same batch, respond with the response to that write
+    RespondLike(MessageId),
+}
+
+impl RequestDeduplication {
+    /// Returns true if the request is a write that should be deduplicated
+    fn is_deduplicated(&mut self, request: &mut Message) -> bool {
+        match request.frame() {
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(frame)) => {
+                matches!(
+                    frame.get_query_type(),
+                    crate::message::QueryType::Write | crate::message::QueryType::ReadWrite
+                ) && !is_idempotent_cassandra(&frame.operation)
+            }
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(RedisFrame::Array(args))) => {
+                let Some(RedisFrame::BulkString(command)) = args.first() else {
+                    return false;
+                };
+                match command.to_ascii_uppercase().as_slice() {
+                    b"MULTI" => {
+                        self.in_redis_transaction = true;
+                        false
+                    }
+                    b"EXEC" | b"DISCARD" => {
+                        self.in_redis_transaction = false;
+                        false
+                    }
+                    command => {
+                        !self.in_redis_transaction
+                            && REDIS_NON_IDEMPOTENT_COMMANDS.contains(&command)
+                    }
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((inserted_at, _)) = self.expiry_order.front() {
+            if now.duration_since(*inserted_at) < self.window
+                && self.expiry_order.len() <= self.max_entries
+            {
+                break;
+            }
+            let (inserted_at, fingerprint) = self.expiry_order.pop_front().unwrap();
+            // The fingerprint may have been expired and remembered again since this entry was pushed
+            if self
+                .responses
+                .get(&fingerprint)
+                .map(|x| x.inserted_at == inserted_at)
+                .unwrap_or(false)
+            {
+                self.responses.remove(&fingerprint);
+            }
+        }
+    }
+
+    fn remember(&mut self, fingerprint: Bytes, response: Message, now: Instant) {
+        self.responses.insert(
+            fingerprint.clone(),
+            CachedResponse {
+                response,
+                inserted_at: now,
+            },
+        );
+        self.expiry_order.push_back((now, fingerprint));
+    }
+}
+
+/// Returns true if repeating the cassandra request leaves the data unchanged.
+/// Only an INSERT without IF NOT EXISTS is known to be idempotent:
+/// an UPDATE may increment a counter or append to a list, and the statement behind a prepared id is unknown.
+#[cfg(feature = "cassandra")]
+fn is_idempotent_cassandra(operation: &crate::frame::CassandraOperation) -> bool {
+    use crate::frame::cassandra::BatchStatementType;
+    use crate::frame::CassandraOperation;
+    use cassandra_protocol::frame::message_batch::BatchType;
+    use cql3_parser::cassandra_statement::CassandraStatement;
+
+    let is_idempotent_statement = |statement: &CassandraStatement| matches!(statement, CassandraStatement::Insert(insert) if !insert.if_not_exists);
+    match operation {
+        CassandraOperation::Query { query, .. } => is_idempotent_statement(query),
+        CassandraOperation::Batch(batch) => {
+            !matches!(batch.ty, BatchType::Counter)
+                && batch.queries.iter().all(|query| match &query.ty {
+                    BatchStatementType::Statement(statement) => is_idempotent_statement(statement),
+                    BatchStatementType::PreparedId(_) => false,
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Identifies a request by its bytes, excluding anything that a client changes when retrying the same request
+fn fingerprint(request: &Message) -> Option<Bytes> {
+    let bytes = request.raw_bytes()?;
+    match request.message_type() {
+        // Skip the version, flags and stream id since the driver assigns a new stream id to a retry
+        #[cfg(feature = "cassandra")]
+        MessageType::Cassandra if bytes.len() > 4 => Some(bytes.slice(4..)),
+        #[cfg(feature = "redis")]
+        MessageType::Redis => Some(bytes.clone()),
+        _ => None,
+    }
+}
+
+/// Turns a response to one request into a response to `request`
+fn respond_to(mut response: Message, request: &Message) -> Message {
+    response.set_request_id(request.id());
+    #[cfg(feature = "cassandra")]
+    if let Ok(crate::message::Metadata::Cassandra(meta)) = request.metadata() {
+        if let Some(Frame::Cassandra(frame)) = response.frame() {
+            frame.stream_id = meta.stream_id;
+            response.invalidate_cache();
+        }
+    }
+    response
+}
+
+#[async_trait]
+impl Transform for RequestDeduplication {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let now = Instant::now();
+        self.expire(now);
+
+        // Writes sent down the chain in this batch, used to detect duplicates within the batch
+        let mut batch: HashMap<Bytes, MessageId> = HashMap::new();
+        // Requests that were replaced with a dummy, kept so that the response can be given their stream id
+        let mut suppressed: MessageIdMap<Message> = MessageIdMap::default();
+
+        for request in requests_wrapper.requests.iter_mut() {
+            if request.is_dummy() || !self.is_deduplicated(request) {
+                continue;
+            }
+            let Some(fingerprint) = fingerprint(request) else {
+                continue;
+            };
+
+            let pending = if let Some(cached) = self.responses.get(&fingerprint) {
+                Pending::Respond(cached.response.clone_with_new_id())
+            } else if let Some(original) = batch.get(&fingerprint) {
+                Pending::RespondLike(*original)
+            } else {
+                batch.insert(fingerprint.clone(), request.id());
+                self.pending
+                    .insert(request.id(), Pending::Remember(fingerprint));
+                continue;
+            };
+
+            self.duplicates.increment(1);
+            self.pending.insert(request.id(), pending);
+            suppressed.insert(request.id(), request.clone());
+            request.replace_with_dummy();
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+
+        // Responses to writes sent down the chain in this batch, keyed by the id of the write
+        let mut batch_responses: MessageIdMap<Message> = MessageIdMap::default();
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            if let Some(Pending::Remember(fingerprint)) = self.pending.remove(&request_id) {
+                if !response.is_error() {
+                    self.remember(fingerprint, response.clone_with_new_id(), now);
+                }
+                batch_responses.insert(request_id, response.clone_with_new_id());
+            }
+        }
+
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            let Some(request) = suppressed.remove(&request_id) else {
+                continue;
+            };
+            *response = match self.pending.remove(&request_id) {
+                Some(Pending::Respond(cached)) => respond_to(cached, &request),
+                Some(Pending::RespondLike(original)) => match batch_responses.get(&original) {
+                    Some(original) => respond_to(original.clone_with_new_id(), &request),
+                    None => request.from_request_to_error_response(
+                        "No response was received for the original of this duplicate request"
+                            .to_owned(),
+                    )?,
+                },
+                _ => continue,
+            };
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::codec::CodecState;
+    use crate::transforms::loopback::Loopback;
+    use crate::transforms::testing::ChainTester;
+    use crate::transforms::TransformAndMetrics;
+    use pretty_assertions::assert_eq;
+
+    fn command(command: &'static [u8], value: &'static [u8]) -> Message {
+        let mut bytes = format!("*3\r\n${}\r\n", command.len()).into_bytes();
+        bytes.extend_from_slice(command);
+        bytes.extend_from_slice(b"\r\n$3\r\nkey\r\n$1\r\n");
+        bytes.extend_from_slice(value);
+        bytes.extend_from_slice(b"\r\n");
+        Message::from_bytes(bytes.into(), CodecState::Redis)
+    }
+
+    fn set(value: &'static [u8]) -> Message {
+        command(b"SET", value)
+    }
+
+    fn incrby(value: &'static [u8]) -> Message {
+        command(b"INCRBY", value)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_duplicate_writes_suppressed() {
+        let mut transform = RequestDeduplication {
+            window: Duration::from_secs(60),
+            max_entries: 1000,
+            duplicates: Counter::noop(),
+            responses: HashMap::new(),
+            expiry_order: VecDeque::new(),
+            pending: MessageIdMap::default(),
+            in_redis_transaction: false,
+        };
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+
+        let requests = vec![incrby(b"1"), incrby(b"1"), incrby(b"2")];
+        let ids: Vec<MessageId> = requests.iter().map(|x| x.id()).collect();
+        let mut wrapper = Wrapper::new_test(requests);
+        wrapper.reset(&mut chain);
+        let mut responses = transform.transform(wrapper).await.unwrap();
+
+        assert_eq!(responses.len(), 3);
+        for (response, id) in responses.iter_mut().zip(&ids) {
+            assert_eq!(response.request_id(), Some(*id));
+            assert!(!response.is_dummy());
+        }
+        // The second write was answered with the response to the first
+        let mut first = responses[0].clone();
+        assert_eq!(responses[1].frame(), first.frame());
+        assert_eq!(transform.responses.len(), 2);
+
+        // A retry in a later batch is answered from the remembered responses
+        let retry = incrby(b"2");
+        let retry_id = retry.id();
+        let mut wrapper = Wrapper::new_test(vec![retry]);
+        wrapper.reset(&mut chain);
+        let mut responses = transform.transform(wrapper).await.unwrap();
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].request_id(), Some(retry_id));
+        assert_eq!(
+            responses[0].frame(),
+            Some(&mut Frame::Redis(RedisFrame::Array(vec![
+                RedisFrame::BulkString("INCRBY".into()),
+                RedisFrame::BulkString("key".into()),
+                RedisFrame::BulkString("2".into()),
+            ])))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_writes_not_suppressed() {
+        let mut tester = ChainTester::from_builders(vec![Box::new(RequestDeduplicationBuilder {
+            window: Duration::from_secs(60),
+            max_entries: 1000,
+            duplicates: Counter::noop(),
+        })])
+        .unwrap();
+
+        // Another client may have written to the key in between, so the repeated SET must reach the backend
+        tester.send(vec![set(b"1")]).await.unwrap();
+        tester.send(vec![set(b"2")]).await.unwrap();
+        tester.send(vec![set(b"1")]).await.unwrap();
+        let received: Vec<_> = tester
+            .sink()
+            .received()
+            .iter_mut()
+            .map(|x| x.frame().cloned())
+            .collect();
+        assert_eq!(
+            received,
+            vec![
+                set(b"1").frame().cloned(),
+                set(b"2").frame().cloned(),
+                set(b"1").frame().cloned()
+            ]
+        );
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_is_idempotent_cassandra() {
+        use crate::frame::cassandra::parse_statement_single;
+        use crate::frame::CassandraOperation;
+
+        let query = |cql: &str| CassandraOperation::Query {
+            query: Box::new(parse_statement_single(cql)),
+            params: Box::default(),
+        };
+        assert!(is_idempotent_cassandra(&query(
+            "INSERT INTO ks.tb (id, x) VALUES (1, 2)"
+        )));
+        assert!(!is_idempotent_cassandra(&query(
+            "INSERT INTO ks.tb (id, x) VALUES (1, 2) IF NOT EXISTS"
+        )));
+        assert!(!is_idempotent_cassandra(&query(
+            "UPDATE ks.tb SET count = count + 1 WHERE id = 1"
+        )));
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut transform = RequestDeduplication {
+            window: Duration::from_secs(60),
+            max_entries: 1,
+            duplicates: Counter::noop(),
+            responses: HashMap::new(),
+            expiry_order: VecDeque::new(),
+            pending: MessageIdMap::default(),
+            in_redis_transaction: false,
+        };
+        let now = Instant::now();
+        transform.remember(Bytes::from_static(b"a"), set(b"1"), now);
+        transform.remember(Bytes::from_static(b"b"), set(b"2"), now);
+
+        transform.expire(now);
+        assert!(!transform.responses.contains_key(&b"a"[..]));
+        assert!(transform.responses.contains_key(&b"b"[..]));
+
+        transform.expire(now + Duration::from_secs(60));
+        assert!(transform.responses.is_empty());
+    }
+}
@@ -0,0 +1,316 @@
+use crate::message::Messages;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RetryBudgetConfig {
+    /// The budget shared by every connection to the chain
+    pub chain: BudgetConfig,
+    /// The budget of each individual connection, when not set connections are only limited by the chain budget
+    pub connection: Option<BudgetConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetConfig {
+    /// The number of retries earned by each request, e.g. 0.1 allows retries to add 10% to the load on the destination
+    pub retry_ratio: f64,
+    /// The number of retries allowed each second regardless of how many requests were sent,
+    /// so that retries are still possible when there is little traffic
+    pub min_retries_per_second: u32,
+}
+
+const NAME: &str = "RetryBudget";
+#[typetag::serde(name = "RetryBudget")]
+#[async_trait(?Send)]
+impl TransformConfig for RetryBudgetConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RetryBudgetBuilder {
+            chain: Arc::new(Budget::new(self.chain)),
+            connection: self.connection,
+            rejected: counter!("shotover_retries_rejected_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+pub struct RetryBudgetBuilder {
+    chain: Arc<Budget>,
+    connection: Option<BudgetConfig>,
+    rejected: Counter,
+}
+
+impl TransformBuilder for RetryBudgetBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RetryBudgetTransform {
+            budget: RetryBudget {
+                chain: self.chain.clone(),
+                connection: self.connection.map(|config| Arc::new(Budget::new(config))),
+                rejected: self.rejected.clone(),
+            },
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        let budgets = std::iter::once(("chain", &self.chain.config)).chain(
+            self.connection
+                .as_ref()
+                .map(|config| ("connection", config)),
+        );
+        for (name, config) in budgets {
+            if config.retry_ratio < 0.0 || !config.retry_ratio.is_finite() {
+                errors.push(format!(
+                    "  {name}.retry_ratio must be a finite number of at least 0 but was {}",
+                    config.retry_ratio
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            errors
+        } else {
+            let mut output = vec![format!("{NAME}:")];
+            output.extend(errors);
+            output
+        }
+    }
+}
+
+/// Makes a [`RetryBudget`] available to every transform down chain of it through [`Wrapper::retry_budget`].
+/// Every request that passes through deposits into the budget.
+pub struct RetryBudgetTransform {
+    budget: RetryBudget,
+}
+
+#[async_trait]
+impl Transform for RetryBudgetTransform {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        self.budget.deposit(requests_wrapper.requests.len());
+        requests_wrapper.retry_budget = Some(self.budget.clone());
+        requests_wrapper.call_next_transform().await
+    }
+}
+
+/// Limits the number of retries a transform may send, relative to the number of requests received.
+///
+/// When the destination is overloaded every transform that retries failed requests multiplies the load on it,
+/// so all transforms that retry, speculatively execute or fail over a request must call [`RetryBudget::try_withdraw`] first
+/// and give up on the retry when it returns false.
+///
+/// A retry is only allowed when both the chain budget and the connection budget have a retry available.
+#[derive(Clone)]
+pub struct RetryBudget {
+    chain: Arc<Budget>,
+    connection: Option<Arc<Budget>>,
+    rejected: Counter,
+}
+
+impl RetryBudget {
+    fn deposit(&self, requests: usize) {
+        self.chain.deposit(requests);
+        if let Some(connection) = &self.connection {
+            connection.deposit(requests);
+        }
+    }
+
+    /// Takes one retry from the budget, returns false if no retry is available.
+    pub fn try_withdraw(&self) -> bool {
+        let allowed = match &self.connection {
+            Some(connection) => match connection.try_withdraw() {
+                Some(withdrawal) => {
+                    if self.chain.try_withdraw().is_some() {
+                        true
+                    } else {
+                        connection.refund(withdrawal);
+                        false
+                    }
+                }
+                None => false,
+            },
+            None => self.chain.try_withdraw().is_some(),
+        };
+        if !allowed {
+            self.rejected.increment(1);
+        }
+        allowed
+    }
+}
+
+/// Deposits of requests that were not followed by a retry are kept for at most this many requests,
+/// otherwise a long period without failures would allow an unbounded burst of retries.
+const MAX_BALANCE_REQUESTS: f64 = 1000.0;
+
+struct Budget {
+    config: BudgetConfig,
+    state: Mutex<BudgetState>,
+}
+
+struct BudgetState {
+    /// Retries earned by requests
+    balance: f64,
+    /// The start of the second that `min_retries_used` counts retries for
+    second_start: Instant,
+    min_retries_used: u32,
+}
+
+impl Budget {
+    fn new(config: BudgetConfig) -> Self {
+        Budget {
+            config,
+            state: Mutex::new(BudgetState {
+                balance: 0.0,
+                second_start: Instant::now(),
+                min_retries_used: 0,
+            }),
+        }
+    }
+
+    fn deposit(&self, requests: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.balance = (state.balance + requests as f64 * self.config.retry_ratio)
+            .min(MAX_BALANCE_REQUESTS * self.config.retry_ratio);
+    }
+
+    fn try_withdraw(&self) -> Option<Withdrawal> {
+        let mut state = self.state.lock().unwrap();
+        if state.second_start.elapsed() >= Duration::from_secs(1) {
+            state.second_start = Instant::now();
+            state.min_retries_used = 0;
+        }
+
+        if state.min_retries_used < self.config.min_retries_per_second {
+            state.min_retries_used += 1;
+            Some(Withdrawal::MinRetries)
+        } else if state.balance >= 1.0 {
+            state.balance -= 1.0;
+            Some(Withdrawal::Balance)
+        } else {
+            None
+        }
+    }
+
+    fn refund(&self, withdrawal: Withdrawal) {
+        let mut state = self.state.lock().unwrap();
+        match withdrawal {
+            Withdrawal::MinRetries => {
+                state.min_retries_used = state.min_retries_used.saturating_sub(1)
+            }
+            Withdrawal::Balance => state.balance += 1.0,
+        }
+    }
+}
+
+/// Where a retry was taken from, so that it can be returned to the same place
+enum Withdrawal {
+    MinRetries,
+    Balance,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn budget(chain: BudgetConfig, connection: Option<BudgetConfig>) -> RetryBudget {
+        RetryBudget {
+            chain: Arc::new(Budget::new(chain)),
+            connection: connection.map(|config| Arc::new(Budget::new(config))),
+            rejected: Counter::noop(),
+        }
+    }
+
+    fn withdraw_all(budget: &RetryBudget) -> usize {
+        let mut count = 0;
+        while budget.try_withdraw() {
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn test_retry_ratio() {
+        let budget = budget(
+            BudgetConfig {
+                retry_ratio: 0.1,
+                min_retries_per_second: 0,
+            },
+            None,
+        );
+        assert_eq!(withdraw_all(&budget), 0);
+
+        budget.deposit(50);
+        assert_eq!(withdraw_all(&budget), 5);
+
+        // deposits are capped so that a quiet period cannot build up an unbounded burst of retries
+        budget.deposit(1_000_000);
+        assert_eq!(withdraw_all(&budget), 100);
+    }
+
+    #[test]
+    fn test_min_retries_per_second() {
+        let budget = budget(
+            BudgetConfig {
+                retry_ratio: 0.1,
+                min_retries_per_second: 3,
+            },
+            None,
+        );
+        assert_eq!(withdraw_all(&budget), 3);
+    }
+
+    #[test]
+    fn test_connection_and_chain_budget() {
+        let chain = BudgetConfig {
+            retry_ratio: 0.1,
+            min_retries_per_second: 0,
+        };
+        let connection = BudgetConfig {
+            retry_ratio: 0.5,
+            min_retries_per_second: 0,
+        };
+        let connection1 = budget(chain, Some(connection));
+        let connection2 = RetryBudget {
+            chain: connection1.chain.clone(),
+            connection: Some(Arc::new(Budget::new(connection))),
+            rejected: Counter::noop(),
+        };
+
+        // the chain budget is exhausted by connection1, so connection2 keeps the retries it earned
+        connection1.deposit(50);
+        connection2.deposit(10);
+        assert_eq!(withdraw_all(&connection1), 6);
+        assert_eq!(withdraw_all(&connection2), 0);
+
+        // once the chain budget recovers connection2 can spend its own budget
+        connection1.deposit(100);
+        assert_eq!(withdraw_all(&connection2), 5);
+    }
+}
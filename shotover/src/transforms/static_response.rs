@@ -0,0 +1,451 @@
+#[cfg(feature = "cassandra")]
+use crate::frame::cassandra::{parse_statement_single, Tracing};
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+#[cfg(feature = "cassandra")]
+use crate::frame::{value::GenericValue, CassandraFrame, CassandraOperation, CassandraResult};
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::guarantees::DeliveryGuarantees;
+use crate::transforms::{
+    DownChainProtocol, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig, UpChainProtocol, Wrapper,
+};
+#[cfg(feature = "cassandra")]
+use anyhow::bail;
+use anyhow::Result;
+use async_trait::async_trait;
+#[cfg(feature = "cassandra")]
+use cassandra_protocol::frame::message_result::{
+    ColSpec, ColType, ColTypeOption, RowsMetadata, RowsMetadataFlags, TableSpec,
+};
+#[cfg(feature = "cassandra")]
+use cql3_parser::{cassandra_statement::CassandraStatement, common::Identifier};
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StaticResponseConfig {
+    /// The requests answered by shotover, each request is answered by the first entry it matches
+    pub responses: Vec<StaticResponseEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum StaticResponseEntry {
+    #[cfg(feature = "redis")]
+    Redis {
+        /// The command and its arguments, compared case insensitively against the whole request
+        command: Vec<String>,
+        reply: RedisReply,
+    },
+    #[cfg(feature = "cassandra")]
+    Cassandra {
+        /// A SELECT from a table qualified by its keyspace, matched against queries that are the same statement once parsed
+        query: String,
+        /// The names of the text columns of the result
+        columns: Vec<String>,
+        /// The values of each row of the result, in the same order as `columns`
+        rows: Vec<Vec<String>>,
+    },
+}
+
+/// A Redis reply, strings may contain `{client_ip}` and `{local_address}` which are replaced when the reply is sent
+#[cfg(feature = "redis")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum RedisReply {
+    SimpleString(String),
+    BulkString(String),
+    Integer(i64),
+    Error(String),
+    Array(Vec<RedisReply>),
+    Null,
+}
+
+const NAME: &str = "StaticResponse";
+#[typetag::serde(name = "StaticResponse")]
+#[async_trait(?Send)]
+impl TransformConfig for StaticResponseConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let responses = self
+            .responses
+            .iter()
+            .map(ConfiguredResponse::from_config)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(StaticResponseBuilder {
+            responses: Arc::new(responses),
+            answered: counter!(
+                "shotover_static_responses_count",
+                "chain" => transform_context.chain_name
+            ),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// A configured response, ready to be matched against requests
+enum ConfiguredResponse {
+    #[cfg(feature = "redis")]
+    Redis {
+        command: Vec<String>,
+        reply: RedisReply,
+    },
+    #[cfg(feature = "cassandra")]
+    Cassandra {
+        /// The configured query as formatted by the parser, so that queries differing only in whitespace or case of keywords still match
+        query: String,
+        metadata: RowsMetadata,
+        rows: Vec<Vec<String>>,
+    },
+}
+
+impl ConfiguredResponse {
+    fn from_config(config: &StaticResponseEntry) -> Result<Self> {
+        match config.clone() {
+            #[cfg(feature = "redis")]
+            StaticResponseEntry::Redis { command, reply } => {
+                Ok(ConfiguredResponse::Redis { command, reply })
+            }
+            #[cfg(feature = "cassandra")]
+            StaticResponseEntry::Cassandra {
+                query,
+                columns,
+                rows,
+            } => {
+                if let Some(row) = rows.iter().find(|row| row.len() != columns.len()) {
+                    bail!(
+                        "the response to {query:?} has {} columns but the row {row:?} has {} values",
+                        columns.len(),
+                        row.len()
+                    );
+                }
+                let statement = parse_statement_single(&query);
+                let CassandraStatement::Select(select) = &statement else {
+                    bail!("{query:?} is not a SELECT, only SELECT queries can be answered");
+                };
+                let Some(keyspace) = &select.table_name.keyspace else {
+                    bail!("{query:?} must qualify the table with its keyspace");
+                };
+                Ok(ConfiguredResponse::Cassandra {
+                    metadata: RowsMetadata {
+                        flags: RowsMetadataFlags::GLOBAL_TABLE_SPACE,
+                        columns_count: columns.len() as i32,
+                        paging_state: None,
+                        new_metadata_id: None,
+                        global_table_spec: Some(TableSpec {
+                            ks_name: identifier_name(keyspace).to_owned(),
+                            table_name: identifier_name(&select.table_name.name).to_owned(),
+                        }),
+                        col_specs: columns
+                            .into_iter()
+                            .map(|name| ColSpec {
+                                table_spec: None,
+                                name,
+                                col_type: ColTypeOption {
+                                    id: ColType::Varchar,
+                                    value: None,
+                                },
+                            })
+                            .collect(),
+                    },
+                    query: statement.to_string(),
+                    rows,
+                })
+            }
+        }
+    }
+
+    /// Returns the response to the request if this is the configured response for it
+    fn respond(&self, request: &mut Message, template: &Template) -> Option<Message> {
+        match (self, request.frame()?) {
+            #[cfg(feature = "redis")]
+            (
+                ConfiguredResponse::Redis { command, reply },
+                Frame::Redis(RedisFrame::Array(args)),
+            ) => {
+                let matches = args.len() == command.len()
+                    && args.iter().zip(command).all(|(arg, word)| match arg {
+                        RedisFrame::BulkString(arg) => arg.eq_ignore_ascii_case(word.as_bytes()),
+                        _ => false,
+                    });
+                matches.then(|| Message::from_frame(Frame::Redis(template.redis(reply))))
+            }
+            #[cfg(feature = "cassandra")]
+            (
+                ConfiguredResponse::Cassandra {
+                    query,
+                    metadata,
+                    rows,
+                },
+                Frame::Cassandra(frame),
+            ) => match &frame.operation {
+                CassandraOperation::Query { query: request, .. }
+                    if request.to_string() == *query =>
+                {
+                    Some(Message::from_frame(Frame::Cassandra(CassandraFrame {
+                        version: frame.version,
+                        stream_id: frame.stream_id,
+                        tracing: Tracing::Response(None),
+                        warnings: vec![],
+                        operation: CassandraOperation::Result(CassandraResult::Rows {
+                            rows: rows
+                                .iter()
+                                .map(|row| {
+                                    row.iter()
+                                        .map(|value| GenericValue::Varchar(template.render(value)))
+                                        .collect()
+                                })
+                                .collect(),
+                            metadata: Box::new(metadata.clone()),
+                        }),
+                    })))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "cassandra")]
+fn identifier_name(identifier: &Identifier) -> &str {
+    match identifier {
+        Identifier::Unquoted(name) | Identifier::Quoted(name) => name,
+    }
+}
+
+/// The values substituted into responses
+struct Template<'a> {
+    client_ip: &'a str,
+    local_address: SocketAddr,
+}
+
+impl Template<'_> {
+    fn render(&self, value: &str) -> String {
+        value
+            .replace("{client_ip}", self.client_ip)
+            .replace("{local_address}", &self.local_address.to_string())
+    }
+
+    #[cfg(feature = "redis")]
+    fn redis(&self, reply: &RedisReply) -> RedisFrame {
+        match reply {
+            RedisReply::SimpleString(value) => RedisFrame::SimpleString(self.render(value).into()),
+            RedisReply::BulkString(value) => RedisFrame::BulkString(self.render(value).into()),
+            RedisReply::Integer(value) => RedisFrame::Integer(*value),
+            RedisReply::Error(value) => RedisFrame::Error(self.render(value).into()),
+            RedisReply::Array(values) => {
+                RedisFrame::Array(values.iter().map(|value| self.redis(value)).collect())
+            }
+            RedisReply::Null => RedisFrame::Null,
+        }
+    }
+}
+
+pub struct StaticResponseBuilder {
+    responses: Arc<Vec<ConfiguredResponse>>,
+    answered: Counter,
+}
+
+impl TransformBuilder for StaticResponseBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(StaticResponse {
+            responses: self.responses.clone(),
+            answered: self.answered.clone(),
+            client_ip: transform_context.client_details,
+            answered_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Matching requests are answered without being delivered
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
+    fn validate(&self) -> Vec<String> {
+        if self.responses.is_empty() {
+            vec![
+                format!("{NAME}:"),
+                "  responses must not be empty".to_owned(),
+            ]
+        } else {
+            vec![]
+        }
+    }
+}
+
+/// Answers the configured requests without sending them down the chain, such as the health checks of load balancers
+/// and the metadata queries drivers make on every connection.
+/// Requests that match none of the configured responses are passed down the chain unmodified.
+pub struct StaticResponse {
+    responses: Arc<Vec<ConfiguredResponse>>,
+    answered: Counter,
+    client_ip: String,
+    answered_requests: MessageIdMap<Message>,
+}
+
+#[async_trait]
+impl Transform for StaticResponse {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, mut requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let template = Template {
+            client_ip: &self.client_ip,
+            local_address: requests_wrapper.local_addr,
+        };
+        for request in requests_wrapper.requests.iter_mut() {
+            if request.is_dummy() {
+                continue;
+            }
+            if let Some(mut response) = self
+                .responses
+                .iter()
+                .find_map(|response| response.respond(request, &template))
+            {
+                response.set_request_id(request.id());
+                self.answered_requests.insert(request.id(), response);
+                request.replace_with_dummy();
+                self.answered.increment(1);
+            }
+        }
+
+        let mut responses = requests_wrapper.call_next_transform().await?;
+        for response in responses.iter_mut() {
+            if let Some(answered) = response
+                .request_id()
+                .and_then(|id| self.answered_requests.remove(&id))
+            {
+                *response = answered;
+            }
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn template() -> Template<'static> {
+        Template {
+            client_ip: "10.0.0.1",
+            local_address: "127.0.0.1:6379".parse().unwrap(),
+        }
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_redis_match() {
+        use crate::transforms::testing::redis_request;
+
+        let response = ConfiguredResponse::from_config(&StaticResponseEntry::Redis {
+            command: vec!["COMMAND".into(), "DOCS".into()],
+            reply: RedisReply::Array(vec![RedisReply::BulkString("{client_ip}".into())]),
+        })
+        .unwrap();
+
+        let mut reply = response
+            .respond(&mut redis_request(&["command", "docs"]), &template())
+            .unwrap();
+        assert_eq!(
+            reply.frame().unwrap(),
+            &mut Frame::Redis(RedisFrame::Array(vec![RedisFrame::BulkString(
+                "10.0.0.1".into()
+            )]))
+        );
+
+        assert!(response
+            .respond(&mut redis_request(&["COMMAND", "DOCS", "GET"]), &template())
+            .is_none());
+        assert!(response
+            .respond(&mut redis_request(&["COMMAND"]), &template())
+            .is_none());
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_cassandra_match() {
+        use cassandra_protocol::frame::Version;
+
+        let response = ConfiguredResponse::from_config(&StaticResponseEntry::Cassandra {
+            query: "SELECT release_version FROM system.local".into(),
+            columns: vec!["release_version".into()],
+            rows: vec![vec!["4.0.6".into()]],
+        })
+        .unwrap();
+        let request = |query: &str| {
+            Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version: Version::V4,
+                stream_id: 7,
+                tracing: Tracing::Request(false),
+                warnings: vec![],
+                operation: CassandraOperation::Query {
+                    query: Box::new(parse_statement_single(query)),
+                    params: Box::default(),
+                },
+            }))
+        };
+
+        let mut reply = response
+            .respond(
+                &mut request("select   release_version from system.local"),
+                &template(),
+            )
+            .unwrap();
+        let Some(Frame::Cassandra(frame)) = reply.frame() else {
+            panic!("expected a cassandra frame");
+        };
+        assert_eq!(frame.stream_id, 7);
+        let CassandraOperation::Result(CassandraResult::Rows { rows, .. }) = &frame.operation
+        else {
+            panic!("expected rows");
+        };
+        assert_eq!(rows, &vec![vec![GenericValue::Varchar("4.0.6".into())]]);
+
+        assert!(response
+            .respond(&mut request("SELECT rack FROM system.local"), &template())
+            .is_none());
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_cassandra_unqualified_table() {
+        let err = ConfiguredResponse::from_config(&StaticResponseEntry::Cassandra {
+            query: "SELECT release_version FROM local".into(),
+            columns: vec!["release_version".into()],
+            rows: vec![],
+        })
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "\"SELECT release_version FROM local\" must qualify the table with its keyspace"
+        );
+    }
+}
@@ -237,6 +237,14 @@ impl TransformConfig for TeeConfig {
     fn down_chain_protocol(&self) -> DownChainProtocol {
         DownChainProtocol::SameAsUpChain
     }
+
+    fn subchains(&self) -> Vec<(&'static str, &TransformChainConfig)> {
+        let mut subchains = vec![("chain", &self.chain)];
+        if let Some(ConsistencyBehaviorConfig::SubchainOnMismatch(chain)) = &self.behavior {
+            subchains.push(("behavior.SubchainOnMismatch", chain));
+        }
+        subchains
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,276 @@
+//! Runs a chain of transforms in process so that custom transforms can be unit tested without opening sockets or running a database.
+//!
+//! A [`ChainTester`] builds a chain from a yaml snippet, in the same format as the `chain` of a source in `topology.yaml`,
+//! and terminates it with a [`FakeSink`] in place of a real sink.
+//! Tests send requests through the chain and then assert on the responses that come out of the chain
+//! and on the requests that reached the sink:
+//!
+//! ```
+//! # #[cfg(feature = "redis")]
+//! # async fn test_query_counter() {
+//! use shotover::frame::{Frame, MessageType, RedisFrame};
+//! use shotover::message::Message;
+//! use shotover::transforms::testing::{redis_request, ChainTester};
+//!
+//! let mut tester = ChainTester::from_yaml(
+//!     "- QueryCounter:\n    name: test",
+//!     MessageType::Redis,
+//! )
+//! .await
+//! .unwrap();
+//! tester.sink().respond_with(|_request| {
+//!     Ok(Message::from_frame(Frame::Redis(RedisFrame::SimpleString("OK".into()))))
+//! });
+//!
+//! let request = redis_request(&["SET", "key", "value"]);
+//! let mut responses = tester.send(vec![request]).await.unwrap();
+//!
+//! assert_eq!(
+//!     responses[0].frame(),
+//!     Some(&mut Frame::Redis(RedisFrame::SimpleString("OK".into())))
+//! );
+//! assert_eq!(tester.sink().received().len(), 1);
+//! # }
+//! ```
+
+use crate::config::chain::TransformChainConfig;
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::{
+    ConnectionEvent, Transform, TransformBuilder, TransformContextBuilder, TransformContextConfig,
+    Wrapper,
+};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+#[cfg(feature = "redis")]
+use bytes::Bytes;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// The name of the chain built by [`ChainTester`], as seen by the transforms in the chain
+const CHAIN_NAME: &str = "test_chain";
+
+/// A chain of transforms terminated by a [`FakeSink`], see the [module level docs](self) for an example.
+pub struct ChainTester {
+    chain: TransformChain,
+    sink: FakeSink,
+    context: TransformContextBuilder,
+    local_addr: SocketAddr,
+}
+
+impl ChainTester {
+    /// Builds the chain described by `yaml`, a list of transforms in the same format as the `chain` of a source in `topology.yaml`.
+    /// The chain receives requests of the `protocol` and must not include a sink, a [`FakeSink`] is added to the end of the chain instead.
+    ///
+    /// Returns an error if the chain fails to build or is invalid, as shotover would when starting with the same chain.
+    pub async fn from_yaml(yaml: &str, protocol: MessageType) -> Result<Self> {
+        let deserializer = serde_yaml::Deserializer::from_str(yaml);
+        let config: TransformChainConfig =
+            serde_yaml::with::singleton_map_recursive::deserialize(deserializer)
+                .map_err(|err| anyhow!("Failed to parse the chain: {err}"))?;
+        let builder = config
+            .get_builder(TransformContextConfig {
+                chain_name: CHAIN_NAME.to_owned(),
+                protocol,
+            })
+            .await?;
+        Self::from_builders(builder.chain.into_iter().map(|x| x.builder).collect())
+    }
+
+    /// Builds a chain of the transforms built by `builders`, followed by a [`FakeSink`].
+    ///
+    /// Returns an error if the chain is invalid, as shotover would when starting with the same chain.
+    pub fn from_builders(mut builders: Vec<Box<dyn TransformBuilder>>) -> Result<Self> {
+        let sink = FakeSink::new();
+        builders.push(Box::new(sink.clone()));
+        let builder = TransformChainBuilder::new(builders, CHAIN_NAME);
+        let errors = builder.validate();
+        if !errors.is_empty() {
+            bail!("The chain is invalid:\n{}", errors.join("\n"));
+        }
+
+        let context = TransformContextBuilder::new_test();
+        Ok(ChainTester {
+            chain: builder.build(context.clone()),
+            sink,
+            context,
+            local_addr: "127.0.0.1:8000".parse().unwrap(),
+        })
+    }
+
+    /// The sink at the end of the chain, used to program its responses and inspect the requests that reached it
+    pub fn sink(&self) -> &FakeSink {
+        &self.sink
+    }
+
+    /// The context the transforms of the chain were built with.
+    /// For example [`TransformContextBuilder::close_connection`] reports whether a transform closed the connection.
+    pub fn context(&self) -> &TransformContextBuilder {
+        &self.context
+    }
+
+    /// Sends a batch of requests through the chain as if they were received from a client, returning the responses that would be sent to the client.
+    pub async fn send(&mut self, requests: Messages) -> Result<Messages> {
+        self.chain
+            .process_request(Wrapper::new_with_addr(requests, self.local_addr))
+            .await
+    }
+
+    /// Runs the chain without any requests, as happens when a transform forces a chain run,
+    /// returning any responses produced by the transforms in the meantime.
+    pub async fn flush(&mut self) -> Result<Messages> {
+        self.send(vec![]).await
+    }
+
+    /// Notifies every transform in the chain of a connection event, as shotover does over the lifetime of a client connection.
+    pub async fn connection_event(&mut self, event: ConnectionEvent) -> Result<()> {
+        self.chain.process_connection_event(event).await
+    }
+}
+
+type Respond = Arc<Mutex<Box<dyn FnMut(&mut Message) -> Result<Message> + Send>>>;
+
+/// A sink that answers requests by calling a programmable function instead of sending them to a database,
+/// recording every request it receives.
+///
+/// By default each request is answered with a copy of itself.
+/// Dummy requests are answered with a dummy response, as a real sink would, and are neither recorded nor passed to the function.
+#[derive(Clone)]
+pub struct FakeSink {
+    received: Arc<Mutex<Messages>>,
+    respond: Respond,
+}
+
+impl FakeSink {
+    fn new() -> Self {
+        FakeSink {
+            received: Arc::new(Mutex::new(vec![])),
+            respond: Arc::new(Mutex::new(Box::new(|request: &mut Message| {
+                Ok(request.clone())
+            }))),
+        }
+    }
+
+    /// Answers each following request with the message returned by `respond`, or fails the chain run if it returns an error.
+    /// The request id of the response is set by the sink.
+    pub fn respond_with<F>(&self, respond: F)
+    where
+        F: FnMut(&mut Message) -> Result<Message> + Send + 'static,
+    {
+        *self.respond.lock().unwrap() = Box::new(respond);
+    }
+
+    /// Every request received by the sink so far, in the order they were received
+    pub fn received(&self) -> Messages {
+        self.received.lock().unwrap().clone()
+    }
+
+    /// Takes every request received by the sink so far, so that the next call only returns requests received after this one
+    pub fn take_received(&self) -> Messages {
+        std::mem::take(&mut *self.received.lock().unwrap())
+    }
+}
+
+const NAME: &str = "FakeSink";
+
+impl TransformBuilder for FakeSink {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(self.clone())
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl Transform for FakeSink {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'a>(&'a mut self, requests_wrapper: Wrapper<'a>) -> Result<Messages> {
+        let mut responses = Vec::with_capacity(requests_wrapper.requests.len());
+        for mut request in requests_wrapper.requests {
+            let mut response = if request.is_dummy() {
+                Message::from_frame(Frame::Dummy)
+            } else {
+                self.received.lock().unwrap().push(request.clone());
+                (self.respond.lock().unwrap())(&mut request)?
+            };
+            response.set_request_id(request.id());
+            responses.push(response);
+        }
+        Ok(responses)
+    }
+}
+
+/// The arguments of a redis command as sent by a client, each argument is sent as a bulk string.
+#[cfg(feature = "redis")]
+pub fn redis_args(args: &[&str]) -> Vec<RedisFrame> {
+    args.iter()
+        .map(|arg| RedisFrame::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+        .collect()
+}
+
+/// A request of a redis command as sent by a client, e.g. `redis_request(&["SET", "key", "value"])`
+#[cfg(feature = "redis")]
+pub fn redis_request(args: &[&str]) -> Message {
+    Message::from_frame(Frame::Redis(RedisFrame::Array(redis_args(args))))
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::transforms::null::NullSink;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_programmed_responses() {
+        let mut tester =
+            ChainTester::from_yaml("- QueryCounter:\n    name: test", MessageType::Redis)
+                .await
+                .unwrap();
+        tester.sink().respond_with(|request| {
+            let Some(Frame::Redis(RedisFrame::Array(args))) = request.frame() else {
+                return Err(anyhow!("expected a redis array"));
+            };
+            Ok(Message::from_frame(Frame::Redis(args[1].clone())))
+        });
+
+        let requests = vec![redis_request(&["GET", "a"]), redis_request(&["GET", "b"])];
+        let request_ids: Vec<_> = requests.iter().map(|x| x.id()).collect();
+        let mut responses = tester.send(requests).await.unwrap();
+
+        assert_eq!(
+            responses.iter().map(|x| x.request_id()).collect::<Vec<_>>(),
+            request_ids.into_iter().map(Some).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            responses[1].frame(),
+            Some(&mut Frame::Redis(RedisFrame::BulkString("b".into())))
+        );
+        assert_eq!(tester.sink().take_received().len(), 2);
+        assert_eq!(tester.sink().received().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sink_in_chain_is_invalid() {
+        let err = ChainTester::from_builders(vec![Box::new(NullSink::default())])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            r#"The chain is invalid:
+test_chain chain:
+  Terminating transform "NullSink" is not last in chain. Terminating transform must be last in chain."#
+        );
+    }
+}
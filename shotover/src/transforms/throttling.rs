@@ -1,6 +1,7 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
 use crate::frame::MessageType;
 use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::guarantees::DeliveryGuarantees;
 use crate::transforms::{Transform, TransformBuilder, TransformConfig, Wrapper};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -63,6 +64,11 @@ impl TransformBuilder for RequestThrottling {
         NAME
     }
 
+    fn delivery_guarantees(&self) -> DeliveryGuarantees {
+        // Requests over the rate limit are rejected
+        DeliveryGuarantees::AT_MOST_ONCE
+    }
+
     fn validate(&self) -> Vec<String> {
         if self.max_requests_per_second < nonzero!(50u32) {
             vec![
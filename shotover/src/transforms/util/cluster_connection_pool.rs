@@ -8,16 +8,18 @@ use crate::transforms::util::{ConnectionError, Request};
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use derivative::Derivative;
-use futures::StreamExt;
-use std::collections::HashMap;
+use futures::{SinkExt, StreamExt};
+use metrics::counter;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fmt;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot;
-use tokio::sync::Mutex;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::Instant;
 use tokio_util::codec::{FramedRead, FramedWrite};
 use tracing::{debug, trace, warn, Instrument};
 
@@ -45,6 +47,28 @@ impl Authenticator<()> for NoopAuthenticator {
     }
 }
 
+/// Controls how requests are written to a connection that may be shared by many clients.
+/// The default writes requests as soon as they arrive, in the order they arrive, with no limit on how many are awaiting a response.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PipeliningConfig {
+    /// Maximum number of requests awaiting a response on a single connection.
+    /// Further requests are held back until responses come in.
+    pub max_in_flight: Option<usize>,
+    /// How long to wait for more requests before writing a partially filled buffer.
+    pub flush_interval_us: Option<u64>,
+    /// The buffer is written as soon as it holds this many bytes, regardless of `flush_interval_us`.
+    pub flush_bytes: Option<usize>,
+    /// Maximum number of requests from one client written before requests queued by other clients get a turn.
+    pub max_consecutive_requests_per_client: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LaneReport {
+    pub authenticated: bool,
+    pub open_connections: BTreeMap<String, usize>,
+}
+
 // TODO: Replace with trait_alias (rust-lang/rust#41517).
 pub trait Token: Send + Sync + std::hash::Hash + Eq + Clone + fmt::Debug {}
 impl<T: Send + Sync + std::hash::Hash + Eq + Clone + fmt::Debug> Token for T {}
@@ -54,6 +78,8 @@ impl<T: Send + Sync + std::hash::Hash + Eq + Clone + fmt::Debug> Token for T {}
 pub struct ConnectionPool<C: CodecBuilder, A: Authenticator<T>, T: Token> {
     connect_timeout: Duration,
     lanes: Arc<Mutex<HashMap<Option<T>, Lane>>>,
+    pipelining: PipeliningConfig,
+    keepalive_interval: Option<Duration>,
 
     #[derivative(Debug = "ignore")]
     codec: C,
@@ -71,10 +97,14 @@ impl<C: CodecBuilder + 'static, A: Authenticator<T>, T: Token> ConnectionPool<C,
         codec: C,
         authenticator: A,
         tls: Option<TlsConnectorConfig>,
+        pipelining: PipeliningConfig,
+        keepalive_interval: Option<Duration>,
     ) -> Result<Self> {
         Ok(Self {
             connect_timeout,
             lanes: Arc::new(Mutex::new(HashMap::new())),
+            pipelining,
+            keepalive_interval,
             tls: tls.map(TlsConnector::new).transpose()?,
             codec,
             authenticator,
@@ -117,6 +147,48 @@ impl<C: CodecBuilder + 'static, A: Authenticator<T>, T: Token> ConnectionPool<C,
         Ok(connections[..connection_count].to_vec())
     }
 
+    /// Grows or shrinks the pooled connections to every address of every lane to `connection_count`.
+    /// Connections removed from the pool stay open until every client that was handed them has dropped them.
+    pub async fn resize(&self, connection_count: usize) {
+        let mut lanes = self.lanes.lock().await;
+        for (token, lane) in lanes.iter_mut() {
+            for (address, connections) in lane.iter_mut() {
+                connections.retain(|connection| !connection.is_closed());
+
+                let shortfall_count = connection_count.saturating_sub(connections.len());
+                if shortfall_count > 0 {
+                    match self
+                        .new_unpooled_connections(address, token, shortfall_count)
+                        .await
+                    {
+                        Ok(mut new_connections) => connections.append(&mut new_connections),
+                        Err(err) => warn!("Failed to grow connection pool for {address}: {err}"),
+                    }
+                }
+                connections.truncate(connection_count);
+            }
+        }
+    }
+
+    /// The number of open pooled connections to each address, for each lane.
+    /// Lanes are identified by whether they were created with a token rather than by the token itself, to avoid reporting credentials.
+    pub async fn open_connections(&self) -> Vec<LaneReport> {
+        let lanes = self.lanes.lock().await;
+        lanes
+            .iter()
+            .map(|(token, lane)| LaneReport {
+                authenticated: token.is_some(),
+                open_connections: lane
+                    .iter()
+                    .map(|(address, connections)| {
+                        let open = connections.iter().filter(|x| !x.is_closed()).count();
+                        (address.clone(), open)
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
     async fn new_unpooled_connections(
         &self,
         address: &str,
@@ -166,13 +238,25 @@ impl<C: CodecBuilder + 'static, A: Authenticator<T>, T: Token> ConnectionPool<C,
                 .await
                 .map_err(ConnectionError::Other)?;
             let (rx, tx) = tokio::io::split(tls_stream);
-            spawn_read_write_tasks(&self.codec, rx, tx)
+            spawn_pipelined_read_write_tasks(
+                &self.codec,
+                rx,
+                tx,
+                self.pipelining.clone(),
+                self.keepalive_interval,
+            )
         } else {
             let tcp_stream = tcp::tcp_stream(self.connect_timeout, address)
                 .await
                 .map_err(ConnectionError::Other)?;
             let (rx, tx) = tcp_stream.into_split();
-            spawn_read_write_tasks(&self.codec, rx, tx)
+            spawn_pipelined_read_write_tasks(
+                &self.codec,
+                rx,
+                tx,
+                self.pipelining.clone(),
+                self.keepalive_interval,
+            )
         };
 
         if let Some(token) = token {
@@ -194,17 +278,41 @@ pub fn spawn_read_write_tasks<
     codec: &C,
     stream_rx: R,
     stream_tx: W,
+) -> Connection {
+    spawn_pipelined_read_write_tasks(
+        codec,
+        stream_rx,
+        stream_tx,
+        PipeliningConfig::default(),
+        None,
+    )
+}
+
+pub fn spawn_pipelined_read_write_tasks<
+    C: CodecBuilder + 'static,
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+>(
+    codec: &C,
+    stream_rx: R,
+    stream_tx: W,
+    pipelining: PipeliningConfig,
+    keepalive_interval: Option<Duration>,
 ) -> Connection {
     let (dummy_request_tx, dummy_request_rx) = tokio::sync::mpsc::unbounded_channel();
     let (out_tx, out_rx) = tokio::sync::mpsc::unbounded_channel();
     let (return_tx, return_rx) = tokio::sync::mpsc::unbounded_channel();
     let (closed_tx, closed_rx) = tokio::sync::oneshot::channel();
+    let in_flight = pipelining
+        .max_in_flight
+        .map(|max| Arc::new(Semaphore::new(max)));
+    let in_flight_rx = in_flight.clone();
 
     let (decoder, encoder) = codec.build();
 
     tokio::spawn(async move {
         tokio::select! {
-            result = tx_process(dummy_request_tx, stream_tx, out_rx, return_tx, encoder) => if let Err(e) = result {
+            result = tx_process(dummy_request_tx, stream_tx, out_rx, return_tx, encoder, pipelining, keepalive_interval, in_flight) => if let Err(e) = result {
                 trace!("connection write-closed with error: {:?}", e);
             } else {
                 trace!("connection write-closed gracefully");
@@ -217,7 +325,15 @@ pub fn spawn_read_write_tasks<
 
     tokio::spawn(
         async move {
-            if let Err(e) = rx_process(dummy_request_rx, stream_rx, return_rx, decoder).await {
+            if let Err(e) = rx_process(
+                dummy_request_rx,
+                stream_rx,
+                return_rx,
+                decoder,
+                in_flight_rx,
+            )
+            .await
+            {
                 trace!("connection read-closed with error: {:?}", e);
             } else {
                 trace!("connection read-closed gracefully");
@@ -232,25 +348,169 @@ pub fn spawn_read_write_tasks<
     out_tx
 }
 
+/// Requests waiting to be written, queued per client so that they can be written round robin.
+#[derive(Default)]
+struct PendingRequests {
+    queues: VecDeque<(Option<usize>, VecDeque<Request>)>,
+    /// Requests written from the client at the front of `queues` since it was last rotated to the back
+    consecutive: usize,
+}
+
+impl PendingRequests {
+    fn push(&mut self, request: Request) {
+        match self
+            .queues
+            .iter_mut()
+            .find(|(client_id, _)| *client_id == request.client_id)
+        {
+            Some((_, queue)) => queue.push_back(request),
+            None => self
+                .queues
+                .push_back((request.client_id, VecDeque::from([request]))),
+        }
+    }
+
+    fn pop(&mut self, max_consecutive: Option<usize>) -> Option<Request> {
+        let (_, queue) = self.queues.front_mut()?;
+        let request = queue.pop_front();
+        self.consecutive += 1;
+        if queue.is_empty() {
+            self.queues.pop_front();
+            self.consecutive = 0;
+        } else if max_consecutive.map_or(false, |max| self.consecutive >= max) {
+            self.queues.rotate_left(1);
+            self.consecutive = 0;
+        }
+        request
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn tx_process<C: EncoderHalf, W: AsyncWrite + Unpin + Send + 'static>(
     dummy_request_tx: UnboundedSender<MessageId>,
     write: W,
-    out_rx: UnboundedReceiver<Request>,
+    mut out_rx: UnboundedReceiver<Request>,
     return_tx: UnboundedSender<ReturnChan>,
     codec: C,
+    pipelining: PipeliningConfig,
+    keepalive_interval: Option<Duration>,
+    in_flight: Option<Arc<Semaphore>>,
 ) -> Result<(), CodecWriteError> {
-    let writer = FramedWrite::new(write, codec);
-    let rx_stream = UnboundedReceiverStream::new(out_rx).map(|x| {
-        if x.message.is_dummy() {
-            dummy_request_tx.send(x.message.id()).ok();
+    let mut writer = FramedWrite::new(write, codec);
+    let mut pending = PendingRequests::default();
+    let flush_interval = pipelining.flush_interval_us.map(Duration::from_micros);
+    let mut unflushed_since: Option<Instant> = None;
+    let abandoned_requests = counter!("shotover_abandoned_upstream_requests_count");
+
+    loop {
+        // Take everything that has been sent so far, waiting only when there is nothing else to do.
+        if pending.is_empty() {
+            let request = match (unflushed_since, flush_interval) {
+                (None, _) => match keepalive_interval {
+                    Some(interval) => match tokio::time::timeout(interval, out_rx.recv()).await {
+                        Ok(request) => request,
+                        Err(_) => {
+                            // Nothing has been written for a whole interval
+                            if let Some(keepalive) = writer.encoder_mut().keepalive_request() {
+                                // The permit is returned by rx_process once the response is received.
+                                // If the connection is at its in flight limit it is evidently not idle, so the keepalive is not needed.
+                                let permitted = in_flight.as_ref().map_or(true, |in_flight| {
+                                    in_flight.try_acquire().map(|x| x.forget()).is_ok()
+                                });
+                                if permitted {
+                                    // There is no client waiting on the response, so rx_process discards it
+                                    return_tx
+                                        .send(None)
+                                        .map_err(|err| CodecWriteError::Encoder(anyhow!(err)))?;
+                                    writer.send(vec![keepalive]).await?;
+                                }
+                            }
+                            continue;
+                        }
+                    },
+                    None => out_rx.recv().await,
+                },
+                (Some(since), Some(interval)) => {
+                    match tokio::time::timeout_at(since + interval, out_rx.recv()).await {
+                        Ok(request) => request,
+                        Err(_) => {
+                            writer.flush().await?;
+                            unflushed_since = None;
+                            continue;
+                        }
+                    }
+                }
+                (Some(_), None) => {
+                    writer.flush().await?;
+                    unflushed_since = None;
+                    continue;
+                }
+            };
+            match request {
+                Some(request) => pending.push(request),
+                None => break,
+            }
+        }
+        while let Ok(request) = out_rx.try_recv() {
+            pending.push(request);
+        }
+
+        while let Some(request) = pending.pop(pipelining.max_consecutive_requests_per_client) {
+            if request
+                .return_chan
+                .as_ref()
+                .is_some_and(|return_chan| return_chan.is_closed())
+            {
+                // The client that sent this request has disconnected, so nobody is waiting on the response.
+                // Skip writing it so the upstream does no work on its behalf and the connection moves on to the requests of other clients.
+                abandoned_requests.increment(1);
+                continue;
+            }
+            if request.message.is_dummy() {
+                dummy_request_tx.send(request.message.id()).ok();
+            } else if let Some(in_flight) = &in_flight {
+                // Anything buffered must be written before waiting, otherwise the responses that free up permits never arrive.
+                let permit = match in_flight.clone().try_acquire_owned() {
+                    Ok(permit) => permit,
+                    Err(_) => {
+                        writer.flush().await?;
+                        unflushed_since = None;
+                        in_flight
+                            .clone()
+                            .acquire_owned()
+                            .await
+                            .map_err(|err| CodecWriteError::Encoder(anyhow!(err)))?
+                    }
+                };
+                // The permit is returned by rx_process once the response is received
+                permit.forget();
+            }
+
+            return_tx
+                .send(request.return_chan)
+                .map_err(|err| CodecWriteError::Encoder(anyhow!(err)))?;
+            writer.feed(vec![request.message]).await?;
+            unflushed_since.get_or_insert_with(Instant::now);
+
+            if pipelining.flush_bytes.map_or(false, |flush_bytes| {
+                writer.write_buffer().len() >= flush_bytes
+            }) {
+                writer.flush().await?;
+                unflushed_since = None;
+            }
+
+            // Pick up requests sent while we were writing so that they are considered for fairness
+            while let Ok(request) = out_rx.try_recv() {
+                pending.push(request);
+            }
         }
-        let ret = Ok(vec![x.message]);
-        return_tx
-            .send(x.return_chan)
-            .map_err(|err| CodecWriteError::Encoder(anyhow!(err)))?;
-        ret
-    });
-    rx_stream.forward(writer).await
+    }
+
+    writer.flush().await
 }
 
 type ReturnChan = Option<oneshot::Sender<Response>>;
@@ -260,6 +520,7 @@ async fn rx_process<C: DecoderHalf, R: AsyncRead + Unpin + Send + 'static>(
     read: R,
     mut return_rx: UnboundedReceiver<ReturnChan>,
     codec: C,
+    in_flight: Option<Arc<Semaphore>>,
 ) -> Result<()> {
     let mut reader = FramedRead::new(read, codec);
 
@@ -272,6 +533,9 @@ async fn rx_process<C: DecoderHalf, R: AsyncRead + Unpin + Send + 'static>(
                 match responses {
                     Some(Ok(responses)) => {
                         for response_message in responses {
+                            if let Some(in_flight) = &in_flight {
+                                in_flight.add_permits(1);
+                            }
                             if let Some(Some(ret)) = return_rx.recv().await {
                                 // If the receiver hangs up, just silently ignore
                                 ret.send(Response {
@@ -307,14 +571,18 @@ async fn rx_process<C: DecoderHalf, R: AsyncRead + Unpin + Send + 'static>(
 
 #[cfg(all(test, feature = "redis"))]
 mod test {
-    use super::spawn_read_write_tasks;
+    use super::{spawn_read_write_tasks, PendingRequests};
     use crate::codec::redis::RedisCodecBuilder;
     use crate::codec::{CodecBuilder, Direction};
+    use crate::frame::{Frame, RedisFrame};
+    use crate::message::Message;
+    use crate::transforms::util::Request;
     use std::mem;
     use std::time::Duration;
-    use tokio::io::AsyncReadExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::net::TcpListener;
     use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
     use tokio::time::timeout;
 
     #[tokio::test]
@@ -391,4 +659,88 @@ mod test {
             "remote did not detect local shutdown"
         );
     }
+
+    #[tokio::test]
+    async fn test_abandoned_requests_are_not_written() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let remote = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut received = vec![];
+            let mut buffer = [0; 1024];
+            while !received.ends_with(b"PING\r\n") {
+                let read = socket.read(&mut buffer[..]).await.unwrap();
+                assert!(read > 0, "connection closed before PING was received");
+                received.extend_from_slice(&buffer[..read]);
+            }
+            socket.write_all(b"+PONG\r\n").await.unwrap();
+            received
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (rx, tx) = stream.into_split();
+        let codec = RedisCodecBuilder::new(Direction::Sink, "redis".to_owned());
+        let sender = spawn_read_write_tasks(&codec, rx, tx);
+
+        let command = |name: &'static str| {
+            Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                RedisFrame::BulkString(name.into()),
+            ])))
+        };
+        // The client that sent this request has already disconnected
+        let (abandoned_tx, abandoned_rx) = oneshot::channel();
+        std::mem::drop(abandoned_rx);
+        sender
+            .send(Request {
+                message: command("DBSIZE"),
+                return_chan: Some(abandoned_tx),
+                client_id: Some(1),
+            })
+            .unwrap();
+        let (return_tx, return_rx) = oneshot::channel();
+        sender
+            .send(Request {
+                message: command("PING"),
+                return_chan: Some(return_tx),
+                client_id: Some(2),
+            })
+            .unwrap();
+
+        let mut response = timeout(Duration::from_secs(1), return_rx)
+            .await
+            .unwrap()
+            .unwrap()
+            .response
+            .unwrap();
+        assert_eq!(
+            response.frame(),
+            Some(&mut Frame::Redis(RedisFrame::SimpleString("PONG".into())))
+        );
+        assert_eq!(remote.await.unwrap(), b"*1\r\n$4\r\nPING\r\n");
+    }
+
+    #[test]
+    fn test_pending_requests_round_robin() {
+        let request = |client_id| Request {
+            message: Message::from_frame(Frame::Redis(RedisFrame::Integer(client_id as i64))),
+            return_chan: None,
+            client_id: Some(client_id),
+        };
+        let mut pending = PendingRequests::default();
+        for _ in 0..4 {
+            pending.push(request(1));
+        }
+        pending.push(request(2));
+        pending.push(request(3));
+
+        let mut order = vec![];
+        while let Some(mut request) = pending.pop(Some(2)) {
+            match request.message.frame() {
+                Some(Frame::Redis(RedisFrame::Integer(client_id))) => order.push(*client_id),
+                frame => panic!("unexpected frame {frame:?}"),
+            }
+        }
+        assert_eq!(order, vec![1, 1, 2, 3, 1, 1]);
+    }
 }
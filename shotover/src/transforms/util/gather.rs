@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use futures::future::join_all;
+use std::future::Future;
+use std::time::Duration;
+
+/// Reassembles the responses to a request that was split into several parts, such as an MGET whose keys live on different nodes.
+///
+/// Each element of the original request is recorded with `push` in order, along with the part it was placed in.
+/// Once every part has responded, `reassemble` puts the elements of the responses back into the order of the original request.
+#[derive(Debug, Default)]
+pub struct Gather {
+    /// For each element of the original request, the part it was placed in and its index within that part
+    positions: Vec<(usize, usize)>,
+    /// The number of elements placed in each part
+    part_lens: Vec<usize>,
+}
+
+impl Gather {
+    /// Records that the next element of the original request was appended to `part`.
+    pub fn push(&mut self, part: usize) {
+        if self.part_lens.len() <= part {
+            self.part_lens.resize(part + 1, 0);
+        }
+        self.positions.push((part, self.part_lens[part]));
+        self.part_lens[part] += 1;
+    }
+
+    pub fn part_count(&self) -> usize {
+        self.part_lens.len()
+    }
+
+    /// Takes the elements of each part's response, indexed by part, and returns them in the order of the original request.
+    pub fn reassemble<T>(&self, parts: Vec<Vec<T>>) -> Result<Vec<T>> {
+        if parts.len() != self.part_lens.len() {
+            return Err(anyhow!(
+                "expected responses for {} parts but received {}",
+                self.part_lens.len(),
+                parts.len()
+            ));
+        }
+        for (part, (received, expected)) in parts.iter().zip(&self.part_lens).enumerate() {
+            if received.len() != *expected {
+                return Err(anyhow!(
+                    "expected {expected} elements in the response to part {part} but received {}",
+                    received.len()
+                ));
+            }
+        }
+
+        let mut parts: Vec<Vec<Option<T>>> = parts
+            .into_iter()
+            .map(|part| part.into_iter().map(Some).collect())
+            .collect();
+        Ok(self
+            .positions
+            .iter()
+            .map(|(part, index)| parts[*part][*index].take().unwrap())
+            .collect())
+    }
+}
+
+/// Awaits every part of a split request concurrently, returning their results in the order the parts were given.
+/// When `part_timeout` is set each part that does not complete in time results in an error for that part alone.
+pub async fn gather_parts<T, F>(
+    parts: impl IntoIterator<Item = F>,
+    part_timeout: Option<Duration>,
+) -> Vec<Result<T>>
+where
+    F: Future<Output = Result<T>>,
+{
+    join_all(parts.into_iter().enumerate().map(|(i, part)| async move {
+        match part_timeout {
+            Some(part_timeout) => tokio::time::timeout(part_timeout, part)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(anyhow!("part {i} did not respond within {part_timeout:?}"))
+                }),
+            None => part.await,
+        }
+    }))
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_reassemble() {
+        let mut gather = Gather::default();
+        // original request: a b c d e, with a, c and e in part 0 and b and d in part 1
+        for part in [0, 1, 0, 1, 0] {
+            gather.push(part);
+        }
+        assert_eq!(gather.part_count(), 2);
+        assert_eq!(
+            gather
+                .reassemble(vec![vec!["a", "c", "e"], vec!["b", "d"]])
+                .unwrap(),
+            vec!["a", "b", "c", "d", "e"]
+        );
+        assert!(gather
+            .reassemble(vec![vec!["a", "c"], vec!["b", "d"]])
+            .is_err());
+        assert!(gather.reassemble(vec![vec!["a", "c", "e"]]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_gather_parts_timeout() {
+        let parts: Vec<std::pin::Pin<Box<dyn Future<Output = Result<u32>>>>> = vec![
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(1)
+            }),
+            Box::pin(async { Ok(2) }),
+        ];
+        let results = gather_parts(parts, Some(Duration::from_millis(10))).await;
+        assert!(results[0].is_err());
+        assert_eq!(results[1].as_ref().unwrap(), &2);
+    }
+}
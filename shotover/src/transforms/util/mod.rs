@@ -4,6 +4,9 @@ use std::fmt;
 use crate::message::Message;
 
 pub mod cluster_connection_pool;
+pub mod gather;
+pub mod hyperloglog;
+pub mod prewarm;
 
 /// Represents a `Request` to a connection within Shotover
 #[derive(Debug)]
@@ -12,6 +15,8 @@ pub struct Request {
     pub message: Message,
     // Channel to return the response to
     pub return_chan: Option<tokio::sync::oneshot::Sender<Response>>,
+    // Identifies the client that sent the request, used to share a connection fairly between clients
+    pub client_id: Option<usize>,
 }
 
 /// Represents a `Response` to a `Request`
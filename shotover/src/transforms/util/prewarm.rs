@@ -0,0 +1,352 @@
+use anyhow::{anyhow, bail, Context, Result};
+use metrics::{gauge, Gauge};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Grows the connection pool of a sink ahead of demand instead of when requests are already queueing up.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum PrewarmConfig {
+    /// Forecasts the request rate from its recent trend and sizes the pool for the forecast rate.
+    Forecast(ForecastConfig),
+    /// Sizes the pool according to the time of day.
+    Schedule(Vec<ScheduleEntry>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ForecastConfig {
+    /// The request rate each connection to a node is expected to handle.
+    pub requests_per_second_per_connection: f64,
+    /// The pool never grows past this many connections to each node.
+    pub max_connection_count: usize,
+    /// How far ahead the request rate is forecast, this should cover the time taken to open connections. Defaults to 60.
+    pub lead_time_secs: Option<u64>,
+    /// The weight given to the latest second when smoothing the request rate and its trend, between 0 and 1. Defaults to 0.1.
+    pub smoothing: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    /// The time of day in UTC from which this entry applies, formatted as HH:MM.
+    pub from_utc: String,
+    pub connection_count: usize,
+}
+
+/// Decides how many connections to each node the pool of a sink should hold.
+/// Shared by every instance of the sink, which report their requests through [`Prewarm::record_requests`]
+/// and read the decision through [`Prewarm::connection_count`].
+pub struct Prewarm {
+    /// The configured connection count, the pool never shrinks below it.
+    min_connection_count: usize,
+    connection_count: AtomicUsize,
+    requests: AtomicU64,
+    plan: Mutex<Plan>,
+    connection_count_gauge: Gauge,
+}
+
+enum Plan {
+    Forecast(Forecast),
+    /// Entries sorted by the minute of the day they apply from.
+    Schedule(Vec<(u32, usize)>),
+}
+
+impl Prewarm {
+    pub fn new(
+        config: &PrewarmConfig,
+        min_connection_count: usize,
+        chain_name: String,
+    ) -> Result<Arc<Self>> {
+        let plan = match config {
+            PrewarmConfig::Forecast(config) => {
+                let rate = config.requests_per_second_per_connection;
+                if rate.is_nan() || rate <= 0.0 {
+                    bail!("prewarm requests_per_second_per_connection must be greater than 0");
+                }
+                if config.max_connection_count < min_connection_count {
+                    bail!(
+                        "prewarm max_connection_count of {} must not be less than the connection_count of {min_connection_count}",
+                        config.max_connection_count
+                    );
+                }
+                let smoothing = config.smoothing.unwrap_or(0.1);
+                if smoothing.is_nan() || smoothing <= 0.0 || smoothing > 1.0 {
+                    bail!("prewarm smoothing must be greater than 0 and at most 1 but was {smoothing}");
+                }
+                Plan::Forecast(Forecast {
+                    requests_per_second_per_connection: config.requests_per_second_per_connection,
+                    max_connection_count: config.max_connection_count,
+                    lead_time_secs: config.lead_time_secs.unwrap_or(60),
+                    smoothing,
+                    level: None,
+                    trend: 0.0,
+                    seconds_below: 0,
+                })
+            }
+            PrewarmConfig::Schedule(entries) => {
+                if entries.is_empty() {
+                    bail!("prewarm schedule must contain at least one entry");
+                }
+                let mut schedule = entries
+                    .iter()
+                    .map(|entry| {
+                        parse_minute_of_day(&entry.from_utc)
+                            .map(|minute| (minute, entry.connection_count))
+                            .with_context(|| {
+                                format!("Invalid prewarm schedule time {:?}", entry.from_utc)
+                            })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                schedule.sort_by_key(|(minute, _)| *minute);
+                Plan::Schedule(schedule)
+            }
+        };
+
+        let prewarm = Prewarm {
+            min_connection_count,
+            connection_count: AtomicUsize::new(min_connection_count),
+            requests: AtomicU64::new(0),
+            plan: Mutex::new(plan),
+            connection_count_gauge: gauge!("shotover_prewarm_connection_count", "chain" => chain_name),
+        };
+        prewarm
+            .connection_count_gauge
+            .set(min_connection_count as f64);
+        Ok(Arc::new(prewarm))
+    }
+
+    pub fn record_requests(&self, count: usize) {
+        self.requests.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// The number of connections to each node that the pool should currently hold.
+    pub fn connection_count(&self) -> usize {
+        self.connection_count.load(Ordering::Relaxed)
+    }
+
+    /// Reevaluates the connection count once a second, calling `resize` with the new count whenever it changes.
+    /// Stops once every other reference to this `Prewarm` has been dropped.
+    pub fn spawn<F, Fut>(self: &Arc<Self>, mut resize: F)
+    where
+        F: FnMut(usize) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let prewarm = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            // the first tick completes immediately, skip it so that a full second of requests is counted
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(prewarm) = prewarm.upgrade() else {
+                    return;
+                };
+                let requests = prewarm.requests.swap(0, Ordering::Relaxed);
+                let connection_count = prewarm.plan_connection_count(requests, minute_of_day());
+                if connection_count != prewarm.connection_count() {
+                    info!(
+                        "Resizing connection pool from {} to {connection_count} connections per node",
+                        prewarm.connection_count()
+                    );
+                    // Open the connections before advertising them so that clients do not open them on demand
+                    resize(connection_count).await;
+                    prewarm
+                        .connection_count
+                        .store(connection_count, Ordering::Relaxed);
+                    prewarm.connection_count_gauge.set(connection_count as f64);
+                }
+            }
+        });
+    }
+
+    /// Called once a second with the number of requests received in that second.
+    fn plan_connection_count(&self, requests: u64, minute_of_day: u32) -> usize {
+        let current = self.connection_count();
+        let planned = match &mut *self.plan.lock().unwrap() {
+            Plan::Forecast(forecast) => forecast.connection_count(requests as f64, current),
+            Plan::Schedule(schedule) => schedule
+                .iter()
+                .rev()
+                .find(|(from, _)| *from <= minute_of_day)
+                // before the first entry of the day the last entry of the previous day still applies
+                .or(schedule.last())
+                .map(|(_, connection_count)| *connection_count)
+                .unwrap_or(current),
+        };
+        planned.max(self.min_connection_count)
+    }
+}
+
+/// Holt's linear smoothing of the request rate, which unlike a plain moving average follows a ramp instead of lagging behind it.
+struct Forecast {
+    requests_per_second_per_connection: f64,
+    max_connection_count: usize,
+    lead_time_secs: u64,
+    smoothing: f64,
+    /// The smoothed request rate, None until the first second has been observed
+    level: Option<f64>,
+    /// The smoothed change in the request rate per second
+    trend: f64,
+    /// How many consecutive seconds the forecast has called for fewer connections than the pool holds
+    seconds_below: u64,
+}
+
+impl Forecast {
+    fn connection_count(&mut self, rate: f64, current: usize) -> usize {
+        let level = match self.level {
+            Some(prev_level) => {
+                let level =
+                    self.smoothing * rate + (1.0 - self.smoothing) * (prev_level + self.trend);
+                self.trend =
+                    self.smoothing * (level - prev_level) + (1.0 - self.smoothing) * self.trend;
+                level
+            }
+            None => rate,
+        };
+        self.level = Some(level);
+
+        // A falling trend must not shrink the pool below what the current rate needs
+        let forecast = (level + self.trend * self.lead_time_secs as f64)
+            .max(level)
+            .max(0.0);
+        let needed = ((forecast / self.requests_per_second_per_connection).ceil() as usize)
+            .min(self.max_connection_count);
+
+        if needed >= current {
+            self.seconds_below = 0;
+            needed
+        } else {
+            // Only shrink once the pool has been larger than needed for the whole lead time,
+            // otherwise a brief lull would close connections that are about to be opened again.
+            self.seconds_below += 1;
+            if self.seconds_below >= self.lead_time_secs {
+                self.seconds_below = 0;
+                needed
+            } else {
+                current
+            }
+        }
+    }
+}
+
+fn parse_minute_of_day(time: &str) -> Result<u32> {
+    let (hours, minutes) = time
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected HH:MM"))?;
+    let hours: u32 = hours.parse().context("hours is not a number")?;
+    let minutes: u32 = minutes.parse().context("minutes is not a number")?;
+    if hours >= 24 || minutes >= 60 {
+        bail!("not a valid time of day");
+    }
+    Ok(hours * 60 + minutes)
+}
+
+fn minute_of_day() -> u32 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    ((secs % 86400) / 60) as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn forecast(lead_time_secs: u64) -> Prewarm {
+        Arc::into_inner(
+            Prewarm::new(
+                &PrewarmConfig::Forecast(ForecastConfig {
+                    requests_per_second_per_connection: 100.0,
+                    max_connection_count: 10,
+                    lead_time_secs: Some(lead_time_secs),
+                    smoothing: Some(0.5),
+                }),
+                1,
+                "chain".to_owned(),
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Runs the forecast for each second of `rates`, returning the connection count planned after the last second
+    fn run(prewarm: &Prewarm, rates: &[u64]) -> usize {
+        for rate in rates {
+            let connection_count = prewarm.plan_connection_count(*rate, 0);
+            prewarm
+                .connection_count
+                .store(connection_count, Ordering::Relaxed);
+        }
+        prewarm.connection_count()
+    }
+
+    #[test]
+    fn test_forecast_grows_ahead_of_ramp() {
+        let prewarm = forecast(10);
+        // the rate is only at 250 but is climbing by 50 each second, so the pool is sized for the rate 10 seconds from now
+        let connection_count = run(&prewarm, &[0, 50, 100, 150, 200, 250]);
+        assert!(connection_count > 3, "{connection_count}");
+        assert!(connection_count <= 10, "{connection_count}");
+    }
+
+    #[test]
+    fn test_forecast_shrinks_after_lead_time() {
+        let prewarm = forecast(5);
+        assert_eq!(run(&prewarm, &[500; 20]), 5);
+
+        // a short lull keeps the connections
+        assert_eq!(run(&prewarm, &[0; 3]), 5);
+        // a sustained lull releases them, but never below the configured connection_count
+        assert_eq!(run(&prewarm, &[0; 30]), 1);
+    }
+
+    #[test]
+    fn test_forecast_capped_at_max() {
+        let prewarm = forecast(5);
+        assert_eq!(run(&prewarm, &[100_000; 5]), 10);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let prewarm = Prewarm::new(
+            &PrewarmConfig::Schedule(vec![
+                ScheduleEntry {
+                    from_utc: "18:00".to_owned(),
+                    connection_count: 2,
+                },
+                ScheduleEntry {
+                    from_utc: "07:30".to_owned(),
+                    connection_count: 8,
+                },
+            ]),
+            1,
+            "chain".to_owned(),
+        )
+        .unwrap();
+
+        assert_eq!(prewarm.plan_connection_count(0, 7 * 60 + 29), 2);
+        assert_eq!(prewarm.plan_connection_count(0, 7 * 60 + 30), 8);
+        assert_eq!(prewarm.plan_connection_count(0, 17 * 60 + 59), 8);
+        assert_eq!(prewarm.plan_connection_count(0, 18 * 60), 2);
+    }
+
+    #[test]
+    fn test_invalid_schedule_time() {
+        let result = Prewarm::new(
+            &PrewarmConfig::Schedule(vec![ScheduleEntry {
+                from_utc: "24:00".to_owned(),
+                connection_count: 2,
+            }]),
+            1,
+            "chain".to_owned(),
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,563 @@
+//! Workload analysis, turning the traffic shotover sees into tuning recommendations for the chains it runs.
+//!
+//! An analysis is started through the `/tuning` endpoint of the observability interface and observes live traffic for the requested period.
+//! Until an analysis is started, or once it has finished, observing costs no more than checking a flag per batch.
+//! While it runs every chain records the command, key and latency of each request it processes, along with how long sinks with a connection pool
+//! take to respond while more batches are in flight through them than they pool connections to each node.
+//!
+//! The report recommends:
+//! * caching key patterns that are read far more often than they are written, and whose keys are each read repeatedly
+//! * raising the pool size of a sink whose latency climbs once there are more batches in flight than connections in its pool
+//! * looking into commands that take up most of the time spent by a chain
+//!
+//! The recommendations are heuristics, they point at where tuning is likely to pay off rather than prescribing exact settings.
+
+use crate::frame::Frame;
+use crate::message::{Messages, QueryType};
+use crate::transforms::util::hyperloglog::HyperLogLog;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Key patterns read fewer times than this during the analysis are never recommended for caching
+const MIN_CACHE_READS: u64 = 100;
+/// The minimum proportion of accesses to a key pattern that must be reads for it to be recommended for caching
+const MIN_CACHE_READ_RATIO: f64 = 0.9;
+/// The minimum number of times each key of a pattern must be read on average for it to be recommended for caching
+const MIN_READS_PER_KEY: f64 = 2.0;
+/// The number of key patterns tracked per analysis, further patterns are counted as untracked so that unbounded key spaces cannot exhaust memory
+const MAX_KEY_PATTERNS: usize = 1000;
+/// The precision of the sketch counting the distinct keys read from each key pattern, using 1KiB per pattern
+const KEY_SKETCH_PRECISION: u8 = 10;
+/// A command taking at least this share of the time spent by its chain is reported as dominating its latency
+const DOMINANT_LATENCY_SHARE: f64 = 0.5;
+/// The minimum number of batches sent to a pooled sink, and the share of them that must be contended, before its pool size is judged
+const MIN_POOL_BATCHES: u64 = 100;
+const MIN_CONTENDED_SHARE: f64 = 0.1;
+/// How much slower contended batches must be than uncontended batches for the pool to be considered too small
+const CONTENDED_SLOWDOWN: f64 = 2.0;
+/// The number of commands of each chain listed in the report
+const REPORTED_COMMANDS: usize = 10;
+
+/// Implemented by the state shared between every instance of a sink that pools its connections to each node
+pub(crate) trait PooledSink: Send + Sync {
+    /// The number of connections currently pooled to each node
+    fn connection_count(&self) -> usize;
+    /// The configuration field to raise to pool more connections
+    fn connection_count_setting(&self) -> &'static str;
+}
+
+struct PoolRegistration {
+    transform: &'static str,
+    chain: String,
+    sink: Weak<dyn PooledSink>,
+    /// The number of batches currently being processed by the sink across every connection
+    in_flight: Arc<AtomicUsize>,
+}
+
+static POOLS: Mutex<Vec<PoolRegistration>> = Mutex::new(Vec::new());
+
+/// Lets the analysis judge the pool size of `sink` until it is dropped
+pub(crate) fn register_pool<S: PooledSink + 'static>(
+    transform: &'static str,
+    chain: &str,
+    sink: &Arc<S>,
+) {
+    let sink: Weak<dyn PooledSink> = Arc::downgrade(sink);
+    let mut pools = POOLS.lock().unwrap();
+    pools.retain(|pool| pool.sink.strong_count() > 0);
+    pools.push(PoolRegistration {
+        transform,
+        chain: chain.to_owned(),
+        sink,
+        in_flight: Arc::new(AtomicUsize::new(0)),
+    });
+}
+
+/// Set while an analysis is observing traffic, lets chains skip observing in the common case of no analysis running
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// The most recently started analysis
+static ANALYSIS: Mutex<Option<Analysis>> = Mutex::new(None);
+
+struct Analysis {
+    started: Instant,
+    duration: Duration,
+    commands: HashMap<(&'static str, String), CommandStats>,
+    key_patterns: HashMap<(&'static str, String), KeyPatternStats>,
+    untracked_key_patterns: u64,
+    pools: HashMap<(&'static str, &'static str), PoolStats>,
+}
+
+impl Analysis {
+    fn finished(&self) -> bool {
+        self.started.elapsed() >= self.duration
+    }
+}
+
+#[derive(Default)]
+struct CommandStats {
+    count: u64,
+    latency: Duration,
+}
+
+struct KeyPatternStats {
+    kind: KeyKind,
+    reads: u64,
+    writes: u64,
+    keys_read: HyperLogLog,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum KeyKind {
+    CassandraTable,
+    RedisKeyPattern,
+}
+
+struct PoolStats {
+    setting: &'static str,
+    connection_count: usize,
+    uncontended: LatencyStats,
+    contended: LatencyStats,
+}
+
+#[derive(Default)]
+struct LatencyStats {
+    count: u64,
+    latency: Duration,
+}
+
+impl LatencyStats {
+    fn record(&mut self, latency: Duration) {
+        self.count += 1;
+        self.latency += latency;
+    }
+
+    fn mean_ms(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.latency.as_secs_f64() * 1000.0 / self.count as f64
+        }
+    }
+}
+
+/// Starts observing traffic for `duration`, discarding the results of any previous analysis
+pub(crate) fn start(duration: Duration) {
+    *ANALYSIS.lock().unwrap() = Some(Analysis {
+        started: Instant::now(),
+        duration,
+        commands: HashMap::new(),
+        key_patterns: HashMap::new(),
+        untracked_key_patterns: 0,
+        pools: HashMap::new(),
+    });
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// What was observed of a single request before it was sent down the chain
+pub(crate) struct RequestObservation {
+    command: String,
+    query_type: QueryType,
+    keys: Vec<ObservedKey>,
+}
+
+struct ObservedKey {
+    kind: KeyKind,
+    pattern: String,
+    key: Vec<u8>,
+}
+
+/// Observes the requests of a batch about to be processed by a chain, returns `None` when no analysis is running
+pub(crate) fn observe(requests: &mut Messages) -> Option<Vec<RequestObservation>> {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut observations = vec![];
+    for request in requests.iter_mut() {
+        match request.frame() {
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(frame)) => {
+                let crate::frame::RedisFrame::Array(args) = &*frame else {
+                    continue;
+                };
+                let Some(command) = crate::frame::redis::redis_query_name(frame) else {
+                    continue;
+                };
+                let keys = crate::transforms::cardinality::redis_keys(args)
+                    .into_iter()
+                    .map(|key| ObservedKey {
+                        kind: KeyKind::RedisKeyPattern,
+                        pattern: redis_key_pattern(key),
+                        key: key.to_vec(),
+                    })
+                    .collect();
+                observations.push(RequestObservation {
+                    command,
+                    query_type: crate::frame::redis::redis_query_type(frame),
+                    keys,
+                });
+            }
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(frame)) => {
+                for statement in frame.operation.queries() {
+                    let table = statement.get_table_name().map(|table| table.to_string());
+                    let keys = match (
+                        &table,
+                        crate::transforms::cardinality::cassandra_key(statement),
+                    ) {
+                        (Some(table), Some(key)) => vec![ObservedKey {
+                            kind: KeyKind::CassandraTable,
+                            pattern: table.clone(),
+                            key,
+                        }],
+                        // Inserts identify their row through the schema, so they are only counted as a write to their table
+                        (Some(table), None) => vec![ObservedKey {
+                            kind: KeyKind::CassandraTable,
+                            pattern: table.clone(),
+                            key: vec![],
+                        }],
+                        _ => vec![],
+                    };
+                    let command = match table {
+                        Some(table) => format!("{} {table}", statement.short_name()),
+                        None => statement.short_name().to_owned(),
+                    };
+                    observations.push(RequestObservation {
+                        command,
+                        query_type: crate::frame::cassandra::get_query_type(statement),
+                        keys,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(observations)
+}
+
+/// Records the batch observed by [`observe`] as having taken `latency` to process, which is shared evenly between its requests
+pub(crate) fn record_batch(
+    chain: &'static str,
+    observations: Vec<RequestObservation>,
+    latency: Duration,
+) {
+    if observations.is_empty() {
+        return;
+    }
+    let mut analysis = ANALYSIS.lock().unwrap();
+    let Some(analysis) = analysis.as_mut().filter(|analysis| !analysis.finished()) else {
+        ACTIVE.store(false, Ordering::Relaxed);
+        return;
+    };
+
+    let latency = latency / observations.len() as u32;
+    for observation in observations {
+        let stats = analysis
+            .commands
+            .entry((chain, observation.command))
+            .or_default();
+        stats.count += 1;
+        stats.latency += latency;
+
+        for key in observation.keys {
+            let pattern = (chain, key.pattern);
+            if analysis.key_patterns.len() >= MAX_KEY_PATTERNS
+                && !analysis.key_patterns.contains_key(&pattern)
+            {
+                analysis.untracked_key_patterns += 1;
+                continue;
+            }
+            let stats = analysis
+                .key_patterns
+                .entry(pattern)
+                .or_insert_with(|| KeyPatternStats {
+                    kind: key.kind,
+                    reads: 0,
+                    writes: 0,
+                    keys_read: HyperLogLog::new(KEY_SKETCH_PRECISION),
+                });
+            match observation.query_type {
+                // Reads that do not identify the rows they access, such as full table scans, could not be served from a cache
+                QueryType::Read if !key.key.is_empty() => {
+                    stats.reads += 1;
+                    stats.keys_read.insert(key.key.as_slice());
+                }
+                QueryType::Write | QueryType::ReadWrite => stats.writes += 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A batch being processed by a pooled sink, recorded once it is dropped
+pub(crate) struct PoolSample {
+    chain: &'static str,
+    transform: &'static str,
+    setting: &'static str,
+    connection_count: usize,
+    contended: bool,
+    in_flight: Arc<AtomicUsize>,
+    start: Instant,
+}
+
+/// Starts timing a batch sent to `transform`, returns `None` when no analysis is running or the transform does not pool connections
+pub(crate) fn sample_pool(chain: &'static str, transform: &'static str) -> Option<PoolSample> {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let pools = POOLS.lock().unwrap();
+    let pool = pools
+        .iter()
+        .find(|pool| pool.transform == transform && pool.chain == chain)?;
+    let sink = pool.sink.upgrade()?;
+    let connection_count = sink.connection_count();
+    let in_flight = pool.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+    Some(PoolSample {
+        chain,
+        transform,
+        setting: sink.connection_count_setting(),
+        connection_count,
+        contended: in_flight > connection_count,
+        in_flight: pool.in_flight.clone(),
+        start: Instant::now(),
+    })
+}
+
+impl Drop for PoolSample {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let mut analysis = ANALYSIS.lock().unwrap();
+        if let Some(analysis) = analysis.as_mut().filter(|analysis| !analysis.finished()) {
+            let stats = analysis
+                .pools
+                .entry((self.chain, self.transform))
+                .or_insert_with(|| PoolStats {
+                    setting: self.setting,
+                    connection_count: self.connection_count,
+                    uncontended: LatencyStats::default(),
+                    contended: LatencyStats::default(),
+                });
+            stats.connection_count = self.connection_count;
+            if self.contended {
+                stats.contended.record(self.start.elapsed());
+            } else {
+                stats.uncontended.record(self.start.elapsed());
+            }
+        }
+    }
+}
+
+/// Replaces every segment of a redis key containing a digit with `*`, so that keys such as `user:1234:profile` are grouped as `user:*:profile`
+fn redis_key_pattern(key: &[u8]) -> String {
+    String::from_utf8_lossy(key)
+        .split(':')
+        .map(|segment| {
+            if segment.bytes().any(|x| x.is_ascii_digit()) {
+                "*"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct TuningReport {
+    /// True while traffic is still being observed, the report only covers the traffic observed so far
+    pub running: bool,
+    pub observed_seconds: u64,
+    pub recommendations: Vec<Recommendation>,
+    /// The commands that took up the most time in each chain, most time consuming first
+    pub commands: Vec<CommandReport>,
+    /// Accesses to key patterns beyond the number that are tracked, a high count means keys are not grouped into patterns well
+    pub untracked_key_pattern_accesses: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "kind")]
+pub(crate) enum Recommendation {
+    EnableCaching {
+        chain: &'static str,
+        key_pattern: String,
+        reads: u64,
+        writes: u64,
+        distinct_keys_read: u64,
+        advice: String,
+    },
+    RaisePoolSize {
+        chain: &'static str,
+        transform: &'static str,
+        connection_count: usize,
+        contended_batches: u64,
+        contended_mean_latency_ms: f64,
+        uncontended_mean_latency_ms: f64,
+        advice: String,
+    },
+    DominantCommand {
+        chain: &'static str,
+        command: String,
+        count: u64,
+        latency_share: f64,
+        mean_latency_ms: f64,
+        advice: String,
+    },
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct CommandReport {
+    pub chain: &'static str,
+    pub command: String,
+    pub count: u64,
+    /// The share of the time spent by the chain that was spent on this command
+    pub latency_share: f64,
+    pub mean_latency_ms: f64,
+}
+
+/// Reports on the most recently started analysis, or returns `None` if no analysis has been started
+pub(crate) fn report() -> Option<TuningReport> {
+    let analysis = ANALYSIS.lock().unwrap();
+    let analysis = analysis.as_ref()?;
+    let mut recommendations = vec![];
+
+    let mut key_patterns: Vec<_> = analysis.key_patterns.iter().collect();
+    key_patterns.sort_by(|a, b| b.1.reads.cmp(&a.1.reads).then_with(|| a.0.cmp(b.0)));
+    for ((chain, pattern), stats) in key_patterns {
+        let distinct_keys_read = stats.keys_read.estimate();
+        let read_ratio = stats.reads as f64 / (stats.reads + stats.writes) as f64;
+        if stats.reads >= MIN_CACHE_READS
+            && read_ratio >= MIN_CACHE_READ_RATIO
+            && distinct_keys_read > 0
+            && stats.reads as f64 / distinct_keys_read as f64 >= MIN_READS_PER_KEY
+        {
+            let advice = match stats.kind {
+                KeyKind::CassandraTable => format!(
+                    "{pattern} is read {:.0} times per write and each row is read {:.1} times on average, add it to the caching_schema of a RedisCache transform",
+                    stats.reads as f64 / stats.writes.max(1) as f64,
+                    stats.reads as f64 / distinct_keys_read as f64,
+                ),
+                KeyKind::RedisKeyPattern => format!(
+                    "keys matching {pattern} are read {:.0} times per write and each key is read {:.1} times on average, they are a good candidate for caching closer to clients",
+                    stats.reads as f64 / stats.writes.max(1) as f64,
+                    stats.reads as f64 / distinct_keys_read as f64,
+                ),
+            };
+            recommendations.push(Recommendation::EnableCaching {
+                chain: *chain,
+                key_pattern: pattern.clone(),
+                reads: stats.reads,
+                writes: stats.writes,
+                distinct_keys_read,
+                advice,
+            });
+        }
+    }
+
+    let mut pools: Vec<_> = analysis.pools.iter().collect();
+    pools.sort_by_key(|(key, _)| *key);
+    for ((chain, transform), stats) in pools {
+        let batches = stats.contended.count + stats.uncontended.count;
+        let contended_mean = stats.contended.mean_ms();
+        let uncontended_mean = stats.uncontended.mean_ms();
+        if batches >= MIN_POOL_BATCHES
+            && stats.contended.count as f64 / batches as f64 >= MIN_CONTENDED_SHARE
+            && stats.uncontended.count > 0
+            && contended_mean >= uncontended_mean * CONTENDED_SLOWDOWN
+        {
+            recommendations.push(Recommendation::RaisePoolSize {
+                chain: *chain,
+                transform: *transform,
+                connection_count: stats.connection_count,
+                contended_batches: stats.contended.count,
+                contended_mean_latency_ms: contended_mean,
+                uncontended_mean_latency_ms: uncontended_mean,
+                advice: format!(
+                    "batches took {:.1} times as long while more than {} were in flight through {transform}, raise its {}",
+                    contended_mean / uncontended_mean.max(f64::MIN_POSITIVE),
+                    stats.connection_count,
+                    stats.setting,
+                ),
+            });
+        }
+    }
+
+    let mut chain_latency: HashMap<&'static str, Duration> = HashMap::new();
+    let mut chain_commands: HashMap<&'static str, usize> = HashMap::new();
+    for ((chain, _), stats) in &analysis.commands {
+        *chain_latency.entry(*chain).or_default() += stats.latency;
+        *chain_commands.entry(*chain).or_default() += 1;
+    }
+    let mut commands: Vec<_> = analysis
+        .commands
+        .iter()
+        .map(|((chain, command), stats)| {
+            let total = chain_latency[chain].as_secs_f64();
+            CommandReport {
+                chain: *chain,
+                command: command.clone(),
+                count: stats.count,
+                latency_share: if total > 0.0 {
+                    stats.latency.as_secs_f64() / total
+                } else {
+                    0.0
+                },
+                mean_latency_ms: stats.latency.as_secs_f64() * 1000.0 / stats.count as f64,
+            }
+        })
+        .collect();
+    commands.sort_by(|a, b| {
+        a.chain
+            .cmp(b.chain)
+            .then(b.latency_share.total_cmp(&a.latency_share))
+            .then_with(|| a.command.cmp(&b.command))
+    });
+    for command in &commands {
+        // A command cannot dominate a chain that only ever runs that one command
+        if chain_commands[command.chain] > 1 && command.latency_share >= DOMINANT_LATENCY_SHARE {
+            recommendations.push(Recommendation::DominantCommand {
+                chain: command.chain,
+                command: command.command.clone(),
+                count: command.count,
+                latency_share: command.latency_share,
+                mean_latency_ms: command.mean_latency_ms,
+                advice: format!(
+                    "{} took {:.0}% of the time spent by chain {}, look into speeding it up or routing it to a dedicated chain",
+                    command.command,
+                    command.latency_share * 100.0,
+                    command.chain,
+                ),
+            });
+        }
+    }
+    let mut reported = HashMap::<&'static str, usize>::new();
+    commands.retain(|command| {
+        let count = reported.entry(command.chain).or_default();
+        *count += 1;
+        *count <= REPORTED_COMMANDS
+    });
+
+    Some(TuningReport {
+        running: !analysis.finished(),
+        observed_seconds: analysis.started.elapsed().min(analysis.duration).as_secs(),
+        recommendations,
+        commands,
+        untracked_key_pattern_accesses: analysis.untracked_key_patterns,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_redis_key_pattern() {
+        assert_eq!(redis_key_pattern(b"user:1234:profile"), "user:*:profile");
+        assert_eq!(redis_key_pattern(b"session:a1b2c3"), "session:*");
+        assert_eq!(redis_key_pattern(b"config"), "config");
+        assert_eq!(redis_key_pattern(b"item42"), "*");
+    }
+}
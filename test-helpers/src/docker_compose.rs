@@ -1,4 +1,6 @@
+use crate::mock_redis;
 use docker_compose_runner::*;
+use std::sync::OnceLock;
 use std::{env, time::Duration};
 
 pub use docker_compose_runner::DockerCompose;
@@ -10,6 +12,52 @@ pub fn docker_compose(file_path: &str) -> DockerCompose {
     DockerCompose::new(&IMAGE_WAITERS, |_| {}, file_path)
 }
 
+/// The services of a docker compose file that are started by [`docker_compose_or_mock`] when docker is not available.
+/// Both are stopped when dropped.
+pub enum TestBackend {
+    DockerCompose(DockerCompose),
+    Mock(Vec<mock_redis::MockHandle>),
+}
+
+/// Compose files whose services can all be replaced by a mock redis, along with the ports to run the mocks on.
+const REDIS_MOCK_SUBSTITUTES: &[(&str, &[u16])] = &[(
+    "tests/test-configs/redis/passthrough/docker-compose.yaml",
+    &[1111],
+)];
+
+/// Like [`docker_compose`] but on machines without docker, such as windows or macOS without docker desktop,
+/// the services are replaced by in-process mock servers.
+/// Only tests that work against the mocks should use this, the mocks implement a small subset of the real services.
+///
+/// Setting the `SHOTOVER_TEST_BACKEND` env var to `mock` or `docker` overrides the detection of docker.
+pub fn docker_compose_or_mock(file_path: &str) -> TestBackend {
+    if use_mocks() {
+        crate::test_tracing::setup_tracing_subscriber_for_test();
+
+        let Some((_, ports)) = REDIS_MOCK_SUBSTITUTES
+            .iter()
+            .find(|(path, _)| *path == file_path)
+        else {
+            panic!("Docker is not available and {file_path} has no mock substitute");
+        };
+        tracing::info!(
+            "Docker is not available, replacing {file_path} with mock redis on ports {ports:?}"
+        );
+        TestBackend::Mock(ports.iter().map(|port| mock_redis::start(*port)).collect())
+    } else {
+        TestBackend::DockerCompose(docker_compose(file_path))
+    }
+}
+
+fn use_mocks() -> bool {
+    static USE_MOCKS: OnceLock<bool> = OnceLock::new();
+    *USE_MOCKS.get_or_init(|| match env::var("SHOTOVER_TEST_BACKEND").as_deref() {
+        Ok("mock") => true,
+        Ok("docker") => false,
+        _ => crate::run_command("docker", &["info"]).is_err(),
+    })
+}
+
 /// Creates a new DockerCompose running an instance of moto the AWS mocking server
 pub fn new_moto() -> DockerCompose {
     // Overwrite any existing AWS credential env vars belonging to the user with dummy values to be sure that
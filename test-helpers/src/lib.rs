@@ -3,6 +3,7 @@ pub mod connection;
 pub mod docker_compose;
 pub mod metrics;
 pub mod mock_cassandra;
+pub mod mock_redis;
 pub mod shotover_process;
 mod test_tracing;
 
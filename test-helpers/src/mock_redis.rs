@@ -0,0 +1,399 @@
+use bytes::{Buf, BytesMut};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A running mock redis, it is shut down when dropped.
+pub struct MockHandle {
+    handle: Option<JoinHandle<()>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Drop for MockHandle {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        self.handle.take().unwrap().join().unwrap();
+    }
+}
+
+type Data = Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>;
+
+/// Spawns a thread which serves a small in memory subset of redis: PING, ECHO, SET, GET, MGET, DEL, EXISTS, INCR, SELECT, FLUSHDB and FLUSHALL.
+/// Any other command is rejected with an error, so it can only stand in for redis in tests that stick to these commands.
+/// The socket is bound before returning so the mock is ready as soon as this returns.
+pub fn start(port: u16) -> MockHandle {
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_clone = shutdown.clone();
+    let handle = Some(std::thread::spawn(move || {
+        let data = Data::default();
+        let mut connections = vec![];
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(10));
+                    if shutdown_clone.load(Ordering::Relaxed) {
+                        break;
+                    } else {
+                        continue;
+                    }
+                }
+                Err(e) => panic!("Unexpected error when listening for streams {e}"),
+            };
+            stream.set_nonblocking(false).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(10)))
+                .unwrap();
+            stream.set_nodelay(true).unwrap();
+
+            let mut connection = Connection {
+                stream,
+                buffer: BytesMut::with_capacity(10000),
+                data: data.clone(),
+            };
+            let shutdown = shutdown_clone.clone();
+            connections.push(std::thread::spawn(move || connection.run(&shutdown)));
+        }
+
+        for connection in connections {
+            connection.join().unwrap();
+        }
+    }));
+    MockHandle { shutdown, handle }
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: BytesMut,
+    data: Data,
+}
+
+impl Connection {
+    fn run(&mut self, shutdown: &AtomicBool) {
+        let mut bytes = [0u8; 2048];
+        while !shutdown.load(Ordering::Relaxed) {
+            while let Some(command) = self.command_from_buffer() {
+                let response = self.execute(command);
+                if self.stream.write_all(&response).is_err() {
+                    return;
+                }
+            }
+
+            match self.stream.read(&mut bytes) {
+                // The client closed the connection
+                Ok(0) => return,
+                Ok(size) => self.buffer.extend(&bytes[..size]),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Commands are sent by clients as an array of bulk strings
+    fn command_from_buffer(&mut self) -> Option<Vec<Vec<u8>>> {
+        let mut cursor = &self.buffer[..];
+        let count = read_length(&mut cursor, b'*')?;
+        let mut command = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = read_length(&mut cursor, b'$')?;
+            if cursor.len() < len + 2 {
+                return None;
+            }
+            command.push(cursor[..len].to_vec());
+            cursor = &cursor[len + 2..];
+        }
+        let consumed = self.buffer.len() - cursor.len();
+        self.buffer.advance(consumed);
+        Some(command)
+    }
+
+    fn execute(&mut self, command: Vec<Vec<u8>>) -> Vec<u8> {
+        let Some(name) = command.first() else {
+            return error("ERR empty command");
+        };
+        let args = &command[1..];
+        let mut data = self.data.lock().unwrap();
+        match name.to_ascii_uppercase().as_slice() {
+            b"PING" => match args.first() {
+                Some(message) => bulk_string(message),
+                None => b"+PONG\r\n".to_vec(),
+            },
+            b"ECHO" if args.len() == 1 => bulk_string(&args[0]),
+            b"SET" if args.len() >= 2 => {
+                data.insert(args[0].clone(), args[1].clone());
+                b"+OK\r\n".to_vec()
+            }
+            b"GET" if args.len() == 1 => match data.get(&args[0]) {
+                Some(value) => bulk_string(value),
+                None => b"$-1\r\n".to_vec(),
+            },
+            b"MGET" if !args.is_empty() => {
+                let mut response = format!("*{}\r\n", args.len()).into_bytes();
+                for key in args {
+                    match data.get(key) {
+                        Some(value) => response.extend(bulk_string(value)),
+                        None => response.extend(b"$-1\r\n"),
+                    }
+                }
+                response
+            }
+            b"DEL" if !args.is_empty() => integer(
+                args.iter()
+                    .filter(|key| data.remove(*key).is_some())
+                    .count() as i64,
+            ),
+            b"EXISTS" if !args.is_empty() => {
+                integer(args.iter().filter(|key| data.contains_key(*key)).count() as i64)
+            }
+            b"INCR" if args.len() == 1 => {
+                let current = match data.get(&args[0]) {
+                    Some(value) => match std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.parse::<i64>().ok())
+                    {
+                        Some(value) => value,
+                        None => return error("ERR value is not an integer or out of range"),
+                    },
+                    None => 0,
+                };
+                data.insert(args[0].clone(), (current + 1).to_string().into_bytes());
+                integer(current + 1)
+            }
+            b"SELECT" if args.len() == 1 => b"+OK\r\n".to_vec(),
+            b"FLUSHDB" | b"FLUSHALL" => {
+                data.clear();
+                b"+OK\r\n".to_vec()
+            }
+            _ => error(&format!(
+                "ERR unknown command or wrong number of arguments for '{}' in the mock redis",
+                String::from_utf8_lossy(name)
+            )),
+        }
+    }
+}
+
+/// Reads a line of the form `<prefix><length>\r\n`
+fn read_length(cursor: &mut &[u8], prefix: u8) -> Option<usize> {
+    let end = cursor.windows(2).position(|window| window == b"\r\n")?;
+    let line = &cursor[..end];
+    if line.first() != Some(&prefix) {
+        panic!(
+            "mock redis only supports commands sent as an array of bulk strings but received {:?}",
+            String::from_utf8_lossy(line)
+        );
+    }
+    let len = std::str::from_utf8(&line[1..]).ok()?.parse().ok()?;
+    *cursor = &cursor[end + 2..];
+    Some(len)
+}
+
+fn bulk_string(value: &[u8]) -> Vec<u8> {
+    let mut response = format!("${}\r\n", value.len()).into_bytes();
+    response.extend(value);
+    response.extend(b"\r\n");
+    response
+}
+
+fn integer(value: i64) -> Vec<u8> {
+    format!(":{value}\r\n").into_bytes()
+}
+
+fn error(message: &str) -> Vec<u8> {
+    format!("-{message}\r\n").into_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use redis::{Connection, RedisResult, Value};
+
+    fn connect(port: u16) -> Connection {
+        redis::Client::open(format!("redis://127.0.0.1:{port}"))
+            .unwrap()
+            .get_connection()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ping_echo() {
+        let _mock = start(16001);
+        let mut connection = connect(16001);
+
+        let pong: String = redis::cmd("PING").query(&mut connection).unwrap();
+        assert_eq!(pong, "PONG");
+        let message: String = redis::cmd("PING").arg("hi").query(&mut connection).unwrap();
+        assert_eq!(message, "hi");
+        let echo: String = redis::cmd("ECHO")
+            .arg("reply")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(echo, "reply");
+    }
+
+    #[test]
+    fn test_get_set_del() {
+        let _mock = start(16002);
+        let mut connection = connect(16002);
+
+        let get: Option<String> = redis::cmd("GET").arg("foo").query(&mut connection).unwrap();
+        assert_eq!(get, None);
+
+        let every_byte: Vec<u8> = (0..=255).collect();
+        let set: String = redis::cmd("sEt")
+            .arg("foo")
+            .arg(&every_byte)
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(set, "OK");
+        let get: Vec<u8> = redis::cmd("GET").arg("foo").query(&mut connection).unwrap();
+        assert_eq!(get, every_byte);
+
+        let mget: Vec<Option<Vec<u8>>> = redis::cmd("MGET")
+            .arg(&["foo", "bar"])
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(mget, vec![Some(every_byte), None]);
+
+        let exists: i64 = redis::cmd("EXISTS")
+            .arg(&["foo", "bar"])
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(exists, 1);
+        let del: i64 = redis::cmd("DEL")
+            .arg(&["foo", "bar"])
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(del, 1);
+        let exists: i64 = redis::cmd("EXISTS")
+            .arg("foo")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(exists, 0);
+    }
+
+    #[test]
+    fn test_incr() {
+        let _mock = start(16003);
+        let mut connection = connect(16003);
+
+        let incr: i64 = redis::cmd("INCR")
+            .arg("count")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(incr, 1);
+        let incr: i64 = redis::cmd("INCR")
+            .arg("count")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(incr, 2);
+
+        let _: () = redis::cmd("SET")
+            .arg("count")
+            .arg("not a number")
+            .query(&mut connection)
+            .unwrap();
+        let err = redis::cmd("INCR")
+            .arg("count")
+            .query::<i64>(&mut connection)
+            .unwrap_err();
+        assert_eq!(
+            err.detail(),
+            Some("value is not an integer or out of range")
+        );
+    }
+
+    #[test]
+    fn test_data_shared_between_connections() {
+        let _mock = start(16004);
+        let mut connection1 = connect(16004);
+        let mut connection2 = connect(16004);
+
+        let _: () = redis::cmd("SET")
+            .arg("foo")
+            .arg("bar")
+            .query(&mut connection1)
+            .unwrap();
+        let get: String = redis::cmd("GET")
+            .arg("foo")
+            .query(&mut connection2)
+            .unwrap();
+        assert_eq!(get, "bar");
+
+        let _: () = redis::cmd("FLUSHDB").query(&mut connection2).unwrap();
+        let get: Option<String> = redis::cmd("GET")
+            .arg("foo")
+            .query(&mut connection1)
+            .unwrap();
+        assert_eq!(get, None);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let _mock = start(16005);
+        let mut connection = connect(16005);
+
+        let results: Vec<Value> = redis::pipe()
+            .cmd("SET")
+            .arg("foo")
+            .arg("1")
+            .cmd("INCR")
+            .arg("foo")
+            .cmd("GET")
+            .arg("foo")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![Value::Okay, Value::Int(2), Value::Data(b"2".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let _mock = start(16006);
+        let mut connection = connect(16006);
+
+        let result: RedisResult<()> = redis::cmd("LPUSH")
+            .arg("list")
+            .arg(1)
+            .query(&mut connection);
+        assert_eq!(
+            result.unwrap_err().detail(),
+            Some("unknown command or wrong number of arguments for 'LPUSH' in the mock redis")
+        );
+
+        // The connection is still usable after an error
+        let pong: String = redis::cmd("PING").query(&mut connection).unwrap();
+        assert_eq!(pong, "PONG");
+    }
+
+    #[test]
+    fn test_shutdown_on_drop() {
+        let mock = start(16007);
+        let mut connection = connect(16007);
+        let _: () = redis::cmd("SET")
+            .arg("foo")
+            .arg("bar")
+            .query(&mut connection)
+            .unwrap();
+        drop(mock);
+
+        assert!(redis::cmd("GET")
+            .arg("foo")
+            .query::<String>(&mut connection)
+            .is_err());
+        // The port is free again and a new mock starts with no data
+        let _mock = start(16007);
+        let mut connection = connect(16007);
+        let get: Option<String> = redis::cmd("GET").arg("foo").query(&mut connection).unwrap();
+        assert_eq!(get, None);
+    }
+}
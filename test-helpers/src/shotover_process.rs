@@ -12,6 +12,7 @@ pub struct ShotoverProcessBuilder {
     bin_path: Option<PathBuf>,
     log_name: Option<String>,
     cores: Option<String>,
+    shards: Option<String>,
     profile: Option<String>,
     event_matchers: Vec<EventMatcher>,
 }
@@ -27,6 +28,7 @@ impl ShotoverProcessBuilder {
             bin_path: None,
             log_name: None,
             cores: None,
+            shards: None,
             profile: None,
             event_matchers: vec![],
         }
@@ -58,6 +60,12 @@ impl ShotoverProcessBuilder {
         self
     }
 
+    /// Run shotover with client connections spread across the specified number of shards
+    pub fn with_shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards.to_string());
+        self
+    }
+
     /// Force shotover to be compiled with the specified profile
     pub fn with_profile(mut self, profile: Option<&str>) -> Self {
         if let Some(profile) = profile {
@@ -104,6 +112,9 @@ impl ShotoverProcessBuilder {
         if let Some(cores) = &self.cores {
             args.extend(["--core-threads", cores]);
         }
+        if let Some(shards) = &self.shards {
+            args.extend(["--shards", shards]);
+        }
         let config_path = self
             .config_path
             .clone()